@@ -50,6 +50,14 @@ class WarpgateProcess:
     postgres_port: int
 
 
+def merge_config(config: dict, patch: dict):
+    for key, value in patch.items():
+        if isinstance(value, dict) and isinstance(config.get(key), dict):
+            merge_config(config[key], value)
+        else:
+            config[key] = value
+
+
 class ProcessManager:
     children: List[Child]
 
@@ -185,6 +193,8 @@ class ProcessManager:
         share_with: Optional[WarpgateProcess] = None,
         stderr=None,
         stdout=None,
+        setup_args=None,
+        config_patch: Optional[dict] = None,
     ) -> WarpgateProcess:
         args = args or ["run", "--enable-admin-token"]
 
@@ -255,6 +265,7 @@ class ProcessManager:
                     str(postgres_port),
                     "--data-path",
                     data_dir,
+                    *(setup_args or []),
                 ],
                 env={"WARPGATE_ADMIN_PASSWORD": "123"},
             )
@@ -266,6 +277,7 @@ class ProcessManager:
 
             config = yaml.safe_load(config_path.open())
             config["ssh"]["host_key_verification"] = "auto_accept"
+            merge_config(config, config_patch or {})
             with config_path.open("w") as f:
                 yaml.safe_dump(config, f)
 
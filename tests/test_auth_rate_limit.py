@@ -0,0 +1,123 @@
+from pathlib import Path
+from uuid import uuid4
+
+import requests
+
+from .api_client import admin_client, sdk
+from .conftest import ProcessManager
+from .util import wait_port
+
+
+class TestAuthRateLimit:
+    def test_lockout_applies_across_protocols(
+        self,
+        processes: ProcessManager,
+        wg_c_ed25519_pubkey: Path,
+        timeout,
+    ):
+        ssh_port = processes.start_ssh_server(
+            trusted_keys=[wg_c_ed25519_pubkey.read_text()]
+        )
+        wg = processes.start_wg(
+            config_patch={
+                "auth_rate_limit": {
+                    "enable": True,
+                    "per_user": {
+                        "max_failures": 3,
+                        "window": "15m",
+                        "lockout": "15m",
+                    },
+                    # All attempts come from localhost
+                    "per_source": {
+                        "max_failures": 0,
+                        "window": "15m",
+                        "lockout": "15m",
+                    },
+                },
+            },
+        )
+        wait_port(ssh_port)
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+        wait_port(wg.ssh_port, for_process=wg.process)
+
+        url = f"https://localhost:{wg.http_port}"
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            users = []
+            for _ in range(2):
+                user = api.create_user(
+                    sdk.CreateUserRequest(username=f"user-{uuid4()}")
+                )
+                api.create_password_credential(
+                    user.id, sdk.NewPasswordCredential(password="123")
+                )
+                api.add_user_role(user.id, role.id)
+                users.append(user)
+            ssh_target = api.create_target(
+                sdk.TargetDataRequest(
+                    name=f"ssh-{uuid4()}",
+                    options=sdk.TargetOptions(
+                        sdk.TargetOptionsTargetSSHOptions(
+                            kind="Ssh",
+                            host="localhost",
+                            port=ssh_port,
+                            username="root",
+                            auth=sdk.SSHTargetAuth(
+                                sdk.SSHTargetAuthSshTargetPublicKeyAuth(
+                                    kind="PublicKey"
+                                )
+                            ),
+                        )
+                    ),
+                )
+            )
+            api.add_target_role(ssh_target.id, role.id)
+
+        def http_login(user, password):
+            return requests.post(
+                f"{url}/@warpgate/api/auth/login",
+                verify=False,
+                json={"username": user.username, "password": password},
+            ).status_code
+
+        def ssh_login(user, password):
+            ssh_client = processes.start_ssh_client(
+                f"{user.username}:{ssh_target.name}@localhost",
+                "-p",
+                str(wg.ssh_port),
+                "-i",
+                "/dev/null",
+                "-o",
+                "PreferredAuthentications=password",
+                "-o",
+                "NumberOfPasswordPrompts=1",
+                "ls",
+                "/bin/sh",
+                password=password,
+            )
+            output = ssh_client.communicate(timeout=timeout)[0]
+            return ssh_client.returncode == 0 and output == b"/bin/sh\n"
+
+        locked_via_http, locked_via_ssh = users
+
+        # Failures over HTTP lock the user out of SSH
+        for _ in range(3):
+            assert http_login(locked_via_http, "321") // 100 != 2
+        assert not ssh_login(locked_via_http, "123")
+        assert http_login(locked_via_http, "123") // 100 != 2
+
+        # ...and the other way around
+        for _ in range(3):
+            assert not ssh_login(locked_via_ssh, "321")
+        assert http_login(locked_via_ssh, "123") // 100 != 2
+        assert not ssh_login(locked_via_ssh, "123")
+
+        # Other users are unaffected
+        with admin_client(url) as api:
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+        assert ssh_login(user, "123")
+        assert http_login(user, "123") // 100 == 2
@@ -0,0 +1,67 @@
+from uuid import uuid4
+
+import requests
+import yaml
+
+from .api_client import admin_client
+from .conftest import ProcessManager
+from .test_http_common import *  # noqa
+from .util import wait_port
+
+
+class TestBootstrap:
+    def test_bootstrap_file(
+        self,
+        processes: ProcessManager,
+        echo_server_port,
+    ):
+        bootstrap_path = processes.ctx.tmpdir / f"bootstrap-{uuid4()}.yaml"
+        with bootstrap_path.open("w") as f:
+            yaml.safe_dump(
+                {
+                    "roles": ["devs"],
+                    "users": [
+                        {
+                            "username": "alice",
+                            "password": "123",
+                            "roles": ["devs"],
+                        },
+                    ],
+                    "targets": [
+                        {
+                            "name": "echo",
+                            "roles": ["devs"],
+                            "options": {
+                                "kind": "Http",
+                                "url": f"http://localhost:{echo_server_port}",
+                                "tls": {"mode": "Disabled", "verify": False},
+                            },
+                        },
+                    ],
+                },
+                f,
+            )
+
+        wg = processes.start_wg(setup_args=["--bootstrap", str(bootstrap_path)])
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+        url = f"https://localhost:{wg.http_port}"
+
+        with admin_client(url) as api:
+            assert "devs" in [r.name for r in api.get_roles()]
+            assert "alice" in [u.username for u in api.get_users()]
+
+        session = requests.Session()
+        session.verify = False
+        response = session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={
+                "username": "alice",
+                "password": "123",
+            },
+        )
+        assert response.status_code // 100 == 2
+
+        response = session.get(
+            f"{url}/some/path?warpgate-target=echo", allow_redirects=False
+        )
+        assert response.status_code == 200
@@ -1,3 +1,5 @@
+import json
+import subprocess
 from uuid import uuid4
 
 from .api_client import admin_client, sdk
@@ -56,3 +58,39 @@ class Test:
         ).process
         proc.wait(timeout=timeout)
         assert proc.returncode != 0
+
+    def test_all_json_report(
+        self,
+        processes: ProcessManager,
+        echo_server_port,
+        timeout,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        with admin_client(url) as api:
+            echo_target = api.create_target(sdk.TargetDataRequest(
+                name=f"echo-{uuid4()}",
+                options=sdk.TargetOptions(sdk.TargetOptionsTargetHTTPOptions(
+                    kind="Http",
+                    url=f"http://localhost:{echo_server_port}",
+                    tls=sdk.Tls(
+                        mode=sdk.TlsMode.DISABLED,
+                        verify=False,
+                    ),
+                )),
+            ))
+
+        proc = processes.start_wg(
+            share_with=shared_wg,
+            args=["test-target", "--all", "--json"],
+            stdout=subprocess.PIPE,
+        ).process
+        stdout, _ = proc.communicate(timeout=timeout)
+        report = {x["target"]: x for x in json.loads(stdout)}
+
+        assert report[echo_target.name]["kind"] == "http"
+        assert report[echo_target.name]["reachable"]
+        # Warpgate doesn't authenticate to HTTP targets or verify this one
+        assert report[echo_target.name]["auth"] == "skipped"
+        assert report[echo_target.name]["trust"] == "skipped"
+        assert report[echo_target.name]["error"] is None
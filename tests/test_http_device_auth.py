@@ -0,0 +1,140 @@
+import requests
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import WarpgateProcess
+from .test_http_common import *  # noqa
+
+
+class TestHTTPDeviceAuth:
+    def test_device_flow(
+        self,
+        echo_server_port,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+            other_user = api.create_user(
+                sdk.CreateUserRequest(username=f"user-{uuid4()}")
+            )
+            api.create_password_credential(
+                other_user.id, sdk.NewPasswordCredential(password="123")
+            )
+            echo_target = api.create_target(
+                sdk.TargetDataRequest(
+                    name=f"echo-{uuid4()}",
+                    options=sdk.TargetOptions(
+                        sdk.TargetOptionsTargetHTTPOptions(
+                            kind="Http",
+                            url=f"http://localhost:{echo_server_port}",
+                            tls=sdk.Tls(
+                                mode=sdk.TlsMode.DISABLED,
+                                verify=False,
+                            ),
+                        )
+                    ),
+                )
+            )
+            api.add_target_role(echo_target.id, role.id)
+
+        device = requests.Session()
+        device.verify = False
+
+        def start(target_name):
+            response = device.post(
+                f"{url}/@warpgate/api/auth/device",
+                json={"target_name": target_name},
+            )
+            assert response.status_code == 200
+            return response.json()
+
+        def poll(device_code):
+            return device.post(
+                f"{url}/@warpgate/api/auth/device/token",
+                json={"device_code": device_code},
+            )
+
+        flow = start(echo_target.name)
+        assert flow["verification_uri_complete"].endswith(flow["user_code"])
+
+        response = poll(flow["device_code"])
+        assert response.status_code == 400
+        assert response.json()["error"] == "authorization_pending"
+
+        response = poll(flow["device_code"])
+        assert response.json()["error"] == "slow_down"
+
+        session = requests.Session()
+        session.verify = False
+        response = session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={
+                "username": user.username,
+                "password": "123",
+            },
+        )
+        assert response.status_code // 100 == 2
+
+        response = session.get(f"{url}/@warpgate/api/auth/device/{flow['user_code']}")
+        assert response.status_code == 200
+        assert response.json()["target"] == echo_target.name
+
+        response = session.post(
+            f"{url}/@warpgate/api/auth/device/{flow['user_code']}/approve"
+        )
+        assert response.status_code == 200
+        assert response.json()["state"] == "Approved"
+
+        response = poll(flow["device_code"])
+        assert response.status_code == 200
+        assert response.json()["username"] == user.username
+        assert response.json()["target"] == echo_target.name
+        assert response.json()["ticket_secret"]
+
+        # The device code is single-use
+        response = poll(flow["device_code"])
+        assert response.json()["error"] == "expired_token"
+
+        # ---
+
+        flow = start(f"missing-{uuid4()}")
+        response = session.post(
+            f"{url}/@warpgate/api/auth/device/{flow['user_code']}/approve"
+        )
+        assert response.status_code == 403
+
+        response = session.post(
+            f"{url}/@warpgate/api/auth/device/{flow['user_code']}/reject"
+        )
+        assert response.status_code == 200
+        response = poll(flow["device_code"])
+        assert response.json()["error"] == "access_denied"
+
+        # Only the user who opened the code can reject it
+        flow = start(echo_target.name)
+        response = session.get(f"{url}/@warpgate/api/auth/device/{flow['user_code']}")
+        assert response.status_code == 200
+
+        other_session = requests.Session()
+        other_session.verify = False
+        response = other_session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={
+                "username": other_user.username,
+                "password": "123",
+            },
+        )
+        assert response.status_code // 100 == 2
+
+        response = other_session.post(
+            f"{url}/@warpgate/api/auth/device/{flow['user_code']}/reject"
+        )
+        assert response.status_code == 404
+        response = poll(flow["device_code"])
+        assert response.json()["error"] == "authorization_pending"
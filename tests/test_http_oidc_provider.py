@@ -0,0 +1,149 @@
+import base64
+import hashlib
+from urllib.parse import parse_qs, urlparse
+from uuid import uuid4
+
+import requests
+
+from .api_client import admin_client, sdk
+from .conftest import ProcessManager
+from .util import wait_port
+
+CLIENT_ID = "app"
+CLIENT_SECRET = "app-secret"
+REDIRECT_URI = "https://app.example.com/callback"
+
+
+class TestHTTPOidcProvider:
+    def test_authorization_code_flow(
+        self,
+        processes: ProcessManager,
+    ):
+        wg = processes.start_wg(
+            config_patch={
+                "external_host": "localhost",
+                "http": {
+                    "oidc_provider": {
+                        "clients": [
+                            {
+                                "client_id": CLIENT_ID,
+                                "client_secret": CLIENT_SECRET,
+                                "redirect_uris": [REDIRECT_URI],
+                            },
+                        ],
+                    },
+                },
+            },
+        )
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+        url = f"https://localhost:{wg.http_port}"
+        issuer = f"{url}/@warpgate/oidc"
+
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+
+        # The issuer doesn't follow the Host header
+        response = requests.get(
+            f"{issuer}/.well-known/openid-configuration",
+            verify=False,
+            headers={"Host": "evil.example.com"},
+        )
+        assert response.status_code == 200
+        assert response.json()["issuer"] == issuer
+
+        session = requests.Session()
+        session.verify = False
+        response = session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={"username": user.username, "password": "123"},
+        )
+        assert response.status_code // 100 == 2
+
+        verifier = "verifier-" + uuid4().hex
+        challenge = (
+            base64.urlsafe_b64encode(hashlib.sha256(verifier.encode()).digest())
+            .rstrip(b"=")
+            .decode()
+        )
+        response = session.get(
+            f"{issuer}/authorize",
+            params={
+                "response_type": "code",
+                "client_id": CLIENT_ID,
+                "redirect_uri": REDIRECT_URI,
+                "scope": "openid profile",
+                "state": "state-value",
+                "nonce": "nonce-value",
+                "code_challenge": challenge,
+                "code_challenge_method": "S256",
+            },
+            allow_redirects=False,
+        )
+        assert response.status_code == 303
+        location = urlparse(response.headers["location"])
+        assert location._replace(query="").geturl() == REDIRECT_URI
+        query = parse_qs(location.query)
+        assert query["state"] == ["state-value"]
+        code = query["code"][0]
+
+        def exchange(code, verifier):
+            return requests.post(
+                f"{issuer}/token",
+                verify=False,
+                auth=(CLIENT_ID, CLIENT_SECRET),
+                data={
+                    "grant_type": "authorization_code",
+                    "code": code,
+                    "redirect_uri": REDIRECT_URI,
+                    "code_verifier": verifier,
+                },
+            )
+
+        response = exchange(code, verifier)
+        assert response.status_code == 200
+        tokens = response.json()
+        assert tokens["token_type"] == "Bearer"
+
+        payload = tokens["id_token"].split(".")[1]
+        claims = base64.urlsafe_b64decode(payload + "=" * (-len(payload) % 4))
+        assert b'"nonce":"nonce-value"' in claims
+        assert f'"iss":"{issuer}"'.encode() in claims
+
+        # Codes are single-use
+        response = exchange(code, verifier)
+        assert response.status_code == 400
+        assert response.json()["error"] == "invalid_grant"
+
+        response = requests.get(
+            f"{issuer}/userinfo",
+            verify=False,
+            headers={"Authorization": f"Bearer {tokens['access_token']}"},
+        )
+        assert response.status_code == 200
+        assert response.json()["sub"] == user.username
+        assert response.json()["roles"] == [role.name]
+
+        # An ID token is not an access token
+        response = requests.get(
+            f"{issuer}/userinfo",
+            verify=False,
+            headers={"Authorization": f"Bearer {tokens['id_token']}"},
+        )
+        assert response.status_code == 401
+
+    def test_requires_external_host(
+        self,
+        processes: ProcessManager,
+    ):
+        wg = processes.start_wg(
+            config_patch={
+                "http": {"oidc_provider": {"clients": []}},
+            },
+        )
+        wg.process.wait(timeout=30)
+        assert wg.process.returncode != 0
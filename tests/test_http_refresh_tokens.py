@@ -0,0 +1,146 @@
+import requests
+from datetime import datetime, timedelta, timezone
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import WarpgateProcess
+from .test_http_common import *  # noqa
+from .test_http_user_auth_api_token import create_http_target
+
+
+class TestHTTPRefreshTokens:
+    def setup_user(self, url, echo_server_port):
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+            targets = []
+            for name in ["echo", "other"]:
+                target = create_http_target(
+                    api, f"{name}-{uuid4()}", f"http://localhost:{echo_server_port}"
+                )
+                api.add_target_role(target.id, role.id)
+                targets.append(target)
+
+        session = requests.Session()
+        session.verify = False
+        response = session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={
+                "username": user.username,
+                "password": "123",
+            },
+        )
+        assert response.status_code // 100 == 2
+        return user, targets, session
+
+    def refresh(self, url, refresh_token):
+        return requests.post(
+            f"{url}/@warpgate/api/auth/refresh-tokens/refresh",
+            verify=False,
+            json={"refresh_token": refresh_token},
+        )
+
+    def get(self, url, target, access_token):
+        return requests.get(
+            f"{url}/some/path?warpgate-target={target.name}",
+            allow_redirects=False,
+            verify=False,
+            headers={"X-Warpgate-Token": access_token},
+        )
+
+    def test_reuse_revokes_family(
+        self,
+        echo_server_port,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        _, [target, _], session = self.setup_user(url, echo_server_port)
+
+        response = session.post(
+            f"{url}/@warpgate/api/auth/refresh-tokens",
+            json={"label": "cli"},
+        )
+        assert response.status_code == 201
+        first = response.json()
+        assert self.get(url, target, first["access_token"]).status_code // 100 == 2
+
+        response = self.refresh(url, first["refresh_token"])
+        assert response.status_code == 200
+        second = response.json()
+        assert second["token"]["id"] == first["token"]["id"]
+        assert self.get(url, target, first["access_token"]).status_code // 100 != 2
+        assert self.get(url, target, second["access_token"]).status_code // 100 == 2
+
+        # The first refresh token has leaked
+        response = self.refresh(url, first["refresh_token"])
+        assert response.status_code == 401
+
+        assert self.get(url, target, second["access_token"]).status_code // 100 != 2
+        response = self.refresh(url, second["refresh_token"])
+        assert response.status_code == 401
+
+        response = session.get(f"{url}/@warpgate/api/profile/refresh-tokens")
+        assert response.status_code == 200
+        assert response.json() == []
+
+    def test_scoped_access_tokens(
+        self,
+        echo_server_port,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        _, [target, other_target], session = self.setup_user(url, echo_server_port)
+
+        response = session.post(
+            f"{url}/@warpgate/api/auth/refresh-tokens",
+            json={"label": "cli", "scope": {"targets": [target.name]}},
+        )
+        assert response.status_code == 201
+        issued = response.json()
+        assert issued["token"]["scope"]["targets"] == [target.name]
+        assert self.get(url, target, issued["access_token"]).status_code // 100 == 2
+        assert (
+            self.get(url, other_target, issued["access_token"]).status_code // 100
+            != 2
+        )
+
+        # The scope is kept across refreshes
+        response = self.refresh(url, issued["refresh_token"])
+        assert response.status_code == 200
+        refreshed = response.json()
+        assert self.get(url, target, refreshed["access_token"]).status_code // 100 == 2
+        assert (
+            self.get(url, other_target, refreshed["access_token"]).status_code // 100
+            != 2
+        )
+
+    def test_inactive_user_cannot_refresh(
+        self,
+        echo_server_port,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        user, _, session = self.setup_user(url, echo_server_port)
+
+        response = session.post(
+            f"{url}/@warpgate/api/auth/refresh-tokens",
+            json={"label": "cli"},
+        )
+        assert response.status_code == 201
+        issued = response.json()
+
+        with admin_client(url) as api:
+            api.update_user(
+                user.id,
+                sdk.UserDataRequest(
+                    username=user.username,
+                    valid_until=datetime.now(timezone.utc) - timedelta(minutes=1),
+                ),
+            )
+
+        response = self.refresh(url, issued["refresh_token"])
+        assert response.status_code == 401
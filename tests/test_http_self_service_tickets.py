@@ -0,0 +1,96 @@
+import requests
+from datetime import datetime, timedelta, timezone
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import WarpgateProcess
+from .test_http_common import *  # noqa
+
+
+class TestHTTPSelfServiceTickets:
+    def test_self_service_tickets(
+        self,
+        echo_server_port,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+            echo_target = api.create_target(
+                sdk.TargetDataRequest(
+                    name=f"echo-{uuid4()}",
+                    options=sdk.TargetOptions(
+                        sdk.TargetOptionsTargetHTTPOptions(
+                            kind="Http",
+                            url=f"http://localhost:{echo_server_port}",
+                            tls=sdk.Tls(
+                                mode=sdk.TlsMode.DISABLED,
+                                verify=False,
+                            ),
+                        )
+                    ),
+                )
+            )
+            api.add_target_role(echo_target.id, role.id)
+            api.update_parameters(
+                sdk.ParameterUpdate(
+                    allow_self_service_tickets=True,
+                    self_service_ticket_max_validity=3600,
+                    self_service_ticket_protocols=["SSH"],
+                )
+            )
+
+        session = requests.Session()
+        session.verify = False
+        response = session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={
+                "username": user.username,
+                "password": "123",
+            },
+        )
+        assert response.status_code // 100 == 2
+
+        def issue(**kwargs):
+            return session.post(
+                f"{url}/@warpgate/api/profile/tickets",
+                json={"target_name": echo_target.name, **kwargs},
+            )
+
+        response = issue()
+        assert response.status_code == 403
+
+        with admin_client(url) as api:
+            api.update_parameters(
+                sdk.ParameterUpdate(self_service_ticket_protocols=["SSH", "HTTP"])
+            )
+
+        too_late = datetime.now(timezone.utc) + timedelta(hours=2)
+        response = issue(expiry=too_late.isoformat())
+        assert response.status_code == 400
+
+        response = issue(number_of_uses=1)
+        assert response.status_code == 201
+        secret = response.json()["secret"]
+        ticket_id = response.json()["ticket"]["id"]
+
+        response = session.get(f"{url}/@warpgate/api/profile/tickets")
+        assert [x["id"] for x in response.json()] == [ticket_id]
+
+        response = requests.get(
+            f"{url}/some/path?warpgate-target={echo_target.name}&warpgate-ticket={secret}",
+            verify=False,
+        )
+        assert response.status_code // 100 == 2
+        assert response.json()["path"] == "/some/path"
+
+        response = session.delete(f"{url}/@warpgate/api/profile/tickets/{ticket_id}")
+        assert response.status_code == 204
+
+        with admin_client(url) as api:
+            api.update_parameters(sdk.ParameterUpdate(allow_self_service_tickets=False))
@@ -0,0 +1,123 @@
+import requests
+from datetime import datetime, timedelta, timezone
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import WarpgateProcess
+from .test_http_common import *  # noqa
+
+
+def create_http_target(api, name, url):
+    return api.create_target(
+        sdk.TargetDataRequest(
+            name=name,
+            options=sdk.TargetOptions(
+                sdk.TargetOptionsTargetHTTPOptions(
+                    kind="Http",
+                    url=url,
+                    tls=sdk.Tls(
+                        mode=sdk.TlsMode.DISABLED,
+                        verify=False,
+                    ),
+                )
+            ),
+        )
+    )
+
+
+class TestHTTPUserAuthApiToken:
+    def test_auth_api_token(
+        self,
+        echo_server_port,
+        shared_wg: WarpgateProcess,
+    ):
+        url = f"https://localhost:{shared_wg.http_port}"
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+            echo_target = create_http_target(
+                api, f"echo-{uuid4()}", f"http://localhost:{echo_server_port}"
+            )
+            api.add_target_role(echo_target.id, role.id)
+            other_target = create_http_target(
+                api, f"other-{uuid4()}", f"http://localhost:{echo_server_port}"
+            )
+            api.add_target_role(other_target.id, role.id)
+
+        session = requests.Session()
+        session.verify = False
+        response = session.post(
+            f"{url}/@warpgate/api/auth/login",
+            json={
+                "username": user.username,
+                "password": "123",
+            },
+        )
+        assert response.status_code // 100 == 2
+
+        expiry = (datetime.now(timezone.utc) + timedelta(days=1)).isoformat()
+        response = session.post(
+            f"{url}/@warpgate/api/profile/api-tokens",
+            json={
+                "label": "scoped",
+                "expiry": expiry,
+                "scope": {"targets": [echo_target.name]},
+            },
+        )
+        assert response.status_code == 201
+        scoped_secret = response.json()["secret"]
+
+        response = session.post(
+            f"{url}/@warpgate/api/profile/api-tokens",
+            json={
+                "label": "ssh only",
+                "expiry": expiry,
+                "scope": {"protocols": ["SSH"]},
+            },
+        )
+        assert response.status_code == 201
+        ssh_secret = response.json()["secret"]
+
+        # ---
+
+        def get(target, secret):
+            return requests.get(
+                f"{url}/some/path?warpgate-target={target.name}",
+                allow_redirects=False,
+                verify=False,
+                headers={"X-Warpgate-Token": secret},
+            )
+
+        response = get(echo_target, scoped_secret)
+        assert response.status_code // 100 == 2
+        assert response.json()["path"] == "/some/path"
+        assert "warpgate-http-session" not in response.cookies
+
+        response = get(other_target, scoped_secret)
+        assert response.status_code // 100 != 2
+
+        response = get(echo_target, ssh_secret)
+        assert response.status_code // 100 != 2
+
+        response = get(echo_target, f"bad{scoped_secret}")
+        assert response.status_code // 100 != 2
+
+        # Tokens only grant access to targets, not to the profile API
+        response = requests.post(
+            f"{url}/@warpgate/api/profile/api-tokens",
+            verify=False,
+            headers={"X-Warpgate-Token": scoped_secret},
+            json={"label": "minted", "expiry": expiry},
+        )
+        assert response.status_code // 100 != 2
+
+        response = requests.get(
+            f"{url}/@warpgate/api/profile/credentials",
+            verify=False,
+            headers={"X-Warpgate-Token": scoped_secret},
+        )
+        assert response.status_code // 100 != 2
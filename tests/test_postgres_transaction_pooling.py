@@ -0,0 +1,82 @@
+import os
+import subprocess
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import WarpgateProcess, ProcessManager
+from .util import wait_port
+
+
+class Test:
+    def test_session_state_does_not_leak_between_users(
+        self,
+        processes: ProcessManager,
+        timeout,
+        shared_wg: WarpgateProcess,
+    ):
+        db_port = processes.start_postgres_server()
+        url = f"https://localhost:{shared_wg.http_port}"
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            users = []
+            for _ in range(2):
+                user = api.create_user(
+                    sdk.CreateUserRequest(username=f"user-{uuid4()}")
+                )
+                api.create_password_credential(
+                    user.id, sdk.NewPasswordCredential(password="123")
+                )
+                api.add_user_role(user.id, role.id)
+                users.append(user)
+            target = api.create_target(sdk.TargetDataRequest(
+                name=f"postgres-{uuid4()}",
+                options=sdk.TargetOptions(sdk.TargetOptionsTargetPostgresOptions(
+                    kind="Postgres",
+                    host="localhost",
+                    port=db_port,
+                    username="user",
+                    password="123",
+                    tls=sdk.Tls(
+                        mode=sdk.TlsMode.PREFERRED,
+                        verify=False,
+                    ),
+                    # A single connection, so that it would be reused
+                    # if users shared the pool
+                    transaction_pooling=sdk.PostgresTransactionPooling(
+                        max_connections=1,
+                        idle_timeout_seconds=60,
+                    ),
+                )),
+            ))
+            api.add_target_role(target.id, role.id)
+
+        wait_port(db_port, recv=False)
+        wait_port(shared_wg.postgres_port, recv=False)
+
+        def run(user, query):
+            client = processes.start(
+                [
+                    "psql",
+                    "--user",
+                    f"{user.username}#{target.name}",
+                    "--host",
+                    "127.0.0.1",
+                    "--port",
+                    str(shared_wg.postgres_port),
+                    "--tuples-only",
+                    "--no-align",
+                    "-c",
+                    query,
+                    "db",
+                ],
+                env={"PGPASSWORD": "123", **os.environ},
+                stdout=subprocess.PIPE,
+            )
+            output = client.communicate(timeout=timeout)[0]
+            assert client.returncode == 0
+            return output.decode().strip()
+
+        assert run(users[0], "SET ROLE pg_read_all_data; SELECT current_user") == (
+            "pg_read_all_data"
+        )
+        assert run(users[1], "SELECT current_user") == "user"
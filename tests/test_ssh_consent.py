@@ -0,0 +1,176 @@
+import subprocess
+from pathlib import Path
+from textwrap import dedent
+from uuid import uuid4
+
+from .api_client import admin_client, sdk
+from .conftest import ProcessManager
+from .util import wait_port
+
+BANNER = "Authorized use only"
+
+
+class TestSSHConsent:
+    def start_with_banner(self, processes: ProcessManager, wg_c_ed25519_pubkey: Path):
+        ssh_port = processes.start_ssh_server(
+            trusted_keys=[wg_c_ed25519_pubkey.read_text()]
+        )
+        wg = processes.start_wg(
+            config_patch={"ssh": {"consent_banner": BANNER}},
+        )
+        wait_port(ssh_port)
+        wait_port(wg.http_port, for_process=wg.process, recv=False)
+        wait_port(wg.ssh_port, for_process=wg.process)
+        url = f"https://localhost:{wg.http_port}"
+        with admin_client(url) as api:
+            role = api.create_role(sdk.RoleDataRequest(name=f"role-{uuid4()}"))
+            user = api.create_user(sdk.CreateUserRequest(username=f"user-{uuid4()}"))
+            api.create_password_credential(
+                user.id, sdk.NewPasswordCredential(password="123")
+            )
+            api.add_user_role(user.id, role.id)
+            ssh_target = api.create_target(
+                sdk.TargetDataRequest(
+                    name=f"ssh-{uuid4()}",
+                    options=sdk.TargetOptions(
+                        sdk.TargetOptionsTargetSSHOptions(
+                            kind="Ssh",
+                            host="localhost",
+                            port=ssh_port,
+                            username="root",
+                            auth=sdk.SSHTargetAuth(
+                                sdk.SSHTargetAuthSshTargetPublicKeyAuth(
+                                    kind="PublicKey"
+                                )
+                            ),
+                        )
+                    ),
+                )
+            )
+            api.add_target_role(ssh_target.id, role.id)
+        return wg, user, ssh_target
+
+    def run_expect(self, processes, wg, user, ssh_target, methods, steps, timeout):
+        script = dedent(
+            f"""
+            set timeout {timeout - 5}
+
+            spawn ssh {user.username}:{ssh_target.name}@localhost -p {wg.ssh_port} -o StrictHostKeychecking=no -o UserKnownHostsFile=/dev/null -o IdentitiesOnly=yes -o IdentityFile=/dev/null -o PreferredAuthentications={methods} ls /bin/sh
+            """
+        ) + dedent(steps)
+        ssh_client = processes.start(
+            ["expect"],
+            stdin=subprocess.PIPE,
+            stdout=subprocess.PIPE,
+            stderr=subprocess.PIPE,
+        )
+        output, stderr = ssh_client.communicate(script.encode(), timeout=timeout)
+        return ssh_client.returncode, output + stderr
+
+    def consent_acknowledged(self, wg, user):
+        url = f"https://localhost:{wg.http_port}"
+        with admin_client(url) as api:
+            sessions = api.get_sessions(logged_in_only=True).items
+        return [
+            session.consent_acknowledged
+            for session in sessions
+            if session.username == user.username
+        ]
+
+    def test_declined(
+        self,
+        processes: ProcessManager,
+        wg_c_ed25519_pubkey: Path,
+        timeout,
+    ):
+        wg, user, ssh_target = self.start_with_banner(processes, wg_c_ed25519_pubkey)
+        code, output = self.run_expect(
+            processes,
+            wg,
+            user,
+            ssh_target,
+            "password,keyboard-interactive",
+            """
+            expect "password:"
+            sleep 0.5
+            send "123\\r"
+            expect "Type 'yes' to continue"
+            sleep 0.5
+            send "no\\r"
+
+            expect {
+                "/bin/sh" { exit 0; }
+                "Permission denied" { exit 1; }
+                eof { exit 1; }
+            }
+            """,
+            timeout,
+        )
+        assert code != 0, output
+        assert BANNER.encode() in output
+
+    def test_accepted_after_password(
+        self,
+        processes: ProcessManager,
+        wg_c_ed25519_pubkey: Path,
+        timeout,
+    ):
+        wg, user, ssh_target = self.start_with_banner(processes, wg_c_ed25519_pubkey)
+        code, output = self.run_expect(
+            processes,
+            wg,
+            user,
+            ssh_target,
+            "password,keyboard-interactive",
+            """
+            expect "password:"
+            sleep 0.5
+            send "123\\r"
+            expect "Type 'yes' to continue"
+            sleep 0.5
+            send "yes\\r"
+
+            expect {
+                "/bin/sh" { exit 0; }
+                eof { exit 1; }
+            }
+            """,
+            timeout,
+        )
+        assert code == 0, output
+        acknowledged = self.consent_acknowledged(wg, user)
+        assert acknowledged and all(acknowledged)
+
+    def test_accepted_before_keyboard_interactive(
+        self,
+        processes: ProcessManager,
+        wg_c_ed25519_pubkey: Path,
+        timeout,
+    ):
+        wg, user, ssh_target = self.start_with_banner(processes, wg_c_ed25519_pubkey)
+        # The banner comes up first and isn't repeated after the password
+        code, output = self.run_expect(
+            processes,
+            wg,
+            user,
+            ssh_target,
+            "keyboard-interactive,password",
+            """
+            expect "Type 'yes' to continue"
+            sleep 0.5
+            send "yes\\r"
+            expect "password:"
+            sleep 0.5
+            send "123\\r"
+
+            expect {
+                "/bin/sh" { exit 0; }
+                "Type 'yes' to continue" { exit 1; }
+                eof { exit 1; }
+            }
+            """,
+            timeout,
+        )
+        assert code == 0, output
+        acknowledged = self.consent_acknowledged(wg, user)
+        assert acknowledged and all(acknowledged)
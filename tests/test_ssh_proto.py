@@ -100,6 +100,59 @@ class Test:
         assert b"stdout" == stdout
         assert stderr.endswith(b"stderr")
 
+    def test_large_download(
+        self,
+        processes: ProcessManager,
+        timeout,
+        wg_c_ed25519_pubkey,
+        shared_wg: WarpgateProcess,
+    ):
+        size = 256 * 1024 * 1024
+        user, ssh_target = setup_user_and_target(
+            processes, shared_wg, wg_c_ed25519_pubkey
+        )
+        ssh_client = processes.start_ssh_client(
+            f"{user.username}:{ssh_target.name}@localhost",
+            "-p",
+            str(shared_wg.ssh_port),
+            *common_args,
+            "head",
+            "-c",
+            str(size),
+            "/dev/zero",
+            password="123",
+        )
+
+        stdout, _ = ssh_client.communicate(timeout=timeout * 6)
+        assert ssh_client.returncode == 0
+        assert len(stdout) == size
+
+    def test_large_upload(
+        self,
+        processes: ProcessManager,
+        timeout,
+        wg_c_ed25519_pubkey,
+        shared_wg: WarpgateProcess,
+    ):
+        size = 256 * 1024 * 1024
+        user, ssh_target = setup_user_and_target(
+            processes, shared_wg, wg_c_ed25519_pubkey
+        )
+        ssh_client = processes.start_ssh_client(
+            f"{user.username}:{ssh_target.name}@localhost",
+            "-p",
+            str(shared_wg.ssh_port),
+            *common_args,
+            "wc",
+            "-c",
+            password="123",
+            stdin=subprocess.PIPE,
+        )
+
+        stdout, _ = ssh_client.communicate(b"\0" * size, timeout=timeout * 6)
+        assert ssh_client.returncode == 0
+        assert int(stdout.strip()) == size
+
     def test_pty(
         self,
         processes: ProcessManager,
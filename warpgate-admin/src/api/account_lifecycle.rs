@@ -0,0 +1,68 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use warpgate_common::{User as UserConfig, WarpgateError};
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{deactivate_expired_accounts, Services};
+use warpgate_db_entities::User;
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetDeactivatedUsersResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<UserConfig>>),
+}
+
+#[derive(ApiResponse)]
+enum DeactivateExpiredUsersResponse {
+    /// Names of the accounts that have just been deactivated
+    #[oai(status = 200)]
+    Ok(Json<Vec<String>>),
+}
+
+#[OpenApi]
+impl Api {
+    /// Accounts that were deactivated after expiring, most recent first
+    #[oai(
+        path = "/account-lifecycle/deactivated",
+        method = "get",
+        operation_id = "get_deactivated_users"
+    )]
+    async fn api_get_deactivated_users(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetDeactivatedUsersResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        let users = User::Entity::find()
+            .filter(User::Column::Deactivated.is_not_null())
+            .order_by_desc(User::Column::Deactivated)
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|x| x.try_into())
+            .collect::<Result<Vec<UserConfig>, _>>()?;
+
+        Ok(GetDeactivatedUsersResponse::Ok(Json(users)))
+    }
+
+    /// Runs the periodic expiry check right away
+    #[oai(
+        path = "/account-lifecycle/run",
+        method = "post",
+        operation_id = "deactivate_expired_users"
+    )]
+    async fn api_deactivate_expired_users(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<DeactivateExpiredUsersResponse, WarpgateError> {
+        let deactivated = deactivate_expired_accounts(&services).await?;
+        Ok(DeactivateExpiredUsersResponse::Ok(Json(deactivated)))
+    }
+}
@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::Query;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use warpgate_common::WarpgateError;
+use warpgate_core::{CommandUsage, Services, UsageBucket, UsageGrouping, UsageRange, UsageSummary};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetUsageSummaryResponse {
+    #[oai(status = 200)]
+    Ok(Json<UsageSummary>),
+}
+
+#[derive(ApiResponse)]
+enum GetSessionUsageResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<UsageBucket>>),
+}
+
+#[derive(ApiResponse)]
+enum GetTopCommandsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<CommandUsage>>),
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/analytics/summary",
+        method = "get",
+        operation_id = "get_usage_summary"
+    )]
+    async fn api_get_usage_summary(
+        &self,
+        services: Data<&Services>,
+        from: Query<Option<DateTime<Utc>>>,
+        to: Query<Option<DateTime<Utc>>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetUsageSummaryResponse, WarpgateError> {
+        let range = UsageRange {
+            from: *from,
+            to: *to,
+        };
+        let summary = services.analytics.summary(range).await?;
+        Ok(GetUsageSummaryResponse::Ok(Json(summary)))
+    }
+
+    #[oai(
+        path = "/analytics/sessions",
+        method = "get",
+        operation_id = "get_session_usage"
+    )]
+    async fn api_get_session_usage(
+        &self,
+        services: Data<&Services>,
+        group_by: Query<UsageGrouping>,
+        from: Query<Option<DateTime<Utc>>>,
+        to: Query<Option<DateTime<Utc>>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetSessionUsageResponse, WarpgateError> {
+        let range = UsageRange {
+            from: *from,
+            to: *to,
+        };
+        let buckets = services.analytics.sessions(range, *group_by).await?;
+        Ok(GetSessionUsageResponse::Ok(Json(buckets)))
+    }
+
+    #[oai(
+        path = "/analytics/top-commands",
+        method = "get",
+        operation_id = "get_top_commands"
+    )]
+    async fn api_get_top_commands(
+        &self,
+        services: Data<&Services>,
+        from: Query<Option<DateTime<Utc>>>,
+        to: Query<Option<DateTime<Utc>>>,
+        limit: Query<Option<u64>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetTopCommandsResponse, WarpgateError> {
+        let range = UsageRange {
+            from: *from,
+            to: *to,
+        };
+        let commands = services
+            .analytics
+            .top_commands(range, limit.unwrap_or(20))
+            .await?;
+        Ok(GetTopCommandsResponse::Ok(Json(commands)))
+    }
+}
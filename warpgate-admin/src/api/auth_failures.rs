@@ -0,0 +1,121 @@
+use chrono::Utc;
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::Services;
+use warpgate_db_entities::AuthRateLimit::AuthRateLimitKind;
+use warpgate_db_entities::{AuthFailure, AuthRateLimit};
+
+use super::pagination::{PaginatedResponse, PaginationParams};
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetAuthFailuresResponse {
+    #[oai(status = 200)]
+    Ok(Json<PaginatedResponse<AuthFailure::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum GetAuthLockoutsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<AuthRateLimit::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum DeleteAuthLockoutResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    /// Rejected login attempts, newest first
+    #[oai(
+        path = "/auth-failures",
+        method = "get",
+        operation_id = "get_auth_failures"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn api_get_auth_failures(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        username: Query<Option<String>>,
+        remote_ip: Query<Option<String>>,
+        protocol: Query<Option<String>>,
+        offset: Query<Option<u64>>,
+        limit: Query<Option<u64>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetAuthFailuresResponse, WarpgateError> {
+        let db = db.lock().await;
+        let mut q = AuthFailure::Entity::find().order_by_desc(AuthFailure::Column::Timestamp);
+
+        if let Some(ref username) = *username {
+            q = q.filter(AuthFailure::Column::Username.eq(username));
+        }
+        if let Some(ref remote_ip) = *remote_ip {
+            q = q.filter(AuthFailure::Column::RemoteIp.eq(remote_ip));
+        }
+        if let Some(ref protocol) = *protocol {
+            q = q.filter(AuthFailure::Column::Protocol.eq(protocol));
+        }
+
+        Ok(GetAuthFailuresResponse::Ok(Json(
+            PaginatedResponse::new(
+                q,
+                PaginationParams {
+                    limit: *limit,
+                    offset: *offset,
+                },
+                &*db,
+                |x| x,
+            )
+            .await?,
+        )))
+    }
+
+    /// Usernames and sources that are currently locked out
+    #[oai(
+        path = "/auth-lockouts",
+        method = "get",
+        operation_id = "get_auth_lockouts"
+    )]
+    async fn api_get_auth_lockouts(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetAuthLockoutsResponse, WarpgateError> {
+        let lockouts = AuthRateLimit::Entity::find()
+            .filter(AuthRateLimit::Column::LockedUntil.gt(Utc::now()))
+            .order_by_desc(AuthRateLimit::Column::LockedUntil)
+            .all(&*db.lock().await)
+            .await?;
+        Ok(GetAuthLockoutsResponse::Ok(Json(lockouts)))
+    }
+
+    #[oai(
+        path = "/auth-lockouts/:kind/:subject",
+        method = "delete",
+        operation_id = "delete_auth_lockout"
+    )]
+    async fn api_delete_auth_lockout(
+        &self,
+        services: Data<&Services>,
+        kind: Path<AuthRateLimitKind>,
+        subject: Path<String>,
+        _auth: AnySecurityScheme,
+    ) -> Result<DeleteAuthLockoutResponse, WarpgateError> {
+        if services.auth_rate_limiter.reset(*kind, &subject).await? {
+            Ok(DeleteAuthLockoutResponse::Deleted)
+        } else {
+            Ok(DeleteAuthLockoutResponse::NotFound)
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{rollback_config_change, ConfigChangeAuthor, ConfigRollbackResult, Services};
+use warpgate_db_entities::ConfigChange::{self, ConfigObjectKind};
+
+use super::pagination::{PaginatedResponse, PaginationParams};
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetConfigHistoryResponse {
+    #[oai(status = 200)]
+    Ok(Json<PaginatedResponse<ConfigChange::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum RollbackConfigChangeResponse {
+    #[oai(status = 200)]
+    Ok(Json<ConfigChange::Model>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 404)]
+    NotFound,
+    #[oai(status = 409)]
+    Conflict,
+}
+
+#[OpenApi]
+impl Api {
+    /// Versions of targets, users, roles and parameters, newest first
+    #[oai(
+        path = "/config-history",
+        method = "get",
+        operation_id = "get_config_history"
+    )]
+    async fn api_get_config_history(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        kind: Query<Option<ConfigObjectKind>>,
+        object_id: Query<Option<Uuid>>,
+        offset: Query<Option<u64>>,
+        limit: Query<Option<u64>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetConfigHistoryResponse, WarpgateError> {
+        let db = db.lock().await;
+        let mut q = ConfigChange::Entity::find()
+            .order_by_desc(ConfigChange::Column::Timestamp)
+            .order_by_desc(ConfigChange::Column::Version);
+
+        if let Some(kind) = *kind {
+            q = q.filter(ConfigChange::Column::ObjectKind.eq(kind));
+        }
+        if let Some(object_id) = *object_id {
+            q = q.filter(ConfigChange::Column::ObjectId.eq(object_id));
+        }
+
+        Ok(GetConfigHistoryResponse::Ok(Json(
+            PaginatedResponse::new(
+                q,
+                PaginationParams {
+                    limit: *limit,
+                    offset: *offset,
+                },
+                &*db,
+                |x| x,
+            )
+            .await?,
+        )))
+    }
+
+    /// Restores the object to the state recorded in this version
+    #[oai(
+        path = "/config-history/:id/rollback",
+        method = "post",
+        operation_id = "rollback_config_change"
+    )]
+    async fn api_rollback_config_change(
+        &self,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<RollbackConfigChangeResponse, WarpgateError> {
+        let result = {
+            let db = services.db.lock().await;
+            rollback_config_change(&db, id.0, &author).await?
+        };
+
+        Ok(match result {
+            ConfigRollbackResult::Done(change) => {
+                services.authorization_cache.invalidate();
+                RollbackConfigChangeResponse::Ok(Json(change))
+            }
+            ConfigRollbackResult::NotFound => RollbackConfigChangeResponse::NotFound,
+            ConfigRollbackResult::NotRestorable(reason) => {
+                RollbackConfigChangeResponse::BadRequest(Json(reason))
+            }
+            ConfigRollbackResult::NameConflict => RollbackConfigChangeResponse::Conflict,
+        })
+    }
+}
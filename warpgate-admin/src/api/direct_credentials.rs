@@ -0,0 +1,77 @@
+use std::sync::Arc;
+
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{revoke_direct_credential, Services};
+use warpgate_db_entities::DirectCredential;
+
+use super::AnySecurityScheme;
+
+#[derive(ApiResponse)]
+enum GetDirectCredentialsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<DirectCredential::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum RevokeDirectCredentialResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+pub struct Api;
+
+#[OpenApi]
+impl Api {
+    /// Newest first, including removed ones as an audit trail
+    #[oai(
+        path = "/direct-credentials",
+        method = "get",
+        operation_id = "get_direct_credentials"
+    )]
+    async fn api_get_direct_credentials(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        username: Query<Option<String>>,
+        active_only: Query<Option<bool>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetDirectCredentialsResponse, WarpgateError> {
+        let mut query =
+            DirectCredential::Entity::find().order_by_desc(DirectCredential::Column::Created);
+        if let Some(ref username) = *username {
+            query = query.filter(DirectCredential::Column::Username.eq(username));
+        }
+        if active_only.unwrap_or(false) {
+            query = query.filter(DirectCredential::Column::Removed.is_null());
+        }
+        let credentials = query.all(&*db.lock().await).await?;
+        Ok(GetDirectCredentialsResponse::Ok(Json(credentials)))
+    }
+
+    /// Drops the account from the target right away
+    #[oai(
+        path = "/direct-credentials/:id",
+        method = "delete",
+        operation_id = "revoke_direct_credential"
+    )]
+    async fn api_revoke_direct_credential(
+        &self,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<RevokeDirectCredentialResponse, WarpgateError> {
+        if revoke_direct_credential(&services, id.0).await? {
+            Ok(RevokeDirectCredentialResponse::Deleted)
+        } else {
+            Ok(RevokeDirectCredentialResponse::NotFound)
+        }
+    }
+}
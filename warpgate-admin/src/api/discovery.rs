@@ -0,0 +1,187 @@
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ActiveModelTrait, Set};
+use uuid::Uuid;
+use warpgate_common::{
+    SSHTargetAuth, Target as TargetConfig, TargetOptions, TargetSSHOptions, WarpgateError,
+};
+use warpgate_core::{record_config_change, ConfigChangeAuthor, DiscoveryStatus, Services};
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
+use warpgate_db_entities::Target;
+use warpgate_protocol_ssh::{KnownHostValidationResult, KnownHosts};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(Object)]
+struct CreateTargetFromCandidateRequest {
+    name: String,
+    username: String,
+}
+
+#[derive(ApiResponse)]
+enum GetDiscoveryStatusResponse {
+    #[oai(status = 200)]
+    Ok(Json<DiscoveryStatus>),
+}
+
+#[derive(ApiResponse)]
+enum StartDiscoveryScanResponse {
+    #[oai(status = 202)]
+    Accepted,
+
+    #[oai(status = 409)]
+    Disabled,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(ApiResponse)]
+enum CreateTargetFromCandidateResponse {
+    #[oai(status = 201)]
+    Created(Json<TargetConfig>),
+
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/discovery",
+        method = "get",
+        operation_id = "get_discovery_status"
+    )]
+    async fn api_get_discovery_status(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetDiscoveryStatusResponse, WarpgateError> {
+        let enabled = services.config.load().store.discovery.enable;
+        let status = services.discovery.lock().await.status(enabled);
+        Ok(GetDiscoveryStatusResponse::Ok(Json(status)))
+    }
+
+    #[oai(
+        path = "/discovery/scan",
+        method = "post",
+        operation_id = "start_discovery_scan"
+    )]
+    async fn api_start_discovery_scan(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<StartDiscoveryScanResponse, WarpgateError> {
+        if !services.config.load().store.discovery.enable {
+            return Ok(StartDiscoveryScanResponse::Disabled);
+        }
+        let discovery = services.discovery.lock().await;
+        if !discovery.is_scanning() {
+            discovery.request_scan();
+        }
+        Ok(StartDiscoveryScanResponse::Accepted)
+    }
+
+    /// Creates an SSH target for a discovered host and trusts the host keys
+    /// collected during the scan
+    #[oai(
+        path = "/discovery/candidates/:id/target",
+        method = "post",
+        operation_id = "create_target_from_candidate"
+    )]
+    async fn api_create_target_from_candidate(
+        &self,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
+        id: Path<Uuid>,
+        body: Json<CreateTargetFromCandidateRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<CreateTargetFromCandidateResponse, WarpgateError> {
+        if body.name.is_empty() {
+            return Ok(CreateTargetFromCandidateResponse::BadRequest(Json(
+                "name".into(),
+            )));
+        }
+        if body.username.is_empty() {
+            return Ok(CreateTargetFromCandidateResponse::BadRequest(Json(
+                "username".into(),
+            )));
+        }
+
+        let Some(candidate) = services.discovery.lock().await.candidate(&id).cloned() else {
+            return Ok(CreateTargetFromCandidateResponse::NotFound);
+        };
+
+        let options = TargetOptions::Ssh(TargetSSHOptions {
+            host: candidate.host.clone(),
+            port: candidate.port,
+            username: body.username.clone(),
+            allow_insecure_algos: None,
+            auth: SSHTargetAuth::default(),
+            aws_ssm: None,
+            forward_presets: vec![],
+            forwarding_policy: None,
+            allowed_forward_destinations: vec![],
+            role_forwarding_policies: vec![],
+            reconnect: None,
+            multiplexing: None,
+            show_fingerprint: false,
+            block_scp_uploads: false,
+            block_scp_downloads: false,
+        });
+
+        let target = {
+            let db = services.db.lock().await;
+            let target = Target::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                name: Set(body.name.clone()),
+                kind: Set((&options).into()),
+                options: Set(serde_json::to_value(options).map_err(WarpgateError::from)?),
+                honeypot: Set(false),
+                host_overrides: Set(None),
+                max_concurrent_sessions: Set(None),
+                session_limit_message: Set(None),
+                monitor: Set(None),
+                idle_timeout_seconds: Set(None),
+                draining: Set(false),
+            }
+            .insert(&*db)
+            .await?;
+            record_config_change(
+                &db,
+                ConfigObjectKind::Target,
+                target.id,
+                ConfigChangeAction::Create,
+                &author,
+            )
+            .await?;
+            services.authorization_cache.invalidate();
+            target
+        };
+
+        let mut known_hosts = KnownHosts::new(&services.db);
+        for key in &candidate.host_keys {
+            let key = russh::keys::parse_public_key_base64(&key.public_key_base64)
+                .map_err(WarpgateError::other)?;
+            if let KnownHostValidationResult::Unknown = known_hosts
+                .validate(&candidate.host, candidate.port, &key)
+                .await?
+            {
+                known_hosts
+                    .trust(&candidate.host, candidate.port, &key)
+                    .await?;
+            }
+        }
+
+        services.discovery.lock().await.remove_candidate(&id);
+
+        Ok(CreateTargetFromCandidateResponse::Created(Json(
+            target.try_into().map_err(WarpgateError::from)?,
+        )))
+    }
+}
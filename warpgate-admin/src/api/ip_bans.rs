@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, EntityTrait, QueryOrder};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{parse_network, Services};
+use warpgate_db_entities::IpBan::{self, IpBanAction};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetIpBansResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<IpBan::Model>>),
+}
+
+#[derive(Object)]
+struct IpBanDataRequest {
+    /// An address or a CIDR network
+    network: String,
+    action: IpBanAction,
+    comment: Option<String>,
+    expires: Option<DateTime<Utc>>,
+}
+
+#[derive(ApiResponse)]
+enum CreateIpBanResponse {
+    #[oai(status = 201)]
+    Created(Json<IpBan::Model>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+}
+
+#[derive(ApiResponse)]
+enum UpdateIpBanResponse {
+    #[oai(status = 200)]
+    Ok(Json<IpBan::Model>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum DeleteIpBanResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    /// Including expired entries that haven't been cleaned up yet
+    #[oai(path = "/ip-bans", method = "get", operation_id = "get_ip_bans")]
+    async fn api_get_ip_bans(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetIpBansResponse, WarpgateError> {
+        let bans = IpBan::Entity::find()
+            .order_by_desc(IpBan::Column::Created)
+            .all(&*services.db.lock().await)
+            .await?;
+        Ok(GetIpBansResponse::Ok(Json(bans)))
+    }
+
+    /// Applies to new connections right away
+    #[oai(path = "/ip-bans", method = "post", operation_id = "create_ip_ban")]
+    async fn api_create_ip_ban(
+        &self,
+        services: Data<&Services>,
+        body: Json<IpBanDataRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<CreateIpBanResponse, WarpgateError> {
+        let Some(network) = parse_network(&body.network) else {
+            return Ok(CreateIpBanResponse::BadRequest(Json("network".into())));
+        };
+
+        let ban = IpBan::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            network: Set(network.to_string()),
+            action: Set(body.action),
+            comment: Set(body.comment.clone().unwrap_or_default()),
+            created: Set(Utc::now()),
+            expires: Set(body.expires),
+        }
+        .insert(&*services.db.lock().await)
+        .await?;
+        services.ip_bans.reload().await?;
+
+        Ok(CreateIpBanResponse::Created(Json(ban)))
+    }
+
+    #[oai(path = "/ip-bans/:id", method = "put", operation_id = "update_ip_ban")]
+    async fn api_update_ip_ban(
+        &self,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        body: Json<IpBanDataRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<UpdateIpBanResponse, WarpgateError> {
+        let Some(network) = parse_network(&body.network) else {
+            return Ok(UpdateIpBanResponse::BadRequest(Json("network".into())));
+        };
+
+        let ban = {
+            let db = services.db.lock().await;
+            let Some(ban) = IpBan::Entity::find_by_id(id.0).one(&*db).await? else {
+                return Ok(UpdateIpBanResponse::NotFound);
+            };
+            let mut model: IpBan::ActiveModel = ban.into();
+            model.network = Set(network.to_string());
+            model.action = Set(body.action);
+            model.comment = Set(body.comment.clone().unwrap_or_default());
+            model.expires = Set(body.expires);
+            model.update(&*db).await?
+        };
+        services.ip_bans.reload().await?;
+
+        Ok(UpdateIpBanResponse::Ok(Json(ban)))
+    }
+
+    #[oai(
+        path = "/ip-bans/:id",
+        method = "delete",
+        operation_id = "delete_ip_ban"
+    )]
+    async fn api_delete_ip_ban(
+        &self,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<DeleteIpBanResponse, WarpgateError> {
+        let result = IpBan::Entity::delete_by_id(id.0)
+            .exec(&*services.db.lock().await)
+            .await?;
+        if result.rows_affected == 0 {
+            return Ok(DeleteIpBanResponse::NotFound);
+        }
+        services.ip_bans.reload().await?;
+        Ok(DeleteIpBanResponse::Deleted)
+    }
+}
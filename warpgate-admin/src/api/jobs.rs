@@ -0,0 +1,87 @@
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{JobInfo, Services};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetJobsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<JobInfo>>),
+}
+
+#[derive(ApiResponse)]
+enum GetJobResponse {
+    #[oai(status = 200)]
+    Ok(Json<JobInfo>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum CancelJobResponse {
+    #[oai(status = 200)]
+    Ok(Json<JobInfo>),
+    #[oai(status = 404)]
+    NotFound,
+    /// The job has already finished
+    #[oai(status = 409)]
+    Conflict,
+}
+
+#[OpenApi]
+impl Api {
+    /// Running and recently finished background jobs, newest first
+    #[oai(path = "/jobs", method = "get", operation_id = "get_jobs")]
+    async fn api_get_jobs(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetJobsResponse, WarpgateError> {
+        Ok(GetJobsResponse::Ok(Json(services.jobs.list())))
+    }
+
+    #[oai(path = "/jobs/:id", method = "get", operation_id = "get_job")]
+    async fn api_get_job(
+        &self,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetJobResponse, WarpgateError> {
+        Ok(match services.jobs.get(id.0) {
+            Some(job) => GetJobResponse::Ok(Json(job)),
+            None => GetJobResponse::NotFound,
+        })
+    }
+
+    /// Jobs stop at the next convenient point, so the job
+    /// may still be running for a moment
+    #[oai(
+        path = "/jobs/:id/cancel",
+        method = "post",
+        operation_id = "cancel_job"
+    )]
+    async fn api_cancel_job(
+        &self,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<CancelJobResponse, WarpgateError> {
+        if !services.jobs.cancel(id.0) {
+            return Ok(match services.jobs.get(id.0) {
+                Some(_) => CancelJobResponse::Conflict,
+                None => CancelJobResponse::NotFound,
+            });
+        }
+        Ok(match services.jobs.get(id.0) {
+            Some(job) => CancelJobResponse::Ok(Json(job)),
+            None => CancelJobResponse::NotFound,
+        })
+    }
+}
@@ -1,12 +1,15 @@
 use std::sync::Arc;
 
 use poem::web::Data;
-use poem_openapi::payload::Json;
-use poem_openapi::{ApiResponse, OpenApi};
+use poem_openapi::payload::{Json, PlainText};
+use poem_openapi::{ApiResponse, Object, OpenApi};
 use sea_orm::{DatabaseConnection, EntityTrait};
 use tokio::sync::Mutex;
 use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::Services;
 use warpgate_db_entities::KnownHost;
+use warpgate_protocol_ssh::KnownHosts;
 
 use super::AnySecurityScheme;
 
@@ -18,6 +21,31 @@ enum GetSSHKnownHostsResponse {
     Ok(Json<Vec<KnownHost::Model>>),
 }
 
+#[derive(Object)]
+struct ImportSSHKnownHostsRequest {
+    /// Contents of an OpenSSH known_hosts file
+    known_hosts: String,
+}
+
+#[derive(Object)]
+struct ImportSSHKnownHostsResult {
+    imported: u64,
+    duplicates: u64,
+    skipped: Vec<String>,
+}
+
+#[derive(ApiResponse)]
+enum ImportSSHKnownHostsResponse {
+    #[oai(status = 200)]
+    Ok(Json<ImportSSHKnownHostsResult>),
+}
+
+#[derive(ApiResponse)]
+enum ExportSSHKnownHostsResponse {
+    #[oai(status = 200)]
+    Ok(PlainText<String>),
+}
+
 #[OpenApi]
 impl Api {
     #[oai(
@@ -27,7 +55,7 @@ impl Api {
     )]
     async fn api_ssh_get_all_known_hosts(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         _auth: AnySecurityScheme,
     ) -> Result<GetSSHKnownHostsResponse, WarpgateError> {
         use warpgate_db_entities::KnownHost;
@@ -36,4 +64,45 @@ impl Api {
         let hosts = KnownHost::Entity::find().all(&*db).await?;
         Ok(GetSSHKnownHostsResponse::Ok(Json(hosts)))
     }
+
+    #[oai(
+        path = "/ssh/known-hosts/import",
+        method = "post",
+        operation_id = "import_ssh_known_hosts"
+    )]
+    async fn api_ssh_import_known_hosts(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        services: Data<&Services>,
+        body: Json<ImportSSHKnownHostsRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<ImportSSHKnownHostsResponse, WarpgateError> {
+        let job = services
+            .jobs
+            .start("known_hosts_import", "Importing SSH known hosts");
+        let result = KnownHosts::new(&db).import(&body.known_hosts, &job).await;
+        job.finish(&result);
+        let result = result?;
+        Ok(ImportSSHKnownHostsResponse::Ok(Json(
+            ImportSSHKnownHostsResult {
+                imported: result.imported as u64,
+                duplicates: result.duplicates as u64,
+                skipped: result.skipped,
+            },
+        )))
+    }
+
+    #[oai(
+        path = "/ssh/known-hosts/export",
+        method = "get",
+        operation_id = "export_ssh_known_hosts"
+    )]
+    async fn api_ssh_export_known_hosts(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        _auth: AnySecurityScheme,
+    ) -> Result<ExportSSHKnownHostsResponse, WarpgateError> {
+        let text = KnownHosts::new(&db).export().await?;
+        Ok(ExportSSHKnownHostsResponse::Ok(PlainText(text)))
+    }
 }
@@ -1,13 +1,11 @@
-use std::sync::Arc;
-
 use chrono::{DateTime, Utc};
 use poem::web::Data;
 use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, Object, OpenApi};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
-use tokio::sync::Mutex;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
 use uuid::Uuid;
 use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
 use warpgate_db_entities::LogEntry;
 
 use super::AnySecurityScheme;
@@ -35,7 +33,7 @@ impl Api {
     #[oai(path = "/logs", method = "post", operation_id = "get_logs")]
     async fn api_get_all_logs(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         body: Json<GetLogsRequest>,
         _auth: AnySecurityScheme,
     ) -> Result<GetLogsResponse, WarpgateError> {
@@ -0,0 +1,81 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use warpgate_common::WarpgateError;
+use warpgate_core::{check_configuration, ConfigCheckReport, ReconciliationReport, Services};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetReconciliationReportResponse {
+    #[oai(status = 200)]
+    Ok(Json<ReconciliationReport>),
+
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum RunReconciliationResponse {
+    #[oai(status = 200)]
+    Ok(Json<ReconciliationReport>),
+}
+
+#[derive(ApiResponse)]
+enum CheckConfigurationResponse {
+    #[oai(status = 200)]
+    Ok(Json<ConfigCheckReport>),
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/maintenance/reconciliation",
+        method = "get",
+        operation_id = "get_reconciliation_report"
+    )]
+    async fn api_get_reconciliation_report(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetReconciliationReportResponse, WarpgateError> {
+        let reaper = services.reaper.lock().await;
+        Ok(match reaper.last_report() {
+            Some(report) => GetReconciliationReportResponse::Ok(Json(report.clone())),
+            None => GetReconciliationReportResponse::NotFound,
+        })
+    }
+
+    #[oai(
+        path = "/maintenance/reconciliation",
+        method = "post",
+        operation_id = "run_reconciliation"
+    )]
+    async fn api_run_reconciliation(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<RunReconciliationResponse, WarpgateError> {
+        let report = services.reaper.lock().await.run().await?;
+        Ok(RunReconciliationResponse::Ok(Json(report)))
+    }
+
+    /// Validates certificates, listeners, SSO providers, database migrations
+    /// and target host names. Takes a while, as it reaches out over the network.
+    #[oai(
+        path = "/maintenance/config-check",
+        method = "get",
+        operation_id = "check_configuration"
+    )]
+    async fn api_check_configuration(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<CheckConfigurationResponse, WarpgateError> {
+        Ok(CheckConfigurationResponse::Ok(Json(
+            check_configuration(&services).await,
+        )))
+    }
+}
@@ -1,24 +1,43 @@
 use poem_openapi::auth::ApiKey;
 use poem_openapi::{OpenApi, SecurityScheme};
 
+mod account_lifecycle;
+mod analytics;
+mod auth_failures;
+mod config_history;
+mod direct_credentials;
+mod discovery;
+mod ip_bans;
+mod jobs;
 mod known_hosts_detail;
 mod known_hosts_list;
 mod logs;
+mod maintenance;
+mod notifications;
 mod otp_credentials;
 mod pagination;
 mod parameters;
 mod password_credentials;
+mod policy_simulation;
 mod public_key_credentials;
 pub mod recordings_detail;
+mod replication;
 mod roles;
-mod sessions_detail;
+mod search;
+pub mod sessions_detail;
 pub mod sessions_list;
 mod ssh_keys;
 mod sso_credentials;
+mod target_baselines;
+mod target_capabilities;
+mod target_drain;
+mod target_monitor;
 mod targets;
 mod tickets_detail;
 mod tickets_list;
 pub mod users;
+mod web_sessions;
+mod work_items;
 
 #[derive(SecurityScheme)]
 #[oai(ty = "api_key", key_name = "X-Warpgate-Token", key_in = "header")]
@@ -39,15 +58,35 @@ pub enum AnySecurityScheme {
 
 pub fn get() -> impl OpenApi {
     (
-        (sessions_list::Api, sessions_detail::Api),
+        (
+            sessions_list::Api,
+            sessions_detail::Api,
+            work_items::Api,
+            web_sessions::Api,
+        ),
         recordings_detail::Api,
         (roles::ListApi, roles::DetailApi),
         (tickets_list::Api, tickets_detail::Api),
         (known_hosts_list::Api, known_hosts_detail::Api),
         ssh_keys::Api,
         logs::Api,
-        (targets::ListApi, targets::DetailApi, targets::RolesApi),
-        (users::ListApi, users::DetailApi, users::RolesApi),
+        (
+            targets::ListApi,
+            targets::DetailApi,
+            targets::RolesApi,
+            targets::ShadowApi,
+            target_drain::Api,
+            target_baselines::Api,
+            target_capabilities::Api,
+            target_monitor::Api,
+        ),
+        (
+            users::ListApi,
+            users::DetailApi,
+            users::RolesApi,
+            account_lifecycle::Api,
+            policy_simulation::Api,
+        ),
         (
             password_credentials::ListApi,
             password_credentials::DetailApi,
@@ -57,7 +96,24 @@ pub fn get() -> impl OpenApi {
             public_key_credentials::ListApi,
             public_key_credentials::DetailApi,
         ),
-        (otp_credentials::ListApi, otp_credentials::DetailApi),
-        parameters::Api,
+        (
+            otp_credentials::ListApi,
+            otp_credentials::DetailApi,
+            direct_credentials::Api,
+        ),
+        (parameters::Api, config_history::Api),
+        (
+            analytics::Api,
+            search::Api,
+            auth_failures::Api,
+            ip_bans::Api,
+        ),
+        (
+            replication::Api,
+            maintenance::Api,
+            discovery::Api,
+            jobs::Api,
+            notifications::Api,
+        ),
     )
 }
@@ -0,0 +1,156 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{notification_reader, ConfigChangeAuthor, Services};
+use warpgate_db_entities::Notification::{self, NotificationSeverity};
+
+use super::AnySecurityScheme;
+
+const DEFAULT_LIMIT: u64 = 100;
+
+pub struct Api;
+
+#[derive(Object)]
+struct NotificationItem {
+    id: Uuid,
+    timestamp: DateTime<Utc>,
+    kind: String,
+    severity: NotificationSeverity,
+    message: String,
+    target: Option<String>,
+    resolved: Option<DateTime<Utc>>,
+    /// Whether the current admin has marked it as read
+    read: bool,
+}
+
+impl NotificationItem {
+    fn new(notification: Notification::Model, read: bool) -> Self {
+        Self {
+            id: notification.id,
+            timestamp: notification.timestamp,
+            kind: notification.kind,
+            severity: notification.severity,
+            message: notification.message,
+            target: notification.target,
+            resolved: notification.resolved,
+            read,
+        }
+    }
+}
+
+#[derive(Object)]
+struct UnreadNotificationCount {
+    count: u64,
+}
+
+#[derive(ApiResponse)]
+enum GetNotificationsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<NotificationItem>>),
+}
+
+#[derive(ApiResponse)]
+enum GetUnreadNotificationCountResponse {
+    #[oai(status = 200)]
+    Ok(Json<UnreadNotificationCount>),
+}
+
+#[derive(ApiResponse)]
+enum MarkNotificationsReadResponse {
+    #[oai(status = 200)]
+    Ok,
+}
+
+#[OpenApi]
+impl Api {
+    /// Newest first
+    #[oai(
+        path = "/notifications",
+        method = "get",
+        operation_id = "get_notifications"
+    )]
+    async fn api_get_notifications(
+        &self,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
+        unread: Query<Option<bool>>,
+        limit: Query<Option<u64>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetNotificationsResponse, WarpgateError> {
+        let notifications = services
+            .notifications
+            .list(
+                notification_reader(&author),
+                unread.unwrap_or(false),
+                limit.unwrap_or(DEFAULT_LIMIT),
+            )
+            .await?;
+        Ok(GetNotificationsResponse::Ok(Json(
+            notifications
+                .into_iter()
+                .map(|(notification, read)| NotificationItem::new(notification, read))
+                .collect(),
+        )))
+    }
+
+    #[oai(
+        path = "/notifications/unread-count",
+        method = "get",
+        operation_id = "get_unread_notification_count"
+    )]
+    async fn api_get_unread_notification_count(
+        &self,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetUnreadNotificationCountResponse, WarpgateError> {
+        let count = services
+            .notifications
+            .unread_count(notification_reader(&author))
+            .await?;
+        Ok(GetUnreadNotificationCountResponse::Ok(Json(
+            UnreadNotificationCount { count },
+        )))
+    }
+
+    #[oai(
+        path = "/notifications/:id/read",
+        method = "post",
+        operation_id = "mark_notification_read"
+    )]
+    async fn api_mark_notification_read(
+        &self,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<MarkNotificationsReadResponse, WarpgateError> {
+        services
+            .notifications
+            .mark_read(notification_reader(&author), Some(&[id.0]))
+            .await?;
+        Ok(MarkNotificationsReadResponse::Ok)
+    }
+
+    #[oai(
+        path = "/notifications/read-all",
+        method = "post",
+        operation_id = "mark_all_notifications_read"
+    )]
+    async fn api_mark_all_notifications_read(
+        &self,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
+        _auth: AnySecurityScheme,
+    ) -> Result<MarkNotificationsReadResponse, WarpgateError> {
+        services
+            .notifications
+            .mark_read(notification_reader(&author), None)
+            .await?;
+        Ok(MarkNotificationsReadResponse::Ok)
+    }
+}
@@ -4,8 +4,10 @@ use poem_openapi::{ApiResponse, Object, OpenApi};
 use sea_orm::{EntityTrait, Set};
 use serde::Serialize;
 use warpgate_common::WarpgateError;
-use warpgate_core::Services;
+use warpgate_core::{record_config_change, ConfigChangeAuthor, Services};
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
 use warpgate_db_entities::Parameters;
+use warpgate_db_entities::Parameters::ProtocolList;
 
 use super::AnySecurityScheme;
 
@@ -14,11 +16,19 @@ pub struct Api;
 #[derive(Serialize, Object)]
 struct ParameterValues {
     pub allow_own_credential_management: bool,
+    pub allow_self_service_tickets: bool,
+    /// Longest validity of a self-issued ticket, in seconds
+    pub self_service_ticket_max_validity: i64,
+    /// Protocols of targets that users can issue tickets for, any if not set
+    pub self_service_ticket_protocols: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Object)]
 struct ParameterUpdate {
     pub allow_own_credential_management: Option<bool>,
+    pub allow_self_service_tickets: Option<bool>,
+    pub self_service_ticket_max_validity: Option<i64>,
+    pub self_service_ticket_protocols: Option<Vec<String>>,
 }
 
 #[derive(ApiResponse)]
@@ -31,6 +41,8 @@ enum GetParametersResponse {
 enum UpdateParametersResponse {
     #[oai(status = 201)]
     Done,
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
 }
 
 #[OpenApi]
@@ -46,6 +58,9 @@ impl Api {
 
         Ok(GetParametersResponse::Ok(Json(ParameterValues {
             allow_own_credential_management: parameters.allow_own_credential_management,
+            allow_self_service_tickets: parameters.allow_self_service_tickets,
+            self_service_ticket_max_validity: parameters.self_service_ticket_max_validity,
+            self_service_ticket_protocols: parameters.self_service_ticket_protocols.map(|x| x.0),
         })))
     }
 
@@ -57,21 +72,45 @@ impl Api {
     async fn api_update_parameters(
         &self,
         services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
         body: Json<ParameterUpdate>,
         _auth: AnySecurityScheme,
     ) -> Result<UpdateParametersResponse, WarpgateError> {
         let db = services.db.lock().await;
 
+        let id = Parameters::Entity::get(&db).await?.id;
         let mut am = Parameters::ActiveModel {
-            id: Set(Parameters::Entity::get(&db).await?.id),
+            id: Set(id),
             ..Default::default()
         };
 
         if let Some(value) = body.allow_own_credential_management {
             am.allow_own_credential_management = Set(value);
         };
+        if let Some(value) = body.allow_self_service_tickets {
+            am.allow_self_service_tickets = Set(value);
+        };
+        if let Some(value) = body.self_service_ticket_max_validity {
+            if value <= 0 {
+                return Ok(UpdateParametersResponse::BadRequest(Json(
+                    "self_service_ticket_max_validity".into(),
+                )));
+            }
+            am.self_service_ticket_max_validity = Set(value);
+        };
+        if let Some(value) = &body.self_service_ticket_protocols {
+            am.self_service_ticket_protocols = Set(Some(ProtocolList(value.clone())));
+        };
 
         Parameters::Entity::update(am).exec(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Parameters,
+            id,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
 
         Ok(UpdateParametersResponse::Done)
     }
@@ -0,0 +1,51 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{
+    simulate_policy_changes, PolicyChange, PolicySimulationReport, PolicySimulationResult,
+};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(Object)]
+struct PolicySimulationRequest {
+    changes: Vec<PolicyChange>,
+}
+
+#[derive(ApiResponse)]
+enum SimulatePolicyChangesResponse {
+    #[oai(status = 200)]
+    Ok(Json<PolicySimulationReport>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+}
+
+#[OpenApi]
+impl Api {
+    /// Reports who would gain or lose access to which targets if the changes
+    /// were applied. Nothing is changed.
+    #[oai(
+        path = "/policy-simulation",
+        method = "post",
+        operation_id = "simulate_policy_changes"
+    )]
+    async fn api_simulate_policy_changes(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        body: Json<PolicySimulationRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<SimulatePolicyChangesResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        Ok(match simulate_policy_changes(&db, &body.changes).await? {
+            PolicySimulationResult::Done(report) => SimulatePolicyChangesResponse::Ok(Json(report)),
+            PolicySimulationResult::UnknownObject(message) => {
+                SimulatePolicyChangesResponse::BadRequest(Json(message))
+            }
+        })
+    }
+}
@@ -11,12 +11,14 @@ use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, OpenApi};
 use sea_orm::{DatabaseConnection, EntityTrait};
 use serde_json::json;
-use tokio::fs::File;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::sync::Mutex;
 use tracing::*;
 use uuid::Uuid;
-use warpgate_core::recordings::{AsciiCast, SessionRecordings, TerminalRecordingItem};
+use warpgate_common::WarpgateError;
+use warpgate_core::recordings::{
+    read_asciicast, AsciiCast, QueryRecordingItem, SessionRecordings, TerminalRecordingItem,
+};
+use warpgate_core::{ConfigChangeAuthor, State};
 use warpgate_db_entities::Recording::{self, RecordingKind};
 
 use super::AnySecurityScheme;
@@ -86,46 +88,26 @@ pub async fn api_get_recording_cast(
             .path_for(&recording.session_id, &recording.name)
     };
 
-    let mut response = vec![]; //String::new();
-
-    let mut last_size = (0, 0);
-    let file = File::open(&path).await.map_err(InternalServerError)?;
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-    while let Some(line) = lines.next_line().await.map_err(InternalServerError)? {
-        let entry: TerminalRecordingItem =
-            serde_json::from_str(&line[..]).map_err(InternalServerError)?;
-        let asciicast: AsciiCast = entry.into();
-        response.push(serde_json::to_string(&asciicast).map_err(InternalServerError)?);
-        if let AsciiCast::Header { width, height, .. } = asciicast {
-            last_size = (width, height);
-        }
-    }
-
-    response.insert(
-        0,
-        serde_json::to_string(&AsciiCast::Header {
-            time: 0.0,
-            version: 2,
-            width: last_size.0,
-            height: last_size.1,
-            title: recording.name,
-        })
-        .map_err(InternalServerError)?,
-    );
+    let response = read_asciicast(&path, recording.name)
+        .await
+        .map_err(InternalServerError)?
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(InternalServerError)?;
 
     Ok(response.join("\n"))
 }
 
-#[handler]
-pub async fn api_get_recording_tcpdump(
-    db: Data<&Arc<Mutex<DatabaseConnection>>>,
-    recordings: Data<&Arc<Mutex<SessionRecordings>>>,
-    id: poem::web::Path<Uuid>,
+async fn read_recording_file(
+    db: &Mutex<DatabaseConnection>,
+    recordings: &Mutex<SessionRecordings>,
+    id: Uuid,
+    kind: RecordingKind,
 ) -> poem::Result<Bytes> {
     let db = db.lock().await;
 
-    let recording = Recording::Entity::find_by_id(id.0)
+    let recording = Recording::Entity::find_by_id(id)
         .one(&*db)
         .await
         .map_err(InternalServerError)?;
@@ -134,7 +116,7 @@ pub async fn api_get_recording_tcpdump(
         return Err(NotFoundError.into());
     };
 
-    if recording.kind != RecordingKind::Traffic {
+    if recording.kind != kind {
         return Err(NotFoundError.into());
     }
 
@@ -150,14 +132,101 @@ pub async fn api_get_recording_tcpdump(
     Ok(Bytes::from(content))
 }
 
+#[handler]
+pub async fn api_get_recording_tcpdump(
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+    id: poem::web::Path<Uuid>,
+) -> poem::Result<Bytes> {
+    read_recording_file(&db, &recordings, id.0, RecordingKind::Traffic).await
+}
+
+/// HTTP exchanges as JSON lines
+#[handler]
+pub async fn api_get_recording_http(
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+    id: poem::web::Path<Uuid>,
+) -> poem::Result<Bytes> {
+    read_recording_file(&db, &recordings, id.0, RecordingKind::Http).await
+}
+
+/// SFTP file operations as JSON lines
+#[handler]
+pub async fn api_get_recording_sftp(
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+    id: poem::web::Path<Uuid>,
+) -> poem::Result<Bytes> {
+    read_recording_file(&db, &recordings, id.0, RecordingKind::Sftp).await
+}
+
+/// Database statements as JSON lines
+#[handler]
+pub async fn api_get_recording_queries(
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+    id: poem::web::Path<Uuid>,
+) -> poem::Result<Bytes> {
+    read_recording_file(&db, &recordings, id.0, RecordingKind::Queries).await
+}
+
+/// Marks the point where an admin started watching a live session
+async fn mark_admin_joined(
+    db: &Arc<Mutex<DatabaseConnection>>,
+    state: &Arc<Mutex<State>>,
+    recording_id: Uuid,
+    author: &ConfigChangeAuthor,
+) -> Result<(), WarpgateError> {
+    let Some(recording) = Recording::Entity::find_by_id(recording_id)
+        .one(&*db.lock().await)
+        .await?
+    else {
+        return Ok(());
+    };
+    let Some(session) = state
+        .lock()
+        .await
+        .sessions
+        .get(&recording.session_id)
+        .cloned()
+    else {
+        return Ok(());
+    };
+    let label = match author.0 {
+        Some(ref username) => format!("Admin joined: {username}"),
+        None => "Admin joined".into(),
+    };
+    session.lock().await.handle.add_recording_marker(label);
+    Ok(())
+}
+
 #[handler]
 pub async fn api_get_recording_stream(
     ws: WebSocket,
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    state: Data<&Arc<Mutex<State>>>,
     recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+    author: Data<&ConfigChangeAuthor>,
     id: poem::web::Path<Uuid>,
 ) -> impl IntoResponse {
-    let recordings = recordings.lock().await;
-    let receiver = recordings.subscribe_live(&id).await;
+    let receiver = recordings.lock().await.subscribe_live(&id).await;
+    let kind = match Recording::Entity::find_by_id(id.0)
+        .one(&*db.lock().await)
+        .await
+    {
+        Ok(recording) => recording.map(|x| x.kind),
+        Err(error) => {
+            warn!(%error, "Failed to look up the recording");
+            None
+        }
+    };
+
+    if receiver.is_some() {
+        if let Err(error) = mark_admin_joined(&db, &state, id.0, &author).await {
+            warn!(%error, "Failed to mark the recording");
+        }
+    }
 
     ws.on_upgrade(|socket| async move {
         let (mut sink, _) = socket.split();
@@ -172,9 +241,14 @@ pub async fn api_get_recording_stream(
             tokio::spawn(async move {
                 if let Err(error) = async {
                     while let Ok(data) = receiver.recv().await {
-                        let content: TerminalRecordingItem = serde_json::from_slice(&data)?;
-                        let cast: AsciiCast = content.into();
-                        let msg = serde_json::to_string(&json!({ "data": cast }))?;
+                        let msg = if kind == Some(RecordingKind::Queries) {
+                            let item: QueryRecordingItem = serde_json::from_slice(&data)?;
+                            serde_json::to_string(&json!({ "query": item }))?
+                        } else {
+                            let content: TerminalRecordingItem = serde_json::from_slice(&data)?;
+                            let cast: AsciiCast = content.into();
+                            serde_json::to_string(&json!({ "data": cast }))?
+                        };
                         sink.send(Message::Text(msg)).await?;
                     }
                     sink.send(Message::Text(serde_json::to_string(&json!({
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::{Binary, Json};
+use poem_openapi::{ApiResponse, OpenApi};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::recordings::{
+    Error as RecordingsError, ReplicationManifest, ReplicationStatus, SessionRecordings,
+};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetReplicationStatusResponse {
+    #[oai(status = 200)]
+    Ok(Json<ReplicationStatus>),
+}
+
+#[derive(ApiResponse)]
+enum PutReplicationChunkResponse {
+    #[oai(status = 200)]
+    Ok(Json<ReplicationStatus>),
+}
+
+#[derive(ApiResponse)]
+enum CompleteReplicationResponse {
+    #[oai(status = 201)]
+    Done,
+    #[oai(status = 400)]
+    BadRequest,
+    #[oai(status = 422)]
+    IntegrityCheckFailed,
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/replication/recordings/:id",
+        method = "get",
+        operation_id = "get_recording_replication_status"
+    )]
+    async fn api_get_replication_status(
+        &self,
+        recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetReplicationStatusResponse, WarpgateError> {
+        let receiver = recordings.lock().await.replication_receiver();
+        let status = receiver
+            .replication_status(&id)
+            .await
+            .map_err(WarpgateError::other)?;
+        Ok(GetReplicationStatusResponse::Ok(Json(status)))
+    }
+
+    #[oai(
+        path = "/replication/recordings/:id/data",
+        method = "put",
+        operation_id = "put_recording_replication_chunk"
+    )]
+    async fn api_put_replication_chunk(
+        &self,
+        recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+        id: Path<Uuid>,
+        offset: Query<u64>,
+        body: Binary<Vec<u8>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<PutReplicationChunkResponse, WarpgateError> {
+        let receiver = recordings.lock().await.replication_receiver();
+        let status = receiver
+            .append_replicated_chunk(&id, *offset, &body)
+            .await
+            .map_err(WarpgateError::other)?;
+        Ok(PutReplicationChunkResponse::Ok(Json(status)))
+    }
+
+    #[oai(
+        path = "/replication/recordings/:id/complete",
+        method = "post",
+        operation_id = "complete_recording_replication"
+    )]
+    async fn api_complete_replication(
+        &self,
+        recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+        id: Path<Uuid>,
+        body: Json<ReplicationManifest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<CompleteReplicationResponse, WarpgateError> {
+        if body.recording.id != *id {
+            return Ok(CompleteReplicationResponse::BadRequest);
+        }
+
+        let receiver = recordings.lock().await.replication_receiver();
+        match receiver.complete_replication(body.0).await {
+            Ok(()) => Ok(CompleteReplicationResponse::Done),
+            Err(RecordingsError::IntegrityCheckFailed) => {
+                Ok(CompleteReplicationResponse::IntegrityCheckFailed)
+            }
+            Err(RecordingsError::InvalidPath) => Ok(CompleteReplicationResponse::BadRequest),
+            Err(error) => Err(WarpgateError::other(error)),
+        }
+    }
+}
@@ -12,7 +12,13 @@ use tokio::sync::Mutex;
 use uuid::Uuid;
 use warpgate_common::{Role as RoleConfig, WarpgateError};
 use warpgate_core::consts::BUILTIN_ADMIN_ROLE_NAME;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{record_config_change, ConfigChangeAuthor, Services};
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
 use warpgate_db_entities::Role;
+use warpgate_db_entities::RolePortalSettings::{
+    self, PortalSection, PortalSectionList, TargetIdList,
+};
 
 use super::AnySecurityScheme;
 
@@ -42,7 +48,7 @@ impl ListApi {
     #[oai(path = "/roles", method = "get", operation_id = "get_roles")]
     async fn api_get_all_roles(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         search: Query<Option<String>>,
         _auth: AnySecurityScheme,
     ) -> Result<GetRolesResponse, WarpgateError> {
@@ -66,6 +72,7 @@ impl ListApi {
     async fn api_create_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
         body: Json<RoleDataRequest>,
         _auth: AnySecurityScheme,
     ) -> Result<CreateRoleResponse, WarpgateError> {
@@ -83,6 +90,14 @@ impl ListApi {
         };
 
         let role = values.insert(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Role,
+            role.id,
+            ConfigChangeAction::Create,
+            &author,
+        )
+        .await?;
 
         Ok(CreateRoleResponse::Created(Json(role.into())))
     }
@@ -116,6 +131,31 @@ enum DeleteRoleResponse {
     NotFound,
 }
 
+/// How the user portal looks for members of the role
+#[derive(Object)]
+struct RolePortalSettingsData {
+    /// Landing page sections, in display order
+    sections: Vec<PortalSection>,
+    /// Targets the role grants without listing them
+    hidden_targets: Vec<Uuid>,
+}
+
+#[derive(ApiResponse)]
+enum GetRolePortalSettingsResponse {
+    #[oai(status = 200)]
+    Ok(Json<RolePortalSettingsData>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum UpdateRolePortalSettingsResponse {
+    #[oai(status = 200)]
+    Ok(Json<RolePortalSettingsData>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
 pub struct DetailApi;
 
 #[OpenApi]
@@ -141,6 +181,7 @@ impl DetailApi {
     async fn api_update_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
         body: Json<RoleDataRequest>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -158,6 +199,14 @@ impl DetailApi {
         let mut model: Role::ActiveModel = role.into();
         model.name = Set(body.name.clone());
         let role = model.update(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Role,
+            role.id,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
 
         Ok(UpdateRoleResponse::Ok(Json(role.into())))
     }
@@ -166,6 +215,8 @@ impl DetailApi {
     async fn api_delete_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
     ) -> Result<DeleteRoleResponse, WarpgateError> {
@@ -179,7 +230,94 @@ impl DetailApi {
             return Ok(DeleteRoleResponse::Forbidden);
         }
 
+        let role_id = role.id;
         role.delete(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Role,
+            role_id,
+            ConfigChangeAction::Delete,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
+
         Ok(DeleteRoleResponse::Deleted)
     }
+
+    /// Empty when the role doesn't customize the portal
+    #[oai(
+        path = "/role/:id/portal",
+        method = "get",
+        operation_id = "get_role_portal_settings"
+    )]
+    async fn api_get_role_portal_settings(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetRolePortalSettingsResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        if Role::Entity::find_by_id(id.0).one(&*db).await?.is_none() {
+            return Ok(GetRolePortalSettingsResponse::NotFound);
+        }
+        let settings = RolePortalSettings::Entity::find_by_id(id.0)
+            .one(&*db)
+            .await?;
+
+        Ok(GetRolePortalSettingsResponse::Ok(Json(match settings {
+            Some(settings) => RolePortalSettingsData {
+                sections: settings.sections.0,
+                hidden_targets: settings.hidden_targets.0,
+            },
+            None => RolePortalSettingsData {
+                sections: vec![],
+                hidden_targets: vec![],
+            },
+        })))
+    }
+
+    #[oai(
+        path = "/role/:id/portal",
+        method = "put",
+        operation_id = "update_role_portal_settings"
+    )]
+    async fn api_update_role_portal_settings(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        body: Json<RolePortalSettingsData>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<UpdateRolePortalSettingsResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        if Role::Entity::find_by_id(id.0).one(&*db).await?.is_none() {
+            return Ok(UpdateRolePortalSettingsResponse::NotFound);
+        }
+
+        RolePortalSettings::Entity::delete_by_id(id.0)
+            .exec(&*db)
+            .await?;
+        if !body.sections.is_empty() || !body.hidden_targets.is_empty() {
+            RolePortalSettings::ActiveModel {
+                role_id: Set(id.0),
+                sections: Set(PortalSectionList(body.sections.clone())),
+                hidden_targets: Set(TargetIdList(body.hidden_targets.clone())),
+            }
+            .insert(&*db)
+            .await?;
+        }
+        record_config_change(
+            &db,
+            ConfigObjectKind::Role,
+            id.0,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+
+        Ok(UpdateRolePortalSettingsResponse::Ok(Json(body.0)))
+    }
 }
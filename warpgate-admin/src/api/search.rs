@@ -0,0 +1,38 @@
+use poem::web::Data;
+use poem_openapi::param::Query;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use warpgate_common::WarpgateError;
+use warpgate_core::{SearchResult, Services};
+
+use super::AnySecurityScheme;
+
+const DEFAULT_LIMIT: usize = 20;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum SearchResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<SearchResult>>),
+}
+
+#[OpenApi]
+impl Api {
+    /// Targets, users, recent sessions and admin UI pages matching
+    /// all words of the query, best matches first
+    #[oai(path = "/search", method = "get", operation_id = "search")]
+    async fn api_search(
+        &self,
+        services: Data<&Services>,
+        q: Query<String>,
+        limit: Query<Option<usize>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<SearchResponse, WarpgateError> {
+        let results = services
+            .search_index
+            .search(&services, &q, limit.unwrap_or(DEFAULT_LIMIT))
+            .await?;
+        Ok(SearchResponse::Ok(Json(results)))
+    }
+}
@@ -1,15 +1,29 @@
 use std::sync::Arc;
 
+use futures::{SinkExt, StreamExt};
+use poem::error::{InternalServerError, NotFoundError};
+use poem::web::websocket::{Message, WebSocket};
 use poem::web::Data;
-use poem_openapi::param::Path;
-use poem_openapi::payload::Json;
+use poem::{handler, IntoResponse};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::{Attachment, AttachmentType, Json};
 use poem_openapi::{ApiResponse, OpenApi};
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::Mutex;
+use tracing::*;
 use uuid::Uuid;
 use warpgate_common::WarpgateError;
-use warpgate_core::{SessionSnapshot, State};
-use warpgate_db_entities::{Recording, Session};
+use warpgate_core::recordings::SessionRecordings;
+use warpgate_core::{
+    AdminIdentity, ConfigChangeAuthor, SessionBundle, SessionSnapshot, SharedConfig, State,
+};
+use warpgate_db_entities::Session::SessionTerminationReason;
+use warpgate_db_entities::{
+    DatabaseSessionDetails, DropBoxEvent, HttpSessionDetails, Recording, Session, SshSessionDetails,
+};
+use warpgate_protocol_ssh::SESSION_BUNDLE_SIGNATURE_NAMESPACE;
 
 use super::AnySecurityScheme;
 
@@ -30,6 +44,12 @@ enum GetSessionRecordingsResponse {
     Ok(Json<Vec<Recording::Model>>),
 }
 
+#[derive(ApiResponse)]
+enum GetSessionDropBoxEventsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<DropBoxEvent::Model>>),
+}
+
 #[derive(ApiResponse)]
 enum CloseSessionResponse {
     #[oai(status = 201)]
@@ -44,17 +64,32 @@ impl Api {
     async fn api_get_session(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        state: Data<&Arc<Mutex<State>>>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
     ) -> Result<GetSessionResponse, WarpgateError> {
         let db = db.lock().await;
+        let Some(session) = Session::Entity::find_by_id(id.0).one(&*db).await? else {
+            return Ok(GetSessionResponse::NotFound);
+        };
 
-        let session = Session::Entity::find_by_id(id.0).one(&*db).await?;
+        let mut snapshot: SessionSnapshot = session.into();
+        snapshot.ssh_details = SshSessionDetails::Entity::find_by_id(id.0)
+            .one(&*db)
+            .await?;
+        snapshot.http_details = HttpSessionDetails::Entity::find_by_id(id.0)
+            .one(&*db)
+            .await?;
+        snapshot.database_details = DatabaseSessionDetails::Entity::find_by_id(id.0)
+            .one(&*db)
+            .await?;
+        drop(db);
 
-        match session {
-            Some(session) => Ok(GetSessionResponse::Ok(Json(session.into()))),
-            None => Ok(GetSessionResponse::NotFound),
+        let session_state = state.lock().await.sessions.get(&id.0).cloned();
+        if let Some(session_state) = session_state {
+            snapshot.channels = Some(session_state.lock().await.channels.snapshot());
         }
+        Ok(GetSessionResponse::Ok(Json(snapshot)))
     }
 
     #[oai(
@@ -77,6 +112,26 @@ impl Api {
         Ok(GetSessionRecordingsResponse::Ok(Json(recordings)))
     }
 
+    #[oai(
+        path = "/sessions/:id/drop-box-events",
+        method = "get",
+        operation_id = "get_session_drop_box_events"
+    )]
+    async fn api_get_session_drop_box_events(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetSessionDropBoxEventsResponse, WarpgateError> {
+        let db = db.lock().await;
+        let events = DropBoxEvent::Entity::find()
+            .order_by_asc(DropBoxEvent::Column::Timestamp)
+            .filter(DropBoxEvent::Column::SessionId.eq(id.0))
+            .all(&*db)
+            .await?;
+        Ok(GetSessionDropBoxEventsResponse::Ok(Json(events)))
+    }
+
     #[oai(
         path = "/sessions/:id/close",
         method = "post",
@@ -86,16 +141,127 @@ impl Api {
         &self,
         state: Data<&Arc<Mutex<State>>>,
         id: Path<Uuid>,
+        /// Shown to the user before they are disconnected
+        message: Query<Option<String>>,
         _auth: AnySecurityScheme,
     ) -> Result<CloseSessionResponse, WarpgateError> {
         let state = state.lock().await;
 
         if let Some(s) = state.sessions.get(&id) {
             let mut session = s.lock().await;
-            session.handle.close();
+            let message = message.0.filter(|x| !x.trim().is_empty());
+            session.terminate_with_message(SessionTerminationReason::AdminAbort, message);
             Ok(CloseSessionResponse::Ok)
         } else {
             Ok(CloseSessionResponse::NotFound)
         }
     }
 }
+
+/// Read-only live terminal output of an SSH session, starting with
+/// the current terminal sizes and recent output. Works without recordings,
+/// and any number of admins can watch at once.
+#[handler]
+pub async fn api_get_session_shadow(
+    ws: WebSocket,
+    state: Data<&Arc<Mutex<State>>>,
+    admin: AdminIdentity,
+    id: poem::web::Path<Uuid>,
+) -> poem::Result<impl IntoResponse> {
+    let Some(session) = state.lock().await.sessions.get(&id.0).cloned() else {
+        return Err(NotFoundError.into());
+    };
+    let (snapshot, mut receiver) = {
+        let mut session = session.lock().await;
+        session
+            .handle
+            .add_recording_marker(format!("Admin started shadowing: {admin}"));
+        session.tap.subscribe()
+    };
+    let id = id.0;
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let (mut sink, mut stream) = socket.split();
+        info!(session=%id, %admin, "Admin started shadowing the session");
+
+        if let Err(error) = async {
+            for event in snapshot {
+                sink.send(Message::Text(serde_json::to_string(&event)?))
+                    .await?;
+            }
+            loop {
+                tokio::select! {
+                    event = receiver.recv() => match event {
+                        Ok(event) => {
+                            sink.send(Message::Text(serde_json::to_string(&event)?))
+                                .await?;
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            warn!(session=%id, skipped, "Shadowing admin fell behind");
+                        }
+                        Err(RecvError::Closed) => {
+                            sink.send(Message::Text(serde_json::to_string(&json!({
+                                "type": "end",
+                            }))?))
+                            .await?;
+                            break;
+                        }
+                    },
+                    // Input is ignored, this only notices the admin leaving
+                    message = stream.next() => {
+                        if !matches!(message, Some(Ok(_))) {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await
+        {
+            error!(%error, "Shadow stream error:");
+        }
+
+        info!(session=%id, %admin, "Admin stopped shadowing the session");
+    }))
+}
+
+/// A tar archive with everything stored about the session, with the
+/// manifest signed by the SSH host key. Check it with
+/// `ssh-keygen -Y verify -n warpgate-session-bundle`.
+#[handler]
+pub async fn api_get_session_bundle(
+    db: Data<&Arc<Mutex<DatabaseConnection>>>,
+    recordings: Data<&Arc<Mutex<SessionRecordings>>>,
+    config: Data<&Arc<SharedConfig>>,
+    author: Data<&ConfigChangeAuthor>,
+    id: poem::web::Path<Uuid>,
+) -> poem::Result<Attachment<Vec<u8>>> {
+    let bundle = {
+        let db = db.lock().await;
+        let recordings = recordings.lock().await;
+        SessionBundle::collect(&db, &recordings, id.0)
+            .await
+            .map_err(InternalServerError)?
+    };
+    let Some(bundle) = bundle else {
+        return Err(NotFoundError.into());
+    };
+
+    let ConfigChangeAuthor(admin) = author.0;
+    let config = config.load();
+    let archive = bundle
+        .finish(admin.as_deref(), |manifest| {
+            Ok(warpgate_protocol_ssh::sign_with_host_key(
+                &config,
+                SESSION_BUNDLE_SIGNATURE_NAMESPACE,
+                manifest,
+            )?)
+        })
+        .map_err(InternalServerError)?;
+    warn!(session=%id.0, ?admin, "Admin exported the session bundle");
+
+    Ok(Attachment::new(archive)
+        .attachment_type(AttachmentType::Attachment)
+        .filename(format!("session-{}.tar", id.0)))
+}
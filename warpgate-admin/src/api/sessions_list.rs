@@ -8,9 +8,11 @@ use poem::{handler, IntoResponse};
 use poem_openapi::param::Query;
 use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, OpenApi};
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder};
 use tokio::sync::Mutex;
+use warpgate_core::db::ReadOnlyDatabase;
 use warpgate_core::{SessionSnapshot, State};
+use warpgate_db_entities::Session::SessionTerminationReason;
 
 use super::pagination::{PaginatedResponse, PaginationParams};
 use super::AnySecurityScheme;
@@ -32,13 +34,15 @@ enum CloseAllSessionsResponse {
 #[OpenApi]
 impl Api {
     #[oai(path = "/sessions", method = "get", operation_id = "get_sessions")]
+    #[allow(clippy::too_many_arguments)]
     async fn api_get_all_sessions(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         offset: Query<Option<u64>>,
         limit: Query<Option<u64>>,
         active_only: Query<Option<bool>>,
         logged_in_only: Query<Option<bool>>,
+        termination_reason: Query<Option<SessionTerminationReason>>,
         _auth: AnySecurityScheme,
     ) -> poem::Result<GetSessionsResponse> {
         use warpgate_db_entities::Session;
@@ -52,6 +56,9 @@ impl Api {
         if logged_in_only.unwrap_or(false) {
             q = q.filter(Session::Column::Username.is_not_null());
         }
+        if let Some(reason) = *termination_reason {
+            q = q.filter(Session::Column::TerminationReason.eq(reason));
+        }
 
         Ok(GetSessionsResponse::Ok(Json(
             PaginatedResponse::new(
@@ -82,7 +89,7 @@ impl Api {
 
         for s in state.sessions.values() {
             let mut session = s.lock().await;
-            session.handle.close();
+            session.terminate(SessionTerminationReason::AdminAbort);
         }
 
         session.purge();
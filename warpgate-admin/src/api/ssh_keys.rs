@@ -1,12 +1,14 @@
 use std::sync::Arc;
 
 use poem::web::Data;
+use poem_openapi::param::Path;
 use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, Object, OpenApi};
-use russh::keys::PublicKeyBase64;
+use russh::keys::{PrivateKey, PublicKeyBase64};
 use serde::Serialize;
-use tokio::sync::Mutex;
-use warpgate_common::{WarpgateConfig, WarpgateError};
+use warpgate_common::{SshKeyAlgorithm, WarpgateError};
+use warpgate_core::SharedConfig;
+use warpgate_protocol_ssh::ClientKey;
 
 use super::AnySecurityScheme;
 
@@ -14,16 +16,75 @@ pub struct Api;
 
 #[derive(Serialize, Object)]
 struct SSHKey {
+    pub name: String,
     pub kind: String,
     pub public_key_base64: String,
 }
 
+impl From<ClientKey> for SSHKey {
+    fn from(k: ClientKey) -> Self {
+        SSHKey {
+            name: k.name,
+            kind: k.key.algorithm().to_string(),
+            public_key_base64: k.key.public_key_base64(),
+        }
+    }
+}
+
+/// Targets using certificate authentication have to trust this key
+#[derive(Serialize, Object)]
+struct SSHCAKey {
+    pub kind: String,
+    pub public_key_openssh: String,
+}
+
+impl TryFrom<PrivateKey> for SSHCAKey {
+    type Error = WarpgateError;
+
+    fn try_from(key: PrivateKey) -> Result<Self, WarpgateError> {
+        Ok(SSHCAKey {
+            kind: key.algorithm().to_string(),
+            public_key_openssh: key.public_key().to_openssh().map_err(anyhow::Error::from)?,
+        })
+    }
+}
+
+#[derive(Object)]
+struct GenerateSSHKeyRequest {
+    name: String,
+    algorithm: SshKeyAlgorithm,
+}
+
 #[derive(ApiResponse)]
 enum GetSSHOwnKeysResponse {
     #[oai(status = 200)]
     Ok(Json<Vec<SSHKey>>),
 }
 
+#[derive(ApiResponse)]
+enum GenerateSSHOwnKeyResponse {
+    #[oai(status = 201)]
+    Created(Json<SSHKey>),
+
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+}
+
+#[derive(ApiResponse)]
+enum DeleteSSHOwnKeyResponse {
+    #[oai(status = 204)]
+    Deleted,
+
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum GetSSHCAKeyResponse {
+    #[oai(status = 200)]
+    Ok(Json<SSHCAKey>),
+}
+
 #[OpenApi]
 impl Api {
     #[oai(
@@ -33,19 +94,83 @@ impl Api {
     )]
     async fn api_ssh_get_own_keys(
         &self,
-        config: Data<&Arc<Mutex<WarpgateConfig>>>,
+        config: Data<&Arc<SharedConfig>>,
         _auth: AnySecurityScheme,
     ) -> Result<GetSSHOwnKeysResponse, WarpgateError> {
-        let config = config.lock().await;
+        let config = config.load();
         let keys = warpgate_protocol_ssh::load_client_keys(&config)?;
 
-        let keys = keys
-            .into_iter()
-            .map(|k| SSHKey {
-                kind: k.algorithm().to_string(),
-                public_key_base64: k.public_key_base64(),
-            })
-            .collect();
+        let keys = keys.into_iter().map(Into::into).collect();
         Ok(GetSSHOwnKeysResponse::Ok(Json(keys)))
     }
+
+    #[oai(
+        path = "/ssh/own-keys",
+        method = "post",
+        operation_id = "generate_ssh_own_key"
+    )]
+    async fn api_ssh_generate_own_key(
+        &self,
+        config: Data<&Arc<SharedConfig>>,
+        body: Json<GenerateSSHKeyRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GenerateSSHOwnKeyResponse, WarpgateError> {
+        let config = config.load();
+        let body = body.0;
+        let key = tokio::task::spawn_blocking(move || {
+            warpgate_protocol_ssh::generate_client_key(&config, &body.name, body.algorithm)
+        })
+        .await
+        .map_err(anyhow::Error::from)?;
+
+        Ok(match key {
+            Ok(key) => GenerateSSHOwnKeyResponse::Created(Json(key.into())),
+            Err(error) => GenerateSSHOwnKeyResponse::BadRequest(Json(error.to_string())),
+        })
+    }
+
+    #[oai(
+        path = "/ssh/own-keys/:name",
+        method = "delete",
+        operation_id = "delete_ssh_own_key"
+    )]
+    async fn api_ssh_delete_own_key(
+        &self,
+        config: Data<&Arc<SharedConfig>>,
+        name: Path<String>,
+        _auth: AnySecurityScheme,
+    ) -> Result<DeleteSSHOwnKeyResponse, WarpgateError> {
+        let config = config.load();
+        let keys = warpgate_protocol_ssh::load_client_keys(&config)?;
+        if !keys.iter().any(|k| k.name == *name) {
+            return Ok(DeleteSSHOwnKeyResponse::NotFound);
+        }
+
+        warpgate_protocol_ssh::delete_client_key(&config, &name)?;
+        Ok(DeleteSSHOwnKeyResponse::Deleted)
+    }
+
+    #[oai(path = "/ssh/ca", method = "get", operation_id = "get_ssh_ca_key")]
+    async fn api_ssh_get_ca_key(
+        &self,
+        config: Data<&Arc<SharedConfig>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetSSHCAKeyResponse, WarpgateError> {
+        let key = warpgate_protocol_ssh::load_ssh_ca_key(&config.load())?;
+        Ok(GetSSHCAKeyResponse::Ok(Json(key.try_into()?)))
+    }
+
+    #[oai(
+        path = "/ssh/ca/rotate",
+        method = "post",
+        operation_id = "rotate_ssh_ca_key"
+    )]
+    async fn api_ssh_rotate_ca_key(
+        &self,
+        config: Data<&Arc<SharedConfig>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetSSHCAKeyResponse, WarpgateError> {
+        let key = warpgate_protocol_ssh::rotate_ssh_ca_key(&config.load())?;
+        Ok(GetSSHCAKeyResponse::Ok(Json(key.try_into()?)))
+    }
 }
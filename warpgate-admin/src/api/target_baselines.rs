@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, ModelTrait, QueryFilter, QueryOrder};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_db_entities::TargetBaseline;
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetTargetBaselinesResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<TargetBaseline::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum DeleteTargetBaselineResponse {
+    #[oai(status = 204)]
+    Deleted,
+
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    /// Host keys, certificates and TLS parameters that target addresses
+    /// are expected to present
+    #[oai(
+        path = "/target-baselines",
+        method = "get",
+        operation_id = "get_target_baselines"
+    )]
+    async fn api_get_target_baselines(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        target_name: Query<Option<String>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetTargetBaselinesResponse, WarpgateError> {
+        let db = db.lock().await;
+        let mut q = TargetBaseline::Entity::find()
+            .order_by_asc(TargetBaseline::Column::Host)
+            .order_by_asc(TargetBaseline::Column::Port);
+        if let Some(ref target_name) = *target_name {
+            q = q.filter(TargetBaseline::Column::TargetName.eq(target_name));
+        }
+        Ok(GetTargetBaselinesResponse::Ok(Json(q.all(&*db).await?)))
+    }
+
+    /// The next connection's value becomes the new baseline,
+    /// e.g. after a planned certificate rotation
+    #[oai(
+        path = "/target-baselines/:id",
+        method = "delete",
+        operation_id = "delete_target_baseline"
+    )]
+    async fn api_delete_target_baseline(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<DeleteTargetBaselineResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        match TargetBaseline::Entity::find_by_id(id.0).one(&*db).await? {
+            Some(baseline) => {
+                baseline.delete(&*db).await?;
+                Ok(DeleteTargetBaselineResponse::Deleted)
+            }
+            None => Ok(DeleteTargetBaselineResponse::NotFound),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{ConfigProvider, NegotiatedCapabilities, Services};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(Object)]
+struct TargetCapabilitiesReport {
+    id: Uuid,
+    name: String,
+    protocol: String,
+    /// Missing if Warpgate hasn't connected to the target since it started
+    capabilities: Option<NegotiatedCapabilities>,
+}
+
+#[derive(ApiResponse)]
+enum GetTargetCapabilitiesResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<TargetCapabilitiesReport>>),
+}
+
+#[OpenApi]
+impl Api {
+    /// TLS versions, auth methods and other protocol features that were
+    /// negotiated on the last session or test of each target
+    #[oai(
+        path = "/target-capabilities",
+        method = "get",
+        operation_id = "get_target_capabilities"
+    )]
+    async fn api_get_target_capabilities(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetTargetCapabilitiesResponse, WarpgateError> {
+        let targets = services.config_provider.lock().await.list_targets().await?;
+        let reports = targets
+            .into_iter()
+            .map(|target| TargetCapabilitiesReport {
+                capabilities: services.target_capabilities.get(&target.name),
+                protocol: target.options.protocol_name().to_owned(),
+                id: target.id,
+                name: target.name,
+            })
+            .collect();
+        Ok(GetTargetCapabilitiesResponse::Ok(Json(reports)))
+    }
+}
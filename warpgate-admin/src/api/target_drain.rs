@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{State, TargetSession};
+use warpgate_db_entities::Target;
+
+use super::AnySecurityScheme;
+
+const DEFAULT_DRAIN_MESSAGE: &str =
+    "This target is going down for maintenance. Please save your work and disconnect.";
+
+#[derive(Object)]
+struct TargetSessions {
+    /// New sessions are rejected while the target is draining
+    draining: bool,
+    /// Sessions turned away by the target's session limit since Warpgate started
+    rejected_sessions: u64,
+    sessions: Vec<TargetSession>,
+}
+
+#[derive(Object)]
+struct DrainTargetRequest {
+    /// Shown to the users of the target's active sessions
+    message: Option<String>,
+}
+
+#[derive(Object)]
+struct DrainTargetResult {
+    /// Sessions that have been shown the message - only SSH sessions can be notified
+    notified: u32,
+    sessions: Vec<TargetSession>,
+}
+
+#[derive(ApiResponse)]
+enum GetTargetSessionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<TargetSessions>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum DrainTargetResponse {
+    #[oai(status = 200)]
+    Ok(Json<DrainTargetResult>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum StopDrainingTargetResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+/// Stored on the target so that it survives restarts and applies to all
+/// instances. Sessions that are already connected are left alone.
+/// Returns `false` if the target doesn't exist.
+async fn set_draining(
+    db: &Arc<Mutex<DatabaseConnection>>,
+    id: Uuid,
+    draining: bool,
+) -> Result<bool, WarpgateError> {
+    let db = db.lock().await;
+    let Some(target) = Target::Entity::find_by_id(id).one(&*db).await? else {
+        return Ok(false);
+    };
+    let mut model: Target::ActiveModel = target.into();
+    model.draining = Set(draining);
+    model.update(&*db).await?;
+    Ok(true)
+}
+
+pub struct Api;
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/targets/:id/sessions",
+        method = "get",
+        operation_id = "get_target_sessions"
+    )]
+    async fn api_get_target_sessions(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        state: Data<&Arc<Mutex<State>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetTargetSessionsResponse, WarpgateError> {
+        let Some(target) = Target::Entity::find_by_id(id.0)
+            .one(&*db.lock().await)
+            .await?
+        else {
+            return Ok(GetTargetSessionsResponse::NotFound);
+        };
+
+        let rejected_sessions = state.lock().await.rejected_sessions(id.0);
+        Ok(GetTargetSessionsResponse::Ok(Json(TargetSessions {
+            draining: target.draining,
+            rejected_sessions,
+            sessions: State::target_sessions(&state, id.0).await,
+        })))
+    }
+
+    /// Stops new sessions to the target and warns its current users
+    #[oai(
+        path = "/targets/:id/drain",
+        method = "post",
+        operation_id = "drain_target"
+    )]
+    async fn api_drain_target(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        state: Data<&Arc<Mutex<State>>>,
+        id: Path<Uuid>,
+        body: Json<DrainTargetRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<DrainTargetResponse, WarpgateError> {
+        if !set_draining(&db, id.0, true).await? {
+            return Ok(DrainTargetResponse::NotFound);
+        }
+
+        let message = body
+            .0
+            .message
+            .filter(|x| !x.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_DRAIN_MESSAGE.to_owned());
+
+        let notified = State::notify_target_sessions(&state, id.0, &message).await;
+        info!(target=%id.0, notified, "Draining target");
+
+        Ok(DrainTargetResponse::Ok(Json(DrainTargetResult {
+            notified: notified as u32,
+            sessions: State::target_sessions(&state, id.0).await,
+        })))
+    }
+
+    #[oai(
+        path = "/targets/:id/drain",
+        method = "delete",
+        operation_id = "stop_draining_target"
+    )]
+    async fn api_stop_draining_target(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<StopDrainingTargetResponse, WarpgateError> {
+        if !set_draining(&db, id.0, false).await? {
+            return Ok(StopDrainingTargetResponse::NotFound);
+        }
+        info!(target=%id.0, "Stopped draining target");
+
+        Ok(StopDrainingTargetResponse::Deleted)
+    }
+}
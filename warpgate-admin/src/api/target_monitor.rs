@@ -0,0 +1,34 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use warpgate_common::WarpgateError;
+use warpgate_core::{Services, TargetMonitorStatus};
+
+use super::AnySecurityScheme;
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetTargetMonitorResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<TargetMonitorStatus>>),
+}
+
+#[OpenApi]
+impl Api {
+    /// Results of the scheduled connection tests since Warpgate started
+    #[oai(
+        path = "/target-monitor",
+        method = "get",
+        operation_id = "get_target_monitor"
+    )]
+    async fn api_get_target_monitor(
+        &self,
+        services: Data<&Services>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetTargetMonitorResponse, WarpgateError> {
+        Ok(GetTargetMonitorResponse::Ok(Json(
+            services.target_monitor.list(),
+        )))
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 use poem::web::Data;
@@ -10,8 +12,13 @@ use sea_orm::{
 };
 use tokio::sync::Mutex;
 use uuid::Uuid;
-use warpgate_common::{Role as RoleConfig, Target as TargetConfig, TargetOptions, WarpgateError};
+use warpgate_common::{
+    Role as RoleConfig, Target as TargetConfig, TargetMonitorOptions, TargetOptions, WarpgateError,
+};
 use warpgate_core::consts::BUILTIN_ADMIN_ROLE_NAME;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{record_config_change, ConfigChangeAuthor, Services, ShadowReport};
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
 use warpgate_db_entities::Target::TargetKind;
 use warpgate_db_entities::{Role, Target, TargetRoleAssignment};
 
@@ -21,6 +28,22 @@ use super::AnySecurityScheme;
 struct TargetDataRequest {
     name: String,
     options: TargetOptions,
+    #[oai(default)]
+    honeypot: bool,
+    #[oai(default)]
+    host_overrides: BTreeMap<String, IpAddr>,
+    max_concurrent_sessions: Option<u32>,
+    session_limit_message: Option<String>,
+    monitor: Option<TargetMonitorOptions>,
+    idle_timeout_seconds: Option<u32>,
+}
+
+impl TargetDataRequest {
+    fn session_limit_message(&self) -> Option<String> {
+        self.session_limit_message
+            .clone()
+            .filter(|x| !x.trim().is_empty())
+    }
 }
 
 #[derive(ApiResponse)]
@@ -28,6 +51,7 @@ enum GetTargetsResponse {
     #[oai(status = 200)]
     Ok(Json<Vec<TargetConfig>>),
 }
+#[allow(clippy::large_enum_variant)]
 #[derive(ApiResponse)]
 enum CreateTargetResponse {
     #[oai(status = 201)]
@@ -44,7 +68,7 @@ impl ListApi {
     #[oai(path = "/targets", method = "get", operation_id = "get_targets")]
     async fn api_get_all_targets(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         search: Query<Option<String>>,
         _auth: AnySecurityScheme,
     ) -> Result<GetTargetsResponse, WarpgateError> {
@@ -70,6 +94,8 @@ impl ListApi {
     async fn api_create_target(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
         body: Json<TargetDataRequest>,
         _auth: AnySecurityScheme,
     ) -> Result<CreateTargetResponse, WarpgateError> {
@@ -88,9 +114,33 @@ impl ListApi {
             name: Set(body.name.clone()),
             kind: Set((&body.options).into()),
             options: Set(serde_json::to_value(body.options.clone()).map_err(WarpgateError::from)?),
+            honeypot: Set(body.honeypot),
+            host_overrides: Set(Some(
+                serde_json::to_value(&body.host_overrides).map_err(WarpgateError::from)?,
+            )),
+            max_concurrent_sessions: Set(body.max_concurrent_sessions.map(|x| x as i32)),
+            session_limit_message: Set(body.session_limit_message()),
+            monitor: Set(body
+                .monitor
+                .as_ref()
+                .map(serde_json::to_value)
+                .transpose()
+                .map_err(WarpgateError::from)?),
+            idle_timeout_seconds: Set(body.idle_timeout_seconds.map(|x| x as i32)),
+            draining: Set(false),
         };
 
         let target = values.insert(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            target.id,
+            ConfigChangeAction::Create,
+            &author,
+        )
+        .await?;
+
+        services.authorization_cache.invalidate();
 
         Ok(CreateTargetResponse::Created(Json(
             target.try_into().map_err(WarpgateError::from)?,
@@ -98,6 +148,7 @@ impl ListApi {
     }
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(ApiResponse)]
 enum GetTargetResponse {
     #[oai(status = 200)]
@@ -106,6 +157,7 @@ enum GetTargetResponse {
     NotFound,
 }
 
+#[allow(clippy::large_enum_variant)]
 #[derive(ApiResponse)]
 enum UpdateTargetResponse {
     #[oai(status = 200)]
@@ -152,6 +204,8 @@ impl DetailApi {
     async fn api_update_target(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         body: Json<TargetDataRequest>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -170,7 +224,29 @@ impl DetailApi {
         model.name = Set(body.name.clone());
         model.options =
             Set(serde_json::to_value(body.options.clone()).map_err(WarpgateError::from)?);
+        model.honeypot = Set(body.honeypot);
+        model.host_overrides = Set(Some(
+            serde_json::to_value(&body.host_overrides).map_err(WarpgateError::from)?,
+        ));
+        model.max_concurrent_sessions = Set(body.max_concurrent_sessions.map(|x| x as i32));
+        model.session_limit_message = Set(body.session_limit_message());
+        model.monitor = Set(body
+            .monitor
+            .as_ref()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(WarpgateError::from)?);
+        model.idle_timeout_seconds = Set(body.idle_timeout_seconds.map(|x| x as i32));
         let target = model.update(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            target.id,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
 
         Ok(UpdateTargetResponse::Ok(Json(
             target.try_into().map_err(WarpgateError::from)?,
@@ -185,6 +261,8 @@ impl DetailApi {
     async fn api_delete_target(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
     ) -> Result<DeleteTargetResponse, WarpgateError> {
@@ -203,7 +281,18 @@ impl DetailApi {
             .exec(&*db)
             .await?;
 
+        let target_id = target.id;
         target.delete(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            target_id,
+            ConfigChangeAction::Delete,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
+
         Ok(DeleteTargetResponse::Deleted)
     }
 }
@@ -274,6 +363,8 @@ impl RolesApi {
     async fn api_add_target_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         id: Path<Uuid>,
         role_id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -298,6 +389,15 @@ impl RolesApi {
         };
 
         values.insert(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            id.0,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
 
         Ok(AddTargetRoleResponse::Created)
     }
@@ -310,6 +410,8 @@ impl RolesApi {
     async fn api_delete_target_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         id: Path<Uuid>,
         role_id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -339,7 +441,85 @@ impl RolesApi {
         };
 
         model.delete(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            id.0,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
 
         Ok(DeleteTargetRoleResponse::Deleted)
     }
 }
+
+#[derive(ApiResponse)]
+enum GetTargetShadowReportResponse {
+    #[oai(status = 200)]
+    Ok(Json<ShadowReport>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum ResetTargetShadowReportResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+pub struct ShadowApi;
+
+#[OpenApi]
+impl ShadowApi {
+    /// Differences between the target and its shadow upstream since
+    /// the last reset
+    #[oai(
+        path = "/targets/:id/shadow-report",
+        method = "get",
+        operation_id = "get_target_shadow_report"
+    )]
+    async fn api_get_target_shadow_report(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetTargetShadowReportResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        let Some(target) = Target::Entity::find_by_id(id.0).one(&*db).await? else {
+            return Ok(GetTargetShadowReportResponse::NotFound);
+        };
+
+        Ok(GetTargetShadowReportResponse::Ok(Json(
+            services.shadow_reports.report(&target.name),
+        )))
+    }
+
+    #[oai(
+        path = "/targets/:id/shadow-report",
+        method = "delete",
+        operation_id = "reset_target_shadow_report"
+    )]
+    async fn api_reset_target_shadow_report(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<ResetTargetShadowReportResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        let Some(target) = Target::Entity::find_by_id(id.0).one(&*db).await? else {
+            return Ok(ResetTargetShadowReportResponse::NotFound);
+        };
+
+        services.shadow_reports.reset(&target.name);
+
+        Ok(ResetTargetShadowReportResponse::Deleted)
+    }
+}
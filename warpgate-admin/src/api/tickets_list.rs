@@ -11,6 +11,7 @@ use tokio::sync::Mutex;
 use uuid::Uuid;
 use warpgate_common::helpers::hash::generate_ticket_secret;
 use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
 use warpgate_db_entities::Ticket;
 
 use super::AnySecurityScheme;
@@ -51,7 +52,7 @@ impl Api {
     #[oai(path = "/tickets", method = "get", operation_id = "get_tickets")]
     async fn api_get_all_tickets(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         _auth: AnySecurityScheme,
     ) -> Result<GetTicketsResponse, WarpgateError> {
         use warpgate_db_entities::Ticket;
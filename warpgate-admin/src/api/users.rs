@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use chrono::{DateTime, Utc};
 use poem::web::Data;
 use poem_openapi::param::{Path, Query};
 use poem_openapi::payload::Json;
@@ -13,6 +14,9 @@ use uuid::Uuid;
 use warpgate_common::{
     Role as RoleConfig, User as UserConfig, UserRequireCredentialsPolicy, WarpgateError,
 };
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{record_config_change, ConfigChangeAuthor, Services};
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
 use warpgate_db_entities::{Role, User, UserRoleAssignment};
 
 use super::AnySecurityScheme;
@@ -25,6 +29,8 @@ struct CreateUserRequest {
 struct UserDataRequest {
     username: String,
     credential_policy: Option<UserRequireCredentialsPolicy>,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
 }
 
 #[derive(ApiResponse)]
@@ -48,7 +54,7 @@ impl ListApi {
     #[oai(path = "/users", method = "get", operation_id = "get_users")]
     async fn api_get_all_users(
         &self,
-        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        db: Data<&ReadOnlyDatabase>,
         search: Query<Option<String>>,
         _auth: AnySecurityScheme,
     ) -> Result<GetUsersResponse, WarpgateError> {
@@ -73,6 +79,8 @@ impl ListApi {
     async fn api_create_user(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        services: Data<&Services>,
+        author: Data<&ConfigChangeAuthor>,
         body: Json<CreateUserRequest>,
         _auth: AnySecurityScheme,
     ) -> Result<CreateUserResponse, WarpgateError> {
@@ -89,9 +97,20 @@ impl ListApi {
                 serde_json::to_value(UserRequireCredentialsPolicy::default())
                     .map_err(WarpgateError::from)?,
             ),
+            ..Default::default()
         };
 
         let user = values.insert(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::User,
+            user.id,
+            ConfigChangeAction::Create,
+            &author,
+        )
+        .await?;
+
+        services.authorization_cache.invalidate();
 
         Ok(CreateUserResponse::Created(Json(
             user.try_into().map_err(WarpgateError::from)?,
@@ -148,6 +167,8 @@ impl DetailApi {
     async fn api_update_user(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         body: Json<UserDataRequest>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -163,7 +184,22 @@ impl DetailApi {
         model.credential_policy =
             Set(serde_json::to_value(body.credential_policy.clone())
                 .map_err(WarpgateError::from)?);
+        model.valid_from = Set(body.valid_from);
+        model.valid_until = Set(body.valid_until);
+        if User::is_within_validity(body.valid_from, body.valid_until, Utc::now()) {
+            // Extending an expired account reactivates it
+            model.deactivated = Set(None);
+        }
         let user = model.update(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::User,
+            user.id,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
 
         Ok(UpdateUserResponse::Ok(Json(
             user.try_into().map_err(WarpgateError::from)?,
@@ -174,6 +210,8 @@ impl DetailApi {
     async fn api_delete_user(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         id: Path<Uuid>,
         _auth: AnySecurityScheme,
     ) -> Result<DeleteUserResponse, WarpgateError> {
@@ -188,7 +226,18 @@ impl DetailApi {
             .exec(&*db)
             .await?;
 
+        let user_id = user.id;
         user.delete(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::User,
+            user_id,
+            ConfigChangeAction::Delete,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
+
         Ok(DeleteUserResponse::Deleted)
     }
 }
@@ -209,6 +258,26 @@ enum AddUserRoleResponse {
     AlreadyExists,
 }
 
+#[derive(Object)]
+struct RoleAssignmentValidity {
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+#[derive(ApiResponse)]
+enum GetUserRoleAssignmentsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<UserRoleAssignment::Model>>),
+}
+
+#[derive(ApiResponse)]
+enum UpdateUserRoleResponse {
+    #[oai(status = 200)]
+    Ok(Json<UserRoleAssignment::Model>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
 #[derive(ApiResponse)]
 enum DeleteUserRoleResponse {
     #[oai(status = 204)]
@@ -257,6 +326,8 @@ impl RolesApi {
     async fn api_add_user_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         id: Path<Uuid>,
         role_id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -281,10 +352,85 @@ impl RolesApi {
         };
 
         values.insert(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::User,
+            id.0,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
 
         Ok(AddUserRoleResponse::Created)
     }
 
+    /// Includes the validity period of each assignment
+    #[oai(
+        path = "/users/:id/role-assignments",
+        method = "get",
+        operation_id = "get_user_role_assignments"
+    )]
+    async fn api_get_user_role_assignments(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetUserRoleAssignmentsResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        let assignments = UserRoleAssignment::Entity::find()
+            .filter(UserRoleAssignment::Column::UserId.eq(id.0))
+            .all(&*db)
+            .await?;
+
+        Ok(GetUserRoleAssignmentsResponse::Ok(Json(assignments)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[oai(
+        path = "/users/:id/roles/:role_id",
+        method = "put",
+        operation_id = "update_user_role"
+    )]
+    async fn api_update_user_role(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+        role_id: Path<Uuid>,
+        body: Json<RoleAssignmentValidity>,
+        _auth: AnySecurityScheme,
+    ) -> Result<UpdateUserRoleResponse, WarpgateError> {
+        let db = db.lock().await;
+
+        let Some(assignment) = UserRoleAssignment::Entity::find()
+            .filter(UserRoleAssignment::Column::UserId.eq(id.0))
+            .filter(UserRoleAssignment::Column::RoleId.eq(role_id.0))
+            .one(&*db)
+            .await?
+        else {
+            return Ok(UpdateUserRoleResponse::NotFound);
+        };
+
+        let mut model: UserRoleAssignment::ActiveModel = assignment.into();
+        model.valid_from = Set(body.valid_from);
+        model.valid_until = Set(body.valid_until);
+        let assignment = model.update(&*db).await?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::User,
+            id.0,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
+
+        Ok(UpdateUserRoleResponse::Ok(Json(assignment)))
+    }
+
     #[oai(
         path = "/users/:id/roles/:role_id",
         method = "delete",
@@ -293,6 +439,8 @@ impl RolesApi {
     async fn api_delete_user_role(
         &self,
         db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        author: Data<&ConfigChangeAuthor>,
+        services: Data<&Services>,
         id: Path<Uuid>,
         role_id: Path<Uuid>,
         _auth: AnySecurityScheme,
@@ -318,6 +466,15 @@ impl RolesApi {
         };
 
         model.delete(&*db).await.map_err(WarpgateError::from)?;
+        record_config_change(
+            &db,
+            ConfigObjectKind::User,
+            id.0,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await?;
+        services.authorization_cache.invalidate();
 
         Ok(DeleteUserRoleResponse::Deleted)
     }
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, OpenApi};
+use tokio::sync::Mutex;
+use tracing::*;
+use warpgate_common::WarpgateError;
+use warpgate_core::{HttpSessionInfo, HttpSessionStorage, State};
+use warpgate_db_entities::Session::SessionTerminationReason;
+
+use super::AnySecurityScheme;
+
+#[derive(ApiResponse)]
+enum GetWebSessionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<HttpSessionInfo>>),
+}
+
+#[derive(ApiResponse)]
+enum RevokeWebSessionResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+pub struct Api;
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/web-sessions",
+        method = "get",
+        operation_id = "get_web_sessions"
+    )]
+    async fn api_get_web_sessions(
+        &self,
+        storage: Data<&HttpSessionStorage>,
+        username: Query<Option<String>>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetWebSessionsResponse, WarpgateError> {
+        let mut sessions = storage.list().await?;
+        if let Some(ref username) = *username {
+            sessions.retain(|s| s.username.as_ref() == Some(username));
+        }
+        Ok(GetWebSessionsResponse::Ok(Json(sessions)))
+    }
+
+    /// Logs the web session out and closes its Warpgate session
+    #[oai(
+        path = "/web-sessions/:id",
+        method = "delete",
+        operation_id = "revoke_web_session"
+    )]
+    async fn api_revoke_web_session(
+        &self,
+        storage: Data<&HttpSessionStorage>,
+        state: Data<&Arc<Mutex<State>>>,
+        id: Path<String>,
+        _auth: AnySecurityScheme,
+    ) -> Result<RevokeWebSessionResponse, WarpgateError> {
+        let Some(session) = storage.revoke(&id.0).await? else {
+            return Ok(RevokeWebSessionResponse::NotFound);
+        };
+
+        if let Some(session_id) = session.session_id {
+            let session_state = state.lock().await.sessions.get(&session_id).cloned();
+            if let Some(session_state) = session_state {
+                session_state
+                    .lock()
+                    .await
+                    .terminate(SessionTerminationReason::AdminAbort);
+            }
+        }
+        info!(username=?session.username, "Revoked web session");
+
+        Ok(RevokeWebSessionResponse::Deleted)
+    }
+}
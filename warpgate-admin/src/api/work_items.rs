@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, Set,
+};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::db::ReadOnlyDatabase;
+use warpgate_core::{
+    normalize_work_item, summarize_work_items, SessionSnapshot, WorkItemSummary,
+    MAX_WORK_ITEM_LENGTH,
+};
+use warpgate_db_entities::Session;
+
+use super::AnySecurityScheme;
+
+#[derive(Object)]
+struct WorkItemTimeline {
+    summary: WorkItemSummary,
+    /// Across all protocols, in the order they were started
+    sessions: Vec<SessionSnapshot>,
+}
+
+#[derive(Object)]
+struct SetSessionWorkItemRequest {
+    /// Leave empty to remove the session from its work item
+    work_item: Option<String>,
+}
+
+#[derive(ApiResponse)]
+enum GetWorkItemsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<WorkItemSummary>>),
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(ApiResponse)]
+enum GetWorkItemResponse {
+    #[oai(status = 200)]
+    Ok(Json<WorkItemTimeline>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(ApiResponse)]
+enum SetSessionWorkItemResponse {
+    #[oai(status = 200)]
+    Ok(Json<SessionSnapshot>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+pub struct Api;
+
+#[OpenApi]
+impl Api {
+    #[oai(path = "/work-items", method = "get", operation_id = "get_work_items")]
+    async fn api_get_work_items(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetWorkItemsResponse, WarpgateError> {
+        let db = db.lock().await;
+        let sessions: Vec<SessionSnapshot> = Session::Entity::find()
+            .filter(Session::Column::WorkItem.is_not_null())
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        Ok(GetWorkItemsResponse::Ok(Json(summarize_work_items(
+            &sessions,
+        ))))
+    }
+
+    #[oai(
+        path = "/work-items/:name",
+        method = "get",
+        operation_id = "get_work_item"
+    )]
+    async fn api_get_work_item(
+        &self,
+        db: Data<&ReadOnlyDatabase>,
+        name: Path<String>,
+        _auth: AnySecurityScheme,
+    ) -> Result<GetWorkItemResponse, WarpgateError> {
+        let db = db.lock().await;
+        let sessions: Vec<SessionSnapshot> = Session::Entity::find()
+            .filter(Session::Column::WorkItem.eq(&name.0))
+            .order_by_asc(Session::Column::Started)
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+
+        let Some(summary) = summarize_work_items(&sessions).pop() else {
+            return Ok(GetWorkItemResponse::NotFound);
+        };
+
+        Ok(GetWorkItemResponse::Ok(Json(WorkItemTimeline {
+            summary,
+            sessions,
+        })))
+    }
+
+    /// Tags a session after the fact, e.g. for clients that can't pass a work item
+    #[oai(
+        path = "/sessions/:id/work-item",
+        method = "put",
+        operation_id = "set_session_work_item"
+    )]
+    async fn api_set_session_work_item(
+        &self,
+        db: Data<&Arc<Mutex<DatabaseConnection>>>,
+        id: Path<Uuid>,
+        body: Json<SetSessionWorkItemRequest>,
+        _auth: AnySecurityScheme,
+    ) -> Result<SetSessionWorkItemResponse, WarpgateError> {
+        let work_item = match body.0.work_item.filter(|x| !x.trim().is_empty()) {
+            None => None,
+            Some(value) => match normalize_work_item(&value) {
+                Some(value) => Some(value),
+                None => {
+                    return Ok(SetSessionWorkItemResponse::BadRequest(Json(format!(
+                        "Invalid work item (max. {MAX_WORK_ITEM_LENGTH} characters)"
+                    ))))
+                }
+            },
+        };
+
+        let db = db.lock().await;
+        let Some(session) = Session::Entity::find_by_id(id.0).one(&*db).await? else {
+            return Ok(SetSessionWorkItemResponse::NotFound);
+        };
+
+        let mut model = session.into_active_model();
+        model.work_item = Set(work_item.clone());
+        let session = model.update(&*db).await?;
+        info!(session=%id.0, ?work_item, "Updated session work item");
+
+        Ok(SetSessionWorkItemResponse::Ok(Json(session.into())))
+    }
+}
@@ -14,6 +14,7 @@ pub fn admin_api_app(services: &Services) -> impl IntoEndpoint {
     let ui = api_service.swagger_ui();
     let spec = api_service.spec_endpoint();
     let db = services.db.clone();
+    let read_db = services.read_db.clone();
     let config = services.config.clone();
     let config_provider = services.config_provider.clone();
     let recordings = services.recordings.clone();
@@ -35,11 +36,32 @@ pub fn admin_api_app(services: &Services) -> impl IntoEndpoint {
             "/recordings/:id/tcpdump",
             crate::api::recordings_detail::api_get_recording_tcpdump,
         )
+        .at(
+            "/recordings/:id/http",
+            crate::api::recordings_detail::api_get_recording_http,
+        )
+        .at(
+            "/recordings/:id/sftp",
+            crate::api::recordings_detail::api_get_recording_sftp,
+        )
+        .at(
+            "/recordings/:id/queries",
+            crate::api::recordings_detail::api_get_recording_queries,
+        )
+        .at(
+            "/sessions/:id/shadow",
+            crate::api::sessions_detail::api_get_session_shadow,
+        )
+        .at(
+            "/sessions/:id/bundle",
+            crate::api::sessions_detail::api_get_session_bundle,
+        )
         .at(
             "/sessions/changes",
             crate::api::sessions_list::api_get_sessions_changes_stream,
         )
         .data(db)
+        .data(read_db)
         .data(config_provider)
         .data(state)
         .data(recordings)
@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt::Debug;
 
 use crate::consts::TICKET_SELECTOR_PREFIX;
@@ -7,12 +8,25 @@ pub enum AuthSelector {
     User {
         username: String,
         target_name: String,
+        /// Given after the target name as `?key=value&key2=value2`,
+        /// used in target address templates
+        parameters: TargetParameters,
     },
     Ticket {
         secret: Secret<String>,
     },
 }
 
+pub type TargetParameters = BTreeMap<String, String>;
+
+fn parse_parameters(query: &str) -> TargetParameters {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
 impl<T: AsRef<str>> From<T> for AuthSelector {
     fn from(selector: T) -> Self {
         if let Some(secret) = selector.as_ref().strip_prefix(TICKET_SELECTOR_PREFIX) {
@@ -28,10 +42,15 @@ impl<T: AsRef<str>> From<T> for AuthSelector {
 
         let mut parts = selector.as_ref().splitn(2, separator);
         let username = parts.next().unwrap_or("").to_string();
-        let target_name = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("");
+        let (target_name, parameters) = match target.split_once('?') {
+            Some((target_name, query)) => (target_name, parse_parameters(query)),
+            None => (target, TargetParameters::new()),
+        };
         AuthSelector::User {
             username,
-            target_name,
+            target_name: target_name.to_string(),
+            parameters,
         }
     }
 }
@@ -42,8 +61,30 @@ impl Debug for AuthSelector {
             AuthSelector::User {
                 username,
                 target_name,
+                ..
             } => write!(f, "<{username} for {target_name}>"),
             AuthSelector::Ticket { .. } => write!(f, "<ticket>"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameters() {
+        let AuthSelector::User {
+            username,
+            target_name,
+            parameters,
+        } = AuthSelector::from("alice:devvm?vm=3&zone=eu")
+        else {
+            panic!("expected a user selector");
+        };
+        assert_eq!(username, "alice");
+        assert_eq!(target_name, "devvm");
+        assert_eq!(parameters.get("vm").map(String::as_str), Some("3"));
+        assert_eq!(parameters.get("zone").map(String::as_str), Some("eu"));
+    }
+}
@@ -23,6 +23,7 @@ pub struct AuthState {
     force_rejected: bool,
     policy: Box<dyn CredentialPolicy + Sync + Send>,
     valid_credentials: Vec<AuthCredential>,
+    invalid_credential_kinds: Vec<CredentialKind>,
     started: DateTime<Utc>,
     identification_string: String,
 }
@@ -52,6 +53,7 @@ impl AuthState {
             force_rejected: false,
             policy,
             valid_credentials: vec![],
+            invalid_credential_kinds: vec![],
             started: Utc::now(),
             identification_string: generate_identification_string(),
         }
@@ -85,6 +87,26 @@ impl AuthState {
         self.valid_credentials.push(credential);
     }
 
+    pub fn add_invalid_credential(&mut self, credential: &AuthCredential) {
+        self.invalid_credential_kinds.push(credential.kind());
+    }
+
+    /// Kinds of all credentials presented so far, valid or not
+    pub fn attempted_credential_kinds(&self) -> Vec<CredentialKind> {
+        let mut kinds = vec![];
+        for kind in self
+            .valid_credentials
+            .iter()
+            .map(AuthCredential::kind)
+            .chain(self.invalid_credential_kinds.iter().copied())
+        {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    }
+
     pub fn reject(&mut self) {
         self.force_rejected = true;
     }
@@ -0,0 +1,334 @@
+//! Target hosts can be templates that are filled in at connect time, e.g.
+//! `user-{username|dns}.dev.internal` or `vm-{param.vm}.internal:{param.port}`.
+//!
+//! Placeholders are `{variable|filter|...}`, literal braces are written as `{{` and `}}`.
+//! Variables:
+//! * `username` - the authenticated user
+//! * `target` - the target name
+//! * `role` - the alphabetically first of the user's roles that grant access to the target
+//! * `param.<name>` - a parameter given in the target selector, e.g. `alice:devvm?vm=3`.
+//!   Parameters must be a single DNS label.
+//!
+//! Filters: `lower`, `upper` and `dns` (turns the value into a valid DNS label).
+//!
+//! HTTP targets can template their `url` and match many hosts with an
+//! `external_host` pattern like `*.apps.corp` or `{app}.apps.corp`, each
+//! wildcard captures one label of the requested host as a parameter.
+
+use crate::auth::TargetParameters;
+
+/// Values available to address templates
+pub struct AddressTemplateContext<'a> {
+    pub username: &'a str,
+    pub target: &'a str,
+    pub role: Option<&'a str>,
+    pub parameters: &'a TargetParameters,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum AddressTemplateError {
+    #[error("unterminated placeholder in address template")]
+    Unterminated,
+    #[error("unknown template variable: {0}")]
+    UnknownVariable(String),
+    #[error("parameter {0} was not provided")]
+    MissingParameter(String),
+    #[error("the user has no role granting access to this target")]
+    NoRole,
+    #[error("unknown template filter: {0}")]
+    UnknownFilter(String),
+    #[error("value of {0} can't be used in an address, try the `dns` filter")]
+    InvalidValue(String),
+    #[error("invalid port in resolved address: {0}")]
+    InvalidPort(String),
+}
+
+pub fn is_address_template(host: &str) -> bool {
+    host.contains('{')
+}
+
+/// Name of the parameter captured by a `*` host label
+pub const HOST_WILDCARD_PARAMETER: &str = "subdomain";
+
+pub fn is_host_pattern(pattern: &str) -> bool {
+    pattern.contains('*') || pattern.contains('{')
+}
+
+/// Matches a requested host against an `external_host` pattern
+/// and returns the captured labels
+pub fn match_host_pattern(pattern: &str, host: &str) -> Option<TargetParameters> {
+    let pattern_labels = pattern.split('.').collect::<Vec<_>>();
+    let host_labels = host.split('.').collect::<Vec<_>>();
+    if pattern_labels.len() != host_labels.len() {
+        return None;
+    }
+
+    let mut parameters = TargetParameters::new();
+    for (pattern_label, host_label) in pattern_labels.into_iter().zip(host_labels) {
+        let name = match pattern_label {
+            "*" => HOST_WILDCARD_PARAMETER,
+            _ => match pattern_label
+                .strip_prefix('{')
+                .and_then(|x| x.strip_suffix('}'))
+                .filter(|x| !x.contains(['{', '}']))
+            {
+                Some(name) => name,
+                None if pattern_label.eq_ignore_ascii_case(host_label) => continue,
+                None => return None,
+            },
+        };
+        if host_label.is_empty()
+            || !host_label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return None;
+        }
+        parameters.insert(name.to_owned(), host_label.to_ascii_lowercase());
+    }
+    Some(parameters)
+}
+
+/// Parameters come from the user's target selector, so they're limited to
+/// a single DNS label and can't point the template at a different domain
+fn is_dns_label(value: &str) -> bool {
+    !value.is_empty()
+        && !value.starts_with('-')
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn is_address_safe(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+fn to_dns_label(value: &str) -> String {
+    let label = value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+    label.trim_matches('-').chars().take(63).collect()
+}
+
+fn evaluate(
+    expression: &str,
+    context: &AddressTemplateContext,
+) -> Result<String, AddressTemplateError> {
+    let mut parts = expression.split('|').map(str::trim);
+    let variable = parts.next().unwrap_or_default();
+
+    let parameter = variable.strip_prefix("param.");
+    let mut value = match (variable, parameter) {
+        ("username", _) => context.username.to_owned(),
+        ("target", _) => context.target.to_owned(),
+        ("role", _) => context.role.ok_or(AddressTemplateError::NoRole)?.to_owned(),
+        (_, Some(name)) => context
+            .parameters
+            .get(name)
+            .ok_or_else(|| AddressTemplateError::MissingParameter(name.to_owned()))?
+            .clone(),
+        (_, None) => return Err(AddressTemplateError::UnknownVariable(variable.to_owned())),
+    };
+
+    for filter in parts {
+        value = match filter {
+            "lower" => value.to_lowercase(),
+            "upper" => value.to_uppercase(),
+            "dns" => to_dns_label(&value),
+            _ => return Err(AddressTemplateError::UnknownFilter(filter.to_owned())),
+        };
+    }
+
+    // Values must not be able to change the port or inject URL syntax
+    let safe = match parameter {
+        Some(_) => is_dns_label(&value),
+        None => is_address_safe(&value),
+    };
+    if !safe {
+        return Err(AddressTemplateError::InvalidValue(variable.to_owned()));
+    }
+    Ok(value)
+}
+
+pub fn render_address_template(
+    template: &str,
+    context: &AddressTemplateContext,
+) -> Result<String, AddressTemplateError> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some((literal, tail)) = rest
+        .find(['{', '}'])
+        .and_then(|index| rest.split_at_checked(index))
+    {
+        result.push_str(literal);
+        if let Some(tail) = tail.strip_prefix("{{") {
+            result.push('{');
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix("}}") {
+            result.push('}');
+            rest = tail;
+        } else if let Some(tail) = tail.strip_prefix('{') {
+            let (expression, tail) = tail
+                .split_once('}')
+                .ok_or(AddressTemplateError::Unterminated)?;
+            result.push_str(&evaluate(expression, context)?);
+            rest = tail;
+        } else {
+            return Err(AddressTemplateError::Unterminated);
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Renders a templated `host` in place. The template can also
+/// override the port with a `:port` suffix.
+pub fn apply_address_template(
+    host: &mut String,
+    port: &mut u16,
+    context: &AddressTemplateContext,
+) -> Result<(), AddressTemplateError> {
+    if !is_address_template(host) {
+        return Ok(());
+    }
+    let address = render_address_template(host, context)?;
+    match address.rsplit_once(':') {
+        Some((resolved_host, resolved_port)) if !resolved_host.contains(':') => {
+            *port = resolved_port
+                .parse()
+                .map_err(|_| AddressTemplateError::InvalidPort(address.clone()))?;
+            *host = resolved_host.to_owned();
+        }
+        _ => *host = address,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(parameters: &TargetParameters) -> AddressTemplateContext<'_> {
+        AddressTemplateContext {
+            username: "Jane.Doe@example.com",
+            target: "devvm",
+            role: Some("developers"),
+            parameters,
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let parameters = TargetParameters::from([("vm".to_owned(), "3".to_owned())]);
+        let context = context(&parameters);
+
+        assert_eq!(
+            render_address_template("user-{username|dns}.dev.internal", &context).unwrap(),
+            "user-jane-doe-example-com.dev.internal"
+        );
+        assert_eq!(
+            render_address_template("{ target }-{param.vm}.{role|upper}", &context).unwrap(),
+            "devvm-3.DEVELOPERS"
+        );
+        assert_eq!(
+            render_address_template("{{literal}}", &context).unwrap(),
+            "{literal}"
+        );
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let parameters = TargetParameters::from([("evil".to_owned(), "x:1/@y".to_owned())]);
+        let context = context(&parameters);
+
+        assert_eq!(
+            render_address_template("{username}.internal", &context),
+            Err(AddressTemplateError::InvalidValue("username".into()))
+        );
+        assert_eq!(
+            render_address_template("{param.evil}", &context),
+            Err(AddressTemplateError::InvalidValue("param.evil".into()))
+        );
+        assert_eq!(
+            render_address_template("{param.vm}", &context),
+            Err(AddressTemplateError::MissingParameter("vm".into()))
+        );
+        assert_eq!(
+            render_address_template("{target|reverse}", &context),
+            Err(AddressTemplateError::UnknownFilter("reverse".into()))
+        );
+        assert_eq!(
+            render_address_template("{target", &context),
+            Err(AddressTemplateError::Unterminated)
+        );
+    }
+
+    #[test]
+    fn test_parameters_are_single_labels() {
+        let parameters = TargetParameters::from([
+            ("vm".to_owned(), "x.evil".to_owned()),
+            ("dash".to_owned(), "-x".to_owned()),
+            ("under".to_owned(), "a_b".to_owned()),
+        ]);
+        let context = context(&parameters);
+
+        for name in ["vm", "dash", "under"] {
+            assert_eq!(
+                render_address_template(&format!("vm-{{param.{name}}}.internal"), &context),
+                Err(AddressTemplateError::InvalidValue(format!("param.{name}")))
+            );
+        }
+        assert_eq!(
+            render_address_template("vm-{param.vm|dns}.internal", &context).unwrap(),
+            "vm-x-evil.internal"
+        );
+    }
+
+    #[test]
+    fn test_match_host_pattern() {
+        let parameters = match_host_pattern("*.apps.corp", "Grafana.apps.corp").unwrap();
+        assert_eq!(
+            parameters.get("subdomain").map(String::as_str),
+            Some("grafana")
+        );
+
+        let parameters = match_host_pattern("{app}-{env}.corp", "x-y.corp");
+        assert_eq!(parameters, None);
+
+        let parameters = match_host_pattern("{app}.{env}.corp", "wiki.staging.corp").unwrap();
+        assert_eq!(parameters.get("app").map(String::as_str), Some("wiki"));
+        assert_eq!(parameters.get("env").map(String::as_str), Some("staging"));
+
+        assert_eq!(match_host_pattern("*.apps.corp", "a.b.apps.corp"), None);
+        assert_eq!(match_host_pattern("*.apps.corp", "a.apps.evil"), None);
+        assert_eq!(match_host_pattern("*.apps.corp", ".apps.corp"), None);
+        assert_eq!(
+            match_host_pattern("app.corp", "APP.corp"),
+            Some(TargetParameters::new())
+        );
+    }
+
+    #[test]
+    fn test_apply_with_port() {
+        let parameters = TargetParameters::from([("port".to_owned(), "2222".to_owned())]);
+        let context = context(&parameters);
+
+        let mut host = "{target}.internal:{param.port}".to_owned();
+        let mut port = 22;
+        apply_address_template(&mut host, &mut port, &context).unwrap();
+        assert_eq!((host.as_str(), port), ("devvm.internal", 2222));
+
+        let mut host = "{target}.internal".to_owned();
+        let mut port = 22;
+        apply_address_template(&mut host, &mut port, &context).unwrap();
+        assert_eq!((host.as_str(), port), ("devvm.internal", 22));
+    }
+}
@@ -1,7 +1,7 @@
 use std::net::{Ipv6Addr, SocketAddr};
 use std::time::Duration;
 
-use crate::{ListenEndpoint, Secret};
+use crate::{AuthRateLimitRule, ListenEndpoint, Secret, SshKeyAlgorithm};
 
 pub(crate) const fn _default_true() -> bool {
     true
@@ -19,11 +19,56 @@ pub(crate) const fn _default_mysql_port() -> u16 {
     3306
 }
 
+pub(crate) const fn _default_postgres_port() -> u16 {
+    5432
+}
+
+pub(crate) const fn _default_redis_port() -> u16 {
+    6379
+}
+
+pub(crate) const fn _default_ssh_reconnect_attempts() -> u32 {
+    5
+}
+
+pub(crate) const fn _default_ssh_reconnect_interval() -> u64 {
+    3
+}
+
+pub(crate) const fn _default_ssh_multiplexing_linger() -> u64 {
+    30
+}
+
+pub(crate) const fn _default_postgres_pool_size() -> u32 {
+    10
+}
+
+pub(crate) const fn _default_postgres_pool_idle_timeout() -> u64 {
+    300
+}
+
+pub(crate) const fn _default_direct_credentials_ttl() -> u64 {
+    60 * 60
+}
+
+pub(crate) const fn _default_http_recorded_body_size() -> usize {
+    64 * 1024
+}
+
+pub(crate) const fn _default_ipmi_port() -> u16 {
+    623
+}
+
 #[inline]
 pub(crate) fn _default_username() -> String {
     "root".to_owned()
 }
 
+#[inline]
+pub(crate) fn _default_container_shell() -> String {
+    "/bin/sh".to_owned()
+}
+
 #[inline]
 pub(crate) fn _default_empty_string() -> String {
     "".to_owned()
@@ -49,16 +94,30 @@ pub(crate) fn _default_mysql_listen() -> ListenEndpoint {
     ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 33306))
 }
 
+pub(crate) const fn _default_mysql_max_packet_size() -> usize {
+    64 * 1024 * 1024
+}
+
 #[inline]
 pub(crate) fn _default_postgres_listen() -> ListenEndpoint {
     ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 55432))
 }
 
+#[inline]
+pub(crate) fn _default_redis_listen() -> ListenEndpoint {
+    ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 56379))
+}
+
 #[inline]
 pub(crate) fn _default_retention() -> Duration {
     Duration::from_secs(60 * 60 * 24 * 7)
 }
 
+#[inline]
+pub(crate) fn _default_analytics_database() -> String {
+    "warpgate".into()
+}
+
 #[inline]
 pub(crate) fn _default_session_max_age() -> Duration {
     Duration::from_secs(60 * 30)
@@ -69,11 +128,31 @@ pub(crate) fn _default_cookie_max_age() -> Duration {
     Duration::from_secs(60 * 60 * 24)
 }
 
+#[inline]
+pub(crate) fn _default_access_token_lifetime() -> Duration {
+    Duration::from_secs(60 * 15)
+}
+
+#[inline]
+pub(crate) fn _default_refresh_token_lifetime() -> Duration {
+    Duration::from_secs(60 * 60 * 24 * 30)
+}
+
 #[inline]
 pub(crate) fn _default_empty_vec<T>() -> Vec<T> {
     vec![]
 }
 
+#[inline]
+pub(crate) fn _default_oidc_signing_key_path() -> String {
+    "./data/oidc-signing-key.pem".to_owned()
+}
+
+#[inline]
+pub(crate) fn _default_oidc_token_lifetime() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
 pub(crate) fn _default_ssh_listen() -> ListenEndpoint {
     ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 2222))
 }
@@ -82,6 +161,110 @@ pub(crate) fn _default_ssh_keys_path() -> String {
     "./data/keys".to_owned()
 }
 
+pub(crate) fn _default_ssh_client_key_algorithms() -> Vec<SshKeyAlgorithm> {
+    vec![SshKeyAlgorithm::Ed25519, SshKeyAlgorithm::Rsa]
+}
+
 pub(crate) fn _default_ssh_inactivity_timeout() -> Duration {
     Duration::from_secs(60 * 5)
 }
+
+pub(crate) const fn _default_ssh_keepalive_max() -> usize {
+    3
+}
+
+pub(crate) const fn _default_http_max_header_size() -> usize {
+    64 * 1024
+}
+
+pub(crate) fn _default_http_header_read_timeout() -> Duration {
+    Duration::from_secs(30)
+}
+
+pub(crate) const fn _default_ssh_max_startups_start() -> usize {
+    10
+}
+
+pub(crate) const fn _default_ssh_max_startups_rate() -> u8 {
+    30
+}
+
+pub(crate) const fn _default_ssh_max_startups_full() -> usize {
+    100
+}
+
+pub(crate) fn _default_replication_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+pub(crate) const fn _default_replication_chunk_size() -> usize {
+    1024 * 1024
+}
+
+pub(crate) fn _default_discovery_ports() -> Vec<u16> {
+    vec![22]
+}
+
+pub(crate) fn _default_discovery_interval() -> Duration {
+    Duration::from_secs(60 * 60 * 24)
+}
+
+pub(crate) fn _default_discovery_timeout() -> Duration {
+    Duration::from_secs(3)
+}
+
+pub(crate) const fn _default_discovery_concurrency() -> usize {
+    64
+}
+
+pub(crate) const fn _default_drop_box_max_item_size() -> usize {
+    10 * 1024 * 1024
+}
+
+pub(crate) const fn _default_drop_box_max_total_size() -> usize {
+    50 * 1024 * 1024
+}
+
+pub(crate) const fn _default_drop_box_max_global_size() -> usize {
+    256 * 1024 * 1024
+}
+
+pub(crate) fn _default_drop_box_retention() -> Duration {
+    Duration::from_secs(60 * 60)
+}
+
+pub(crate) fn _default_auth_failure_retention() -> Duration {
+    Duration::from_secs(60 * 60 * 24 * 30)
+}
+
+pub(crate) const fn _default_auth_failure_rate_limit() -> u32 {
+    20
+}
+
+pub(crate) fn _default_auth_rate_limit_per_user() -> AuthRateLimitRule {
+    AuthRateLimitRule {
+        max_failures: 10,
+        window: Duration::from_secs(60 * 15),
+        lockout: Duration::from_secs(60 * 15),
+    }
+}
+
+pub(crate) fn _default_auth_rate_limit_per_source() -> AuthRateLimitRule {
+    AuthRateLimitRule {
+        max_failures: 30,
+        window: Duration::from_secs(60 * 15),
+        lockout: Duration::from_secs(60 * 60),
+    }
+}
+
+pub(crate) const fn _default_recording_queue_size() -> usize {
+    16 * 1024 * 1024
+}
+
+pub(crate) const fn _default_recording_batch_size() -> usize {
+    256 * 1024
+}
+
+pub(crate) fn _default_recording_flush_interval() -> Duration {
+    Duration::from_secs(5)
+}
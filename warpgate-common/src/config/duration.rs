@@ -0,0 +1,39 @@
+//! Durations that used to be stored in serde's default `{ secs, nanos }`
+//! form and are now written like `30s`. Both are accepted when loading,
+//! so that existing config files keep working.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CompatDuration {
+    Humantime(#[serde(with = "humantime_serde")] Duration),
+    Legacy(Duration),
+}
+
+impl From<CompatDuration> for Duration {
+    fn from(value: CompatDuration) -> Self {
+        match value {
+            CompatDuration::Humantime(x) | CompatDuration::Legacy(x) => x,
+        }
+    }
+}
+
+pub(crate) mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        humantime_serde::serialize(value, serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<CompatDuration>::deserialize(deserializer)?.map(Into::into))
+    }
+}
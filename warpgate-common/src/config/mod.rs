@@ -1,13 +1,19 @@
+mod address_template;
 mod defaults;
+mod duration;
 mod target;
 
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::time::Duration;
 
+pub use address_template::*;
+use chrono::{DateTime, Utc};
 use defaults::*;
 use poem::http::uri;
-use poem_openapi::{Object, Union};
+use poem_openapi::{Enum, Object, Union};
 use serde::{Deserialize, Serialize};
 pub use target::*;
 use tracing::warn;
@@ -19,7 +25,7 @@ use warpgate_sso::SsoProviderConfig;
 use crate::auth::CredentialKind;
 use crate::helpers::hash::hash_password;
 use crate::helpers::otp::OtpSecretKey;
-use crate::{ListenEndpoint, Secret, WarpgateError};
+use crate::{ListenEndpoint, Secret, TcpSocketOptions, WarpgateError};
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Union)]
 #[serde(tag = "type")]
@@ -134,6 +140,15 @@ pub struct User {
     pub username: String,
     #[serde(skip_serializing_if = "Option::is_none", rename = "require")]
     pub credential_policy: Option<UserRequireCredentialsPolicy>,
+    /// The account can't be used before this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<DateTime<Utc>>,
+    /// The account can't be used after this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    /// When the account was deactivated after its expiry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deactivated: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object)]
@@ -169,6 +184,90 @@ pub enum SshHostKeyVerificationMode {
     AutoReject,
 }
 
+/// Limits on connections that haven't authenticated yet, modelled after
+/// OpenSSH's `MaxStartups` and `PerSourceMaxStartups`.
+///
+/// Once `start` unauthenticated connections are pending, new ones are dropped
+/// with a probability of `rate` percent, rising linearly to 100% at `full`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct SshMaxStartups {
+    #[serde(default = "_default_ssh_max_startups_start")]
+    pub start: usize,
+
+    /// Percent
+    #[serde(default = "_default_ssh_max_startups_rate")]
+    pub rate: u8,
+
+    #[serde(default = "_default_ssh_max_startups_full")]
+    pub full: usize,
+
+    /// Maximum number of unauthenticated connections from a single IP address
+    #[serde(default)]
+    pub per_source: Option<usize>,
+}
+
+impl Default for SshMaxStartups {
+    fn default() -> Self {
+        SshMaxStartups {
+            start: _default_ssh_max_startups_start(),
+            rate: _default_ssh_max_startups_rate(),
+            full: _default_ssh_max_startups_full(),
+            per_source: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Enum)]
+pub enum SshKeyAlgorithm {
+    #[serde(rename = "ed25519")]
+    #[oai(rename = "ed25519")]
+    Ed25519,
+    #[serde(rename = "rsa")]
+    #[oai(rename = "rsa")]
+    Rsa,
+    #[serde(rename = "ecdsa-p256")]
+    #[oai(rename = "ecdsa-p256")]
+    EcdsaP256,
+    #[serde(rename = "ecdsa-p384")]
+    #[oai(rename = "ecdsa-p384")]
+    EcdsaP384,
+    #[serde(rename = "ecdsa-p521")]
+    #[oai(rename = "ecdsa-p521")]
+    EcdsaP521,
+}
+
+impl SshKeyAlgorithm {
+    pub const ALL: &'static [SshKeyAlgorithm] = &[
+        Self::Ed25519,
+        Self::Rsa,
+        Self::EcdsaP256,
+        Self::EcdsaP384,
+        Self::EcdsaP521,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ed25519 => "ed25519",
+            Self::Rsa => "rsa",
+            Self::EcdsaP256 => "ecdsa-p256",
+            Self::EcdsaP384 => "ecdsa-p384",
+            Self::EcdsaP521 => "ecdsa-p521",
+        }
+    }
+}
+
+impl std::str::FromStr for SshKeyAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|x| x.as_str() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown key algorithm: {s}"))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct SshConfig {
     #[serde(default = "_default_false")]
@@ -180,17 +279,46 @@ pub struct SshConfig {
     #[serde(default)]
     pub external_port: Option<u16>,
 
+    #[serde(default)]
+    pub socket: TcpSocketOptions,
+
     #[serde(default = "_default_ssh_keys_path")]
     pub keys: String,
 
+    /// Algorithms of the default client keys generated on startup
+    #[serde(default = "_default_ssh_client_key_algorithms")]
+    pub client_key_algorithms: Vec<SshKeyAlgorithm>,
+
     #[serde(default)]
     pub host_key_verification: SshHostKeyVerificationMode,
 
     #[serde(default = "_default_ssh_inactivity_timeout", with = "humantime_serde")]
     pub inactivity_timeout: Duration,
 
-    #[serde(default)]
+    /// Sends keepalive requests to clients that have been silent for this
+    /// long, which also keeps NAT mappings on the way from expiring
+    #[serde(default, with = "duration::option")]
     pub keepalive_interval: Option<Duration>,
+
+    /// Clients that leave this many keepalives in a row unanswered are disconnected
+    #[serde(default = "_default_ssh_keepalive_max")]
+    pub keepalive_max: usize,
+
+    /// Same as `keepalive_interval`, for the connections made to SSH targets
+    #[serde(default, with = "humantime_serde")]
+    pub target_keepalive_interval: Option<Duration>,
+
+    #[serde(default = "_default_ssh_keepalive_max")]
+    pub target_keepalive_max: usize,
+
+    #[serde(default)]
+    pub max_startups: SshMaxStartups,
+
+    /// Shown after authentication - the user has to type `yes` before
+    /// the target connection is made. The acceptance time is stored
+    /// with the session.
+    #[serde(default)]
+    pub consent_banner: Option<String>,
 }
 
 impl Default for SshConfig {
@@ -199,10 +327,17 @@ impl Default for SshConfig {
             enable: false,
             listen: _default_ssh_listen(),
             keys: _default_ssh_keys_path(),
+            client_key_algorithms: _default_ssh_client_key_algorithms(),
             host_key_verification: Default::default(),
             external_port: None,
+            socket: <_>::default(),
             inactivity_timeout: _default_ssh_inactivity_timeout(),
             keepalive_interval: None,
+            keepalive_max: _default_ssh_keepalive_max(),
+            target_keepalive_interval: None,
+            target_keepalive_max: _default_ssh_keepalive_max(),
+            max_startups: <_>::default(),
+            consent_banner: None,
         }
     }
 }
@@ -224,6 +359,9 @@ pub struct HttpConfig {
     #[serde(default)]
     pub external_port: Option<u16>,
 
+    #[serde(default)]
+    pub socket: TcpSocketOptions,
+
     #[serde(default)]
     pub certificate: String,
 
@@ -238,6 +376,52 @@ pub struct HttpConfig {
 
     #[serde(default = "_default_cookie_max_age", with = "humantime_serde")]
     pub cookie_max_age: Duration,
+
+    /// How long an access token obtained with a refresh token stays valid
+    #[serde(default = "_default_access_token_lifetime", with = "humantime_serde")]
+    pub access_token_lifetime: Duration,
+
+    /// A refresh token expires if it isn't used within this time.
+    /// Every refresh issues a new one and invalidates the old one.
+    #[serde(default = "_default_refresh_token_lifetime", with = "humantime_serde")]
+    pub refresh_token_lifetime: Duration,
+
+    #[serde(default)]
+    pub session_storage: HttpSessionStorageKind,
+
+    /// Web session data is encrypted with a key derived from this secret,
+    /// or from the TLS private key if it's not set. In the latter case,
+    /// replacing the key (e.g. when renewing the certificate) logs everyone
+    /// out, so this is required with `session_storage: database`.
+    #[serde(default)]
+    pub session_encryption_key: Option<Secret<String>>,
+
+    /// Bytes, including the request line. Applies to each request on
+    /// a connection; connections that exceed it are closed.
+    #[serde(default = "_default_http_max_header_size")]
+    pub max_header_size: usize,
+
+    /// Bytes, unlimited if not set
+    #[serde(default)]
+    pub max_body_size: Option<u64>,
+
+    /// Time a new connection has to complete the TLS handshake and send
+    /// the headers of its first request, and that any later request has
+    /// to send its headers in once it has started
+    #[serde(
+        default = "_default_http_header_read_timeout",
+        with = "humantime_serde"
+    )]
+    pub header_read_timeout: Duration,
+
+    /// Unlimited if not set. When running behind a reverse proxy,
+    /// all connections come from the proxy's address.
+    #[serde(default)]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Lets other apps log users in through Warpgate over OpenID Connect
+    #[serde(default)]
+    pub oidc_provider: Option<OidcProviderConfig>,
 }
 
 impl Default for HttpConfig {
@@ -246,15 +430,69 @@ impl Default for HttpConfig {
             enable: false,
             listen: _default_http_listen(),
             external_port: None,
+            socket: <_>::default(),
             certificate: "".to_owned(),
             key: "".to_owned(),
             trust_x_forwarded_headers: false,
             session_max_age: _default_session_max_age(),
             cookie_max_age: _default_cookie_max_age(),
+            access_token_lifetime: _default_access_token_lifetime(),
+            refresh_token_lifetime: _default_refresh_token_lifetime(),
+            session_storage: <_>::default(),
+            session_encryption_key: None,
+            max_header_size: _default_http_max_header_size(),
+            max_body_size: None,
+            header_read_timeout: _default_http_header_read_timeout(),
+            max_connections_per_ip: None,
+            oidc_provider: None,
         }
     }
 }
 
+/// Where web sessions are kept
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HttpSessionStorageKind {
+    /// Sessions are lost on restart
+    #[serde(rename = "memory")]
+    #[default]
+    Memory,
+    /// Sessions survive restarts and are shared between instances
+    /// using the same database
+    #[serde(rename = "database")]
+    Database,
+}
+
+/// The issuer is `https://<external host>/@warpgate/oidc`, so `external_host`
+/// must be set to use it
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OidcProviderConfig {
+    /// ECDSA P-256 key in PEM format that ID tokens are signed with,
+    /// generated if it doesn't exist
+    #[serde(default = "_default_oidc_signing_key_path")]
+    pub signing_key: String,
+
+    /// How long ID and access tokens stay valid
+    #[serde(default = "_default_oidc_token_lifetime", with = "humantime_serde")]
+    pub token_lifetime: Duration,
+
+    #[serde(default)]
+    pub clients: Vec<OidcProviderClient>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OidcProviderClient {
+    pub client_id: String,
+
+    pub client_secret: Secret<String>,
+
+    /// Exact URLs that users can be sent back to after logging in
+    pub redirect_uris: Vec<String>,
+
+    /// Only users with one of these roles can log in. Everyone can if empty.
+    #[serde(default)]
+    pub allowed_roles: Vec<String>,
+}
+
 impl HttpConfig {
     pub fn external_port(&self) -> u16 {
         self.external_port.unwrap_or(self.listen.port())
@@ -272,11 +510,19 @@ pub struct MySqlConfig {
     #[serde(default)]
     pub external_port: Option<u16>,
 
+    #[serde(default)]
+    pub socket: TcpSocketOptions,
+
     #[serde(default)]
     pub certificate: String,
 
     #[serde(default)]
     pub key: String,
+
+    /// Largest packet relayed in either direction, in bytes. Packets over
+    /// 16 MB arrive in parts and are reassembled up to this size
+    #[serde(default = "_default_mysql_max_packet_size")]
+    pub max_packet_size: usize,
 }
 
 impl Default for MySqlConfig {
@@ -285,8 +531,10 @@ impl Default for MySqlConfig {
             enable: false,
             listen: _default_mysql_listen(),
             external_port: None,
+            socket: <_>::default(),
             certificate: "".to_owned(),
             key: "".to_owned(),
+            max_packet_size: _default_mysql_max_packet_size(),
         }
     }
 }
@@ -308,6 +556,9 @@ pub struct PostgresConfig {
     #[serde(default)]
     pub external_port: Option<u16>,
 
+    #[serde(default)]
+    pub socket: TcpSocketOptions,
+
     #[serde(default)]
     pub certificate: String,
 
@@ -321,6 +572,7 @@ impl Default for PostgresConfig {
             enable: false,
             listen: _default_postgres_listen(),
             external_port: None,
+            socket: <_>::default(),
             certificate: "".to_owned(),
             key: "".to_owned(),
         }
@@ -333,6 +585,47 @@ impl PostgresConfig {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RedisConfig {
+    #[serde(default = "_default_false")]
+    pub enable: bool,
+
+    #[serde(default = "_default_redis_listen")]
+    pub listen: ListenEndpoint,
+
+    #[serde(default)]
+    pub external_port: Option<u16>,
+
+    #[serde(default)]
+    pub socket: TcpSocketOptions,
+
+    /// Clients always connect over TLS, e.g. with `redis-cli --tls`
+    #[serde(default)]
+    pub certificate: String,
+
+    #[serde(default)]
+    pub key: String,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            enable: false,
+            listen: _default_redis_listen(),
+            external_port: None,
+            socket: <_>::default(),
+            certificate: "".to_owned(),
+            key: "".to_owned(),
+        }
+    }
+}
+
+impl RedisConfig {
+    pub fn external_port(&self) -> u16 {
+        self.external_port.unwrap_or(self.listen.port())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct RecordingsConfig {
     #[serde(default = "_default_false")]
@@ -340,6 +633,18 @@ pub struct RecordingsConfig {
 
     #[serde(default = "_default_recordings_path")]
     pub path: String,
+
+    #[serde(default)]
+    pub replication: Option<RecordingReplicationConfig>,
+
+    #[serde(default)]
+    pub writer: RecordingWriterConfig,
+
+    #[serde(default)]
+    pub secret_detection: SecretDetectionConfig,
+
+    #[serde(default)]
+    pub quotas: RecordingQuotasConfig,
 }
 
 impl Default for RecordingsConfig {
@@ -347,10 +652,138 @@ impl Default for RecordingsConfig {
         Self {
             enable: false,
             path: _default_recordings_path(),
+            replication: None,
+            writer: <_>::default(),
+            secret_detection: <_>::default(),
+            quotas: <_>::default(),
         }
     }
 }
 
+/// Looks for private keys, AWS keys and passwords in terminal recordings
+/// and logs a warning for each one found
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SecretDetectionConfig {
+    #[serde(default = "_default_false")]
+    pub enable: bool,
+
+    /// Replace detected secrets with `*` in the recording
+    #[serde(default = "_default_false")]
+    pub redact: bool,
+}
+
+/// Limits on the disk space taken up by recordings, counted separately
+/// for each target and each user
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct RecordingQuotasConfig {
+    /// Applies to targets that aren't listed in `targets`
+    #[serde(default)]
+    pub per_target: RecordingQuota,
+
+    /// Applies to users that aren't listed in `users`
+    #[serde(default)]
+    pub per_user: RecordingQuota,
+
+    /// Quotas for specific targets, by name
+    #[serde(default)]
+    pub targets: HashMap<String, RecordingQuota>,
+
+    /// Quotas for specific users, by username
+    #[serde(default)]
+    pub users: HashMap<String, RecordingQuota>,
+
+    #[serde(default)]
+    pub on_exceeded: RecordingQuotaPolicy,
+}
+
+/// Limits in bytes
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordingQuota {
+    /// Raises a warning alert once exceeded
+    #[serde(default)]
+    pub soft_limit: Option<u64>,
+
+    /// Raises a critical alert and applies `on_exceeded` once exceeded
+    #[serde(default)]
+    pub hard_limit: Option<u64>,
+}
+
+/// What happens once a hard recording quota is exceeded
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingQuotaPolicy {
+    /// Keep sessions going without recording them
+    #[serde(rename = "stop_recording")]
+    #[default]
+    StopRecording,
+    /// Refuse new sessions to the target or by the user
+    #[serde(rename = "block_sessions")]
+    BlockSessions,
+}
+
+/// What to do when recording data is produced faster than it can be written
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordingOverflowPolicy {
+    /// Slow the session down until the writer catches up
+    #[serde(rename = "block")]
+    #[default]
+    Block,
+    /// Keep the session going and leave the excess data out of the recording
+    #[serde(rename = "drop")]
+    Drop,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RecordingWriterConfig {
+    /// Maximum amount of recording data (in bytes) waiting to be written
+    /// before the overflow policy kicks in
+    #[serde(default = "_default_recording_queue_size")]
+    pub queue_size: usize,
+
+    /// Maximum amount of queued data written out in one go
+    #[serde(default = "_default_recording_batch_size")]
+    pub batch_size: usize,
+
+    #[serde(
+        default = "_default_recording_flush_interval",
+        with = "humantime_serde"
+    )]
+    pub flush_interval: Duration,
+
+    #[serde(default)]
+    pub on_overflow: RecordingOverflowPolicy,
+}
+
+impl Default for RecordingWriterConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: _default_recording_queue_size(),
+            batch_size: _default_recording_batch_size(),
+            flush_interval: _default_recording_flush_interval(),
+            on_overflow: <_>::default(),
+        }
+    }
+}
+
+/// Pushes finished recordings and their session audit data
+/// to the admin API of a standby Warpgate instance
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RecordingReplicationConfig {
+    /// Base URL of the remote admin API, e.g. `https://standby:8888/@warpgate/admin/api`
+    pub url: String,
+
+    /// Admin API token for the remote instance
+    pub token: Secret<String>,
+
+    #[serde(default = "_default_replication_interval", with = "humantime_serde")]
+    pub interval: Duration,
+
+    #[serde(default = "_default_replication_chunk_size")]
+    pub chunk_size: usize,
+
+    #[serde(default = "_default_true")]
+    pub verify_tls: bool,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct LogConfig {
     #[serde(default = "_default_retention", with = "humantime_serde")]
@@ -358,6 +791,19 @@ pub struct LogConfig {
 
     #[serde(default)]
     pub send_to: Option<String>,
+
+    /// Log the values bound to prepared statements along with their query
+    /// text. Values can contain personal data and secrets
+    #[serde(default = "_default_false")]
+    pub query_parameters: bool,
+
+    /// Store query and command logs in an analytics database instead of the
+    /// main one, and read usage statistics from it
+    #[serde(default)]
+    pub analytics_sink: Option<AnalyticsSinkConfig>,
+
+    #[serde(default)]
+    pub auth_failures: AuthFailureLogConfig,
 }
 
 impl Default for LogConfig {
@@ -365,10 +811,275 @@ impl Default for LogConfig {
         Self {
             retention: _default_retention(),
             send_to: None,
+            query_parameters: false,
+            analytics_sink: None,
+            auth_failures: AuthFailureLogConfig::default(),
+        }
+    }
+}
+
+/// Forensic records of failed logins
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthFailureLogConfig {
+    #[serde(default = "_default_auth_failure_retention", with = "humantime_serde")]
+    pub retention: Duration,
+
+    /// Failures recorded per source IP per minute. Anything above that is
+    /// dropped so that a brute force attack can't flood the database
+    #[serde(default = "_default_auth_failure_rate_limit")]
+    pub max_per_source_per_minute: u32,
+}
+
+impl Default for AuthFailureLogConfig {
+    fn default() -> Self {
+        Self {
+            retention: _default_auth_failure_retention(),
+            max_per_source_per_minute: _default_auth_failure_rate_limit(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type")]
+pub enum AnalyticsSinkConfig {
+    /// Uses the HTTP interface, e.g. `http://clickhouse:8123`
+    #[serde(rename = "clickhouse")]
+    ClickHouse {
+        url: String,
+        #[serde(default = "_default_analytics_database")]
+        database: String,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<Secret<String>>,
+    },
+    /// A PostgreSQL URL. Requires a build with PostgreSQL support.
+    #[serde(rename = "timescale")]
+    Timescale { url: Secret<String> },
+}
+
+/// Periodic scan of local networks for SSH servers that aren't targets yet.
+/// Candidates are listed in the admin API.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DiscoveryConfig {
+    #[serde(default = "_default_false")]
+    pub enable: bool,
+
+    /// Networks to scan in CIDR notation, e.g. `10.0.0.0/24`
+    #[serde(default)]
+    pub ranges: Vec<String>,
+
+    #[serde(default = "_default_discovery_ports")]
+    pub ports: Vec<u16>,
+
+    #[serde(default = "_default_discovery_interval", with = "humantime_serde")]
+    pub interval: Duration,
+
+    /// Per-host connection timeout
+    #[serde(default = "_default_discovery_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+
+    /// Maximum number of hosts probed at once
+    #[serde(default = "_default_discovery_concurrency")]
+    pub concurrency: usize,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            ranges: vec![],
+            ports: _default_discovery_ports(),
+            interval: _default_discovery_interval(),
+            timeout: _default_discovery_timeout(),
+            concurrency: _default_discovery_concurrency(),
+        }
+    }
+}
+
+/// Security alerts, e.g. a target presenting a different host key
+/// or certificate than it used to
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AlertsConfig {
+    /// URLs that every alert is POSTed to as JSON
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+/// TLS settings for connections that Warpgate makes itself -
+/// to targets, SSO providers, webhooks etc.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct OutboundTlsConfig {
+    /// PEM files with CA certificates to trust in addition to the system
+    /// root store, e.g. a corporate internal CA
+    #[serde(default)]
+    pub trusted_ca_certificates: Vec<String>,
+}
+
+/// Resolver used for target hostnames. Without any upstreams, the
+/// system configuration (`/etc/resolv.conf`) is used
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct DnsConfig {
+    #[serde(default)]
+    pub upstreams: Vec<DnsUpstream>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct DnsUpstream {
+    pub address: IpAddr,
+
+    /// Defaults to 53, or 853 for `tls` and 443 for `https`
+    #[serde(default)]
+    pub port: Option<u16>,
+
+    #[serde(default)]
+    pub protocol: DnsProtocol,
+
+    /// Name to verify the server certificate against, required for `tls`
+    /// and `https`
+    #[serde(default)]
+    pub tls_name: Option<String>,
+}
+
+impl DnsUpstream {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.protocol {
+            DnsProtocol::Udp | DnsProtocol::Tcp => 53,
+            DnsProtocol::Tls => 853,
+            DnsProtocol::Https => 443,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsProtocol {
+    #[default]
+    Udp,
+    Tcp,
+    /// DNS-over-TLS
+    Tls,
+    /// DNS-over-HTTPS
+    Https,
+}
+
+/// Temporary lockouts after repeated failed logins, across all protocols.
+/// Attempts are refused while the username or the source address is
+/// locked out, without checking the credentials.
+///
+/// Off by default: anyone who knows a username can keep it locked out,
+/// so enable it together with per-source limits or when usernames
+/// aren't guessable.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AuthRateLimitConfig {
+    #[serde(default = "_default_false")]
+    pub enable: bool,
+
+    #[serde(default = "_default_auth_rate_limit_per_user")]
+    pub per_user: AuthRateLimitRule,
+
+    #[serde(default = "_default_auth_rate_limit_per_source")]
+    pub per_source: AuthRateLimitRule,
+}
+
+impl Default for AuthRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            per_user: _default_auth_rate_limit_per_user(),
+            per_source: _default_auth_rate_limit_per_source(),
         }
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct AuthRateLimitRule {
+    /// Failures within `window` that trigger a lockout, `0` to turn the rule off
+    pub max_failures: u32,
+
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+
+    #[serde(with = "humantime_serde")]
+    pub lockout: Duration,
+}
+
+/// Per-session drop-box for exchanging files and text between the
+/// portal and an SSH session (see `warpgate-dropbox help`)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DropBoxConfig {
+    #[serde(default = "_default_false")]
+    pub enable: bool,
+
+    /// Maximum size of a single item in bytes
+    #[serde(default = "_default_drop_box_max_item_size")]
+    pub max_item_size: usize,
+
+    /// Maximum total size of the items in a session's drop-box in bytes
+    #[serde(default = "_default_drop_box_max_total_size")]
+    pub max_total_size: usize,
+
+    /// Maximum total size of the items in all sessions' drop-boxes,
+    /// including uploads in progress, in bytes. Items are kept in memory.
+    #[serde(default = "_default_drop_box_max_global_size")]
+    pub max_global_size: usize,
+
+    /// Items are removed after this time, or when the session ends
+    #[serde(default = "_default_drop_box_retention", with = "humantime_serde")]
+    pub retention: Duration,
+}
+
+impl Default for DropBoxConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            max_item_size: _default_drop_box_max_item_size(),
+            max_total_size: _default_drop_box_max_total_size(),
+            max_global_size: _default_drop_box_max_global_size(),
+            retention: _default_drop_box_retention(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeThreadsConfig {
+    /// Defaults to the number of CPU cores
+    #[serde(default)]
+    pub worker_threads: Option<usize>,
+
+    #[serde(default)]
+    pub max_blocking_threads: Option<usize>,
+
+    /// CPU core IDs to pin the runtime's threads to
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+}
+
+/// Async runtime layout. Protocol servers that have their own section
+/// here run on a dedicated runtime and don't compete with the rest of
+/// Warpgate for worker threads. Note that the admin API is served by
+/// the HTTP server.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    #[serde(flatten)]
+    pub main: RuntimeThreadsConfig,
+
+    #[serde(default)]
+    pub ssh: Option<RuntimeThreadsConfig>,
+
+    #[serde(default)]
+    pub http: Option<RuntimeThreadsConfig>,
+
+    #[serde(default)]
+    pub mysql: Option<RuntimeThreadsConfig>,
+
+    #[serde(default)]
+    pub postgres: Option<RuntimeThreadsConfig>,
+
+    #[serde(default)]
+    pub redis: Option<RuntimeThreadsConfig>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Default)]
 pub enum ConfigProviderKind {
     #[serde(rename = "file")]
@@ -392,6 +1103,10 @@ pub struct WarpgateConfigStore {
     #[serde(default = "_default_database_url")]
     pub database_url: Secret<String>,
 
+    /// Read-only replica of the database, used for list and report queries
+    #[serde(default)]
+    pub database_read_replica_url: Option<Secret<String>>,
+
     #[serde(default)]
     pub ssh: SshConfig,
 
@@ -404,11 +1119,40 @@ pub struct WarpgateConfigStore {
     #[serde(default)]
     pub postgres: PostgresConfig,
 
+    #[serde(default)]
+    pub redis: RedisConfig,
+
     #[serde(default)]
     pub log: LogConfig,
 
     #[serde(default)]
     pub config_provider: ConfigProviderKind,
+
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+
+    #[serde(default)]
+    pub drop_box: DropBoxConfig,
+
+    #[serde(default)]
+    pub alerts: AlertsConfig,
+
+    #[serde(default)]
+    pub outbound_tls: OutboundTlsConfig,
+
+    #[serde(default)]
+    pub dns: DnsConfig,
+
+    #[serde(default)]
+    pub auth_rate_limit: AuthRateLimitConfig,
+
+    /// Closes sessions of any protocol after this long without traffic.
+    /// Targets can override it with `idle_timeout_seconds`.
+    #[serde(default, with = "humantime_serde")]
+    pub idle_timeout: Option<Duration>,
 }
 
 impl Default for WarpgateConfigStore {
@@ -418,12 +1162,22 @@ impl Default for WarpgateConfigStore {
             recordings: <_>::default(),
             external_host: None,
             database_url: _default_database_url(),
+            database_read_replica_url: None,
             ssh: <_>::default(),
             http: <_>::default(),
             mysql: <_>::default(),
             postgres: <_>::default(),
+            redis: <_>::default(),
             log: <_>::default(),
             config_provider: <_>::default(),
+            runtime: <_>::default(),
+            discovery: <_>::default(),
+            drop_box: <_>::default(),
+            alerts: <_>::default(),
+            outbound_tls: <_>::default(),
+            dns: <_>::default(),
+            auth_rate_limit: <_>::default(),
+            idle_timeout: None,
         }
     }
 }
@@ -531,5 +1285,93 @@ impl WarpgateConfig {
                 warn!("Set the external port via the `http.external_port`, `ssh.external_port` or `mysql.external_port` options.");
             }
         }
+
+        for (section, socket) in [
+            ("ssh", &self.store.ssh.socket),
+            ("http", &self.store.http.socket),
+            ("mysql", &self.store.mysql.socket),
+            ("postgres", &self.store.postgres.socket),
+            ("redis", &self.store.redis.socket),
+        ] {
+            if let Some(dscp) = socket.dscp.filter(|x| *x > 63) {
+                warn!("`{section}.socket.dscp` must be between 0 and 63 (got {dscp}) - it will be ignored.");
+            }
+        }
+
+        let runtime = &self.store.runtime;
+        for (section, threads) in [
+            ("runtime", Some(&runtime.main)),
+            ("runtime.ssh", runtime.ssh.as_ref()),
+            ("runtime.http", runtime.http.as_ref()),
+            ("runtime.mysql", runtime.mysql.as_ref()),
+            ("runtime.postgres", runtime.postgres.as_ref()),
+            ("runtime.redis", runtime.redis.as_ref()),
+        ] {
+            let Some(threads) = threads else {
+                continue;
+            };
+            if threads.worker_threads == Some(0) {
+                warn!("`{section}.worker_threads` must be at least 1 - it will be ignored.");
+            }
+            if threads.max_blocking_threads == Some(0) {
+                warn!("`{section}.max_blocking_threads` must be at least 1 - it will be ignored.");
+            }
+        }
+
+        for (index, upstream) in self.store.dns.upstreams.iter().enumerate() {
+            if matches!(upstream.protocol, DnsProtocol::Tls | DnsProtocol::Https)
+                && upstream.tls_name.is_none()
+            {
+                warn!(
+                    "`dns.upstreams[{index}]` needs a `tls_name` for {:?} - it will be ignored.",
+                    upstream.protocol
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_keepalive_config() {
+        let config: SshConfig = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.keepalive_interval, None);
+        assert_eq!(config.keepalive_max, _default_ssh_keepalive_max());
+        assert_eq!(config.target_keepalive_interval, None);
+        assert_eq!(config.target_keepalive_max, _default_ssh_keepalive_max());
+
+        let config: SshConfig = serde_json::from_str(
+            r#"{
+                "keepalive_interval": "30s",
+                "keepalive_max": 5,
+                "target_keepalive_interval": "1m",
+                "target_keepalive_max": 2
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(30)));
+        assert_eq!(config.keepalive_max, 5);
+        assert_eq!(
+            config.target_keepalive_interval,
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(config.target_keepalive_max, 2);
+    }
+
+    #[test]
+    fn test_ssh_keepalive_interval_legacy_format() {
+        let config: SshConfig =
+            serde_json::from_str(r#"{"keepalive_interval": {"secs": 30, "nanos": 0}}"#).unwrap();
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(30)));
+
+        let config: SshConfig = serde_json::from_str(r#"{"keepalive_interval": null}"#).unwrap();
+        assert_eq!(config.keepalive_interval, None);
+
+        let serialized = serde_json::to_value(&config).unwrap();
+        let config: SshConfig = serde_json::from_value(serialized).unwrap();
+        assert_eq!(config.keepalive_interval, None);
     }
 }
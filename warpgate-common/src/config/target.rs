@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
 
 use poem_openapi::{Enum, Object, Union};
 use serde::{Deserialize, Serialize};
@@ -18,6 +19,144 @@ pub struct TargetSSHOptions {
     pub allow_insecure_algos: Option<bool>,
     #[serde(default)]
     pub auth: SSHTargetAuth,
+    /// Connect through AWS SSM Session Manager instead of directly
+    #[serde(default)]
+    pub aws_ssm: Option<Box<SshAwsSsmOptions>>,
+    #[serde(default)]
+    #[oai(default)]
+    pub forward_presets: Vec<SshForwardPreset>,
+    /// Unset on targets configured before forwarding policies existed,
+    /// see [TargetSSHOptions::effective_forwarding_policy]
+    #[serde(default)]
+    pub forwarding_policy: Option<SshForwardingPolicy>,
+    /// `host:port` patterns that port forwarding (including `ssh -D`)
+    /// may use under the `allowlist` policy
+    #[serde(default)]
+    #[oai(default)]
+    pub allowed_forward_destinations: Vec<String>,
+    /// Replace the target's forwarding policy for users with these roles
+    #[serde(default)]
+    #[oai(default)]
+    pub role_forwarding_policies: Vec<SshRoleForwardingPolicy>,
+    /// Reconnect and reopen shells when the connection to the target drops
+    /// instead of ending the session
+    #[serde(default)]
+    pub reconnect: Option<TargetSshReconnect>,
+    /// Share one connection to the target between sessions of the same
+    /// user instead of connecting and authenticating for each of them
+    #[serde(default)]
+    pub multiplexing: Option<TargetSshMultiplexing>,
+
+    /// Show the target's host key fingerprint to users before
+    /// connecting, so that they can verify it out-of-band
+    #[serde(default)]
+    #[oai(default)]
+    pub show_fingerprint: bool,
+
+    /// Reject `scp` commands that copy files to the target
+    #[serde(default)]
+    #[oai(default)]
+    pub block_scp_uploads: bool,
+    /// Reject `scp` commands that copy files from the target
+    #[serde(default)]
+    #[oai(default)]
+    pub block_scp_downloads: bool,
+}
+
+/// Only interactive shells are reopened - commands, file transfers and
+/// forwarded connections are closed when the connection drops, and the
+/// state of the shell itself (running programs, scrollback) is lost
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct TargetSshReconnect {
+    #[serde(default = "_default_ssh_reconnect_attempts")]
+    pub attempts: u32,
+    #[serde(default = "_default_ssh_reconnect_interval")]
+    pub interval_seconds: u64,
+}
+
+/// Sessions reuse a connection only while it's alive - a connection
+/// without sessions is kept open for `linger_seconds` and then closed
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct TargetSshMultiplexing {
+    #[serde(default = "_default_ssh_multiplexing_linger")]
+    pub linger_seconds: u64,
+}
+
+/// A named port-forward destination, so that users can reach it
+/// without knowing its address
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct SshForwardPreset {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// Roles that may use this preset in addition to having access to
+    /// the target. If empty, everyone with access to the target can.
+    #[serde(default)]
+    #[oai(default)]
+    pub allow_roles: Vec<String>,
+}
+
+/// Which port forwards users may open through an SSH target,
+/// both local (`ssh -L`, `-D`) and remote (`ssh -R`)
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Enum, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum SshForwardingPolicy {
+    AllowAll,
+    DenyAll,
+    /// Only to and from addresses matching the allowed destinations
+    Allowlist,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct SshRoleForwardingPolicy {
+    pub role: String,
+    pub policy: SshForwardingPolicy,
+    #[serde(default)]
+    #[oai(default)]
+    pub allowed_destinations: Vec<String>,
+}
+
+impl TargetSSHOptions {
+    /// Without an explicit policy, listing any destinations
+    /// restricts forwarding to them
+    pub fn effective_forwarding_policy(&self) -> SshForwardingPolicy {
+        self.forwarding_policy
+            .unwrap_or(if self.allowed_forward_destinations.is_empty() {
+                SshForwardingPolicy::AllowAll
+            } else {
+                SshForwardingPolicy::Allowlist
+            })
+    }
+}
+
+impl SshForwardPreset {
+    pub fn is_allowed_for(&self, user_roles: &[String]) -> bool {
+        self.allow_roles.is_empty() || self.allow_roles.iter().any(|x| user_roles.contains(x))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct SshAwsSsmOptions {
+    pub region: String,
+
+    #[serde(default)]
+    pub instance_id: Option<String>,
+
+    /// Used to look up a running instance if `instance_id` is not set
+    #[serde(default)]
+    #[oai(default)]
+    pub instance_tags: HashMap<String, String>,
+
+    /// Named profile from the AWS config on the Warpgate host
+    #[serde(default)]
+    pub profile: Option<String>,
+
+    #[serde(default)]
+    pub access_key_id: Option<String>,
+
+    #[serde(default)]
+    pub secret_access_key: Option<Secret<String>>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Union)]
@@ -26,6 +165,9 @@ pub struct TargetSSHOptions {
 pub enum SSHTargetAuth {
     #[serde(rename = "password")]
     Password(SshTargetPasswordAuth),
+    /// Must come before `PublicKey`, which has no required fields
+    #[serde(rename = "certificate")]
+    Certificate(SshTargetCertificateAuth),
     #[serde(rename = "publickey")]
     PublicKey(SshTargetPublicKeyAuth),
 }
@@ -36,7 +178,19 @@ pub struct SshTargetPasswordAuth {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object, Default)]
-pub struct SshTargetPublicKeyAuth {}
+pub struct SshTargetPublicKeyAuth {
+    /// Names of the client keys to present, all keys are tried if empty
+    #[serde(default)]
+    #[oai(default)]
+    pub keys: Vec<String>,
+}
+
+/// Authenticates with a short-lived certificate from Warpgate's SSH CA.
+/// The target has to trust the CA, e.g. through `TrustedUserCAKeys`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Object)]
+pub struct SshTargetCertificateAuth {
+    pub certificate_validity_seconds: u64,
+}
 
 impl Default for SSHTargetAuth {
     fn default() -> Self {
@@ -57,6 +211,60 @@ pub struct TargetHTTPOptions {
 
     #[serde(default)]
     pub external_host: Option<String>,
+
+    /// Bytes, unlimited if not set
+    #[serde(default)]
+    pub max_body_size: Option<u64>,
+
+    /// Mirrors GET and HEAD requests so that a new upstream can be tried
+    /// out on real traffic - clients only ever see the target's response
+    #[serde(default)]
+    pub shadow: Option<TargetHttpShadow>,
+
+    /// Saves request and response bodies into the session recording
+    #[serde(default)]
+    pub record_bodies: Option<TargetHttpBodyRecording>,
+
+    /// HTTP methods that may be used, e.g. `GET` and `HEAD` for read-only
+    /// access. Unrestricted if empty.
+    #[serde(default)]
+    #[oai(default)]
+    pub allowed_methods: Vec<String>,
+
+    /// Path patterns that may be requested, where `*` matches anything,
+    /// e.g. `/api/*`. Unrestricted if empty. When any path patterns are set,
+    /// paths with dot segments, `;`, `\` or encoded separators are refused.
+    #[serde(default)]
+    #[oai(default)]
+    pub allowed_paths: Vec<String>,
+
+    /// Path patterns that are refused even if they're allowed above
+    #[serde(default)]
+    #[oai(default)]
+    pub denied_paths: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct TargetHttpShadow {
+    #[serde(default = "_default_empty_string")]
+    pub url: String,
+
+    #[serde(default)]
+    pub tls: Tls,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct TargetHttpBodyRecording {
+    /// Bytes kept from each body, the rest is only counted
+    #[serde(default = "_default_http_recorded_body_size")]
+    pub max_size: usize,
+
+    /// Regular expressions for secrets to replace before the bodies and
+    /// URLs are stored. If a pattern has a capture group, only the group
+    /// is replaced.
+    #[serde(default)]
+    #[oai(default)]
+    pub redact: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Enum, PartialEq, Eq, Default)]
@@ -101,10 +309,48 @@ pub struct TargetMySqlOptions {
     pub username: String,
 
     #[serde(default)]
-    pub password: Option<String>,
+    pub password: Option<Secret<String>>,
 
     #[serde(default)]
     pub tls: Tls,
+
+    /// Run on every new target connection before the client can use it,
+    /// e.g. `SET SESSION sql_mode = 'STRICT_ALL_TABLES'`. A failing
+    /// statement ends the session.
+    #[serde(default)]
+    pub init_statements: Vec<String>,
+
+    /// Show the certificate fingerprint that the target presented on its
+    /// last TLS connection to users, so that they can verify it out-of-band
+    #[serde(default)]
+    #[oai(default)]
+    pub show_fingerprint: bool,
+
+    /// Let clients run several statements in one query, e.g. `SELECT 1; SELECT 2`
+    #[serde(default)]
+    #[oai(default)]
+    pub allow_multi_statements: bool,
+
+    #[serde(default)]
+    #[oai(default)]
+    pub change_user: MySqlChangeUserPolicy,
+
+    /// Lets users get a short-lived account on the target from the portal
+    /// to connect to it directly, bypassing Warpgate
+    #[serde(default)]
+    pub direct_credentials: Option<DirectCredentialsOptions>,
+}
+
+/// What happens when a client switches users with `COM_CHANGE_USER`
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Enum, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+#[oai(rename_all = "snake_case")]
+pub enum MySqlChangeUserPolicy {
+    #[default]
+    Deny,
+    /// Check the new credentials like on login. The new user has to
+    /// have access to the same target.
+    Reauthorize,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object)]
@@ -119,10 +365,154 @@ pub struct TargetPostgresOptions {
     pub username: String,
 
     #[serde(default)]
-    pub password: Option<String>,
+    pub password: Option<Secret<String>>,
 
     #[serde(default)]
     pub tls: Tls,
+
+    /// Multiplex client sessions onto a few shared target connections.
+    /// Each connection is only held for the duration of a transaction.
+    #[serde(default)]
+    pub transaction_pooling: Option<PostgresTransactionPooling>,
+
+    /// Receives a copy of every read-only simple query. Its results are
+    /// only compared with the target's and never returned to the client.
+    #[serde(default)]
+    pub shadow: Option<TargetPostgresShadow>,
+
+    /// Run on every new target connection, including pooled ones, before
+    /// the client can use it, e.g. `SET ROLE readonly`. A failing statement
+    /// ends the session.
+    #[serde(default)]
+    pub init_statements: Vec<String>,
+
+    /// Show the certificate fingerprint that the target presented on its
+    /// last TLS connection to users, so that they can verify it out-of-band
+    #[serde(default)]
+    #[oai(default)]
+    pub show_fingerprint: bool,
+
+    /// Lets users get a short-lived account on the target from the portal
+    /// to connect to it directly, bypassing Warpgate
+    #[serde(default)]
+    pub direct_credentials: Option<DirectCredentialsOptions>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct TargetPostgresShadow {
+    #[serde(default = "_default_empty_string")]
+    pub host: String,
+
+    #[serde(default = "_default_postgres_port")]
+    pub port: u16,
+
+    #[serde(default = "_default_username")]
+    pub username: String,
+
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+
+    #[serde(default)]
+    pub tls: Tls,
+}
+
+/// The target credentials need the privilege to create and drop users
+/// and to close their connections (on MySQL: `CREATE USER`, `PROCESS`
+/// and `CONNECTION_ADMIN`)
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct DirectCredentialsOptions {
+    /// The account is dropped from the target after this time and its
+    /// connections are closed. PostgreSQL accounts also expire on their
+    /// own through `VALID UNTIL`; MySQL has no equivalent, so there the
+    /// account stays usable until Warpgate gets to drop it.
+    #[serde(default = "_default_direct_credentials_ttl")]
+    pub ttl_seconds: u64,
+
+    /// Run after creating the account, with `{username}` replaced by its
+    /// quoted name, e.g. `GRANT SELECT ON ALL TABLES IN SCHEMA public TO {username}`
+    #[serde(default)]
+    pub grant_statements: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct PostgresTransactionPooling {
+    /// Per database
+    #[serde(default = "_default_postgres_pool_size")]
+    pub max_connections: u32,
+
+    /// Idle target connections are closed after this time
+    #[serde(default = "_default_postgres_pool_idle_timeout")]
+    pub idle_timeout_seconds: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct TargetRedisOptions {
+    #[serde(default = "_default_empty_string")]
+    pub host: String,
+
+    #[serde(default = "_default_redis_port")]
+    pub port: u16,
+
+    /// ACL user, only the password is sent if not set
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+
+    /// Redis has no TLS negotiation, so `Preferred` falls back
+    /// to a plain connection if the TLS handshake fails
+    #[serde(default)]
+    pub tls: Tls,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Enum, PartialEq, Eq, Default)]
+pub enum ContainerRuntime {
+    #[serde(rename = "docker")]
+    #[default]
+    Docker,
+    #[serde(rename = "podman")]
+    Podman,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct TargetDockerOptions {
+    /// SSH connection to the container host
+    pub ssh: TargetSSHOptions,
+
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
+
+    /// Daemon socket on the host, e.g. `unix:///run/podman/podman.sock`,
+    /// the runtime's default is used if not set
+    #[serde(default)]
+    pub socket: Option<String>,
+
+    /// Containers that users can exec into, a trailing `*` matches by prefix
+    #[serde(default)]
+    #[oai(default)]
+    pub allowed_containers: Vec<String>,
+
+    /// Started when no command is given
+    #[serde(default = "_default_container_shell")]
+    pub shell: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Object)]
+pub struct TargetIpmiOptions {
+    pub host: String,
+
+    #[serde(default = "_default_ipmi_port")]
+    pub port: u16,
+
+    pub username: String,
+
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+
+    /// IPMI 2.0 cipher suite ID, e.g. 17 for BMCs that disable the older ones
+    #[serde(default)]
+    pub cipher_suite: Option<u8>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, Object, Default)]
@@ -135,10 +525,55 @@ pub struct Target {
     pub name: String,
     #[serde(default = "_default_empty_vec")]
     pub allow_roles: Vec<String>,
+    /// Decoy for intrusion detection. Sessions never reach the real target
+    /// address: each one raises a critical alert, and SSH and HTTP clients
+    /// are served a built-in fake instead
+    #[serde(default)]
+    pub honeypot: bool,
+    /// Fixed addresses for hostnames that this target connects to,
+    /// taking precedence over DNS
+    #[serde(default)]
+    pub host_overrides: BTreeMap<String, IpAddr>,
+    /// Further sessions are rejected while this many are connected
+    #[serde(default)]
+    pub max_concurrent_sessions: Option<u32>,
+    /// Shown to users whose session is rejected because of the limit
+    #[serde(default)]
+    pub session_limit_message: Option<String>,
+    #[serde(default)]
+    pub monitor: Option<TargetMonitorOptions>,
+    /// Overrides the global `idle_timeout` for sessions to this target
+    #[serde(default)]
+    pub idle_timeout_seconds: Option<u32>,
+    /// New sessions are rejected while the target is drained for maintenance.
+    /// Set through the admin API rather than the target's settings.
+    #[serde(default)]
+    pub draining: bool,
     #[serde(flatten)]
     pub options: TargetOptions,
 }
 
+/// Scheduled connection tests, the same ones as `warpgate test-target`.
+/// An alert is raised when the target becomes unhealthy and when it recovers.
+#[derive(Debug, Deserialize, Serialize, Clone, Object, PartialEq, Eq)]
+pub struct TargetMonitorOptions {
+    #[serde(default = "_default_monitor_interval")]
+    #[oai(default = "_default_monitor_interval")]
+    pub interval_seconds: u32,
+    /// Consecutive failed tests before the target counts as unhealthy
+    #[serde(default = "_default_monitor_failure_threshold")]
+    #[oai(default = "_default_monitor_failure_threshold")]
+    pub failure_threshold: u32,
+}
+
+fn _default_monitor_interval() -> u32 {
+    300
+}
+
+fn _default_monitor_failure_threshold() -> u32 {
+    3
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Union)]
 #[oai(discriminator_name = "kind", one_of)]
 pub enum TargetOptions {
@@ -150,6 +585,33 @@ pub enum TargetOptions {
     MySql(TargetMySqlOptions),
     #[serde(rename = "postgres")]
     Postgres(TargetPostgresOptions),
+    #[serde(rename = "redis")]
+    Redis(TargetRedisOptions),
+    #[serde(rename = "docker")]
+    Docker(TargetDockerOptions),
+    #[serde(rename = "ipmi")]
+    Ipmi(TargetIpmiOptions),
     #[serde(rename = "web_admin")]
     WebAdmin(TargetWebAdminOptions),
 }
+
+impl TargetOptions {
+    /// Name of the protocol used to connect to the target
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            Self::Ssh(_) | Self::Docker(_) | Self::Ipmi(_) => "SSH",
+            Self::Http(_) | Self::WebAdmin(_) => "HTTP",
+            Self::MySql(_) => "MySQL",
+            Self::Postgres(_) => "PostgreSQL",
+            Self::Redis(_) => "Redis",
+        }
+    }
+
+    pub fn direct_credentials(&self) -> Option<&DirectCredentialsOptions> {
+        match self {
+            Self::MySql(options) => options.direct_credentials.as_ref(),
+            Self::Postgres(options) => options.direct_credentials.as_ref(),
+            _ => None,
+        }
+    }
+}
@@ -1,10 +1,100 @@
 use std::error::Error;
+use std::fmt::Display;
 
 use poem::error::ResponseError;
+use poem::Response;
 use poem_openapi::ApiResponse;
 use uuid::Uuid;
 use warpgate_sso::SsoError;
 
+use crate::AddressTemplateError;
+
+/// Stable identifiers shown to clients next to error messages, so that
+/// a failure can be matched to the server logs and reported to support.
+/// Codes must never be reused for a different condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// The provided credentials were rejected
+    AuthFailed,
+    /// The ticket doesn't exist, has expired or has been used up
+    InvalidTicket,
+    InvalidCredentialType,
+    /// The SSO provider failed or rejected the login
+    SsoFailed,
+    /// The user may not access the target or perform the request
+    AccessDenied,
+    UserNotFound,
+    RoleNotFound,
+    TargetNotFound,
+    /// The target is being drained for maintenance
+    TargetDraining,
+    /// The target's `max_concurrent_sessions` are all in use
+    TargetSessionLimitReached,
+    /// The target's SSH host key differs from the stored one
+    TargetHostKeyMismatch,
+    /// The target or user has used up their recording storage quota
+    RecordingQuotaExceeded,
+    /// The target reported an error after the connection was made
+    TargetError,
+    TargetConnectionFailed,
+    TargetConnectionClosed,
+    InvalidRequest,
+    /// The request is valid but not allowed by the target's configuration
+    RequestRejected,
+    NotImplemented,
+    /// The client doesn't support the TLS that Warpgate requires
+    TlsRequired,
+    ExternalHostUnknown,
+    ExternalHostNotWhitelisted,
+    SessionEnded,
+    SessionClosedByAdmin,
+    Database,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AuthFailed => "WG-AUTH-001",
+            Self::InvalidTicket => "WG-AUTH-002",
+            Self::InvalidCredentialType => "WG-AUTH-003",
+            Self::SsoFailed => "WG-AUTH-004",
+            Self::AccessDenied => "WG-AUTH-403",
+            Self::UserNotFound => "WG-USR-404",
+            Self::RoleNotFound => "WG-ROLE-404",
+            Self::TargetNotFound => "WG-TGT-404",
+            Self::TargetHostKeyMismatch => "WG-TGT-001",
+            Self::TargetDraining => "WG-TGT-002",
+            Self::TargetSessionLimitReached => "WG-TGT-003",
+            Self::RecordingQuotaExceeded => "WG-REC-001",
+            Self::TargetError => "WG-TGT-500",
+            Self::TargetConnectionFailed => "WG-TGT-502",
+            Self::TargetConnectionClosed => "WG-TGT-503",
+            Self::InvalidRequest => "WG-REQ-400",
+            Self::RequestRejected => "WG-REQ-403",
+            Self::NotImplemented => "WG-REQ-501",
+            Self::TlsRequired => "WG-TLS-001",
+            Self::ExternalHostUnknown => "WG-CFG-001",
+            Self::ExternalHostNotWhitelisted => "WG-CFG-002",
+            Self::SessionEnded => "WG-SES-001",
+            Self::SessionClosedByAdmin => "WG-SES-002",
+            Self::Database => "WG-DB-500",
+            Self::Internal => "WG-INT-500",
+        }
+    }
+
+    /// Appends the code to a message shown to the user
+    pub fn annotate(&self, message: impl Display) -> String {
+        format!("{message} [{self}]")
+    }
+}
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum WarpgateError {
     #[error("database error: {0}")]
@@ -40,20 +130,78 @@ pub enum WarpgateError {
     #[error("I/O: {0}")]
     Io(#[from] std::io::Error),
 
+    #[error("target {0} is under maintenance and doesn't accept new sessions")]
+    TargetDraining(String),
+
+    /// Carries the target name and its `session_limit_message`
+    #[error("{}", session_limit_message(.0, .1))]
+    TargetSessionLimit(String, Option<String>),
+
+    /// Only returned if `recordings.quotas.on_exceeded` is `block_sessions`
+    #[error("the recording storage quota of the {0} is used up")]
+    RecordingQuotaExceeded(String),
+
+    #[error("could not resolve the target address: {0}")]
+    AddressTemplate(#[from] AddressTemplateError),
+
     #[error("Session end")]
     SessionEnd,
 }
 
+fn session_limit_message(target: &str, message: &Option<String>) -> String {
+    match message {
+        Some(message) => message.clone(),
+        None => format!("target {target} has too many active sessions, try again later"),
+    }
+}
+
 impl ResponseError for WarpgateError {
     fn status(&self) -> poem::http::StatusCode {
         poem::http::StatusCode::INTERNAL_SERVER_ERROR
     }
+
+    fn as_response(&self) -> Response {
+        let body = serde_json::json!({
+            "code": self.code().as_str(),
+            "message": self.to_string(),
+        });
+        Response::builder()
+            .status(self.status())
+            .content_type("application/json")
+            .body(body.to_string())
+    }
 }
 
 impl WarpgateError {
     pub fn other<E: Error + Send + Sync + 'static>(err: E) -> Self {
         Self::Other(Box::new(err))
     }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::DatabaseError(_) => ErrorCode::Database,
+            Self::InvalidTicket(_) => ErrorCode::InvalidTicket,
+            Self::InvalidCredentialType => ErrorCode::InvalidCredentialType,
+            Self::UserNotFound(_) => ErrorCode::UserNotFound,
+            Self::RoleNotFound(_) => ErrorCode::RoleNotFound,
+            Self::UrlParse(_)
+            | Self::DeserializeJson(_)
+            | Self::NoHostInUrl
+            | Self::AddressTemplate(_) => ErrorCode::InvalidRequest,
+            Self::ExternalHostUnknown => ErrorCode::ExternalHostUnknown,
+            Self::ExternalHostNotWhitelisted(..) => ErrorCode::ExternalHostNotWhitelisted,
+            Self::Sso(_) => ErrorCode::SsoFailed,
+            Self::TargetDraining(_) => ErrorCode::TargetDraining,
+            Self::TargetSessionLimit(..) => ErrorCode::TargetSessionLimitReached,
+            Self::RecordingQuotaExceeded(_) => ErrorCode::RecordingQuotaExceeded,
+            Self::SessionEnd => ErrorCode::SessionEnded,
+            Self::Other(_)
+            | Self::InconsistentState
+            | Self::Anyhow(_)
+            | Self::RusshKeys(_)
+            | Self::Io(_) => ErrorCode::Internal,
+        }
+    }
 }
 
 impl ApiResponse for WarpgateError {
@@ -65,3 +213,39 @@ impl ApiResponse for WarpgateError {
         poem::error::Error::register(registry)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_error_response_body() {
+        let response = WarpgateError::UserNotFound("alice".into()).as_response();
+        assert_eq!(response.status(), 500);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_body().into_string().await.unwrap()).unwrap();
+        assert_eq!(body["code"], "WG-USR-404");
+        assert_eq!(body["message"], "user alice not found");
+    }
+
+    #[test]
+    fn test_session_limit_message() {
+        assert_eq!(
+            WarpgateError::TargetSessionLimit("db".into(), None).to_string(),
+            "target db has too many active sessions, try again later"
+        );
+        assert_eq!(
+            WarpgateError::TargetSessionLimit("db".into(), Some("Use db-replica".into()))
+                .to_string(),
+            "Use db-replica"
+        );
+    }
+
+    #[test]
+    fn test_annotate() {
+        assert_eq!(
+            ErrorCode::AuthFailed.annotate("Authentication failed"),
+            "Authentication failed [WG-AUTH-001]"
+        );
+    }
+}
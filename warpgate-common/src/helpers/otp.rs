@@ -10,7 +10,10 @@ pub type OtpExposedSecretKey = Vec<u8>;
 pub type OtpSecretKey = Secret<OtpExposedSecretKey>;
 
 pub fn generate_key() -> OtpSecretKey {
-    Secret::new(get_crypto_rng().gen::<[u8; 32]>().into())
+    // Filled in place so that no copy of the seed is left on the stack
+    let mut key = vec![0; 32];
+    get_crypto_rng().fill(&mut key[..]);
+    Secret::new(key)
 }
 
 pub fn generate_setup_url(key: &OtpSecretKey, label: &str) -> Secret<String> {
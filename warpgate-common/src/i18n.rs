@@ -0,0 +1,173 @@
+/// Languages that user-facing messages are translated to.
+/// Anything else falls back to English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+    French,
+}
+
+impl Language {
+    /// Accepts BCP 47 tags (`de-CH`) as well as POSIX locales (`de_CH.UTF-8`)
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_', '.', '@']).next()?;
+        match primary.to_ascii_lowercase().as_str() {
+            "en" => Some(Self::English),
+            "de" => Some(Self::German),
+            "fr" => Some(Self::French),
+            _ => None,
+        }
+    }
+
+    /// Picks the supported language with the highest quality value
+    /// from an `Accept-Language` header
+    pub fn from_accept_language(header: &str) -> Option<Self> {
+        let mut candidates = header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.split(';');
+                let language = Self::from_tag(parts.next()?)?;
+                let quality = parts
+                    .find_map(|x| x.trim().strip_prefix("q="))
+                    .map(|x| x.parse::<f32>().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                (quality > 0.0).then_some((language, quality))
+            })
+            .collect::<Vec<_>>();
+        // Stable, so the header order decides between equal values
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        candidates.first().map(|x| x.0)
+    }
+
+    /// Resolves the message language from POSIX locale variables
+    /// in their usual order of precedence
+    pub fn from_locale<'a>(
+        lc_all: Option<&'a str>,
+        lc_messages: Option<&'a str>,
+        lang: Option<&'a str>,
+    ) -> Option<Self> {
+        [lc_all, lc_messages, lang]
+            .into_iter()
+            .flatten()
+            .find(|x| !x.is_empty())
+            .and_then(Self::from_tag)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    IncorrectCredentials,
+    RequestFailed,
+    SelectedTarget,
+    SelectedTargetNotFound,
+    Connected,
+    ConnectionFailed,
+    ConnectionLost,
+    Reconnecting,
+    SessionClosedByAdmin,
+    HostKeyMismatch,
+    HostKeyMismatchHint,
+}
+
+impl Message {
+    pub fn text(&self, language: Language) -> &'static str {
+        use Language::*;
+        match (self, language) {
+            (Self::IncorrectCredentials, English) => "Incorrect credentials",
+            (Self::IncorrectCredentials, German) => "Ungültige Anmeldedaten",
+            (Self::IncorrectCredentials, French) => "Identifiants incorrects",
+
+            (Self::RequestFailed, English) => "Request failed",
+            (Self::RequestFailed, German) => "Anfrage fehlgeschlagen",
+            (Self::RequestFailed, French) => "La requête a échoué",
+
+            (Self::SelectedTarget, English) => "Selected target",
+            (Self::SelectedTarget, German) => "Ausgewähltes Ziel",
+            (Self::SelectedTarget, French) => "Cible sélectionnée",
+
+            (Self::SelectedTargetNotFound, English) => "Selected target not found",
+            (Self::SelectedTargetNotFound, German) => "Ausgewähltes Ziel nicht gefunden",
+            (Self::SelectedTargetNotFound, French) => "Cible sélectionnée introuvable",
+
+            (Self::Connected, English) => "Warpgate connected",
+            (Self::Connected, German) => "Warpgate verbunden",
+            (Self::Connected, French) => "Warpgate connecté",
+
+            (Self::ConnectionFailed, English) => "Connection failed",
+            (Self::ConnectionFailed, German) => "Verbindung fehlgeschlagen",
+            (Self::ConnectionFailed, French) => "Échec de la connexion",
+
+            (Self::ConnectionLost, English) => "Connection to the target lost",
+            (Self::ConnectionLost, German) => "Verbindung zum Ziel unterbrochen",
+            (Self::ConnectionLost, French) => "Connexion à la cible perdue",
+
+            (Self::Reconnecting, English) => "Reconnecting",
+            (Self::Reconnecting, German) => "Verbindung wird wiederhergestellt",
+            (Self::Reconnecting, French) => "Reconnexion",
+
+            (Self::SessionClosedByAdmin, English) => "Session closed by admin",
+            (Self::SessionClosedByAdmin, German) => "Sitzung vom Administrator beendet",
+            (Self::SessionClosedByAdmin, French) => "Session fermée par l'administrateur",
+
+            (Self::HostKeyMismatch, English) => "Host key doesn't match the stored one.",
+            (Self::HostKeyMismatch, German) => {
+                "Der Host-Schlüssel stimmt nicht mit dem gespeicherten überein."
+            }
+            (Self::HostKeyMismatch, French) => {
+                "La clé d'hôte ne correspond pas à celle enregistrée."
+            }
+
+            (Self::HostKeyMismatchHint, English) => concat!(
+                "If you know that the key is correct (e.g. it has been changed),\n",
+                "you can remove the old key in the Warpgate management UI and try again",
+            ),
+            (Self::HostKeyMismatchHint, German) => concat!(
+                "Wenn der Schlüssel korrekt ist (z. B. weil er geändert wurde),\n",
+                "entfernen Sie den alten Schlüssel in der Warpgate-Verwaltung und versuchen Sie es erneut",
+            ),
+            (Self::HostKeyMismatchHint, French) => concat!(
+                "Si la clé est correcte (par exemple, si elle a été changée),\n",
+                "supprimez l'ancienne clé dans l'interface d'administration de Warpgate et réessayez",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(Language::from_tag("de-CH"), Some(Language::German));
+        assert_eq!(Language::from_tag("fr_FR.UTF-8"), Some(Language::French));
+        assert_eq!(Language::from_tag("EN"), Some(Language::English));
+        assert_eq!(Language::from_tag("C.UTF-8"), None);
+        assert_eq!(Language::from_tag("*"), None);
+    }
+
+    #[test]
+    fn test_accept_language() {
+        let negotiate = Language::from_accept_language;
+        assert_eq!(negotiate("de-DE,de;q=0.9,en;q=0.8"), Some(Language::German));
+        assert_eq!(negotiate("ja, fr;q=0.5, en;q=0.7"), Some(Language::English));
+        assert_eq!(negotiate("en;q=0, fr"), Some(Language::French));
+        assert_eq!(negotiate("fr, de"), Some(Language::French));
+        assert_eq!(negotiate("ja"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn test_locale() {
+        assert_eq!(
+            Language::from_locale(None, Some("fr_FR.UTF-8"), Some("de_DE.UTF-8")),
+            Some(Language::French)
+        );
+        assert_eq!(
+            Language::from_locale(Some(""), None, Some("de_DE.UTF-8")),
+            Some(Language::German)
+        );
+        assert_eq!(Language::from_locale(Some("C"), None, Some("de")), None);
+    }
+}
@@ -4,11 +4,12 @@ pub mod consts;
 mod error;
 pub mod eventhub;
 pub mod helpers;
+pub mod i18n;
 mod tls;
 mod try_macro;
 mod types;
 
 pub use config::*;
-pub use error::WarpgateError;
+pub use error::{ErrorCode, WarpgateError};
 pub use tls::*;
 pub use types::*;
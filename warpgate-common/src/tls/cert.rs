@@ -2,11 +2,14 @@ use std::path::Path;
 use std::sync::Arc;
 
 use aho_corasick::AhoCorasick;
+use chrono::{DateTime, Utc};
 use poem::listener::RustlsCertificate;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
 use rustls::sign::{CertifiedKey, SigningKey};
+use simple_asn1::ASN1Block;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use zeroize::Zeroize;
 
 use crate::RustlsSetupError;
 
@@ -48,6 +51,34 @@ impl TlsCertificateBundle {
             certificates,
         })
     }
+
+    /// Expiry of the leaf certificate, if it can be parsed
+    pub fn not_after(&self) -> Option<DateTime<Utc>> {
+        certificate_not_after(self.certificates.first()?)
+    }
+}
+
+/// Reads `tbsCertificate.validity.notAfter` from a DER certificate
+fn certificate_not_after(der: &[u8]) -> Option<DateTime<Utc>> {
+    let blocks = simple_asn1::from_der(der).ok()?;
+    let ASN1Block::Sequence(_, certificate) = blocks.first()? else {
+        return None;
+    };
+    let ASN1Block::Sequence(_, tbs_certificate) = certificate.first()? else {
+        return None;
+    };
+    // The only field that's a sequence of two timestamps
+    tbs_certificate.iter().find_map(|block| {
+        let ASN1Block::Sequence(_, validity) = block else {
+            return None;
+        };
+        match validity.as_slice() {
+            [ASN1Block::UTCTime(..) | ASN1Block::GeneralizedTime(..), ASN1Block::UTCTime(_, time) | ASN1Block::GeneralizedTime(_, time)] => {
+                DateTime::from_timestamp(time.assume_utc().unix_timestamp(), 0)
+            }
+            _ => None,
+        }
+    })
 }
 
 impl TlsPrivateKey {
@@ -58,7 +89,7 @@ impl TlsPrivateKey {
         Self::from_bytes(bytes)
     }
 
-    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, RustlsSetupError> {
+    pub fn from_bytes(mut bytes: Vec<u8>) -> Result<Self, RustlsSetupError> {
         let bytes = {
             // https://github.com/rustls/rustls/issues/767
             #[allow(clippy::expect_used)]
@@ -68,29 +99,27 @@ impl TlsPrivateKey {
                 dst.extend_from_slice(b"PRIVATE KEY");
                 true
             });
+            bytes.zeroize();
             new_bytes
         };
-        let mut key = rustls_pemfile::pkcs8_private_keys(&mut bytes.as_slice())?
-            .drain(..)
-            .next()
-            .and_then(|x| PrivateKeyDer::try_from(x).ok());
-
-        if key.is_none() {
-            key = rustls_pemfile::ec_private_keys(&mut bytes.as_slice())?
-                .drain(..)
-                .next()
-                .and_then(|x| PrivateKeyDer::try_from(x).ok());
-        }
-
-        if key.is_none() {
-            key = rustls_pemfile::rsa_private_keys(&mut bytes.as_slice())?
-                .drain(..)
-                .next()
-                .and_then(|x| PrivateKeyDer::try_from(x).ok());
-        }
-
-        let key = key.ok_or(RustlsSetupError::NoKeys)?;
-        let key = rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)?;
+        // Decoded keys are only borrowed so that every copy can be wiped afterwards
+        let mut candidates = [
+            rustls_pemfile::pkcs8_private_keys(&mut bytes.as_slice())?,
+            rustls_pemfile::ec_private_keys(&mut bytes.as_slice())?,
+            rustls_pemfile::rsa_private_keys(&mut bytes.as_slice())?,
+        ];
+
+        let key = candidates
+            .iter()
+            .find_map(|keys| {
+                keys.first()
+                    .and_then(|x| PrivateKeyDer::try_from(x.as_slice()).ok())
+            })
+            .ok_or(RustlsSetupError::NoKeys)
+            .and_then(|key| Ok(rustls::crypto::aws_lc_rs::sign::any_supported_type(&key)?));
+
+        candidates.iter_mut().flatten().for_each(Zeroize::zeroize);
+        let key = key?;
 
         Ok(Self { bytes, key })
     }
@@ -102,9 +131,15 @@ impl From<TlsCertificateBundle> for Vec<u8> {
     }
 }
 
+impl Drop for TlsPrivateKey {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+    }
+}
+
 impl From<TlsPrivateKey> for Vec<u8> {
-    fn from(val: TlsPrivateKey) -> Self {
-        val.bytes
+    fn from(mut val: TlsPrivateKey) -> Self {
+        std::mem::take(&mut val.bytes)
     }
 }
 
@@ -122,8 +157,31 @@ impl From<TlsCertificateAndPrivateKey> for CertifiedKey {
         let key = val.private_key;
         CertifiedKey {
             cert: cert.certificates,
-            key: key.key,
+            key: key.key.clone(),
             ocsp: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_not_after() {
+        let mut params = rcgen::CertificateParams::new(vec!["warpgate.local".into()]);
+        params.not_after = rcgen::date_time_ymd(2031, 5, 17);
+        let pem = rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_pem()
+            .unwrap();
+
+        let bundle = TlsCertificateBundle::from_bytes(pem.into_bytes()).unwrap();
+        assert_eq!(
+            bundle.not_after(),
+            Some(Utc.with_ymd_and_hms(2031, 5, 17, 0, 0, 0).unwrap())
+        );
+    }
+}
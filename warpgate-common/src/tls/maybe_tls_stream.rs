@@ -46,6 +46,13 @@ where
     pub fn new(stream: S) -> Self {
         Self::Raw(stream)
     }
+
+    pub fn tls(&self) -> Option<&TS> {
+        match self {
+            Self::Tls(stream) => Some(stream),
+            _ => None,
+        }
+    }
 }
 
 impl<S, TS> MaybeTlsStream<S, TS>
@@ -8,4 +8,6 @@ pub use cert::*;
 pub use error::*;
 pub use maybe_tls_stream::{MaybeTlsStream, MaybeTlsStreamError, UpgradableStream};
 pub use rustls_helpers::{configure_tls_connector, ResolveServerCert};
-pub use rustls_root_certs::ROOT_CERT_STORE;
+pub use rustls_root_certs::{
+    root_cert_store, set_trusted_ca_certificates, trusted_ca_certificates, ROOT_CERT_STORE,
+};
@@ -8,7 +8,7 @@ use rustls::server::{ClientHello, ResolvesServerCert};
 use rustls::sign::CertifiedKey;
 use rustls::{CertificateError, ClientConfig, Error as TlsError, SignatureScheme};
 
-use super::{RustlsSetupError, ROOT_CERT_STORE};
+use super::{root_cert_store, RustlsSetupError};
 
 #[derive(Debug)]
 pub struct ResolveServerCert(pub Arc<CertifiedKey>);
@@ -35,7 +35,7 @@ pub async fn configure_tls_connector(
             .with_custom_certificate_verifier(Arc::new(DummyTlsVerifier))
             .with_no_client_auth()
     } else {
-        let mut cert_store = ROOT_CERT_STORE.clone();
+        let mut cert_store = root_cert_store()?;
 
         if let Some(data) = root_cert {
             let mut cursor = Cursor::new(data);
@@ -1,7 +1,12 @@
+use std::sync::RwLock;
+
 use once_cell::sync::Lazy;
 use rustls::pki_types::CertificateDer;
 use rustls::RootCertStore;
 
+use super::RustlsSetupError;
+use crate::WarpgateConfig;
+
 #[allow(clippy::expect_used)]
 pub static ROOT_CERT_STORE: Lazy<RootCertStore> = Lazy::new(|| {
     let mut roots = RootCertStore::empty();
@@ -14,3 +19,49 @@ pub static ROOT_CERT_STORE: Lazy<RootCertStore> = Lazy::new(|| {
     }
     roots
 });
+
+static TRUSTED_CA_CERTIFICATES: Lazy<RwLock<Vec<CertificateDer<'static>>>> =
+    Lazy::new(Default::default);
+
+/// Additional CAs from `outbound_tls.trusted_ca_certificates`
+pub fn trusted_ca_certificates() -> Vec<CertificateDer<'static>> {
+    TRUSTED_CA_CERTIFICATES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+pub fn set_trusted_ca_certificates(certificates: Vec<CertificateDer<'static>>) {
+    warpgate_sso::set_trusted_ca_certificates(
+        certificates.iter().map(|cert| cert.to_vec()).collect(),
+    );
+    *TRUSTED_CA_CERTIFICATES
+        .write()
+        .unwrap_or_else(|e| e.into_inner()) = certificates;
+}
+
+/// System roots plus the configured additional CAs
+pub fn root_cert_store() -> Result<RootCertStore, RustlsSetupError> {
+    let mut store = ROOT_CERT_STORE.clone();
+    for cert in trusted_ca_certificates() {
+        store.add(cert)?;
+    }
+    Ok(store)
+}
+
+impl WarpgateConfig {
+    pub fn load_trusted_ca_certificates(
+        &self,
+    ) -> Result<Vec<CertificateDer<'static>>, RustlsSetupError> {
+        let mut result = vec![];
+        for path in &self.store.outbound_tls.trusted_ca_certificates {
+            let bytes = std::fs::read(self.paths_relative_to.join(path))?;
+            let certificates = rustls_pemfile::certs(&mut &bytes[..])?;
+            if certificates.is_empty() {
+                return Err(RustlsSetupError::NoCertificates);
+            }
+            result.extend(certificates.into_iter().map(CertificateDer::from));
+        }
+        Ok(result)
+    }
+}
@@ -4,12 +4,15 @@ use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 
 use futures::stream::{iter, FuturesUnordered};
 use futures::{Stream, StreamExt, TryStreamExt};
-use poem::listener::Listener;
+use poem::http::uri::Scheme;
+use poem::listener::{Acceptor, Listener, TcpAcceptor};
+use poem::web::{LocalAddr, RemoteAddr};
 use serde::{Deserialize, Serialize};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_stream::wrappers::TcpListenerStream;
+use tracing::warn;
 
-use crate::WarpgateError;
+use crate::{TcpSocketOptions, WarpgateError};
 
 #[derive(Clone)]
 pub struct ListenEndpoint(SocketAddr);
@@ -45,15 +48,18 @@ impl ListenEndpoint {
             .await?)
     }
 
-    pub async fn poem_listener(&self) -> Result<poem::listener::BoxListener, WarpgateError> {
+    pub async fn poem_listener(
+        &self,
+        options: &TcpSocketOptions,
+    ) -> Result<poem::listener::BoxListener, WarpgateError> {
         let addrs = self.addresses_to_listen_on()?;
         #[allow(clippy::unwrap_used)] // length known >=1
         let (first, rest) = addrs.split_first().unwrap();
         let mut listener: poem::listener::BoxListener =
-            poem::listener::TcpListener::bind(first.to_string()).boxed();
+            TunedTcpListener::new(*first, options).boxed();
         for addr in rest {
             listener = listener
-                .combine(poem::listener::TcpListener::bind(addr.to_string()))
+                .combine(TunedTcpListener::new(*addr, options))
                 .boxed();
         }
 
@@ -62,19 +68,76 @@ impl ListenEndpoint {
 
     pub async fn tcp_accept_stream(
         &self,
+        options: &TcpSocketOptions,
     ) -> Result<impl Stream<Item = std::io::Result<TcpStream>>, WarpgateError> {
+        let options = options.clone();
         Ok(iter(
             self.tcp_listeners()
                 .await?
                 .into_iter()
                 .map(TcpListenerStream::new),
         )
-        .flatten_unordered(None))
+        .flatten_unordered(None)
+        .inspect_ok(move |stream| apply_socket_options(&options, stream)))
     }
 
     pub fn port(&self) -> u16 {
         self.0.port()
     }
+
+    pub fn address(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+fn apply_socket_options(options: &TcpSocketOptions, stream: &TcpStream) {
+    if let Err(error) = options.apply(stream) {
+        warn!(?error, peer=?stream.peer_addr().ok(), "Failed to apply socket options");
+    }
+}
+
+struct TunedTcpListener {
+    addr: SocketAddr,
+    options: TcpSocketOptions,
+}
+
+impl TunedTcpListener {
+    fn new(addr: SocketAddr, options: &TcpSocketOptions) -> Self {
+        Self {
+            addr,
+            options: options.clone(),
+        }
+    }
+}
+
+impl Listener for TunedTcpListener {
+    type Acceptor = TunedTcpAcceptor;
+
+    async fn into_acceptor(self) -> std::io::Result<Self::Acceptor> {
+        Ok(TunedTcpAcceptor {
+            inner: TcpAcceptor::from_tokio(TcpListener::bind(self.addr).await?)?,
+            options: self.options,
+        })
+    }
+}
+
+struct TunedTcpAcceptor {
+    inner: TcpAcceptor,
+    options: TcpSocketOptions,
+}
+
+impl Acceptor for TunedTcpAcceptor {
+    type Io = TcpStream;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.inner.local_addr()
+    }
+
+    async fn accept(&mut self) -> std::io::Result<(TcpStream, LocalAddr, RemoteAddr, Scheme)> {
+        let result = self.inner.accept().await?;
+        apply_socket_options(&self.options, &result.0);
+        Ok(result)
+    }
 }
 
 impl From<SocketAddr> for ListenEndpoint {
@@ -1,7 +1,9 @@
 mod aliases;
 mod listen_endpoint;
 mod secret;
+mod socket_options;
 
 pub use aliases::*;
 pub use listen_endpoint::*;
 pub use secret::*;
+pub use socket_options::*;
@@ -8,11 +8,14 @@ use poem_openapi::registry::{MetaSchemaRef, Registry};
 use poem_openapi::types::{ParseError, ParseFromJSON, ToJSON};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
 use crate::helpers::rng::get_crypto_rng;
 
+/// Wraps sensitive values, hiding them from `Debug` output and
+/// zeroizing the memory when dropped.
 #[derive(PartialEq, Eq, Clone)]
-pub struct Secret<T>(T);
+pub struct Secret<T: Zeroize>(T);
 
 impl Secret<String> {
     pub fn random() -> Self {
@@ -20,7 +23,7 @@ impl Secret<String> {
     }
 }
 
-impl<T> Secret<T> {
+impl<T: Zeroize> Secret<T> {
     pub const fn new(v: T) -> Self {
         Self(v)
     }
@@ -30,7 +33,13 @@ impl<T> Secret<T> {
     }
 }
 
-impl<T> From<T> for Secret<T> {
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
     fn from(v: T) -> Self {
         Self::new(v)
     }
@@ -38,7 +47,7 @@ impl<T> From<T> for Secret<T> {
 
 impl<'de, T> Deserialize<'de> for Secret<T>
 where
-    T: Deserialize<'de>,
+    T: Deserialize<'de> + Zeroize,
 {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -51,7 +60,7 @@ where
 
 impl<T> Serialize for Secret<T>
 where
-    T: Serialize,
+    T: Serialize + Zeroize,
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -61,13 +70,13 @@ where
     }
 }
 
-impl<T> Debug for Secret<T> {
+impl<T: Zeroize> Debug for Secret<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "<secret>")
     }
 }
 
-impl<T: poem_openapi::types::Type> poem_openapi::types::Type for Secret<T> {
+impl<T: poem_openapi::types::Type + Zeroize> poem_openapi::types::Type for Secret<T> {
     const IS_REQUIRED: bool = T::IS_REQUIRED;
     type RawValueType = T::RawValueType;
     type RawElementValueType = T::RawElementValueType;
@@ -94,7 +103,7 @@ impl<T: poem_openapi::types::Type> poem_openapi::types::Type for Secret<T> {
     }
 }
 
-impl<T: ParseFromJSON> ParseFromJSON for Secret<T> {
+impl<T: ParseFromJSON + Zeroize> ParseFromJSON for Secret<T> {
     fn parse_from_json(value: Option<serde_json::Value>) -> poem_openapi::types::ParseResult<Self> {
         T::parse_from_json(value)
             .map(Self::new)
@@ -102,8 +111,39 @@ impl<T: ParseFromJSON> ParseFromJSON for Secret<T> {
     }
 }
 
-impl<T: ToJSON> ToJSON for Secret<T> {
+impl<T: ToJSON + Zeroize> ToJSON for Secret<T> {
     fn to_json(&self) -> Option<serde_json::Value> {
         self.0.to_json()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    /// Counts how many times it was zeroized
+    #[derive(Clone)]
+    struct Canary(Rc<Cell<usize>>);
+
+    impl Zeroize for Canary {
+        fn zeroize(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn secrets_are_zeroized_on_drop() {
+        let zeroized = Rc::new(Cell::new(0));
+        let secret = Secret::new(Canary(zeroized.clone()));
+        let copy = secret.clone();
+        assert_eq!(zeroized.get(), 0);
+
+        drop(secret);
+        assert_eq!(zeroized.get(), 1);
+        drop(copy);
+        assert_eq!(zeroized.get(), 2);
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::TcpStream;
+
+/// Per-listener tuning applied to every accepted TCP connection
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct TcpSocketOptions {
+    #[serde(default)]
+    pub nodelay: Option<bool>,
+
+    /// Idle time before the first keepalive probe. Enables TCP keepalive
+    #[serde(default, with = "humantime_serde")]
+    pub keepalive_time: Option<Duration>,
+
+    #[serde(default, with = "humantime_serde")]
+    pub keepalive_interval: Option<Duration>,
+
+    #[serde(default)]
+    pub keepalive_retries: Option<u32>,
+
+    /// DSCP code point (0-63) to mark outgoing packets with
+    #[serde(default)]
+    pub dscp: Option<u8>,
+
+    #[serde(default)]
+    pub send_buffer_size: Option<usize>,
+
+    #[serde(default)]
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl TcpSocketOptions {
+    pub fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        let socket = SockRef::from(stream);
+
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+
+        if let Some(time) = self.keepalive_time {
+            let keepalive = TcpKeepalive::new().with_time(time);
+            let keepalive = match self.keepalive_interval {
+                Some(interval) => keepalive.with_interval(interval),
+                None => keepalive,
+            };
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            let keepalive = match self.keepalive_retries {
+                Some(retries) => keepalive.with_retries(retries),
+                None => keepalive,
+            };
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+
+        if let Some(dscp) = self.dscp.filter(|x| *x <= 63) {
+            // DSCP occupies the upper six bits of the TOS / traffic class byte
+            let tos = (dscp as u32) << 2;
+            match stream.local_addr()? {
+                std::net::SocketAddr::V4(_) => socket.set_tos(tos)?,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                std::net::SocketAddr::V6(_) => socket.set_tclass_v6(tos)?,
+                #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+                std::net::SocketAddr::V6(_) => (),
+            }
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
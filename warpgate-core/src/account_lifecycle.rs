@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use tracing::*;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
+use warpgate_db_entities::Session::SessionTerminationReason;
+use warpgate_db_entities::{ApiToken, RefreshToken, Ticket, User};
+
+use crate::{record_config_change, ConfigChangeAuthor, Services};
+
+/// How often accounts are checked for expiry
+pub const ACCOUNT_LIFECYCLE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Deactivates accounts whose validity period has ended. Logins are refused
+/// from the moment an account expires - this additionally closes its live
+/// sessions and revokes its tickets and tokens so that nothing outlives it.
+/// Returns the names of the deactivated accounts.
+pub async fn deactivate_expired_accounts(
+    services: &Services,
+) -> Result<Vec<String>, WarpgateError> {
+    let now = Utc::now();
+    let mut deactivated = vec![];
+    {
+        let db = services.db.lock().await;
+        let expired = User::Entity::find()
+            .filter(User::Column::ValidUntil.lte(now))
+            .filter(User::Column::Deactivated.is_null())
+            .all(&*db)
+            .await?;
+
+        for user in expired {
+            ApiToken::Entity::delete_many()
+                .filter(ApiToken::Column::UserId.eq(user.id))
+                .exec(&*db)
+                .await?;
+            RefreshToken::Entity::delete_many()
+                .filter(RefreshToken::Column::UserId.eq(user.id))
+                .exec(&*db)
+                .await?;
+            Ticket::Entity::delete_many()
+                .filter(Ticket::Column::Username.eq(&user.username))
+                .exec(&*db)
+                .await?;
+
+            let username = user.username.clone();
+            let mut model: User::ActiveModel = user.into();
+            model.deactivated = Set(Some(now));
+            let user = model.update(&*db).await?;
+            record_config_change(
+                &db,
+                ConfigObjectKind::User,
+                user.id,
+                ConfigChangeAction::Update,
+                &ConfigChangeAuthor(None),
+            )
+            .await?;
+            deactivated.push(username);
+        }
+    }
+
+    if deactivated.is_empty() {
+        return Ok(deactivated);
+    }
+    services.authorization_cache.invalidate();
+
+    let state = services.state.lock().await;
+    for username in &deactivated {
+        let closed_sessions = state
+            .close_user_sessions(username, SessionTerminationReason::PolicyViolation)
+            .await;
+        warn!(%username, closed_sessions, "Deactivated an expired account");
+    }
+
+    Ok(deactivated)
+}
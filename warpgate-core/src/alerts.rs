@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::*;
+use warpgate_common::eventhub::{EventHub, EventSender, EventSubscription};
+
+use crate::{http_client_builder, SharedConfig};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertSeverity {
+    /// e.g. recovery from an earlier alert
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub time: DateTime<Utc>,
+    pub severity: AlertSeverity,
+    /// e.g. `target_baseline_mismatch`
+    pub kind: String,
+    pub message: String,
+    pub target: Option<String>,
+    pub details: serde_json::Value,
+}
+
+/// Fans security alerts out to in-process subscribers
+/// and to the webhooks from the `alerts` config section
+#[derive(Clone)]
+pub struct Alerts {
+    hub: Arc<EventHub<Alert>>,
+    sender: EventSender<Alert>,
+}
+
+impl Alerts {
+    pub async fn new(config: Arc<SharedConfig>) -> Self {
+        let (hub, sender) = EventHub::setup();
+        let alerts = Self {
+            hub: Arc::new(hub),
+            sender,
+        };
+        tokio::spawn(deliver_to_webhooks(alerts.subscribe().await, config));
+        alerts
+    }
+
+    pub async fn raise(&self, alert: Alert) {
+        match alert.severity {
+            AlertSeverity::Critical => {
+                error!(kind=%alert.kind, target=?alert.target, details=%alert.details, "Alert: {}", alert.message)
+            }
+            AlertSeverity::Warning => {
+                warn!(kind=%alert.kind, target=?alert.target, details=%alert.details, "Alert: {}", alert.message)
+            }
+            AlertSeverity::Info => {
+                info!(kind=%alert.kind, target=?alert.target, details=%alert.details, "Alert: {}", alert.message)
+            }
+        }
+        let _ = self.sender.send_all(alert).await;
+    }
+
+    pub async fn subscribe(&self) -> EventSubscription<Alert> {
+        self.hub.subscribe(|_| true).await
+    }
+}
+
+async fn deliver_to_webhooks(mut alerts: EventSubscription<Alert>, config: Arc<SharedConfig>) {
+    let client = http_client_builder().build().unwrap_or_default();
+    while let Some(alert) = alerts.recv().await {
+        let webhooks = config.load().store.alerts.webhooks.clone();
+        for url in webhooks {
+            let result = client
+                .post(&url)
+                .json(&alert)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(error) = result {
+                error!(%url, %error, "Failed to deliver an alert to the webhook");
+            }
+        }
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::{LogEntry, Session};
+
+use crate::AnalyticsSinkHandle;
+
+const CACHE_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_RANGE: chrono::Duration = chrono::Duration::days(30);
+
+/// Log messages whose `query` / `command` values are counted as commands
+pub(crate) const COMMAND_LOG_MESSAGES: &[&str] = &["SQL", "Query", "Requested exec"];
+pub(crate) const COMMAND_LOG_KEYS: &[&str] = &["query", "command"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Enum)]
+#[oai(rename_all = "lowercase")]
+pub enum UsageGrouping {
+    Target,
+    User,
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsageRange {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl UsageRange {
+    fn resolve(&self) -> (DateTime<Utc>, DateTime<Utc>) {
+        let to = self.to.unwrap_or_else(Utc::now);
+        let from = self.from.unwrap_or(to - DEFAULT_RANGE);
+        (from, to)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct UsageBucket {
+    pub key: String,
+    pub sessions: u64,
+    pub total_duration_seconds: f64,
+    pub median_duration_seconds: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct UsageSummary {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub total_sessions: u64,
+    pub active_sessions: u64,
+    pub unique_users: u64,
+    pub unique_targets: u64,
+    pub median_duration_seconds: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct CommandUsage {
+    pub command: String,
+    pub count: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum CacheKey {
+    Summary(UsageRange),
+    Sessions(UsageRange, UsageGrouping),
+    TopCommands(UsageRange, u64),
+}
+
+#[derive(Clone)]
+enum CacheValue {
+    Summary(UsageSummary),
+    Sessions(Vec<UsageBucket>),
+    TopCommands(Vec<CommandUsage>),
+}
+
+/// Server-side usage aggregation for admin dashboards.
+/// Results are cached for a short period since they require full scans.
+/// Ended sessions and commands are read from the analytics sink if one is configured.
+/// Only the cache is locked, so concurrent requests don't queue behind a scan.
+pub struct UsageAnalytics {
+    db: Arc<Mutex<DatabaseConnection>>,
+    sink: Option<AnalyticsSinkHandle>,
+    cache: SyncMutex<HashMap<CacheKey, (Instant, CacheValue)>>,
+}
+
+impl UsageAnalytics {
+    pub fn new(db: &Arc<Mutex<DatabaseConnection>>, sink: Option<AnalyticsSinkHandle>) -> Self {
+        Self {
+            db: db.clone(),
+            sink,
+            cache: SyncMutex::new(HashMap::new()),
+        }
+    }
+
+    fn cached(&self, key: &CacheKey) -> Option<CacheValue> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.retain(|_, (created, _)| created.elapsed() < CACHE_TTL);
+        cache.get(key).map(|(_, v)| v.clone())
+    }
+
+    fn store(&self, key: CacheKey, value: CacheValue) {
+        self.cache
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, (Instant::now(), value));
+    }
+
+    async fn sessions_in_range(
+        &self,
+        range: &UsageRange,
+    ) -> Result<Vec<Session::Model>, WarpgateError> {
+        let (from, to) = range.resolve();
+        let db = self.db.lock().await;
+        Ok(Session::Entity::find()
+            .filter(Session::Column::Started.gte(from))
+            .filter(Session::Column::Started.lt(to))
+            .order_by_asc(Session::Column::Started)
+            .all(&*db)
+            .await?)
+    }
+
+    pub async fn summary(&self, range: UsageRange) -> Result<UsageSummary, WarpgateError> {
+        let key = CacheKey::Summary(range);
+        if let Some(CacheValue::Summary(v)) = self.cached(&key) {
+            return Ok(v);
+        }
+
+        let (from, to) = range.resolve();
+
+        if let Some(ref sink) = self.sink {
+            let ended = sink.sink().summary(from, to).await?;
+            let active_sessions = {
+                let db = self.db.lock().await;
+                Session::Entity::find()
+                    .filter(Session::Column::Started.gte(from))
+                    .filter(Session::Column::Started.lt(to))
+                    .filter(Session::Column::Ended.is_null())
+                    .count(&*db)
+                    .await?
+            };
+            let summary = UsageSummary {
+                from,
+                to,
+                total_sessions: ended.sessions + active_sessions,
+                active_sessions,
+                unique_users: ended.unique_users,
+                unique_targets: ended.unique_targets,
+                median_duration_seconds: ended.median_duration_seconds,
+            };
+            self.store(key, CacheValue::Summary(summary.clone()));
+            return Ok(summary);
+        }
+
+        let sessions = self.sessions_in_range(&range).await?;
+        let summary = summarize(&sessions, from, to);
+
+        self.store(key, CacheValue::Summary(summary.clone()));
+        Ok(summary)
+    }
+
+    pub async fn sessions(
+        &self,
+        range: UsageRange,
+        grouping: UsageGrouping,
+    ) -> Result<Vec<UsageBucket>, WarpgateError> {
+        let key = CacheKey::Sessions(range, grouping);
+        if let Some(CacheValue::Sessions(v)) = self.cached(&key) {
+            return Ok(v);
+        }
+
+        if let Some(ref sink) = self.sink {
+            let (from, to) = range.resolve();
+            let mut buckets = sink.sink().sessions(from, to, grouping).await?;
+            sort_buckets(&mut buckets, grouping);
+            self.store(key, CacheValue::Sessions(buckets.clone()));
+            return Ok(buckets);
+        }
+
+        let sessions = self.sessions_in_range(&range).await?;
+        let buckets = group_sessions(&sessions, grouping);
+
+        self.store(key, CacheValue::Sessions(buckets.clone()));
+        Ok(buckets)
+    }
+
+    pub async fn top_commands(
+        &self,
+        range: UsageRange,
+        limit: u64,
+    ) -> Result<Vec<CommandUsage>, WarpgateError> {
+        let key = CacheKey::TopCommands(range, limit);
+        if let Some(CacheValue::TopCommands(v)) = self.cached(&key) {
+            return Ok(v);
+        }
+
+        let (from, to) = range.resolve();
+
+        if let Some(ref sink) = self.sink {
+            let commands = sink.sink().top_commands(from, to, limit).await?;
+            self.store(key, CacheValue::TopCommands(commands.clone()));
+            return Ok(commands);
+        }
+
+        let entries = {
+            let db = self.db.lock().await;
+            LogEntry::Entity::find()
+                .filter(LogEntry::Column::Timestamp.gte(from))
+                .filter(LogEntry::Column::Timestamp.lt(to))
+                .filter(LogEntry::Column::Text.is_in(COMMAND_LOG_MESSAGES.iter().copied()))
+                .all(&*db)
+                .await?
+        };
+
+        let commands = count_commands(&entries, limit);
+
+        self.store(key, CacheValue::TopCommands(commands.clone()));
+        Ok(commands)
+    }
+}
+
+fn summarize(sessions: &[Session::Model], from: DateTime<Utc>, to: DateTime<Utc>) -> UsageSummary {
+    let mut users = sessions
+        .iter()
+        .filter_map(|s| s.username.as_deref())
+        .collect::<Vec<_>>();
+    users.sort_unstable();
+    users.dedup();
+
+    let mut targets = sessions.iter().filter_map(target_name).collect::<Vec<_>>();
+    targets.sort_unstable();
+    targets.dedup();
+
+    UsageSummary {
+        from,
+        to,
+        total_sessions: sessions.len() as u64,
+        active_sessions: sessions.iter().filter(|s| s.ended.is_none()).count() as u64,
+        unique_users: users.len() as u64,
+        unique_targets: targets.len() as u64,
+        median_duration_seconds: median(sessions.iter().filter_map(duration_seconds).collect()),
+    }
+}
+
+fn group_sessions(sessions: &[Session::Model], grouping: UsageGrouping) -> Vec<UsageBucket> {
+    let mut groups: HashMap<String, Vec<Option<f64>>> = HashMap::new();
+    for session in sessions.iter() {
+        let key = match grouping {
+            UsageGrouping::Target => target_name(session),
+            UsageGrouping::User => session.username.clone(),
+            UsageGrouping::Day => Some(day_of(session).to_string()),
+        };
+        let Some(key) = key else {
+            continue;
+        };
+        groups
+            .entry(key)
+            .or_default()
+            .push(duration_seconds(session));
+    }
+
+    let mut buckets = groups
+        .into_iter()
+        .map(|(key, durations)| {
+            let sessions = durations.len() as u64;
+            let durations = durations.into_iter().flatten().collect::<Vec<_>>();
+            UsageBucket {
+                key,
+                sessions,
+                total_duration_seconds: durations.iter().sum(),
+                median_duration_seconds: median(durations),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    sort_buckets(&mut buckets, grouping);
+    buckets
+}
+
+fn count_commands(entries: &[LogEntry::Model], limit: u64) -> Vec<CommandUsage> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for entry in entries {
+        let Some(command) = COMMAND_LOG_KEYS
+            .iter()
+            .find_map(|k| entry.values.get(k).and_then(|v| v.as_str()))
+        else {
+            continue;
+        };
+        let command = command.split_whitespace().collect::<Vec<_>>().join(" ");
+        if command.is_empty() {
+            continue;
+        }
+        *counts.entry(command).or_default() += 1;
+    }
+
+    let mut commands = counts
+        .into_iter()
+        .map(|(command, count)| CommandUsage { command, count })
+        .collect::<Vec<_>>();
+    commands.sort_by(|a, b| b.count.cmp(&a.count).then(a.command.cmp(&b.command)));
+    commands.truncate(limit as usize);
+    commands
+}
+
+fn sort_buckets(buckets: &mut [UsageBucket], grouping: UsageGrouping) {
+    match grouping {
+        UsageGrouping::Day => buckets.sort_by(|a, b| a.key.cmp(&b.key)),
+        _ => buckets.sort_by(|a, b| b.sessions.cmp(&a.sessions).then(a.key.cmp(&b.key))),
+    }
+}
+
+pub(crate) fn target_name(session: &Session::Model) -> Option<String> {
+    session
+        .target_snapshot
+        .as_deref()
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+        .and_then(|v| v.get("name")?.as_str().map(str::to_owned))
+}
+
+fn day_of(session: &Session::Model) -> NaiveDate {
+    session.started.date_naive()
+}
+
+fn duration_seconds(session: &Session::Model) -> Option<f64> {
+    session
+        .ended
+        .map(|ended| (ended - session.started).num_milliseconds() as f64 / 1000.0)
+}
+
+fn median(mut values: Vec<f64>) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        Some((values.get(mid - 1)? + values.get(mid)?) / 2.0)
+    } else {
+        values.get(mid).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn session(
+        username: Option<&str>,
+        target: Option<&str>,
+        started: DateTime<Utc>,
+        duration_seconds: Option<i64>,
+    ) -> Session::Model {
+        Session::Model {
+            id: Uuid::new_v4(),
+            target_snapshot: target.map(|name| json!({ "name": name }).to_string()),
+            username: username.map(str::to_owned),
+            remote_address: "127.0.0.1:1234".into(),
+            started,
+            ended: duration_seconds.map(|d| started + chrono::Duration::seconds(d)),
+            ticket_id: None,
+            protocol: "SSH".into(),
+            consent_acknowledged: None,
+            work_item: None,
+            user_agent: None,
+            termination_reason: None,
+            exit_code: None,
+        }
+    }
+
+    fn log_entry(text: &str, values: serde_json::Value) -> LogEntry::Model {
+        LogEntry::Model {
+            id: Uuid::new_v4(),
+            text: text.into(),
+            values,
+            timestamp: Utc::now(),
+            session_id: Uuid::new_v4(),
+            username: None,
+        }
+    }
+
+    fn day(d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(vec![]), None);
+        assert_eq!(median(vec![3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(vec![4.0, 1.0, 3.0, 2.0]), Some(2.5));
+    }
+
+    #[test]
+    fn test_summarize() {
+        let sessions = vec![
+            session(Some("alice"), Some("db"), day(1, 10), Some(10)),
+            session(Some("alice"), Some("web"), day(1, 11), Some(30)),
+            session(Some("bob"), Some("db"), day(2, 10), Some(20)),
+            session(None, None, day(2, 11), None),
+        ];
+        let summary = summarize(&sessions, day(1, 0), day(3, 0));
+
+        assert_eq!(summary.total_sessions, 4);
+        assert_eq!(summary.active_sessions, 1);
+        assert_eq!(summary.unique_users, 2);
+        assert_eq!(summary.unique_targets, 2);
+        assert_eq!(summary.median_duration_seconds, Some(20.0));
+    }
+
+    #[test]
+    fn test_summarize_empty() {
+        let summary = summarize(&[], day(1, 0), day(3, 0));
+        assert_eq!(summary.total_sessions, 0);
+        assert_eq!(summary.unique_users, 0);
+        assert_eq!(summary.median_duration_seconds, None);
+    }
+
+    #[test]
+    fn test_group_by_target() {
+        let sessions = vec![
+            session(Some("alice"), Some("web"), day(1, 10), Some(10)),
+            session(Some("alice"), Some("db"), day(1, 11), Some(30)),
+            session(Some("bob"), Some("db"), day(2, 10), Some(20)),
+            session(Some("bob"), Some("db"), day(2, 11), None),
+            session(Some("carol"), None, day(2, 12), Some(5)),
+        ];
+        let buckets = group_sessions(&sessions, UsageGrouping::Target);
+
+        // Busiest first; sessions without a target are left out
+        assert_eq!(
+            buckets.iter().map(|b| b.key.as_str()).collect::<Vec<_>>(),
+            vec!["db", "web"]
+        );
+        assert_eq!(buckets[0].sessions, 3);
+        // Active sessions count towards the total but not the durations
+        assert_eq!(buckets[0].total_duration_seconds, 50.0);
+        assert_eq!(buckets[0].median_duration_seconds, Some(25.0));
+        assert_eq!(buckets[1].sessions, 1);
+        assert_eq!(buckets[1].total_duration_seconds, 10.0);
+    }
+
+    #[test]
+    fn test_group_by_user_breaks_ties_by_name() {
+        let sessions = vec![
+            session(Some("bob"), Some("db"), day(1, 10), Some(10)),
+            session(Some("alice"), Some("db"), day(1, 11), Some(10)),
+            session(None, Some("db"), day(1, 12), Some(10)),
+        ];
+        let buckets = group_sessions(&sessions, UsageGrouping::User);
+        assert_eq!(
+            buckets.iter().map(|b| b.key.as_str()).collect::<Vec<_>>(),
+            vec!["alice", "bob"]
+        );
+    }
+
+    #[test]
+    fn test_group_by_day_is_chronological() {
+        let sessions = vec![
+            session(Some("alice"), Some("db"), day(3, 10), Some(10)),
+            session(Some("alice"), Some("db"), day(1, 10), Some(10)),
+            session(Some("bob"), Some("db"), day(3, 11), Some(10)),
+            session(Some("bob"), Some("db"), day(2, 10), Some(10)),
+        ];
+        let buckets = group_sessions(&sessions, UsageGrouping::Day);
+        assert_eq!(
+            buckets
+                .iter()
+                .map(|b| (b.key.as_str(), b.sessions))
+                .collect::<Vec<_>>(),
+            vec![("2024-05-01", 1), ("2024-05-02", 1), ("2024-05-03", 2)]
+        );
+    }
+
+    #[test]
+    fn test_count_commands() {
+        let entries = vec![
+            log_entry("SQL", json!({ "query": "SELECT 1" })),
+            log_entry("Query", json!({ "query": "SELECT   1\n" })),
+            log_entry("Requested exec", json!({ "command": "uptime" })),
+            log_entry("Requested exec", json!({ "command": "ls -la" })),
+            log_entry("Requested exec", json!({ "command": "ls  -la" })),
+            log_entry("Requested exec", json!({ "command": "whoami" })),
+            log_entry("Requested exec", json!({ "command": "   " })),
+            log_entry("SQL", json!({})),
+        ];
+
+        let commands = count_commands(&entries, 10);
+        assert_eq!(
+            commands
+                .iter()
+                .map(|c| (c.command.as_str(), c.count))
+                .collect::<Vec<_>>(),
+            vec![("SELECT 1", 2), ("ls -la", 2), ("uptime", 1), ("whoami", 1)]
+        );
+
+        let commands = count_commands(&entries, 1);
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].command, "SELECT 1");
+    }
+}
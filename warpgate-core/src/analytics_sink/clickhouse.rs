@@ -0,0 +1,300 @@
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use super::{AnalyticsSink, QueryLogRecord, SessionMetricsRecord, SinkSessionSummary};
+use crate::{http_client_builder, CommandUsage, UsageBucket, UsageGrouping};
+
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// Talks to ClickHouse over its HTTP interface
+pub struct ClickHouseSink {
+    client: reqwest::Client,
+    url: Url,
+    database: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct QueryResponse<T> {
+    data: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct SummaryRow {
+    sessions: u64,
+    unique_users: u64,
+    unique_targets: u64,
+    median_duration_seconds: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct BucketRow {
+    key: String,
+    sessions: u64,
+    total_duration_seconds: f64,
+    median_duration_seconds: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct CommandRow {
+    command: String,
+    count: u64,
+}
+
+fn format_datetime(time: &DateTime<Utc>) -> String {
+    time.format(DATETIME_FORMAT).to_string()
+}
+
+fn grouping_key(grouping: UsageGrouping) -> &'static str {
+    match grouping {
+        UsageGrouping::Target => "target",
+        UsageGrouping::User => "username",
+        UsageGrouping::Day => "toString(toDate(started, 'UTC'))",
+    }
+}
+
+impl ClickHouseSink {
+    pub fn new(
+        url: &str,
+        database: &str,
+        username: Option<String>,
+        password: Option<String>,
+    ) -> Result<Self> {
+        // The database name can't be passed as a parameter in DDL
+        if database.is_empty()
+            || !database
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!("Invalid ClickHouse database name: {database}");
+        }
+        Ok(Self {
+            client: http_client_builder().build()?,
+            url: Url::parse(url).context("Invalid ClickHouse URL")?,
+            database: database.to_owned(),
+            username,
+            password,
+        })
+    }
+
+    fn request(&self, params: &[(&str, String)]) -> reqwest::RequestBuilder {
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("output_format_json_quote_64bit_integers", "0")
+            // Accepts the RFC 3339 timestamps produced by serde
+            .append_pair("date_time_input_format", "best_effort")
+            .extend_pairs(params.iter().map(|(k, v)| (format!("param_{k}"), v)));
+        let mut request = self.client.post(url);
+        if let Some(ref username) = self.username {
+            request = request.header("X-ClickHouse-User", username);
+        }
+        if let Some(ref password) = self.password {
+            request = request.header("X-ClickHouse-Key", password);
+        }
+        request
+    }
+
+    async fn execute(&self, query: String, params: &[(&str, String)]) -> Result<String> {
+        let response = self.request(params).body(query).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            bail!("ClickHouse returned {status}: {}", body.trim());
+        }
+        Ok(body)
+    }
+
+    async fn select<T: DeserializeOwned>(
+        &self,
+        query: String,
+        params: &[(&str, String)],
+    ) -> Result<Vec<T>> {
+        let body = self.execute(format!("{query} FORMAT JSON"), params).await?;
+        Ok(serde_json::from_str::<QueryResponse<T>>(&body)?.data)
+    }
+
+    async fn insert<T: Serialize>(&self, table: &str, records: &[T]) -> Result<()> {
+        let mut query = format!("INSERT INTO {}.{table} FORMAT JSONEachRow\n", self.database);
+        for record in records {
+            query.push_str(&serde_json::to_string(&record)?);
+            query.push('\n');
+        }
+        self.execute(query, &[]).await?;
+        Ok(())
+    }
+
+    fn range_params(from: DateTime<Utc>, to: DateTime<Utc>) -> [(&'static str, String); 2] {
+        [
+            ("from", format_datetime(&from)),
+            ("to", format_datetime(&to)),
+        ]
+    }
+}
+
+#[async_trait]
+impl AnalyticsSink for ClickHouseSink {
+    async fn init(&self) -> Result<()> {
+        let db = &self.database;
+        self.execute(format!("CREATE DATABASE IF NOT EXISTS {db}"), &[])
+            .await?;
+        self.execute(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {db}.query_logs (
+                    time DateTime64(3, 'UTC'),
+                    session_id UUID,
+                    username Nullable(String),
+                    kind LowCardinality(String),
+                    query String
+                ) ENGINE = MergeTree ORDER BY time"
+            ),
+            &[],
+        )
+        .await?;
+        self.execute(
+            format!(
+                "CREATE TABLE IF NOT EXISTS {db}.session_metrics (
+                    id UUID,
+                    username Nullable(String),
+                    target Nullable(String),
+                    protocol LowCardinality(String),
+                    started DateTime64(3, 'UTC'),
+                    ended DateTime64(3, 'UTC'),
+                    duration_seconds Float64,
+                    termination_reason LowCardinality(Nullable(String))
+                ) ENGINE = MergeTree ORDER BY started"
+            ),
+            &[],
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn write_query_logs(&self, records: &[QueryLogRecord]) -> Result<()> {
+        self.insert("query_logs", records).await
+    }
+
+    async fn write_session_metrics(&self, records: &[SessionMetricsRecord]) -> Result<()> {
+        self.insert("session_metrics", records).await
+    }
+
+    async fn summary(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<SinkSessionSummary> {
+        let rows: Vec<SummaryRow> = self
+            .select(
+                format!(
+                    "SELECT
+                        count() AS sessions,
+                        uniqExact(username) AS unique_users,
+                        uniqExact(target) AS unique_targets,
+                        quantileExact(0.5)(duration_seconds) AS median_duration_seconds
+                    FROM {}.session_metrics
+                    WHERE started >= {{from:DateTime64(3)}} AND started < {{to:DateTime64(3)}}",
+                    self.database
+                ),
+                &Self::range_params(from, to),
+            )
+            .await?;
+        let Some(row) = rows.into_iter().next() else {
+            return Ok(SinkSessionSummary::default());
+        };
+        Ok(SinkSessionSummary {
+            sessions: row.sessions,
+            unique_users: row.unique_users,
+            unique_targets: row.unique_targets,
+            median_duration_seconds: row.median_duration_seconds.filter(|_| row.sessions > 0),
+        })
+    }
+
+    async fn sessions(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        grouping: UsageGrouping,
+    ) -> Result<Vec<UsageBucket>> {
+        let rows: Vec<BucketRow> = self
+            .select(
+                format!(
+                    "SELECT
+                        assumeNotNull({key}) AS key,
+                        count() AS sessions,
+                        sum(duration_seconds) AS total_duration_seconds,
+                        quantileExact(0.5)(duration_seconds) AS median_duration_seconds
+                    FROM {db}.session_metrics
+                    WHERE started >= {{from:DateTime64(3)}} AND started < {{to:DateTime64(3)}}
+                        AND {key} IS NOT NULL
+                    GROUP BY key",
+                    key = grouping_key(grouping),
+                    db = self.database,
+                ),
+                &Self::range_params(from, to),
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| UsageBucket {
+                key: row.key,
+                sessions: row.sessions,
+                total_duration_seconds: row.total_duration_seconds,
+                median_duration_seconds: row.median_duration_seconds,
+            })
+            .collect())
+    }
+
+    async fn top_commands(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: u64,
+    ) -> Result<Vec<CommandUsage>> {
+        let [from, to] = Self::range_params(from, to);
+        let rows: Vec<CommandRow> = self
+            .select(
+                format!(
+                    "SELECT
+                        trimBoth(replaceRegexpAll(query, '\\\\s+', ' ')) AS command,
+                        count() AS count
+                    FROM {}.query_logs
+                    WHERE time >= {{from:DateTime64(3)}} AND time < {{to:DateTime64(3)}}
+                        AND command != ''
+                    GROUP BY command
+                    ORDER BY count DESC, command ASC
+                    LIMIT {{limit:UInt64}}",
+                    self.database
+                ),
+                &[from, to, ("limit", limit.to_string())],
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| CommandUsage {
+                command: row.command,
+                count: row.count,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_format_datetime() {
+        let time = Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 15).unwrap()
+            + chrono::Duration::milliseconds(250);
+        assert_eq!(format_datetime(&time), "2024-05-01 12:30:15.250");
+    }
+
+    #[test]
+    fn test_database_name_validation() {
+        assert!(ClickHouseSink::new("http://localhost:8123", "warpgate", None, None).is_ok());
+        assert!(ClickHouseSink::new("http://localhost:8123", "a; DROP", None, None).is_err());
+        assert!(ClickHouseSink::new("http://localhost:8123", "", None, None).is_err());
+    }
+}
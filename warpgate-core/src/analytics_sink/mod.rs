@@ -0,0 +1,200 @@
+mod clickhouse;
+mod timescale;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sea_orm::ActiveEnum;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::AnalyticsSinkConfig;
+use warpgate_db_entities::Session;
+
+use self::clickhouse::ClickHouseSink;
+use self::timescale::TimescaleSink;
+use crate::{CommandUsage, UsageBucket, UsageGrouping};
+
+const QUEUE_SIZE: usize = 10000;
+const BATCH_SIZE: usize = 500;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A logged query or command
+#[derive(Debug, Clone, Serialize)]
+pub struct QueryLogRecord {
+    pub time: DateTime<Utc>,
+    pub session_id: Uuid,
+    pub username: Option<String>,
+    /// The log message the query was recorded under
+    pub kind: String,
+    pub query: String,
+}
+
+/// Written once a session has ended
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionMetricsRecord {
+    pub id: Uuid,
+    pub username: Option<String>,
+    pub target: Option<String>,
+    pub protocol: String,
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    pub duration_seconds: f64,
+    pub termination_reason: Option<String>,
+}
+
+impl SessionMetricsRecord {
+    pub fn from_session(session: &Session::Model) -> Option<Self> {
+        let ended = session.ended?;
+        Some(Self {
+            id: session.id,
+            username: session.username.clone(),
+            target: crate::analytics::target_name(session),
+            protocol: session.protocol.clone(),
+            started: session.started,
+            ended,
+            duration_seconds: (ended - session.started).num_milliseconds() as f64 / 1000.0,
+            termination_reason: session.termination_reason.map(|r| r.to_value()),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AnalyticsRecord {
+    QueryLog(QueryLogRecord),
+    SessionMetrics(SessionMetricsRecord),
+}
+
+/// Aggregates over ended sessions
+#[derive(Debug, Clone, Default)]
+pub struct SinkSessionSummary {
+    pub sessions: u64,
+    pub unique_users: u64,
+    pub unique_targets: u64,
+    pub median_duration_seconds: Option<f64>,
+}
+
+#[async_trait]
+pub trait AnalyticsSink: Send + Sync {
+    /// Creates the tables if they don't exist yet
+    async fn init(&self) -> Result<()>;
+
+    async fn write_query_logs(&self, records: &[QueryLogRecord]) -> Result<()>;
+
+    async fn write_session_metrics(&self, records: &[SessionMetricsRecord]) -> Result<()>;
+
+    async fn summary(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<SinkSessionSummary>;
+
+    /// Buckets are returned unsorted
+    async fn sessions(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        grouping: UsageGrouping,
+    ) -> Result<Vec<UsageBucket>>;
+
+    /// Queries are compared with collapsed whitespace
+    async fn top_commands(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: u64,
+    ) -> Result<Vec<CommandUsage>>;
+}
+
+/// An external analytics database receiving query logs and session
+/// metrics instead of the main database.
+/// Records are queued and written in batches in the background.
+#[derive(Clone)]
+pub struct AnalyticsSinkHandle {
+    sink: Arc<dyn AnalyticsSink>,
+    sender: mpsc::Sender<AnalyticsRecord>,
+}
+
+impl AnalyticsSinkHandle {
+    pub async fn connect(config: &AnalyticsSinkConfig) -> Result<Self> {
+        let sink: Arc<dyn AnalyticsSink> = match config {
+            AnalyticsSinkConfig::ClickHouse {
+                url,
+                database,
+                username,
+                password,
+            } => Arc::new(ClickHouseSink::new(
+                url,
+                database,
+                username.clone(),
+                password.as_ref().map(|p| p.expose_secret().clone()),
+            )?),
+            AnalyticsSinkConfig::Timescale { url } => {
+                Arc::new(TimescaleSink::connect(url.expose_secret()).await?)
+            }
+        };
+        sink.init().await?;
+
+        let (sender, receiver) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(run_writer(sink.clone(), receiver));
+
+        Ok(Self { sink, sender })
+    }
+
+    /// Drops the record if the sink can't keep up
+    pub fn record(&self, record: AnalyticsRecord) {
+        if self.sender.try_send(record).is_err() {
+            warn!("Analytics sink queue is full, dropping a record");
+        }
+    }
+
+    pub fn sink(&self) -> &dyn AnalyticsSink {
+        &*self.sink
+    }
+}
+
+async fn run_writer(sink: Arc<dyn AnalyticsSink>, mut receiver: mpsc::Receiver<AnalyticsRecord>) {
+    let mut query_logs = vec![];
+    let mut session_metrics = vec![];
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+    loop {
+        let (closed, due) = tokio::select! {
+            record = receiver.recv() => match record {
+                Some(AnalyticsRecord::QueryLog(r)) => { query_logs.push(r); (false, false) }
+                Some(AnalyticsRecord::SessionMetrics(r)) => { session_metrics.push(r); (false, false) }
+                None => (true, true),
+            },
+            _ = interval.tick() => (false, true),
+        };
+
+        let full = query_logs.len() >= BATCH_SIZE || session_metrics.len() >= BATCH_SIZE;
+        if !(full || due) {
+            continue;
+        }
+
+        if !query_logs.is_empty() {
+            if let Err(error) = sink.write_query_logs(&query_logs).await {
+                error!(
+                    ?error,
+                    count = query_logs.len(),
+                    "Failed to write query logs"
+                );
+            }
+            query_logs.clear();
+        }
+        if !session_metrics.is_empty() {
+            if let Err(error) = sink.write_session_metrics(&session_metrics).await {
+                error!(
+                    ?error,
+                    count = session_metrics.len(),
+                    "Failed to write session metrics"
+                );
+            }
+            session_metrics.clear();
+        }
+
+        if closed {
+            break;
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sea_orm::{ConnectionTrait, Database, DatabaseConnection, DbBackend, Statement, Value};
+
+use super::{AnalyticsSink, QueryLogRecord, SessionMetricsRecord, SinkSessionSummary};
+use crate::{CommandUsage, UsageBucket, UsageGrouping};
+
+/// Writes into TimescaleDB hypertables through a regular PostgreSQL connection
+pub struct TimescaleSink {
+    db: DatabaseConnection,
+}
+
+/// Builds `($1, $2), ($3, $4)` for a multi-row insert
+fn placeholders(rows: usize, columns: usize) -> String {
+    (0..rows)
+        .map(|row| {
+            let values = (1..=columns)
+                .map(|column| format!("${}", row * columns + column))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("({values})")
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn grouping_key(grouping: UsageGrouping) -> &'static str {
+    match grouping {
+        UsageGrouping::Target => "target",
+        UsageGrouping::User => "username",
+        UsageGrouping::Day => "to_char(started AT TIME ZONE 'UTC', 'YYYY-MM-DD')",
+    }
+}
+
+impl TimescaleSink {
+    pub async fn connect(url: &str) -> Result<Self> {
+        Ok(Self {
+            db: Database::connect(url).await?,
+        })
+    }
+
+    async fn execute(&self, sql: &str) -> Result<()> {
+        self.db
+            .execute(Statement::from_string(DbBackend::Postgres, sql))
+            .await?;
+        Ok(())
+    }
+
+    async fn insert(
+        &self,
+        table: &str,
+        columns: &[&str],
+        rows: usize,
+        values: Vec<Value>,
+    ) -> Result<()> {
+        if rows == 0 {
+            return Ok(());
+        }
+        let sql = format!(
+            "INSERT INTO {table} ({}) VALUES {}",
+            columns.join(", "),
+            placeholders(rows, columns.len())
+        );
+        self.db
+            .execute(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                sql,
+                values,
+            ))
+            .await?;
+        Ok(())
+    }
+
+    async fn query(&self, sql: &str, values: Vec<Value>) -> Result<Vec<sea_orm::QueryResult>> {
+        Ok(self
+            .db
+            .query_all(Statement::from_sql_and_values(
+                DbBackend::Postgres,
+                sql,
+                values,
+            ))
+            .await?)
+    }
+}
+
+#[async_trait]
+impl AnalyticsSink for TimescaleSink {
+    async fn init(&self) -> Result<()> {
+        self.execute(
+            "CREATE TABLE IF NOT EXISTS query_logs (
+                time TIMESTAMPTZ NOT NULL,
+                session_id UUID NOT NULL,
+                username TEXT,
+                kind TEXT NOT NULL,
+                query TEXT NOT NULL
+            )",
+        )
+        .await?;
+        self.execute("SELECT create_hypertable('query_logs', 'time', if_not_exists => TRUE)")
+            .await?;
+        self.execute(
+            "CREATE TABLE IF NOT EXISTS session_metrics (
+                id UUID NOT NULL,
+                username TEXT,
+                target TEXT,
+                protocol TEXT NOT NULL,
+                started TIMESTAMPTZ NOT NULL,
+                ended TIMESTAMPTZ NOT NULL,
+                duration_seconds DOUBLE PRECISION NOT NULL,
+                termination_reason TEXT
+            )",
+        )
+        .await?;
+        self.execute(
+            "SELECT create_hypertable('session_metrics', 'started', if_not_exists => TRUE)",
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn write_query_logs(&self, records: &[QueryLogRecord]) -> Result<()> {
+        let values = records
+            .iter()
+            .flat_map(|r| {
+                [
+                    r.time.into(),
+                    r.session_id.into(),
+                    r.username.clone().into(),
+                    r.kind.clone().into(),
+                    r.query.clone().into(),
+                ]
+            })
+            .collect();
+        self.insert(
+            "query_logs",
+            &["time", "session_id", "username", "kind", "query"],
+            records.len(),
+            values,
+        )
+        .await
+    }
+
+    async fn write_session_metrics(&self, records: &[SessionMetricsRecord]) -> Result<()> {
+        let values = records
+            .iter()
+            .flat_map(|r| {
+                [
+                    r.id.into(),
+                    r.username.clone().into(),
+                    r.target.clone().into(),
+                    r.protocol.clone().into(),
+                    r.started.into(),
+                    r.ended.into(),
+                    r.duration_seconds.into(),
+                    r.termination_reason.clone().into(),
+                ]
+            })
+            .collect();
+        self.insert(
+            "session_metrics",
+            &[
+                "id",
+                "username",
+                "target",
+                "protocol",
+                "started",
+                "ended",
+                "duration_seconds",
+                "termination_reason",
+            ],
+            records.len(),
+            values,
+        )
+        .await
+    }
+
+    async fn summary(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<SinkSessionSummary> {
+        let rows = self
+            .query(
+                "SELECT
+                    COUNT(*) AS sessions,
+                    COUNT(DISTINCT username) AS unique_users,
+                    COUNT(DISTINCT target) AS unique_targets,
+                    percentile_cont(0.5) WITHIN GROUP (ORDER BY duration_seconds)
+                        AS median_duration_seconds
+                FROM session_metrics
+                WHERE started >= $1 AND started < $2",
+                vec![from.into(), to.into()],
+            )
+            .await?;
+        let Some(row) = rows.into_iter().next() else {
+            return Ok(SinkSessionSummary::default());
+        };
+        Ok(SinkSessionSummary {
+            sessions: row.try_get::<i64>("", "sessions")? as u64,
+            unique_users: row.try_get::<i64>("", "unique_users")? as u64,
+            unique_targets: row.try_get::<i64>("", "unique_targets")? as u64,
+            median_duration_seconds: row.try_get("", "median_duration_seconds")?,
+        })
+    }
+
+    async fn sessions(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        grouping: UsageGrouping,
+    ) -> Result<Vec<UsageBucket>> {
+        let rows = self
+            .query(
+                &format!(
+                    "SELECT
+                        {key} AS key,
+                        COUNT(*) AS sessions,
+                        SUM(duration_seconds) AS total_duration_seconds,
+                        percentile_cont(0.5) WITHIN GROUP (ORDER BY duration_seconds)
+                            AS median_duration_seconds
+                    FROM session_metrics
+                    WHERE started >= $1 AND started < $2 AND {key} IS NOT NULL
+                    GROUP BY 1",
+                    key = grouping_key(grouping),
+                ),
+                vec![from.into(), to.into()],
+            )
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(UsageBucket {
+                    key: row.try_get("", "key")?,
+                    sessions: row.try_get::<i64>("", "sessions")? as u64,
+                    total_duration_seconds: row.try_get("", "total_duration_seconds")?,
+                    median_duration_seconds: row.try_get("", "median_duration_seconds")?,
+                })
+            })
+            .collect()
+    }
+
+    async fn top_commands(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        limit: u64,
+    ) -> Result<Vec<CommandUsage>> {
+        let rows = self
+            .query(
+                "SELECT command, COUNT(*) AS count FROM (
+                    SELECT btrim(regexp_replace(query, '\\s+', ' ', 'g')) AS command
+                    FROM query_logs
+                    WHERE time >= $1 AND time < $2
+                ) commands
+                WHERE command != ''
+                GROUP BY command
+                ORDER BY count DESC, command ASC
+                LIMIT $3",
+                vec![from.into(), to.into(), (limit as i64).into()],
+            )
+            .await?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(CommandUsage {
+                    command: row.try_get("", "command")?,
+                    count: row.try_get::<i64>("", "count")? as u64,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(placeholders(1, 1), "($1)");
+        assert_eq!(placeholders(2, 3), "($1, $2, $3), ($4, $5, $6)");
+    }
+}
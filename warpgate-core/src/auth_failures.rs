@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::auth::CredentialKind;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::AuthFailure;
+
+use crate::SharedConfig;
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Context of a rejected login attempt, as known to the protocol server
+#[derive(Debug, Clone, Default)]
+pub struct AuthFailureContext {
+    pub protocol: String,
+    pub remote_ip: Option<String>,
+    pub remote_port: Option<u16>,
+    pub username: Option<String>,
+    pub credential_kinds: Vec<CredentialKind>,
+    pub client_banner: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl AuthFailureContext {
+    pub fn new(protocol: &str, remote_address: SocketAddr) -> Self {
+        Self {
+            protocol: protocol.to_owned(),
+            remote_ip: Some(remote_address.ip().to_string()),
+            remote_port: Some(remote_address.port()),
+            ..Default::default()
+        }
+    }
+}
+
+/// Fixed one-minute windows per source IP
+#[derive(Default)]
+struct SourceRateLimiter {
+    windows: HashMap<String, (Instant, u32)>,
+}
+
+impl SourceRateLimiter {
+    fn allow(&mut self, source: &str, limit: u32, now: Instant) -> bool {
+        if self.windows.len() > 10_000 {
+            self.windows
+                .retain(|_, (started, _)| now.duration_since(*started) < RATE_LIMIT_WINDOW);
+        }
+        let (started, count) = self.windows.entry(source.to_owned()).or_insert((now, 0));
+        if now.duration_since(*started) >= RATE_LIMIT_WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= limit
+    }
+}
+
+/// Stores failed logins for later investigation
+pub struct AuthFailureLog {
+    db: Arc<tokio::sync::Mutex<DatabaseConnection>>,
+    config: Arc<SharedConfig>,
+    rate_limiter: Mutex<SourceRateLimiter>,
+}
+
+impl AuthFailureLog {
+    pub fn new(db: Arc<tokio::sync::Mutex<DatabaseConnection>>, config: Arc<SharedConfig>) -> Self {
+        Self {
+            db,
+            config,
+            rate_limiter: Mutex::new(SourceRateLimiter::default()),
+        }
+    }
+
+    /// Never fails, since a broken store must not affect the login itself
+    pub async fn record(&self, context: AuthFailureContext) {
+        let limit = self
+            .config
+            .load()
+            .store
+            .log
+            .auth_failures
+            .max_per_source_per_minute;
+        let source = context.remote_ip.as_deref().unwrap_or_default();
+
+        #[allow(clippy::unwrap_used)]
+        if !self
+            .rate_limiter
+            .lock()
+            .unwrap()
+            .allow(source, limit, Instant::now())
+        {
+            debug!(%source, "Not recording the auth failure, rate limit reached");
+            return;
+        }
+
+        let model = AuthFailure::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            timestamp: Set(Utc::now()),
+            protocol: Set(context.protocol),
+            remote_ip: Set(context.remote_ip),
+            remote_port: Set(context.remote_port.map(Into::into)),
+            username: Set(context.username),
+            credential_kinds: Set(
+                serde_json::to_value(&context.credential_kinds).unwrap_or_default()
+            ),
+            client_banner: Set(context.client_banner),
+            user_agent: Set(context.user_agent),
+        };
+        if let Err(error) = model.insert(&*self.db.lock().await).await {
+            error!(%error, "Failed to record the auth failure");
+        }
+    }
+
+    pub async fn cleanup(&self) -> Result<(), WarpgateError> {
+        let retention = self.config.load().store.log.auth_failures.retention;
+        let cutoff =
+            Utc::now() - chrono::Duration::from_std(retention).map_err(WarpgateError::other)?;
+        AuthFailure::Entity::delete_many()
+            .filter(Expr::col(AuthFailure::Column::Timestamp).lt(cutoff))
+            .exec(&*self.db.lock().await)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_rate_limit() {
+        let mut limiter = SourceRateLimiter::default();
+        let now = Instant::now();
+
+        assert!(limiter.allow("10.0.0.1", 2, now));
+        assert!(limiter.allow("10.0.0.1", 2, now));
+        assert!(!limiter.allow("10.0.0.1", 2, now));
+        assert!(limiter.allow("10.0.0.2", 2, now));
+
+        let later = now + RATE_LIMIT_WINDOW;
+        assert!(limiter.allow("10.0.0.1", 2, later));
+    }
+}
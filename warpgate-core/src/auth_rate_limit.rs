@@ -0,0 +1,220 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tokio::sync::Mutex;
+use tracing::*;
+use warpgate_common::{AuthRateLimitRule, WarpgateError};
+use warpgate_db_entities::AuthRateLimit::{self, AuthRateLimitKind, FailureTimes};
+
+use crate::{AuthFailureContext, SharedConfig};
+
+/// Locks out usernames and source IPs after repeated failed logins
+/// (see `auth_rate_limit` in the config). Kept in the database so that
+/// lockouts survive restarts and apply across protocols.
+pub struct AuthRateLimiter {
+    db: Arc<Mutex<DatabaseConnection>>,
+    config: Arc<SharedConfig>,
+}
+
+impl AuthRateLimiter {
+    pub fn new(db: Arc<Mutex<DatabaseConnection>>, config: Arc<SharedConfig>) -> Self {
+        Self { db, config }
+    }
+
+    fn subjects<'a>(
+        username: Option<&'a str>,
+        remote_ip: Option<&'a str>,
+    ) -> impl Iterator<Item = (AuthRateLimitKind, &'a str)> {
+        username
+            .map(|x| (AuthRateLimitKind::Username, x))
+            .into_iter()
+            .chain(remote_ip.map(|x| (AuthRateLimitKind::Source, x)))
+    }
+
+    /// Returns when the lockout ends if either the username or the source
+    /// is locked out. Lookup errors don't lock anyone out.
+    pub async fn locked_until(
+        &self,
+        username: Option<&str>,
+        remote_ip: Option<&str>,
+    ) -> Option<DateTime<Utc>> {
+        if !self.config.load().store.auth_rate_limit.enable {
+            return None;
+        }
+        let now = Utc::now();
+        let db = self.db.lock().await;
+        let mut result = None;
+        for (kind, subject) in Self::subjects(username, remote_ip) {
+            match AuthRateLimit::Entity::find_by_id((kind, subject.to_owned()))
+                .one(&*db)
+                .await
+            {
+                Ok(Some(AuthRateLimit::Model {
+                    locked_until: Some(until),
+                    ..
+                })) if until > now => {
+                    result = result.max(Some(until));
+                }
+                Ok(_) => (),
+                Err(error) => error!(%error, "Failed to check the auth rate limit"),
+            }
+        }
+        result
+    }
+
+    /// Whether a login attempt may proceed. Rejections are logged here
+    /// and should look like an ordinary failed login to the client.
+    pub async fn allows(&self, username: Option<&str>, remote_ip: Option<&str>) -> bool {
+        match self.locked_until(username, remote_ip).await {
+            Some(until) => {
+                warn!(?username, ?remote_ip, %until, "Rejecting a login attempt during a lockout");
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Counts a failed login against its username and source
+    pub async fn record_failure(&self, context: &AuthFailureContext) {
+        let config = self.config.load().store.auth_rate_limit.clone();
+        if !config.enable {
+            return;
+        }
+        let now = Utc::now();
+        for (kind, subject) in
+            Self::subjects(context.username.as_deref(), context.remote_ip.as_deref())
+        {
+            let rule = match kind {
+                AuthRateLimitKind::Username => &config.per_user,
+                AuthRateLimitKind::Source => &config.per_source,
+            };
+            if let Err(error) = self.record(kind, subject, rule, now).await {
+                error!(%error, "Failed to update the auth rate limit");
+            }
+        }
+    }
+
+    async fn record(
+        &self,
+        kind: AuthRateLimitKind,
+        subject: &str,
+        rule: &AuthRateLimitRule,
+        now: DateTime<Utc>,
+    ) -> Result<(), WarpgateError> {
+        let db = self.db.lock().await;
+        let existing = AuthRateLimit::Entity::find_by_id((kind, subject.to_owned()))
+            .one(&*db)
+            .await?;
+        let mut failures = existing
+            .as_ref()
+            .map(|x| x.failures.0.clone())
+            .unwrap_or_default();
+        let locked_until = add_failure(&mut failures, rule, now)?;
+        if locked_until.is_some() {
+            warn!(?kind, %subject, until=?locked_until, "Too many failed logins, locking out");
+        }
+
+        let model = AuthRateLimit::ActiveModel {
+            kind: Set(kind),
+            subject: Set(subject.to_owned()),
+            failures: Set(FailureTimes(failures)),
+            locked_until: Set(locked_until.or(existing.as_ref().and_then(|x| x.locked_until))),
+        };
+        match existing {
+            Some(_) => model.update(&*db).await?,
+            None => model.insert(&*db).await?,
+        };
+        Ok(())
+    }
+
+    /// Lifts a lockout and forgets the failures behind it
+    pub async fn reset(
+        &self,
+        kind: AuthRateLimitKind,
+        subject: &str,
+    ) -> Result<bool, WarpgateError> {
+        let result = AuthRateLimit::Entity::delete_by_id((kind, subject.to_owned()))
+            .exec(&*self.db.lock().await)
+            .await?;
+        Ok(result.rows_affected > 0)
+    }
+
+    /// Drops entries that no longer have any effect
+    pub async fn cleanup(&self) -> Result<(), WarpgateError> {
+        let config = self.config.load().store.auth_rate_limit.clone();
+        let window = config.per_user.window.max(config.per_source.window);
+        let now = Utc::now();
+        let cutoff = now - chrono::Duration::from_std(window).map_err(WarpgateError::other)?;
+        let db = self.db.lock().await;
+        for entry in AuthRateLimit::Entity::find()
+            .filter(
+                AuthRateLimit::Column::LockedUntil
+                    .is_null()
+                    .or(AuthRateLimit::Column::LockedUntil.lt(now)),
+            )
+            .all(&*db)
+            .await?
+        {
+            if entry.failures.0.iter().all(|x| *x < cutoff) {
+                AuthRateLimit::Entity::delete_by_id((entry.kind, entry.subject))
+                    .exec(&*db)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Slides the window forward and adds a failure at `now`. Returns the end
+/// of the lockout if this failure triggered one, which also starts a
+/// fresh window.
+fn add_failure(
+    failures: &mut Vec<DateTime<Utc>>,
+    rule: &AuthRateLimitRule,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, WarpgateError> {
+    if rule.max_failures == 0 {
+        return Ok(None);
+    }
+    let window = chrono::Duration::from_std(rule.window).map_err(WarpgateError::other)?;
+    failures.retain(|x| now - *x < window);
+    failures.push(now);
+    if failures.len() < rule.max_failures as usize {
+        return Ok(None);
+    }
+    failures.clear();
+    let lockout = chrono::Duration::from_std(rule.lockout).map_err(WarpgateError::other)?;
+    Ok(Some(now + lockout))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_add_failure() {
+        let rule = AuthRateLimitRule {
+            max_failures: 3,
+            window: Duration::from_secs(60),
+            lockout: Duration::from_secs(600),
+        };
+        let start = Utc::now();
+        let at = |secs| start + chrono::Duration::seconds(secs);
+        let mut failures = vec![];
+
+        assert_eq!(add_failure(&mut failures, &rule, at(0)).unwrap(), None);
+        assert_eq!(add_failure(&mut failures, &rule, at(30)).unwrap(), None);
+        // The first one has slid out of the window
+        assert_eq!(add_failure(&mut failures, &rule, at(70)).unwrap(), None);
+        assert_eq!(failures.len(), 2);
+
+        assert_eq!(
+            add_failure(&mut failures, &rule, at(80)).unwrap(),
+            Some(at(680))
+        );
+        assert!(failures.is_empty());
+    }
+}
@@ -3,16 +3,67 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use once_cell::sync::Lazy;
+use rand::seq::SliceRandom;
 use tokio::sync::{broadcast, Mutex};
 use uuid::Uuid;
 use warpgate_common::auth::{AuthResult, AuthState, CredentialKind};
-use warpgate_common::{SessionId, WarpgateError};
+use warpgate_common::helpers::hash::generate_ticket_secret;
+use warpgate_common::{Secret, SessionId, WarpgateError};
 
 use crate::{ConfigProvider, ConfigProviderEnum};
 
 #[allow(clippy::unwrap_used)]
 pub static TIMEOUT: Lazy<Duration> = Lazy::new(|| Duration::from_secs(60 * 10));
 
+/// Minimum delay between two polls of the same device code
+pub const DEVICE_CODE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Unambiguous characters for user codes (no vowels or look-alikes), per RFC 8628
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+/// Device authorizations can be started without logging in,
+/// so the number of pending ones is capped
+const MAX_DEVICE_AUTHORIZATIONS: usize = 1000;
+const MAX_DEVICE_AUTHORIZATIONS_PER_CLIENT: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceAuthorizationStatus {
+    Pending,
+    Approved { username: String },
+    Rejected,
+}
+
+/// A pending OAuth2 device authorization (RFC 8628) for a single target
+#[derive(Clone, Debug)]
+pub struct DeviceAuthorization {
+    pub user_code: String,
+    pub target: String,
+    pub status: DeviceAuthorizationStatus,
+    pub started: chrono::DateTime<chrono::Utc>,
+    /// The first logged in user to open the code,
+    /// the only one who can approve or reject it
+    claimed_by: Option<String>,
+    client_ip: Option<String>,
+    created_at: Instant,
+    last_poll: Option<Instant>,
+}
+
+impl DeviceAuthorization {
+    pub fn is_expired(&self) -> bool {
+        self.created_at.elapsed() > *TIMEOUT
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DevicePollResult {
+    Pending,
+    SlowDown,
+    Approved { username: String, target: String },
+    Denied,
+    Expired,
+}
+
 struct AuthCompletionSignal {
     sender: broadcast::Sender<AuthResult>,
     created_at: Instant,
@@ -28,6 +79,9 @@ pub struct AuthStateStore {
     config_provider: Arc<Mutex<ConfigProviderEnum>>,
     store: HashMap<Uuid, (Arc<Mutex<AuthState>>, Instant)>,
     completion_signals: HashMap<Uuid, AuthCompletionSignal>,
+    device_authorizations: HashMap<String, DeviceAuthorization>,
+    /// Normalized user code -> device code
+    device_authorization_codes: HashMap<String, String>,
 }
 
 impl AuthStateStore {
@@ -36,6 +90,8 @@ impl AuthStateStore {
             store: HashMap::new(),
             config_provider,
             completion_signals: HashMap::new(),
+            device_authorizations: HashMap::new(),
+            device_authorization_codes: HashMap::new(),
         }
     }
 
@@ -100,11 +156,191 @@ impl AuthStateStore {
         }
     }
 
+    /// Starts a device authorization for `target` and returns
+    /// the device code along with the user code.
+    /// Returns `None` if too many authorizations are already pending,
+    /// either in total or for `client_ip`.
+    pub fn create_device_authorization(
+        &mut self,
+        target: &str,
+        client_ip: Option<&str>,
+    ) -> Option<(Secret<String>, String)> {
+        self.vacuum_device_authorizations();
+        if self.device_authorizations.len() >= MAX_DEVICE_AUTHORIZATIONS {
+            return None;
+        }
+        if let Some(client_ip) = client_ip {
+            let from_client = self
+                .device_authorizations
+                .values()
+                .filter(|x| x.client_ip.as_deref() == Some(client_ip))
+                .count();
+            if from_client >= MAX_DEVICE_AUTHORIZATIONS_PER_CLIENT {
+                return None;
+            }
+        }
+
+        let device_code = generate_ticket_secret();
+        let user_code = loop {
+            let code = generate_user_code();
+            if !self
+                .device_authorization_codes
+                .contains_key(&normalize_user_code(&code))
+            {
+                break code;
+            }
+        };
+        self.device_authorization_codes.insert(
+            normalize_user_code(&user_code),
+            device_code.expose_secret().clone(),
+        );
+        self.device_authorizations.insert(
+            device_code.expose_secret().clone(),
+            DeviceAuthorization {
+                user_code: user_code.clone(),
+                target: target.to_owned(),
+                status: DeviceAuthorizationStatus::Pending,
+                started: chrono::Utc::now(),
+                claimed_by: None,
+                client_ip: client_ip.map(ToOwned::to_owned),
+                created_at: Instant::now(),
+                last_poll: None,
+            },
+        );
+        Some((device_code, user_code))
+    }
+
+    pub fn find_device_authorization(&self, user_code: &str) -> Option<&DeviceAuthorization> {
+        let device_code = self
+            .device_authorization_codes
+            .get(&normalize_user_code(user_code))?;
+        self.device_authorizations
+            .get(device_code)
+            .filter(|x| !x.is_expired())
+    }
+
+    fn find_device_authorization_mut(
+        &mut self,
+        user_code: &str,
+    ) -> Option<&mut DeviceAuthorization> {
+        let device_code = self
+            .device_authorization_codes
+            .get(&normalize_user_code(user_code))?;
+        self.device_authorizations
+            .get_mut(device_code)
+            .filter(|x| !x.is_expired())
+    }
+
+    fn remove_device_authorization(&mut self, device_code: &str) {
+        if let Some(authorization) = self.device_authorizations.remove(device_code) {
+            self.device_authorization_codes
+                .remove(&normalize_user_code(&authorization.user_code));
+        }
+    }
+
+    fn vacuum_device_authorizations(&mut self) {
+        self.device_authorizations
+            .retain(|_, authorization| !authorization.is_expired());
+        let device_authorizations = &self.device_authorizations;
+        self.device_authorization_codes
+            .retain(|_, device_code| device_authorizations.contains_key(device_code));
+    }
+
+    /// Binds the authorization to `username` if nobody has opened it yet.
+    /// Returns `None` if there's no such authorization or it was
+    /// opened by another user.
+    pub fn claim_device_authorization(
+        &mut self,
+        user_code: &str,
+        username: &str,
+    ) -> Option<&DeviceAuthorization> {
+        let authorization = self.find_device_authorization_mut(user_code)?;
+        let claimed_by = authorization
+            .claimed_by
+            .get_or_insert_with(|| username.to_owned());
+        if claimed_by.as_str() != username {
+            return None;
+        }
+        Some(&*authorization)
+    }
+
+    /// Approves or rejects a pending device authorization on behalf of `username`.
+    /// Returns `false` if there's no such pending authorization or
+    /// it was claimed by another user.
+    pub fn resolve_device_authorization(
+        &mut self,
+        user_code: &str,
+        username: &str,
+        status: DeviceAuthorizationStatus,
+    ) -> bool {
+        let Some(authorization) = self.find_device_authorization_mut(user_code) else {
+            return false;
+        };
+        if authorization.status != DeviceAuthorizationStatus::Pending
+            || authorization.claimed_by.as_deref() != Some(username)
+        {
+            return false;
+        }
+        authorization.status = status;
+        true
+    }
+
+    /// Checks the state of a device authorization on behalf of the device.
+    /// A resolved authorization is consumed by the first poll that sees it.
+    pub fn poll_device_authorization(&mut self, device_code: &str) -> DevicePollResult {
+        let Some(authorization) = self.device_authorizations.get_mut(device_code) else {
+            return DevicePollResult::Expired;
+        };
+        if authorization.is_expired() {
+            self.remove_device_authorization(device_code);
+            return DevicePollResult::Expired;
+        }
+        let too_fast = authorization
+            .last_poll
+            .is_some_and(|x| x.elapsed() < DEVICE_CODE_POLL_INTERVAL);
+        authorization.last_poll = Some(Instant::now());
+
+        match authorization.status.clone() {
+            DeviceAuthorizationStatus::Pending if too_fast => DevicePollResult::SlowDown,
+            DeviceAuthorizationStatus::Pending => DevicePollResult::Pending,
+            DeviceAuthorizationStatus::Approved { username } => {
+                let target = authorization.target.clone();
+                self.remove_device_authorization(device_code);
+                DevicePollResult::Approved { username, target }
+            }
+            DeviceAuthorizationStatus::Rejected => {
+                self.remove_device_authorization(device_code);
+                DevicePollResult::Denied
+            }
+        }
+    }
+
     pub async fn vacuum(&mut self) {
         self.store
             .retain(|_, (_, started_at)| started_at.elapsed() < *TIMEOUT);
 
         self.completion_signals
             .retain(|_, signal| !signal.is_expired());
+
+        self.vacuum_device_authorizations();
     }
 }
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    let mut half = || -> String {
+        (0..USER_CODE_LENGTH / 2)
+            .filter_map(|_| USER_CODE_ALPHABET.choose(&mut rng))
+            .map(|c| *c as char)
+            .collect()
+    };
+    let first = half();
+    format!("{first}-{}", half())
+}
+
+fn normalize_user_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
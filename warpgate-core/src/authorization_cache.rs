@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// How long a user→target authorization result is reused for
+pub const AUTHORIZATION_CACHE_TTL: Duration = Duration::from_secs(10);
+
+const MAX_ENTRIES_BEFORE_PRUNING: usize = 1024;
+
+/// Short-lived cache of [crate::ConfigProvider::authorize_target] results.
+///
+/// Anything that changes users, targets, roles or their assignments has to
+/// call [AuthorizationCache::invalidate]. [crate::SearchIndex] relies on
+/// that too.
+#[derive(Debug, Default)]
+pub struct AuthorizationCache {
+    inner: Mutex<AuthorizationCacheInner>,
+}
+
+#[derive(Debug, Default)]
+struct AuthorizationCacheInner {
+    generation: u64,
+    entries: HashMap<(String, String), (Instant, bool)>,
+}
+
+/// Marks the state of the cache before a lookup so that results computed
+/// from data read before an invalidation don't get cached afterwards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthorizationCacheGeneration(u64);
+
+impl AuthorizationCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn inner(&self) -> MutexGuard<'_, AuthorizationCacheInner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn get(
+        &self,
+        username: &str,
+        target_name: &str,
+    ) -> Result<bool, AuthorizationCacheGeneration> {
+        let inner = self.inner();
+        match inner
+            .entries
+            .get(&(username.to_owned(), target_name.to_owned()))
+        {
+            Some((created_at, result)) if created_at.elapsed() < AUTHORIZATION_CACHE_TTL => {
+                Ok(*result)
+            }
+            _ => Err(AuthorizationCacheGeneration(inner.generation)),
+        }
+    }
+
+    pub fn insert(
+        &self,
+        generation: AuthorizationCacheGeneration,
+        username: &str,
+        target_name: &str,
+        result: bool,
+    ) {
+        let mut inner = self.inner();
+        if inner.generation != generation.0 {
+            return;
+        }
+        if inner.entries.len() >= MAX_ENTRIES_BEFORE_PRUNING {
+            inner
+                .entries
+                .retain(|_, (created_at, _)| created_at.elapsed() < AUTHORIZATION_CACHE_TTL);
+        }
+        inner.entries.insert(
+            (username.to_owned(), target_name.to_owned()),
+            (Instant::now(), result),
+        );
+    }
+
+    /// Changes on every invalidation
+    pub fn generation(&self) -> AuthorizationCacheGeneration {
+        AuthorizationCacheGeneration(self.inner().generation)
+    }
+
+    pub fn invalidate(&self) {
+        let mut inner = self.inner();
+        inner.generation += 1;
+        inner.entries.clear();
+    }
+}
@@ -0,0 +1,392 @@
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use poem_openapi::{Enum, Object};
+use serde::Serialize;
+use warpgate_common::{
+    is_address_template, ListenEndpoint, Target, TargetOptions, TlsCertificateBundle,
+    TlsPrivateKey, WarpgateConfig,
+};
+use warpgate_db_migrations::pending_migrations;
+use warpgate_sso::discover_metadata;
+
+use crate::{http_client_builder, ConfigProvider, Services, TargetResolver};
+
+const SSO_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const TARGET_LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum ConfigDiagnosticSeverity {
+    Ok,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum ConfigCheckKind {
+    Certificate,
+    Listener,
+    Sso,
+    Database,
+    Target,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct ConfigDiagnostic {
+    pub kind: ConfigCheckKind,
+    /// What was checked, e.g. a config key or a target name
+    pub subject: String,
+    pub severity: ConfigDiagnosticSeverity,
+    pub message: String,
+    /// How to fix the problem
+    pub hint: Option<String>,
+}
+
+impl ConfigDiagnostic {
+    fn ok(kind: ConfigCheckKind, subject: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            subject: subject.into(),
+            severity: ConfigDiagnosticSeverity::Ok,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn problem(
+        severity: ConfigDiagnosticSeverity,
+        kind: ConfigCheckKind,
+        subject: impl Into<String>,
+        message: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Self {
+            kind,
+            subject: subject.into(),
+            severity,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct ConfigCheckReport {
+    pub checked: DateTime<Utc>,
+    pub diagnostics: Vec<ConfigDiagnostic>,
+}
+
+/// TLS certificates and keys of the enabled protocols
+/// and the additionally trusted CAs
+pub async fn check_certificates(config: &WarpgateConfig) -> Vec<ConfigDiagnostic> {
+    let store = &config.store;
+    let mut result = vec![];
+    for (section, enabled, certificate, key) in [
+        (
+            "http",
+            store.http.enable,
+            &store.http.certificate,
+            &store.http.key,
+        ),
+        (
+            "mysql",
+            store.mysql.enable,
+            &store.mysql.certificate,
+            &store.mysql.key,
+        ),
+        (
+            "postgres",
+            store.postgres.enable,
+            &store.postgres.certificate,
+            &store.postgres.key,
+        ),
+        (
+            "redis",
+            store.redis.enable,
+            &store.redis.certificate,
+            &store.redis.key,
+        ),
+    ] {
+        if !enabled {
+            continue;
+        }
+
+        let subject = format!("{section}.certificate");
+        let path = config.paths_relative_to.join(certificate);
+        result.push(match TlsCertificateBundle::from_file(&path).await {
+            Ok(_) => ConfigDiagnostic::ok(ConfigCheckKind::Certificate, subject, "Readable"),
+            Err(error) => ConfigDiagnostic::problem(
+                ConfigDiagnosticSeverity::Error,
+                ConfigCheckKind::Certificate,
+                subject,
+                format!("Could not load {}: {error}", path.display()),
+                "Point it to a PEM certificate chain readable by Warpgate. \
+                 Relative paths are resolved against the config file's directory.",
+            ),
+        });
+
+        let subject = format!("{section}.key");
+        let path = config.paths_relative_to.join(key);
+        result.push(match TlsPrivateKey::from_file(&path).await {
+            Ok(_) => ConfigDiagnostic::ok(ConfigCheckKind::Certificate, subject, "Readable"),
+            Err(error) => ConfigDiagnostic::problem(
+                ConfigDiagnosticSeverity::Error,
+                ConfigCheckKind::Certificate,
+                subject,
+                format!("Could not load {}: {error}", path.display()),
+                "Point it to a PEM private key readable by Warpgate. \
+                 Relative paths are resolved against the config file's directory.",
+            ),
+        });
+    }
+
+    for (index, certificate) in store
+        .outbound_tls
+        .trusted_ca_certificates
+        .iter()
+        .enumerate()
+    {
+        let subject = format!("outbound_tls.trusted_ca_certificates[{index}]");
+        let path = config.paths_relative_to.join(certificate);
+        result.push(match TlsCertificateBundle::from_file(&path).await {
+            Ok(_) => ConfigDiagnostic::ok(ConfigCheckKind::Certificate, subject, "Readable"),
+            Err(error) => ConfigDiagnostic::problem(
+                ConfigDiagnosticSeverity::Error,
+                ConfigCheckKind::Certificate,
+                subject,
+                format!("Could not load {}: {error}", path.display()),
+                "Point it to a PEM file with one or more CA certificates.",
+            ),
+        });
+    }
+    result
+}
+
+fn check_listener(subject: String, endpoint: &ListenEndpoint, running: bool) -> ConfigDiagnostic {
+    let address = endpoint.address();
+    match (TcpListener::bind(address), running) {
+        (Ok(_), false) => ConfigDiagnostic::ok(ConfigCheckKind::Listener, subject, "Available"),
+        (Ok(_), true) => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Error,
+            ConfigCheckKind::Listener,
+            subject,
+            format!("Nothing is listening on {address}"),
+            "The protocol server has stopped - check the log for errors and restart Warpgate.",
+        ),
+        (Err(error), true) if error.kind() == ErrorKind::AddrInUse => {
+            ConfigDiagnostic::ok(ConfigCheckKind::Listener, subject, "Listening")
+        }
+        (Err(error), false) if error.kind() == ErrorKind::AddrInUse => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Warning,
+            ConfigCheckKind::Listener,
+            subject,
+            format!("{address} is already in use"),
+            "Expected if Warpgate is already running. \
+             Otherwise, stop the program using the port or pick a different one.",
+        ),
+        (Err(error), _) if error.kind() == ErrorKind::PermissionDenied => {
+            ConfigDiagnostic::problem(
+                ConfigDiagnosticSeverity::Error,
+                ConfigCheckKind::Listener,
+                subject,
+                format!("Not allowed to listen on {address}"),
+                "Ports below 1024 need root or the CAP_NET_BIND_SERVICE capability.",
+            )
+        }
+        (Err(error), _) => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Error,
+            ConfigCheckKind::Listener,
+            subject,
+            format!("Cannot listen on {address}: {error}"),
+            "Make sure the address belongs to this host.",
+        ),
+    }
+}
+
+/// Listen addresses of the enabled protocols. With `running`, they're
+/// expected to be taken by this Warpgate instance, otherwise to be free.
+pub fn check_listeners(config: &WarpgateConfig, running: bool) -> Vec<ConfigDiagnostic> {
+    let store = &config.store;
+    [
+        ("ssh", store.ssh.enable, &store.ssh.listen),
+        ("http", store.http.enable, &store.http.listen),
+        ("mysql", store.mysql.enable, &store.mysql.listen),
+        ("postgres", store.postgres.enable, &store.postgres.listen),
+        ("redis", store.redis.enable, &store.redis.listen),
+    ]
+    .into_iter()
+    .filter(|(_, enabled, _)| *enabled)
+    .map(|(section, _, listen)| check_listener(format!("{section}.listen"), listen, running))
+    .collect()
+}
+
+async fn check_sso_providers(config: &WarpgateConfig) -> Vec<ConfigDiagnostic> {
+    let http_client = http_client_builder().build().unwrap_or_default();
+    join_all(config.store.sso_providers.iter().map(|provider| {
+        let http_client = http_client.clone();
+        async move {
+            let subject = format!("sso_providers.{}", provider.name);
+            let discovery = tokio::time::timeout(
+                SSO_DISCOVERY_TIMEOUT,
+                discover_metadata(&provider.provider, &http_client),
+            )
+            .await;
+            match discovery {
+                Ok(Ok(_)) => ConfigDiagnostic::ok(ConfigCheckKind::Sso, subject, "Reachable"),
+                Ok(Err(error)) => ConfigDiagnostic::problem(
+                    ConfigDiagnosticSeverity::Error,
+                    ConfigCheckKind::Sso,
+                    subject,
+                    format!("Discovery failed: {error}"),
+                    "Check the issuer URL and that Warpgate can reach the provider over HTTPS.",
+                ),
+                Err(_) => ConfigDiagnostic::problem(
+                    ConfigDiagnosticSeverity::Error,
+                    ConfigCheckKind::Sso,
+                    subject,
+                    "Discovery timed out",
+                    "Check that Warpgate can reach the provider over HTTPS.",
+                ),
+            }
+        }
+    }))
+    .await
+}
+
+async fn check_database(services: &Services) -> ConfigDiagnostic {
+    let db = services.db.lock().await;
+    match pending_migrations(&db).await {
+        Ok(pending) if pending.is_empty() => {
+            ConfigDiagnostic::ok(ConfigCheckKind::Database, "database_url", "Up to date")
+        }
+        Ok(pending) => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Error,
+            ConfigCheckKind::Database,
+            "database_url",
+            format!("Migrations not applied: {}", pending.join(", ")),
+            "Restart Warpgate to apply them.",
+        ),
+        Err(error) => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Error,
+            ConfigCheckKind::Database,
+            "database_url",
+            format!("Could not query the database: {error}"),
+            "Check that the database server is up.",
+        ),
+    }
+}
+
+pub(crate) fn target_address(target: &Target) -> Option<(String, u16)> {
+    match &target.options {
+        TargetOptions::Ssh(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::MySql(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Postgres(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Redis(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Ipmi(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Http(options) => {
+            let url = url::Url::parse(&options.url).ok()?;
+            Some((url.host_str()?.to_owned(), url.port_or_known_default()?))
+        }
+        TargetOptions::Docker(_) | TargetOptions::WebAdmin(_) => None,
+    }
+}
+
+async fn check_target(resolver: TargetResolver, target: Target) -> Option<ConfigDiagnostic> {
+    let (host, port) = target_address(&target)?;
+    if is_address_template(&host) {
+        return Some(ConfigDiagnostic::ok(
+            ConfigCheckKind::Target,
+            target.name,
+            "Address is resolved at connect time",
+        ));
+    }
+    let lookup = tokio::time::timeout(TARGET_LOOKUP_TIMEOUT, resolver.resolve(&host, port)).await;
+    Some(match lookup {
+        Ok(Ok(addresses)) => match addresses.first() {
+            Some(_) => ConfigDiagnostic::ok(ConfigCheckKind::Target, target.name, "Resolvable"),
+            None => ConfigDiagnostic::problem(
+                ConfigDiagnosticSeverity::Warning,
+                ConfigCheckKind::Target,
+                target.name,
+                format!("{host} has no addresses"),
+                "Check the target's host name and DNS records.",
+            ),
+        },
+        Ok(Err(error)) => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Warning,
+            ConfigCheckKind::Target,
+            target.name,
+            format!("Could not resolve {host}: {error}"),
+            "Check the target's host name and the `dns` config section.",
+        ),
+        Err(_) => ConfigDiagnostic::problem(
+            ConfigDiagnosticSeverity::Warning,
+            ConfigCheckKind::Target,
+            target.name,
+            format!("Resolving {host} timed out"),
+            "Check the `dns` config section.",
+        ),
+    })
+}
+
+async fn check_targets(services: &Services) -> Vec<ConfigDiagnostic> {
+    let targets = match services.config_provider.lock().await.list_targets().await {
+        Ok(targets) => targets,
+        Err(error) => {
+            return vec![ConfigDiagnostic::problem(
+                ConfigDiagnosticSeverity::Error,
+                ConfigCheckKind::Target,
+                "targets",
+                format!("Could not list targets: {error}"),
+                "Check that the database server is up.",
+            )]
+        }
+    };
+    join_all(
+        targets
+            .into_iter()
+            .map(|target| check_target(services.dns.for_target(&target), target)),
+    )
+    .await
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Checks the configuration of this running instance
+pub async fn check_configuration(services: &Services) -> ConfigCheckReport {
+    let config = services.config.load().as_ref().clone();
+
+    let mut diagnostics = check_certificates(&config).await;
+    diagnostics.extend(check_listeners(&config, true));
+    diagnostics.push(check_database(services).await);
+    diagnostics.extend(check_sso_providers(&config).await);
+    diagnostics.extend(check_targets(services).await);
+
+    ConfigCheckReport {
+        checked: Utc::now(),
+        diagnostics,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = ListenEndpoint::from(listener.local_addr().unwrap());
+
+        let check = |running| check_listener("test".into(), &endpoint, running).severity;
+        assert_eq!(check(true), ConfigDiagnosticSeverity::Ok);
+        assert_eq!(check(false), ConfigDiagnosticSeverity::Warning);
+
+        drop(listener);
+        assert_eq!(check(true), ConfigDiagnosticSeverity::Error);
+        assert_eq!(check(false), ConfigDiagnosticSeverity::Ok);
+    }
+}
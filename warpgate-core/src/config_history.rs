@@ -0,0 +1,753 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveEnum, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, EntityTrait,
+    NotSet, QueryFilter, QueryOrder, Set, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::ConfigChange::{self, ConfigChangeAction, ConfigObjectKind};
+use warpgate_db_entities::RolePortalSettings::{self, PortalSectionList, TargetIdList};
+use warpgate_db_entities::Target::TargetKind;
+use warpgate_db_entities::{
+    Parameters, Role, Target, TargetRoleAssignment, User, UserRoleAssignment,
+};
+
+use crate::consts::BUILTIN_ADMIN_ROLE_NAME;
+
+/// Who made a configuration change through the admin API.
+/// Not set when the admin token was used or Warpgate made the change itself.
+#[derive(Debug, Clone)]
+pub struct ConfigChangeAuthor(pub Option<String>);
+
+/// Stands in for secrets in target snapshots
+const REDACTED_SECRET: &str = "[REDACTED]";
+
+/// Option fields that hold secrets, at any depth
+const SECRET_OPTION_FIELDS: &[&str] = &["password", "secret_access_key"];
+
+/// Option fields whose values are all treated as secrets
+const SECRET_OPTION_MAPS: &[&str] = &["headers"];
+
+/// Target secrets are not versioned and show up as [REDACTED_SECRET]
+#[derive(Serialize, Deserialize)]
+struct TargetSnapshot {
+    name: String,
+    kind: String,
+    options: Value,
+    roles: Vec<Uuid>,
+    #[serde(default)]
+    honeypot: bool,
+    #[serde(default)]
+    host_overrides: Option<Value>,
+    #[serde(default)]
+    max_concurrent_sessions: Option<i32>,
+    #[serde(default)]
+    session_limit_message: Option<String>,
+    #[serde(default)]
+    monitor: Option<Value>,
+    #[serde(default)]
+    idle_timeout_seconds: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UserRoleSnapshot {
+    role_id: Uuid,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+}
+
+/// Credentials are not versioned
+#[derive(Serialize, Deserialize)]
+struct UserSnapshot {
+    username: String,
+    credential_policy: Value,
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    deactivated: Option<DateTime<Utc>>,
+    roles: Vec<UserRoleSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoleSnapshot {
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    portal: Option<RolePortalSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RolePortalSnapshot {
+    sections: PortalSectionList,
+    hidden_targets: TargetIdList,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ParametersSnapshot {
+    allow_own_credential_management: bool,
+    allow_self_service_tickets: bool,
+    self_service_ticket_max_validity: i64,
+    self_service_ticket_protocols: Option<Vec<String>>,
+}
+
+async fn load_snapshot(
+    db: &impl ConnectionTrait,
+    kind: ConfigObjectKind,
+    id: Uuid,
+) -> Result<Option<Value>, WarpgateError> {
+    let snapshot = match kind {
+        ConfigObjectKind::Target => {
+            let Some(target) = Target::Entity::find_by_id(id).one(db).await? else {
+                return Ok(None);
+            };
+            let mut roles: Vec<Uuid> = TargetRoleAssignment::Entity::find()
+                .filter(TargetRoleAssignment::Column::TargetId.eq(id))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| x.role_id)
+                .collect();
+            roles.sort();
+            serde_json::to_value(TargetSnapshot {
+                name: target.name,
+                kind: target.kind.to_value(),
+                options: redact_secrets(target.options),
+                roles,
+                honeypot: target.honeypot,
+                host_overrides: target.host_overrides,
+                max_concurrent_sessions: target.max_concurrent_sessions,
+                session_limit_message: target.session_limit_message,
+                monitor: target.monitor,
+                idle_timeout_seconds: target.idle_timeout_seconds,
+            })?
+        }
+        ConfigObjectKind::User => {
+            let Some(user) = User::Entity::find_by_id(id).one(db).await? else {
+                return Ok(None);
+            };
+            let mut roles: Vec<UserRoleSnapshot> = UserRoleAssignment::Entity::find()
+                .filter(UserRoleAssignment::Column::UserId.eq(id))
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| UserRoleSnapshot {
+                    role_id: x.role_id,
+                    valid_from: x.valid_from,
+                    valid_until: x.valid_until,
+                })
+                .collect();
+            roles.sort_by_key(|x| x.role_id);
+            serde_json::to_value(UserSnapshot {
+                username: user.username,
+                credential_policy: user.credential_policy,
+                valid_from: user.valid_from,
+                valid_until: user.valid_until,
+                deactivated: user.deactivated,
+                roles,
+            })?
+        }
+        ConfigObjectKind::Role => {
+            let Some(role) = Role::Entity::find_by_id(id).one(db).await? else {
+                return Ok(None);
+            };
+            let portal = RolePortalSettings::Entity::find_by_id(id)
+                .one(db)
+                .await?
+                .map(|x| RolePortalSnapshot {
+                    sections: x.sections,
+                    hidden_targets: x.hidden_targets,
+                });
+            serde_json::to_value(RoleSnapshot {
+                name: role.name,
+                portal,
+            })?
+        }
+        ConfigObjectKind::Parameters => {
+            let Some(parameters) = Parameters::Entity::find_by_id(id).one(db).await? else {
+                return Ok(None);
+            };
+            serde_json::to_value(ParametersSnapshot {
+                allow_own_credential_management: parameters.allow_own_credential_management,
+                allow_self_service_tickets: parameters.allow_self_service_tickets,
+                self_service_ticket_max_validity: parameters.self_service_ticket_max_validity,
+                self_service_ticket_protocols: parameters
+                    .self_service_ticket_protocols
+                    .map(|x| x.0),
+            })?
+        }
+    };
+    Ok(Some(snapshot))
+}
+
+fn redact_secrets(mut options: Value) -> Value {
+    fn redact(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if value.is_null() {
+                        continue;
+                    }
+                    if SECRET_OPTION_FIELDS.contains(&key.as_str()) {
+                        *value = Value::String(REDACTED_SECRET.into());
+                    } else if SECRET_OPTION_MAPS.contains(&key.as_str()) {
+                        if let Value::Object(map) = value {
+                            for value in map.values_mut() {
+                                *value = Value::String(REDACTED_SECRET.into());
+                            }
+                        }
+                    } else {
+                        redact(value);
+                    }
+                }
+            }
+            Value::Array(items) => items.iter_mut().for_each(redact),
+            _ => (),
+        }
+    }
+    redact(&mut options);
+    options
+}
+
+/// Fills the secrets left out of a snapshot back in from `current`.
+/// Returns `None` if one of them isn't present there anymore.
+fn restore_secrets(snapshot: &mut Value, current: Option<&Value>) -> Option<()> {
+    match snapshot {
+        Value::String(x) if x == REDACTED_SECRET => {
+            *snapshot = current?.clone();
+        }
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                restore_secrets(value, current.and_then(|x| x.get(key)))?;
+            }
+        }
+        Value::Array(items) => {
+            for (index, value) in items.iter_mut().enumerate() {
+                restore_secrets(value, current.and_then(|x| x.get(index)))?;
+            }
+        }
+        _ => (),
+    }
+    Some(())
+}
+
+fn diff_into(path: &str, old: &Value, new: &Value, changes: &mut Vec<Value>) {
+    if let (Value::Object(old), Value::Object(new)) = (old, new) {
+        let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        for key in keys {
+            let path = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{path}.{key}")
+            };
+            diff_into(
+                &path,
+                old.get(key).unwrap_or(&Value::Null),
+                new.get(key).unwrap_or(&Value::Null),
+                changes,
+            );
+        }
+    } else if old != new {
+        changes.push(serde_json::json!({
+            "path": path,
+            "old": old,
+            "new": new,
+        }));
+    }
+}
+
+/// Lists the changed fields between two snapshots as `{ path, old, new }`,
+/// nested objects are compared field by field and arrays as a whole
+pub fn diff_snapshots(old: Option<&Value>, new: Option<&Value>) -> Value {
+    let empty = Value::Object(Map::new());
+    let mut changes = vec![];
+    diff_into(
+        "",
+        old.unwrap_or(&empty),
+        new.unwrap_or(&empty),
+        &mut changes,
+    );
+    Value::Array(changes)
+}
+
+/// Records the current state of an object as its next version.
+/// Call after the change has been written. Updates that didn't change
+/// anything are not recorded.
+pub async fn record_config_change(
+    db: &DatabaseConnection,
+    kind: ConfigObjectKind,
+    object_id: Uuid,
+    action: ConfigChangeAction,
+    author: &ConfigChangeAuthor,
+) -> Result<Option<ConfigChange::Model>, WarpgateError> {
+    record_change(db, kind, object_id, action, author).await
+}
+
+async fn record_change(
+    db: &impl ConnectionTrait,
+    kind: ConfigObjectKind,
+    object_id: Uuid,
+    action: ConfigChangeAction,
+    author: &ConfigChangeAuthor,
+) -> Result<Option<ConfigChange::Model>, WarpgateError> {
+    let snapshot = load_snapshot(db, kind, object_id).await?;
+
+    let previous = ConfigChange::Entity::find()
+        .filter(ConfigChange::Column::ObjectKind.eq(kind))
+        .filter(ConfigChange::Column::ObjectId.eq(object_id))
+        .order_by_desc(ConfigChange::Column::Version)
+        .one(db)
+        .await?;
+
+    let diff = diff_snapshots(
+        previous.as_ref().and_then(|x| x.snapshot.as_ref()),
+        snapshot.as_ref(),
+    );
+    if action == ConfigChangeAction::Update && diff.as_array().is_some_and(Vec::is_empty) {
+        return Ok(None);
+    }
+
+    let change = ConfigChange::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        object_kind: Set(kind),
+        object_id: Set(object_id),
+        version: Set(previous.map(|x| x.version).unwrap_or_default() + 1),
+        action: Set(action),
+        author: Set(author.0.clone()),
+        timestamp: Set(Utc::now()),
+        snapshot: Set(snapshot),
+        diff: Set(diff),
+    }
+    .insert(db)
+    .await?;
+    Ok(Some(change))
+}
+
+pub enum ConfigRollbackResult {
+    Done(ConfigChange::Model),
+    NotFound,
+    /// The version records a deletion or can't be applied anymore
+    NotRestorable(String),
+    /// Another object already uses the name
+    NameConflict,
+}
+
+async fn restore_target(
+    db: &impl ConnectionTrait,
+    id: Uuid,
+    mut snapshot: TargetSnapshot,
+) -> Result<Option<ConfigRollbackResult>, WarpgateError> {
+    let kind = TargetKind::try_from_value(&snapshot.kind)?;
+    let current = Target::Entity::find_by_id(id).one(db).await?;
+    if restore_secrets(&mut snapshot.options, current.as_ref().map(|x| &x.options)).is_none() {
+        return Ok(Some(ConfigRollbackResult::NotRestorable(
+            "the target's credentials are not kept in the history and it doesn't have them anymore"
+                .into(),
+        )));
+    }
+    if Target::Entity::find()
+        .filter(Target::Column::Name.eq(&snapshot.name))
+        .filter(Target::Column::Id.ne(id))
+        .one(db)
+        .await?
+        .is_some()
+    {
+        return Ok(Some(ConfigRollbackResult::NameConflict));
+    }
+
+    let model = Target::ActiveModel {
+        id: Set(id),
+        name: Set(snapshot.name),
+        kind: Set(kind),
+        options: Set(snapshot.options),
+        honeypot: Set(snapshot.honeypot),
+        host_overrides: Set(snapshot.host_overrides),
+        max_concurrent_sessions: Set(snapshot.max_concurrent_sessions),
+        session_limit_message: Set(snapshot.session_limit_message),
+        monitor: Set(snapshot.monitor),
+        idle_timeout_seconds: Set(snapshot.idle_timeout_seconds),
+        // Maintenance state, not configuration
+        draining: NotSet,
+    };
+    match current {
+        Some(_) => model.update(db).await?,
+        None => model.insert(db).await?,
+    };
+
+    TargetRoleAssignment::Entity::delete_many()
+        .filter(TargetRoleAssignment::Column::TargetId.eq(id))
+        .exec(db)
+        .await?;
+    let existing_roles = Role::Entity::find()
+        .filter(Role::Column::Id.is_in(snapshot.roles))
+        .all(db)
+        .await?;
+    for role in existing_roles {
+        TargetRoleAssignment::ActiveModel {
+            target_id: Set(id),
+            role_id: Set(role.id),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+    }
+    Ok(None)
+}
+
+async fn restore_user(
+    db: &impl ConnectionTrait,
+    id: Uuid,
+    snapshot: UserSnapshot,
+) -> Result<Option<ConfigRollbackResult>, WarpgateError> {
+    if User::Entity::find()
+        .filter(User::Column::Username.eq(&snapshot.username))
+        .filter(User::Column::Id.ne(id))
+        .one(db)
+        .await?
+        .is_some()
+    {
+        return Ok(Some(ConfigRollbackResult::NameConflict));
+    }
+
+    let model = User::ActiveModel {
+        id: Set(id),
+        username: Set(snapshot.username),
+        credential_policy: Set(snapshot.credential_policy),
+        valid_from: Set(snapshot.valid_from),
+        valid_until: Set(snapshot.valid_until),
+        deactivated: Set(snapshot.deactivated),
+    };
+    match User::Entity::find_by_id(id).one(db).await? {
+        Some(_) => model.update(db).await?,
+        None => model.insert(db).await?,
+    };
+
+    UserRoleAssignment::Entity::delete_many()
+        .filter(UserRoleAssignment::Column::UserId.eq(id))
+        .exec(db)
+        .await?;
+    let existing_roles: Vec<Uuid> = Role::Entity::find()
+        .filter(Role::Column::Id.is_in(snapshot.roles.iter().map(|x| x.role_id)))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|x| x.id)
+        .collect();
+    for role in snapshot.roles {
+        if !existing_roles.contains(&role.role_id) {
+            continue;
+        }
+        UserRoleAssignment::ActiveModel {
+            user_id: Set(id),
+            role_id: Set(role.role_id),
+            valid_from: Set(role.valid_from),
+            valid_until: Set(role.valid_until),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+    }
+    Ok(None)
+}
+
+async fn restore_role(
+    db: &impl ConnectionTrait,
+    id: Uuid,
+    snapshot: RoleSnapshot,
+) -> Result<Option<ConfigRollbackResult>, WarpgateError> {
+    let current = Role::Entity::find_by_id(id).one(db).await?;
+    if current
+        .as_ref()
+        .is_some_and(|x| x.name == BUILTIN_ADMIN_ROLE_NAME && x.name != snapshot.name)
+    {
+        return Ok(Some(ConfigRollbackResult::NotRestorable(
+            "the built-in admin role can't be renamed".into(),
+        )));
+    }
+    if Role::Entity::find()
+        .filter(Role::Column::Name.eq(&snapshot.name))
+        .filter(Role::Column::Id.ne(id))
+        .one(db)
+        .await?
+        .is_some()
+    {
+        return Ok(Some(ConfigRollbackResult::NameConflict));
+    }
+
+    let model = Role::ActiveModel {
+        id: Set(id),
+        name: Set(snapshot.name),
+    };
+    match current {
+        Some(_) => model.update(db).await?,
+        None => model.insert(db).await?,
+    };
+
+    RolePortalSettings::Entity::delete_by_id(id)
+        .exec(db)
+        .await?;
+    if let Some(portal) = snapshot.portal {
+        RolePortalSettings::ActiveModel {
+            role_id: Set(id),
+            sections: Set(portal.sections),
+            hidden_targets: Set(portal.hidden_targets),
+        }
+        .insert(db)
+        .await?;
+    }
+    Ok(None)
+}
+
+async fn restore_parameters(
+    db: &impl ConnectionTrait,
+    snapshot: ParametersSnapshot,
+) -> Result<Uuid, WarpgateError> {
+    // There is only ever one row
+    let id = Parameters::Entity::find()
+        .one(db)
+        .await?
+        .ok_or(WarpgateError::InconsistentState)?
+        .id;
+    Parameters::ActiveModel {
+        id: Set(id),
+        allow_own_credential_management: Set(snapshot.allow_own_credential_management),
+        allow_self_service_tickets: Set(snapshot.allow_self_service_tickets),
+        self_service_ticket_max_validity: Set(snapshot.self_service_ticket_max_validity),
+        self_service_ticket_protocols: Set(snapshot
+            .self_service_ticket_protocols
+            .map(Parameters::ProtocolList)),
+    }
+    .update(db)
+    .await?;
+    Ok(id)
+}
+
+/// Restores an object to the state recorded in a change and records
+/// that as a new version. A deleted object is recreated with its
+/// original ID, role assignments to roles that no longer exist are skipped
+/// and credentials are left as they are. Target secrets are taken from the
+/// target's current options. All of it happens in one transaction.
+/// The caller must invalidate the authorization cache.
+pub async fn rollback_config_change(
+    db: &DatabaseConnection,
+    change_id: Uuid,
+    author: &ConfigChangeAuthor,
+) -> Result<ConfigRollbackResult, WarpgateError> {
+    let txn = db.begin().await?;
+    let db = &txn;
+    let Some(change) = ConfigChange::Entity::find_by_id(change_id).one(db).await? else {
+        return Ok(ConfigRollbackResult::NotFound);
+    };
+    let Some(snapshot) = change.snapshot else {
+        return Ok(ConfigRollbackResult::NotRestorable(
+            "this version records a deletion".into(),
+        ));
+    };
+
+    let mut object_id = change.object_id;
+    let rejection = match change.object_kind {
+        ConfigObjectKind::Target => {
+            restore_target(db, object_id, serde_json::from_value(snapshot)?).await?
+        }
+        ConfigObjectKind::User => {
+            restore_user(db, object_id, serde_json::from_value(snapshot)?).await?
+        }
+        ConfigObjectKind::Role => {
+            restore_role(db, object_id, serde_json::from_value(snapshot)?).await?
+        }
+        ConfigObjectKind::Parameters => {
+            object_id = restore_parameters(db, serde_json::from_value(snapshot)?).await?;
+            None
+        }
+    };
+    if let Some(rejection) = rejection {
+        return Ok(rejection);
+    }
+
+    let change = record_change(
+        db,
+        change.object_kind,
+        object_id,
+        ConfigChangeAction::Rollback,
+        author,
+    )
+    .await?
+    .ok_or(WarpgateError::InconsistentState)?;
+    txn.commit().await?;
+    Ok(ConfigRollbackResult::Done(change))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_redact_secrets() {
+        let options = json!({
+            "kind": "Ssh",
+            "auth": { "kind": "Password", "password": "hunter2" },
+            "aws_ssm": { "region": "x", "secret_access_key": "key", "session_token": null },
+            "headers": { "Authorization": "Bearer x" },
+            "shadow": [{ "password": null }],
+        });
+        assert_eq!(
+            redact_secrets(options.clone()),
+            json!({
+                "kind": "Ssh",
+                "auth": { "kind": "Password", "password": REDACTED_SECRET },
+                "aws_ssm": { "region": "x", "secret_access_key": REDACTED_SECRET, "session_token": null },
+                "headers": { "Authorization": REDACTED_SECRET },
+                "shadow": [{ "password": null }],
+            })
+        );
+
+        let mut restored = redact_secrets(options.clone());
+        assert_eq!(restore_secrets(&mut restored, Some(&options)), Some(()));
+        assert_eq!(restored, options);
+
+        let mut restored = redact_secrets(options);
+        assert_eq!(restore_secrets(&mut restored, Some(&json!({}))), None);
+        assert_eq!(restore_secrets(&mut restored, None), None);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_target_secrets_stay_out_of_history() {
+        use sea_orm::{Database, IntoActiveModel};
+        use warpgate_db_migrations::migrate_database;
+
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate_database(&db).await.unwrap();
+        let author = ConfigChangeAuthor(None);
+        let id = Uuid::new_v4();
+        let options = |host: &str, password: &str| {
+            json!({
+                "kind": "Ssh",
+                "host": host,
+                "auth": { "kind": "Password", "password": password },
+            })
+        };
+
+        let mut target = Target::ActiveModel {
+            id: Set(id),
+            name: Set("ssh".into()),
+            kind: Set(TargetKind::Ssh),
+            options: Set(options("a", "old-secret")),
+            honeypot: Set(false),
+            host_overrides: Set(None),
+            max_concurrent_sessions: Set(None),
+            session_limit_message: Set(None),
+            monitor: Set(None),
+            idle_timeout_seconds: Set(None),
+            draining: Set(false),
+        }
+        .insert(&db)
+        .await
+        .unwrap()
+        .into_active_model();
+        let first = record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            id,
+            ConfigChangeAction::Create,
+            &author,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        target.options = Set(options("b", "new-secret"));
+        target.update(&db).await.unwrap();
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            id,
+            ConfigChangeAction::Update,
+            &author,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+        for change in ConfigChange::Entity::find().all(&db).await.unwrap() {
+            let stored = serde_json::to_string(&(change.snapshot, change.diff)).unwrap();
+            assert!(!stored.contains("secret"), "{stored}");
+        }
+
+        // The current secret is kept
+        let ConfigRollbackResult::Done(_) = rollback_config_change(&db, first.id, &author)
+            .await
+            .unwrap()
+        else {
+            panic!("rollback failed");
+        };
+        let target = Target::Entity::find_by_id(id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(target.options, options("a", "new-secret"));
+
+        // A deleted target has no secrets to take
+        Target::Entity::delete_by_id(id).exec(&db).await.unwrap();
+        record_config_change(
+            &db,
+            ConfigObjectKind::Target,
+            id,
+            ConfigChangeAction::Delete,
+            &author,
+        )
+        .await
+        .unwrap();
+        let ConfigRollbackResult::NotRestorable(_) = rollback_config_change(&db, first.id, &author)
+            .await
+            .unwrap()
+        else {
+            panic!("rollback should have been refused");
+        };
+        assert!(Target::Entity::find_by_id(id)
+            .one(&db)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let old = json!({
+            "name": "db",
+            "options": { "host": "a", "port": 22, "auth": { "kind": "password" } },
+            "roles": [1, 2],
+        });
+        let new = json!({
+            "name": "db",
+            "options": { "host": "b", "port": 22, "auth": { "kind": "publickey" } },
+            "roles": [1],
+        });
+        assert_eq!(
+            diff_snapshots(Some(&old), Some(&new)),
+            json!([
+                { "path": "options.auth.kind", "old": "password", "new": "publickey" },
+                { "path": "options.host", "old": "a", "new": "b" },
+                { "path": "roles", "old": [1, 2], "new": [1] },
+            ])
+        );
+        assert_eq!(diff_snapshots(Some(&old), Some(&old)), json!([]));
+    }
+
+    #[test]
+    fn test_diff_snapshots_create_delete() {
+        let snapshot = json!({ "name": "admins" });
+        assert_eq!(
+            diff_snapshots(None, Some(&snapshot)),
+            json!([{ "path": "name", "old": null, "new": "admins" }])
+        );
+        assert_eq!(
+            diff_snapshots(Some(&snapshot), None),
+            json!([{ "path": "name", "old": "admins", "new": null }])
+        );
+    }
+}
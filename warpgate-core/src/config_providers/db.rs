@@ -20,16 +20,29 @@ use warpgate_common::{
     UserSsoCredential, UserTotpCredential, WarpgateError,
 };
 use warpgate_db_entities as entities;
+use warpgate_db_entities::ApiToken::ApiTokenScope;
+use warpgate_db_entities::ConfigChange::{ConfigChangeAction, ConfigObjectKind};
 
 use super::ConfigProvider;
+use crate::{
+    find_refresh_token_by_access_token, record_config_change, AuthorizationCache,
+    ConfigChangeAuthor,
+};
 
 pub struct DatabaseConfigProvider {
     db: Arc<Mutex<DatabaseConnection>>,
+    authorization_cache: Arc<AuthorizationCache>,
 }
 
 impl DatabaseConfigProvider {
-    pub async fn new(db: &Arc<Mutex<DatabaseConnection>>) -> Self {
-        Self { db: db.clone() }
+    pub async fn new(
+        db: &Arc<Mutex<DatabaseConnection>>,
+        authorization_cache: Arc<AuthorizationCache>,
+    ) -> Self {
+        Self {
+            db: db.clone(),
+            authorization_cache,
+        }
     }
 }
 
@@ -44,7 +57,7 @@ impl ConfigProvider for DatabaseConfigProvider {
 
         let users: Result<Vec<User>, _> = users.into_iter().map(|t| t.try_into()).collect();
 
-        Ok(users?)
+        users
     }
 
     async fn list_targets(&mut self) -> Result<Vec<Target>, WarpgateError> {
@@ -77,6 +90,11 @@ impl ConfigProvider for DatabaseConfigProvider {
             return Ok(None);
         };
 
+        if !user_model.is_active(Utc::now()) {
+            warn!(%username, "Account is outside of its validity period");
+            return Ok(None);
+        }
+
         let user = user_model.load_details(&db).await?;
 
         let supported_credential_types: HashSet<CredentialKind> = user
@@ -190,6 +208,11 @@ impl ConfigProvider for DatabaseConfigProvider {
             return Ok(false);
         };
 
+        if !user_model.is_active(Utc::now()) {
+            warn!(%username, "Account is outside of its validity period");
+            return Ok(false);
+        }
+
         let user_details = user_model.load_details(&db).await?;
 
         match client_credential {
@@ -204,7 +227,7 @@ impl ConfigProvider for DatabaseConfigProvider {
                     "Client key: {}", openssh_public_key
                 );
 
-                return Ok(user_details
+                Ok(user_details
                     .credentials
                     .iter()
                     .any(|credential| match credential {
@@ -212,10 +235,10 @@ impl ConfigProvider for DatabaseConfigProvider {
                             key: ref user_key,
                         }) => &openssh_public_key == user_key.expose_secret(),
                         _ => false,
-                    }));
+                    }))
             }
             AuthCredential::Password(client_password) => {
-                return Ok(user_details
+                Ok(user_details
                     .credentials
                     .iter()
                     .any(|credential| match credential {
@@ -236,7 +259,7 @@ impl ConfigProvider for DatabaseConfigProvider {
                     }))
             }
             AuthCredential::Otp(client_otp) => {
-                return Ok(user_details
+                Ok(user_details
                     .credentials
                     .iter()
                     .any(|credential| match credential {
@@ -263,9 +286,9 @@ impl ConfigProvider for DatabaseConfigProvider {
                         }
                     }
                 }
-                return Ok(false);
+                Ok(false)
             }
-            _ => return Err(WarpgateError::InvalidCredentialType),
+            _ => Err(WarpgateError::InvalidCredentialType),
         }
     }
 
@@ -274,6 +297,11 @@ impl ConfigProvider for DatabaseConfigProvider {
         username: &str,
         target_name: &str,
     ) -> Result<bool, WarpgateError> {
+        let generation = match self.authorization_cache.get(username, target_name) {
+            Ok(result) => return Ok(result),
+            Err(generation) => generation,
+        };
+
         let db = self.db.lock().await;
 
         let target_model = entities::Target::Entity::find()
@@ -306,8 +334,7 @@ impl ConfigProvider for DatabaseConfigProvider {
             .collect();
 
         let user_roles: HashSet<String> = user_model
-            .find_related(entities::Role::Entity)
-            .all(&*db)
+            .find_active_roles(&db)
             .await?
             .into_iter()
             .map(Into::<Role>::into)
@@ -316,9 +343,31 @@ impl ConfigProvider for DatabaseConfigProvider {
 
         let intersect = user_roles.intersection(&target_roles).count() > 0;
 
+        self.authorization_cache
+            .insert(generation, username, target_name, intersect);
+
         Ok(intersect)
     }
 
+    async fn list_user_roles(&mut self, username: &str) -> Result<Vec<String>, WarpgateError> {
+        let db = self.db.lock().await;
+
+        let Some(user_model) = entities::User::Entity::find()
+            .filter(entities::User::Column::Username.eq(username))
+            .one(&*db)
+            .await?
+        else {
+            return Ok(vec![]);
+        };
+
+        Ok(user_model
+            .find_active_roles(&db)
+            .await?
+            .into_iter()
+            .map(|x| x.name)
+            .collect())
+    }
+
     async fn apply_sso_role_mappings(
         &mut self,
         username: &str,
@@ -369,10 +418,28 @@ impl ConfigProvider for DatabaseConfigProvider {
                     };
 
                     values.insert(&*db).await.map_err(WarpgateError::from)?;
+                    record_config_change(
+                        &db,
+                        ConfigObjectKind::User,
+                        user.id,
+                        ConfigChangeAction::Update,
+                        &ConfigChangeAuthor(None),
+                    )
+                    .await?;
+                    self.authorization_cache.invalidate();
                 }
                 (Some(assignment), false) => {
                     info!("Removing role {role_name} for user {username} (from SSO)");
                     assignment.delete(&*db).await.map_err(WarpgateError::from)?;
+                    record_config_change(
+                        &db,
+                        ConfigObjectKind::User,
+                        user.id,
+                        ConfigChangeAction::Update,
+                        &ConfigChangeAuthor(None),
+                    )
+                    .await?;
+                    self.authorization_cache.invalidate();
                 }
                 _ => (),
             }
@@ -435,7 +502,10 @@ impl ConfigProvider for DatabaseConfigProvider {
         Ok(())
     }
 
-    async fn validate_api_token(&mut self, token: &str) -> Result<Option<User>, WarpgateError> {
+    async fn validate_api_token(
+        &mut self,
+        token: &str,
+    ) -> Result<Option<(User, ApiTokenScope)>, WarpgateError> {
         let db = self.db.lock().await;
         let Some(ticket) = entities::ApiToken::Entity::find()
             .filter(
@@ -446,7 +516,22 @@ impl ConfigProvider for DatabaseConfigProvider {
             .one(&*db)
             .await?
         else {
-            return Ok(None);
+            // Access tokens issued with a refresh token
+            let Some(refresh_token) = find_refresh_token_by_access_token(&db, token).await? else {
+                return Ok(None);
+            };
+            let Some(user) = refresh_token
+                .find_related(entities::User::Entity)
+                .one(&*db)
+                .await?
+            else {
+                return Err(WarpgateError::InconsistentState);
+            };
+            if !user.is_active(Utc::now()) {
+                return Ok(None);
+            }
+            let scope = refresh_token.scope.unwrap_or_default();
+            return Ok(Some((user.try_into()?, scope)));
         };
 
         let Some(user) = ticket
@@ -457,6 +542,10 @@ impl ConfigProvider for DatabaseConfigProvider {
             return Err(WarpgateError::InconsistentState);
         };
 
-        Ok(Some(user.try_into()?))
+        if !user.is_active(Utc::now()) {
+            return Ok(None);
+        }
+
+        Ok(Some((user.try_into()?, ticket.scope.unwrap_or_default())))
     }
 }
@@ -10,7 +10,8 @@ use tracing::*;
 use uuid::Uuid;
 use warpgate_common::auth::{AuthCredential, CredentialKind, CredentialPolicy};
 use warpgate_common::{Secret, Target, User, WarpgateError};
-use warpgate_db_entities::Ticket;
+use warpgate_db_entities::ApiToken::ApiTokenScope;
+use warpgate_db_entities::{Ticket, User as UserEntity};
 
 #[enum_dispatch]
 pub enum ConfigProviderEnum {
@@ -54,12 +55,17 @@ pub trait ConfigProvider {
         target: &str,
     ) -> Result<bool, WarpgateError>;
 
+    async fn list_user_roles(&mut self, username: &str) -> Result<Vec<String>, WarpgateError>;
+
     async fn update_public_key_last_used(
         &self,
         credential: Option<AuthCredential>,
     ) -> Result<(), WarpgateError>;
 
-    async fn validate_api_token(&mut self, token: &str) -> Result<Option<User>, WarpgateError>;
+    async fn validate_api_token(
+        &mut self,
+        token: &str,
+    ) -> Result<Option<(User, ApiTokenScope)>, WarpgateError>;
 }
 
 //TODO: move this somewhere
@@ -88,6 +94,18 @@ pub async fn authorize_ticket(
                 }
             }
 
+            let user = {
+                let db = db.lock().await;
+                UserEntity::Entity::find()
+                    .filter(UserEntity::Column::Username.eq(&ticket.username))
+                    .one(&*db)
+                    .await?
+            };
+            if user.is_some_and(|user| !user.is_active(chrono::Utc::now())) {
+                warn!("Ticket belongs to an inactive account: {}", &ticket.id);
+                return Ok(None);
+            }
+
             Ok(Some(ticket))
         }
         None => {
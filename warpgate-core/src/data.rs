@@ -1,9 +1,14 @@
 use chrono::{DateTime, Utc};
+use poem::http::StatusCode;
+use poem::{FromRequest, Request, RequestBody};
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warpgate_common::{SessionId, Target};
-use warpgate_db_entities::Session;
+use warpgate_db_entities::Session::{self, SessionTerminationReason};
+use warpgate_db_entities::{DatabaseSessionDetails, HttpSessionDetails, SshSessionDetails};
+
+use crate::SessionChannelsSnapshot;
 
 #[derive(Serialize, Deserialize, Object)]
 pub struct SessionSnapshot {
@@ -14,6 +19,19 @@ pub struct SessionSnapshot {
     pub ended: Option<DateTime<Utc>>,
     pub ticket_id: Option<Uuid>,
     pub protocol: String,
+    /// When the user accepted the consent banner
+    pub consent_acknowledged: Option<DateTime<Utc>>,
+    /// Groups sessions that belong to the same task or incident
+    pub work_item: Option<String>,
+    /// Only set once the session has ended
+    pub termination_reason: Option<SessionTerminationReason>,
+    pub exit_code: Option<i64>,
+    /// Only included for active sessions
+    pub channels: Option<SessionChannelsSnapshot>,
+    /// Protocol specifics, only included in the session detail
+    pub ssh_details: Option<SshSessionDetails::Model>,
+    pub http_details: Option<HttpSessionDetails::Model>,
+    pub database_details: Option<DatabaseSessionDetails::Model>,
 }
 
 impl From<Session::Model> for SessionSnapshot {
@@ -28,6 +46,39 @@ impl From<Session::Model> for SessionSnapshot {
             ended: model.ended,
             ticket_id: model.ticket_id,
             protocol: model.protocol,
+            consent_acknowledged: model.consent_acknowledged,
+            work_item: model.work_item,
+            termination_reason: model.termination_reason,
+            exit_code: model.exit_code,
+            channels: None,
+            ssh_details: None,
+            http_details: None,
+            database_details: None,
         }
     }
 }
+
+/// The admin making an admin API request, set once the request has been
+/// authorized. `username` is not set when the admin token was used.
+#[derive(Debug, Clone)]
+pub struct AdminIdentity {
+    pub username: Option<String>,
+}
+
+impl std::fmt::Display for AdminIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.username {
+            Some(ref username) => f.write_str(username),
+            None => f.write_str("admin token"),
+        }
+    }
+}
+
+impl<'a> FromRequest<'a> for AdminIdentity {
+    async fn from_request(req: &'a Request, _: &mut RequestBody) -> poem::Result<Self> {
+        req.extensions()
+            .get::<AdminIdentity>()
+            .cloned()
+            .ok_or_else(|| poem::Error::from_status(StatusCode::UNAUTHORIZED))
+    }
+}
@@ -1,3 +1,5 @@
+use std::ops::Deref;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Result;
@@ -6,6 +8,7 @@ use sea_orm::{
     ActiveModelTrait, ColumnTrait, ConnectOptions, Database, DatabaseConnection, EntityTrait,
     ModelTrait, QueryFilter, TransactionTrait,
 };
+use tokio::sync::Mutex;
 use tracing::*;
 use uuid::Uuid;
 use warpgate_common::helpers::fs::secure_file;
@@ -43,6 +46,28 @@ pub async fn connect_to_db(config: &WarpgateConfig) -> Result<DatabaseConnection
         secure_file(&abs_path)?;
     }
 
+    let connection = Database::connect(connect_options(&url)).await?;
+
+    migrate_database(&connection).await?;
+    Ok(connection)
+}
+
+/// Connects to the configured read replica, if any. The replica is expected
+/// to follow the primary, so no migrations are run against it.
+pub async fn connect_to_read_replica(
+    config: &WarpgateConfig,
+) -> Result<Option<DatabaseConnection>> {
+    let Some(ref replica_url) = config.store.database_read_replica_url else {
+        return Ok(None);
+    };
+    let url = url::Url::parse(replica_url.expose_secret().as_str())?;
+    if url.scheme() == "sqlite" {
+        anyhow::bail!("SQLite databases can't be used as a read replica");
+    }
+    Ok(Some(Database::connect(connect_options(&url)).await?))
+}
+
+fn connect_options(url: &url::Url) -> ConnectOptions {
     let mut opt = ConnectOptions::new(url.to_string());
     opt.max_connections(100)
         .min_connections(5)
@@ -50,11 +75,34 @@ pub async fn connect_to_db(config: &WarpgateConfig) -> Result<DatabaseConnection
         .idle_timeout(Duration::from_secs(8))
         .max_lifetime(Duration::from_secs(8))
         .sqlx_logging(true);
+    opt
+}
 
-    let connection = Database::connect(opt).await?;
+/// Database connection for read-only list and report queries. Points to the
+/// read replica if one is configured and to the primary database otherwise,
+/// so it may lag slightly behind writes.
+#[derive(Clone)]
+pub struct ReadOnlyDatabase(Arc<Mutex<DatabaseConnection>>);
 
-    migrate_database(&connection).await?;
-    Ok(connection)
+impl ReadOnlyDatabase {
+    /// Writes and transactions keep going through `primary`
+    pub fn new(
+        primary: &Arc<Mutex<DatabaseConnection>>,
+        replica: Option<DatabaseConnection>,
+    ) -> Self {
+        Self(match replica {
+            Some(replica) => Arc::new(Mutex::new(replica)),
+            None => primary.clone(),
+        })
+    }
+}
+
+impl Deref for ReadOnlyDatabase {
+    type Target = Arc<Mutex<DatabaseConnection>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
 }
 
 pub async fn populate_db(
@@ -62,27 +110,6 @@ pub async fn populate_db(
     _config: &mut WarpgateConfig,
 ) -> Result<(), WarpgateError> {
     use sea_orm::ActiveValue::Set;
-    use warpgate_db_entities::{Recording, Session};
-
-    Recording::Entity::update_many()
-        .set(Recording::ActiveModel {
-            ended: Set(Some(chrono::Utc::now())),
-            ..Default::default()
-        })
-        .filter(Expr::col(Recording::Column::Ended).is_null())
-        .exec(db)
-        .await
-        .map_err(WarpgateError::from)?;
-
-    Session::Entity::update_many()
-        .set(Session::ActiveModel {
-            ended: Set(Some(chrono::Utc::now())),
-            ..Default::default()
-        })
-        .filter(Expr::col(Session::Column::Ended).is_null())
-        .exec(db)
-        .await
-        .map_err(WarpgateError::from)?;
 
     let admin_role = match Role::Entity::find()
         .filter(Role::Column::Name.eq(BUILTIN_ADMIN_ROLE_NAME))
@@ -116,6 +143,13 @@ pub async fn populate_db(
                     TargetWebAdminOptions {},
                 ))
                 .map_err(WarpgateError::from)?),
+                honeypot: Set(false),
+                host_overrides: Set(None),
+                max_concurrent_sessions: Set(None),
+                session_limit_message: Set(None),
+                monitor: Set(None),
+                idle_timeout_seconds: Set(None),
+                draining: Set(false),
             };
 
             values.insert(&*db).await.map_err(WarpgateError::from)?
@@ -145,7 +179,7 @@ pub async fn cleanup_db(
     recordings: &mut SessionRecordings,
     retention: &Duration,
 ) -> Result<()> {
-    use warpgate_db_entities::{Recording, Session};
+    use warpgate_db_entities::{DropBoxEvent, Recording, RefreshToken, Session};
     let cutoff = chrono::Utc::now() - chrono::Duration::from_std(*retention)?;
 
     LogEntry::Entity::delete_many()
@@ -153,6 +187,16 @@ pub async fn cleanup_db(
         .exec(db)
         .await?;
 
+    DropBoxEvent::Entity::delete_many()
+        .filter(Expr::col(DropBoxEvent::Column::Timestamp).lt(cutoff))
+        .exec(db)
+        .await?;
+
+    RefreshToken::Entity::delete_many()
+        .filter(RefreshToken::Column::Expiry.lt(chrono::Utc::now()))
+        .exec(db)
+        .await?;
+
     let recordings_to_delete = Recording::Entity::find()
         .filter(Expr::col(Session::Column::Ended).is_not_null())
         .filter(Expr::col(Session::Column::Ended).lt(cutoff))
@@ -177,3 +221,63 @@ pub async fn cleanup_db(
 
     Ok(())
 }
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use sea_orm::Set;
+
+    use super::*;
+
+    async fn memory_db() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate_database(&db).await.unwrap();
+        db
+    }
+
+    async fn add_role(db: &impl sea_orm::ConnectionTrait, name: &str) {
+        Role::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            name: Set(name.to_owned()),
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    async fn has_role(db: &Mutex<DatabaseConnection>, name: &str) -> bool {
+        Role::Entity::find()
+            .filter(Role::Column::Name.eq(name))
+            .one(&*db.lock().await)
+            .await
+            .unwrap()
+            .is_some()
+    }
+
+    #[tokio::test]
+    async fn test_reads_go_to_the_replica() {
+        let primary = Arc::new(Mutex::new(memory_db().await));
+        let read_db = ReadOnlyDatabase::new(&primary, Some(memory_db().await));
+
+        {
+            let db = primary.lock().await;
+            let txn = db.begin().await.unwrap();
+            add_role(&txn, "written").await;
+            txn.commit().await.unwrap();
+        }
+        assert!(has_role(&primary, "written").await);
+        assert!(!has_role(&read_db, "written").await);
+
+        add_role(&*read_db.lock().await, "replicated").await;
+        assert!(has_role(&read_db, "replicated").await);
+        assert!(!has_role(&primary, "replicated").await);
+    }
+
+    #[tokio::test]
+    async fn test_reads_fall_back_to_the_primary() {
+        let primary = Arc::new(Mutex::new(memory_db().await));
+        let read_db = ReadOnlyDatabase::new(&primary, None);
+
+        add_role(&*primary.lock().await, "written").await;
+        assert!(has_role(&read_db, "written").await);
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use rand::distributions::{Alphanumeric, DistString};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde::Serialize;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::helpers::rng::get_crypto_rng;
+use warpgate_common::{is_address_template, Target, TargetOptions, WarpgateError};
+use warpgate_db_entities::DirectCredential;
+
+use crate::{ConfigProvider, Services, TargetResolver};
+
+/// How often expired direct credentials are dropped from their targets
+pub const DIRECT_CREDENTIALS_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+const PASSWORD_LENGTH: usize = 32;
+
+/// Creates and drops accounts on one kind of database target
+#[async_trait]
+pub trait DirectCredentialProvisioner: Send + Sync {
+    /// `username` and `password` only consist of characters
+    /// that don't need escaping
+    async fn create_user(
+        &self,
+        target: &Target,
+        resolver: &TargetResolver,
+        username: &str,
+        password: &str,
+        expires: DateTime<Utc>,
+    ) -> Result<(), WarpgateError>;
+
+    /// Also closes the account's connections where the target allows it.
+    /// Succeeds if the account is already gone.
+    async fn drop_user(
+        &self,
+        target: &Target,
+        resolver: &TargetResolver,
+        username: &str,
+    ) -> Result<(), WarpgateError>;
+}
+
+/// Filled in by the database protocol servers, by protocol name
+#[derive(Default)]
+pub struct DirectCredentialProvisioners(
+    RwLock<HashMap<&'static str, Arc<dyn DirectCredentialProvisioner>>>,
+);
+
+impl DirectCredentialProvisioners {
+    pub fn register(
+        &self,
+        protocol: &'static str,
+        provisioner: Arc<dyn DirectCredentialProvisioner>,
+    ) {
+        self.0
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(protocol, provisioner);
+    }
+
+    fn get(&self, target: &Target) -> Option<Arc<dyn DirectCredentialProvisioner>> {
+        self.0
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target.options.protocol_name())
+            .cloned()
+    }
+}
+
+#[derive(Debug, Serialize, Object)]
+pub struct IssuedDirectCredential {
+    pub id: Uuid,
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    /// Only ever shown once
+    pub password: String,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DirectCredentialError {
+    #[error("the target doesn't offer direct credentials")]
+    NotAvailable,
+    #[error(transparent)]
+    Other(#[from] WarpgateError),
+}
+
+fn host_and_port(options: &TargetOptions) -> Option<(&str, u16)> {
+    match options {
+        TargetOptions::MySql(options) => Some((&options.host, options.port)),
+        TargetOptions::Postgres(options) => Some((&options.host, options.port)),
+        _ => None,
+    }
+}
+
+/// e.g. `wg_alice_k3x9q2`, at most 26 characters to suit MySQL
+fn database_username(username: &str) -> String {
+    let name = username
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .take(16)
+        .collect::<String>();
+    let suffix = Alphanumeric
+        .sample_string(&mut get_crypto_rng(), 6)
+        .to_ascii_lowercase();
+    format!("wg_{name}_{suffix}")
+}
+
+/// Creates a short-lived account for `username` on the target, which must
+/// have direct credentials enabled and already be authorized for the user
+pub async fn issue_direct_credential(
+    services: &Services,
+    target: &Target,
+    username: &str,
+) -> Result<IssuedDirectCredential, DirectCredentialError> {
+    let (Some(options), Some((host, port))) = (
+        target.options.direct_credentials(),
+        host_and_port(&target.options),
+    ) else {
+        return Err(DirectCredentialError::NotAvailable);
+    };
+    // There'd be no telling which host to clean up later
+    if target.honeypot || is_address_template(host) {
+        return Err(DirectCredentialError::NotAvailable);
+    }
+    let Some(provisioner) = services.direct_credential_provisioners.get(target) else {
+        return Err(DirectCredentialError::NotAvailable);
+    };
+
+    let now = Utc::now();
+    let expires = now + chrono::Duration::seconds(options.ttl_seconds as i64);
+    let database_username = database_username(username);
+    let password = Alphanumeric.sample_string(&mut get_crypto_rng(), PASSWORD_LENGTH);
+
+    // Recorded first, so that the account is cleaned up even if
+    // creating it fails halfway through
+    let credential = DirectCredential::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        target_id: Set(target.id),
+        target_name: Set(target.name.clone()),
+        username: Set(username.to_owned()),
+        database_username: Set(database_username.clone()),
+        created: Set(now),
+        expires: Set(expires),
+        removed: Set(None),
+        removal_error: Set(None),
+    }
+    .insert(&*services.db.lock().await)
+    .await
+    .map_err(WarpgateError::from)?;
+
+    let resolver = services.dns.for_target(target);
+    if let Err(error) = provisioner
+        .create_user(target, &resolver, &database_username, &password, expires)
+        .await
+    {
+        error!(target=%target.name, %username, %database_username, %error, "Failed to create direct credentials");
+        if let Err(error) =
+            remove_credential(services, provisioner.as_ref(), target, credential).await
+        {
+            error!(?error, "Failed to update direct credentials");
+        }
+        return Err(error.into());
+    }
+
+    info!(target=%target.name, %username, %database_username, %expires, "Issued direct credentials");
+    Ok(IssuedDirectCredential {
+        id: credential.id,
+        host: host.to_owned(),
+        port,
+        username: database_username,
+        password,
+        expires,
+    })
+}
+
+/// Drops the account right away instead of waiting for it to expire.
+/// Returns `false` if there's no such credential.
+pub async fn revoke_direct_credential(
+    services: &Services,
+    id: Uuid,
+) -> Result<bool, WarpgateError> {
+    let credential = {
+        let db = services.db.lock().await;
+        let Some(credential) = DirectCredential::Entity::find_by_id(id).one(&*db).await? else {
+            return Ok(false);
+        };
+        if credential.removed.is_some() {
+            return Ok(true);
+        }
+        let mut model: DirectCredential::ActiveModel = credential.into();
+        model.expires = Set(Utc::now());
+        model.update(&*db).await?
+    };
+    info!(target=%credential.target_name, username=%credential.username, database_username=%credential.database_username, "Revoking direct credentials");
+    remove_direct_credentials(services, vec![credential]).await?;
+    Ok(true)
+}
+
+/// Drops expired accounts from their targets, retrying earlier failures
+pub async fn remove_expired_direct_credentials(services: &Services) -> Result<(), WarpgateError> {
+    let expired = DirectCredential::Entity::find()
+        .filter(DirectCredential::Column::Expires.lte(Utc::now()))
+        .filter(DirectCredential::Column::Removed.is_null())
+        .all(&*services.db.lock().await)
+        .await?;
+    if !expired.is_empty() {
+        remove_direct_credentials(services, expired).await?;
+    }
+    Ok(())
+}
+
+async fn remove_direct_credentials(
+    services: &Services,
+    credentials: Vec<DirectCredential::Model>,
+) -> Result<(), WarpgateError> {
+    let targets = services
+        .config_provider
+        .lock()
+        .await
+        .list_targets()
+        .await?
+        .into_iter()
+        .map(|t| (t.id, t))
+        .collect::<HashMap<_, _>>();
+
+    for credential in credentials {
+        let Some(target) = targets.get(&credential.target_id) else {
+            // Nothing left to connect to
+            warn!(target=%credential.target_name, database_username=%credential.database_username, "Target is gone, can't drop direct credentials");
+            let mut model: DirectCredential::ActiveModel = credential.into();
+            model.removed = Set(Some(Utc::now()));
+            model.removal_error = Set(Some("The target has been deleted".into()));
+            model.update(&*services.db.lock().await).await?;
+            continue;
+        };
+        let Some(provisioner) = services.direct_credential_provisioners.get(target) else {
+            continue;
+        };
+        if let Err(error) =
+            remove_credential(services, provisioner.as_ref(), target, credential).await
+        {
+            error!(?error, "Failed to update direct credentials");
+        }
+    }
+    Ok(())
+}
+
+async fn remove_credential(
+    services: &Services,
+    provisioner: &dyn DirectCredentialProvisioner,
+    target: &Target,
+    credential: DirectCredential::Model,
+) -> Result<(), WarpgateError> {
+    let resolver = services.dns.for_target(target);
+    let result = provisioner
+        .drop_user(target, &resolver, &credential.database_username)
+        .await;
+    let mut model: DirectCredential::ActiveModel = credential.clone().into();
+    match result {
+        Ok(()) => {
+            info!(target=%target.name, username=%credential.username, database_username=%credential.database_username, "Removed direct credentials");
+            model.removed = Set(Some(Utc::now()));
+            model.removal_error = Set(None);
+        }
+        Err(error) => {
+            error!(target=%target.name, database_username=%credential.database_username, %error, "Failed to drop direct credentials");
+            model.removal_error = Set(Some(error.to_string()));
+        }
+    }
+    model.update(&*services.db.lock().await).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_username() {
+        let name = database_username("Alice.Smith@example.com");
+        assert!(name.starts_with("wg_alicesmithexampl_"));
+        assert_eq!(name.len(), "wg_alicesmithexampl_".len() + 6);
+        assert!(name
+            .chars()
+            .all(|c| c == '_' || c.is_ascii_lowercase() || c.is_ascii_digit()));
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::Serialize;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct DiscoveredHostKey {
+    pub kind: String,
+    pub public_key_base64: String,
+}
+
+/// An SSH server found by the discovery scan that isn't a target yet
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct DiscoveredHost {
+    pub id: Uuid,
+    pub host: String,
+    pub port: u16,
+    /// The server's SSH identification string, e.g. `SSH-2.0-OpenSSH_9.6`
+    pub banner: String,
+    pub host_keys: Vec<DiscoveredHostKey>,
+    pub discovered: DateTime<Utc>,
+}
+
+impl DiscoveredHost {
+    pub fn new(host: String, port: u16, banner: String, host_keys: Vec<DiscoveredHostKey>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            host,
+            port,
+            banner,
+            host_keys,
+            discovered: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct DiscoveryStatus {
+    pub enabled: bool,
+    pub scanning: bool,
+    pub last_scan_started: Option<DateTime<Utc>>,
+    pub last_scan_finished: Option<DateTime<Utc>>,
+    pub candidates: Vec<DiscoveredHost>,
+}
+
+/// Results of the SSH target discovery scan. The scan itself is run
+/// by the SSH protocol crate, which also owns the host key handling.
+pub struct TargetDiscovery {
+    candidates: Vec<DiscoveredHost>,
+    scanning: bool,
+    last_scan_started: Option<DateTime<Utc>>,
+    last_scan_finished: Option<DateTime<Utc>>,
+    scan_requested: Arc<Notify>,
+}
+
+impl TargetDiscovery {
+    pub fn new() -> Self {
+        Self {
+            candidates: vec![],
+            scanning: false,
+            last_scan_started: None,
+            last_scan_finished: None,
+            scan_requested: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn status(&self, enabled: bool) -> DiscoveryStatus {
+        DiscoveryStatus {
+            enabled,
+            scanning: self.scanning,
+            last_scan_started: self.last_scan_started,
+            last_scan_finished: self.last_scan_finished,
+            candidates: self.candidates.clone(),
+        }
+    }
+
+    pub fn candidate(&self, id: &Uuid) -> Option<&DiscoveredHost> {
+        self.candidates.iter().find(|x| &x.id == id)
+    }
+
+    pub fn remove_candidate(&mut self, id: &Uuid) -> Option<DiscoveredHost> {
+        let index = self.candidates.iter().position(|x| &x.id == id)?;
+        Some(self.candidates.remove(index))
+    }
+
+    pub fn is_scanning(&self) -> bool {
+        self.scanning
+    }
+
+    /// Wakes up the scanner without waiting for the next interval
+    pub fn request_scan(&self) {
+        self.scan_requested.notify_one();
+    }
+
+    pub fn scan_requested(&self) -> Arc<Notify> {
+        self.scan_requested.clone()
+    }
+
+    pub fn scan_started(&mut self) {
+        self.scanning = true;
+        self.last_scan_started = Some(Utc::now());
+    }
+
+    /// Replaces the candidate list, keeping the IDs and discovery
+    /// time of hosts that were already known
+    pub fn scan_finished(&mut self, mut candidates: Vec<DiscoveredHost>) {
+        for candidate in candidates.iter_mut() {
+            if let Some(existing) = self
+                .candidates
+                .iter()
+                .find(|x| x.host == candidate.host && x.port == candidate.port)
+            {
+                candidate.id = existing.id;
+                candidate.discovered = existing.discovered;
+            }
+        }
+        self.candidates = candidates;
+        self.scanning = false;
+        self.last_scan_finished = Some(Utc::now());
+    }
+
+    /// Keeps the previous candidates
+    pub fn scan_cancelled(&mut self) {
+        self.scanning = false;
+    }
+}
+
+impl Default for TargetDiscovery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
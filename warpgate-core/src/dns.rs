@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use hickory_resolver::config::{NameServerConfig, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::TokioResolver;
+use tokio::net::TcpStream;
+use tracing::*;
+use warpgate_common::{DnsConfig, DnsProtocol, Target};
+
+/// Resolves the hostnames of targets. Rebuilt with [DnsResolver::reload]
+/// when the config changes.
+pub struct DnsResolver {
+    resolver: ArcSwap<TokioResolver>,
+}
+
+impl DnsResolver {
+    pub fn new(config: &DnsConfig) -> Self {
+        Self {
+            resolver: ArcSwap::from_pointee(build_resolver(config)),
+        }
+    }
+
+    pub fn reload(&self, config: &DnsConfig) {
+        self.resolver.store(Arc::new(build_resolver(config)));
+    }
+
+    /// Resolver that also applies the target's `host_overrides`
+    pub fn for_target(self: &Arc<Self>, target: &Target) -> TargetResolver {
+        TargetResolver {
+            dns: self.clone(),
+            overrides: Arc::new(target.host_overrides.clone()),
+        }
+    }
+
+    pub async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        let resolver = self.resolver.load_full();
+        let lookup = resolver.lookup_ip(host).await.map_err(io::Error::other)?;
+        let addresses: Vec<_> = lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect();
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{host} has no addresses"),
+            ));
+        }
+        Ok(addresses)
+    }
+}
+
+fn build_resolver(config: &DnsConfig) -> TokioResolver {
+    if config.upstreams.is_empty() {
+        match TokioResolver::builder_tokio() {
+            Ok(builder) => return builder.build(),
+            Err(error) => {
+                warn!(%error, "Could not load the system DNS configuration, using public resolvers")
+            }
+        }
+        return TokioResolver::builder_with_config(
+            ResolverConfig::default(),
+            TokioConnectionProvider::default(),
+        )
+        .build();
+    }
+
+    let mut resolver_config = ResolverConfig::new();
+    for upstream in &config.upstreams {
+        let protocol = match upstream.protocol {
+            DnsProtocol::Udp => Protocol::Udp,
+            DnsProtocol::Tcp => Protocol::Tcp,
+            DnsProtocol::Tls => Protocol::Tls,
+            DnsProtocol::Https => Protocol::Https,
+        };
+        if matches!(protocol, Protocol::Tls | Protocol::Https) && upstream.tls_name.is_none() {
+            continue;
+        }
+        let mut name_server =
+            NameServerConfig::new(SocketAddr::new(upstream.address, upstream.port()), protocol);
+        name_server.tls_dns_name = upstream.tls_name.clone();
+        resolver_config.add_name_server(name_server);
+    }
+    TokioResolver::builder_with_config(resolver_config, TokioConnectionProvider::default()).build()
+}
+
+/// Resolves and connects to the addresses a single target uses
+#[derive(Clone)]
+pub struct TargetResolver {
+    dns: Arc<DnsResolver>,
+    overrides: Arc<BTreeMap<String, IpAddr>>,
+}
+
+impl TargetResolver {
+    pub async fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Some(ip) = self
+            .overrides
+            .iter()
+            .find_map(|(name, ip)| name.eq_ignore_ascii_case(host).then_some(*ip))
+        {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        self.dns.resolve(host, port).await
+    }
+
+    /// Tries each resolved address in turn
+    pub async fn connect(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let mut last_error = None;
+        for address in self.resolve(host, port).await? {
+            match TcpStream::connect(address).await {
+                Ok(stream) => return Ok(stream),
+                Err(error) => {
+                    debug!(%address, %error, "Connection failed");
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+    }
+}
+
+impl Debug for TargetResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TargetResolver")
+            .field("overrides", &self.overrides)
+            .finish()
+    }
+}
+
+impl reqwest::dns::Resolve for TargetResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let this = self.clone();
+        Box::pin(async move {
+            let addresses = TargetResolver::resolve(&this, name.as_str(), 0).await?;
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_host_overrides() {
+        let target: Target = serde_json::from_value(serde_json::json!({
+            "name": "db",
+            "postgres": {"host": "db.internal"},
+            "host_overrides": {"DB.internal": "10.0.0.5"},
+        }))
+        .unwrap();
+        let resolver = Arc::new(DnsResolver::new(&DnsConfig::default())).for_target(&target);
+
+        assert_eq!(
+            resolver.resolve("db.internal", 5432).await.unwrap(),
+            vec!["10.0.0.5:5432".parse().unwrap()]
+        );
+        assert_eq!(
+            resolver.resolve("127.0.0.1", 22).await.unwrap(),
+            vec!["127.0.0.1:22".parse().unwrap()]
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use poem_openapi::{Enum, Object};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{DropBoxConfig, SessionId};
+use warpgate_db_entities::DropBoxEvent::{self, DropBoxAction};
+
+const MAX_NAME_LENGTH: usize = 255;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DropBoxError {
+    #[error("The drop-box is disabled")]
+    Disabled,
+
+    #[error("Invalid item name")]
+    InvalidName,
+
+    #[error("Item is larger than {0} bytes")]
+    ItemTooLarge(usize),
+
+    #[error("Drop-box would exceed {0} bytes")]
+    Full(usize),
+
+    #[error("Not enough drop-box space on the server")]
+    OutOfSpace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum DropBoxItemSource {
+    /// Uploaded through the web portal
+    Portal,
+    /// Staged with `warpgate-dropbox put` in the SSH session
+    Session,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct DropBoxItem {
+    pub id: Uuid,
+    pub name: String,
+    pub size: u64,
+    /// Hex SHA-256 of the content
+    pub sha256: String,
+    pub source: DropBoxItemSource,
+    pub created: DateTime<Utc>,
+}
+
+/// Memory held by the drop-boxes of all sessions, see
+/// [DropBoxConfig::max_global_size]
+#[derive(Clone, Default)]
+pub struct DropBoxUsage(Arc<AtomicUsize>);
+
+impl DropBoxUsage {
+    pub fn current(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn reserve(&self, size: usize, limit: usize) -> Result<DropBoxReservation, DropBoxError> {
+        let mut reservation = DropBoxReservation {
+            usage: self.0.clone(),
+            size: 0,
+        };
+        reservation.grow(size, limit)?;
+        Ok(reservation)
+    }
+}
+
+/// Released back to [DropBoxUsage] when dropped
+pub struct DropBoxReservation {
+    usage: Arc<AtomicUsize>,
+    size: usize,
+}
+
+impl DropBoxReservation {
+    fn grow(&mut self, additional: usize, limit: usize) -> Result<(), DropBoxError> {
+        self.usage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                current
+                    .checked_add(additional)
+                    .filter(|total| *total <= limit)
+            })
+            .map_err(|_| DropBoxError::OutOfSpace)?;
+        self.size += additional;
+        Ok(())
+    }
+}
+
+impl Drop for DropBoxReservation {
+    fn drop(&mut self) {
+        self.usage.fetch_sub(self.size, Ordering::Relaxed);
+    }
+}
+
+/// Data received so far for an item uploaded in chunks.
+/// It counts towards the global limit while it's being received.
+pub struct DropBoxUpload {
+    pub name: String,
+    data: BytesMut,
+    reservation: DropBoxReservation,
+}
+
+impl DropBoxUpload {
+    pub fn append(&mut self, data: &[u8], config: &DropBoxConfig) -> Result<(), DropBoxError> {
+        if self.data.len() + data.len() > config.max_item_size {
+            return Err(DropBoxError::ItemTooLarge(config.max_item_size));
+        }
+        self.reservation.grow(data.len(), config.max_global_size)?;
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+struct StoredItem {
+    item: DropBoxItem,
+    data: Bytes,
+    _reservation: DropBoxReservation,
+}
+
+/// Files and text staged for a single session. Lives in [crate::SessionState],
+/// so all items are gone once the session ends.
+pub struct SessionDropBox {
+    items: Vec<StoredItem>,
+    usage: DropBoxUsage,
+}
+
+impl SessionDropBox {
+    pub fn new(usage: DropBoxUsage) -> Self {
+        Self {
+            items: vec![],
+            usage,
+        }
+    }
+
+    pub fn list(&mut self, config: &DropBoxConfig) -> Vec<DropBoxItem> {
+        self.prune(config);
+        self.items
+            .iter()
+            .map(|stored| stored.item.clone())
+            .collect()
+    }
+
+    pub fn get(&mut self, name: &str, config: &DropBoxConfig) -> Option<(DropBoxItem, Bytes)> {
+        self.prune(config);
+        self.items
+            .iter()
+            .find(|stored| stored.item.name == name)
+            .map(|stored| (stored.item.clone(), stored.data.clone()))
+    }
+
+    pub fn get_by_id(&mut self, id: &Uuid, config: &DropBoxConfig) -> Option<(DropBoxItem, Bytes)> {
+        self.prune(config);
+        self.items
+            .iter()
+            .find(|stored| &stored.item.id == id)
+            .map(|stored| (stored.item.clone(), stored.data.clone()))
+    }
+
+    /// Stages an item, replacing an existing one with the same name
+    pub fn put(
+        &mut self,
+        name: &str,
+        source: DropBoxItemSource,
+        data: Bytes,
+        config: &DropBoxConfig,
+    ) -> Result<DropBoxItem, DropBoxError> {
+        if !config.enable {
+            return Err(DropBoxError::Disabled);
+        }
+        if !is_valid_name(name) {
+            return Err(DropBoxError::InvalidName);
+        }
+        if data.len() > config.max_item_size {
+            return Err(DropBoxError::ItemTooLarge(config.max_item_size));
+        }
+        self.prune(config);
+        let other_items_size: usize = self
+            .items
+            .iter()
+            .filter(|stored| stored.item.name != name)
+            .map(|stored| stored.data.len())
+            .sum();
+        if other_items_size + data.len() > config.max_total_size {
+            return Err(DropBoxError::Full(config.max_total_size));
+        }
+
+        // The replaced item's space is only given up if the new one fits
+        let replaced = self
+            .items
+            .iter()
+            .position(|stored| stored.item.name == name)
+            .map(|index| self.items.remove(index));
+        let reservation = match self.usage.reserve(data.len(), config.max_global_size) {
+            Ok(reservation) => reservation,
+            Err(error) => {
+                self.items.extend(replaced);
+                return Err(error);
+            }
+        };
+        drop(replaced);
+
+        let item = DropBoxItem {
+            id: Uuid::new_v4(),
+            name: name.to_owned(),
+            size: data.len() as u64,
+            sha256: HEXLOWER.encode(&Sha256::digest(&data)),
+            source,
+            created: Utc::now(),
+        };
+        self.items.push(StoredItem {
+            item: item.clone(),
+            data,
+            _reservation: reservation,
+        });
+        Ok(item)
+    }
+
+    /// Starts receiving an item that will be staged with [Self::finish_upload]
+    pub fn start_upload(
+        &self,
+        name: String,
+        config: &DropBoxConfig,
+    ) -> Result<DropBoxUpload, DropBoxError> {
+        if !config.enable {
+            return Err(DropBoxError::Disabled);
+        }
+        if !is_valid_name(&name) {
+            return Err(DropBoxError::InvalidName);
+        }
+        Ok(DropBoxUpload {
+            name,
+            data: BytesMut::new(),
+            reservation: self.usage.reserve(0, config.max_global_size)?,
+        })
+    }
+
+    pub fn finish_upload(
+        &mut self,
+        upload: DropBoxUpload,
+        source: DropBoxItemSource,
+        config: &DropBoxConfig,
+    ) -> Result<DropBoxItem, DropBoxError> {
+        let DropBoxUpload {
+            name,
+            data,
+            reservation,
+        } = upload;
+        drop(reservation);
+        self.put(&name, source, data.freeze(), config)
+    }
+
+    pub fn remove(&mut self, id: &Uuid) -> Option<DropBoxItem> {
+        let index = self.items.iter().position(|stored| &stored.item.id == id)?;
+        Some(self.items.remove(index).item)
+    }
+
+    fn prune(&mut self, config: &DropBoxConfig) {
+        let now = Utc::now();
+        self.items.retain(|stored| {
+            (now - stored.item.created)
+                .to_std()
+                .map(|age| age < config.retention)
+                .unwrap_or(true)
+        });
+    }
+}
+
+/// Persists who staged, retrieved and deleted which items
+pub struct DropBoxAuditLog {
+    db: Arc<Mutex<DatabaseConnection>>,
+}
+
+impl DropBoxAuditLog {
+    pub fn new(db: Arc<Mutex<DatabaseConnection>>) -> Self {
+        Self { db }
+    }
+
+    /// Never fails, since a broken store must not affect the transfer itself
+    pub async fn record(
+        &self,
+        session_id: SessionId,
+        username: Option<String>,
+        action: DropBoxAction,
+        via_portal: bool,
+        item: &DropBoxItem,
+    ) {
+        let model = DropBoxEvent::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            session_id: Set(session_id),
+            timestamp: Set(Utc::now()),
+            username: Set(username),
+            action: Set(action),
+            via_portal: Set(via_portal),
+            item_name: Set(item.name.clone()),
+            size: Set(item.size as i64),
+            sha256: Set(item.sha256.clone()),
+        };
+        if let Err(error) = model.insert(&*self.db.lock().await).await {
+            error!(session=%session_id, %error, "Failed to record the drop-box event");
+        }
+    }
+}
+
+/// Names are passed as a single shell-like word to `warpgate-dropbox`
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name != "."
+        && name != ".."
+        && !name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '/' || c == '\\')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn config() -> DropBoxConfig {
+        DropBoxConfig {
+            enable: true,
+            max_item_size: 10,
+            max_total_size: 15,
+            max_global_size: 25,
+            retention: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let config = config();
+        let mut drop_box = SessionDropBox::new(DropBoxUsage::default());
+        let source = DropBoxItemSource::Portal;
+
+        assert!(drop_box
+            .put("a.txt", source, Bytes::from_static(b"0123456789"), &config)
+            .is_ok());
+        assert!(matches!(
+            drop_box.put("b.txt", source, Bytes::from_static(b"0123456789a"), &config),
+            Err(DropBoxError::ItemTooLarge(10))
+        ));
+        assert!(matches!(
+            drop_box.put("b.txt", source, Bytes::from_static(b"012345"), &config),
+            Err(DropBoxError::Full(15))
+        ));
+        // Replacing an item only counts its new size
+        assert!(drop_box
+            .put("a.txt", source, Bytes::from_static(b"01234"), &config)
+            .is_ok());
+        assert!(drop_box
+            .put("b.txt", source, Bytes::from_static(b"0123456789"), &config)
+            .is_ok());
+        assert_eq!(drop_box.list(&config).len(), 2);
+
+        for name in ["", "..", "a/b", "a b"] {
+            assert!(matches!(
+                drop_box.put(name, source, Bytes::new(), &config),
+                Err(DropBoxError::InvalidName)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut config = config();
+        let mut drop_box = SessionDropBox::new(DropBoxUsage::default());
+        drop_box
+            .put("a.txt", DropBoxItemSource::Session, Bytes::new(), &config)
+            .unwrap();
+        assert!(drop_box.get("a.txt", &config).is_some());
+
+        config.retention = Duration::ZERO;
+        assert!(drop_box.get("a.txt", &config).is_none());
+        assert!(drop_box.list(&config).is_empty());
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let config = config();
+        let usage = DropBoxUsage::default();
+        let mut first = SessionDropBox::new(usage.clone());
+        let mut second = SessionDropBox::new(usage.clone());
+        let source = DropBoxItemSource::Portal;
+
+        first
+            .put("a.txt", source, Bytes::from_static(b"0123456789"), &config)
+            .unwrap();
+        second
+            .put("a.txt", source, Bytes::from_static(b"0123456789"), &config)
+            .unwrap();
+        assert_eq!(usage.current(), 20);
+
+        // Uploads in progress count as well
+        let mut upload = first.start_upload("b.txt".into(), &config).unwrap();
+        upload.append(b"0123", &config).unwrap();
+        assert!(matches!(
+            second.put("b.txt", source, Bytes::from_static(b"01"), &config),
+            Err(DropBoxError::OutOfSpace)
+        ));
+        assert!(matches!(
+            upload.append(b"01", &config),
+            Err(DropBoxError::OutOfSpace)
+        ));
+        first.finish_upload(upload, source, &config).unwrap();
+        assert_eq!(usage.current(), 24);
+
+        // A failed replacement keeps the old item
+        assert!(matches!(
+            second.put("a.txt", source, Bytes::from_static(b"0123456789"), &config),
+            Err(DropBoxError::OutOfSpace)
+        ));
+        assert!(second.get("a.txt", &config).is_some());
+
+        drop(second);
+        assert_eq!(usage.current(), 14);
+        let id = first.get("a.txt", &config).unwrap().0.id;
+        first.remove(&id);
+        assert_eq!(usage.current(), 4);
+    }
+}
@@ -0,0 +1,15 @@
+use tracing::*;
+use warpgate_common::trusted_ca_certificates;
+
+/// A [reqwest::ClientBuilder] that also trusts
+/// `outbound_tls.trusted_ca_certificates`
+pub fn http_client_builder() -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder();
+    for cert in trusted_ca_certificates() {
+        match reqwest::Certificate::from_der(&cert) {
+            Ok(cert) => builder = builder.add_root_certificate(cert),
+            Err(error) => error!(%error, "Invalid trusted CA certificate"),
+        }
+    }
+    builder
+}
@@ -0,0 +1,307 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::{DateTime, Utc};
+use data_encoding::{BASE64, HEXLOWER};
+use hkdf::Hkdf;
+use poem_openapi::Object;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{HttpSessionStorageKind, WarpgateError};
+use warpgate_db_entities::HttpSession;
+
+const KEY_DERIVATION_INFO: &[u8] = b"warpgate-http-session";
+const NONCE_SIZE: usize = 12;
+
+/// Encrypts web session entries so that neither memory dumps nor
+/// database backups reveal their contents
+pub struct HttpSessionCipher(Aes256Gcm);
+
+impl HttpSessionCipher {
+    pub fn new(secret: &[u8]) -> Self {
+        let mut key = [0; 32];
+        // Only fails for output lengths over 255 hash lengths
+        #[allow(clippy::expect_used)]
+        Hkdf::<Sha256>::new(None, secret)
+            .expand(KEY_DERIVATION_INFO, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self(Aes256Gcm::new(&key.into()))
+    }
+
+    pub fn encrypt(&self, entries: &BTreeMap<String, Value>) -> Result<String, WarpgateError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let mut data = nonce.to_vec();
+        data.extend(
+            self.0
+                .encrypt(&nonce, serde_json::to_vec(entries)?.as_slice())
+                .map_err(|_| WarpgateError::InconsistentState)?,
+        );
+        Ok(BASE64.encode(&data))
+    }
+
+    /// Returns `None` for data that was tampered with or encrypted with another key
+    pub fn decrypt(&self, data: &str) -> Option<BTreeMap<String, Value>> {
+        let data = BASE64.decode(data.as_bytes()).ok()?;
+        if data.len() < NONCE_SIZE {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+        let plaintext = self.0.decrypt(Nonce::from_slice(nonce), ciphertext).ok()?;
+        serde_json::from_slice(&plaintext).ok()
+    }
+}
+
+/// Stored alongside the encrypted entries so that sessions can be listed
+#[derive(Default)]
+pub struct HttpSessionMetadata {
+    pub username: Option<String>,
+    pub session_id: Option<Uuid>,
+}
+
+#[derive(Object, Clone, Debug)]
+pub struct HttpSessionInfo {
+    /// Derived from the session cookie, which itself is never stored
+    pub id: String,
+    pub username: Option<String>,
+    pub session_id: Option<Uuid>,
+    pub created: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl From<HttpSession::Model> for HttpSessionInfo {
+    fn from(model: HttpSession::Model) -> Self {
+        Self {
+            id: model.id,
+            username: model.username,
+            session_id: model.session_id,
+            created: model.created,
+            last_seen: model.updated,
+            expires: model.expires,
+        }
+    }
+}
+
+struct MemoryHttpSession {
+    data: String,
+    info: HttpSessionInfo,
+}
+
+enum HttpSessionBackend {
+    Memory(Mutex<HashMap<String, MemoryHttpSession>>),
+    Database(Arc<Mutex<DatabaseConnection>>),
+}
+
+/// Web session storage shared by all HTTP requests and the admin API
+#[derive(Clone)]
+pub struct HttpSessionStorage {
+    cipher: Arc<HttpSessionCipher>,
+    backend: Arc<HttpSessionBackend>,
+}
+
+fn storage_id(cookie: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(cookie.as_bytes()))
+}
+
+fn is_expired(expires: Option<DateTime<Utc>>) -> bool {
+    expires.is_some_and(|x| x <= Utc::now())
+}
+
+impl HttpSessionStorage {
+    pub fn new(
+        kind: HttpSessionStorageKind,
+        cipher: HttpSessionCipher,
+        db: Arc<Mutex<DatabaseConnection>>,
+    ) -> Self {
+        let backend = match kind {
+            HttpSessionStorageKind::Memory => HttpSessionBackend::Memory(Default::default()),
+            HttpSessionStorageKind::Database => HttpSessionBackend::Database(db),
+        };
+        Self {
+            cipher: Arc::new(cipher),
+            backend: Arc::new(backend),
+        }
+    }
+
+    pub async fn load(
+        &self,
+        cookie: &str,
+    ) -> Result<Option<BTreeMap<String, Value>>, WarpgateError> {
+        let id = storage_id(cookie);
+        let data = match *self.backend {
+            HttpSessionBackend::Memory(ref sessions) => sessions
+                .lock()
+                .await
+                .get(&id)
+                .filter(|s| !is_expired(s.info.expires))
+                .map(|s| s.data.clone()),
+            HttpSessionBackend::Database(ref db) => HttpSession::Entity::find_by_id(id)
+                .one(&*db.lock().await)
+                .await?
+                .filter(|s| !is_expired(s.expires))
+                .map(|s| s.data),
+        };
+        Ok(data.and_then(|data| {
+            let entries = self.cipher.decrypt(&data);
+            if entries.is_none() {
+                debug!("Could not decrypt a web session, the key might have changed");
+            }
+            entries
+        }))
+    }
+
+    pub async fn save(
+        &self,
+        cookie: &str,
+        entries: &BTreeMap<String, Value>,
+        expires: Option<Duration>,
+        metadata: HttpSessionMetadata,
+    ) -> Result<(), WarpgateError> {
+        let id = storage_id(cookie);
+        let data = self.cipher.encrypt(entries)?;
+        let now = Utc::now();
+        let expires = expires
+            .and_then(|x| chrono::Duration::from_std(x).ok())
+            .map(|x| now + x);
+
+        match *self.backend {
+            HttpSessionBackend::Memory(ref sessions) => {
+                let mut sessions = sessions.lock().await;
+                let created = sessions.get(&id).map(|s| s.info.created).unwrap_or(now);
+                sessions.insert(
+                    id.clone(),
+                    MemoryHttpSession {
+                        data,
+                        info: HttpSessionInfo {
+                            id,
+                            username: metadata.username,
+                            session_id: metadata.session_id,
+                            created,
+                            last_seen: now,
+                            expires,
+                        },
+                    },
+                );
+            }
+            HttpSessionBackend::Database(ref db) => {
+                let values = HttpSession::ActiveModel {
+                    id: Set(id),
+                    data: Set(data),
+                    username: Set(metadata.username),
+                    session_id: Set(metadata.session_id),
+                    created: Set(now),
+                    updated: Set(now),
+                    expires: Set(expires),
+                };
+                HttpSession::Entity::insert(values)
+                    .on_conflict(
+                        OnConflict::column(HttpSession::Column::Id)
+                            .update_columns([
+                                HttpSession::Column::Data,
+                                HttpSession::Column::Username,
+                                HttpSession::Column::SessionId,
+                                HttpSession::Column::Updated,
+                                HttpSession::Column::Expires,
+                            ])
+                            .to_owned(),
+                    )
+                    .exec(&*db.lock().await)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&self, cookie: &str) -> Result<(), WarpgateError> {
+        self.revoke(&storage_id(cookie)).await?;
+        Ok(())
+    }
+
+    /// The [HttpSessionInfo::id] of the session behind a cookie
+    pub fn id_for_cookie(cookie: &str) -> String {
+        storage_id(cookie)
+    }
+
+    pub async fn list(&self) -> Result<Vec<HttpSessionInfo>, WarpgateError> {
+        Ok(match *self.backend {
+            HttpSessionBackend::Memory(ref sessions) => {
+                let mut sessions: Vec<_> = sessions
+                    .lock()
+                    .await
+                    .values()
+                    .filter(|s| !is_expired(s.info.expires))
+                    .map(|s| s.info.clone())
+                    .collect();
+                sessions.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+                sessions
+            }
+            HttpSessionBackend::Database(ref db) => HttpSession::Entity::find()
+                .order_by_desc(HttpSession::Column::Updated)
+                .all(&*db.lock().await)
+                .await?
+                .into_iter()
+                .filter(|s| !is_expired(s.expires))
+                .map(Into::into)
+                .collect(),
+        })
+    }
+
+    /// Removes a session by its [HttpSessionInfo::id], logging its user out
+    pub async fn revoke(&self, id: &str) -> Result<Option<HttpSessionInfo>, WarpgateError> {
+        Ok(match *self.backend {
+            HttpSessionBackend::Memory(ref sessions) => {
+                sessions.lock().await.remove(id).map(|s| s.info)
+            }
+            HttpSessionBackend::Database(ref db) => {
+                let db = db.lock().await;
+                let session = HttpSession::Entity::find_by_id(id).one(&*db).await?;
+                if session.is_some() {
+                    HttpSession::Entity::delete_by_id(id).exec(&*db).await?;
+                }
+                session.map(Into::into)
+            }
+        })
+    }
+
+    pub async fn vacuum(&self) -> Result<(), WarpgateError> {
+        match *self.backend {
+            HttpSessionBackend::Memory(ref sessions) => {
+                sessions
+                    .lock()
+                    .await
+                    .retain(|_, s| !is_expired(s.info.expires));
+            }
+            HttpSessionBackend::Database(ref db) => {
+                HttpSession::Entity::delete_many()
+                    .filter(HttpSession::Column::Expires.lte(Utc::now()))
+                    .exec(&*db.lock().await)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cipher_roundtrip() {
+        let cipher = HttpSessionCipher::new(b"secret");
+        let entries = BTreeMap::from([("auth".to_owned(), Value::from("user"))]);
+        let data = cipher.encrypt(&entries).unwrap();
+        assert!(!data.contains("user"));
+        assert_eq!(cipher.decrypt(&data), Some(entries));
+        assert_eq!(HttpSessionCipher::new(b"other").decrypt(&data), None);
+        assert_eq!(cipher.decrypt("AAAA"), None);
+    }
+}
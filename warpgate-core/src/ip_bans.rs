@@ -0,0 +1,164 @@
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tokio::sync::Mutex;
+use tracing::*;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::IpBan::{self, IpBanAction};
+
+struct Rule {
+    network: IpNet,
+    action: IpBanAction,
+    expires: Option<DateTime<Utc>>,
+}
+
+/// Deny and allow lists of source addresses, checked by every protocol
+/// server as it accepts a connection. Served from memory - call
+/// [IpBanList::reload] after changing the `ip_bans` table.
+pub struct IpBanList {
+    db: Arc<Mutex<DatabaseConnection>>,
+    rules: RwLock<Vec<Rule>>,
+}
+
+impl IpBanList {
+    pub async fn new(db: Arc<Mutex<DatabaseConnection>>) -> Result<Self, WarpgateError> {
+        let this = Self {
+            db,
+            rules: RwLock::new(vec![]),
+        };
+        this.reload().await?;
+        Ok(this)
+    }
+
+    pub async fn reload(&self) -> Result<(), WarpgateError> {
+        let entries = IpBan::Entity::find()
+            .filter(
+                IpBan::Column::Expires
+                    .is_null()
+                    .or(IpBan::Column::Expires.gt(Utc::now())),
+            )
+            .all(&*self.db.lock().await)
+            .await?;
+        let rules = entries
+            .into_iter()
+            .filter_map(|entry| match parse_network(&entry.network) {
+                Some(network) => Some(Rule {
+                    network,
+                    action: entry.action,
+                    expires: entry.expires,
+                }),
+                None => {
+                    warn!(network=%entry.network, "Ignoring an IP ban with an invalid network");
+                    None
+                }
+            })
+            .collect();
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = rules;
+        Ok(())
+    }
+
+    /// Deny entries win over allow entries
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        check(&rules, ip, Utc::now())
+    }
+
+    /// Drops expired entries
+    pub async fn cleanup(&self) -> Result<(), WarpgateError> {
+        IpBan::Entity::delete_many()
+            .filter(IpBan::Column::Expires.lt(Utc::now()))
+            .exec(&*self.db.lock().await)
+            .await?;
+        self.reload().await
+    }
+}
+
+/// Accepts a CIDR network or a single address, with host bits cleared
+pub fn parse_network(value: &str) -> Option<IpNet> {
+    let value = value.trim();
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .ok()
+        .map(|network| network.trunc())
+}
+
+fn check(rules: &[Rule], ip: IpAddr, now: DateTime<Utc>) -> bool {
+    let ip = ip.to_canonical();
+    let mut has_allow_rules = false;
+    let mut allowed = false;
+    for rule in rules.iter().filter(|x| x.expires.is_none_or(|e| e > now)) {
+        let matches = rule.network.contains(&ip);
+        match rule.action {
+            IpBanAction::Deny if matches => return false,
+            IpBanAction::Deny => (),
+            IpBanAction::Allow => {
+                has_allow_rules = true;
+                allowed |= matches;
+            }
+        }
+    }
+    !has_allow_rules || allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(network: &str, action: IpBanAction, expires: Option<DateTime<Utc>>) -> Rule {
+        Rule {
+            network: parse_network(network).unwrap(),
+            action,
+            expires,
+        }
+    }
+
+    #[test]
+    fn test_parse_network() {
+        assert_eq!(
+            parse_network(" 192.0.2.7 ").map(|x| x.to_string()),
+            Some("192.0.2.7/32".into())
+        );
+        assert_eq!(
+            parse_network("10.1.2.3/8").map(|x| x.to_string()),
+            Some("10.0.0.0/8".into())
+        );
+        assert_eq!(
+            parse_network("2001:db8::1/32").map(|x| x.to_string()),
+            Some("2001:db8::/32".into())
+        );
+        assert!(parse_network("example.com").is_none());
+    }
+
+    #[test]
+    fn test_check() {
+        let now = Utc::now();
+        let ip = |x: &str| x.parse::<IpAddr>().unwrap();
+
+        assert!(check(&[], ip("192.0.2.1"), now));
+
+        let rules = [
+            rule("192.0.2.0/24", IpBanAction::Deny, None),
+            rule(
+                "198.51.100.0/24",
+                IpBanAction::Deny,
+                Some(now - chrono::Duration::minutes(1)),
+            ),
+        ];
+        assert!(!check(&rules, ip("192.0.2.1"), now));
+        assert!(!check(&rules, ip("::ffff:192.0.2.1"), now));
+        // Expired
+        assert!(check(&rules, ip("198.51.100.1"), now));
+
+        let rules = [
+            rule("10.0.0.0/8", IpBanAction::Allow, None),
+            rule("10.0.0.13", IpBanAction::Deny, None),
+        ];
+        assert!(check(&rules, ip("10.1.1.1"), now));
+        assert!(!check(&rules, ip("10.0.0.13"), now));
+        assert!(!check(&rules, ip("192.0.2.1"), now));
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt::Display;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::*;
+use uuid::Uuid;
+
+/// Finished jobs of each kind that are kept around for the admin UI
+const FINISHED_JOBS_PER_KIND: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct JobInfo {
+    pub id: Uuid,
+    /// e.g. `target_discovery`
+    pub kind: String,
+    pub description: String,
+    pub status: JobStatus,
+    pub cancel_requested: bool,
+    pub progress_done: u64,
+    /// Unknown until the job has figured out how much work there is
+    pub progress_total: Option<u64>,
+    pub error: Option<String>,
+    pub started: DateTime<Utc>,
+    pub finished: Option<DateTime<Utc>>,
+}
+
+struct Job {
+    info: JobInfo,
+    cancel: watch::Sender<bool>,
+}
+
+/// Long-running background work like discovery scans and imports,
+/// which admins can follow and cancel through the `/jobs` API
+#[derive(Default)]
+pub struct Jobs {
+    jobs: Mutex<Vec<Job>>,
+}
+
+impl Jobs {
+    /// Registers a running job. The caller does the work
+    /// and reports back through the handle.
+    pub fn start(self: &Arc<Self>, kind: &str, description: impl Into<String>) -> JobHandle {
+        let (cancel, cancel_rx) = watch::channel(false);
+        let info = JobInfo {
+            id: Uuid::new_v4(),
+            kind: kind.to_owned(),
+            description: description.into(),
+            status: JobStatus::Running,
+            cancel_requested: false,
+            progress_done: 0,
+            progress_total: None,
+            error: None,
+            started: Utc::now(),
+            finished: None,
+        };
+        let id = info.id;
+        debug!(job=%id, %kind, "Job started");
+        self.jobs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Job { info, cancel });
+        JobHandle {
+            jobs: self.clone(),
+            id,
+            cancel: cancel_rx,
+            finished: false,
+        }
+    }
+
+    /// Newest first
+    pub fn list(&self) -> Vec<JobInfo> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter().rev().map(|x| x.info.clone()).collect()
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<JobInfo> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter()
+            .find(|x| x.info.id == id)
+            .map(|x| x.info.clone())
+    }
+
+    /// Asks a running job to stop. Returns `false` if there's no such job
+    /// or it has already finished.
+    pub fn cancel(&self, id: Uuid) -> bool {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(job) = jobs
+            .iter_mut()
+            .find(|x| x.info.id == id && x.info.status == JobStatus::Running)
+        else {
+            return false;
+        };
+        info!(job=%id, kind=%job.info.kind, "Job cancellation requested");
+        job.info.cancel_requested = true;
+        let _ = job.cancel.send(true);
+        true
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut JobInfo)) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(job) = jobs.iter_mut().find(|x| x.info.id == id) {
+            f(&mut job.info);
+        }
+    }
+
+    fn finish(&self, id: Uuid, status: JobStatus, error: Option<String>) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(job) = jobs.iter_mut().find(|x| x.info.id == id) else {
+            return;
+        };
+        job.info.status = status;
+        job.info.error = error;
+        job.info.finished = Some(Utc::now());
+        debug!(job=%id, kind=%job.info.kind, ?status, "Job finished");
+
+        let kind = job.info.kind.clone();
+        let mut finished = jobs
+            .iter()
+            .filter(|x| x.info.kind == kind && x.info.status != JobStatus::Running)
+            .count();
+        jobs.retain(|x| {
+            if finished > FINISHED_JOBS_PER_KIND
+                && x.info.kind == kind
+                && x.info.status != JobStatus::Running
+            {
+                finished -= 1;
+                return false;
+            }
+            true
+        });
+    }
+}
+
+/// Held by the code doing the work. A job that is dropped without
+/// calling [JobHandle::finish] is recorded as failed.
+pub struct JobHandle {
+    jobs: Arc<Jobs>,
+    id: Uuid,
+    cancel: watch::Receiver<bool>,
+    finished: bool,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn set_progress(&self, done: u64, total: Option<u64>) {
+        self.jobs.update(self.id, |info| {
+            info.progress_done = done;
+            info.progress_total = total;
+        });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancel.borrow()
+    }
+
+    /// Runs `future` unless the job gets cancelled first,
+    /// in which case it's dropped and [None] is returned
+    pub async fn run_until_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+        let mut cancel = self.cancel.clone();
+        tokio::select! {
+            output = future => Some(output),
+            _ = cancel.wait_for(|x| *x) => None,
+        }
+    }
+
+    /// A cancelled job is recorded as such, whatever the result
+    pub fn finish<T, E: Display>(mut self, result: &Result<T, E>) {
+        self.finished = true;
+        let (status, error) = match result {
+            _ if self.is_cancelled() => (JobStatus::Cancelled, None),
+            Ok(_) => (JobStatus::Succeeded, None),
+            Err(error) => (JobStatus::Failed, Some(error.to_string())),
+        };
+        self.jobs.finish(self.id, status, error);
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        if !self.finished {
+            let status = if self.is_cancelled() {
+                JobStatus::Cancelled
+            } else {
+                JobStatus::Failed
+            };
+            self.jobs
+                .finish(self.id, status, Some("Interrupted".to_owned()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_job_lifecycle() {
+        let jobs = Arc::new(Jobs::default());
+        let job = jobs.start("test", "Test job");
+        job.set_progress(1, Some(2));
+        let info = jobs.get(job.id()).unwrap();
+        assert_eq!(info.status, JobStatus::Running);
+        assert_eq!(info.progress_total, Some(2));
+
+        let id = job.id();
+        job.finish(&Err::<(), _>("oops"));
+        let info = jobs.get(id).unwrap();
+        assert_eq!(info.status, JobStatus::Failed);
+        assert_eq!(info.error.as_deref(), Some("oops"));
+        assert!(!jobs.cancel(id));
+    }
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        let jobs = Arc::new(Jobs::default());
+        let job = jobs.start("test", "Test job");
+        assert!(jobs.cancel(job.id()));
+        assert!(job.is_cancelled());
+        assert_eq!(
+            job.run_until_cancelled(std::future::pending::<()>()).await,
+            None
+        );
+        let id = job.id();
+        job.finish(&Ok::<(), String>(()));
+        assert_eq!(jobs.get(id).unwrap().status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_retention() {
+        let jobs = Arc::new(Jobs::default());
+        let running = jobs.start("test", "Still running");
+        for _ in 0..FINISHED_JOBS_PER_KIND + 5 {
+            jobs.start("test", "Done").finish(&Ok::<(), String>(()));
+        }
+        jobs.start("other", "Done").finish(&Ok::<(), String>(()));
+        let list = jobs.list();
+        assert_eq!(list.len(), FINISHED_JOBS_PER_KIND + 2);
+        assert!(list.iter().any(|x| x.id == running.id()));
+        assert_eq!(list.iter().filter(|x| x.kind == "other").count(), 1);
+    }
+}
@@ -2,7 +2,7 @@ pub mod consts;
 mod data;
 mod state;
 pub use data::*;
-pub use state::{SessionState, SessionStateInit, State};
+pub use state::{SessionActivity, SessionState, SessionStateInit, State, TargetSession};
 mod config_providers;
 pub use config_providers::*;
 pub mod db;
@@ -13,4 +13,80 @@ mod services;
 pub use services::*;
 mod auth_state_store;
 pub use auth_state_store::*;
+mod authorization_cache;
+pub use authorization_cache::*;
+mod shared_config;
+pub use shared_config::*;
+mod analytics;
 pub mod logging;
+pub use analytics::*;
+mod analytics_sink;
+pub use analytics_sink::*;
+mod reaper;
+pub use reaper::*;
+mod discovery;
+pub use discovery::*;
+mod drop_box;
+pub use drop_box::*;
+mod session_sharing;
+pub use session_sharing::*;
+mod session_channels;
+pub use session_channels::*;
+mod session_tap;
+pub use session_tap::*;
+mod shadow;
+pub use shadow::*;
+mod work_items;
+pub use work_items::*;
+mod http_sessions;
+pub use http_sessions::*;
+mod refresh_tokens;
+pub use refresh_tokens::*;
+mod account_lifecycle;
+pub use account_lifecycle::*;
+mod config_check;
+pub use config_check::*;
+mod target_address;
+pub use target_address::*;
+mod config_history;
+pub use config_history::*;
+mod policy_simulation;
+pub use policy_simulation::*;
+mod target_health;
+pub use target_health::*;
+mod target_monitor;
+pub use target_monitor::*;
+mod session_bundle;
+pub use session_bundle::*;
+mod session_idle;
+pub use session_idle::*;
+mod target_fingerprints;
+pub use target_fingerprints::*;
+mod alerts;
+pub use alerts::*;
+mod tripwire;
+pub use tripwire::*;
+mod http_client;
+pub use http_client::*;
+mod search_index;
+pub use search_index::*;
+mod auth_failures;
+pub use auth_failures::*;
+mod auth_rate_limit;
+pub use auth_rate_limit::*;
+mod ip_bans;
+pub use ip_bans::*;
+mod dns;
+pub use dns::*;
+mod tickets;
+pub use tickets::*;
+mod target_capabilities;
+pub use target_capabilities::*;
+mod jobs;
+pub use jobs::*;
+mod notifications;
+pub use notifications::*;
+mod portal;
+pub use portal::*;
+mod direct_credentials;
+pub use direct_credentials::*;
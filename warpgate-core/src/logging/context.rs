@@ -0,0 +1,93 @@
+use std::net::IpAddr;
+
+use tracing::field::{display, Empty};
+use tracing::{info_span, Span};
+use warpgate_common::{ProtocolName, SessionId};
+
+/// A session's tracing span, carrying the same fields for every protocol:
+/// `session`, `protocol`, `client_ip`, `session_username` and
+/// `session_target`. The last two are filled in once known.
+#[derive(Clone, Debug)]
+pub struct SessionContext {
+    id: SessionId,
+    protocol: ProtocolName,
+    span: Span,
+}
+
+impl SessionContext {
+    pub fn new(id: SessionId, protocol: ProtocolName, client_ip: Option<IpAddr>) -> Self {
+        let span = info_span!(
+            parent: None,
+            "session",
+            session = %id,
+            protocol,
+            client_ip = Empty,
+            session_username = Empty,
+            session_target = Empty,
+        );
+        if let Some(client_ip) = client_ip {
+            span.record("client_ip", display(client_ip));
+        }
+        Self { id, protocol, span }
+    }
+
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    pub fn protocol(&self) -> ProtocolName {
+        self.protocol
+    }
+
+    /// Instrument the session's work with this
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn set_username(&self, username: &str) {
+        self.span.record("session_username", username);
+    }
+
+    pub fn set_target(&self, target_name: &str) {
+        self.span.record("session_target", target_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing::info;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::logging::layer::ValuesLogLayer;
+
+    #[test]
+    fn test_fields_reach_log_entries() {
+        let entries = Arc::new(Mutex::new(vec![]));
+        let layer = ValuesLogLayer::new({
+            let entries = entries.clone();
+            move |values| entries.lock().unwrap().push(values.into_values())
+        });
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let id = SessionId::new_v4();
+            let context = SessionContext::new(id, "ssh", "10.0.0.1".parse().ok());
+            context.span().in_scope(|| info!("Connected"));
+            context.set_username("alice");
+            context.set_target("db");
+            context.span().in_scope(|| info!("Authenticated"));
+
+            let entries = entries.lock().unwrap();
+            assert_eq!(entries.len(), 2);
+            assert_eq!(entries[0]["session"], id.to_string());
+            assert_eq!(entries[0]["protocol"], "ssh");
+            assert_eq!(entries[0]["client_ip"], "10.0.0.1");
+            assert!(!entries[0].contains_key("session_username"));
+            assert_eq!(entries[1]["session_username"], "alice");
+            assert_eq!(entries[1]["session_target"], "db");
+        });
+    }
+}
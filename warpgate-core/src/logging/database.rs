@@ -12,9 +12,10 @@ use warpgate_db_entities::LogEntry;
 
 use super::layer::ValuesLogLayer;
 use super::values::SerializedRecordValues;
+use crate::analytics::{COMMAND_LOG_KEYS, COMMAND_LOG_MESSAGES};
+use crate::{AnalyticsRecord, AnalyticsSinkHandle, QueryLogRecord};
 
-static LOG_SENDER: OnceCell<tokio::sync::broadcast::Sender<LogEntry::ActiveModel>> =
-    OnceCell::new();
+static LOG_SENDER: OnceCell<tokio::sync::broadcast::Sender<LogEntry::Model>> = OnceCell::new();
 
 pub fn make_database_logger_layer<S>() -> impl Layer<S>
 where
@@ -30,7 +31,12 @@ where
     })
 }
 
-pub fn install_database_logger(database: Arc<Mutex<DatabaseConnection>>) {
+/// Query and command logs go to the analytics sink instead of the database
+/// if one is configured
+pub fn install_database_logger(
+    database: Arc<Mutex<DatabaseConnection>>,
+    analytics_sink: Option<AnalyticsSinkHandle>,
+) {
     tokio::spawn(async move {
         #[allow(clippy::expect_used)]
         let mut receiver = LOG_SENDER
@@ -41,7 +47,14 @@ pub fn install_database_logger(database: Arc<Mutex<DatabaseConnection>>) {
             match receiver.recv().await {
                 Err(_) => break,
                 Ok(log_entry) => {
+                    if let Some(ref sink) = analytics_sink {
+                        if let Some(record) = query_log_record(&log_entry) {
+                            sink.record(AnalyticsRecord::QueryLog(record));
+                            continue;
+                        }
+                    }
                     let database = database.lock().await;
+                    let log_entry: LogEntry::ActiveModel = log_entry.into();
                     if let Err(error) = log_entry.insert(&*database).await {
                         error!(?error, "Failed to store log entry");
                     }
@@ -51,24 +64,39 @@ pub fn install_database_logger(database: Arc<Mutex<DatabaseConnection>>) {
     });
 }
 
-fn values_to_log_entry_data(mut values: SerializedRecordValues) -> Option<LogEntry::ActiveModel> {
+fn query_log_record(entry: &LogEntry::Model) -> Option<QueryLogRecord> {
+    if !COMMAND_LOG_MESSAGES.contains(&entry.text.as_str()) {
+        return None;
+    }
+    let query = COMMAND_LOG_KEYS
+        .iter()
+        .find_map(|k| entry.values.get(k).and_then(|v| v.as_str()))?;
+    Some(QueryLogRecord {
+        time: entry.timestamp,
+        session_id: entry.session_id,
+        username: entry.username.clone(),
+        kind: entry.text.clone(),
+        query: query.to_owned(),
+    })
+}
+
+fn values_to_log_entry_data(mut values: SerializedRecordValues) -> Option<LogEntry::Model> {
     let session_id = (*values).remove("session");
     let username = (*values).remove("session_username");
     let message = (*values).remove("message").unwrap_or_default();
 
-    use sea_orm::ActiveValue::Set;
     let session_id = session_id.and_then(|x| Uuid::parse_str(&x).ok())?;
 
-    Some(LogEntry::ActiveModel {
-        id: Set(Uuid::new_v4()),
-        text: Set(message),
-        values: Set(values
+    Some(LogEntry::Model {
+        id: Uuid::new_v4(),
+        text: message,
+        values: values
             .into_values()
             .into_iter()
             .map(|(k, v)| (k, JsonValue::from(v)))
-            .collect()),
-        session_id: Set(session_id),
-        username: Set(username),
-        timestamp: Set(chrono::Utc::now()),
+            .collect(),
+        session_id,
+        username,
+        timestamp: chrono::Utc::now(),
     })
 }
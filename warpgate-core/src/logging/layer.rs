@@ -42,6 +42,19 @@ where
         span.extensions_mut().replace(values);
     }
 
+    fn on_record(
+        &self,
+        id: &tracing_core::span::Id,
+        values: &tracing_core::span::Record<'_>,
+        ctx: Context<'_, S>,
+    ) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut extensions = span.extensions_mut();
+        if let Some(existing) = extensions.get_mut::<SerializedRecordValues>() {
+            values.record(&mut RecordVisitor::new(existing));
+        }
+    }
+
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
         if !event.metadata().target().starts_with("warpgate") {
             return;
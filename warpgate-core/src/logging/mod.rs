@@ -1,7 +1,9 @@
+mod context;
 mod layer;
 mod socket;
 mod values;
 
+pub use context::SessionContext;
 pub use socket::make_socket_logger_layer;
 mod database;
 pub use database::{install_database_logger, make_database_logger_layer};
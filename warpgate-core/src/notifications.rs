@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::eventhub::EventSubscription;
+use warpgate_common::{TlsCertificateBundle, WarpgateConfig, WarpgateError};
+use warpgate_db_entities::Notification::NotificationSeverity;
+use warpgate_db_entities::{Notification, NotificationRead};
+
+use crate::{Alert, AlertSeverity, ConfigChangeAuthor, Services, TargetHealth};
+
+/// How often the listener certificates are checked for expiry
+pub const CERTIFICATE_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Certificates that expire sooner than this raise a notification
+const CERTIFICATE_EXPIRY_WARNING: chrono::Duration = chrono::Duration::days(14);
+
+const KIND_CERTIFICATE_EXPIRING: &str = "certificate_expiring";
+const KIND_TARGET_UNREACHABLE: &str = "target_unreachable";
+
+#[derive(Debug, Clone)]
+pub struct NewNotification {
+    pub kind: String,
+    pub severity: NotificationSeverity,
+    pub message: String,
+    pub target: Option<String>,
+    /// See [Notification::Model::key]
+    pub key: Option<String>,
+}
+
+impl From<Alert> for NewNotification {
+    fn from(alert: Alert) -> Self {
+        Self {
+            kind: alert.kind,
+            severity: match alert.severity {
+                AlertSeverity::Info => NotificationSeverity::Info,
+                AlertSeverity::Warning => NotificationSeverity::Warning,
+                AlertSeverity::Critical => NotificationSeverity::Critical,
+            },
+            message: alert.message,
+            target: alert.target,
+            key: None,
+        }
+    }
+}
+
+/// Read state is tracked per admin username, with the
+/// admin token counting as a separate reader
+pub fn notification_reader(author: &ConfigChangeAuthor) -> &str {
+    author.0.as_deref().unwrap_or_default()
+}
+
+/// Actionable items for admins, with per-admin read state
+pub struct Notifications {
+    db: Arc<Mutex<DatabaseConnection>>,
+}
+
+impl Notifications {
+    pub fn new(db: Arc<Mutex<DatabaseConnection>>) -> Self {
+        Self { db }
+    }
+
+    /// Does nothing if there's already an unresolved notification with the same key
+    pub async fn raise(&self, notification: NewNotification) -> Result<(), WarpgateError> {
+        let db = self.db.lock().await;
+        if let Some(ref key) = notification.key {
+            let open = Notification::Entity::find()
+                .filter(Notification::Column::Key.eq(key))
+                .filter(Notification::Column::Resolved.is_null())
+                .count(&*db)
+                .await?;
+            if open > 0 {
+                return Ok(());
+            }
+        }
+        Notification::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            timestamp: Set(Utc::now()),
+            kind: Set(notification.kind),
+            severity: Set(notification.severity),
+            message: Set(notification.message),
+            target: Set(notification.target),
+            key: Set(notification.key),
+            resolved: Set(None),
+        }
+        .insert(&*db)
+        .await?;
+        Ok(())
+    }
+
+    /// Makes `active` the complete set of open conditions of this `kind`:
+    /// raises the new ones and resolves the ones that are gone
+    pub async fn sync(
+        &self,
+        kind: &str,
+        active: Vec<NewNotification>,
+    ) -> Result<(), WarpgateError> {
+        let active_keys = active
+            .iter()
+            .filter_map(|x| x.key.clone())
+            .collect::<HashSet<_>>();
+        {
+            let db = self.db.lock().await;
+            Notification::Entity::update_many()
+                .col_expr(Notification::Column::Resolved, Expr::value(Utc::now()))
+                .filter(Notification::Column::Kind.eq(kind))
+                .filter(Notification::Column::Resolved.is_null())
+                .filter(Notification::Column::Key.is_not_in(active_keys))
+                .exec(&*db)
+                .await?;
+        }
+        for notification in active {
+            self.raise(notification).await?;
+        }
+        Ok(())
+    }
+
+    /// Newest first, with whether `reader` has seen each one
+    pub async fn list(
+        &self,
+        reader: &str,
+        unread_only: bool,
+        limit: u64,
+    ) -> Result<Vec<(Notification::Model, bool)>, WarpgateError> {
+        let db = self.db.lock().await;
+        let read_ids = NotificationRead::Entity::find()
+            .filter(NotificationRead::Column::Username.eq(reader))
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|x| x.notification_id)
+            .collect::<HashSet<_>>();
+
+        let mut query = Notification::Entity::find()
+            .order_by_desc(Notification::Column::Timestamp)
+            .limit(limit);
+        if unread_only {
+            query = query.filter(Notification::Column::Id.is_not_in(read_ids.clone()));
+        }
+        Ok(query
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|x| {
+                let read = read_ids.contains(&x.id);
+                (x, read)
+            })
+            .collect())
+    }
+
+    pub async fn unread_count(&self, reader: &str) -> Result<u64, WarpgateError> {
+        let db = self.db.lock().await;
+        let read_ids = NotificationRead::Entity::find()
+            .filter(NotificationRead::Column::Username.eq(reader))
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|x| x.notification_id);
+        Ok(Notification::Entity::find()
+            .filter(Notification::Column::Id.is_not_in(read_ids))
+            .count(&*db)
+            .await?)
+    }
+
+    /// Marks the given notifications, or all of them, as read by `reader`
+    pub async fn mark_read(&self, reader: &str, ids: Option<&[Uuid]>) -> Result<(), WarpgateError> {
+        let db = self.db.lock().await;
+        let read_ids = NotificationRead::Entity::find()
+            .filter(NotificationRead::Column::Username.eq(reader))
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|x| x.notification_id)
+            .collect::<HashSet<_>>();
+
+        let mut query = Notification::Entity::find();
+        if let Some(ids) = ids {
+            query = query.filter(Notification::Column::Id.is_in(ids.iter().copied()));
+        }
+        let now = Utc::now();
+        for notification in query.all(&*db).await? {
+            if read_ids.contains(&notification.id) {
+                continue;
+            }
+            NotificationRead::ActiveModel {
+                notification_id: Set(notification.id),
+                username: Set(reader.to_owned()),
+                timestamp: Set(now),
+                ..Default::default()
+            }
+            .insert(&*db)
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn cleanup(&self, retention: Duration) -> Result<(), WarpgateError> {
+        let cutoff =
+            Utc::now() - chrono::Duration::from_std(retention).map_err(WarpgateError::other)?;
+        let db = self.db.lock().await;
+        let expired = Notification::Entity::find()
+            .filter(Notification::Column::Timestamp.lt(cutoff))
+            .filter(
+                Notification::Column::Key
+                    .is_null()
+                    .or(Notification::Column::Resolved.is_not_null()),
+            )
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        NotificationRead::Entity::delete_many()
+            .filter(NotificationRead::Column::NotificationId.is_in(expired.clone()))
+            .exec(&*db)
+            .await?;
+        Notification::Entity::delete_many()
+            .filter(Notification::Column::Id.is_in(expired))
+            .exec(&*db)
+            .await?;
+        Ok(())
+    }
+
+    /// Turns every alert into a notification
+    pub async fn collect_alerts(self: Arc<Self>, mut alerts: EventSubscription<Alert>) {
+        while let Some(alert) = alerts.recv().await {
+            if let Err(error) = self.raise(alert.into()).await {
+                error!(%error, "Failed to store the notification");
+            }
+        }
+    }
+}
+
+/// One notification per unreachable target, resolved once it's back
+pub async fn update_target_health_notifications(
+    services: &Services,
+    results: &[(String, TargetHealth)],
+) -> Result<(), WarpgateError> {
+    let unreachable = results
+        .iter()
+        .filter(|(_, health)| *health == TargetHealth::Unreachable)
+        .map(|(name, _)| NewNotification {
+            kind: KIND_TARGET_UNREACHABLE.into(),
+            severity: NotificationSeverity::Warning,
+            message: format!("Target {name} is unreachable"),
+            target: Some(name.clone()),
+            key: Some(format!("{KIND_TARGET_UNREACHABLE}:{name}")),
+        })
+        .collect();
+    services
+        .notifications
+        .sync(KIND_TARGET_UNREACHABLE, unreachable)
+        .await
+}
+
+/// Listener certificates of the enabled protocols
+fn listener_certificates(config: &WarpgateConfig) -> Vec<(&'static str, &String)> {
+    let store = &config.store;
+    [
+        ("http", store.http.enable, &store.http.certificate),
+        ("mysql", store.mysql.enable, &store.mysql.certificate),
+        (
+            "postgres",
+            store.postgres.enable,
+            &store.postgres.certificate,
+        ),
+        ("redis", store.redis.enable, &store.redis.certificate),
+    ]
+    .into_iter()
+    .filter(|(_, enabled, _)| *enabled)
+    .map(|(section, _, certificate)| (section, certificate))
+    .collect()
+}
+
+/// Raises a notification for each listener certificate that expires soon
+pub async fn check_certificate_expiry(services: &Services) -> Result<(), WarpgateError> {
+    let config = services.config.load();
+    let now = Utc::now();
+    let mut expiring = vec![];
+    for (section, certificate) in listener_certificates(&config) {
+        let path = config.paths_relative_to.join(certificate);
+        let bundle = match TlsCertificateBundle::from_file(&path).await {
+            Ok(bundle) => bundle,
+            Err(error) => {
+                warn!(?path, %error, "Could not read the certificate to check its expiry");
+                continue;
+            }
+        };
+        let Some(not_after) = bundle.not_after() else {
+            continue;
+        };
+        if not_after - now > CERTIFICATE_EXPIRY_WARNING {
+            continue;
+        }
+        let (severity, message) = if not_after <= now {
+            (
+                NotificationSeverity::Critical,
+                format!("The {section} certificate has expired on {not_after}"),
+            )
+        } else {
+            (
+                NotificationSeverity::Warning,
+                format!("The {section} certificate expires on {not_after}"),
+            )
+        };
+        expiring.push(NewNotification {
+            kind: KIND_CERTIFICATE_EXPIRING.into(),
+            severity,
+            message,
+            target: None,
+            // A renewed certificate that's about to expire again is a new condition
+            key: Some(format!("{KIND_CERTIFICATE_EXPIRING}:{section}:{not_after}")),
+        });
+    }
+    services
+        .notifications
+        .sync(KIND_CERTIFICATE_EXPIRING, expiring)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::Database;
+    use warpgate_db_migrations::migrate_database;
+
+    use super::*;
+
+    fn unreachable(name: &str) -> NewNotification {
+        NewNotification {
+            kind: KIND_TARGET_UNREACHABLE.into(),
+            severity: NotificationSeverity::Warning,
+            message: format!("Target {name} is unreachable"),
+            target: Some(name.into()),
+            key: Some(format!("{KIND_TARGET_UNREACHABLE}:{name}")),
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sync_and_read_state() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate_database(&db).await.unwrap();
+        let notifications = Notifications::new(Arc::new(Mutex::new(db)));
+
+        let kind = KIND_TARGET_UNREACHABLE;
+        notifications
+            .sync(kind, vec![unreachable("a"), unreachable("b")])
+            .await
+            .unwrap();
+        notifications
+            .sync(kind, vec![unreachable("a")])
+            .await
+            .unwrap();
+
+        let list = notifications.list("admin", false, 10).await.unwrap();
+        assert_eq!(list.len(), 2);
+        let open = list
+            .iter()
+            .filter(|(x, _)| x.resolved.is_none())
+            .map(|(x, _)| x.target.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(open, vec![Some("a")]);
+
+        let id = list[0].0.id;
+        notifications.mark_read("admin", Some(&[id])).await.unwrap();
+        assert_eq!(notifications.unread_count("admin").await.unwrap(), 1);
+        assert_eq!(notifications.unread_count("").await.unwrap(), 2);
+        let unread = notifications.list("admin", true, 10).await.unwrap();
+        assert_eq!(unread.len(), 1);
+        assert_ne!(unread[0].0.id, id);
+
+        notifications.mark_read("admin", None).await.unwrap();
+        assert_eq!(notifications.unread_count("admin").await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,567 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Utc;
+use poem_openapi::{Enum, Object, Union};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use serde::Serialize;
+use uuid::Uuid;
+use warpgate_common::auth::CredentialKind;
+use warpgate_common::{TargetOptions, UserRequireCredentialsPolicy, WarpgateError};
+use warpgate_db_entities::{
+    OtpCredential, PasswordCredential, PublicKeyCredential, Role, SsoCredential, Target,
+    TargetRoleAssignment, User, UserRoleAssignment,
+};
+
+#[derive(Debug, Clone, Object)]
+pub struct SimulatedUserRole {
+    pub user_id: Uuid,
+    pub role_id: Uuid,
+}
+
+#[derive(Debug, Clone, Object)]
+pub struct SimulatedTargetRole {
+    pub target_id: Uuid,
+    pub role_id: Uuid,
+}
+
+#[derive(Debug, Clone, Object)]
+pub struct SimulatedCredentialPolicy {
+    pub user_id: Uuid,
+    pub credential_policy: Option<UserRequireCredentialsPolicy>,
+}
+
+#[derive(Debug, Clone, Object)]
+pub struct SimulatedRoleDeletion {
+    pub role_id: Uuid,
+}
+
+/// A change that hasn't been applied yet
+#[derive(Debug, Clone, Union)]
+#[oai(discriminator_name = "kind", one_of)]
+pub enum PolicyChange {
+    #[oai(mapping = "add_user_role")]
+    AddUserRole(SimulatedUserRole),
+    #[oai(mapping = "remove_user_role")]
+    RemoveUserRole(SimulatedUserRole),
+    #[oai(mapping = "add_target_role")]
+    AddTargetRole(SimulatedTargetRole),
+    #[oai(mapping = "remove_target_role")]
+    RemoveTargetRole(SimulatedTargetRole),
+    #[oai(mapping = "set_credential_policy")]
+    SetCredentialPolicy(SimulatedCredentialPolicy),
+    #[oai(mapping = "delete_role")]
+    DeleteRole(SimulatedRoleDeletion),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum AccessLossReason {
+    /// The account is outside of its validity period
+    Inactive,
+    /// None of the user's roles grant access to the target
+    NoRole,
+    /// The user can't satisfy their credential policy for the target's protocol
+    Credentials,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct AccessChange {
+    pub user_id: Uuid,
+    pub username: String,
+    pub target_id: Uuid,
+    pub target_name: String,
+    /// Only set for lost access
+    pub reason: Option<AccessLossReason>,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct PolicySimulationReport {
+    pub gained: Vec<AccessChange>,
+    pub lost: Vec<AccessChange>,
+    /// Users that would no longer be able to access any target
+    pub locked_out_users: Vec<String>,
+    /// Nobody would be able to access the admin UI anymore
+    pub admin_access_lost: bool,
+}
+
+struct SimulatedUser {
+    id: Uuid,
+    username: String,
+    active: bool,
+    roles: HashSet<Uuid>,
+    credential_kinds: HashSet<CredentialKind>,
+    credential_policy: Option<UserRequireCredentialsPolicy>,
+}
+
+struct SimulatedTarget {
+    id: Uuid,
+    name: String,
+    protocol: &'static str,
+    is_admin: bool,
+    roles: HashSet<Uuid>,
+}
+
+/// Credential kinds each protocol's login accepts
+fn supported_credential_kinds(protocol: &str) -> &'static [CredentialKind] {
+    match protocol {
+        "SSH" => &[
+            CredentialKind::Password,
+            CredentialKind::PublicKey,
+            CredentialKind::Totp,
+            CredentialKind::WebUserApproval,
+        ],
+        "HTTP" => &[
+            CredentialKind::PublicKey,
+            CredentialKind::Password,
+            CredentialKind::Totp,
+            CredentialKind::Sso,
+            CredentialKind::WebUserApproval,
+        ],
+        _ => &[CredentialKind::Password],
+    }
+}
+
+impl SimulatedUser {
+    fn required_credential_kinds(&self, protocol: &str) -> Option<&Vec<CredentialKind>> {
+        let policy = self.credential_policy.as_ref()?;
+        match protocol {
+            "SSH" => policy.ssh.as_ref(),
+            "HTTP" => policy.http.as_ref(),
+            "MySQL" => policy.mysql.as_ref(),
+            "PostgreSQL" => policy.postgres.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Mirrors the policies built by the database config provider
+    fn can_authenticate(&self, protocol: &str) -> bool {
+        let supported = supported_credential_kinds(protocol);
+        let usable: HashSet<CredentialKind> = self
+            .credential_kinds
+            .iter()
+            .copied()
+            .filter(|x| supported.contains(x))
+            .collect();
+        match self.required_credential_kinds(protocol) {
+            None => !usable.is_empty(),
+            Some(required) => {
+                // Web approval isn't a stored credential, any user can give it
+                required.iter().all(|x| {
+                    usable.contains(x)
+                        || (*x == CredentialKind::WebUserApproval && supported.contains(x))
+                }) && !(required.is_empty() && usable.is_empty())
+            }
+        }
+    }
+}
+
+struct PolicyModel {
+    users: Vec<SimulatedUser>,
+    targets: Vec<SimulatedTarget>,
+}
+
+impl PolicyModel {
+    async fn load(db: &DatabaseConnection) -> Result<Self, WarpgateError> {
+        let now = Utc::now();
+
+        let mut user_roles: HashMap<Uuid, HashSet<Uuid>> = HashMap::new();
+        for assignment in UserRoleAssignment::Entity::find().all(db).await? {
+            if assignment.is_active(now) {
+                user_roles
+                    .entry(assignment.user_id)
+                    .or_default()
+                    .insert(assignment.role_id);
+            }
+        }
+
+        let mut credential_kinds: HashMap<Uuid, HashSet<CredentialKind>> = HashMap::new();
+        let mut add_credentials = |kind, user_ids: Vec<Uuid>| {
+            for user_id in user_ids {
+                credential_kinds.entry(user_id).or_default().insert(kind);
+            }
+        };
+        add_credentials(
+            CredentialKind::Password,
+            PasswordCredential::Entity::find()
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| x.user_id)
+                .collect(),
+        );
+        add_credentials(
+            CredentialKind::PublicKey,
+            PublicKeyCredential::Entity::find()
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| x.user_id)
+                .collect(),
+        );
+        add_credentials(
+            CredentialKind::Totp,
+            OtpCredential::Entity::find()
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| x.user_id)
+                .collect(),
+        );
+        add_credentials(
+            CredentialKind::Sso,
+            SsoCredential::Entity::find()
+                .all(db)
+                .await?
+                .into_iter()
+                .map(|x| x.user_id)
+                .collect(),
+        );
+
+        let users = User::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|user| {
+                Ok(SimulatedUser {
+                    id: user.id,
+                    active: user.is_active(now),
+                    roles: user_roles.remove(&user.id).unwrap_or_default(),
+                    credential_kinds: credential_kinds.remove(&user.id).unwrap_or_default(),
+                    credential_policy: serde_json::from_value(user.credential_policy)?,
+                    username: user.username,
+                })
+            })
+            .collect::<Result<Vec<_>, WarpgateError>>()?;
+
+        let mut target_roles: HashMap<Uuid, HashSet<Uuid>> = HashMap::new();
+        for assignment in TargetRoleAssignment::Entity::find().all(db).await? {
+            target_roles
+                .entry(assignment.target_id)
+                .or_default()
+                .insert(assignment.role_id);
+        }
+
+        let targets = Target::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|target| {
+                let options: TargetOptions = serde_json::from_value(target.options)?;
+                Ok(SimulatedTarget {
+                    id: target.id,
+                    name: target.name,
+                    protocol: options.protocol_name(),
+                    is_admin: matches!(options, TargetOptions::WebAdmin(_)),
+                    roles: target_roles.remove(&target.id).unwrap_or_default(),
+                })
+            })
+            .collect::<Result<Vec<_>, WarpgateError>>()?;
+
+        Ok(Self { users, targets })
+    }
+
+    fn user_mut(&mut self, id: Uuid) -> Result<&mut SimulatedUser, String> {
+        self.users
+            .iter_mut()
+            .find(|x| x.id == id)
+            .ok_or_else(|| format!("user {id} not found"))
+    }
+
+    fn target_mut(&mut self, id: Uuid) -> Result<&mut SimulatedTarget, String> {
+        self.targets
+            .iter_mut()
+            .find(|x| x.id == id)
+            .ok_or_else(|| format!("target {id} not found"))
+    }
+
+    fn apply(&mut self, change: &PolicyChange) -> Result<(), String> {
+        match change {
+            PolicyChange::AddUserRole(change) => {
+                self.user_mut(change.user_id)?.roles.insert(change.role_id);
+            }
+            PolicyChange::RemoveUserRole(change) => {
+                self.user_mut(change.user_id)?.roles.remove(&change.role_id);
+            }
+            PolicyChange::AddTargetRole(change) => {
+                self.target_mut(change.target_id)?
+                    .roles
+                    .insert(change.role_id);
+            }
+            PolicyChange::RemoveTargetRole(change) => {
+                self.target_mut(change.target_id)?
+                    .roles
+                    .remove(&change.role_id);
+            }
+            PolicyChange::SetCredentialPolicy(change) => {
+                self.user_mut(change.user_id)?.credential_policy = change.credential_policy.clone();
+            }
+            PolicyChange::DeleteRole(change) => {
+                for user in &mut self.users {
+                    user.roles.remove(&change.role_id);
+                }
+                for target in &mut self.targets {
+                    target.roles.remove(&change.role_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(user: &SimulatedUser, target: &SimulatedTarget) -> Result<(), AccessLossReason> {
+        if !user.active {
+            return Err(AccessLossReason::Inactive);
+        }
+        if user.roles.is_disjoint(&target.roles) {
+            return Err(AccessLossReason::NoRole);
+        }
+        if !user.can_authenticate(target.protocol) {
+            return Err(AccessLossReason::Credentials);
+        }
+        Ok(())
+    }
+
+    /// Result for every user and target, in the order of `users` x `targets`
+    fn access(&self) -> Vec<Result<(), AccessLossReason>> {
+        self.users
+            .iter()
+            .flat_map(|user| {
+                self.targets
+                    .iter()
+                    .map(move |target| Self::evaluate(user, target))
+            })
+            .collect()
+    }
+
+    fn has_admin_access(&self, access: &[Result<(), AccessLossReason>]) -> bool {
+        access
+            .iter()
+            .zip(self.targets.iter().cycle())
+            .any(|(result, target)| result.is_ok() && target.is_admin)
+    }
+}
+
+fn simulate(
+    mut model: PolicyModel,
+    changes: &[PolicyChange],
+) -> Result<PolicySimulationReport, String> {
+    let before = model.access();
+    let admin_access_before = model.has_admin_access(&before);
+    for change in changes {
+        model.apply(change)?;
+    }
+    let after = model.access();
+
+    let mut report = PolicySimulationReport {
+        gained: vec![],
+        lost: vec![],
+        locked_out_users: vec![],
+        admin_access_lost: admin_access_before && !model.has_admin_access(&after),
+    };
+    if model.targets.is_empty() {
+        return Ok(report);
+    }
+
+    for (user_index, user) in model.users.iter().enumerate() {
+        let range = user_index * model.targets.len()..(user_index + 1) * model.targets.len();
+        let (Some(before), Some(after)) = (before.get(range.clone()), after.get(range)) else {
+            continue;
+        };
+        for (target, (before, after)) in model.targets.iter().zip(before.iter().zip(after)) {
+            let change = |reason| AccessChange {
+                user_id: user.id,
+                username: user.username.clone(),
+                target_id: target.id,
+                target_name: target.name.clone(),
+                reason,
+            };
+            match (before, after) {
+                (Err(_), Ok(())) => report.gained.push(change(None)),
+                (Ok(()), Err(reason)) => report.lost.push(change(Some(*reason))),
+                _ => (),
+            }
+        }
+        if before.iter().any(Result::is_ok) && !after.iter().any(Result::is_ok) {
+            report.locked_out_users.push(user.username.clone());
+        }
+    }
+    Ok(report)
+}
+
+pub enum PolicySimulationResult {
+    Done(PolicySimulationReport),
+    /// A change refers to a user, target or role that doesn't exist
+    UnknownObject(String),
+}
+
+/// Reports which users would gain or lose access to which targets if the
+/// changes were applied, without applying them
+pub async fn simulate_policy_changes(
+    db: &DatabaseConnection,
+    changes: &[PolicyChange],
+) -> Result<PolicySimulationResult, WarpgateError> {
+    for change in changes {
+        let role_id = match change {
+            PolicyChange::AddUserRole(x) | PolicyChange::RemoveUserRole(x) => x.role_id,
+            PolicyChange::AddTargetRole(x) | PolicyChange::RemoveTargetRole(x) => x.role_id,
+            PolicyChange::DeleteRole(x) => x.role_id,
+            PolicyChange::SetCredentialPolicy(_) => continue,
+        };
+        if Role::Entity::find_by_id(role_id).one(db).await?.is_none() {
+            return Ok(PolicySimulationResult::UnknownObject(format!(
+                "role {role_id} not found"
+            )));
+        }
+    }
+
+    let model = PolicyModel::load(db).await?;
+    Ok(match simulate(model, changes) {
+        Ok(report) => PolicySimulationResult::Done(report),
+        Err(message) => PolicySimulationResult::UnknownObject(message),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str, roles: &[Uuid], credentials: &[CredentialKind]) -> SimulatedUser {
+        SimulatedUser {
+            id: Uuid::new_v4(),
+            username: username.into(),
+            active: true,
+            roles: roles.iter().copied().collect(),
+            credential_kinds: credentials.iter().copied().collect(),
+            credential_policy: None,
+        }
+    }
+
+    fn target(name: &str, protocol: &'static str, roles: &[Uuid]) -> SimulatedTarget {
+        SimulatedTarget {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            protocol,
+            is_admin: name == "admin",
+            roles: roles.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn test_role_changes() {
+        let admins = Uuid::new_v4();
+        let devs = Uuid::new_v4();
+        let model = PolicyModel {
+            users: vec![
+                user("alice", &[admins], &[CredentialKind::Password]),
+                user("bob", &[devs], &[CredentialKind::PublicKey]),
+            ],
+            targets: vec![
+                target("admin", "HTTP", &[admins]),
+                target("server", "SSH", &[devs]),
+            ],
+        };
+        let alice = model.users[0].id;
+        let bob = model.users[1].id;
+
+        let report = simulate(
+            model,
+            &[
+                PolicyChange::RemoveUserRole(SimulatedUserRole {
+                    user_id: alice,
+                    role_id: admins,
+                }),
+                PolicyChange::AddUserRole(SimulatedUserRole {
+                    user_id: bob,
+                    role_id: admins,
+                }),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(report.lost.len(), 1);
+        assert_eq!(report.lost[0].username, "alice");
+        assert_eq!(report.lost[0].reason, Some(AccessLossReason::NoRole));
+        assert_eq!(report.gained.len(), 1);
+        assert_eq!(report.gained[0].username, "bob");
+        assert_eq!(report.gained[0].target_name, "admin");
+        assert_eq!(report.locked_out_users, vec!["alice".to_string()]);
+        assert!(!report.admin_access_lost);
+    }
+
+    #[test]
+    fn test_admin_lockout() {
+        let admins = Uuid::new_v4();
+        let model = PolicyModel {
+            users: vec![user("alice", &[admins], &[CredentialKind::Password])],
+            targets: vec![target("admin", "HTTP", &[admins])],
+        };
+
+        let report = simulate(
+            model,
+            &[PolicyChange::DeleteRole(SimulatedRoleDeletion {
+                role_id: admins,
+            })],
+        )
+        .unwrap();
+        assert!(report.admin_access_lost);
+    }
+
+    #[test]
+    fn test_credential_policy() {
+        let role = Uuid::new_v4();
+        let model = PolicyModel {
+            users: vec![user("alice", &[role], &[CredentialKind::Password])],
+            targets: vec![
+                target("server", "SSH", &[role]),
+                target("db", "PostgreSQL", &[role]),
+            ],
+        };
+        let alice = model.users[0].id;
+
+        let report = simulate(
+            model,
+            &[PolicyChange::SetCredentialPolicy(
+                SimulatedCredentialPolicy {
+                    user_id: alice,
+                    credential_policy: Some(UserRequireCredentialsPolicy {
+                        ssh: Some(vec![CredentialKind::Password, CredentialKind::Totp]),
+                        ..Default::default()
+                    }),
+                },
+            )],
+        )
+        .unwrap();
+        assert_eq!(report.lost.len(), 1);
+        assert_eq!(report.lost[0].target_name, "server");
+        assert_eq!(report.lost[0].reason, Some(AccessLossReason::Credentials));
+        assert!(report.locked_out_users.is_empty());
+    }
+
+    #[test]
+    fn test_web_approval_is_always_available() {
+        let mut alice = user("alice", &[], &[CredentialKind::PublicKey]);
+        alice.credential_policy = Some(UserRequireCredentialsPolicy {
+            ssh: Some(vec![
+                CredentialKind::PublicKey,
+                CredentialKind::WebUserApproval,
+            ]),
+            ..Default::default()
+        });
+        assert!(alice.can_authenticate("SSH"));
+        assert!(!alice.can_authenticate("MySQL"));
+    }
+
+    #[test]
+    fn test_unknown_user() {
+        let model = PolicyModel {
+            users: vec![],
+            targets: vec![],
+        };
+        assert!(simulate(
+            model,
+            &[PolicyChange::RemoveUserRole(SimulatedUserRole {
+                user_id: Uuid::new_v4(),
+                role_id: Uuid::new_v4(),
+            })],
+        )
+        .is_err());
+    }
+}
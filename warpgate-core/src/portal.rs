@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::RolePortalSettings::{self, PortalSection};
+use warpgate_db_entities::{TargetRoleAssignment, User};
+
+/// What the user portal shows a particular user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortalLayout {
+    pub sections: Vec<PortalSection>,
+    /// Accessible, but left out of the target list
+    pub hidden_targets: HashSet<Uuid>,
+}
+
+impl Default for PortalLayout {
+    fn default() -> Self {
+        Self {
+            sections: vec![PortalSection::Targets],
+            hidden_targets: HashSet::new(),
+        }
+    }
+}
+
+/// Combines the portal settings of the user's active roles. Sections
+/// are shown in the order of the first role (by name) that lists them,
+/// and a target is only hidden if every role granting it hides it.
+pub async fn user_portal_layout(
+    db: &DatabaseConnection,
+    username: &str,
+) -> Result<PortalLayout, WarpgateError> {
+    let Some(user) = User::Entity::find()
+        .filter(User::Column::Username.eq(username))
+        .one(db)
+        .await?
+    else {
+        return Ok(PortalLayout::default());
+    };
+
+    let mut roles = user.find_active_roles(db).await?;
+    roles.sort_by(|a, b| a.name.cmp(&b.name));
+    let role_ids = roles.iter().map(|x| x.id).collect::<Vec<_>>();
+
+    let mut settings = RolePortalSettings::Entity::find()
+        .filter(RolePortalSettings::Column::RoleId.is_in(role_ids.clone()))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|x| (x.role_id, x))
+        .collect::<HashMap<_, _>>();
+    let settings = role_ids
+        .iter()
+        .filter_map(|id| settings.remove(id))
+        .collect::<Vec<_>>();
+
+    let mut grants: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for assignment in TargetRoleAssignment::Entity::find()
+        .filter(TargetRoleAssignment::Column::RoleId.is_in(role_ids))
+        .all(db)
+        .await?
+    {
+        grants
+            .entry(assignment.target_id)
+            .or_default()
+            .push(assignment.role_id);
+    }
+
+    Ok(merge_portal_settings(&settings, &grants))
+}
+
+/// `settings` is in role order, `grants` maps targets to the user's
+/// roles that grant access to them
+fn merge_portal_settings(
+    settings: &[RolePortalSettings::Model],
+    grants: &HashMap<Uuid, Vec<Uuid>>,
+) -> PortalLayout {
+    let mut sections = vec![];
+    for section in settings.iter().flat_map(|x| x.sections.0.iter()) {
+        if !sections.contains(section) {
+            sections.push(*section);
+        }
+    }
+    if sections.is_empty() {
+        sections = PortalLayout::default().sections;
+    }
+
+    let hides = |role_id: &Uuid, target_id: &Uuid| {
+        settings
+            .iter()
+            .any(|x| x.role_id == *role_id && x.hidden_targets.0.contains(target_id))
+    };
+    let hidden_targets = settings
+        .iter()
+        .flat_map(|x| x.hidden_targets.0.iter())
+        .filter(|target_id| {
+            grants
+                .get(target_id)
+                .is_none_or(|roles| roles.iter().all(|role_id| hides(role_id, target_id)))
+        })
+        .copied()
+        .collect();
+
+    PortalLayout {
+        sections,
+        hidden_targets,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_db_entities::RolePortalSettings::{PortalSectionList, TargetIdList};
+
+    use super::*;
+
+    fn role_settings(sections: Vec<PortalSection>, hidden: Vec<Uuid>) -> RolePortalSettings::Model {
+        RolePortalSettings::Model {
+            role_id: Uuid::new_v4(),
+            sections: PortalSectionList(sections),
+            hidden_targets: TargetIdList(hidden),
+        }
+    }
+
+    #[test]
+    fn test_merge_portal_settings() {
+        assert_eq!(
+            merge_portal_settings(&[], &HashMap::new()),
+            PortalLayout::default()
+        );
+
+        let noisy = Uuid::new_v4();
+        let shared = Uuid::new_v4();
+        let ops = role_settings(
+            vec![PortalSection::Tickets, PortalSection::Targets],
+            vec![noisy, shared],
+        );
+        let dev = role_settings(vec![PortalSection::Targets, PortalSection::DropBox], vec![]);
+        let grants = HashMap::from([
+            (noisy, vec![ops.role_id]),
+            (shared, vec![ops.role_id, dev.role_id]),
+        ]);
+
+        let layout = merge_portal_settings(&[ops, dev], &grants);
+        assert_eq!(
+            layout.sections,
+            vec![
+                PortalSection::Tickets,
+                PortalSection::Targets,
+                PortalSection::DropBox
+            ]
+        );
+        assert_eq!(layout.hidden_targets, HashSet::from([noisy]));
+    }
+}
@@ -1,35 +1,79 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use bytes::Bytes;
+use sea_orm::{
+    ActiveModelBehavior, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait,
+    IntoActiveModel, PrimaryKeyTrait, QueryFilter,
+};
 use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
 use warpgate_common::{SessionId, Target, WarpgateError};
-use warpgate_db_entities::Session;
+use warpgate_db_entities::Session::{self, SessionTerminationReason};
+use warpgate_db_entities::{DatabaseSessionDetails, HttpSessionDetails, SshSessionDetails};
 
-use crate::{SessionState, State};
+use crate::logging::SessionContext;
+use crate::recordings::RecordingQuotas;
+use crate::{SessionLastActivity, SessionState, State};
+
+/// How long a protocol gets to deliver [SessionHandle::close_with_message]
+/// before the session is dropped regardless
+pub const CLOSE_MESSAGE_TIMEOUT: Duration = Duration::from_secs(3);
 
 pub trait SessionHandle {
     fn close(&mut self);
+
+    /// Shows `message` to the user before closing the session,
+    /// where the protocol has a way to do so
+    fn close_with_message(&mut self, _message: String) {
+        self.close()
+    }
+
+    /// Types `data` into the terminal recorded as `recording_id`.
+    /// Returns `false` if the protocol has no terminals.
+    fn send_input(&mut self, _recording_id: Uuid, _data: Bytes) -> bool {
+        false
+    }
+
+    /// Adds a labeled marker to the session's terminal recordings
+    fn add_recording_marker(&mut self, _label: String) {}
+
+    /// Shows a message to the user.
+    /// Returns `false` if the protocol has no way to do so.
+    fn notify(&mut self, _message: String) -> bool {
+        false
+    }
 }
 
 pub struct WarpgateServerHandle {
     id: SessionId,
+    context: SessionContext,
     db: Arc<Mutex<DatabaseConnection>>,
     state: Arc<Mutex<State>>,
     session_state: Arc<Mutex<SessionState>>,
+    last_activity: SessionLastActivity,
+    recording_quotas: Arc<RecordingQuotas>,
 }
 
 impl WarpgateServerHandle {
     pub fn new(
         id: SessionId,
+        context: SessionContext,
         db: Arc<Mutex<DatabaseConnection>>,
         state: Arc<Mutex<State>>,
         session_state: Arc<Mutex<SessionState>>,
+        last_activity: SessionLastActivity,
+        recording_quotas: Arc<RecordingQuotas>,
     ) -> Self {
         WarpgateServerHandle {
             id,
+            context,
             db,
             state,
             session_state,
+            last_activity,
+            recording_quotas,
         }
     }
 
@@ -37,10 +81,30 @@ impl WarpgateServerHandle {
         self.id
     }
 
+    pub fn context(&self) -> &SessionContext {
+        &self.context
+    }
+
     pub fn session_state(&self) -> &Arc<Mutex<SessionState>> {
         &self.session_state
     }
 
+    /// Touch it whenever the client sends something
+    pub fn last_activity(&self) -> &SessionLastActivity {
+        &self.last_activity
+    }
+
+    pub async fn set_termination_reason(&self, reason: SessionTerminationReason) {
+        self.session_state
+            .lock()
+            .await
+            .set_termination_reason(reason);
+    }
+
+    pub async fn set_exit_code(&self, exit_code: i64) {
+        self.session_state.lock().await.exit_code = Some(exit_code);
+    }
+
     pub async fn set_username(&self, username: String) -> Result<(), WarpgateError> {
         use sea_orm::ActiveValue::Set;
 
@@ -49,6 +113,7 @@ impl WarpgateServerHandle {
             state.username = Some(username.clone());
             state.emit_change()
         }
+        self.context.set_username(&username);
 
         let db = self.db.lock().await;
 
@@ -64,13 +129,134 @@ impl WarpgateServerHandle {
         Ok(())
     }
 
+    pub async fn set_user_agent(&self, user_agent: Option<String>) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+
+        let db = self.db.lock().await;
+
+        Session::Entity::update_many()
+            .set(Session::ActiveModel {
+                user_agent: Set(user_agent),
+                ..Default::default()
+            })
+            .filter(Session::Column::Id.eq(self.id))
+            .exec(&*db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn record_consent(&self) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+
+        let db = self.db.lock().await;
+
+        Session::Entity::update_many()
+            .set(Session::ActiveModel {
+                consent_acknowledged: Set(Some(chrono::Utc::now())),
+                ..Default::default()
+            })
+            .filter(Session::Column::Id.eq(self.id))
+            .exec(&*db)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_work_item(&self, work_item: Option<String>) -> Result<(), WarpgateError> {
+        use sea_orm::ActiveValue::Set;
+
+        let db = self.db.lock().await;
+
+        Session::Entity::update_many()
+            .set(Session::ActiveModel {
+                work_item: Set(work_item),
+                ..Default::default()
+            })
+            .filter(Session::Column::Id.eq(self.id))
+            .exec(&*db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Only the fields that are set in `details` are updated
+    pub async fn update_ssh_details(
+        &self,
+        mut details: SshSessionDetails::ActiveModel,
+    ) -> Result<(), WarpgateError> {
+        details.session_id = sea_orm::ActiveValue::Set(self.id);
+        self.save_details(details).await
+    }
+
+    /// Only the fields that are set in `details` are updated
+    pub async fn update_http_details(
+        &self,
+        mut details: HttpSessionDetails::ActiveModel,
+    ) -> Result<(), WarpgateError> {
+        details.session_id = sea_orm::ActiveValue::Set(self.id);
+        self.save_details(details).await
+    }
+
+    /// Only the fields that are set in `details` are updated
+    pub async fn update_database_details(
+        &self,
+        mut details: DatabaseSessionDetails::ActiveModel,
+    ) -> Result<(), WarpgateError> {
+        details.session_id = sea_orm::ActiveValue::Set(self.id);
+        self.save_details(details).await
+    }
+
+    async fn save_details<A>(&self, details: A) -> Result<(), WarpgateError>
+    where
+        A: ActiveModelTrait + ActiveModelBehavior + Send,
+        <A::Entity as EntityTrait>::Model: IntoActiveModel<A>,
+        <<A::Entity as EntityTrait>::PrimaryKey as PrimaryKeyTrait>::ValueType: From<SessionId>,
+    {
+        let db = self.db.lock().await;
+        if A::Entity::find_by_id(self.id).one(&*db).await?.is_some() {
+            details.update(&*db).await?;
+        } else {
+            details.insert(&*db).await?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the session isn't connected to the target yet and the target
+    /// is draining or at its session limit, or its recording quota or the
+    /// user's is used up
     pub async fn set_target(&self, target: &Target) -> Result<(), WarpgateError> {
         use sea_orm::ActiveValue::Set;
+        let username = self.session_state.lock().await.username.clone();
+        {
+            // Session locks are never taken under the state lock, so this
+            // only holds up other connects and disconnects briefly
+            let mut global_state = self.state.lock().await;
+            if global_state.session_target(self.id) != Some(target.id) {
+                if target.draining {
+                    return Err(WarpgateError::TargetDraining(target.name.clone()));
+                }
+                if let Some(max) = target.max_concurrent_sessions {
+                    if global_state.count_target_sessions(target.id) >= max as usize {
+                        global_state.record_rejected_session(target.id);
+                        warn!(target=%target.name, max, "Rejecting session over the target's limit");
+                        return Err(WarpgateError::TargetSessionLimit(
+                            target.name.clone(),
+                            target.session_limit_message.clone(),
+                        ));
+                    }
+                }
+                self.recording_quotas
+                    .check_session(&target.name, username.as_deref())?;
+            }
+            global_state.set_session_target(self.id, target.id);
+        }
         {
             let mut state = self.session_state.lock().await;
             state.target = Some(target.clone());
             state.emit_change()
         }
+        self.context.set_target(&target.name);
 
         let db = self.db.lock().await;
 
@@ -1,9 +1,11 @@
 mod handle;
+mod registry;
 
 use std::future::Future;
 
 use anyhow::Result;
-pub use handle::{SessionHandle, WarpgateServerHandle};
+pub use handle::{SessionHandle, WarpgateServerHandle, CLOSE_MESSAGE_TIMEOUT};
+pub use registry::{DynProtocolServer, ProtocolRegistry, ProtocolServerFactory};
 use warpgate_common::{ListenEndpoint, Target};
 
 #[derive(Debug, thiserror::Error)]
@@ -12,6 +14,9 @@ pub enum TargetTestError {
     Unreachable,
     #[error("authentication failed")]
     AuthenticationError,
+    /// The target's host key or TLS certificate was rejected
+    #[error("untrusted: {0}")]
+    Untrusted(String),
     #[error("connection error: {0}")]
     ConnectionError(String),
     #[error("misconfigured: {0}")]
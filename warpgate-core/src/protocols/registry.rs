@@ -0,0 +1,186 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use warpgate_common::{
+    ListenEndpoint, RuntimeThreadsConfig, Target, TargetOptions, WarpgateConfigStore,
+};
+
+use super::{ProtocolServer, TargetTestError};
+use crate::Services;
+
+/// Object-safe counterpart of [ProtocolServer], implemented for every
+/// protocol server automatically
+pub trait DynProtocolServer: Send + Sync {
+    fn run(self: Box<Self>, address: ListenEndpoint) -> BoxFuture<'static, Result<()>>;
+    fn test_target(&self, target: Target) -> BoxFuture<'_, Result<(), TargetTestError>>;
+}
+
+impl<T: ProtocolServer + Send + Sync + 'static> DynProtocolServer for T {
+    fn run(self: Box<Self>, address: ListenEndpoint) -> BoxFuture<'static, Result<()>> {
+        ProtocolServer::run(*self, address).boxed()
+    }
+
+    fn test_target(&self, target: Target) -> BoxFuture<'_, Result<(), TargetTestError>> {
+        ProtocolServer::test_target(self, target).boxed()
+    }
+}
+
+/// Describes a protocol to the registry and creates its server
+#[async_trait]
+pub trait ProtocolServerFactory: Send + Sync {
+    /// Unique, e.g. `ssh` - also names the protocol's dedicated runtime
+    fn name(&self) -> &'static str;
+
+    /// `None` if the protocol is disabled in the config
+    fn listen_endpoint(&self, config: &WarpgateConfigStore) -> Option<ListenEndpoint>;
+
+    /// Threads for a dedicated runtime, or `None` to share the main one
+    fn runtime_config<'a>(
+        &self,
+        _config: &'a WarpgateConfigStore,
+    ) -> Option<&'a RuntimeThreadsConfig> {
+        None
+    }
+
+    /// Whether the server can test connections to this kind of target
+    fn supports_target(&self, options: &TargetOptions) -> bool;
+
+    async fn create(&self, services: &Services) -> Result<Box<dyn DynProtocolServer>>;
+}
+
+struct RegisteredProtocol {
+    factory: Arc<dyn ProtocolServerFactory>,
+    enabled: bool,
+}
+
+/// The protocol servers known to this build. A protocol has to be both
+/// enabled here and have a listen endpoint in the config to be started.
+#[derive(Default)]
+pub struct ProtocolRegistry {
+    protocols: Vec<RegisteredProtocol>,
+}
+
+impl ProtocolRegistry {
+    /// Replaces a protocol that was registered under the same name
+    pub fn register(&mut self, factory: Arc<dyn ProtocolServerFactory>) {
+        let registered = RegisteredProtocol {
+            factory,
+            enabled: true,
+        };
+        match self
+            .protocols
+            .iter_mut()
+            .find(|x| x.factory.name() == registered.factory.name())
+        {
+            Some(existing) => *existing = registered,
+            None => self.protocols.push(registered),
+        }
+    }
+
+    /// Returns `false` if there's no such protocol
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.protocols.iter_mut().find(|x| x.factory.name() == name) {
+            Some(protocol) => {
+                protocol.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.protocols.iter().map(|x| x.factory.name()).collect()
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Arc<dyn ProtocolServerFactory>> {
+        self.protocols
+            .iter()
+            .filter(|x| x.enabled)
+            .map(|x| &x.factory)
+    }
+
+    /// The protocols to start, in registration order
+    pub fn listeners(
+        &self,
+        config: &WarpgateConfigStore,
+    ) -> Vec<(Arc<dyn ProtocolServerFactory>, ListenEndpoint)> {
+        self.enabled()
+            .filter_map(|factory| Some((factory.clone(), factory.listen_endpoint(config)?)))
+            .collect()
+    }
+
+    /// The first enabled protocol that can test the target,
+    /// whether or not it's listening
+    pub fn for_target(&self, options: &TargetOptions) -> Option<Arc<dyn ProtocolServerFactory>> {
+        self.enabled()
+            .find(|factory| factory.supports_target(options))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::{TargetWebAdminOptions, WarpgateConfigStore};
+
+    use super::*;
+
+    struct TestProtocol(&'static str);
+
+    struct TestServer;
+
+    impl ProtocolServer for TestServer {
+        async fn run(self, _address: ListenEndpoint) -> Result<()> {
+            Ok(())
+        }
+
+        async fn test_target(&self, _target: Target) -> Result<(), TargetTestError> {
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl ProtocolServerFactory for TestProtocol {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn listen_endpoint(&self, _config: &WarpgateConfigStore) -> Option<ListenEndpoint> {
+            Some(ListenEndpoint::from(
+                "127.0.0.1:0".parse::<std::net::SocketAddr>().unwrap(),
+            ))
+        }
+
+        fn supports_target(&self, _options: &TargetOptions) -> bool {
+            true
+        }
+
+        async fn create(&self, _services: &Services) -> Result<Box<dyn DynProtocolServer>> {
+            Ok(Box::new(TestServer))
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let config = WarpgateConfigStore::default();
+        let target = TargetOptions::WebAdmin(TargetWebAdminOptions {});
+        let mut registry = ProtocolRegistry::default();
+        registry.register(Arc::new(TestProtocol("a")));
+        registry.register(Arc::new(TestProtocol("b")));
+        registry.register(Arc::new(TestProtocol("a")));
+        assert_eq!(registry.names(), vec!["a", "b"]);
+        assert_eq!(registry.listeners(&config).len(), 2);
+
+        assert!(registry.set_enabled("a", false));
+        assert!(!registry.set_enabled("c", false));
+        let listeners = registry.listeners(&config);
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].0.name(), "b");
+        assert_eq!(registry.for_target(&target).map(|x| x.name()), Some("b"));
+
+        registry.set_enabled("b", false);
+        assert!(registry.for_target(&target).is_none());
+    }
+}
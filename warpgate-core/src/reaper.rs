@@ -0,0 +1,397 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::{LogEntry, Recording, Session};
+
+use crate::recordings::{recording_path, remove_recording_file, SessionRecordings};
+use crate::State;
+
+/// Outcome of a single [SessionReaper] pass.
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct ReconciliationReport {
+    pub started: DateTime<Utc>,
+    pub finished: DateTime<Utc>,
+    /// Sessions that were still marked as active but have no live handle
+    pub closed_sessions: Vec<Uuid>,
+    /// Unfinished recordings belonging to sessions that are no longer live
+    pub closed_recordings: Vec<Uuid>,
+    /// Recordings whose file is gone from disk. These are only reported,
+    /// since the file might still be on its way, e.g. from replication.
+    pub missing_recording_files: Vec<Uuid>,
+    /// Recordings that point to a session which doesn't exist
+    pub orphan_recordings: Vec<Uuid>,
+    /// Files in the recordings directory with no matching recording
+    pub orphan_files: Vec<String>,
+}
+
+/// Files younger than this are never removed as orphans. Replication moves
+/// a recording's file into place before it adds the recording to the database.
+const ORPHAN_FILE_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Closes sessions left over from a crash or an unclean shutdown and brings
+/// the recordings table back in sync with the recordings directory.
+pub struct SessionReaper {
+    db: Arc<Mutex<DatabaseConnection>>,
+    state: Arc<Mutex<State>>,
+    recordings: Arc<Mutex<SessionRecordings>>,
+    last_report: Option<ReconciliationReport>,
+}
+
+impl SessionReaper {
+    pub fn new(
+        db: Arc<Mutex<DatabaseConnection>>,
+        state: Arc<Mutex<State>>,
+        recordings: Arc<Mutex<SessionRecordings>>,
+    ) -> Self {
+        Self {
+            db,
+            state,
+            recordings,
+            last_report: None,
+        }
+    }
+
+    pub fn last_report(&self) -> Option<&ReconciliationReport> {
+        self.last_report.as_ref()
+    }
+
+    pub async fn run(&mut self) -> Result<ReconciliationReport, WarpgateError> {
+        let started = Utc::now();
+
+        // Sessions are registered in the state before they're written to
+        // the database, so any session that was active in the database
+        // before the state snapshot and is missing from it is gone for good.
+        let active_sessions = {
+            let db = self.db.lock().await;
+            Session::Entity::find()
+                .filter(Session::Column::Ended.is_null())
+                .all(&*db)
+                .await?
+        };
+        let live: HashSet<Uuid> = self.state.lock().await.sessions.keys().copied().collect();
+
+        let mut report = ReconciliationReport {
+            started,
+            finished: started,
+            closed_sessions: vec![],
+            closed_recordings: vec![],
+            missing_recording_files: vec![],
+            orphan_recordings: vec![],
+            orphan_files: vec![],
+        };
+
+        for session in active_sessions {
+            if live.contains(&session.id) {
+                continue;
+            }
+            let db = self.db.lock().await;
+            let last_activity = LogEntry::Entity::find()
+                .filter(LogEntry::Column::SessionId.eq(session.id))
+                .order_by_desc(LogEntry::Column::Timestamp)
+                .one(&*db)
+                .await?
+                .map(|entry| entry.timestamp);
+            let ended = last_activity
+                .unwrap_or(session.started)
+                .max(session.started);
+
+            let id = session.id;
+            let mut model = session.into_active_model();
+            model.ended = sea_orm::Set(Some(ended));
+            model.update(&*db).await?;
+            info!(session=%id, %ended, "Closed stale session");
+            report.closed_sessions.push(id);
+        }
+
+        {
+            let db = self.db.lock().await;
+            let open_recordings = Recording::Entity::find()
+                .filter(Recording::Column::Ended.is_null())
+                .find_also_related(Session::Entity)
+                .all(&*db)
+                .await?;
+            for (recording, session) in open_recordings {
+                if live.contains(&recording.session_id) {
+                    continue;
+                }
+                let ended = session
+                    .and_then(|s| s.ended)
+                    .unwrap_or(started)
+                    .max(recording.started);
+                let id = recording.id;
+                let mut model = recording.into_active_model();
+                model.ended = sea_orm::Set(Some(ended));
+                model.update(&*db).await?;
+                info!(recording=%id, "Closed stale recording");
+                report.closed_recordings.push(id);
+            }
+        }
+
+        // The recordings lock is not held for the file system access below
+        let root = self.recordings.lock().await.root().to_owned();
+
+        // Only finished recordings are checked since the writer creates
+        // the file after the database row.
+        let finished_recordings = {
+            let db = self.db.lock().await;
+            Recording::Entity::find()
+                .filter(Recording::Column::Ended.is_not_null())
+                .all(&*db)
+                .await?
+        };
+        for recording in finished_recordings {
+            if live.contains(&recording.session_id) {
+                continue;
+            }
+            let path = recording_path(&root, &recording.session_id, &recording.name);
+            if tokio::fs::try_exists(&path).await? {
+                continue;
+            }
+            warn!(recording=%recording.id, ?path, "Recording file is missing");
+            report.missing_recording_files.push(recording.id);
+        }
+
+        let orphans = {
+            let db = self.db.lock().await;
+            Recording::Entity::find()
+                .left_join(Session::Entity)
+                .filter(Session::Column::Id.is_null())
+                .all(&*db)
+                .await?
+        };
+        for recording in orphans {
+            let db = self.db.lock().await;
+            Recording::Entity::delete_by_id(recording.id)
+                .exec(&*db)
+                .await?;
+            drop(db);
+            let path = recording_path(&root, &recording.session_id, &recording.name);
+            match remove_recording_file(&path).await {
+                Ok(()) => (),
+                Err(crate::recordings::Error::Io(e))
+                    if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => {
+                    warn!(recording=%recording.id, ?error, "Failed to remove recording file")
+                }
+            }
+            warn!(recording=%recording.id, session=%recording.session_id, "Removed recording without a session");
+            report.orphan_recordings.push(recording.id);
+        }
+
+        // Files are listed before the known recordings are loaded so that a
+        // recording started in between is never mistaken for an orphan.
+        let mut files = vec![];
+        let grace_cutoff = SystemTime::now() - ORPHAN_FILE_GRACE_PERIOD;
+        if tokio::fs::try_exists(&root).await? {
+            let mut dirs = tokio::fs::read_dir(&root).await?;
+            while let Some(dir) = dirs.next_entry().await? {
+                let Some(session_id) = dir
+                    .file_name()
+                    .to_str()
+                    .and_then(|name| Uuid::parse_str(name).ok())
+                else {
+                    continue;
+                };
+                if !dir.file_type().await?.is_dir() || live.contains(&session_id) {
+                    continue;
+                }
+                let mut entries = tokio::fs::read_dir(dir.path()).await?;
+                while let Some(entry) = entries.next_entry().await? {
+                    let Some(name) = entry.file_name().to_str().map(ToOwned::to_owned) else {
+                        continue;
+                    };
+                    if name.starts_with('.') {
+                        continue;
+                    }
+                    let modified = entry.metadata().await?.modified()?;
+                    if modified > grace_cutoff {
+                        continue;
+                    }
+                    files.push((session_id, name));
+                }
+            }
+        }
+
+        if !files.is_empty() {
+            let known: HashSet<(Uuid, String)> = {
+                let db = self.db.lock().await;
+                Recording::Entity::find()
+                    .select_only()
+                    .column(Recording::Column::SessionId)
+                    .column(Recording::Column::Name)
+                    .into_tuple::<(Uuid, String)>()
+                    .all(&*db)
+                    .await?
+                    .into_iter()
+                    .collect()
+            };
+            for (session_id, name) in files {
+                if known.contains(&(session_id, name.clone())) {
+                    continue;
+                }
+                let path = recording_path(&root, &session_id, &name);
+                if let Err(error) = remove_recording_file(&path).await {
+                    warn!(?path, ?error, "Failed to remove orphan recording file");
+                    continue;
+                }
+                warn!(?path, "Removed recording file without a recording");
+                report.orphan_files.push(path.display().to_string());
+            }
+        }
+
+        report.finished = Utc::now();
+        info!(
+            closed_sessions = report.closed_sessions.len(),
+            closed_recordings = report.closed_recordings.len(),
+            missing_recording_files = report.missing_recording_files.len(),
+            orphan_recordings = report.orphan_recordings.len(),
+            orphan_files = report.orphan_files.len(),
+            "Session reconciliation complete"
+        );
+        self.last_report = Some(report.clone());
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use sea_orm::{Database, Set};
+    use warpgate_common::WarpgateConfig;
+    use warpgate_db_entities::Recording::RecordingKind;
+    use warpgate_db_migrations::migrate_database;
+
+    use super::*;
+    use crate::recordings::RecordingQuotas;
+    use crate::{Alerts, SharedConfig};
+
+    struct Fixture {
+        reaper: SessionReaper,
+        db: Arc<Mutex<DatabaseConnection>>,
+        root: PathBuf,
+        session_id: Uuid,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(self.root.parent().unwrap_or(&self.root));
+        }
+    }
+
+    async fn fixture() -> Fixture {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate_database(&db).await.unwrap();
+        let db = Arc::new(Mutex::new(db));
+
+        let mut config = WarpgateConfig {
+            store: Default::default(),
+            paths_relative_to: std::env::temp_dir().join(format!("warpgate-{}", Uuid::new_v4())),
+        };
+        config.store.recordings.enable = true;
+        let shared_config = Arc::new(SharedConfig::new(config.clone()));
+        let quotas = Arc::new(RecordingQuotas::new(
+            shared_config.clone(),
+            Alerts::new(shared_config).await,
+        ));
+        let state = State::new(&db, None, quotas.clone());
+        let recordings = SessionRecordings::new(db.clone(), &config, quotas).unwrap();
+        let root = recordings.root().to_owned();
+
+        let session_id = Uuid::new_v4();
+        Session::ActiveModel {
+            id: Set(session_id),
+            remote_address: Set("127.0.0.1:1234".into()),
+            started: Set(Utc::now()),
+            ended: Set(Some(Utc::now())),
+            protocol: Set("SSH".into()),
+            ..Default::default()
+        }
+        .insert(&*db.lock().await)
+        .await
+        .unwrap();
+
+        Fixture {
+            reaper: SessionReaper::new(db.clone(), state, Arc::new(Mutex::new(recordings))),
+            db,
+            root,
+            session_id,
+        }
+    }
+
+    async fn add_recording(fixture: &Fixture, name: &str) -> Uuid {
+        let id = Uuid::new_v4();
+        Recording::ActiveModel {
+            id: Set(id),
+            name: Set(name.into()),
+            started: Set(Utc::now()),
+            ended: Set(Some(Utc::now())),
+            session_id: Set(fixture.session_id),
+            kind: Set(RecordingKind::Terminal),
+            replicated: Set(None),
+        }
+        .insert(&*fixture.db.lock().await)
+        .await
+        .unwrap();
+        id
+    }
+
+    fn write_file(fixture: &Fixture, name: &str, age: Duration) -> PathBuf {
+        let path = recording_path(&fixture.root, &fixture.session_id, name);
+        std::fs::create_dir_all(path.parent().unwrap_or(Path::new("."))).unwrap();
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        path
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_missing_files_are_only_reported() {
+        let mut fixture = fixture().await;
+        let present = add_recording(&fixture, "present").await;
+        write_file(&fixture, "present", Duration::ZERO);
+        let missing = add_recording(&fixture, "missing").await;
+
+        let report = fixture.reaper.run().await.unwrap();
+        assert_eq!(report.missing_recording_files, vec![missing]);
+
+        let db = fixture.db.lock().await;
+        for id in [present, missing] {
+            assert!(Recording::Entity::find_by_id(id)
+                .one(&*db)
+                .await
+                .unwrap()
+                .is_some());
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_orphan_files_are_removed_after_grace_period() {
+        let mut fixture = fixture().await;
+        add_recording(&fixture, "known").await;
+        let known = write_file(&fixture, "known", ORPHAN_FILE_GRACE_PERIOD * 2);
+        let old = write_file(&fixture, "old", ORPHAN_FILE_GRACE_PERIOD * 2);
+        // e.g. a replicated recording that isn't in the database yet
+        let recent = write_file(&fixture, "recent", Duration::ZERO);
+
+        let report = fixture.reaper.run().await.unwrap();
+        assert_eq!(report.orphan_files, vec![old.display().to_string()]);
+        assert!(report.missing_recording_files.is_empty());
+        assert!(known.exists());
+        assert!(!old.exists());
+        assert!(recent.exists());
+    }
+}
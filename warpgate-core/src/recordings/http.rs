@@ -0,0 +1,66 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use uuid::Uuid;
+use warpgate_common::RecordingsConfig;
+use warpgate_db_entities::Recording::RecordingKind;
+
+use super::writer::RecordingWriter;
+use super::{Error, Recorder, Result};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct HttpRecordedBody {
+    #[serde(with = "warpgate_common::helpers::serde_base64")]
+    pub data: Bytes,
+    /// Size of the whole body, of which only the start may have been kept
+    pub size: u64,
+}
+
+/// A single proxied request and its response
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HttpRecordingItem {
+    pub time: f32,
+    pub target: String,
+    pub method: String,
+    pub uri: String,
+    /// Not set if the target couldn't be reached
+    pub status: Option<u16>,
+    pub request_body: HttpRecordedBody,
+    pub response_body: HttpRecordedBody,
+}
+
+#[derive(Clone)]
+pub struct HttpRecorder {
+    writer: RecordingWriter,
+    started_at: Instant,
+}
+
+impl HttpRecorder {
+    pub fn id(&self) -> Uuid {
+        self.writer.recording_id()
+    }
+
+    pub fn get_time(&self) -> f32 {
+        self.started_at.elapsed().as_secs_f32()
+    }
+
+    pub async fn write_exchange(&mut self, item: &HttpRecordingItem) -> Result<()> {
+        let mut serialized_item = serde_json::to_vec(&item).map_err(Error::Serialization)?;
+        serialized_item.push(b'\n');
+        self.writer.write(&serialized_item).await?;
+        Ok(())
+    }
+}
+
+impl Recorder for HttpRecorder {
+    fn kind() -> RecordingKind {
+        RecordingKind::Http
+    }
+
+    fn new(writer: RecordingWriter, _config: &RecordingsConfig) -> Self {
+        HttpRecorder {
+            writer,
+            started_at: Instant::now(),
+        }
+    }
+}
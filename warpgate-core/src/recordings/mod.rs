@@ -3,16 +3,29 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use bytes::Bytes;
-use sea_orm::{ActiveModelTrait, DatabaseConnection};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait};
 use tokio::sync::{broadcast, Mutex};
 use tracing::*;
 use uuid::Uuid;
 use warpgate_common::helpers::fs::secure_directory;
 use warpgate_common::{RecordingsConfig, SessionId, WarpgateConfig};
 use warpgate_db_entities::Recording::{self, RecordingKind};
+use warpgate_db_entities::Session;
+mod http;
+mod queries;
+mod quotas;
+mod replication;
+mod secrets;
+mod sftp;
 mod terminal;
 mod traffic;
 mod writer;
+pub use http::*;
+pub use queries::*;
+pub use quotas::*;
+pub use replication::*;
+pub use secrets::*;
+pub use sftp::*;
 pub use terminal::*;
 pub use traffic::*;
 use writer::RecordingWriter;
@@ -34,15 +47,21 @@ pub enum Error {
     #[error("Disabled")]
     Disabled,
 
+    #[error("Recording quota exceeded")]
+    QuotaExceeded,
+
     #[error("Invalid recording path")]
     InvalidPath,
+
+    #[error("Integrity check failed")]
+    IntegrityCheckFailed,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
 pub trait Recorder {
     fn kind() -> RecordingKind;
-    fn new(writer: RecordingWriter) -> Self;
+    fn new(writer: RecordingWriter, config: &RecordingsConfig) -> Self;
 }
 
 pub struct SessionRecordings {
@@ -50,10 +69,15 @@ pub struct SessionRecordings {
     path: PathBuf,
     config: RecordingsConfig,
     live: Arc<Mutex<HashMap<Uuid, broadcast::Sender<Bytes>>>>,
+    quotas: Arc<RecordingQuotas>,
 }
 
 impl SessionRecordings {
-    pub fn new(db: Arc<Mutex<DatabaseConnection>>, config: &WarpgateConfig) -> Result<Self> {
+    pub fn new(
+        db: Arc<Mutex<DatabaseConnection>>,
+        config: &WarpgateConfig,
+        quotas: Arc<RecordingQuotas>,
+    ) -> Result<Self> {
         let mut path = config.paths_relative_to.clone();
         path.push(&config.store.recordings.path);
         if config.store.recordings.enable {
@@ -65,6 +89,7 @@ impl SessionRecordings {
             config: config.store.recordings.clone(),
             path,
             live: Arc::new(Mutex::new(HashMap::new())),
+            quotas,
         })
     }
 
@@ -76,6 +101,18 @@ impl SessionRecordings {
             return Err(Error::Disabled);
         }
 
+        let quota_owners = {
+            let db = self.db.lock().await;
+            Session::Entity::find_by_id(*id)
+                .one(&*db)
+                .await?
+                .map(|session| QuotaOwner::for_session_model(&session))
+                .unwrap_or_default()
+        };
+        if !self.quotas.allows_recording(&quota_owners) {
+            return Err(Error::QuotaExceeded);
+        }
+
         let path = self.path_for(id, &name);
         tokio::fs::create_dir_all(&path.parent().ok_or(Error::InvalidPath)?).await?;
         info!(%name, path=?path, "Recording session {}", id);
@@ -95,8 +132,17 @@ impl SessionRecordings {
             values.insert(&*db).await.map_err(Error::Database)?
         };
 
-        let writer = RecordingWriter::new(path, model, self.db.clone(), self.live.clone()).await?;
-        Ok(T::new(writer))
+        let writer = RecordingWriter::new(
+            path,
+            model,
+            self.db.clone(),
+            self.live.clone(),
+            self.config.writer.clone(),
+            self.quotas.clone(),
+            quota_owners,
+        )
+        .await?;
+        Ok(T::new(writer, &self.config))
     }
 
     pub async fn subscribe_live(&self, id: &Uuid) -> Option<broadcast::Receiver<Bytes>> {
@@ -105,22 +151,34 @@ impl SessionRecordings {
     }
 
     pub async fn remove<P: AsRef<Path>>(&self, session_id: &SessionId, name: P) -> Result<()> {
-        let path = self.path_for(session_id, name);
-        tokio::fs::remove_file(&path).await?;
-        if let Some(parent) = path.parent() {
-            if tokio::fs::read_dir(parent)
-                .await?
-                .next_entry()
-                .await?
-                .is_none()
-            {
-                tokio::fs::remove_dir(parent).await?;
-            }
-        }
-        Ok(())
+        remove_recording_file(&self.path_for(session_id, name)).await
     }
 
     pub fn path_for<P: AsRef<Path>>(&self, session_id: &SessionId, name: P) -> PathBuf {
-        self.path.join(session_id.to_string()).join(&name)
+        recording_path(&self.path, session_id, name)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.path
+    }
+}
+
+pub fn recording_path<P: AsRef<Path>>(root: &Path, session_id: &SessionId, name: P) -> PathBuf {
+    root.join(session_id.to_string()).join(&name)
+}
+
+/// Removes a recording file along with its session directory once it's empty
+pub async fn remove_recording_file(path: &Path) -> Result<()> {
+    tokio::fs::remove_file(path).await?;
+    if let Some(parent) = path.parent() {
+        if tokio::fs::read_dir(parent)
+            .await?
+            .next_entry()
+            .await?
+            .is_none()
+        {
+            tokio::fs::remove_dir(parent).await?;
+        }
     }
+    Ok(())
 }
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use uuid::Uuid;
+use warpgate_common::RecordingsConfig;
+use warpgate_db_entities::Recording::RecordingKind;
+
+use super::writer::RecordingWriter;
+use super::{Error, Recorder, Result};
+
+/// A single statement sent to a database target and the outcome
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QueryRecordingItem {
+    pub time: f32,
+    /// Until the target has finished responding
+    pub duration: f32,
+    pub query: String,
+    /// Rows returned or, for statements that don't return any, affected
+    pub rows: Option<u64>,
+    /// Set if the target rejected the statement
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct QueryRecorder {
+    writer: RecordingWriter,
+    started_at: Instant,
+}
+
+impl QueryRecorder {
+    pub fn id(&self) -> Uuid {
+        self.writer.recording_id()
+    }
+
+    pub fn get_time(&self) -> f32 {
+        self.started_at.elapsed().as_secs_f32()
+    }
+
+    /// `time` is when the statement was received, from [QueryRecorder::get_time]
+    pub async fn write_query(
+        &mut self,
+        time: f32,
+        query: String,
+        rows: Option<u64>,
+        error: Option<String>,
+    ) -> Result<()> {
+        let item = QueryRecordingItem {
+            time,
+            duration: self.get_time() - time,
+            query,
+            rows,
+            error,
+        };
+        let mut serialized_item = serde_json::to_vec(&item).map_err(Error::Serialization)?;
+        serialized_item.push(b'\n');
+        self.writer.write(&serialized_item).await?;
+        Ok(())
+    }
+}
+
+impl Recorder for QueryRecorder {
+    fn kind() -> RecordingKind {
+        RecordingKind::Queries
+    }
+
+    fn new(writer: RecordingWriter, _config: &RecordingsConfig) -> Self {
+        QueryRecorder {
+            writer,
+            started_at: Instant::now(),
+        }
+    }
+}
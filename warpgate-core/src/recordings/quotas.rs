@@ -0,0 +1,315 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::{Arc, Mutex as SyncMutex};
+
+use chrono::Utc;
+use sea_orm::{DatabaseConnection, EntityTrait};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use warpgate_common::{RecordingQuota, RecordingQuotaPolicy, WarpgateError};
+use warpgate_db_entities::{Recording, Session};
+
+use super::Result;
+use crate::{Alert, AlertSeverity, Alerts, SharedConfig};
+
+/// Whose quota a recording counts against
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "kind", content = "name", rename_all = "snake_case")]
+pub enum QuotaOwner {
+    Target(String),
+    User(String),
+}
+
+impl QuotaOwner {
+    pub fn for_session(target: Option<&str>, username: Option<&str>) -> Vec<Self> {
+        target
+            .map(|x| Self::Target(x.to_owned()))
+            .into_iter()
+            .chain(username.map(|x| Self::User(x.to_owned())))
+            .collect()
+    }
+
+    pub(crate) fn for_session_model(session: &Session::Model) -> Vec<Self> {
+        let target = session
+            .target_snapshot
+            .as_deref()
+            .and_then(|x| serde_json::from_str::<serde_json::Value>(x).ok())
+            .and_then(|x| x.get("name")?.as_str().map(str::to_owned));
+        Self::for_session(target.as_deref(), session.username.as_deref())
+    }
+}
+
+impl Display for QuotaOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Target(name) => write!(f, "target {name}"),
+            Self::User(name) => write!(f, "user {name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Limit {
+    Soft,
+    Hard,
+}
+
+impl Limit {
+    fn of(&self, quota: &RecordingQuota) -> Option<u64> {
+        match self {
+            Self::Soft => quota.soft_limit,
+            Self::Hard => quota.hard_limit,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    bytes: HashMap<QuotaOwner, u64>,
+    /// Limits that have already been alerted about
+    exceeded: HashSet<(QuotaOwner, Limit)>,
+}
+
+/// Counts the disk space used by the recordings of each target and user
+/// and enforces `recordings.quotas`.
+///
+/// Usage is updated as recordings are written and recounted from disk
+/// with [RecordingQuotas::rescan], which also picks up deleted recordings.
+pub struct RecordingQuotas {
+    config: Arc<SharedConfig>,
+    alerts: Alerts,
+    usage: SyncMutex<Usage>,
+}
+
+impl RecordingQuotas {
+    pub fn new(config: Arc<SharedConfig>, alerts: Alerts) -> Self {
+        Self {
+            config,
+            alerts,
+            usage: SyncMutex::new(Usage::default()),
+        }
+    }
+
+    fn quota(&self, owner: &QuotaOwner) -> RecordingQuota {
+        let config = &self.config.load().store.recordings.quotas;
+        match owner {
+            QuotaOwner::Target(name) => config.targets.get(name).unwrap_or(&config.per_target),
+            QuotaOwner::User(name) => config.users.get(name).unwrap_or(&config.per_user),
+        }
+        .to_owned()
+    }
+
+    fn policy(&self) -> RecordingQuotaPolicy {
+        self.config.load().store.recordings.quotas.on_exceeded
+    }
+
+    fn over_hard_limit<'a>(&self, owners: &'a [QuotaOwner]) -> Option<&'a QuotaOwner> {
+        let usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        owners.iter().find(|owner| {
+            self.quota(owner)
+                .hard_limit
+                .is_some_and(|limit| usage.bytes.get(owner).copied().unwrap_or_default() > limit)
+        })
+    }
+
+    /// Whether a new recording for these owners may be started
+    pub fn allows_recording(&self, owners: &[QuotaOwner]) -> bool {
+        self.policy() != RecordingQuotaPolicy::StopRecording
+            || self.over_hard_limit(owners).is_none()
+    }
+
+    /// Fails if sessions are blocked once the quota is used up
+    /// and the target or the user is over their hard limit
+    pub fn check_session(
+        &self,
+        target: &str,
+        username: Option<&str>,
+    ) -> std::result::Result<(), WarpgateError> {
+        let config = self.config.load();
+        if !config.store.recordings.enable
+            || config.store.recordings.quotas.on_exceeded != RecordingQuotaPolicy::BlockSessions
+        {
+            return Ok(());
+        }
+        match self.over_hard_limit(&QuotaOwner::for_session(Some(target), username)) {
+            Some(owner) => Err(WarpgateError::RecordingQuotaExceeded(owner.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds newly recorded data to the usage of its owners.
+    /// Returns `false` if the recording has to stop.
+    pub async fn charge(&self, owners: &[QuotaOwner], bytes: u64) -> bool {
+        let policy = self.policy();
+        let mut allowed = true;
+        let mut newly_exceeded = vec![];
+        {
+            let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+            for owner in owners {
+                let used = usage.bytes.entry(owner.clone()).or_default();
+                *used += bytes;
+                let used = *used;
+                let quota = self.quota(owner);
+                for limit in [Limit::Soft, Limit::Hard] {
+                    let Some(value) = limit.of(&quota) else {
+                        continue;
+                    };
+                    if used <= value {
+                        continue;
+                    }
+                    if limit == Limit::Hard && policy == RecordingQuotaPolicy::StopRecording {
+                        allowed = false;
+                    }
+                    if usage.exceeded.insert((owner.clone(), limit)) {
+                        newly_exceeded.push((owner.clone(), limit, value, used));
+                    }
+                }
+            }
+        }
+
+        for (owner, limit, value, used) in newly_exceeded {
+            let (severity, consequence) = match (limit, policy) {
+                (Limit::Soft, _) => (AlertSeverity::Warning, ""),
+                (Limit::Hard, RecordingQuotaPolicy::StopRecording) => (
+                    AlertSeverity::Critical,
+                    " - its sessions are no longer recorded",
+                ),
+                (Limit::Hard, RecordingQuotaPolicy::BlockSessions) => {
+                    (AlertSeverity::Critical, " - new sessions are refused")
+                }
+            };
+            self.alerts
+                .raise(Alert {
+                    time: Utc::now(),
+                    severity,
+                    kind: "recording_quota_exceeded".into(),
+                    message: format!(
+                        "Recordings of the {owner} take up {used} bytes, over the {} limit of {value} bytes{consequence}",
+                        match limit {
+                            Limit::Soft => "soft",
+                            Limit::Hard => "hard",
+                        }
+                    ),
+                    target: match &owner {
+                        QuotaOwner::Target(name) => Some(name.clone()),
+                        QuotaOwner::User(_) => None,
+                    },
+                    details: json!({
+                        "owner": owner,
+                        "limit": limit,
+                        "limit_bytes": value,
+                        "used_bytes": used,
+                    }),
+                })
+                .await;
+        }
+        allowed
+    }
+
+    /// Recounts the usage from the recording files in `root`
+    pub async fn rescan(&self, db: &Mutex<DatabaseConnection>, root: &Path) -> Result<()> {
+        let recordings = {
+            let db = db.lock().await;
+            Recording::Entity::find()
+                .find_also_related(Session::Entity)
+                .all(&*db)
+                .await?
+        };
+
+        let mut bytes = HashMap::<QuotaOwner, u64>::new();
+        for (recording, session) in recordings {
+            let Some(session) = session else {
+                continue;
+            };
+            let path = root
+                .join(recording.session_id.to_string())
+                .join(&recording.name);
+            let Ok(metadata) = tokio::fs::metadata(&path).await else {
+                continue;
+            };
+            for owner in QuotaOwner::for_session_model(&session) {
+                *bytes.entry(owner).or_default() += metadata.len();
+            }
+        }
+
+        let mut usage = self.usage.lock().unwrap_or_else(|e| e.into_inner());
+        // Limits that aren't exceeded anymore get alerted about again
+        usage.exceeded.retain(|(owner, limit)| {
+            limit
+                .of(&self.quota(owner))
+                .is_some_and(|value| bytes.get(owner).copied().unwrap_or_default() > value)
+        });
+        usage.bytes = bytes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use warpgate_common::{RecordingQuotasConfig, WarpgateConfig};
+
+    use super::*;
+
+    async fn quotas(on_exceeded: RecordingQuotaPolicy) -> RecordingQuotas {
+        let mut config = WarpgateConfig {
+            store: Default::default(),
+            paths_relative_to: PathBuf::new(),
+        };
+        config.store.recordings.enable = true;
+        config.store.recordings.quotas = RecordingQuotasConfig {
+            per_user: RecordingQuota {
+                soft_limit: Some(10),
+                hard_limit: Some(20),
+            },
+            targets: HashMap::from([(
+                "db".into(),
+                RecordingQuota {
+                    soft_limit: None,
+                    hard_limit: Some(100),
+                },
+            )]),
+            on_exceeded,
+            ..Default::default()
+        };
+        let config = Arc::new(SharedConfig::new(config));
+        RecordingQuotas::new(config.clone(), Alerts::new(config).await)
+    }
+
+    #[tokio::test]
+    async fn test_stop_recording() {
+        let quotas = quotas(RecordingQuotaPolicy::StopRecording).await;
+        let owners = QuotaOwner::for_session(Some("db"), Some("alice"));
+
+        assert!(quotas.charge(&owners, 15).await);
+        assert!(quotas.allows_recording(&owners));
+        assert!(!quotas.charge(&owners, 15).await);
+        assert!(!quotas.allows_recording(&owners));
+
+        // Other users and targets are unaffected
+        assert!(quotas.allows_recording(&QuotaOwner::for_session(Some("db"), Some("bob"))));
+        assert!(quotas.check_session("db", Some("alice")).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_block_sessions() {
+        let quotas = quotas(RecordingQuotaPolicy::BlockSessions).await;
+        let owners = QuotaOwner::for_session(Some("db"), Some("alice"));
+
+        assert!(quotas.charge(&owners, 101).await);
+        assert!(quotas.allows_recording(&owners));
+        assert!(matches!(
+            quotas.check_session("db", Some("bob")),
+            Err(WarpgateError::RecordingQuotaExceeded(owner)) if owner == "target db"
+        ));
+        assert!(matches!(
+            quotas.check_session("web", Some("alice")),
+            Err(WarpgateError::RecordingQuotaExceeded(owner)) if owner == "user alice"
+        ));
+        assert!(quotas.check_session("web", Some("bob")).is_ok());
+    }
+}
@@ -0,0 +1,519 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use poem_openapi::Object;
+use sea_orm::query::JsonValue;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::RecordingReplicationConfig;
+use warpgate_db_entities::LogEntry;
+use warpgate_db_entities::Recording::{self, RecordingKind};
+use warpgate_db_entities::Session::{self, SessionTerminationReason};
+
+use super::{recording_path, Error, Result, SessionRecordings};
+use crate::{http_client_builder, JobHandle, Jobs};
+
+const STAGING_DIR: &str = ".replication";
+const BATCH_SIZE: u64 = 50;
+
+#[derive(Serialize, Deserialize, Object, Debug)]
+pub struct ReplicationStatus {
+    /// Bytes of the recording file received so far
+    pub received: u64,
+    pub complete: bool,
+}
+
+#[derive(Serialize, Deserialize, Object)]
+pub struct ReplicatedRecording {
+    pub id: Uuid,
+    pub name: String,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    pub session_id: Uuid,
+    pub kind: RecordingKind,
+}
+
+#[derive(Serialize, Deserialize, Object)]
+pub struct ReplicatedSession {
+    pub id: Uuid,
+    pub target_snapshot: Option<String>,
+    pub username: Option<String>,
+    pub remote_address: String,
+    pub started: DateTime<Utc>,
+    pub ended: Option<DateTime<Utc>>,
+    pub ticket_id: Option<Uuid>,
+    pub protocol: String,
+    #[serde(default)]
+    pub consent_acknowledged: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub work_item: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub termination_reason: Option<SessionTerminationReason>,
+    #[serde(default)]
+    pub exit_code: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Object)]
+pub struct ReplicatedLogEntry {
+    pub id: Uuid,
+    pub text: String,
+    pub values: JsonValue,
+    pub timestamp: DateTime<Utc>,
+    pub username: Option<String>,
+}
+
+/// Sent once the whole recording file has been transferred
+#[derive(Serialize, Deserialize, Object)]
+pub struct ReplicationManifest {
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the complete recording file
+    pub sha256: String,
+    pub recording: ReplicatedRecording,
+    pub session: ReplicatedSession,
+    pub log: Vec<ReplicatedLogEntry>,
+}
+
+impl From<Recording::Model> for ReplicatedRecording {
+    fn from(model: Recording::Model) -> Self {
+        Self {
+            id: model.id,
+            name: model.name,
+            started: model.started,
+            ended: model.ended,
+            session_id: model.session_id,
+            kind: model.kind,
+        }
+    }
+}
+
+impl From<Session::Model> for ReplicatedSession {
+    fn from(model: Session::Model) -> Self {
+        Self {
+            id: model.id,
+            target_snapshot: model.target_snapshot,
+            username: model.username,
+            remote_address: model.remote_address,
+            started: model.started,
+            ended: model.ended,
+            ticket_id: model.ticket_id,
+            protocol: model.protocol,
+            consent_acknowledged: model.consent_acknowledged,
+            work_item: model.work_item,
+            user_agent: model.user_agent,
+            termination_reason: model.termination_reason,
+            exit_code: model.exit_code,
+        }
+    }
+}
+
+impl From<LogEntry::Model> for ReplicatedLogEntry {
+    fn from(model: LogEntry::Model) -> Self {
+        Self {
+            id: model.id,
+            text: model.text,
+            values: model.values,
+            timestamp: model.timestamp,
+            username: model.username,
+        }
+    }
+}
+
+async fn file_digest(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        size += n as u64;
+        hasher.update(buffer.get(..n).unwrap_or_default());
+    }
+    Ok((size, HEXLOWER.encode(&hasher.finalize())))
+}
+
+impl SessionRecordings {
+    /// Only copies the paths and the DB handle, so that the recordings
+    /// lock isn't held for the duration of the file I/O
+    pub fn replication_receiver(&self) -> ReplicationReceiver {
+        ReplicationReceiver {
+            db: self.db.clone(),
+            path: self.path.clone(),
+        }
+    }
+}
+
+/// Receiving side of the replication
+pub struct ReplicationReceiver {
+    db: Arc<Mutex<DatabaseConnection>>,
+    path: PathBuf,
+}
+
+impl ReplicationReceiver {
+    fn staging_path_for(&self, id: &Uuid) -> PathBuf {
+        self.path.join(STAGING_DIR).join(format!("{id}.part"))
+    }
+
+    pub async fn replication_status(&self, id: &Uuid) -> Result<ReplicationStatus> {
+        let existing = {
+            let db = self.db.lock().await;
+            Recording::Entity::find_by_id(*id).one(&*db).await?
+        };
+        if existing.is_some() {
+            return Ok(ReplicationStatus {
+                received: 0,
+                complete: true,
+            });
+        }
+
+        let received = match tokio::fs::metadata(self.staging_path_for(id)).await {
+            Ok(m) => m.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(ReplicationStatus {
+            received,
+            complete: false,
+        })
+    }
+
+    /// Appends a chunk at `offset`. An offset of zero restarts the transfer.
+    /// Returns the current status without writing if the offset doesn't match
+    pub async fn append_replicated_chunk(
+        &self,
+        id: &Uuid,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<ReplicationStatus> {
+        let status = self.replication_status(id).await?;
+        if status.complete || (offset != status.received && offset != 0) {
+            return Ok(status);
+        }
+
+        let path = self.staging_path_for(id);
+        tokio::fs::create_dir_all(path.parent().ok_or(Error::InvalidPath)?).await?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(offset == 0)
+            .open(&path)
+            .await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        Ok(ReplicationStatus {
+            received: offset + data.len() as u64,
+            complete: false,
+        })
+    }
+
+    /// Verifies the staged file against the manifest and files it
+    /// together with the session and log entries
+    pub async fn complete_replication(&self, manifest: ReplicationManifest) -> Result<()> {
+        use sea_orm::ActiveValue::Set;
+
+        let recording = &manifest.recording;
+        let mut name_components = Path::new(&recording.name).components();
+        if !matches!(
+            (name_components.next(), name_components.next()),
+            (Some(Component::Normal(_)), None)
+        ) || recording.session_id != manifest.session.id
+        {
+            return Err(Error::InvalidPath);
+        }
+
+        let staging_path = self.staging_path_for(&recording.id);
+        let (size, digest) = file_digest(&staging_path).await?;
+        if size != manifest.size || digest != manifest.sha256 {
+            warn!(recording=%recording.id, "Replicated recording failed the integrity check");
+            tokio::fs::remove_file(&staging_path).await?;
+            return Err(Error::IntegrityCheckFailed);
+        }
+
+        let path = recording_path(&self.path, &recording.session_id, &recording.name);
+        tokio::fs::create_dir_all(path.parent().ok_or(Error::InvalidPath)?).await?;
+        tokio::fs::rename(&staging_path, &path).await?;
+
+        let db = self.db.lock().await;
+
+        let session = &manifest.session;
+        let session_model = Session::ActiveModel {
+            id: Set(session.id),
+            target_snapshot: Set(session.target_snapshot.clone()),
+            username: Set(session.username.clone()),
+            remote_address: Set(session.remote_address.clone()),
+            started: Set(session.started),
+            ended: Set(session.ended),
+            ticket_id: Set(None),
+            protocol: Set(session.protocol.clone()),
+            consent_acknowledged: Set(session.consent_acknowledged),
+            work_item: Set(session.work_item.clone()),
+            user_agent: Set(session.user_agent.clone()),
+            termination_reason: Set(session.termination_reason),
+            exit_code: Set(session.exit_code),
+        };
+        if Session::Entity::find_by_id(session.id)
+            .one(&*db)
+            .await?
+            .is_some()
+        {
+            session_model.update(&*db).await?;
+        } else {
+            session_model.insert(&*db).await?;
+        }
+
+        for entry in manifest.log {
+            if LogEntry::Entity::find_by_id(entry.id)
+                .one(&*db)
+                .await?
+                .is_some()
+            {
+                continue;
+            }
+            LogEntry::ActiveModel {
+                id: Set(entry.id),
+                text: Set(entry.text),
+                values: Set(entry.values),
+                timestamp: Set(entry.timestamp),
+                session_id: Set(session.id),
+                username: Set(entry.username),
+            }
+            .insert(&*db)
+            .await?;
+        }
+
+        Recording::ActiveModel {
+            id: Set(recording.id),
+            name: Set(recording.name.clone()),
+            started: Set(recording.started),
+            ended: Set(recording.ended),
+            session_id: Set(recording.session_id),
+            kind: Set(recording.kind.clone()),
+            replicated: Set(None),
+        }
+        .insert(&*db)
+        .await?;
+
+        info!(recording=%recording.id, session=%session.id, "Received replicated recording");
+        Ok(())
+    }
+}
+
+/// Sending side of the replication - periodically pushes
+/// finished recordings to the configured standby instance
+pub struct RecordingReplicator {
+    db: Arc<Mutex<DatabaseConnection>>,
+    recordings: Arc<Mutex<SessionRecordings>>,
+    config: RecordingReplicationConfig,
+    client: reqwest::Client,
+    jobs: Arc<Jobs>,
+}
+
+impl RecordingReplicator {
+    pub fn new(
+        db: Arc<Mutex<DatabaseConnection>>,
+        recordings: Arc<Mutex<SessionRecordings>>,
+        config: RecordingReplicationConfig,
+        jobs: Arc<Jobs>,
+    ) -> anyhow::Result<Self> {
+        let client = http_client_builder()
+            .danger_accept_invalid_certs(!config.verify_tls)
+            .build()?;
+        Ok(Self {
+            db,
+            recordings,
+            config,
+            client,
+            jobs,
+        })
+    }
+
+    pub async fn run(self) {
+        info!(url=%self.config.url, "Replicating recordings");
+        loop {
+            if let Err(error) = self.replicate_pending().await {
+                error!(?error, "Recording replication failed");
+            }
+            tokio::time::sleep(self.config.interval).await;
+        }
+    }
+
+    async fn replicate_pending(&self) -> anyhow::Result<()> {
+        let pending = {
+            let db = self.db.lock().await;
+            Recording::Entity::find()
+                .filter(Recording::Column::Ended.is_not_null())
+                .filter(Recording::Column::Replicated.is_null())
+                .order_by_asc(Recording::Column::Started)
+                .limit(BATCH_SIZE)
+                .all(&*db)
+                .await?
+        };
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let job = self.jobs.start(
+            "recording_replication",
+            format!("Replicating {} recordings", pending.len()),
+        );
+        let result = self.replicate_batch(pending, &job).await;
+        job.finish(&result);
+        result
+    }
+
+    /// Cancelling only stops the current batch,
+    /// the rest is picked up by the next round
+    async fn replicate_batch(
+        &self,
+        pending: Vec<Recording::Model>,
+        job: &JobHandle,
+    ) -> anyhow::Result<()> {
+        let total = pending.len() as u64;
+        for (index, recording) in pending.into_iter().enumerate() {
+            if job.is_cancelled() {
+                break;
+            }
+            job.set_progress(index as u64, Some(total));
+            let id = recording.id;
+            if let Err(error) = self.replicate(recording.clone()).await {
+                // Leave it for the next round, transfers resume where they stopped
+                warn!(recording=%id, ?error, "Failed to replicate recording");
+                continue;
+            }
+
+            use sea_orm::ActiveValue::Set;
+            let db = self.db.lock().await;
+            let mut model: Recording::ActiveModel = recording.into();
+            model.replicated = Set(Some(Utc::now()));
+            model.update(&*db).await?;
+            debug!(recording=%id, "Recording replicated");
+        }
+        Ok(())
+    }
+
+    fn url(&self, id: &Uuid, suffix: &str) -> String {
+        format!(
+            "{}/replication/recordings/{id}{suffix}",
+            self.config.url.trim_end_matches('/')
+        )
+    }
+
+    fn request(&self, method: reqwest::Method, url: String) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, url)
+            .header("X-Warpgate-Token", self.config.token.expose_secret())
+    }
+
+    async fn remote_status(&self, id: &Uuid) -> anyhow::Result<ReplicationStatus> {
+        Ok(self
+            .request(reqwest::Method::GET, self.url(id, ""))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn replicate(&self, recording: Recording::Model) -> anyhow::Result<()> {
+        let path = self
+            .recordings
+            .lock()
+            .await
+            .path_for(&recording.session_id, &recording.name);
+
+        let (size, sha256) = file_digest(&path)
+            .await
+            .with_context(|| format!("reading {}", path.display()))?;
+
+        let status = self.remote_status(&recording.id).await?;
+        if status.complete {
+            return Ok(());
+        }
+
+        let mut offset = if status.received > size {
+            0
+        } else {
+            status.received
+        };
+
+        let mut file = File::open(&path).await?;
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut buffer = vec![0; self.config.chunk_size.max(1)];
+        while offset < size {
+            let n = file.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            let chunk = buffer.get(..n).unwrap_or_default().to_vec();
+            let status: ReplicationStatus = self
+                .request(reqwest::Method::PUT, self.url(&recording.id, "/data"))
+                .query(&[("offset", offset)])
+                .body(chunk)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            if status.received != offset + n as u64 {
+                anyhow::bail!(
+                    "remote is at offset {} instead of {}",
+                    status.received,
+                    offset + n as u64
+                );
+            }
+            offset = status.received;
+        }
+
+        let (session, log) = {
+            let db = self.db.lock().await;
+            let session = Session::Entity::find_by_id(recording.session_id)
+                .one(&*db)
+                .await?
+                .context("session not found")?;
+            let log = LogEntry::Entity::find()
+                .filter(LogEntry::Column::SessionId.eq(recording.session_id))
+                .order_by_asc(LogEntry::Column::Timestamp)
+                .all(&*db)
+                .await?;
+            (session, log)
+        };
+
+        let manifest = ReplicationManifest {
+            size,
+            sha256,
+            recording: recording.into(),
+            session: session.into(),
+            log: log.into_iter().map(Into::into).collect(),
+        };
+
+        self.request(
+            reqwest::Method::POST,
+            self.url(&manifest.recording.id, "/complete"),
+        )
+        .json(&manifest)
+        .send()
+        .await?
+        .error_for_status()?;
+
+        Ok(())
+    }
+}
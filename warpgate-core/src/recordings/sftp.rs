@@ -0,0 +1,89 @@
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use uuid::Uuid;
+use warpgate_common::RecordingsConfig;
+use warpgate_db_entities::Recording::RecordingKind;
+
+use super::writer::RecordingWriter;
+use super::{Error, Recorder, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum SftpOperation {
+    Open {
+        path: String,
+        write: bool,
+    },
+    /// Transfer totals for a file, once the client is done with it
+    Close {
+        path: String,
+        bytes_read: u64,
+        bytes_written: u64,
+    },
+    Rename {
+        path: String,
+        new_path: String,
+    },
+    Remove {
+        path: String,
+    },
+    Mkdir {
+        path: String,
+    },
+    Rmdir {
+        path: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SftpRecordingItem {
+    pub time: f32,
+    pub channel: Uuid,
+    #[serde(flatten)]
+    pub operation: SftpOperation,
+    /// Set if the target refused the operation
+    pub error: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct SftpRecorder {
+    writer: RecordingWriter,
+    started_at: Instant,
+}
+
+impl SftpRecorder {
+    pub fn id(&self) -> Uuid {
+        self.writer.recording_id()
+    }
+
+    pub async fn write_operation(
+        &mut self,
+        channel: Uuid,
+        operation: SftpOperation,
+        error: Option<String>,
+    ) -> Result<()> {
+        let item = SftpRecordingItem {
+            time: self.started_at.elapsed().as_secs_f32(),
+            channel,
+            operation,
+            error,
+        };
+        let mut serialized_item = serde_json::to_vec(&item).map_err(Error::Serialization)?;
+        serialized_item.push(b'\n');
+        self.writer.write(&serialized_item).await?;
+        Ok(())
+    }
+}
+
+impl Recorder for SftpRecorder {
+    fn kind() -> RecordingKind {
+        RecordingKind::Sftp
+    }
+
+    fn new(writer: RecordingWriter, _config: &RecordingsConfig) -> Self {
+        SftpRecorder {
+            writer,
+            started_at: Instant::now(),
+        }
+    }
+}
@@ -1,10 +1,17 @@
+use std::path::Path;
+
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::time::Instant;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::RecordingsConfig;
 use warpgate_db_entities::Recording::RecordingKind;
 
 use super::writer::RecordingWriter;
-use super::{Error, Recorder, Result};
+use super::{Error, Recorder, Result, SecretDetector};
 
 #[derive(Serialize)]
 #[serde(untagged)]
@@ -19,7 +26,42 @@ pub enum AsciiCast {
     Output(f32, String, String),
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+impl AsciiCast {
+    /// Whether this is user input, which isn't shown in playback
+    pub fn is_input(&self) -> bool {
+        matches!(self, AsciiCast::Output(_, stream, _) if stream == "i")
+    }
+}
+
+/// Reads a terminal recording as asciicast v2 items, starting with
+/// a header that has the last known terminal size
+pub async fn read_asciicast(path: &Path, title: String) -> Result<Vec<AsciiCast>> {
+    let mut items = vec![];
+    let mut last_size = (0, 0);
+    let mut lines = BufReader::new(File::open(path).await?).lines();
+    while let Some(line) = lines.next_line().await? {
+        let entry: TerminalRecordingItem = serde_json::from_str(&line[..])?;
+        let asciicast: AsciiCast = entry.into();
+        if let AsciiCast::Header { width, height, .. } = asciicast {
+            last_size = (width, height);
+        }
+        items.push(asciicast);
+    }
+
+    items.insert(
+        0,
+        AsciiCast::Header {
+            time: 0.0,
+            version: 2,
+            width: last_size.0,
+            height: last_size.1,
+            title,
+        },
+    );
+    Ok(items)
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TerminalRecordingStreamId {
     Input,
     #[default]
@@ -42,6 +84,10 @@ pub enum TerminalRecordingItem {
         cols: u32,
         rows: u32,
     },
+    Marker {
+        time: f32,
+        label: String,
+    },
 }
 
 impl From<TerminalRecordingItem> for AsciiCast {
@@ -63,6 +109,9 @@ impl From<TerminalRecordingItem> for AsciiCast {
                 height: rows,
                 title: "".to_string(),
             },
+            TerminalRecordingItem::Marker { time, label } => {
+                AsciiCast::Output(time, "m".to_string(), label)
+            }
         }
     }
 }
@@ -70,9 +119,14 @@ impl From<TerminalRecordingItem> for AsciiCast {
 pub struct TerminalRecorder {
     writer: RecordingWriter,
     started_at: Instant,
+    secret_detector: Option<SecretDetector>,
 }
 
 impl TerminalRecorder {
+    pub fn id(&self) -> Uuid {
+        self.writer.recording_id()
+    }
+
     fn get_time(&self) -> f32 {
         self.started_at.elapsed().as_secs_f32()
     }
@@ -85,12 +139,22 @@ impl TerminalRecorder {
     }
 
     pub async fn write(&mut self, stream: TerminalRecordingStreamId, data: &[u8]) -> Result<()> {
+        let mut data = data.to_vec();
+        let secrets = match self.secret_detector {
+            Some(ref mut detector) => detector.scan(stream, &mut data),
+            None => vec![],
+        };
         self.write_item(&TerminalRecordingItem::Data {
             time: self.get_time(),
             stream,
-            data: Bytes::from(data.to_vec()),
+            data: Bytes::from(data),
         })
-        .await
+        .await?;
+        for kind in secrets {
+            warn!(recording=%self.id(), ?stream, %kind, "Possible secret in the session");
+            self.write_marker(format!("Possible {kind}")).await?;
+        }
+        Ok(())
     }
 
     pub async fn write_pty_resize(&mut self, cols: u32, rows: u32) -> Result<()> {
@@ -101,6 +165,15 @@ impl TerminalRecorder {
         })
         .await
     }
+
+    /// Marks a point in time, e.g. a change of who is typing
+    pub async fn write_marker(&mut self, label: String) -> Result<()> {
+        self.write_item(&TerminalRecordingItem::Marker {
+            time: self.get_time(),
+            label,
+        })
+        .await
+    }
 }
 
 impl Recorder for TerminalRecorder {
@@ -108,10 +181,14 @@ impl Recorder for TerminalRecorder {
         RecordingKind::Terminal
     }
 
-    fn new(writer: RecordingWriter) -> Self {
+    fn new(writer: RecordingWriter, config: &RecordingsConfig) -> Self {
         TerminalRecorder {
             writer,
             started_at: Instant::now(),
+            secret_detector: config
+                .secret_detection
+                .enable
+                .then(|| SecretDetector::new(config.secret_detection.redact)),
         }
     }
 }
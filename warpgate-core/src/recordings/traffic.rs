@@ -6,6 +6,7 @@ use packet::Builder;
 use rand::Rng;
 use tokio::time::Instant;
 use tracing::*;
+use warpgate_common::RecordingsConfig;
 use warpgate_db_entities::Recording::RecordingKind;
 
 use super::writer::RecordingWriter;
@@ -35,7 +36,7 @@ impl Recorder for TrafficRecorder {
         RecordingKind::Traffic
     }
 
-    fn new(writer: RecordingWriter) -> Self {
+    fn new(writer: RecordingWriter, _config: &RecordingsConfig) -> Self {
         TrafficRecorder {
             writer,
             started_at: Instant::now(),
@@ -78,19 +79,15 @@ impl ConnectionRecorder {
 
     async fn write_packet(&mut self, data: Bytes) -> Result<()> {
         let ms = Instant::now().duration_since(self.started_at).as_micros();
-        self.writer
-            .write(&u32::to_le_bytes((ms / 10u128.pow(6)) as u32))
-            .await?;
-        self.writer
-            .write(&u32::to_le_bytes((ms % 10u128.pow(6)) as u32))
-            .await?;
-        self.writer
-            .write(&u32::to_le_bytes(data.len() as u32))
-            .await?;
-        self.writer
-            .write(&u32::to_le_bytes(data.len() as u32))
-            .await?;
-        self.writer.write(&data).await?;
+        // Header and payload go out as a single write so that the writer
+        // never drops half of a packet record
+        let mut record = Vec::with_capacity(16 + data.len());
+        record.extend_from_slice(&u32::to_le_bytes((ms / 10u128.pow(6)) as u32));
+        record.extend_from_slice(&u32::to_le_bytes((ms % 10u128.pow(6)) as u32));
+        record.extend_from_slice(&u32::to_le_bytes(data.len() as u32));
+        record.extend_from_slice(&u32::to_le_bytes(data.len() as u32));
+        record.extend_from_slice(&data);
+        self.writer.write(&record).await?;
         Ok(())
     }
 
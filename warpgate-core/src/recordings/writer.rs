@@ -1,26 +1,40 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait};
 use tokio::fs::File;
 use tokio::io::{AsyncWriteExt, BufWriter};
-use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex, OwnedSemaphorePermit, Semaphore, TryAcquireError};
+use tokio::time::MissedTickBehavior;
 use tracing::*;
 use uuid::Uuid;
 use warpgate_common::helpers::fs::secure_file;
-use warpgate_common::try_block;
+use warpgate_common::{try_block, RecordingOverflowPolicy, RecordingWriterConfig};
 use warpgate_db_entities::Recording;
 
-use super::{Error, Result};
+use super::{Error, QuotaOwner, RecordingQuotas, Result};
 
+/// Writes recording data in a background task so that disk I/O
+/// stays off the session's data path.
+///
+/// The amount of queued data is capped at `queue_size` bytes - once it's
+/// reached, writes either wait for the writer to catch up or get dropped,
+/// depending on the configured [RecordingOverflowPolicy].
 #[derive(Clone)]
 pub struct RecordingWriter {
-    sender: mpsc::Sender<Bytes>,
+    recording_id: Uuid,
+    sender: mpsc::UnboundedSender<(Bytes, OwnedSemaphorePermit)>,
+    queue: Arc<Semaphore>,
+    queue_size: usize,
+    overflow_policy: RecordingOverflowPolicy,
+    dropped_bytes: Arc<AtomicUsize>,
     live_sender: broadcast::Sender<Bytes>,
     drop_signal: mpsc::Sender<()>,
+    quotas: Arc<RecordingQuotas>,
+    quota_owners: Arc<[QuotaOwner]>,
 }
 
 impl RecordingWriter {
@@ -29,13 +43,20 @@ impl RecordingWriter {
         model: Recording::Model,
         db: Arc<Mutex<DatabaseConnection>>,
         live: Arc<Mutex<HashMap<Uuid, broadcast::Sender<Bytes>>>>,
+        config: RecordingWriterConfig,
+        quotas: Arc<RecordingQuotas>,
+        quota_owners: Vec<QuotaOwner>,
     ) -> Result<Self> {
         let file = File::create(&path).await?;
         secure_file(&path)?;
         let mut writer = BufWriter::new(file);
-        let (sender, mut receiver) = mpsc::channel::<Bytes>(1024);
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(Bytes, OwnedSemaphorePermit)>();
         let (drop_signal, mut drop_receiver) = mpsc::channel(1);
 
+        let queue_size = config.queue_size.clamp(1, u32::MAX as usize);
+        let queue = Arc::new(Semaphore::new(queue_size));
+        let dropped_bytes = Arc::new(AtomicUsize::new(0));
+
         let live_sender = broadcast::channel(128).0;
         {
             let mut live = live.lock().await;
@@ -52,60 +73,138 @@ impl RecordingWriter {
             }
         });
 
-        tokio::spawn(async move {
-            try_block!(async {
-                let mut last_flush = Instant::now();
-                loop {
-                    if Instant::now() - last_flush > Duration::from_secs(5) {
-                        last_flush = Instant::now();
-                        writer.flush().await?;
-                    }
-                    tokio::select! {
-                        data = receiver.recv() => match data {
-                            Some(bytes) => {
-                                writer.write_all(&bytes).await?;
+        tokio::spawn({
+            let queue = queue.clone();
+            let dropped_bytes = dropped_bytes.clone();
+            async move {
+                try_block!(async {
+                    let batch_size = config.batch_size.max(1);
+                    let mut batch = BytesMut::with_capacity(batch_size);
+                    let mut permits = vec![];
+                    let mut dirty = false;
+                    let mut flush_interval = tokio::time::interval(config.flush_interval);
+                    flush_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+                    loop {
+                        tokio::select! {
+                            item = receiver.recv() => match item {
+                                Some((data, permit)) => {
+                                    batch.extend_from_slice(&data);
+                                    permits.push(permit);
+                                    // Coalesce whatever else is already queued
+                                    while batch.len() < batch_size {
+                                        let Ok((data, permit)) = receiver.try_recv() else {
+                                            break;
+                                        };
+                                        batch.extend_from_slice(&data);
+                                        permits.push(permit);
+                                    }
+                                    writer.write_all(&batch).await?;
+                                    batch.clear();
+                                    // Release the queue space only once the data is written
+                                    permits.clear();
+                                    dirty = true;
+                                }
+                                None => break,
+                            },
+                            _ = flush_interval.tick() => {
+                                if dirty {
+                                    writer.flush().await?;
+                                    dirty = false;
+                                }
                             }
-                            None => break,
-                        },
-                        _ = tokio::time::sleep(Duration::from_millis(5000)) => ()
+                        }
                     }
+                    Ok::<(), anyhow::Error>(())
+                } catch (error: anyhow::Error) {
+                    error!(%error, ?path, "Failed to write recording");
+                });
+
+                // Unblock any writers waiting for queue space
+                queue.close();
+
+                let dropped_bytes = dropped_bytes.load(Ordering::Relaxed);
+                if dropped_bytes > 0 {
+                    warn!(
+                        ?path,
+                        dropped_bytes, "Recording is incomplete - the writer could not keep up"
+                    );
                 }
-                Ok::<(), anyhow::Error>(())
-            } catch (error: anyhow::Error) {
-                error!(%error, ?path, "Failed to write recording");
-            });
-
-            try_block!(async {
-                writer.flush().await?;
-
-                use sea_orm::ActiveValue::Set;
-                let id = model.id;
-                let db = db.lock().await;
-                let recording = Recording::Entity::find_by_id(id)
-                    .one(&*db)
-                    .await?
-                    .ok_or_else(|| anyhow::anyhow!("Recording not found"))?;
-                let mut model: Recording::ActiveModel = recording.into();
-                model.ended = Set(Some(chrono::Utc::now()));
-                model.update(&*db).await?;
-                Ok::<(), anyhow::Error>(())
-            } catch (error: anyhow::Error) {
-                error!(%error, ?path, "Failed to write recording");
-            });
+
+                try_block!(async {
+                    writer.flush().await?;
+
+                    use sea_orm::ActiveValue::Set;
+                    let id = model.id;
+                    let db = db.lock().await;
+                    let recording = Recording::Entity::find_by_id(id)
+                        .one(&*db)
+                        .await?
+                        .ok_or_else(|| anyhow::anyhow!("Recording not found"))?;
+                    let mut model: Recording::ActiveModel = recording.into();
+                    model.ended = Set(Some(chrono::Utc::now()));
+                    model.update(&*db).await?;
+                    Ok::<(), anyhow::Error>(())
+                } catch (error: anyhow::Error) {
+                    error!(%error, ?path, "Failed to write recording");
+                });
+            }
         });
 
         Ok(RecordingWriter {
+            recording_id: model.id,
             sender,
+            queue,
+            queue_size,
+            overflow_policy: config.on_overflow,
+            dropped_bytes,
             live_sender,
             drop_signal,
+            quotas,
+            quota_owners: quota_owners.into(),
         })
     }
 
+    pub fn recording_id(&self) -> Uuid {
+        self.recording_id
+    }
+
+    /// Queues a chunk for writing. Chunks are always written or dropped
+    /// as a whole, so callers should pass complete records.
     pub async fn write(&mut self, data: &[u8]) -> Result<()> {
-        let data = Bytes::from(data.to_vec());
-        self.sender
-            .send(data.clone())
+        if !self
+            .quotas
+            .charge(&self.quota_owners, data.len() as u64)
             .await
+        {
+            return Err(Error::QuotaExceeded);
+        }
+        let data = Bytes::copy_from_slice(data);
+        // Oversized chunks take up the whole queue instead of never fitting
+        let amount = data.len().clamp(1, self.queue_size) as u32;
+
+        let permit = match self.overflow_policy {
+            RecordingOverflowPolicy::Block => self
+                .queue
+                .clone()
+                .acquire_many_owned(amount)
+                .await
+                .map_err(|_| Error::Closed)?,
+            RecordingOverflowPolicy::Drop => {
+                match self.queue.clone().try_acquire_many_owned(amount) {
+                    Ok(permit) => permit,
+                    Err(TryAcquireError::NoPermits) => {
+                        self.dropped_bytes.fetch_add(data.len(), Ordering::Relaxed);
+                        let _ = self.live_sender.send(data);
+                        return Ok(());
+                    }
+                    Err(TryAcquireError::Closed) => return Err(Error::Closed),
+                }
+            }
+        };
+
+        self.sender
+            .send((data.clone(), permit))
             .map_err(|_| Error::Closed)?;
         let _ = self.live_sender.send(data);
         Ok(())
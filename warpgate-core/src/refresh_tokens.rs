@@ -0,0 +1,183 @@
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, ModelTrait, QueryFilter, Set,
+    TransactionTrait,
+};
+use sha2::{Digest, Sha256};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::helpers::hash::generate_ticket_secret;
+use warpgate_common::{HttpConfig, Secret, WarpgateError};
+use warpgate_db_entities::ApiToken::ApiTokenScope;
+use warpgate_db_entities::{RefreshToken, User};
+
+/// A fresh access and refresh token pair. The secrets are only ever
+/// returned here - the database keeps their hashes.
+pub struct IssuedRefreshToken {
+    pub model: RefreshToken::Model,
+    pub access_token: Secret<String>,
+    pub refresh_token: Secret<String>,
+}
+
+pub enum RefreshOutcome {
+    Refreshed(Box<IssuedRefreshToken>),
+    Invalid,
+    /// An already exchanged token was presented again, so it has probably
+    /// leaked. The whole family has been revoked.
+    Reused,
+}
+
+fn token_hash(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
+
+fn new_generation(
+    family_id: Uuid,
+    user_id: Uuid,
+    label: String,
+    scope: Option<ApiTokenScope>,
+    created: DateTime<Utc>,
+    config: &HttpConfig,
+) -> Result<(RefreshToken::ActiveModel, Secret<String>, Secret<String>), WarpgateError> {
+    let now = Utc::now();
+    let lifetime = |x| chrono::Duration::from_std(x).map_err(WarpgateError::other);
+    let access_token = generate_ticket_secret();
+    let refresh_token = generate_ticket_secret();
+    let model = RefreshToken::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        family_id: Set(family_id),
+        user_id: Set(user_id),
+        label: Set(label),
+        secret: Set(token_hash(refresh_token.expose_secret())),
+        access_secret: Set(token_hash(access_token.expose_secret())),
+        access_expiry: Set(now + lifetime(config.access_token_lifetime)?),
+        created: Set(created),
+        refreshed: Set(now),
+        expiry: Set(now + lifetime(config.refresh_token_lifetime)?),
+        used: Set(false),
+        scope: Set(scope),
+    };
+    Ok((model, access_token, refresh_token))
+}
+
+/// Starts a new refresh token family for a user
+pub async fn issue_refresh_token(
+    db: &DatabaseConnection,
+    user_id: Uuid,
+    label: String,
+    scope: Option<ApiTokenScope>,
+    config: &HttpConfig,
+) -> Result<IssuedRefreshToken, WarpgateError> {
+    let (model, access_token, refresh_token) =
+        new_generation(Uuid::new_v4(), user_id, label, scope, Utc::now(), config)?;
+    Ok(IssuedRefreshToken {
+        model: model.insert(db).await?,
+        access_token,
+        refresh_token,
+    })
+}
+
+/// Exchanges a refresh token for a new pair. The old refresh token and
+/// its access token stop working immediately. Fails if the user's account
+/// is no longer active.
+pub async fn rotate_refresh_token(
+    db: &DatabaseConnection,
+    token: &str,
+    config: &HttpConfig,
+) -> Result<RefreshOutcome, WarpgateError> {
+    let Some(current) = RefreshToken::Entity::find()
+        .filter(RefreshToken::Column::Secret.eq(token_hash(token)))
+        .one(db)
+        .await?
+    else {
+        return Ok(RefreshOutcome::Invalid);
+    };
+
+    if current.used {
+        warn!(
+            family=%current.family_id,
+            "A used refresh token was presented again, revoking its family"
+        );
+        revoke_refresh_token_family(db, current.family_id).await?;
+        return Ok(RefreshOutcome::Reused);
+    }
+
+    if current.expiry <= Utc::now() {
+        return Ok(RefreshOutcome::Invalid);
+    }
+
+    let Some(user) = current.find_related(User::Entity).one(db).await? else {
+        return Err(WarpgateError::InconsistentState);
+    };
+    if !user.is_active(Utc::now()) {
+        info!(username=%user.username, "Not refreshing a token of an inactive account");
+        return Ok(RefreshOutcome::Invalid);
+    }
+
+    let (model, access_token, refresh_token) = new_generation(
+        current.family_id,
+        current.user_id,
+        current.label.clone(),
+        current.scope.clone(),
+        current.created,
+        config,
+    )?;
+
+    let txn = db.begin().await?;
+    let mut previous: RefreshToken::ActiveModel = current.into();
+    previous.used = Set(true);
+    previous.update(&txn).await?;
+    let model = model.insert(&txn).await?;
+    txn.commit().await?;
+
+    Ok(RefreshOutcome::Refreshed(Box::new(IssuedRefreshToken {
+        model,
+        access_token,
+        refresh_token,
+    })))
+}
+
+/// Revokes the family a refresh token belongs to.
+/// Returns `false` if the token is unknown.
+pub async fn revoke_refresh_token(
+    db: &DatabaseConnection,
+    token: &str,
+) -> Result<bool, WarpgateError> {
+    let Some(model) = RefreshToken::Entity::find()
+        .filter(RefreshToken::Column::Secret.eq(token_hash(token)))
+        .one(db)
+        .await?
+    else {
+        return Ok(false);
+    };
+    revoke_refresh_token_family(db, model.family_id).await?;
+    Ok(true)
+}
+
+pub async fn revoke_refresh_token_family(
+    db: &DatabaseConnection,
+    family_id: Uuid,
+) -> Result<(), WarpgateError> {
+    RefreshToken::Entity::delete_many()
+        .filter(RefreshToken::Column::FamilyId.eq(family_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Finds the current generation that issued a still valid access token
+pub async fn find_refresh_token_by_access_token(
+    db: &DatabaseConnection,
+    token: &str,
+) -> Result<Option<RefreshToken::Model>, WarpgateError> {
+    Ok(RefreshToken::Entity::find()
+        .filter(
+            RefreshToken::Column::AccessSecret
+                .eq(token_hash(token))
+                .and(RefreshToken::Column::Used.eq(false))
+                .and(RefreshToken::Column::AccessExpiry.gt(Utc::now())),
+        )
+        .one(db)
+        .await?)
+}
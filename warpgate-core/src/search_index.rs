@@ -0,0 +1,301 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use poem_openapi::{Enum, Object};
+use sea_orm::{EntityTrait, QueryOrder, QuerySelect};
+use serde::Serialize;
+use tokio::sync::{broadcast, Mutex};
+use warpgate_common::{Target, WarpgateError};
+use warpgate_db_entities::Session;
+
+use crate::config_check::target_address;
+use crate::{AuthorizationCacheGeneration, ConfigProvider, Services};
+
+/// How many of the latest sessions are searchable
+const INDEXED_SESSIONS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Enum)]
+pub enum SearchResultKind {
+    Page,
+    Target,
+    User,
+    Session,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct SearchResult {
+    pub kind: SearchResultKind,
+    /// Object ID, or the admin UI path for pages
+    pub id: String,
+    pub title: String,
+    pub subtitle: Option<String>,
+}
+
+/// Admin UI pages as (path, title, extra keywords)
+const PAGES: &[(&str, &str, &str)] = &[
+    ("/", "Sessions", "active recent"),
+    ("/log", "Log", "events audit"),
+    (
+        "/auth-failures",
+        "Failed logins",
+        "auth failures attacks brute force",
+    ),
+    ("/config/targets", "Targets", "config servers hosts"),
+    ("/config/users", "Users", "config accounts"),
+    ("/config/roles", "Roles", "config permissions"),
+    ("/config/tickets", "Tickets", "config access"),
+    ("/config/ssh", "SSH keys", "config known hosts host keys"),
+    (
+        "/config/parameters",
+        "Parameters",
+        "config settings self-service credentials",
+    ),
+    ("/config/check", "Configuration check", "config diagnostics"),
+];
+
+struct IndexEntry {
+    result: SearchResult,
+    /// Lowercase fields that the query is matched against, title first
+    fields: Vec<String>,
+}
+
+impl IndexEntry {
+    fn new(result: SearchResult, extra_fields: Vec<String>) -> Self {
+        let mut fields = vec![result.title.to_lowercase()];
+        fields.extend(extra_fields.into_iter().map(|x| x.to_lowercase()));
+        Self { result, fields }
+    }
+
+    /// Every term has to appear in some field. Lower is better.
+    fn rank(&self, terms: &[String]) -> Option<u8> {
+        let mut rank = 0;
+        for term in terms {
+            let term_rank = self
+                .fields
+                .iter()
+                .enumerate()
+                .filter_map(|(index, field)| {
+                    let in_title = index == 0;
+                    if field == term && in_title {
+                        Some(0)
+                    } else if field.starts_with(term.as_str()) {
+                        Some(if in_title { 1 } else { 2 })
+                    } else if field.contains(term.as_str()) {
+                        Some(3)
+                    } else {
+                        None
+                    }
+                })
+                .min()?;
+            rank = rank.max(term_rank);
+        }
+        Some(rank)
+    }
+}
+
+#[derive(Default)]
+struct SearchIndexInner {
+    /// Pages, targets and users, and the authorization cache generation
+    /// they were loaded at
+    config: Option<(AuthorizationCacheGeneration, Vec<IndexEntry>)>,
+    sessions: Option<Vec<IndexEntry>>,
+}
+
+/// In-memory index behind the admin UI's search. Config objects are
+/// reloaded whenever the authorization cache is invalidated, and
+/// sessions whenever the session state changes.
+#[derive(Default)]
+pub struct SearchIndex {
+    inner: Mutex<SearchIndexInner>,
+    sessions_changed: AtomicBool,
+}
+
+impl SearchIndex {
+    /// Marks sessions as changed on every state change
+    pub fn watch_sessions(self: &Arc<Self>, mut changes: broadcast::Receiver<()>) {
+        let this = Arc::downgrade(self);
+        tokio::spawn(async move {
+            loop {
+                if let Err(broadcast::error::RecvError::Closed) = changes.recv().await {
+                    break;
+                }
+                let Some(this) = this.upgrade() else {
+                    break;
+                };
+                this.sessions_changed.store(true, Ordering::Relaxed);
+            }
+        });
+    }
+
+    pub async fn search(
+        &self,
+        services: &Services,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>, WarpgateError> {
+        let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        if terms.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut inner = self.inner.lock().await;
+
+        let generation = services.authorization_cache.generation();
+        if inner.config.as_ref().is_none_or(|x| x.0 != generation) {
+            inner.config = Some((generation, load_config_entries(services).await?));
+        }
+        if self.sessions_changed.swap(false, Ordering::Relaxed) || inner.sessions.is_none() {
+            inner.sessions = Some(load_session_entries(services).await?);
+        }
+
+        let mut results: Vec<_> = inner
+            .config
+            .iter()
+            .flat_map(|x| &x.1)
+            .chain(inner.sessions.iter().flatten())
+            .filter_map(|entry| Some((entry.rank(&terms)?, &entry.result)))
+            .collect();
+        // Sessions keep their most-recent-first order within a rank
+        results.sort_by_key(|(rank, result)| (*rank, result.kind));
+        Ok(results
+            .into_iter()
+            .take(limit)
+            .map(|(_, result)| result.clone())
+            .collect())
+    }
+}
+
+fn target_entry(target: Target) -> IndexEntry {
+    let address = target_address(&target).map(|(host, port)| format!("{host}:{port}"));
+    let kind = target.options.protocol_name();
+    IndexEntry::new(
+        SearchResult {
+            kind: SearchResultKind::Target,
+            id: target.id.to_string(),
+            subtitle: Some(match address {
+                Some(ref address) => format!("{kind} · {address}"),
+                None => kind.to_string(),
+            }),
+            title: target.name,
+        },
+        address.into_iter().collect(),
+    )
+}
+
+async fn load_config_entries(services: &Services) -> Result<Vec<IndexEntry>, WarpgateError> {
+    let (targets, users) = {
+        let mut config_provider = services.config_provider.lock().await;
+        (
+            config_provider.list_targets().await?,
+            config_provider.list_users().await?,
+        )
+    };
+
+    let mut entries: Vec<_> = PAGES
+        .iter()
+        .map(|(path, title, keywords)| {
+            IndexEntry::new(
+                SearchResult {
+                    kind: SearchResultKind::Page,
+                    id: (*path).into(),
+                    title: (*title).into(),
+                    subtitle: None,
+                },
+                keywords.split(' ').map(Into::into).collect(),
+            )
+        })
+        .collect();
+    entries.extend(targets.into_iter().map(target_entry));
+    entries.extend(users.into_iter().map(|user| {
+        IndexEntry::new(
+            SearchResult {
+                kind: SearchResultKind::User,
+                id: user.id.to_string(),
+                title: user.username,
+                subtitle: user.deactivated.map(|_| "Deactivated".into()),
+            },
+            vec![],
+        )
+    }));
+    Ok(entries)
+}
+
+async fn load_session_entries(services: &Services) -> Result<Vec<IndexEntry>, WarpgateError> {
+    let sessions = {
+        let db = services.read_db.lock().await;
+        Session::Entity::find()
+            .order_by_desc(Session::Column::Started)
+            .limit(INDEXED_SESSIONS)
+            .all(&*db)
+            .await?
+    };
+
+    Ok(sessions
+        .into_iter()
+        .map(|session| {
+            let target_name = session
+                .target_snapshot
+                .as_deref()
+                .and_then(|x| serde_json::from_str::<Target>(x).ok())
+                .map(|x| x.name);
+            let title = format!(
+                "{} → {}",
+                session.username.as_deref().unwrap_or("<unknown>"),
+                target_name.as_deref().unwrap_or("<no target>")
+            );
+            let subtitle = format!(
+                "{} from {} · {}",
+                session.protocol,
+                session.remote_address,
+                session.started.format("%Y-%m-%d %H:%M")
+            );
+            let mut fields = vec![session.id.to_string(), session.remote_address.clone()];
+            fields.extend(session.username);
+            fields.extend(target_name);
+            fields.extend(session.work_item);
+            IndexEntry::new(
+                SearchResult {
+                    kind: SearchResultKind::Session,
+                    id: session.id.to_string(),
+                    title,
+                    subtitle: Some(subtitle),
+                },
+                fields,
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, fields: &[&str]) -> IndexEntry {
+        IndexEntry::new(
+            SearchResult {
+                kind: SearchResultKind::Target,
+                id: title.into(),
+                title: title.into(),
+                subtitle: None,
+            },
+            fields.iter().map(|x| (*x).into()).collect(),
+        )
+    }
+
+    fn terms(query: &str) -> Vec<String> {
+        query.split_whitespace().map(str::to_lowercase).collect()
+    }
+
+    #[test]
+    fn test_rank() {
+        let db = entry("db", &["10.0.0.5:5432"]);
+        let db_prod = entry("DB-Prod", &["db-prod.internal:5432"]);
+
+        assert_eq!(db.rank(&terms("db")), Some(0));
+        assert_eq!(db_prod.rank(&terms("db")), Some(1));
+        assert_eq!(db_prod.rank(&terms("prod")), Some(3));
+        assert_eq!(db.rank(&terms("10.0")), Some(2));
+        assert_eq!(db_prod.rank(&terms("DB 5432")), Some(3));
+        assert_eq!(db.rank(&terms("db prod")), None);
+    }
+}
@@ -1,26 +1,62 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use sea_orm::DatabaseConnection;
 use tokio::sync::Mutex;
+use tracing::*;
 use warpgate_common::{ConfigProviderKind, WarpgateConfig};
 
-use crate::db::{connect_to_db, populate_db};
-use crate::recordings::SessionRecordings;
-use crate::{AuthStateStore, ConfigProviderEnum, DatabaseConfigProvider, State};
+use crate::db::{connect_to_db, connect_to_read_replica, populate_db, ReadOnlyDatabase};
+use crate::recordings::{RecordingQuotas, SessionRecordings};
+use crate::{
+    Alerts, AnalyticsSinkHandle, AuthFailureLog, AuthRateLimiter, AuthStateStore,
+    AuthorizationCache, ConfigProviderEnum, DatabaseConfigProvider, DirectCredentialProvisioners,
+    DnsResolver, DropBoxAuditLog, IpBanList, Jobs, Notifications, SearchIndex, SessionReaper,
+    ShadowReports, SharedConfig, State, TargetCapabilities, TargetDiscovery, TargetFingerprints,
+    TargetHealthChecker, TargetMonitor, UsageAnalytics,
+};
 
 type ConfigProviderArc = Arc<Mutex<ConfigProviderEnum>>;
 
+/// Picks up changes made by other instances
+const IP_BAN_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Catches up with deleted recordings and ones that grew
+/// without being counted, e.g. by other instances
+const RECORDING_QUOTA_RESCAN_INTERVAL: Duration = Duration::from_secs(600);
+
 #[derive(Clone)]
 pub struct Services {
     pub db: Arc<Mutex<DatabaseConnection>>,
+    pub read_db: ReadOnlyDatabase,
     pub recordings: Arc<Mutex<SessionRecordings>>,
-    pub config: Arc<Mutex<WarpgateConfig>>,
+    pub recording_quotas: Arc<RecordingQuotas>,
+    pub config: Arc<SharedConfig>,
     pub state: Arc<Mutex<State>>,
     pub config_provider: ConfigProviderArc,
+    pub authorization_cache: Arc<AuthorizationCache>,
     pub auth_state_store: Arc<Mutex<AuthStateStore>>,
+    pub auth_failures: Arc<AuthFailureLog>,
+    pub auth_rate_limiter: Arc<AuthRateLimiter>,
+    pub ip_bans: Arc<IpBanList>,
     pub admin_token: Arc<Mutex<Option<String>>>,
+    pub analytics: Arc<UsageAnalytics>,
+    pub analytics_sink: Option<AnalyticsSinkHandle>,
+    pub reaper: Arc<Mutex<SessionReaper>>,
+    pub discovery: Arc<Mutex<TargetDiscovery>>,
+    pub shadow_reports: Arc<ShadowReports>,
+    pub target_health: Arc<TargetHealthChecker>,
+    pub target_monitor: Arc<TargetMonitor>,
+    pub target_fingerprints: Arc<TargetFingerprints>,
+    pub target_capabilities: Arc<TargetCapabilities>,
+    pub alerts: Alerts,
+    pub search_index: Arc<SearchIndex>,
+    pub dns: Arc<DnsResolver>,
+    pub jobs: Arc<Jobs>,
+    pub notifications: Arc<Notifications>,
+    pub direct_credential_provisioners: Arc<DirectCredentialProvisioners>,
+    pub drop_box_audit: Arc<DropBoxAuditLog>,
 }
 
 impl Services {
@@ -28,21 +64,49 @@ impl Services {
         let mut db = connect_to_db(&config).await?;
         populate_db(&mut db, &mut config).await?;
         let db = Arc::new(Mutex::new(db));
-
-        let recordings = SessionRecordings::new(db.clone(), &config)?;
-        let recordings = Arc::new(Mutex::new(recordings));
+        let read_db = ReadOnlyDatabase::new(&db, connect_to_read_replica(&config).await?);
 
         let provider = config.store.config_provider.clone();
-        let config = Arc::new(Mutex::new(config));
+        let analytics_sink_config = config.store.log.analytics_sink.clone();
+        let config = Arc::new(SharedConfig::new(config));
+        let alerts = Alerts::new(config.clone()).await;
+
+        let notifications = Arc::new(Notifications::new(db.clone()));
+        tokio::spawn(
+            notifications
+                .clone()
+                .collect_alerts(alerts.subscribe().await),
+        );
+
+        let recording_quotas = Arc::new(RecordingQuotas::new(config.clone(), alerts.clone()));
+        let recordings =
+            SessionRecordings::new(db.clone(), &config.load(), recording_quotas.clone())?;
+
+        tokio::spawn({
+            let recording_quotas = recording_quotas.clone();
+            let db = db.clone();
+            let root = recordings.root().to_owned();
+            async move {
+                loop {
+                    if let Err(error) = recording_quotas.rescan(&db, &root).await {
+                        error!(?error, "Failed to count recording storage usage");
+                    }
+                    tokio::time::sleep(RECORDING_QUOTA_RESCAN_INTERVAL).await;
+                }
+            }
+        });
+        let recordings = Arc::new(Mutex::new(recordings));
+        let authorization_cache = Arc::new(AuthorizationCache::new());
 
         let config_provider = match provider {
             ConfigProviderKind::File => {
                 anyhow::bail!("File based config provider in no longer supported");
             }
-            ConfigProviderKind::Database => {
-                Arc::new(Mutex::new(DatabaseConfigProvider::new(&db).await.into()))
-                    as ConfigProviderArc
-            }
+            ConfigProviderKind::Database => Arc::new(Mutex::new(
+                DatabaseConfigProvider::new(&db, authorization_cache.clone())
+                    .await
+                    .into(),
+            )) as ConfigProviderArc,
         };
 
         let auth_state_store = Arc::new(Mutex::new(AuthStateStore::new(config_provider.clone())));
@@ -57,14 +121,69 @@ impl Services {
             }
         });
 
+        let ip_bans = Arc::new(IpBanList::new(db.clone()).await?);
+        tokio::spawn({
+            let ip_bans = ip_bans.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(IP_BAN_RELOAD_INTERVAL).await;
+                    if let Err(error) = ip_bans.reload().await {
+                        error!(?error, "Failed to reload IP bans");
+                    }
+                }
+            }
+        });
+
+        let analytics_sink = match analytics_sink_config {
+            Some(ref sink_config) => Some(
+                AnalyticsSinkHandle::connect(sink_config)
+                    .await
+                    .context("Could not connect to the analytics sink")?,
+            ),
+            None => None,
+        };
+
+        let analytics = Arc::new(UsageAnalytics::new(&read_db, analytics_sink.clone()));
+
+        let state = State::new(&db, analytics_sink.clone(), recording_quotas.clone());
+        let search_index = Arc::new(SearchIndex::default());
+        search_index.watch_sessions(state.lock().await.subscribe());
+        let reaper = Arc::new(Mutex::new(SessionReaper::new(
+            db.clone(),
+            state.clone(),
+            recordings.clone(),
+        )));
+
         Ok(Self {
             db: db.clone(),
+            read_db,
             recordings,
+            recording_quotas,
             config: config.clone(),
-            state: State::new(&db),
+            state,
             config_provider,
+            authorization_cache,
             auth_state_store,
+            auth_failures: Arc::new(AuthFailureLog::new(db.clone(), config.clone())),
+            auth_rate_limiter: Arc::new(AuthRateLimiter::new(db.clone(), config.clone())),
+            ip_bans,
             admin_token: Arc::new(Mutex::new(admin_token)),
+            analytics,
+            analytics_sink,
+            reaper,
+            discovery: Arc::new(Mutex::new(TargetDiscovery::new())),
+            shadow_reports: Arc::new(ShadowReports::default()),
+            target_health: Arc::new(TargetHealthChecker::default()),
+            target_monitor: Arc::new(TargetMonitor::default()),
+            target_fingerprints: Arc::new(TargetFingerprints::default()),
+            target_capabilities: Arc::new(TargetCapabilities::default()),
+            alerts,
+            search_index,
+            dns: Arc::new(DnsResolver::new(&config.load().store.dns)),
+            jobs: Arc::new(Jobs::default()),
+            notifications,
+            direct_credential_provisioners: Arc::new(DirectCredentialProvisioners::default()),
+            drop_box_audit: Arc::new(DropBoxAuditLog::new(db.clone())),
         })
     }
 }
@@ -0,0 +1,295 @@
+use chrono::{DateTime, Utc};
+use data_encoding::HEXLOWER;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::{
+    DatabaseSessionDetails, HttpSessionDetails, LogEntry, Recording, Session, SshSessionDetails,
+};
+
+use crate::recordings::SessionRecordings;
+use crate::SessionSnapshot;
+
+pub const SESSION_BUNDLE_MANIFEST: &str = "manifest.json";
+pub const SESSION_BUNDLE_SIGNATURE: &str = "manifest.json.sig";
+pub const SESSION_BUNDLE_SIGNER: &str = "signer.pub";
+
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+const TAR_BLOCK_SIZE: usize = 512;
+
+#[derive(Serialize)]
+struct BundleManifest<'a> {
+    format: u32,
+    session_id: Uuid,
+    created: DateTime<Utc>,
+    exported_by: Option<&'a str>,
+    files: Vec<BundleFile<'a>>,
+    /// Recordings whose files were already gone
+    missing_recordings: &'a [String],
+}
+
+#[derive(Serialize)]
+struct BundleFile<'a> {
+    path: &'a str,
+    size: usize,
+    sha256: String,
+}
+
+/// Everything stored about a single session, for handing over to auditors:
+/// the session with its protocol details, its log (including the auth
+/// events), the recording metadata and the recording files themselves.
+/// Packed as a tar archive whose manifest lists the SHA-256 of every file
+/// and is signed by the caller.
+pub struct SessionBundle {
+    session_id: Uuid,
+    files: Vec<(String, Vec<u8>)>,
+    missing_recordings: Vec<String>,
+}
+
+impl SessionBundle {
+    pub async fn collect(
+        db: &DatabaseConnection,
+        recordings: &SessionRecordings,
+        session_id: Uuid,
+    ) -> Result<Option<Self>, WarpgateError> {
+        let Some(session) = Session::Entity::find_by_id(session_id).one(db).await? else {
+            return Ok(None);
+        };
+        let mut snapshot: SessionSnapshot = session.into();
+        snapshot.ssh_details = SshSessionDetails::Entity::find_by_id(session_id)
+            .one(db)
+            .await?;
+        snapshot.http_details = HttpSessionDetails::Entity::find_by_id(session_id)
+            .one(db)
+            .await?;
+        snapshot.database_details = DatabaseSessionDetails::Entity::find_by_id(session_id)
+            .one(db)
+            .await?;
+
+        let log = LogEntry::Entity::find()
+            .filter(LogEntry::Column::SessionId.eq(session_id))
+            .order_by_asc(LogEntry::Column::Timestamp)
+            .all(db)
+            .await?;
+        let recording_models = Recording::Entity::find()
+            .filter(Recording::Column::SessionId.eq(session_id))
+            .order_by_asc(Recording::Column::Started)
+            .all(db)
+            .await?;
+
+        let mut bundle = Self {
+            session_id,
+            files: vec![],
+            missing_recordings: vec![],
+        };
+        bundle.add_json("session.json", &snapshot)?;
+        bundle.add_json("log.json", &log)?;
+        bundle.add_json("recordings.json", &recording_models)?;
+
+        for recording in recording_models {
+            let path = recordings.path_for(&session_id, &recording.name);
+            match tokio::fs::read(&path).await {
+                Ok(data) => bundle
+                    .files
+                    .push((format!("recordings/{}", recording.name), data)),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    warn!(recording=%recording.id, "Recording file is missing from the bundle");
+                    bundle.missing_recordings.push(recording.name);
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        Ok(Some(bundle))
+    }
+
+    fn add_json<T: Serialize>(&mut self, path: &str, value: &T) -> Result<(), WarpgateError> {
+        self.files
+            .push((path.into(), serde_json::to_vec_pretty(value)?));
+        Ok(())
+    }
+
+    /// `sign` receives the manifest and returns the signer's public key
+    /// and the signature, which are stored next to it
+    pub fn finish<F>(self, exported_by: Option<&str>, sign: F) -> Result<Vec<u8>, WarpgateError>
+    where
+        F: FnOnce(&[u8]) -> Result<(String, String), WarpgateError>,
+    {
+        let manifest = serde_json::to_vec_pretty(&BundleManifest {
+            format: BUNDLE_FORMAT_VERSION,
+            session_id: self.session_id,
+            created: Utc::now(),
+            exported_by,
+            files: self
+                .files
+                .iter()
+                .map(|(path, data)| BundleFile {
+                    path,
+                    size: data.len(),
+                    sha256: HEXLOWER.encode(&Sha256::digest(data)),
+                })
+                .collect(),
+            missing_recordings: &self.missing_recordings,
+        })?;
+        let (signer, signature) = sign(&manifest)?;
+
+        let mtime = Utc::now().timestamp().max(0) as u64;
+        let mut archive = vec![];
+        write_tar_entry(&mut archive, SESSION_BUNDLE_MANIFEST, &manifest, mtime)?;
+        write_tar_entry(
+            &mut archive,
+            SESSION_BUNDLE_SIGNATURE,
+            signature.as_bytes(),
+            mtime,
+        )?;
+        write_tar_entry(
+            &mut archive,
+            SESSION_BUNDLE_SIGNER,
+            signer.as_bytes(),
+            mtime,
+        )?;
+        for (path, data) in &self.files {
+            write_tar_entry(&mut archive, path, data, mtime)?;
+        }
+        // End of archive marker
+        archive.resize(archive.len() + TAR_BLOCK_SIZE * 2, 0);
+        Ok(archive)
+    }
+}
+
+/// Appends a regular file in the ustar format
+fn write_tar_entry(
+    archive: &mut Vec<u8>,
+    path: &str,
+    data: &[u8],
+    mtime: u64,
+) -> Result<(), WarpgateError> {
+    if path.len() > 100 {
+        return Err(WarpgateError::Other(
+            format!("bundle path is too long: {path}").into(),
+        ));
+    }
+
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    set_tar_field(&mut header, 0, path.as_bytes());
+    set_tar_field(&mut header, 100, b"0000644\0");
+    set_tar_field(&mut header, 108, b"0000000\0");
+    set_tar_field(&mut header, 116, b"0000000\0");
+    set_tar_field(
+        &mut header,
+        124,
+        format!("{:011o}\0", data.len()).as_bytes(),
+    );
+    set_tar_field(&mut header, 136, format!("{mtime:011o}\0").as_bytes());
+    // The checksum is calculated with its own field set to spaces
+    set_tar_field(&mut header, 148, b"        ");
+    set_tar_field(&mut header, 156, b"0");
+    set_tar_field(&mut header, 257, b"ustar\0");
+    set_tar_field(&mut header, 263, b"00");
+    let checksum: u32 = header.iter().map(|x| u32::from(*x)).sum();
+    set_tar_field(&mut header, 148, format!("{checksum:06o}\0 ").as_bytes());
+
+    archive.extend_from_slice(&header);
+    archive.extend_from_slice(data);
+    let padding = (TAR_BLOCK_SIZE - data.len() % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+    archive.resize(archive.len() + padding, 0);
+    Ok(())
+}
+
+fn set_tar_field(header: &mut [u8], offset: usize, value: &[u8]) {
+    if let Some(field) = header.get_mut(offset..offset + value.len()) {
+        field.copy_from_slice(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn parse_octal(field: &[u8]) -> u64 {
+        let text = std::str::from_utf8(field).unwrap();
+        u64::from_str_radix(text.trim_matches(['\0', ' ']), 8).unwrap()
+    }
+
+    /// Walks the archive the way `tar` does
+    fn read_tar(archive: &[u8]) -> BTreeMap<String, Vec<u8>> {
+        let mut entries = BTreeMap::new();
+        let mut offset = 0;
+        loop {
+            let header = &archive[offset..offset + TAR_BLOCK_SIZE];
+            if header.iter().all(|x| *x == 0) {
+                break;
+            }
+            let mut blank = header.to_vec();
+            blank[148..156].copy_from_slice(b"        ");
+            let checksum: u64 = blank.iter().map(|x| u64::from(*x)).sum();
+            assert_eq!(parse_octal(&header[148..156]), checksum);
+            assert_eq!(&header[257..263], b"ustar\0");
+
+            let name_len = header[..100].iter().position(|x| *x == 0).unwrap_or(100);
+            let name = String::from_utf8(header[..name_len].to_vec()).unwrap();
+            let size = parse_octal(&header[124..136]) as usize;
+            offset += TAR_BLOCK_SIZE;
+            assert!(entries
+                .insert(name, archive[offset..offset + size].to_vec())
+                .is_none());
+            offset += size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE;
+        }
+        assert_eq!(archive.len(), offset + TAR_BLOCK_SIZE * 2);
+        entries
+    }
+
+    #[test]
+    fn test_finish() {
+        let bundle = SessionBundle {
+            session_id: Uuid::new_v4(),
+            files: vec![
+                ("session.json".into(), b"{}".to_vec()),
+                ("recordings/shell".into(), vec![7; 1000]),
+            ],
+            missing_recordings: vec!["sftp".into()],
+        };
+        let mut signed = vec![];
+        let archive = bundle
+            .finish(Some("admin"), |manifest| {
+                signed = manifest.to_vec();
+                Ok(("ssh-ed25519 AAAA".into(), "SIGNATURE".into()))
+            })
+            .unwrap();
+
+        let entries = read_tar(&archive);
+        let names: Vec<_> = entries.keys().map(String::as_str).collect();
+        let mut expected = [
+            SESSION_BUNDLE_MANIFEST,
+            SESSION_BUNDLE_SIGNATURE,
+            SESSION_BUNDLE_SIGNER,
+            "session.json",
+            "recordings/shell",
+        ];
+        expected.sort_unstable();
+        assert_eq!(names, expected);
+        assert_eq!(entries[SESSION_BUNDLE_MANIFEST], signed);
+        assert_eq!(entries[SESSION_BUNDLE_SIGNATURE], b"SIGNATURE");
+        assert_eq!(entries["recordings/shell"], vec![7; 1000]);
+
+        let manifest: serde_json::Value = serde_json::from_slice(&signed).unwrap();
+        assert_eq!(manifest["exported_by"], "admin");
+        assert_eq!(manifest["missing_recordings"][0], "sftp");
+        let shell = manifest["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["path"] == "recordings/shell")
+            .unwrap();
+        assert_eq!(shell["size"], 1000);
+        assert_eq!(
+            shell["sha256"],
+            HEXLOWER.encode(&Sha256::digest(vec![7; 1000]))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Enum)]
+pub enum SessionChannelKind {
+    Session,
+    DirectTcpIp,
+    ForwardedTcpIp,
+    X11,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SessionChannelPty {
+    pub term: String,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct SessionChannel {
+    pub id: Uuid,
+    /// Channel number on the client's side of the connection
+    pub client_channel: u32,
+    pub kind: SessionChannelKind,
+    /// Shell, command, subsystem or forwarding destination
+    pub description: Option<String>,
+    pub pty: Option<SessionChannelPty>,
+    pub opened: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    /// From the client to the target
+    pub bytes_sent: u64,
+    /// From the target to the client
+    pub bytes_received: u64,
+    pub client_eof: bool,
+    pub target_eof: bool,
+    /// The client has closed the channel, but the target hasn't yet
+    pub closing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Object)]
+pub struct SessionRemoteForward {
+    pub address: String,
+    pub port: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct SessionChannelsSnapshot {
+    pub channels: Vec<SessionChannel>,
+    pub remote_forwards: Vec<SessionRemoteForward>,
+}
+
+/// Live channel state of a session, updated by the protocol server.
+/// Cheap to clone - all clones share the same state, so it can be read
+/// even while the session itself is stuck.
+#[derive(Clone, Default)]
+pub struct SessionChannels {
+    inner: Arc<Mutex<SessionChannelsSnapshot>>,
+}
+
+impl SessionChannels {
+    fn lock(&self) -> MutexGuard<'_, SessionChannelsSnapshot> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut SessionChannel)) {
+        if let Some(channel) = self.lock().channels.iter_mut().find(|x| x.id == id) {
+            f(channel);
+        }
+    }
+
+    pub fn snapshot(&self) -> SessionChannelsSnapshot {
+        self.lock().clone()
+    }
+
+    pub fn open(
+        &self,
+        id: Uuid,
+        client_channel: u32,
+        kind: SessionChannelKind,
+        description: Option<String>,
+    ) {
+        let now = Utc::now();
+        let mut inner = self.lock();
+        inner.channels.retain(|x| x.id != id);
+        inner.channels.push(SessionChannel {
+            id,
+            client_channel,
+            kind,
+            description,
+            pty: None,
+            opened: now,
+            last_activity: now,
+            bytes_sent: 0,
+            bytes_received: 0,
+            client_eof: false,
+            target_eof: false,
+            closing: false,
+        });
+    }
+
+    pub fn set_description(&self, id: Uuid, description: String) {
+        self.update(id, |channel| channel.description = Some(description));
+    }
+
+    pub fn set_pty(&self, id: Uuid, term: &str, columns: u32, rows: u32) {
+        self.update(id, |channel| {
+            channel.pty = Some(SessionChannelPty {
+                term: term.to_owned(),
+                columns,
+                rows,
+            })
+        });
+    }
+
+    pub fn record_sent(&self, id: Uuid, bytes: usize) {
+        self.update(id, |channel| {
+            channel.bytes_sent += bytes as u64;
+            channel.last_activity = Utc::now();
+        });
+    }
+
+    pub fn record_received(&self, id: Uuid, bytes: usize) {
+        self.update(id, |channel| {
+            channel.bytes_received += bytes as u64;
+            channel.last_activity = Utc::now();
+        });
+    }
+
+    pub fn client_eof(&self, id: Uuid) {
+        self.update(id, |channel| channel.client_eof = true);
+    }
+
+    pub fn target_eof(&self, id: Uuid) {
+        self.update(id, |channel| channel.target_eof = true);
+    }
+
+    pub fn closing(&self, id: Uuid) {
+        self.update(id, |channel| channel.closing = true);
+    }
+
+    pub fn close(&self, id: Uuid) {
+        self.lock().channels.retain(|x| x.id != id);
+    }
+
+    pub fn add_remote_forward(&self, address: String, port: u32) {
+        let forward = SessionRemoteForward { address, port };
+        let mut inner = self.lock();
+        if !inner.remote_forwards.contains(&forward) {
+            inner.remote_forwards.push(forward);
+        }
+    }
+
+    pub fn remove_remote_forward(&self, address: &str, port: u32) {
+        self.lock()
+            .remote_forwards
+            .retain(|x| x.address != address || x.port != port);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_lifecycle() {
+        let channels = SessionChannels::default();
+        let id = Uuid::new_v4();
+        channels.open(id, 0, SessionChannelKind::Session, None);
+        channels.set_pty(id, "xterm", 80, 24);
+        channels.record_sent(id, 3);
+        channels.record_received(id, 10);
+        channels.record_received(Uuid::new_v4(), 100);
+        channels.closing(id);
+
+        let snapshot = channels.clone().snapshot();
+        assert_eq!(snapshot.channels.len(), 1);
+        let channel = &snapshot.channels[0];
+        assert_eq!(channel.bytes_sent, 3);
+        assert_eq!(channel.bytes_received, 10);
+        assert_eq!(channel.pty.as_ref().map(|x| x.columns), Some(80));
+        assert!(channel.closing);
+
+        channels.close(id);
+        assert!(channels.snapshot().channels.is_empty());
+
+        channels.add_remote_forward("0.0.0.0".into(), 8080);
+        channels.add_remote_forward("0.0.0.0".into(), 8080);
+        assert_eq!(channels.snapshot().remote_forwards.len(), 1);
+        channels.remove_remote_forward("0.0.0.0", 8080);
+        assert!(channels.snapshot().remote_forwards.is_empty());
+    }
+}
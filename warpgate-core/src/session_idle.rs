@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use humantime_serde::re::humantime::format_duration;
+use tracing::*;
+use warpgate_common::Target;
+use warpgate_db_entities::Session::SessionTerminationReason;
+
+use crate::Services;
+
+pub const IDLE_SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// When the client last sent anything through the session. Cheap to clone
+/// and to update, so protocol servers can touch it for every message.
+#[derive(Clone, Debug)]
+pub struct SessionLastActivity(Arc<AtomicI64>);
+
+impl Default for SessionLastActivity {
+    fn default() -> Self {
+        Self(Arc::new(AtomicI64::new(Utc::now().timestamp_millis())))
+    }
+}
+
+impl SessionLastActivity {
+    pub fn touch(&self) {
+        self.0
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.0.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+}
+
+/// The target's setting wins over the global one, and `0` turns
+/// the timeout off for the target
+fn idle_timeout(target: Option<&Target>, global: Option<Duration>) -> Option<Duration> {
+    match target.and_then(|t| t.idle_timeout_seconds) {
+        Some(seconds) => Some(Duration::from_secs(seconds.into())),
+        None => global,
+    }
+    .filter(|x| !x.is_zero())
+}
+
+/// Asks sessions that have been idle for longer than their timeout
+/// to close. Returns the number of sessions closed.
+pub async fn close_idle_sessions(services: &Services) -> usize {
+    let global = services.config.load().store.idle_timeout;
+    let now = Utc::now();
+    let mut closed = 0;
+    for (id, session) in services.state.lock().await.sessions.iter() {
+        let mut session = session.lock().await;
+        if session.termination_reason.is_some() {
+            continue;
+        }
+        let Some(timeout) = idle_timeout(session.target.as_ref(), global) else {
+            continue;
+        };
+        let idle = (now - session.last_activity.get())
+            .to_std()
+            .unwrap_or_default();
+        if idle < timeout {
+            continue;
+        }
+        info!(session=%id, idle=%format_duration(idle), "Closing an idle session");
+        session.terminate_with_message(
+            SessionTerminationReason::IdleTimeout,
+            Some(format!(
+                "Session closed after {} of inactivity",
+                format_duration(timeout)
+            )),
+        );
+        closed += 1;
+    }
+    closed
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::{TargetOptions, TargetWebAdminOptions};
+
+    use super::*;
+
+    #[test]
+    fn test_idle_timeout() {
+        let global = Some(Duration::from_secs(600));
+        let target = |idle_timeout_seconds| Target {
+            id: Default::default(),
+            name: "db".into(),
+            allow_roles: vec![],
+            honeypot: false,
+            host_overrides: Default::default(),
+            max_concurrent_sessions: None,
+            session_limit_message: None,
+            monitor: None,
+            idle_timeout_seconds,
+            draining: false,
+            options: TargetOptions::WebAdmin(TargetWebAdminOptions {}),
+        };
+
+        assert_eq!(idle_timeout(None, global), global);
+        assert_eq!(idle_timeout(Some(&target(None)), global), global);
+        assert_eq!(idle_timeout(Some(&target(None)), None), None);
+        assert_eq!(
+            idle_timeout(Some(&target(Some(60))), global),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            idle_timeout(Some(&target(Some(60))), None),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(idle_timeout(Some(&target(Some(0))), global), None);
+    }
+}
@@ -0,0 +1,206 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use warpgate_common::helpers::hash::generate_ticket_secret;
+use warpgate_common::Secret;
+
+/// A time-limited, read-only link to a live session
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct SessionShare {
+    pub id: Uuid,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+}
+
+/// Someone currently watching the session through a [SessionShare]
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct SessionViewer {
+    pub id: Uuid,
+    pub share_id: Uuid,
+    pub username: String,
+    pub joined: DateTime<Utc>,
+}
+
+/// A viewer temporarily allowed to type into the session
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct SessionController {
+    pub viewer_id: Uuid,
+    pub username: String,
+    pub expires: DateTime<Utc>,
+}
+
+fn secret_hash(secret: &str) -> Vec<u8> {
+    Sha256::digest(secret.as_bytes()).to_vec()
+}
+
+/// Watch links issued by the session's owner. Lives in [crate::SessionState],
+/// so all links stop working once the session ends.
+#[derive(Default)]
+pub struct SessionShares {
+    /// Only secret hashes are kept, so that looking one up
+    /// doesn't leak the secret through timing
+    shares: Vec<(SessionShare, Vec<u8>)>,
+    viewers: Vec<SessionViewer>,
+    controller: Option<SessionController>,
+}
+
+impl SessionShares {
+    pub fn create(&mut self, expires: DateTime<Utc>) -> (SessionShare, Secret<String>) {
+        self.prune();
+        let share = SessionShare {
+            id: Uuid::new_v4(),
+            created: Utc::now(),
+            expires,
+        };
+        let secret = generate_ticket_secret();
+        self.shares
+            .push((share.clone(), secret_hash(secret.expose_secret())));
+        (share, secret)
+    }
+
+    pub fn list(&mut self) -> Vec<SessionShare> {
+        self.prune();
+        self.shares.iter().map(|(share, _)| share.clone()).collect()
+    }
+
+    pub fn revoke(&mut self, id: &Uuid) -> Option<SessionShare> {
+        let index = self.shares.iter().position(|(share, _)| &share.id == id)?;
+        Some(self.shares.remove(index).0)
+    }
+
+    /// Finds the unexpired share that `secret` belongs to
+    pub fn authorize(&mut self, secret: &str) -> Option<SessionShare> {
+        self.prune();
+        let hash = secret_hash(secret);
+        self.shares
+            .iter()
+            .find(|(_, x)| *x == hash)
+            .map(|(share, _)| share.clone())
+    }
+
+    pub fn join(&mut self, share_id: Uuid, username: String) -> SessionViewer {
+        let viewer = SessionViewer {
+            id: Uuid::new_v4(),
+            share_id,
+            username,
+            joined: Utc::now(),
+        };
+        self.viewers.push(viewer.clone());
+        viewer
+    }
+
+    /// Returns the viewer's input control if they had it
+    pub fn leave(&mut self, viewer_id: &Uuid) -> Option<SessionController> {
+        self.viewers.retain(|x| &x.id != viewer_id);
+        if self.controller.as_ref().map(|x| &x.viewer_id) == Some(viewer_id) {
+            return self.controller.take();
+        }
+        None
+    }
+
+    pub fn viewers(&self) -> Vec<SessionViewer> {
+        self.viewers.clone()
+    }
+
+    /// Hands input control to a connected viewer, replacing the current controller
+    pub fn grant_control(
+        &mut self,
+        viewer_id: &Uuid,
+        expires: DateTime<Utc>,
+    ) -> Option<SessionController> {
+        let viewer = self.viewers.iter().find(|x| &x.id == viewer_id)?;
+        let controller = SessionController {
+            viewer_id: viewer.id,
+            username: viewer.username.clone(),
+            expires,
+        };
+        self.controller = Some(controller.clone());
+        Some(controller)
+    }
+
+    pub fn revoke_control(&mut self) -> Option<SessionController> {
+        self.controller.take()
+    }
+
+    pub fn controller(&self) -> Option<SessionController> {
+        self.controller.clone().filter(|x| x.expires > Utc::now())
+    }
+
+    pub fn has_control(&self, viewer_id: &Uuid) -> bool {
+        self.controller().is_some_and(|x| &x.viewer_id == viewer_id)
+    }
+
+    /// Clears input control once it has expired and returns the expired controller
+    pub fn take_expired_control(&mut self) -> Option<SessionController> {
+        if self
+            .controller
+            .as_ref()
+            .is_some_and(|x| x.expires <= Utc::now())
+        {
+            return self.controller.take();
+        }
+        None
+    }
+
+    fn prune(&mut self) {
+        let now = Utc::now();
+        self.shares.retain(|(share, _)| share.expires > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_authorize() {
+        let mut shares = SessionShares::default();
+        let (share, secret) = shares.create(Utc::now() + Duration::hours(1));
+        let (_, expired_secret) = shares.create(Utc::now() - Duration::seconds(1));
+
+        assert_eq!(
+            shares.authorize(secret.expose_secret()).map(|x| x.id),
+            Some(share.id)
+        );
+        assert!(shares.authorize(expired_secret.expose_secret()).is_none());
+        assert!(shares.authorize("").is_none());
+        assert_eq!(shares.list().len(), 1);
+
+        assert!(shares.revoke(&share.id).is_some());
+        assert!(shares.authorize(secret.expose_secret()).is_none());
+    }
+
+    #[test]
+    fn test_control() {
+        let mut shares = SessionShares::default();
+        let (share, _) = shares.create(Utc::now() + Duration::hours(1));
+        let viewer = shares.join(share.id, "bob".into());
+        let other = shares.join(share.id, "eve".into());
+
+        assert!(shares
+            .grant_control(&Uuid::new_v4(), Utc::now() + Duration::hours(1))
+            .is_none());
+        assert!(shares
+            .grant_control(&viewer.id, Utc::now() + Duration::hours(1))
+            .is_some());
+        assert!(shares.has_control(&viewer.id));
+        assert!(!shares.has_control(&other.id));
+
+        assert!(shares.leave(&other.id).is_none());
+        assert_eq!(
+            shares.leave(&viewer.id).map(|x| x.username),
+            Some("bob".into())
+        );
+        assert!(shares.controller().is_none());
+
+        let viewer = shares.join(share.id, "bob".into());
+        shares.grant_control(&viewer.id, Utc::now() - Duration::seconds(1));
+        assert!(!shares.has_control(&viewer.id));
+        assert!(shares.take_expired_control().is_some());
+        assert!(shares.take_expired_control().is_none());
+    }
+}
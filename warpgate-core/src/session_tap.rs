@@ -0,0 +1,207 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use bytes::Bytes;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::recordings::{SecretDetector, TerminalRecordingStreamId};
+
+/// Output replayed to an admin who starts shadowing mid-session, per channel
+const BACKLOG_SIZE: usize = 64 * 1024;
+const EVENT_QUEUE_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionTapEvent {
+    Output {
+        channel: Uuid,
+        #[serde(with = "warpgate_common::helpers::serde_base64")]
+        data: Bytes,
+    },
+    Resize {
+        channel: Uuid,
+        columns: u32,
+        rows: u32,
+    },
+    Closed {
+        channel: Uuid,
+    },
+}
+
+struct TerminalState {
+    size: Option<(u32, u32)>,
+    backlog: VecDeque<Bytes>,
+    backlog_size: usize,
+    secrets: SecretDetector,
+}
+
+impl Default for TerminalState {
+    fn default() -> Self {
+        Self {
+            size: None,
+            backlog: VecDeque::new(),
+            backlog_size: 0,
+            secrets: SecretDetector::new(true),
+        }
+    }
+}
+
+/// Terminal output of a live session for admins shadowing it, whether or
+/// not the session is recorded. Probable secrets are always masked, same
+/// as in recordings with `secret_detection.redact` enabled. Cheap to clone - the protocol server keeps
+/// one and feeds it, watchers only ever get receivers.
+#[derive(Clone)]
+pub struct SessionTap {
+    sender: broadcast::Sender<SessionTapEvent>,
+    terminals: Arc<Mutex<HashMap<Uuid, TerminalState>>>,
+}
+
+impl Default for SessionTap {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_QUEUE_SIZE).0,
+            terminals: Default::default(),
+        }
+    }
+}
+
+impl SessionTap {
+    fn lock(&self) -> MutexGuard<'_, HashMap<Uuid, TerminalState>> {
+        self.terminals.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the current terminal sizes and recent output,
+    /// followed by everything that happens from now on
+    pub fn subscribe(&self) -> (Vec<SessionTapEvent>, broadcast::Receiver<SessionTapEvent>) {
+        // Holding the lock keeps the snapshot and the receiver in step
+        let terminals = self.lock();
+        let receiver = self.sender.subscribe();
+        let mut snapshot = vec![];
+        for (channel, terminal) in terminals.iter() {
+            if let Some((columns, rows)) = terminal.size {
+                snapshot.push(SessionTapEvent::Resize {
+                    channel: *channel,
+                    columns,
+                    rows,
+                });
+            }
+            snapshot.extend(terminal.backlog.iter().map(|data| SessionTapEvent::Output {
+                channel: *channel,
+                data: data.clone(),
+            }));
+        }
+        (snapshot, receiver)
+    }
+
+    pub fn watcher_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn output(&self, channel: Uuid, data: &[u8]) {
+        let mut terminals = self.lock();
+        let terminal = terminals.entry(channel).or_default();
+        let mut data = data.to_vec();
+        terminal
+            .secrets
+            .scan(TerminalRecordingStreamId::Output, &mut data);
+        let data = Bytes::from(data);
+        terminal.backlog_size += data.len();
+        terminal.backlog.push_back(data.clone());
+        while terminal.backlog_size > BACKLOG_SIZE && terminal.backlog.len() > 1 {
+            if let Some(chunk) = terminal.backlog.pop_front() {
+                terminal.backlog_size -= chunk.len();
+            }
+        }
+        let _ = self.sender.send(SessionTapEvent::Output { channel, data });
+    }
+
+    pub fn resize(&self, channel: Uuid, columns: u32, rows: u32) {
+        let mut terminals = self.lock();
+        terminals.entry(channel).or_default().size = Some((columns, rows));
+        let _ = self.sender.send(SessionTapEvent::Resize {
+            channel,
+            columns,
+            rows,
+        });
+    }
+
+    pub fn close(&self, channel: Uuid) {
+        let mut terminals = self.lock();
+        if terminals.remove(&channel).is_some() {
+            let _ = self.sender.send(SessionTapEvent::Closed { channel });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscribe_replays_backlog() {
+        let tap = SessionTap::default();
+        let channel = Uuid::new_v4();
+        tap.resize(channel, 80, 24);
+        tap.output(channel, &vec![b'a'; BACKLOG_SIZE]);
+        tap.output(channel, b"$ ");
+
+        let (snapshot, mut receiver) = tap.subscribe();
+        assert_eq!(tap.watcher_count(), 1);
+        assert_eq!(
+            snapshot,
+            vec![
+                SessionTapEvent::Resize {
+                    channel,
+                    columns: 80,
+                    rows: 24
+                },
+                SessionTapEvent::Output {
+                    channel,
+                    data: Bytes::from_static(b"$ ")
+                },
+            ]
+        );
+
+        tap.close(channel);
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            SessionTapEvent::Closed { channel }
+        );
+        assert!(tap.subscribe().0.is_empty());
+    }
+
+    #[test]
+    fn test_secrets_are_masked() {
+        let tap = SessionTap::default();
+        let channel = Uuid::new_v4();
+        let (_, mut receiver) = tap.subscribe();
+        tap.output(channel, b"DB_PASSWORD=hun");
+        tap.output(channel, b"ter2\r\n$ ");
+
+        let expected = [
+            Bytes::from_static(b"DB_PASSWORD=***"),
+            Bytes::from_static(b"****\r\n$ "),
+        ];
+        for data in expected.iter() {
+            assert_eq!(
+                receiver.try_recv().unwrap(),
+                SessionTapEvent::Output {
+                    channel,
+                    data: data.clone()
+                }
+            );
+        }
+
+        // Including in the backlog for admins joining later
+        let (snapshot, _) = tap.subscribe();
+        assert_eq!(
+            snapshot,
+            expected
+                .into_iter()
+                .map(|data| SessionTapEvent::Output { channel, data })
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::Serialize;
+use tracing::*;
+
+const MAX_RECENT_MISMATCHES: usize = 50;
+const MAX_REQUEST_LENGTH: usize = 200;
+
+/// How a request fared on the primary target and on its shadow
+pub struct ShadowComparison {
+    /// e.g. `GET /api/items` or an SQL query
+    pub request: String,
+    /// e.g. an HTTP status or a command tag
+    pub primary: String,
+    /// [Err] if the shadow couldn't be reached at all
+    pub shadow: Result<String, String>,
+    pub primary_latency: Duration,
+    pub shadow_latency: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct ShadowMismatch {
+    pub time: DateTime<Utc>,
+    pub request: String,
+    pub primary: String,
+    pub shadow: String,
+    pub primary_latency_ms: u64,
+    pub shadow_latency_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Object)]
+pub struct ShadowReport {
+    pub requests: u64,
+    pub mismatches: u64,
+    /// Requests that the shadow failed to answer
+    pub shadow_errors: u64,
+    pub primary_latency_ms_avg: u64,
+    pub shadow_latency_ms_avg: u64,
+    /// Newest first
+    pub recent_mismatches: Vec<ShadowMismatch>,
+}
+
+#[derive(Default)]
+struct TargetShadowStats {
+    requests: u64,
+    mismatches: u64,
+    shadow_errors: u64,
+    primary_latency: Duration,
+    shadow_latency: Duration,
+    recent_mismatches: VecDeque<ShadowMismatch>,
+}
+
+/// Differences between targets and their shadow upstreams, kept in
+/// memory per target name until reset or restart
+#[derive(Default)]
+pub struct ShadowReports {
+    targets: Mutex<HashMap<String, TargetShadowStats>>,
+}
+
+impl ShadowReports {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, TargetShadowStats>> {
+        self.targets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn record(&self, target_name: &str, comparison: ShadowComparison) {
+        let mut request = comparison.request;
+        if let Some((index, _)) = request.char_indices().nth(MAX_REQUEST_LENGTH) {
+            request.truncate(index);
+            request.push('…');
+        }
+        let primary_latency_ms = comparison.primary_latency.as_millis() as u64;
+        let shadow_latency_ms = comparison.shadow_latency.as_millis() as u64;
+
+        let mut targets = self.lock();
+        let stats = targets.entry(target_name.to_owned()).or_default();
+        stats.requests += 1;
+        stats.primary_latency += comparison.primary_latency;
+        stats.shadow_latency += comparison.shadow_latency;
+
+        let shadow = match comparison.shadow {
+            Ok(shadow) if shadow == comparison.primary => {
+                debug!(
+                    %request,
+                    status=%shadow,
+                    primary_latency_ms,
+                    shadow_latency_ms,
+                    "Shadow response matches"
+                );
+                return;
+            }
+            Ok(shadow) => shadow,
+            Err(error) => {
+                stats.shadow_errors += 1;
+                format!("error: {error}")
+            }
+        };
+
+        warn!(
+            %request,
+            primary=%comparison.primary,
+            %shadow,
+            primary_latency_ms,
+            shadow_latency_ms,
+            "Shadow response differs"
+        );
+        stats.mismatches += 1;
+        stats.recent_mismatches.push_front(ShadowMismatch {
+            time: Utc::now(),
+            request,
+            primary: comparison.primary,
+            shadow,
+            primary_latency_ms,
+            shadow_latency_ms,
+        });
+        stats.recent_mismatches.truncate(MAX_RECENT_MISMATCHES);
+    }
+
+    pub fn report(&self, target_name: &str) -> ShadowReport {
+        let targets = self.lock();
+        let Some(stats) = targets.get(target_name) else {
+            return ShadowReport::default();
+        };
+        let average = |total: Duration| {
+            total
+                .as_millis()
+                .checked_div(stats.requests.into())
+                .unwrap_or_default() as u64
+        };
+        ShadowReport {
+            requests: stats.requests,
+            mismatches: stats.mismatches,
+            shadow_errors: stats.shadow_errors,
+            primary_latency_ms_avg: average(stats.primary_latency),
+            shadow_latency_ms_avg: average(stats.shadow_latency),
+            recent_mismatches: stats.recent_mismatches.iter().cloned().collect(),
+        }
+    }
+
+    pub fn reset(&self, target_name: &str) {
+        self.lock().remove(target_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comparison(primary: &str, shadow: Result<&str, &str>) -> ShadowComparison {
+        ShadowComparison {
+            request: "GET /".into(),
+            primary: primary.into(),
+            shadow: shadow.map(Into::into).map_err(Into::into),
+            primary_latency: Duration::from_millis(10),
+            shadow_latency: Duration::from_millis(30),
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let reports = ShadowReports::default();
+        reports.record("a", comparison("200", Ok("200")));
+        reports.record("a", comparison("200", Ok("500")));
+        reports.record("a", comparison("200", Err("timeout")));
+        reports.record("b", comparison("200", Ok("200")));
+
+        let report = reports.report("a");
+        assert_eq!(report.requests, 3);
+        assert_eq!(report.mismatches, 2);
+        assert_eq!(report.shadow_errors, 1);
+        assert_eq!(report.primary_latency_ms_avg, 10);
+        assert_eq!(report.shadow_latency_ms_avg, 30);
+        assert_eq!(report.recent_mismatches[0].shadow, "error: timeout");
+        assert_eq!(report.recent_mismatches[1].shadow, "500");
+
+        reports.reset("a");
+        assert_eq!(reports.report("a").requests, 0);
+        assert_eq!(reports.report("b").mismatches, 0);
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use tokio::sync::watch;
+use warpgate_common::WarpgateConfig;
+
+/// Holds the current [WarpgateConfig] as an immutable snapshot.
+///
+/// Readers get a cheap `Arc` clone and never wait on each other or on a
+/// reload. A reload swaps in a whole new snapshot and notifies subscribers.
+pub struct SharedConfig {
+    current: ArcSwap<WarpgateConfig>,
+    updates: watch::Sender<Arc<WarpgateConfig>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: WarpgateConfig) -> Self {
+        let config = Arc::new(config);
+        Self {
+            current: ArcSwap::new(config.clone()),
+            updates: watch::Sender::new(config),
+        }
+    }
+
+    /// Returns the current snapshot. Hold on to it for as long as a
+    /// consistent view is needed; later reloads won't affect it.
+    pub fn load(&self) -> Arc<WarpgateConfig> {
+        self.current.load_full()
+    }
+
+    /// Replaces the current snapshot and notifies subscribers
+    pub fn store(&self, config: WarpgateConfig) {
+        let config = Arc::new(config);
+        self.current.store(config.clone());
+        self.updates.send_replace(config);
+    }
+
+    /// Yields every snapshot stored after this call
+    pub fn subscribe(&self) -> watch::Receiver<Arc<WarpgateConfig>> {
+        self.updates.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn config(external_host: Option<&str>) -> WarpgateConfig {
+        let mut config = WarpgateConfig {
+            store: Default::default(),
+            paths_relative_to: PathBuf::new(),
+        };
+        config.store.external_host = external_host.map(Into::into);
+        config
+    }
+
+    #[tokio::test]
+    async fn store_replaces_snapshot_and_notifies() {
+        let shared = SharedConfig::new(config(None));
+        let mut updates = shared.subscribe();
+        let before = shared.load();
+
+        shared.store(config(Some("example.com")));
+
+        assert_eq!(before.store.external_host, None);
+        assert_eq!(
+            shared.load().store.external_host.as_deref(),
+            Some("example.com")
+        );
+        updates.changed().await.unwrap();
+        assert_eq!(
+            updates.borrow().store.external_host.as_deref(),
+            Some("example.com")
+        );
+    }
+}
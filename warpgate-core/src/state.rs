@@ -3,29 +3,76 @@ use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
 
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use poem_openapi::{Enum, Object};
 use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait};
+use serde::Serialize;
 use tokio::sync::{broadcast, Mutex};
 use tracing::*;
 use uuid::Uuid;
 use warpgate_common::{ProtocolName, SessionId, Target, WarpgateError};
-use warpgate_db_entities::Session;
+use warpgate_db_entities::Session::{self, SessionTerminationReason};
 
-use crate::{SessionHandle, WarpgateServerHandle};
+use crate::logging::SessionContext;
+use crate::recordings::RecordingQuotas;
+use crate::{
+    AnalyticsRecord, AnalyticsSinkHandle, DropBoxUsage, SessionChannels, SessionDropBox,
+    SessionHandle, SessionLastActivity, SessionMetricsRecord, SessionShares, SessionTap,
+    WarpgateServerHandle,
+};
+
+/// Sessions without channel traffic for this long are considered idle
+const SESSION_IDLE_AFTER: TimeDelta = TimeDelta::minutes(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum SessionActivity {
+    Active,
+    Idle,
+    /// The protocol doesn't report channel activity
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Object)]
+pub struct TargetSession {
+    pub id: SessionId,
+    pub username: Option<String>,
+    pub remote_address: Option<String>,
+    pub started: DateTime<Utc>,
+    pub last_activity: Option<DateTime<Utc>>,
+    pub activity: SessionActivity,
+}
 
 pub struct State {
     pub sessions: HashMap<SessionId, Arc<Mutex<SessionState>>>,
+    /// Target each session is connected to, kept here so that session
+    /// limits can be checked without locking every session
+    session_targets: HashMap<SessionId, Uuid>,
+    /// Sessions turned away by `max_concurrent_sessions` since startup
+    rejected_sessions: HashMap<Uuid, u64>,
     db: Arc<Mutex<DatabaseConnection>>,
+    analytics_sink: Option<AnalyticsSinkHandle>,
+    recording_quotas: Arc<RecordingQuotas>,
+    drop_box_usage: DropBoxUsage,
     this: Weak<Mutex<Self>>,
     change_sender: broadcast::Sender<()>,
 }
 
 impl State {
-    pub fn new(db: &Arc<Mutex<DatabaseConnection>>) -> Arc<Mutex<Self>> {
+    pub fn new(
+        db: &Arc<Mutex<DatabaseConnection>>,
+        analytics_sink: Option<AnalyticsSinkHandle>,
+        recording_quotas: Arc<RecordingQuotas>,
+    ) -> Arc<Mutex<Self>> {
         let sender = broadcast::channel(2).0;
         Arc::<Mutex<Self>>::new_cyclic(|me| {
             Mutex::new(Self {
                 sessions: HashMap::new(),
+                session_targets: HashMap::new(),
+                rejected_sessions: HashMap::new(),
                 db: db.clone(),
+                analytics_sink,
+                recording_quotas,
+                drop_box_usage: DropBoxUsage::default(),
                 this: me.clone(),
                 change_sender: sender,
             })
@@ -38,12 +85,17 @@ impl State {
         state: SessionStateInit,
     ) -> Result<Arc<Mutex<WarpgateServerHandle>>, WarpgateError> {
         let id = uuid::Uuid::new_v4();
+        let started = chrono::Utc::now();
 
+        let context = SessionContext::new(id, protocol, state.remote_address.map(|x| x.ip()));
         let state = Arc::new(Mutex::new(SessionState::new(
             state,
+            started,
+            self.drop_box_usage.clone(),
             self.change_sender.clone(),
         )));
 
+        let last_activity = state.lock().await.last_activity.clone();
         self.sessions.insert(id, state.clone());
 
         {
@@ -51,7 +103,7 @@ impl State {
 
             let values = Session::ActiveModel {
                 id: Set(id),
-                started: Set(chrono::Utc::now()),
+                started: Set(started),
                 remote_address: Set(state
                     .lock()
                     .await
@@ -75,29 +127,167 @@ impl State {
         match self.this.upgrade() {
             Some(this) => Ok(Arc::new(Mutex::new(WarpgateServerHandle::new(
                 id,
+                context,
                 self.db.clone(),
                 this,
                 state,
+                last_activity,
+                self.recording_quotas.clone(),
             )))),
             None => Err(anyhow!("State is being detroyed").into()),
         }
     }
 
+    pub fn session_target(&self, id: SessionId) -> Option<Uuid> {
+        self.session_targets.get(&id).copied()
+    }
+
+    pub fn set_session_target(&mut self, id: SessionId, target_id: Uuid) {
+        self.session_targets.insert(id, target_id);
+    }
+
+    pub fn count_target_sessions(&self, target_id: Uuid) -> usize {
+        self.session_targets
+            .values()
+            .filter(|id| **id == target_id)
+            .count()
+    }
+
+    pub fn record_rejected_session(&mut self, target_id: Uuid) {
+        *self.rejected_sessions.entry(target_id).or_default() += 1;
+    }
+
+    pub fn rejected_sessions(&self, target_id: Uuid) -> u64 {
+        self.rejected_sessions.get(&target_id).copied().unwrap_or(0)
+    }
+
+    /// The state lock is only held while the handles are cloned, so that
+    /// a busy session doesn't hold up everything else that needs the state
+    async fn sessions_for_target(
+        this: &Mutex<Self>,
+        target_id: Uuid,
+    ) -> Vec<(SessionId, Arc<Mutex<SessionState>>)> {
+        let sessions: Vec<_> = this
+            .lock()
+            .await
+            .sessions
+            .iter()
+            .map(|(id, session)| (*id, session.clone()))
+            .collect();
+        let mut result = vec![];
+        for (id, session) in sessions {
+            let connected = session
+                .lock()
+                .await
+                .target
+                .as_ref()
+                .is_some_and(|t| t.id == target_id);
+            if connected {
+                result.push((id, session));
+            }
+        }
+        result
+    }
+
+    pub async fn target_sessions(this: &Mutex<Self>, target_id: Uuid) -> Vec<TargetSession> {
+        let now = Utc::now();
+        let mut result = vec![];
+        for (id, session) in Self::sessions_for_target(this, target_id).await {
+            let state = session.lock().await;
+            let last_activity = state
+                .channels
+                .snapshot()
+                .channels
+                .iter()
+                .map(|x| x.last_activity)
+                .max();
+            let activity = match last_activity {
+                Some(time) if now - time < SESSION_IDLE_AFTER => SessionActivity::Active,
+                Some(_) => SessionActivity::Idle,
+                None => SessionActivity::Unknown,
+            };
+            result.push(TargetSession {
+                id,
+                username: state.username.clone(),
+                remote_address: state.remote_address.map(|x| x.to_string()),
+                started: state.started,
+                last_activity,
+                activity,
+            });
+        }
+        result.sort_by_key(|x| x.started);
+        result
+    }
+
+    /// Returns the number of sessions that could show the message
+    pub async fn notify_target_sessions(
+        this: &Mutex<Self>,
+        target_id: Uuid,
+        message: &str,
+    ) -> usize {
+        let mut notified = 0;
+        for (_, session) in Self::sessions_for_target(this, target_id).await {
+            if session.lock().await.handle.notify(message.to_owned()) {
+                notified += 1;
+            }
+        }
+        notified
+    }
+
+    /// Returns the number of sessions that were asked to close
+    pub async fn close_user_sessions(
+        &self,
+        username: &str,
+        reason: SessionTerminationReason,
+    ) -> usize {
+        let mut closed = 0;
+        for session in self.sessions.values() {
+            let mut session = session.lock().await;
+            if session.username.as_deref() == Some(username) {
+                session.terminate(reason);
+                closed += 1;
+            }
+        }
+        closed
+    }
+
     pub fn subscribe(&mut self) -> broadcast::Receiver<()> {
         self.change_sender.subscribe()
     }
 
     pub async fn remove_session(&mut self, id: SessionId) {
-        self.sessions.remove(&id);
+        self.session_targets.remove(&id);
+        let (reason, exit_code) = match self.sessions.remove(&id) {
+            Some(session) => {
+                let session = session.lock().await;
+                (session.termination_reason, session.exit_code)
+            }
+            None => (None, None),
+        };
+        let reason = reason.unwrap_or(SessionTerminationReason::UserDisconnect);
 
-        if let Err(error) = self.mark_session_complete(id).await {
-            error!(%error, %id, "Could not update session in the DB");
+        match self.mark_session_complete(id, reason, exit_code).await {
+            Ok(session) => {
+                if let Some(ref sink) = self.analytics_sink {
+                    if let Some(record) = SessionMetricsRecord::from_session(&session) {
+                        sink.record(AnalyticsRecord::SessionMetrics(record));
+                    }
+                }
+            }
+            Err(error) => {
+                error!(%error, %id, "Could not update session in the DB");
+            }
         }
 
         let _ = self.change_sender.send(());
     }
 
-    async fn mark_session_complete(&mut self, id: Uuid) -> Result<()> {
+    async fn mark_session_complete(
+        &mut self,
+        id: Uuid,
+        reason: SessionTerminationReason,
+        exit_code: Option<i64>,
+    ) -> Result<Session::Model> {
         use sea_orm::ActiveValue::Set;
         let db = self.db.lock().await;
         let session = Session::Entity::find_by_id(id)
@@ -106,16 +296,27 @@ impl State {
             .ok_or_else(|| anyhow::anyhow!("Session not found"))?;
         let mut model: Session::ActiveModel = session.into();
         model.ended = Set(Some(chrono::Utc::now()));
-        model.update(&*db).await?;
-        Ok(())
+        model.termination_reason = Set(Some(reason));
+        model.exit_code = Set(exit_code);
+        Ok(model.update(&*db).await?)
     }
 }
 
 pub struct SessionState {
+    pub started: DateTime<Utc>,
     pub remote_address: Option<SocketAddr>,
     pub username: Option<String>,
     pub target: Option<Target>,
     pub handle: Box<dyn SessionHandle + Send>,
+    pub drop_box: SessionDropBox,
+    pub shares: SessionShares,
+    pub channels: SessionChannels,
+    pub tap: SessionTap,
+    pub last_activity: SessionLastActivity,
+    /// Recorded when the session ends. Sessions that end without one were
+    /// closed by the user.
+    pub termination_reason: Option<SessionTerminationReason>,
+    pub exit_code: Option<i64>,
     change_sender: broadcast::Sender<()>,
 }
 
@@ -125,17 +326,217 @@ pub struct SessionStateInit {
 }
 
 impl SessionState {
-    fn new(init: SessionStateInit, change_sender: broadcast::Sender<()>) -> Self {
+    fn new(
+        init: SessionStateInit,
+        started: DateTime<Utc>,
+        drop_box_usage: DropBoxUsage,
+        change_sender: broadcast::Sender<()>,
+    ) -> Self {
         SessionState {
+            started,
             remote_address: init.remote_address,
             username: None,
             target: None,
             handle: init.handle,
+            drop_box: SessionDropBox::new(drop_box_usage),
+            shares: SessionShares::default(),
+            channels: SessionChannels::default(),
+            tap: SessionTap::default(),
+            last_activity: SessionLastActivity::default(),
+            termination_reason: None,
+            exit_code: None,
             change_sender,
         }
     }
 
+    /// Only the first reason is kept, since whatever follows it
+    /// is usually just a consequence
+    pub fn set_termination_reason(&mut self, reason: SessionTerminationReason) {
+        self.termination_reason.get_or_insert(reason);
+    }
+
+    /// Asks the protocol to close the session
+    pub fn terminate(&mut self, reason: SessionTerminationReason) {
+        self.set_termination_reason(reason);
+        self.handle.close();
+    }
+
+    /// Like [Self::terminate], letting the user know why if `message` is set
+    pub fn terminate_with_message(
+        &mut self,
+        reason: SessionTerminationReason,
+        message: Option<String>,
+    ) {
+        self.set_termination_reason(reason);
+        match message {
+            Some(message) => self.handle.close_with_message(message),
+            None => self.handle.close(),
+        }
+    }
+
     pub fn emit_change(&self) {
         let _ = self.change_sender.send(());
     }
 }
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use std::time::Duration;
+
+    use sea_orm::Database;
+    use warpgate_common::{TargetOptions, TargetWebAdminOptions, WarpgateConfig};
+    use warpgate_db_migrations::migrate_database;
+
+    use super::*;
+    use crate::{Alerts, SharedConfig};
+
+    struct TestHandle;
+
+    impl SessionHandle for TestHandle {
+        fn close(&mut self) {}
+
+        fn notify(&mut self, _message: String) -> bool {
+            true
+        }
+    }
+
+    fn target(draining: bool) -> Target {
+        Target {
+            id: Uuid::new_v4(),
+            name: format!("target-{}", Uuid::new_v4()),
+            allow_roles: vec![],
+            honeypot: false,
+            host_overrides: Default::default(),
+            max_concurrent_sessions: None,
+            session_limit_message: None,
+            monitor: None,
+            idle_timeout_seconds: None,
+            draining,
+            options: TargetOptions::WebAdmin(TargetWebAdminOptions {}),
+        }
+    }
+
+    async fn state() -> Arc<Mutex<State>> {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        migrate_database(&db).await.unwrap();
+        let config = Arc::new(SharedConfig::new(WarpgateConfig {
+            store: Default::default(),
+            paths_relative_to: std::env::temp_dir(),
+        }));
+        let quotas = Arc::new(RecordingQuotas::new(
+            config.clone(),
+            Alerts::new(config).await,
+        ));
+        State::new(&Arc::new(Mutex::new(db)), None, quotas)
+    }
+
+    async fn add_session(
+        state: &Arc<Mutex<State>>,
+        target: Option<&Target>,
+    ) -> Arc<Mutex<WarpgateServerHandle>> {
+        let handle = state
+            .lock()
+            .await
+            .register_session(
+                &"SSH",
+                SessionStateInit {
+                    remote_address: None,
+                    handle: Box::new(TestHandle),
+                },
+            )
+            .await
+            .unwrap();
+        if let Some(target) = target {
+            handle.lock().await.set_target(target).await.unwrap();
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn test_target_sessions() {
+        let state = state().await;
+        let (first, second) = (target(false), target(false));
+        let _sessions = [
+            add_session(&state, Some(&first)).await,
+            add_session(&state, Some(&first)).await,
+            add_session(&state, Some(&second)).await,
+            add_session(&state, None).await,
+        ];
+
+        assert_eq!(State::target_sessions(&state, first.id).await.len(), 2);
+        assert_eq!(
+            State::notify_target_sessions(&state, first.id, "maintenance").await,
+            2
+        );
+        assert_eq!(State::target_sessions(&state, second.id).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_state_is_not_held_while_sessions_are_locked() {
+        let state = state().await;
+        let target = target(false);
+        let session = add_session(&state, Some(&target)).await;
+        let session_state = session.lock().await.session_state().clone();
+
+        let busy = session_state.lock().await;
+        let listing = tokio::spawn({
+            let state = state.clone();
+            async move { State::target_sessions(&state, target.id).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!listing.is_finished());
+        assert!(tokio::time::timeout(Duration::from_secs(1), state.lock())
+            .await
+            .is_ok());
+
+        drop(busy);
+        assert_eq!(listing.await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_draining_target_rejects_new_sessions() {
+        let state = state().await;
+        let mut target = target(false);
+        let connected = add_session(&state, Some(&target)).await;
+
+        target.draining = true;
+        let new = add_session(&state, None).await;
+        assert!(matches!(
+            new.lock().await.set_target(&target).await,
+            Err(WarpgateError::TargetDraining(_))
+        ));
+        // Sessions that are already connected are left alone
+        assert!(connected.lock().await.set_target(&target).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_sessions_respect_the_limit() {
+        let state = state().await;
+        let mut target = target(false);
+        target.max_concurrent_sessions = Some(2);
+
+        let mut sessions = vec![];
+        for _ in 0..10 {
+            sessions.push(add_session(&state, None).await);
+        }
+        let attempts: Vec<_> = sessions
+            .iter()
+            .map(|session| {
+                let session = session.clone();
+                let target = target.clone();
+                tokio::spawn(async move { session.lock().await.set_target(&target).await })
+            })
+            .collect();
+        let mut connected = 0;
+        for attempt in attempts {
+            match attempt.await.unwrap() {
+                Ok(()) => connected += 1,
+                Err(error) => assert!(matches!(error, WarpgateError::TargetSessionLimit(..))),
+            }
+        }
+
+        assert_eq!(connected, 2);
+        assert_eq!(state.lock().await.rejected_sessions(target.id), 8);
+        assert_eq!(State::target_sessions(&state, target.id).await.len(), 2);
+    }
+}
@@ -0,0 +1,84 @@
+use warpgate_common::auth::TargetParameters;
+use warpgate_common::{
+    apply_address_template, is_address_template, render_address_template, AddressTemplateContext,
+    Target, TargetOptions, WarpgateError,
+};
+
+use crate::{ConfigProvider, Services};
+
+enum TargetAddress<'a> {
+    HostPort(&'a mut String, &'a mut u16),
+    Url(&'a mut String),
+}
+
+impl TargetAddress<'_> {
+    fn is_template(&self) -> bool {
+        match self {
+            Self::HostPort(host, _) => is_address_template(host),
+            Self::Url(url) => is_address_template(url),
+        }
+    }
+}
+
+fn address_mut(options: &mut TargetOptions) -> Option<TargetAddress<'_>> {
+    Some(match options {
+        TargetOptions::Ssh(options) => {
+            TargetAddress::HostPort(&mut options.host, &mut options.port)
+        }
+        TargetOptions::Docker(options) => {
+            TargetAddress::HostPort(&mut options.ssh.host, &mut options.ssh.port)
+        }
+        TargetOptions::MySql(options) => {
+            TargetAddress::HostPort(&mut options.host, &mut options.port)
+        }
+        TargetOptions::Postgres(options) => {
+            TargetAddress::HostPort(&mut options.host, &mut options.port)
+        }
+        TargetOptions::Redis(options) => {
+            TargetAddress::HostPort(&mut options.host, &mut options.port)
+        }
+        TargetOptions::Ipmi(options) => {
+            TargetAddress::HostPort(&mut options.host, &mut options.port)
+        }
+        TargetOptions::Http(options) => TargetAddress::Url(&mut options.url),
+        TargetOptions::WebAdmin(_) => return None,
+    })
+}
+
+/// Fills in a templated target host (or HTTP target URL) for this
+/// connection, see [warpgate_common::render_address_template]
+pub async fn resolve_target_address(
+    services: &Services,
+    username: &str,
+    target: &mut Target,
+    parameters: &TargetParameters,
+) -> Result<(), WarpgateError> {
+    let target_name = target.name.clone();
+    let Some(address) = address_mut(&mut target.options) else {
+        return Ok(());
+    };
+    if !address.is_template() {
+        return Ok(());
+    }
+
+    let mut roles = services
+        .config_provider
+        .lock()
+        .await
+        .list_user_roles(username)
+        .await?;
+    roles.retain(|role| target.allow_roles.contains(role));
+    roles.sort();
+
+    let context = AddressTemplateContext {
+        username,
+        target: &target_name,
+        role: roles.first().map(String::as_str),
+        parameters,
+    };
+    match address {
+        TargetAddress::HostPort(host, port) => apply_address_template(host, port, &context)?,
+        TargetAddress::Url(url) => *url = render_address_template(url, &context)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use rustls::ClientConnection;
+use serde::Serialize;
+
+/// Protocol features that Warpgate and a target agreed on
+/// for a single connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Object)]
+pub struct NegotiatedCapabilities {
+    pub time: DateTime<Utc>,
+    pub tls_version: Option<String>,
+    pub tls_cipher_suite: Option<String>,
+    /// MySQL auth plugin, PostgreSQL authentication method
+    /// or SSH authentication method
+    pub auth_method: Option<String>,
+    pub ssh_host_key_algorithm: Option<String>,
+    pub http_version: Option<String>,
+    /// Protocol-specific flags, e.g. MySQL capabilities
+    pub flags: Vec<String>,
+}
+
+impl NegotiatedCapabilities {
+    pub fn new() -> Self {
+        Self {
+            time: Utc::now(),
+            tls_version: None,
+            tls_cipher_suite: None,
+            auth_method: None,
+            ssh_host_key_algorithm: None,
+            http_version: None,
+            flags: vec![],
+        }
+    }
+
+    /// `connection` is [None] if the target was connected to without TLS
+    pub fn with_tls(mut self, connection: Option<&ClientConnection>) -> Self {
+        let Some(connection) = connection else {
+            return self;
+        };
+        self.tls_version = connection.protocol_version().map(|x| format!("{x:?}"));
+        self.tls_cipher_suite = connection
+            .negotiated_cipher_suite()
+            .map(|x| format!("{:?}", x.suite()));
+        self
+    }
+}
+
+impl Default for NegotiatedCapabilities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Capabilities negotiated on the last connection to each target,
+/// whether for a session or a test, by target name
+#[derive(Default)]
+pub struct TargetCapabilities {
+    last_seen: Mutex<HashMap<String, NegotiatedCapabilities>>,
+}
+
+impl TargetCapabilities {
+    pub fn record(&self, target_name: &str, capabilities: NegotiatedCapabilities) {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(target_name.to_owned(), capabilities);
+    }
+
+    pub fn get(&self, target_name: &str) -> Option<NegotiatedCapabilities> {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target_name)
+            .cloned()
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use data_encoding::{BASE64, BASE64_NOPAD, HEXUPPER};
+use poem_openapi::{Enum, Object};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use warpgate_common::WarpgateError;
+use warpgate_db_entities::KnownHost;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum TargetFingerprintKind {
+    SshHostKey,
+    TlsCertificate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Object)]
+pub struct TargetFingerprint {
+    pub kind: TargetFingerprintKind,
+    /// Key type for host keys
+    pub algorithm: Option<String>,
+    /// SHA-256, in the format used by `ssh-keygen -l` for host keys
+    /// and `openssl x509 -fingerprint` for certificates
+    pub fingerprint: String,
+}
+
+impl TargetFingerprint {
+    /// `key_base64` is the key as it appears in `known_hosts`
+    pub fn ssh_host_key(algorithm: &str, key_base64: &str) -> Option<Self> {
+        let key = BASE64.decode(key_base64.as_bytes()).ok()?;
+        Some(Self {
+            kind: TargetFingerprintKind::SshHostKey,
+            algorithm: Some(algorithm.to_owned()),
+            fingerprint: format!("SHA256:{}", BASE64_NOPAD.encode(&Sha256::digest(key))),
+        })
+    }
+
+    pub fn tls_certificate(der: &[u8]) -> Self {
+        let digest = HEXUPPER.encode(&Sha256::digest(der));
+        let mut fingerprint = String::new();
+        for (i, byte) in digest.as_bytes().chunks(2).enumerate() {
+            if i > 0 {
+                fingerprint.push(':');
+            }
+            fingerprint.push_str(&String::from_utf8_lossy(byte));
+        }
+        Self {
+            kind: TargetFingerprintKind::TlsCertificate,
+            algorithm: None,
+            fingerprint,
+        }
+    }
+}
+
+/// Host keys and certificates that targets presented on their last
+/// connection, by target name
+#[derive(Default)]
+pub struct TargetFingerprints {
+    last_seen: Mutex<HashMap<String, TargetFingerprint>>,
+}
+
+impl TargetFingerprints {
+    pub fn record(&self, target_name: &str, fingerprint: TargetFingerprint) {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(target_name.to_owned(), fingerprint);
+    }
+
+    pub fn get(&self, target_name: &str) -> Option<TargetFingerprint> {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target_name)
+            .cloned()
+    }
+}
+
+/// Trusted host keys of an SSH server
+pub async fn known_host_fingerprints(
+    db: &DatabaseConnection,
+    host: &str,
+    port: u16,
+) -> Result<Vec<TargetFingerprint>, WarpgateError> {
+    Ok(KnownHost::Entity::find()
+        .filter(KnownHost::Column::Host.eq(host))
+        .filter(KnownHost::Column::Port.eq(port as i32))
+        .all(db)
+        .await?
+        .into_iter()
+        .filter_map(|host| TargetFingerprint::ssh_host_key(&host.key_type, &host.key_base64))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_formats() {
+        // as printed by ssh-keygen -l
+        assert_eq!(
+            TargetFingerprint::ssh_host_key(
+                "ssh-ed25519",
+                "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl"
+            )
+            .map(|x| x.fingerprint),
+            Some("SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU".into())
+        );
+        assert_eq!(TargetFingerprint::ssh_host_key("ssh-ed25519", "!"), None);
+
+        let fingerprint = TargetFingerprint::tls_certificate(b"").fingerprint;
+        assert!(fingerprint.starts_with("E3:B0:C4:42:98:FC"));
+        assert_eq!(fingerprint.len(), 32 * 3 - 1);
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::future::join_all;
+use poem_openapi::Enum;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::*;
+use warpgate_common::{is_address_template, Target, TargetOptions, WarpgateError};
+
+use crate::config_check::target_address;
+use crate::{update_target_health_notifications, ConfigProvider, Services, TargetResolver};
+
+/// How often targets are probed
+pub const TARGET_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const TARGET_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Enum)]
+pub enum TargetHealth {
+    /// Accepted a TCP connection during the last check
+    Healthy,
+    Unreachable,
+    /// Not checked yet, or the address is only known at connect time
+    Unknown,
+}
+
+/// Last known reachability of each target, by name
+pub struct TargetHealthChecker {
+    statuses: Mutex<HashMap<String, TargetHealth>>,
+    change_sender: broadcast::Sender<()>,
+}
+
+impl Default for TargetHealthChecker {
+    fn default() -> Self {
+        Self {
+            statuses: Mutex::new(HashMap::new()),
+            change_sender: broadcast::channel(2).0,
+        }
+    }
+}
+
+impl TargetHealthChecker {
+    pub fn get(&self, target_name: &str) -> TargetHealth {
+        self.statuses
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(target_name)
+            .copied()
+            .unwrap_or(TargetHealth::Unknown)
+    }
+
+    /// Notified whenever the health of a target changes
+    pub fn subscribe(&self) -> broadcast::Receiver<()> {
+        self.change_sender.subscribe()
+    }
+
+    fn update(&self, results: HashMap<String, TargetHealth>) {
+        let mut statuses = self.statuses.lock().unwrap_or_else(|e| e.into_inner());
+        let changed = *statuses != results;
+        *statuses = results;
+        drop(statuses);
+        if changed {
+            let _ = self.change_sender.send(());
+        }
+    }
+}
+
+async fn probe_target(resolver: TargetResolver, target: &Target) -> TargetHealth {
+    if let TargetOptions::WebAdmin(_) = target.options {
+        return TargetHealth::Healthy;
+    }
+    let Some((host, port)) = target_address(target) else {
+        return TargetHealth::Unknown;
+    };
+    if is_address_template(&host) {
+        return TargetHealth::Unknown;
+    }
+    match tokio::time::timeout(TARGET_PROBE_TIMEOUT, resolver.connect(&host, port)).await {
+        Ok(Ok(_)) => TargetHealth::Healthy,
+        _ => TargetHealth::Unreachable,
+    }
+}
+
+/// Probes every target with a TCP connection to its address
+pub async fn check_target_health(services: &Services) -> Result<(), WarpgateError> {
+    let targets = services.config_provider.lock().await.list_targets().await?;
+    let job = services.jobs.start("target_health", "Target health check");
+    let total = targets.len() as u64;
+    let done = AtomicU64::new(0);
+    let results = job
+        .run_until_cancelled(join_all(targets.iter().map(|target| {
+            let job = &job;
+            let done = &done;
+            async move {
+                let resolver = services.dns.for_target(target);
+                let health = probe_target(resolver, target).await;
+                job.set_progress(done.fetch_add(1, Ordering::Relaxed) + 1, Some(total));
+                (target.name.clone(), health)
+            }
+        })))
+        .await;
+    if let Some(results) = results {
+        if let Err(error) = update_target_health_notifications(services, &results).await {
+            error!(%error, "Failed to update the target health notifications");
+        }
+        services.target_health.update(results.into_iter().collect());
+    }
+    job.finish(&Ok::<_, WarpgateError>(()));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_notifies_on_change() {
+        let checker = TargetHealthChecker::default();
+        let mut receiver = checker.subscribe();
+
+        checker.update(HashMap::from([("a".into(), TargetHealth::Healthy)]));
+        assert!(receiver.try_recv().is_ok());
+        assert_eq!(checker.get("a"), TargetHealth::Healthy);
+        assert_eq!(checker.get("b"), TargetHealth::Unknown);
+
+        checker.update(HashMap::from([("a".into(), TargetHealth::Healthy)]));
+        assert!(receiver.try_recv().is_err());
+
+        checker.update(HashMap::from([("a".into(), TargetHealth::Unreachable)]));
+        assert!(receiver.try_recv().is_ok());
+    }
+}
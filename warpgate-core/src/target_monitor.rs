@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde_json::json;
+use uuid::Uuid;
+use warpgate_common::{Target, TargetMonitorOptions};
+
+use crate::{Alert, AlertSeverity, Alerts};
+
+/// Protects the targets from an overly eager config
+const MIN_MONITOR_INTERVAL: Duration = Duration::from_secs(10);
+
+pub const ALERT_TARGET_UNHEALTHY: &str = "target_unhealthy";
+pub const ALERT_TARGET_RECOVERED: &str = "target_recovered";
+
+#[derive(Debug, Clone, Object)]
+pub struct TargetMonitorStatus {
+    pub target_id: Uuid,
+    pub target_name: String,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+    pub last_checked: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+struct MonitorState {
+    status: TargetMonitorStatus,
+    next_check: Instant,
+}
+
+/// What to alert about after a test
+#[derive(Debug, PartialEq, Eq)]
+enum Transition {
+    BecameUnhealthy,
+    Recovered,
+}
+
+/// Results of the scheduled connection tests of targets that have
+/// [TargetMonitorOptions]. The tests themselves need the protocol
+/// servers and are run by the main binary.
+#[derive(Default)]
+pub struct TargetMonitor {
+    states: Mutex<HashMap<Uuid, MonitorState>>,
+}
+
+impl TargetMonitor {
+    /// Monitored targets whose next test is due, which is then scheduled
+    /// right away so that a slow test doesn't get started twice.
+    /// Forgets targets that are no longer monitored.
+    pub fn take_due(&self, targets: &[Target], now: Instant) -> Vec<Target> {
+        let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        states.retain(|id, _| targets.iter().any(|t| t.id == *id && t.monitor.is_some()));
+
+        let mut due = vec![];
+        for target in targets {
+            let Some(ref options) = target.monitor else {
+                continue;
+            };
+            let state = states.entry(target.id).or_insert_with(|| MonitorState {
+                status: TargetMonitorStatus {
+                    target_id: target.id,
+                    target_name: target.name.clone(),
+                    healthy: true,
+                    consecutive_failures: 0,
+                    last_checked: None,
+                    last_error: None,
+                },
+                next_check: now,
+            });
+            state.status.target_name.clone_from(&target.name);
+            if state.next_check <= now {
+                state.next_check = now + interval(options);
+                due.push(target.clone());
+            }
+        }
+        due
+    }
+
+    /// Records a test result and raises an alert if the target
+    /// became unhealthy or recovered
+    pub async fn record(&self, alerts: &Alerts, target: &Target, result: Result<(), String>) {
+        let Some(ref options) = target.monitor else {
+            return;
+        };
+        let (transition, failures, error) = {
+            let mut states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+            let Some(state) = states.get_mut(&target.id) else {
+                return;
+            };
+            let transition = apply_result(&mut state.status, options, result);
+            (
+                transition,
+                state.status.consecutive_failures,
+                state.status.last_error.clone(),
+            )
+        };
+
+        let Some(transition) = transition else {
+            return;
+        };
+        let (severity, kind, message) = match transition {
+            Transition::BecameUnhealthy => (
+                AlertSeverity::Critical,
+                ALERT_TARGET_UNHEALTHY,
+                format!(
+                    "Target {} failed {failures} connection tests in a row",
+                    target.name
+                ),
+            ),
+            Transition::Recovered => (
+                AlertSeverity::Info,
+                ALERT_TARGET_RECOVERED,
+                format!("Target {} passes connection tests again", target.name),
+            ),
+        };
+        alerts
+            .raise(Alert {
+                time: Utc::now(),
+                severity,
+                kind: kind.into(),
+                message,
+                target: Some(target.name.clone()),
+                details: json!({
+                    "consecutive_failures": failures,
+                    "error": error,
+                }),
+            })
+            .await;
+    }
+
+    pub fn get(&self, target_id: Uuid) -> Option<TargetMonitorStatus> {
+        let states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        states.get(&target_id).map(|x| x.status.clone())
+    }
+
+    pub fn list(&self) -> Vec<TargetMonitorStatus> {
+        let states = self.states.lock().unwrap_or_else(|e| e.into_inner());
+        let mut statuses: Vec<_> = states.values().map(|x| x.status.clone()).collect();
+        statuses.sort_by(|a, b| a.target_name.cmp(&b.target_name));
+        statuses
+    }
+}
+
+fn interval(options: &TargetMonitorOptions) -> Duration {
+    Duration::from_secs(options.interval_seconds.into()).max(MIN_MONITOR_INTERVAL)
+}
+
+fn apply_result(
+    status: &mut TargetMonitorStatus,
+    options: &TargetMonitorOptions,
+    result: Result<(), String>,
+) -> Option<Transition> {
+    status.last_checked = Some(Utc::now());
+    match result {
+        Ok(()) => {
+            status.consecutive_failures = 0;
+            status.last_error = None;
+            if status.healthy {
+                return None;
+            }
+            status.healthy = true;
+            Some(Transition::Recovered)
+        }
+        Err(error) => {
+            status.consecutive_failures += 1;
+            status.last_error = Some(error);
+            if !status.healthy || status.consecutive_failures < options.failure_threshold.max(1) {
+                return None;
+            }
+            status.healthy = false;
+            Some(Transition::BecameUnhealthy)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_result() {
+        let options = TargetMonitorOptions {
+            interval_seconds: 60,
+            failure_threshold: 2,
+        };
+        let mut status = TargetMonitorStatus {
+            target_id: Uuid::new_v4(),
+            target_name: "db".into(),
+            healthy: true,
+            consecutive_failures: 0,
+            last_checked: None,
+            last_error: None,
+        };
+        let fail = || Err("refused".to_string());
+
+        assert_eq!(apply_result(&mut status, &options, fail()), None);
+        assert_eq!(apply_result(&mut status, &options, Ok(())), None);
+        assert_eq!(status.consecutive_failures, 0);
+
+        assert_eq!(apply_result(&mut status, &options, fail()), None);
+        assert_eq!(
+            apply_result(&mut status, &options, fail()),
+            Some(Transition::BecameUnhealthy)
+        );
+        // Alerted once per outage
+        assert_eq!(apply_result(&mut status, &options, fail()), None);
+        assert_eq!(status.last_error.as_deref(), Some("refused"));
+
+        assert_eq!(
+            apply_result(&mut status, &options, Ok(())),
+            Some(Transition::Recovered)
+        );
+        assert!(status.healthy);
+    }
+}
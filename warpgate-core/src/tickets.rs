@@ -0,0 +1,139 @@
+use chrono::{DateTime, Duration, Utc};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, Set};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::helpers::hash::generate_ticket_secret;
+use warpgate_common::{Secret, WarpgateError};
+use warpgate_db_entities::{Parameters, Ticket};
+
+use crate::{ConfigProvider, Services};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SelfServiceTicketError {
+    #[error("self-service tickets are disabled")]
+    Disabled,
+    /// Names the offending field
+    #[error("invalid {0}")]
+    Invalid(&'static str),
+    #[error("no access to this target")]
+    NoAccess,
+    #[error("tickets for {0} targets are not allowed")]
+    ProtocolNotAllowed(&'static str),
+    #[error(transparent)]
+    Warpgate(#[from] WarpgateError),
+}
+
+impl From<sea_orm::DbErr> for SelfServiceTicketError {
+    fn from(error: sea_orm::DbErr) -> Self {
+        Self::Warpgate(error.into())
+    }
+}
+
+pub struct SelfServiceTicketRequest {
+    pub target_name: String,
+    /// Defaults to the longest validity allowed by the administrator
+    pub expiry: Option<DateTime<Utc>>,
+    pub number_of_uses: Option<i16>,
+}
+
+/// Issues a ticket that a user has requested for themselves.
+///
+/// `allows_target` and `allows_protocol` apply additional restrictions
+/// of the credentials used for the request, e.g. API token scopes.
+pub async fn issue_self_service_ticket(
+    services: &Services,
+    username: &str,
+    request: SelfServiceTicketRequest,
+    allows_target: impl Fn(&str) -> bool,
+    allows_protocol: impl Fn(&str) -> bool,
+) -> Result<(Ticket::Model, Secret<String>), SelfServiceTicketError> {
+    let parameters = {
+        let db = services.db.lock().await;
+        Parameters::Entity::get(&db).await?
+    };
+    if !parameters.allow_self_service_tickets {
+        return Err(SelfServiceTicketError::Disabled);
+    }
+
+    let now = Utc::now();
+    let max_expiry = now + Duration::seconds(parameters.self_service_ticket_max_validity);
+    let expiry = request.expiry.unwrap_or(max_expiry);
+    if expiry <= now || expiry > max_expiry {
+        return Err(SelfServiceTicketError::Invalid("expiry"));
+    }
+    if request.number_of_uses.is_some_and(|x| x <= 0) {
+        return Err(SelfServiceTicketError::Invalid("number_of_uses"));
+    }
+
+    let target = {
+        let mut config_provider = services.config_provider.lock().await;
+        let target = config_provider
+            .list_targets()
+            .await?
+            .into_iter()
+            .find(|t| t.name == request.target_name);
+        match target {
+            Some(target)
+                if allows_target(&target.name)
+                    && config_provider
+                        .authorize_target(username, &target.name)
+                        .await? =>
+            {
+                target
+            }
+            _ => return Err(SelfServiceTicketError::NoAccess),
+        }
+    };
+
+    let protocol = target.options.protocol_name();
+    if !parameters.allows_self_service_ticket_protocol(protocol) || !allows_protocol(protocol) {
+        return Err(SelfServiceTicketError::ProtocolNotAllowed(protocol));
+    }
+
+    let secret = generate_ticket_secret();
+    let db = services.db.lock().await;
+    let ticket = Ticket::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        secret: Set(secret.expose_secret().to_string()),
+        username: Set(username.to_owned()),
+        target: Set(target.name.clone()),
+        created: Set(now),
+        expiry: Set(Some(expiry)),
+        uses_left: Set(request.number_of_uses),
+    }
+    .insert(&*db)
+    .await?;
+    info!(%username, target=%target.name, "Issued a self-service ticket");
+
+    Ok((ticket, secret))
+}
+
+pub async fn list_user_tickets(
+    services: &Services,
+    username: &str,
+) -> Result<Vec<Ticket::Model>, WarpgateError> {
+    let db = services.db.lock().await;
+    Ok(Ticket::Entity::find()
+        .filter(Ticket::Column::Username.eq(username))
+        .all(&*db)
+        .await?)
+}
+
+/// Returns `false` if the user has no such ticket
+pub async fn revoke_user_ticket(
+    services: &Services,
+    username: &str,
+    id: Uuid,
+) -> Result<bool, WarpgateError> {
+    let db = services.db.lock().await;
+    let Some(ticket) = Ticket::Entity::find_by_id(id)
+        .filter(Ticket::Column::Username.eq(username))
+        .one(&*db)
+        .await?
+    else {
+        return Ok(false);
+    };
+    ticket.delete(&*db).await?;
+    info!(%username, ticket=%id, "Revoked a self-service ticket");
+    Ok(true)
+}
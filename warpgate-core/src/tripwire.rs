@@ -0,0 +1,230 @@
+use chrono::Utc;
+use rustls::ClientConnection;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde_json::json;
+use uuid::Uuid;
+use warpgate_common::{SessionId, Target, WarpgateError};
+use warpgate_db_entities::TargetBaseline::{self, TargetBaselineProperty};
+
+use crate::{Alert, AlertSeverity, Services, TargetFingerprint};
+
+/// Properties of a single connection to a target address
+pub struct TargetObservation {
+    pub target_name: String,
+    pub host: String,
+    pub port: u16,
+    pub properties: Vec<(TargetBaselineProperty, String)>,
+}
+
+impl TargetObservation {
+    pub fn ssh(target_name: &str, host: &str, port: u16, host_key: &TargetFingerprint) -> Self {
+        Self {
+            target_name: target_name.to_owned(),
+            host: host.to_owned(),
+            port,
+            properties: vec![(
+                TargetBaselineProperty::SshHostKey,
+                format!(
+                    "{} {}",
+                    host_key.algorithm.as_deref().unwrap_or_default(),
+                    host_key.fingerprint
+                ),
+            )],
+        }
+    }
+
+    /// `connection` is [None] if the target was connected to without TLS
+    pub fn tls(
+        target_name: &str,
+        host: &str,
+        port: u16,
+        connection: Option<&ClientConnection>,
+    ) -> Self {
+        let mut properties = vec![(
+            TargetBaselineProperty::Tls,
+            if connection.is_some() {
+                "enabled"
+            } else {
+                "disabled"
+            }
+            .to_owned(),
+        )];
+        if let Some(connection) = connection {
+            if let Some(version) = connection.protocol_version() {
+                properties.push((TargetBaselineProperty::TlsVersion, format!("{version:?}")));
+            }
+            if let Some(suite) = connection.negotiated_cipher_suite() {
+                properties.push((
+                    TargetBaselineProperty::TlsCipherSuite,
+                    format!("{:?}", suite.suite()),
+                ));
+            }
+            if let Some(cert) = connection.peer_certificates().and_then(|x| x.first()) {
+                properties.push((
+                    TargetBaselineProperty::TlsCertificate,
+                    TargetFingerprint::tls_certificate(cert).fingerprint,
+                ));
+            }
+        }
+        Self {
+            target_name: target_name.to_owned(),
+            host: host.to_owned(),
+            port,
+            properties,
+        }
+    }
+}
+
+fn describe(property: TargetBaselineProperty) -> &'static str {
+    match property {
+        TargetBaselineProperty::SshHostKey => "SSH host key",
+        TargetBaselineProperty::Tls => "TLS usage",
+        TargetBaselineProperty::TlsVersion => "TLS version",
+        TargetBaselineProperty::TlsCipherSuite => "TLS cipher suite",
+        TargetBaselineProperty::TlsCertificate => "TLS certificate",
+    }
+}
+
+/// Compares a connection with what the address presented the first time
+/// and raises a critical alert if anything changed. Unknown properties
+/// become the new baseline, changed ones stay as they are until
+/// an admin resets them.
+pub async fn check_target_baseline(
+    services: &Services,
+    observation: TargetObservation,
+) -> Result<(), WarpgateError> {
+    let mut changes = vec![];
+    {
+        let db = services.db.lock().await;
+        let baselines = TargetBaseline::Entity::find()
+            .filter(TargetBaseline::Column::Host.eq(&observation.host))
+            .filter(TargetBaseline::Column::Port.eq(observation.port as i32))
+            .all(&*db)
+            .await?;
+
+        let now = Utc::now();
+        for (property, value) in &observation.properties {
+            match baselines.iter().find(|b| b.property == *property) {
+                None => {
+                    TargetBaseline::ActiveModel {
+                        id: Set(Uuid::new_v4()),
+                        target_name: Set(observation.target_name.clone()),
+                        host: Set(observation.host.clone()),
+                        port: Set(observation.port as i32),
+                        property: Set(*property),
+                        value: Set(value.clone()),
+                        first_seen: Set(now),
+                        last_seen: Set(now),
+                    }
+                    .insert(&*db)
+                    .await?;
+                }
+                Some(baseline) if baseline.value == *value => {
+                    let mut model: TargetBaseline::ActiveModel = baseline.clone().into();
+                    model.target_name = Set(observation.target_name.clone());
+                    model.last_seen = Set(now);
+                    model.update(&*db).await?;
+                }
+                Some(baseline) => changes.push((*property, baseline.value.clone(), value.clone())),
+            }
+        }
+    }
+
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!(
+        "{}:{} ({}) presented a different {} than before",
+        observation.host,
+        observation.port,
+        observation.target_name,
+        changes
+            .iter()
+            .map(|(property, _, _)| describe(*property))
+            .collect::<Vec<_>>()
+            .join(", "),
+    );
+    services
+        .alerts
+        .raise(Alert {
+            time: Utc::now(),
+            severity: AlertSeverity::Critical,
+            kind: "target_baseline_mismatch".into(),
+            message,
+            target: Some(observation.target_name),
+            details: json!({
+                "host": observation.host,
+                "port": observation.port,
+                "changes": changes
+                    .into_iter()
+                    .map(|(property, expected, observed)| json!({
+                        "property": property,
+                        "expected": expected,
+                        "observed": observed,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        })
+        .await;
+    Ok(())
+}
+
+/// Raises a critical alert for a session that has selected a honeypot target
+pub async fn trip_honeypot(
+    services: &Services,
+    target: &Target,
+    protocol: &str,
+    session_id: Option<SessionId>,
+    username: Option<&str>,
+    remote_address: Option<String>,
+) {
+    services
+        .alerts
+        .raise(Alert {
+            time: Utc::now(),
+            severity: AlertSeverity::Critical,
+            kind: "honeypot_accessed".into(),
+            message: format!(
+                "{} accessed the honeypot target {} over {protocol}",
+                username.unwrap_or("An unknown user"),
+                target.name
+            ),
+            target: Some(target.name.clone()),
+            details: json!({
+                "protocol": protocol,
+                "session_id": session_id,
+                "username": username,
+                "remote_address": remote_address,
+            }),
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_observations() {
+        let observation = TargetObservation::tls("db", "db.internal", 5432, None);
+        assert_eq!(
+            observation.properties,
+            vec![(TargetBaselineProperty::Tls, "disabled".to_owned())]
+        );
+
+        let host_key = TargetFingerprint::ssh_host_key(
+            "ssh-ed25519",
+            "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl",
+        )
+        .unwrap();
+        let observation = TargetObservation::ssh("server", "server.internal", 22, &host_key);
+        assert_eq!(
+            observation.properties,
+            vec![(
+                TargetBaselineProperty::SshHostKey,
+                "ssh-ed25519 SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU".to_owned()
+            )]
+        );
+    }
+}
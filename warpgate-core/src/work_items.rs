@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use serde::Serialize;
+
+use crate::SessionSnapshot;
+
+pub const MAX_WORK_ITEM_LENGTH: usize = 128;
+
+/// Trims the tag supplied by the client and rejects empty
+/// or oversized ones
+pub fn normalize_work_item(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() || value.len() > MAX_WORK_ITEM_LENGTH || value.contains(char::is_control) {
+        return None;
+    }
+    Some(value.to_owned())
+}
+
+#[derive(Serialize, Object, Debug, PartialEq, Eq)]
+pub struct WorkItemSummary {
+    pub name: String,
+    pub sessions: u32,
+    pub usernames: Vec<String>,
+    pub protocols: Vec<String>,
+    pub started: DateTime<Utc>,
+    /// Unset while any of the sessions are still running
+    pub ended: Option<DateTime<Utc>>,
+}
+
+/// Groups tagged sessions by work item, most recently started first
+pub fn summarize_work_items(sessions: &[SessionSnapshot]) -> Vec<WorkItemSummary> {
+    let mut groups = BTreeMap::<&str, Vec<&SessionSnapshot>>::new();
+    for session in sessions {
+        if let Some(ref work_item) = session.work_item {
+            groups.entry(work_item).or_default().push(session);
+        }
+    }
+
+    let mut summaries: Vec<_> = groups
+        .into_iter()
+        .filter_map(|(name, sessions)| {
+            let started = sessions.iter().map(|s| s.started).min()?;
+            let ended = sessions
+                .iter()
+                .map(|s| s.ended)
+                .collect::<Option<Vec<_>>>()
+                .and_then(|x| x.into_iter().max());
+            Some(WorkItemSummary {
+                name: name.to_owned(),
+                sessions: sessions.len() as u32,
+                usernames: sessions
+                    .iter()
+                    .filter_map(|s| s.username.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+                protocols: sessions
+                    .iter()
+                    .map(|s| s.protocol.clone())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect(),
+                started,
+                ended,
+            })
+        })
+        .collect();
+    summaries.sort_by(|a, b| b.started.cmp(&a.started));
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn session(
+        work_item: Option<&str>,
+        protocol: &str,
+        start: i64,
+        end: Option<i64>,
+    ) -> SessionSnapshot {
+        SessionSnapshot {
+            id: Uuid::new_v4(),
+            username: Some("user".into()),
+            target: None,
+            started: Utc.timestamp_opt(start, 0).unwrap(),
+            ended: end.map(|x| Utc.timestamp_opt(x, 0).unwrap()),
+            ticket_id: None,
+            protocol: protocol.into(),
+            consent_acknowledged: None,
+            work_item: work_item.map(Into::into),
+            termination_reason: None,
+            exit_code: None,
+            channels: None,
+            ssh_details: None,
+            http_details: None,
+            database_details: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize_work_item() {
+        assert_eq!(normalize_work_item("  INC-1 "), Some("INC-1".into()));
+        assert_eq!(normalize_work_item(" "), None);
+        assert_eq!(normalize_work_item("a\nb"), None);
+        assert_eq!(
+            normalize_work_item(&"x".repeat(MAX_WORK_ITEM_LENGTH + 1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_summarize_work_items() {
+        let summaries = summarize_work_items(&[
+            session(Some("INC-1"), "SSH", 100, Some(200)),
+            session(Some("INC-1"), "PostgreSQL", 150, Some(300)),
+            session(None, "SSH", 500, None),
+            session(Some("INC-2"), "HTTP", 400, None),
+        ]);
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].name, "INC-2");
+        assert_eq!(summaries[0].ended, None);
+        assert_eq!(summaries[1].sessions, 2);
+        assert_eq!(summaries[1].protocols, vec!["PostgreSQL", "SSH"]);
+        assert_eq!(summaries[1].started, Utc.timestamp_opt(100, 0).unwrap());
+        assert_eq!(summaries[1].ended, Some(Utc.timestamp_opt(300, 0).unwrap()));
+    }
+}
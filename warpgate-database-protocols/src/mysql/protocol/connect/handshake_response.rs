@@ -30,6 +30,10 @@ pub struct HandshakeResponse {
 
     /// Opaque authentication response
     pub auth_response: Option<Bytes>,
+
+    /// Client-reported attributes such as `program_name`.
+    /// Only decoded, never sent.
+    pub connect_attrs: Vec<(String, String)>,
 }
 
 impl Encode<'_, Capabilities> for HandshakeResponse {
@@ -108,6 +112,7 @@ impl Decode<'_, &mut Capabilities> for HandshakeResponse {
                 auth_response: None,
                 auth_plugin: None,
                 database: None,
+                connect_attrs: vec![],
             });
         }
         let username = buf.get_str_nul()?;
@@ -133,6 +138,21 @@ impl Decode<'_, &mut Capabilities> for HandshakeResponse {
             None
         };
 
+        let mut connect_attrs = vec![];
+        if partial_cap.contains(Capabilities::CONNECT_ATTRS) {
+            if let Some(mut attrs) = get_bytes_lenenc_checked(&mut buf) {
+                while let (Some(key), Some(value)) = (
+                    get_bytes_lenenc_checked(&mut attrs),
+                    get_bytes_lenenc_checked(&mut attrs),
+                ) {
+                    connect_attrs.push((
+                        String::from_utf8_lossy(&key).into_owned(),
+                        String::from_utf8_lossy(&value).into_owned(),
+                    ));
+                }
+            }
+        }
+
         *server_capabilities &= Capabilities::from_bits_truncate(capabilities);
 
         Ok(HandshakeResponse {
@@ -142,6 +162,64 @@ impl Decode<'_, &mut Capabilities> for HandshakeResponse {
             auth_response,
             auth_plugin,
             database,
+            connect_attrs,
         })
     }
 }
+
+/// Like [MySqlBufExt::get_bytes_lenenc], but returns [None] instead of
+/// panicking on truncated data, since connect attributes are optional
+fn get_bytes_lenenc_checked(buf: &mut Bytes) -> Option<Bytes> {
+    let width = match *buf.first()? {
+        0xfc => 3,
+        0xfd => 4,
+        0xfe => 9,
+        _ => 1,
+    };
+    if buf.remaining() < width {
+        return None;
+    }
+    let len = buf.get_uint_lenenc() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    Some(buf.split_to(len))
+}
+
+#[test]
+#[allow(clippy::unwrap_used)]
+fn test_decode_handshake_response_connect_attrs() {
+    let capabilities = Capabilities::MYSQL
+        | Capabilities::PROTOCOL_41
+        | Capabilities::SECURE_CONNECTION
+        | Capabilities::CONNECT_WITH_DB
+        | Capabilities::CONNECT_ATTRS;
+    let attrs = b"\x0cprogram_name\x05mysql\x04_pid\x0242";
+
+    let mut buf = vec![];
+    buf.extend_from_slice(&(capabilities.bits() as u32).to_le_bytes());
+    buf.extend_from_slice(&16777216u32.to_le_bytes());
+    buf.push(45);
+    buf.extend_from_slice(&[0; 23]);
+    buf.extend_from_slice(b"root\0\0db\0");
+    buf.push(attrs.len() as u8);
+    buf.extend_from_slice(attrs);
+
+    let mut server_capabilities = Capabilities::all();
+    let response =
+        HandshakeResponse::decode_with(buf.clone().into(), &mut server_capabilities).unwrap();
+    assert_eq!(response.username, "root");
+    assert_eq!(response.database.as_deref(), Some("db"));
+    assert_eq!(
+        response.connect_attrs,
+        vec![
+            ("program_name".to_owned(), "mysql".to_owned()),
+            ("_pid".to_owned(), "42".to_owned()),
+        ]
+    );
+
+    // truncated attributes are ignored
+    buf.truncate(buf.len() - 3);
+    let response = HandshakeResponse::decode_with(buf.into(), &mut server_capabilities).unwrap();
+    assert!(response.connect_attrs.is_empty());
+}
@@ -1,9 +1,37 @@
 use chrono::{DateTime, Utc};
+use poem_openapi::Object;
 use sea_orm::entity::prelude::*;
 use sea_orm::sea_query::ForeignKeyAction;
-use serde::Serialize;
+use sea_orm::FromJsonQueryResult;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Restricts what an API token can be used for.
+/// A missing list means no restriction on that axis.
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult, Object,
+)]
+pub struct ApiTokenScope {
+    /// Names of the targets the token can access
+    pub targets: Option<Vec<String>>,
+    /// Names of the protocols the token can be used with, e.g. `HTTP`
+    pub protocols: Option<Vec<String>>,
+}
+
+impl ApiTokenScope {
+    pub fn allows_target(&self, target_name: &str) -> bool {
+        self.targets
+            .as_ref()
+            .is_none_or(|targets| targets.iter().any(|x| x == target_name))
+    }
+
+    pub fn allows_protocol(&self, protocol: &str) -> bool {
+        self.protocols
+            .as_ref()
+            .is_none_or(|protocols| protocols.iter().any(|x| x.eq_ignore_ascii_case(protocol)))
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
 #[sea_orm(table_name = "api_tokens")]
 pub struct Model {
@@ -14,6 +42,8 @@ pub struct Model {
     pub secret: String,
     pub created: DateTime<Utc>,
     pub expiry: DateTime<Utc>,
+    #[sea_orm(column_type = "Json", nullable)]
+    pub scope: Option<ApiTokenScope>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -0,0 +1,30 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Context of a rejected login attempt
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "auth_failures")]
+#[oai(rename = "AuthFailure")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub protocol: String,
+    pub remote_ip: Option<String>,
+    pub remote_port: Option<i32>,
+    /// Username as offered by the client, whether or not it exists
+    pub username: Option<String>,
+    /// List of credential kinds the client presented
+    pub credential_kinds: serde_json::Value,
+    /// SSH identification string or database client name
+    pub client_banner: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
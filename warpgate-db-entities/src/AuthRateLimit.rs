@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use sea_orm::FromJsonQueryResult;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Serialize, Clone, Copy, Enum, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum AuthRateLimitKind {
+    #[sea_orm(string_value = "username")]
+    Username,
+    #[sea_orm(string_value = "source")]
+    Source,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct FailureTimes(pub Vec<DateTime<Utc>>);
+
+/// Recent failed logins for a username or a source IP
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "auth_rate_limits")]
+#[oai(rename = "AuthRateLimit")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub kind: AuthRateLimitKind,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub subject: String,
+    /// Within the current window, oldest first
+    #[sea_orm(column_type = "Json")]
+    #[oai(skip)]
+    pub failures: FailureTimes,
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,61 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum ConfigObjectKind {
+    #[sea_orm(string_value = "target")]
+    Target,
+    #[sea_orm(string_value = "user")]
+    User,
+    #[sea_orm(string_value = "role")]
+    Role,
+    #[sea_orm(string_value = "parameters")]
+    Parameters,
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum ConfigChangeAction {
+    #[sea_orm(string_value = "create")]
+    Create,
+    #[sea_orm(string_value = "update")]
+    Update,
+    #[sea_orm(string_value = "delete")]
+    Delete,
+    #[sea_orm(string_value = "rollback")]
+    Rollback,
+}
+
+/// One version of a target, user, role or the global parameters
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "config_changes")]
+#[oai(rename = "ConfigChange")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub object_kind: ConfigObjectKind,
+    pub object_id: Uuid,
+    /// Counts up from 1 for each object
+    pub version: i64,
+    pub action: ConfigChangeAction,
+    /// Admin username, or not set when an admin token was used
+    pub author: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    /// State of the object after the change, not set for deletions
+    pub snapshot: Option<serde_json::Value>,
+    /// Changed fields as a list of `{ path, old, new }`
+    pub diff: serde_json::Value,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
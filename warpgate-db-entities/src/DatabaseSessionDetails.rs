@@ -0,0 +1,42 @@
+use poem_openapi::Object;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// MySQL and PostgreSQL specifics of a session
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize, Object)]
+#[sea_orm(table_name = "database_session_details")]
+#[oai(rename = "DatabaseSessionDetails")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub session_id: Uuid,
+    /// Database selected at login or later in the session
+    pub database: Option<String>,
+    /// As reported by the client, e.g. `application_name` in PostgreSQL
+    pub client_application: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    Session,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::Session => Entity::belongs_to(super::Session::Entity)
+                .from(Column::SessionId)
+                .to(super::Session::Column::Id)
+                .on_delete(ForeignKeyAction::Cascade)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::Session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,33 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::Object;
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// A short-lived account created on a database target for a user to
+/// connect to it directly. The password is never stored.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "direct_credentials")]
+#[oai(rename = "DirectCredential")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub target_id: Uuid,
+    pub target_name: String,
+    /// The Warpgate user that requested it
+    pub username: String,
+    /// The account on the target
+    pub database_username: String,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    /// When the account was dropped from the target
+    pub removed: Option<DateTime<Utc>>,
+    /// Why the account couldn't be dropped yet
+    #[sea_orm(column_type = "Text", nullable)]
+    pub removal_error: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
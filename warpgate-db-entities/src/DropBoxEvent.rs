@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum DropBoxAction {
+    #[sea_orm(string_value = "staged")]
+    Staged,
+    #[sea_orm(string_value = "retrieved")]
+    Retrieved,
+    #[sea_orm(string_value = "deleted")]
+    Deleted,
+}
+
+/// Audit trail of a session's drop-box, kept after the items themselves are gone
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "drop_box_events")]
+#[oai(rename = "DropBoxEvent")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub username: Option<String>,
+    pub action: DropBoxAction,
+    /// Whether the action was taken in the web portal rather than the session
+    pub via_portal: bool,
+    pub item_name: String,
+    pub size: i64,
+    /// Hex SHA-256 of the item's content
+    pub sha256: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,27 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// A web session kept in the database
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "http_sessions")]
+pub struct Model {
+    /// Hash of the session cookie
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    /// Encrypted session entries
+    #[sea_orm(column_type = "Text")]
+    pub data: String,
+    pub username: Option<String>,
+    /// The Warpgate session recorded for this web session, if any
+    pub session_id: Option<Uuid>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
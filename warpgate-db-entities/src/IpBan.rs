@@ -0,0 +1,35 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Clone, Copy, Enum, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum IpBanAction {
+    #[sea_orm(string_value = "deny")]
+    Deny,
+    /// Once any allow entries exist, only matching addresses can connect
+    #[sea_orm(string_value = "allow")]
+    Allow,
+}
+
+/// An address or CIDR network checked when a connection is accepted
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "ip_bans")]
+#[oai(rename = "IpBan")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// Normalized CIDR notation, e.g. `192.0.2.1/32`
+    pub network: String,
+    pub action: IpBanAction,
+    pub comment: String,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum NotificationSeverity {
+    #[sea_orm(string_value = "info")]
+    Info,
+    #[sea_orm(string_value = "warning")]
+    Warning,
+    #[sea_orm(string_value = "critical")]
+    Critical,
+}
+
+/// Something that needs an admin's attention
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "notifications")]
+#[oai(rename = "Notification")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    /// e.g. `target_unreachable`
+    pub kind: String,
+    pub severity: NotificationSeverity,
+    #[sea_orm(column_type = "Text")]
+    pub message: String,
+    pub target: Option<String>,
+    /// Identifies the condition behind the notification, so that it isn't
+    /// repeated while it persists. Not set for one-off events.
+    pub key: Option<String>,
+    /// When the condition went away by itself
+    pub resolved: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
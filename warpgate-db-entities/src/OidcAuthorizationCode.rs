@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::FromJsonQueryResult;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct RoleNameList(pub Vec<String>);
+
+/// A code issued by the built-in OIDC provider that a client hasn't
+/// exchanged for tokens yet
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "oidc_authorization_codes")]
+pub struct Model {
+    /// Hash of the code
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub client_id: String,
+    #[sea_orm(column_type = "Text")]
+    pub redirect_uri: String,
+    pub username: String,
+    /// The user's roles at the time of the login
+    #[sea_orm(column_type = "Json")]
+    pub roles: RoleNameList,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    #[sea_orm(column_type = "String(Some(16))", nullable)]
+    pub code_challenge_method: Option<String>,
+    pub auth_time: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,13 +1,34 @@
 use sea_orm::entity::prelude::*;
-use sea_orm::Set;
+use sea_orm::{FromJsonQueryResult, Set};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Default upper bound for the validity of self-issued tickets, in seconds
+pub const DEFAULT_SELF_SERVICE_TICKET_MAX_VALIDITY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct ProtocolList(pub Vec<String>);
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "parameters")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
     pub allow_own_credential_management: bool,
+    pub allow_self_service_tickets: bool,
+    /// Seconds
+    pub self_service_ticket_max_validity: i64,
+    /// Protocols of the targets users can issue tickets for, any if not set
+    #[sea_orm(column_type = "Json", nullable)]
+    pub self_service_ticket_protocols: Option<ProtocolList>,
+}
+
+impl Model {
+    pub fn allows_self_service_ticket_protocol(&self, protocol: &str) -> bool {
+        self.self_service_ticket_protocols
+            .as_ref()
+            .is_none_or(|list| list.0.iter().any(|x| x.eq_ignore_ascii_case(protocol)))
+    }
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -23,6 +44,9 @@ impl Entity {
                 ActiveModel {
                     id: Set(Uuid::new_v4()),
                     allow_own_credential_management: Set(true),
+                    allow_self_service_tickets: Set(false),
+                    self_service_ticket_max_validity: Set(DEFAULT_SELF_SERVICE_TICKET_MAX_VALIDITY),
+                    self_service_ticket_protocols: Set(None),
                 }
                 .insert(db)
                 .await
@@ -2,16 +2,23 @@ use chrono::{DateTime, Utc};
 use poem_openapi::{Enum, Object};
 use sea_orm::entity::prelude::*;
 use sea_orm::sea_query::ForeignKeyAction;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize)]
 #[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
 pub enum RecordingKind {
     #[sea_orm(string_value = "terminal")]
     Terminal,
     #[sea_orm(string_value = "traffic")]
     Traffic,
+    #[sea_orm(string_value = "http")]
+    Http,
+    #[sea_orm(string_value = "sftp")]
+    Sftp,
+    /// Statements sent to a database target
+    #[sea_orm(string_value = "queries")]
+    Queries,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
@@ -25,6 +32,7 @@ pub struct Model {
     pub ended: Option<DateTime<Utc>>,
     pub session_id: Uuid,
     pub kind: RecordingKind,
+    pub replicated: Option<DateTime<Utc>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
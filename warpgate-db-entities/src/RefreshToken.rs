@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use sea_orm::sea_query::ForeignKeyAction;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::ApiToken::ApiTokenScope;
+
+/// One generation of a long-lived API login. Each refresh replaces the row
+/// with a new one in the same family and marks the old one as used.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+#[sea_orm(table_name = "refresh_tokens")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// Shared by all generations of the same login
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    pub label: String,
+    /// Hash of the refresh token
+    pub secret: String,
+    /// Hash of the access token issued alongside it
+    pub access_secret: String,
+    pub access_expiry: DateTime<Utc>,
+    /// When the family was first issued
+    pub created: DateTime<Utc>,
+    pub refreshed: DateTime<Utc>,
+    pub expiry: DateTime<Utc>,
+    /// Set once the token has been exchanged for a new one
+    pub used: bool,
+    /// Applies to the access tokens, carried over to every generation
+    #[sea_orm(column_type = "Json", nullable)]
+    pub scope: Option<ApiTokenScope>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    User,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::User => Entity::belongs_to(super::User::Entity)
+                .from(Column::UserId)
+                .to(super::User::Column::Id)
+                .on_delete(ForeignKeyAction::Cascade)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::User::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
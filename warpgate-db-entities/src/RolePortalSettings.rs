@@ -0,0 +1,56 @@
+use poem_openapi::Enum;
+use sea_orm::entity::prelude::*;
+use sea_orm::FromJsonQueryResult;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A block on the user portal's landing page
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Enum)]
+pub enum PortalSection {
+    Targets,
+    Tickets,
+    SessionSharing,
+    DropBox,
+    Credentials,
+    ApiTokens,
+    Logins,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct PortalSectionList(pub Vec<PortalSection>);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct TargetIdList(pub Vec<Uuid>);
+
+/// How the user portal looks for members of a role
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "role_portal_settings")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub role_id: Uuid,
+    /// In display order
+    #[sea_orm(column_type = "Json")]
+    pub sections: PortalSectionList,
+    /// Still accessible, just not listed
+    #[sea_orm(column_type = "Json")]
+    pub hidden_targets: TargetIdList,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    Role,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::Role => Entity::belongs_to(super::Role::Entity)
+                .from(Column::RoleId)
+                .to(super::Role::Column::Id)
+                .on_delete(ForeignKeyAction::Cascade)
+                .into(),
+        }
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
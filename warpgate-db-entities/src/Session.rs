@@ -1,7 +1,26 @@
 use chrono::{DateTime, Utc};
+use poem_openapi::Enum;
 use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(16))")]
+pub enum SessionTerminationReason {
+    #[sea_orm(string_value = "user_disconnect")]
+    UserDisconnect,
+    #[sea_orm(string_value = "admin_abort")]
+    AdminAbort,
+    #[sea_orm(string_value = "idle_timeout")]
+    IdleTimeout,
+    #[sea_orm(string_value = "target_error")]
+    TargetError,
+    #[sea_orm(string_value = "policy_violation")]
+    PolicyViolation,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "sessions")]
 pub struct Model {
@@ -14,6 +33,14 @@ pub struct Model {
     pub ended: Option<DateTime<Utc>>,
     pub ticket_id: Option<Uuid>,
     pub protocol: String,
+    pub consent_acknowledged: Option<DateTime<Utc>>,
+    pub work_item: Option<String>,
+    /// `User-Agent` of the browser used to log in over HTTP
+    #[sea_orm(column_type = "Text", nullable)]
+    pub user_agent: Option<String>,
+    pub termination_reason: Option<SessionTerminationReason>,
+    /// Exit status of the last command or shell that exited on the target
+    pub exit_code: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -0,0 +1,46 @@
+use poem_openapi::Object;
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// SSH specifics of a session
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize, Object)]
+#[sea_orm(table_name = "ssh_session_details")]
+#[oai(rename = "SshSessionDetails")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub session_id: Uuid,
+    /// Identification string of the client, e.g. `SSH-2.0-OpenSSH_9.6`
+    pub client_version: Option<String>,
+    /// Last requested terminal size
+    pub pty_columns: Option<i32>,
+    pub pty_rows: Option<i32>,
+    /// Last command requested with `exec`
+    #[sea_orm(column_type = "Text", nullable)]
+    pub command: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter)]
+pub enum Relation {
+    Session,
+}
+
+impl RelationTrait for Relation {
+    fn def(&self) -> RelationDef {
+        match self {
+            Self::Session => Entity::belongs_to(super::Session::Entity)
+                .from(Column::SessionId)
+                .to(super::Session::Column::Id)
+                .on_delete(ForeignKeyAction::Cascade)
+                .into(),
+        }
+    }
+}
+
+impl Related<super::Session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
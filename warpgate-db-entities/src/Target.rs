@@ -15,6 +15,12 @@ pub enum TargetKind {
     Ssh,
     #[sea_orm(string_value = "postgres")]
     Postgres,
+    #[sea_orm(string_value = "redis")]
+    Redis,
+    #[sea_orm(string_value = "docker")]
+    Docker,
+    #[sea_orm(string_value = "ipmi")]
+    Ipmi,
     #[sea_orm(string_value = "web_admin")]
     WebAdmin,
 }
@@ -25,7 +31,10 @@ impl From<&TargetOptions> for TargetKind {
             TargetOptions::Http(_) => Self::Http,
             TargetOptions::MySql(_) => Self::MySql,
             TargetOptions::Postgres(_) => Self::Postgres,
+            TargetOptions::Redis(_) => Self::Redis,
             TargetOptions::Ssh(_) => Self::Ssh,
+            TargetOptions::Docker(_) => Self::Docker,
+            TargetOptions::Ipmi(_) => Self::Ipmi,
             TargetOptions::WebAdmin(_) => Self::WebAdmin,
         }
     }
@@ -49,6 +58,15 @@ pub struct Model {
     pub name: String,
     pub kind: TargetKind,
     pub options: serde_json::Value,
+    pub honeypot: bool,
+    pub host_overrides: Option<serde_json::Value>,
+    pub max_concurrent_sessions: Option<i32>,
+    #[sea_orm(column_type = "Text")]
+    pub session_limit_message: Option<String>,
+    pub monitor: Option<serde_json::Value>,
+    pub idle_timeout_seconds: Option<i32>,
+    /// New sessions are rejected while the target is drained for maintenance
+    pub draining: bool,
 }
 
 impl Related<super::Role::Entity> for Entity {
@@ -75,6 +93,17 @@ impl TryFrom<Model> for Target {
             id: model.id,
             name: model.name,
             allow_roles: vec![],
+            honeypot: model.honeypot,
+            host_overrides: model
+                .host_overrides
+                .map(serde_json::from_value)
+                .transpose()?
+                .unwrap_or_default(),
+            max_concurrent_sessions: model.max_concurrent_sessions.map(|x| x.max(0) as u32),
+            session_limit_message: model.session_limit_message,
+            monitor: model.monitor.map(serde_json::from_value).transpose()?,
+            idle_timeout_seconds: model.idle_timeout_seconds.map(|x| x.max(0) as u32),
+            draining: model.draining,
             options,
         })
     }
@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use poem_openapi::{Enum, Object};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, EnumIter, Enum, DeriveActiveEnum, Serialize, Deserialize,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(32))")]
+pub enum TargetBaselineProperty {
+    #[sea_orm(string_value = "ssh_host_key")]
+    SshHostKey,
+    /// Whether the connection used TLS at all
+    #[sea_orm(string_value = "tls")]
+    Tls,
+    #[sea_orm(string_value = "tls_version")]
+    TlsVersion,
+    #[sea_orm(string_value = "tls_cipher_suite")]
+    TlsCipherSuite,
+    #[sea_orm(string_value = "tls_certificate")]
+    TlsCertificate,
+}
+
+/// What a target address presented when Warpgate first connected to it
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
+#[sea_orm(table_name = "target_baselines")]
+#[oai(rename = "TargetBaseline")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// Target that the address was last seen for
+    pub target_name: String,
+    pub host: String,
+    pub port: i32,
+    pub property: TargetBaselineProperty,
+    pub value: String,
+    pub first_seen: DateTime<Utc>,
+    /// Last time the target presented the same value
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,11 +1,14 @@
+use chrono::{DateTime, Utc};
 use poem_openapi::Object;
 use sea_orm::entity::prelude::*;
-use sea_orm::Set;
+use sea_orm::{QueryFilter, Set};
 use serde::Serialize;
 use uuid::Uuid;
 use warpgate_common::{User, UserDetails, WarpgateError};
 
-use crate::{OtpCredential, PasswordCredential, PublicKeyCredential, Role, SsoCredential};
+use crate::{
+    OtpCredential, PasswordCredential, PublicKeyCredential, Role, SsoCredential, UserRoleAssignment,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Object)]
 #[sea_orm(table_name = "users")]
@@ -15,6 +18,12 @@ pub struct Model {
     pub id: Uuid,
     pub username: String,
     pub credential_policy: serde_json::Value,
+    /// The account can't be used before this time
+    pub valid_from: Option<DateTime<Utc>>,
+    /// The account can't be used after this time
+    pub valid_until: Option<DateTime<Utc>>,
+    /// When the account was deactivated after its expiry
+    pub deactivated: Option<DateTime<Utc>>,
 }
 
 impl Related<super::Role::Entity> for Entity {
@@ -57,6 +66,12 @@ impl Related<super::ApiToken::Entity> for Entity {
     }
 }
 
+impl Related<super::RefreshToken::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::RefreshTokens.def()
+    }
+}
+
 #[derive(Copy, Clone, Debug, EnumIter)]
 #[allow(clippy::enum_variant_names)]
 pub enum Relation {
@@ -65,6 +80,7 @@ pub enum Relation {
     PublicKeyCredentials,
     SsoCredentials,
     ApiTokens,
+    RefreshTokens,
 }
 
 impl RelationTrait for Relation {
@@ -90,6 +106,10 @@ impl RelationTrait for Relation {
                 .from(Column::Id)
                 .to(super::ApiToken::Column::UserId)
                 .into(),
+            Self::RefreshTokens => Entity::has_many(super::RefreshToken::Entity)
+                .from(Column::Id)
+                .to(super::RefreshToken::Column::UserId)
+                .into(),
         }
     }
 }
@@ -104,15 +124,54 @@ impl TryFrom<Model> for User {
             id: model.id,
             username: model.username,
             credential_policy: serde_json::from_value(model.credential_policy)?,
+            valid_from: model.valid_from,
+            valid_until: model.valid_until,
+            deactivated: model.deactivated,
         })
     }
 }
 
+/// Whether `now` falls within an optional validity period
+pub fn is_within_validity(
+    valid_from: Option<DateTime<Utc>>,
+    valid_until: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    valid_from.is_none_or(|x| x <= now) && valid_until.is_none_or(|x| x > now)
+}
+
 impl Model {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        is_within_validity(self.valid_from, self.valid_until, now)
+    }
+
+    /// Roles whose assignment is currently within its validity period.
+    /// An inactive account has no roles at all.
+    pub async fn find_active_roles(
+        &self,
+        db: &DatabaseConnection,
+    ) -> Result<Vec<Role::Model>, DbErr> {
+        let now = Utc::now();
+        if !self.is_active(now) {
+            return Ok(vec![]);
+        }
+        let role_ids: Vec<Uuid> = UserRoleAssignment::Entity::find()
+            .filter(UserRoleAssignment::Column::UserId.eq(self.id))
+            .all(db)
+            .await?
+            .into_iter()
+            .filter(|x| x.is_active(now))
+            .map(|x| x.role_id)
+            .collect();
+        Role::Entity::find()
+            .filter(Role::Column::Id.is_in(role_ids))
+            .all(db)
+            .await
+    }
+
     pub async fn load_details(self, db: &DatabaseConnection) -> Result<UserDetails, WarpgateError> {
         let roles: Vec<String> = self
-            .find_related(Role::Entity)
-            .all(db)
+            .find_active_roles(db)
             .await?
             .into_iter()
             .map(Into::<warpgate_common::Role>::into)
@@ -165,6 +224,9 @@ impl TryFrom<User> for ActiveModel {
             id: Set(user.id),
             username: Set(user.username),
             credential_policy: Set(serde_json::to_value(&user.credential_policy)?),
+            valid_from: Set(user.valid_from),
+            valid_until: Set(user.valid_until),
+            deactivated: Set(user.deactivated),
         })
     }
 }
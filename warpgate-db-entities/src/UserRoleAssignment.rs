@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use poem_openapi::Object;
 use sea_orm::entity::prelude::*;
 use serde::Serialize;
@@ -11,6 +12,16 @@ pub struct Model {
     pub id: i32,
     pub user_id: Uuid,
     pub role_id: Uuid,
+    /// The role doesn't apply before this time
+    pub valid_from: Option<DateTime<Utc>>,
+    /// The role doesn't apply after this time
+    pub valid_until: Option<DateTime<Utc>>,
+}
+
+impl Model {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        super::User::is_within_validity(self.valid_from, self.valid_until, now)
+    }
 }
 
 #[derive(Copy, Clone, Debug, EnumIter)]
@@ -1,17 +1,33 @@
 #![allow(non_snake_case)]
 
 pub mod ApiToken;
+pub mod AuthFailure;
+pub mod AuthRateLimit;
+pub mod ConfigChange;
+pub mod DatabaseSessionDetails;
+pub mod DirectCredential;
+pub mod DropBoxEvent;
+pub mod HttpSession;
+pub mod HttpSessionDetails;
+pub mod IpBan;
 pub mod KnownHost;
 pub mod LogEntry;
+pub mod Notification;
+pub mod NotificationRead;
 pub mod OtpCredential;
 pub mod Parameters;
+pub mod OidcAuthorizationCode;
 pub mod PasswordCredential;
 pub mod PublicKeyCredential;
 pub mod Recording;
+pub mod RefreshToken;
 pub mod Role;
+pub mod RolePortalSettings;
 pub mod Session;
+pub mod SshSessionDetails;
 pub mod SsoCredential;
 pub mod Target;
+pub mod TargetBaseline;
 pub mod TargetRoleAssignment;
 pub mod Ticket;
 pub mod User;
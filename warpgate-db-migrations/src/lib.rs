@@ -16,6 +16,34 @@ mod m00011_rsa_key_algos;
 mod m00012_add_openssh_public_key_label;
 mod m00013_add_openssh_public_key_dates;
 mod m00014_api_tokens;
+mod m00015_recording_replication;
+mod m00016_api_token_scopes;
+mod m00017_self_service_tickets;
+mod m00018_session_consent;
+mod m00019_session_work_item;
+mod m00020_http_sessions;
+mod m00021_refresh_tokens;
+mod m00022_account_lifecycle;
+mod m00023_session_user_agent;
+mod m00024_session_termination;
+mod m00025_config_changes;
+mod m00026_target_baselines;
+mod m00027_session_details;
+mod m00028_auth_failures;
+mod m00029_target_honeypot;
+mod m00030_target_host_overrides;
+mod m00031_notifications;
+mod m00032_role_portal_settings;
+mod m00033_direct_credentials;
+mod m00034_target_session_limits;
+mod m00035_auth_rate_limits;
+mod m00036_ip_bans;
+mod m00037_target_monitor;
+mod m00038_target_idle_timeout;
+mod m00039_refresh_token_scopes;
+mod m00040_oidc_authorization_codes;
+mod m00041_drop_box_events;
+mod m00042_target_draining;
 
 pub struct Migrator;
 
@@ -37,6 +65,34 @@ impl MigratorTrait for Migrator {
             Box::new(m00012_add_openssh_public_key_label::Migration),
             Box::new(m00013_add_openssh_public_key_dates::Migration),
             Box::new(m00014_api_tokens::Migration),
+            Box::new(m00015_recording_replication::Migration),
+            Box::new(m00016_api_token_scopes::Migration),
+            Box::new(m00017_self_service_tickets::Migration),
+            Box::new(m00018_session_consent::Migration),
+            Box::new(m00019_session_work_item::Migration),
+            Box::new(m00020_http_sessions::Migration),
+            Box::new(m00021_refresh_tokens::Migration),
+            Box::new(m00022_account_lifecycle::Migration),
+            Box::new(m00023_session_user_agent::Migration),
+            Box::new(m00024_session_termination::Migration),
+            Box::new(m00025_config_changes::Migration),
+            Box::new(m00026_target_baselines::Migration),
+            Box::new(m00027_session_details::Migration),
+            Box::new(m00028_auth_failures::Migration),
+            Box::new(m00029_target_honeypot::Migration),
+            Box::new(m00030_target_host_overrides::Migration),
+            Box::new(m00031_notifications::Migration),
+            Box::new(m00032_role_portal_settings::Migration),
+            Box::new(m00033_direct_credentials::Migration),
+            Box::new(m00034_target_session_limits::Migration),
+            Box::new(m00035_auth_rate_limits::Migration),
+            Box::new(m00036_ip_bans::Migration),
+            Box::new(m00037_target_monitor::Migration),
+            Box::new(m00038_target_idle_timeout::Migration),
+            Box::new(m00039_refresh_token_scopes::Migration),
+            Box::new(m00040_oidc_authorization_codes::Migration),
+            Box::new(m00041_drop_box_events::Migration),
+            Box::new(m00042_target_draining::Migration),
         ]
     }
 }
@@ -44,3 +100,12 @@ impl MigratorTrait for Migrator {
 pub async fn migrate_database(connection: &DatabaseConnection) -> Result<(), DbErr> {
     Migrator::up(connection, None).await
 }
+
+/// Names of the migrations that haven't been applied to the database yet
+pub async fn pending_migrations(connection: &DatabaseConnection) -> Result<Vec<String>, DbErr> {
+    Ok(Migrator::get_pending_migrations(connection)
+        .await?
+        .iter()
+        .map(|m| m.name().to_owned())
+        .collect())
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00015_recording_replication"
+    }
+}
+
+use crate::m00003_create_recording::recording;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(recording::Entity)
+                    .add_column(ColumnDef::new(Alias::new("replicated")).date_time().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(recording::Entity)
+                    .drop_column(Alias::new("replicated"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
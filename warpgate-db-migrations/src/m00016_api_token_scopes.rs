@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00016_api_token_scopes"
+    }
+}
+
+use crate::m00014_api_tokens::api_tokens;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(api_tokens::Entity)
+                    .add_column(ColumnDef::new(Alias::new("scope")).json().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(api_tokens::Entity)
+                    .drop_column(Alias::new("scope"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00017_self_service_tickets"
+    }
+}
+
+use crate::m00010_parameters::parameters;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(parameters::Entity)
+                    .add_column(
+                        ColumnDef::new(Alias::new("allow_self_service_tickets"))
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(parameters::Entity)
+                    .add_column(
+                        ColumnDef::new(Alias::new("self_service_ticket_max_validity"))
+                            .big_integer()
+                            .not_null()
+                            .default(24 * 60 * 60),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(parameters::Entity)
+                    .add_column(
+                        ColumnDef::new(Alias::new("self_service_ticket_protocols"))
+                            .json()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            "self_service_ticket_protocols",
+            "self_service_ticket_max_validity",
+            "allow_self_service_tickets",
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(parameters::Entity)
+                        .drop_column(Alias::new(column))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00019_session_work_item"
+    }
+}
+
+use crate::m00002_create_session::session;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .add_column(ColumnDef::new(Alias::new("work_item")).string().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .drop_column(Alias::new("work_item"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,99 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+use super::m00008_users::user as User;
+
+pub mod refresh_tokens {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use sea_orm::sea_query::ForeignKeyAction;
+    use serde::Serialize;
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+    #[sea_orm(table_name = "refresh_tokens")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub family_id: Uuid,
+        pub user_id: Uuid,
+        pub label: String,
+        pub secret: String,
+        pub access_secret: String,
+        pub access_expiry: DateTime<Utc>,
+        pub created: DateTime<Utc>,
+        pub refreshed: DateTime<Utc>,
+        pub expiry: DateTime<Utc>,
+        pub used: bool,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        User,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::User => Entity::belongs_to(super::User::Entity)
+                    .from(Column::UserId)
+                    .to(super::User::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl Related<super::User::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::User.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00021_refresh_tokens"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(refresh_tokens::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(refresh_tokens::Entity)
+                    .name("refresh_tokens__secret")
+                    .col(refresh_tokens::Column::Secret)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(refresh_tokens::Entity)
+                    .name("refresh_tokens__access_secret")
+                    .col(refresh_tokens::Column::AccessSecret)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(refresh_tokens::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
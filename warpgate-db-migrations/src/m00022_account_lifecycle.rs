@@ -0,0 +1,65 @@
+use sea_orm_migration::prelude::*;
+
+use crate::m00008_users::user;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00022_account_lifecycle"
+    }
+}
+
+const USER_COLUMNS: &[&str] = &["valid_from", "valid_until", "deactivated"];
+const USER_ROLE_COLUMNS: &[&str] = &["valid_from", "valid_until"];
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in USER_COLUMNS {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(user::Entity)
+                        .add_column(ColumnDef::new(Alias::new(*column)).date_time().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+        for column in USER_ROLE_COLUMNS {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new("user_roles"))
+                        .add_column(ColumnDef::new(Alias::new(*column)).date_time().null())
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in USER_ROLE_COLUMNS {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Alias::new("user_roles"))
+                        .drop_column(Alias::new(*column))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        for column in USER_COLUMNS {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(user::Entity)
+                        .drop_column(Alias::new(*column))
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
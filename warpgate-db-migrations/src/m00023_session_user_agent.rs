@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00023_session_user_agent"
+    }
+}
+
+use crate::m00002_create_session::session;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .add_column(ColumnDef::new(Alias::new("user_agent")).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .drop_column(Alias::new("user_agent"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
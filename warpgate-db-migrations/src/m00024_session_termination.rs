@@ -0,0 +1,56 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00024_session_termination"
+    }
+}
+
+use crate::m00002_create_session::session;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .add_column(
+                        ColumnDef::new(Alias::new("termination_reason"))
+                            .string_len(16)
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .add_column(ColumnDef::new(Alias::new("exit_code")).big_integer().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .drop_column(Alias::new("exit_code"))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(session::Entity)
+                    .drop_column(Alias::new("termination_reason"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,68 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod config_changes {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use serde::Serialize;
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+    #[sea_orm(table_name = "config_changes")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        #[sea_orm(column_type = "String(Some(16))")]
+        pub object_kind: String,
+        pub object_id: Uuid,
+        pub version: i64,
+        #[sea_orm(column_type = "String(Some(16))")]
+        pub action: String,
+        pub author: Option<String>,
+        pub timestamp: DateTime<Utc>,
+        pub snapshot: Option<serde_json::Value>,
+        pub diff: serde_json::Value,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00025_config_changes"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(config_changes::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(config_changes::Entity)
+                    .name("config_changes__object")
+                    .col(config_changes::Column::ObjectKind)
+                    .col(config_changes::Column::ObjectId)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(config_changes::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
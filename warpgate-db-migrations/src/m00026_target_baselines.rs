@@ -0,0 +1,68 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod target_baselines {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use serde::Serialize;
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+    #[sea_orm(table_name = "target_baselines")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub target_name: String,
+        pub host: String,
+        pub port: i32,
+        #[sea_orm(column_type = "String(Some(32))")]
+        pub property: String,
+        pub value: String,
+        pub first_seen: DateTime<Utc>,
+        pub last_seen: DateTime<Utc>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00026_target_baselines"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(target_baselines::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(target_baselines::Entity)
+                    .name("target_baselines__address_property")
+                    .col(target_baselines::Column::Host)
+                    .col(target_baselines::Column::Port)
+                    .col(target_baselines::Column::Property)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(target_baselines::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
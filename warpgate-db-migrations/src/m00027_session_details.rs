@@ -0,0 +1,172 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod ssh_session_details {
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    use crate::m00002_create_session::session;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "ssh_session_details")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub session_id: Uuid,
+        pub client_version: Option<String>,
+        pub pty_columns: Option<i32>,
+        pub pty_rows: Option<i32>,
+        #[sea_orm(column_type = "Text", nullable)]
+        pub command: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        Session,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::Session => Entity::belongs_to(session::Entity)
+                    .from(Column::SessionId)
+                    .to(session::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl Related<session::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::Session.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod http_session_details {
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    use crate::m00002_create_session::session;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "http_session_details")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub session_id: Uuid,
+        pub host: Option<String>,
+        #[sea_orm(column_type = "Text", nullable)]
+        pub user_agent: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        Session,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::Session => Entity::belongs_to(session::Entity)
+                    .from(Column::SessionId)
+                    .to(session::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl Related<session::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::Session.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod database_session_details {
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    use crate::m00002_create_session::session;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "database_session_details")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub session_id: Uuid,
+        pub database: Option<String>,
+        pub client_application: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        Session,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::Session => Entity::belongs_to(session::Entity)
+                    .from(Column::SessionId)
+                    .to(session::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl Related<session::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::Session.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00027_session_details"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(ssh_session_details::Entity))
+            .await?;
+        manager
+            .create_table(schema.create_table_from_entity(http_session_details::Entity))
+            .await?;
+        manager
+            .create_table(schema.create_table_from_entity(database_session_details::Entity))
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(database_session_details::Entity)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(http_session_details::Entity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(ssh_session_details::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod auth_failures {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use serde::Serialize;
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize)]
+    #[sea_orm(table_name = "auth_failures")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub timestamp: DateTime<Utc>,
+        pub protocol: String,
+        pub remote_ip: Option<String>,
+        pub remote_port: Option<i32>,
+        pub username: Option<String>,
+        pub credential_kinds: serde_json::Value,
+        pub client_banner: Option<String>,
+        pub user_agent: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00028_auth_failures"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(auth_failures::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(auth_failures::Entity)
+                    .name("auth_failures__timestamp")
+                    .col(auth_failures::Column::Timestamp)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(auth_failures::Entity)
+                    .name("auth_failures__remote_ip")
+                    .col(auth_failures::Column::RemoteIp)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(auth_failures::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
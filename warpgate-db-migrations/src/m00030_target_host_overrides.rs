@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00030_target_host_overrides"
+    }
+}
+
+use crate::m00007_targets_and_roles::target;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(target::Entity)
+                    .add_column(ColumnDef::new(Alias::new("host_overrides")).json().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(target::Entity)
+                    .drop_column(Alias::new("host_overrides"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
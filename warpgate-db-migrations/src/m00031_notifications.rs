@@ -0,0 +1,134 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod notifications {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "notifications")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub timestamp: DateTime<Utc>,
+        pub kind: String,
+        #[sea_orm(column_type = "String(Some(16))")]
+        pub severity: String,
+        #[sea_orm(column_type = "Text")]
+        pub message: String,
+        pub target: Option<String>,
+        pub key: Option<String>,
+        pub resolved: Option<DateTime<Utc>>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod notification_reads {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    use super::notifications;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "notification_reads")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = true)]
+        pub id: i32,
+        pub notification_id: Uuid,
+        pub username: String,
+        pub timestamp: DateTime<Utc>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        Notification,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::Notification => Entity::belongs_to(notifications::Entity)
+                    .from(Column::NotificationId)
+                    .to(notifications::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl Related<notifications::Entity> for Entity {
+        fn to() -> RelationDef {
+            Relation::Notification.def()
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00031_notifications"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(notifications::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(notifications::Entity)
+                    .name("notifications__timestamp")
+                    .col(notifications::Column::Timestamp)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(notifications::Entity)
+                    .name("notifications__key")
+                    .col(notifications::Column::Key)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_table(schema.create_table_from_entity(notification_reads::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(notification_reads::Entity)
+                    .name("notification_reads__notification_username")
+                    .col(notification_reads::Column::NotificationId)
+                    .col(notification_reads::Column::Username)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(notification_reads::Entity).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(notifications::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod role_portal_settings {
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    use crate::m00007_targets_and_roles::role;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "role_portal_settings")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub role_id: Uuid,
+        #[sea_orm(column_type = "Json")]
+        pub sections: serde_json::Value,
+        #[sea_orm(column_type = "Json")]
+        pub hidden_targets: serde_json::Value,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter)]
+    pub enum Relation {
+        Role,
+    }
+
+    impl RelationTrait for Relation {
+        fn def(&self) -> RelationDef {
+            match self {
+                Self::Role => Entity::belongs_to(role::Entity)
+                    .from(Column::RoleId)
+                    .to(role::Column::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .into(),
+            }
+        }
+    }
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00032_role_portal_settings"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(role_portal_settings::Entity))
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(role_portal_settings::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
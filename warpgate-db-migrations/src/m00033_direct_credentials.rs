@@ -0,0 +1,65 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod direct_credentials {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+    use uuid::Uuid;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "direct_credentials")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub target_id: Uuid,
+        pub target_name: String,
+        pub username: String,
+        pub database_username: String,
+        pub created: DateTime<Utc>,
+        pub expires: DateTime<Utc>,
+        pub removed: Option<DateTime<Utc>>,
+        #[sea_orm(column_type = "Text", nullable)]
+        pub removal_error: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00033_direct_credentials"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(direct_credentials::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .table(direct_credentials::Entity)
+                    .name("direct_credentials__expires")
+                    .col(direct_credentials::Column::Expires)
+                    .to_owned(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(direct_credentials::Entity).to_owned())
+            .await?;
+        Ok(())
+    }
+}
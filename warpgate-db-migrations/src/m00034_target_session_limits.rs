@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00034_target_session_limits"
+    }
+}
+
+use crate::m00007_targets_and_roles::target;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite can only add one column per statement
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(target::Entity)
+                    .add_column(
+                        ColumnDef::new(Alias::new("max_concurrent_sessions"))
+                            .integer()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(target::Entity)
+                    .add_column(
+                        ColumnDef::new(Alias::new("session_limit_message"))
+                            .text()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(target::Entity)
+                    .drop_column(Alias::new("session_limit_message"))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(target::Entity)
+                    .drop_column(Alias::new("max_concurrent_sessions"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,61 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod oidc_authorization_codes {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "oidc_authorization_codes")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: String,
+        pub client_id: String,
+        #[sea_orm(column_type = "Text")]
+        pub redirect_uri: String,
+        pub username: String,
+        #[sea_orm(column_type = "Json")]
+        pub roles: serde_json::Value,
+        #[sea_orm(column_type = "Text", nullable)]
+        pub nonce: Option<String>,
+        pub code_challenge: Option<String>,
+        #[sea_orm(column_type = "String(Some(16))", nullable)]
+        pub code_challenge_method: Option<String>,
+        pub auth_time: DateTime<Utc>,
+        pub expires: DateTime<Utc>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00040_oidc_authorization_codes"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(oidc_authorization_codes::Entity))
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(oidc_authorization_codes::Entity)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,62 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+pub mod drop_box_events {
+    use chrono::{DateTime, Utc};
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "drop_box_events")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: Uuid,
+        pub session_id: Uuid,
+        pub timestamp: DateTime<Utc>,
+        pub username: Option<String>,
+        #[sea_orm(column_type = "String(Some(16))")]
+        pub action: String,
+        pub via_portal: bool,
+        pub item_name: String,
+        pub size: i64,
+        pub sha256: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &str {
+        "m00041_drop_box_events"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+        manager
+            .create_table(schema.create_table_from_entity(drop_box_events::Entity))
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("drop_box_events__session_id")
+                    .table(drop_box_events::Entity)
+                    .col(drop_box_events::Column::SessionId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(drop_box_events::Entity).to_owned())
+            .await
+    }
+}
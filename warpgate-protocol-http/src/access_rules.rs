@@ -0,0 +1,172 @@
+use http::Method;
+use percent_encoding::percent_decode_str;
+use warpgate_common::TargetHTTPOptions;
+
+/// Checks a request against the target's allowed methods and allowed
+/// and denied path patterns. Paths are normalized first so that e.g.
+/// `/%61dmin` can't sneak past an `/admin*` rule. The request is forwarded
+/// with its original path, so paths that an upstream could resolve
+/// differently from the normalized form are refused outright.
+pub fn is_request_allowed(options: &TargetHTTPOptions, method: &Method, path: &str) -> bool {
+    if !options.allowed_methods.is_empty()
+        && !options
+            .allowed_methods
+            .iter()
+            .any(|x| x.trim().eq_ignore_ascii_case(method.as_str()))
+    {
+        return false;
+    }
+
+    if options.allowed_paths.is_empty() && options.denied_paths.is_empty() {
+        return true;
+    }
+
+    if is_ambiguous_path(path) {
+        return false;
+    }
+
+    let path = normalize_path(path);
+    if options
+        .denied_paths
+        .iter()
+        .any(|pattern| matches_pattern(pattern.trim(), &path))
+    {
+        return false;
+    }
+    options.allowed_paths.is_empty()
+        || options
+            .allowed_paths
+            .iter()
+            .any(|pattern| matches_pattern(pattern.trim(), &path))
+}
+
+/// Dot segments, path parameters, backslashes and encoded separators or
+/// percent signs are resolved differently by different servers
+fn is_ambiguous_path(path: &str) -> bool {
+    path.split('/').any(|segment| {
+        let decoded = percent_decode_str(segment).decode_utf8_lossy();
+        decoded == "." || decoded == ".." || decoded.contains(['/', '\\', ';', '%', '\0'])
+    })
+}
+
+fn normalize_path(path: &str) -> String {
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let mut segments = vec![];
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    let mut normalized = format!("/{}", segments.join("/"));
+    if path.ends_with('/') && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// `*` matches any run of characters, including slashes
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(first) = parts.next() else {
+        return false;
+    };
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.split_once(part) {
+            Some((_, after)) => rest = after,
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::Tls;
+
+    use super::*;
+
+    fn options(methods: &[&str], allowed: &[&str], denied: &[&str]) -> TargetHTTPOptions {
+        let list = |x: &[&str]| x.iter().map(|x| x.to_string()).collect();
+        TargetHTTPOptions {
+            url: "http://localhost".into(),
+            tls: Tls::default(),
+            headers: None,
+            external_host: None,
+            max_body_size: None,
+            shadow: None,
+            record_bodies: None,
+            allowed_methods: list(methods),
+            allowed_paths: list(allowed),
+            denied_paths: list(denied),
+        }
+    }
+
+    #[test]
+    fn test_methods() {
+        let options = options(&["GET", "head"], &[], &[]);
+        assert!(is_request_allowed(&options, &Method::GET, "/"));
+        assert!(is_request_allowed(&options, &Method::HEAD, "/"));
+        assert!(!is_request_allowed(&options, &Method::POST, "/"));
+        assert!(is_request_allowed(
+            &self::options(&[], &[], &[]),
+            &Method::DELETE,
+            "/"
+        ));
+    }
+
+    #[test]
+    fn test_paths() {
+        let options = options(&[], &["/api/*", "/"], &["/api/admin*", "*.env"]);
+        assert!(is_request_allowed(&options, &Method::GET, "/"));
+        assert!(is_request_allowed(&options, &Method::GET, "/api/users"));
+        assert!(!is_request_allowed(&options, &Method::GET, "/other"));
+        assert!(!is_request_allowed(&options, &Method::GET, "/api/admin/x"));
+        assert!(!is_request_allowed(&options, &Method::GET, "/api/x/.env"));
+        assert!(!is_request_allowed(&options, &Method::GET, "/api//%61dmin"));
+    }
+
+    #[test]
+    fn test_ambiguous_paths() {
+        let options = options(&[], &["/api/*"], &["/api/admin*"]);
+        for path in [
+            "/api/x/../admin",
+            "/api/x/%2e%2e/admin",
+            "/api/./users",
+            "/api/users;x=1",
+            "/api/users%3b",
+            "/api\\admin",
+            "/api%2fadmin",
+            "/api%2Fadmin",
+            "/api%5cadmin",
+            "/api/%2561dmin",
+            "/api/users%00",
+        ] {
+            assert!(!is_request_allowed(&options, &Method::GET, path), "{path}");
+        }
+        assert!(is_request_allowed(&options, &Method::GET, "/api/a%20b"));
+
+        // Without path rules, paths are passed through untouched
+        let options = self::options(&["GET"], &[], &[]);
+        assert!(is_request_allowed(&options, &Method::GET, "/x/../y;z"));
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("/a/*/c", "/a/b/c"));
+        assert!(matches_pattern("/a*a", "/a/a"));
+        assert!(!matches_pattern("/a*a", "/a"));
+        assert!(matches_pattern("*", "/anything"));
+        assert!(!matches_pattern("/a", "/ab"));
+    }
+}
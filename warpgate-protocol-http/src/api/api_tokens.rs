@@ -9,6 +9,7 @@ use warpgate_common::helpers::hash::generate_ticket_secret;
 use warpgate_common::WarpgateError;
 use warpgate_core::Services;
 use warpgate_db_entities::ApiToken;
+use warpgate_db_entities::ApiToken::ApiTokenScope;
 
 use super::common::get_user;
 use crate::common::{endpoint_auth, RequestAuthorization};
@@ -27,6 +28,8 @@ enum GetApiTokensResponse {
 struct NewApiToken {
     label: String,
     expiry: DateTime<Utc>,
+    /// Limits the token to specific targets and protocols
+    scope: Option<ApiTokenScope>,
 }
 
 #[derive(Object)]
@@ -35,6 +38,7 @@ struct ExistingApiToken {
     label: String,
     created: DateTime<Utc>,
     expiry: DateTime<Utc>,
+    scope: Option<ApiTokenScope>,
 }
 
 impl From<ApiToken::Model> for ExistingApiToken {
@@ -44,6 +48,7 @@ impl From<ApiToken::Model> for ExistingApiToken {
             label: token.label,
             created: token.created,
             expiry: token.expiry,
+            scope: token.scope,
         }
     }
 }
@@ -87,7 +92,7 @@ impl Api {
     ) -> Result<GetApiTokensResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(*auth, &*db).await? else {
+        let Some(user_model) = get_user(*auth, &db).await? else {
             return Ok(GetApiTokensResponse::Unauthorized);
         };
 
@@ -112,7 +117,7 @@ impl Api {
     ) -> Result<CreateApiTokenResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(CreateApiTokenResponse::Unauthorized);
         };
 
@@ -124,6 +129,7 @@ impl Api {
             expiry: Set(body.expiry),
             label: Set(body.label.clone()),
             secret: Set(secret.expose_secret().to_string()),
+            scope: Set(body.scope.clone()),
         }
         .insert(&*db)
         .await
@@ -149,7 +155,7 @@ impl Api {
     ) -> Result<DeleteApiTokenResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(DeleteApiTokenResponse::Unauthorized);
         };
 
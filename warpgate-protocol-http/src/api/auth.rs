@@ -12,13 +12,14 @@ use tokio::sync::Mutex;
 use tracing::*;
 use uuid::Uuid;
 use warpgate_common::auth::{AuthCredential, AuthResult, AuthState, CredentialKind};
-use warpgate_common::{Secret, WarpgateError};
+use warpgate_common::i18n::Message;
+use warpgate_common::{ErrorCode, Secret, WarpgateError};
 use warpgate_core::{ConfigProvider, Services};
 
 use super::common::logout;
 use crate::common::{
-    authorize_session, endpoint_auth, get_auth_state_for_request, RequestAuthorization,
-    SessionAuthorization, SessionExt,
+    auth_attempt_allowed, authorize_session, endpoint_auth, get_auth_state_for_request,
+    record_auth_failure, request_language, RequestAuthorization, SessionAuthorization, SessionExt,
 };
 use crate::session::SessionStore;
 
@@ -27,15 +28,15 @@ pub struct Api;
 #[derive(Object)]
 struct LoginRequest {
     username: String,
-    password: String,
+    password: Secret<String>,
 }
 
 #[derive(Object)]
 struct OtpLoginRequest {
-    otp: String,
+    otp: Secret<String>,
 }
 
-#[derive(Enum)]
+#[derive(Enum, PartialEq, Eq)]
 enum ApiAuthState {
     NotStarted,
     Failed,
@@ -50,6 +51,31 @@ enum ApiAuthState {
 #[derive(Object)]
 struct LoginFailureResponse {
     state: ApiAuthState,
+    /// Only set if the login has failed for good
+    code: Option<String>,
+    /// Translated according to `Accept-Language`
+    message: Option<String>,
+}
+
+impl LoginFailureResponse {
+    fn new(state: ApiAuthState, req: &Request) -> Self {
+        if state != ApiAuthState::Failed {
+            return Self {
+                state,
+                code: None,
+                message: None,
+            };
+        }
+        Self {
+            state,
+            code: Some(ErrorCode::AuthFailed.to_string()),
+            message: Some(
+                Message::IncorrectCredentials
+                    .text(request_language(req))
+                    .to_owned(),
+            ),
+        }
+    }
 }
 
 #[derive(ApiResponse)]
@@ -125,6 +151,13 @@ impl Api {
         services: Data<&Services>,
         body: Json<LoginRequest>,
     ) -> poem::Result<LoginResponse> {
+        if !auth_attempt_allowed(req, &services, &body.username).await {
+            return Ok(LoginResponse::Failure(Json(LoginFailureResponse::new(
+                ApiAuthState::Failed,
+                req,
+            ))));
+        }
+
         let mut auth_state_store = services.auth_state_store.lock().await;
         let state_arc = match get_auth_state_for_request(
             &body.username,
@@ -134,9 +167,17 @@ impl Api {
         .await
         {
             Err(WarpgateError::UserNotFound(_)) => {
-                return Ok(LoginResponse::Failure(Json(LoginFailureResponse {
-                    state: ApiAuthState::Failed,
-                })))
+                record_auth_failure(
+                    req,
+                    &services,
+                    &body.username,
+                    vec![CredentialKind::Password],
+                )
+                .await;
+                return Ok(LoginResponse::Failure(Json(LoginFailureResponse::new(
+                    ApiAuthState::Failed,
+                    req,
+                ))));
             }
             x => x,
         }?;
@@ -144,12 +185,21 @@ impl Api {
 
         let mut cp = services.config_provider.lock().await;
 
-        let password_cred = AuthCredential::Password(Secret::new(body.password.clone()));
+        let password_cred = AuthCredential::Password(body.password.clone());
         if cp
             .validate_credential(state.username(), &password_cred)
             .await?
         {
             state.add_valid_credential(password_cred);
+        } else {
+            state.add_invalid_credential(&password_cred);
+            record_auth_failure(
+                req,
+                &services,
+                state.username(),
+                state.attempted_credential_kinds(),
+            )
+            .await;
         }
 
         match state.verify() {
@@ -160,9 +210,10 @@ impl Api {
             }
             x => {
                 error!("Auth rejected");
-                Ok(LoginResponse::Failure(Json(LoginFailureResponse {
-                    state: x.into(),
-                })))
+                Ok(LoginResponse::Failure(Json(LoginFailureResponse::new(
+                    x.into(),
+                    req,
+                ))))
             }
         }
     }
@@ -180,18 +231,35 @@ impl Api {
         let mut auth_state_store = services.auth_state_store.lock().await;
 
         let Some(state_arc) = state_id.and_then(|id| auth_state_store.get(&id.0)) else {
-            return Ok(LoginResponse::Failure(Json(LoginFailureResponse {
-                state: ApiAuthState::NotStarted,
-            })));
+            return Ok(LoginResponse::Failure(Json(LoginFailureResponse::new(
+                ApiAuthState::NotStarted,
+                req,
+            ))));
         };
 
         let mut state = state_arc.lock().await;
 
+        if !auth_attempt_allowed(req, &services, state.username()).await {
+            return Ok(LoginResponse::Failure(Json(LoginFailureResponse::new(
+                ApiAuthState::Failed,
+                req,
+            ))));
+        }
+
         let mut cp = services.config_provider.lock().await;
 
-        let otp_cred = AuthCredential::Otp(body.otp.clone().into());
+        let otp_cred = AuthCredential::Otp(body.otp.clone());
         if cp.validate_credential(state.username(), &otp_cred).await? {
             state.add_valid_credential(otp_cred);
+        } else {
+            state.add_invalid_credential(&otp_cred);
+            record_auth_failure(
+                req,
+                &services,
+                state.username(),
+                state.attempted_credential_kinds(),
+            )
+            .await;
         }
 
         match state.verify() {
@@ -200,9 +268,10 @@ impl Api {
                 authorize_session(req, username).await?;
                 Ok(LoginResponse::Success)
             }
-            x => Ok(LoginResponse::Failure(Json(LoginFailureResponse {
-                state: x.into(),
-            }))),
+            x => Ok(LoginResponse::Failure(Json(LoginFailureResponse::new(
+                x.into(),
+                req,
+            )))),
         }
     }
 
@@ -1,10 +1,14 @@
+use std::sync::Arc;
+
 use poem::session::Session;
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tokio::sync::Mutex;
 use tracing::info;
-use warpgate_common::WarpgateError;
+use warpgate_common::{SessionId, WarpgateError};
+use warpgate_core::{Services, SessionState};
 use warpgate_db_entities as entities;
 
-use crate::common::RequestAuthorization;
+use crate::common::{RequestAuthorization, SessionAuthorization};
 use crate::session::SessionStore;
 
 pub fn logout(session: &Session, session_middleware: &mut SessionStore) {
@@ -17,6 +21,10 @@ pub async fn get_user(
     auth: &RequestAuthorization,
     db: &DatabaseConnection,
 ) -> Result<Option<entities::User::Model>, WarpgateError> {
+    // API tokens only grant access to targets, not to the user's profile
+    if let RequestAuthorization::Session(SessionAuthorization::ApiToken { .. }) = auth {
+        return Ok(None);
+    }
     let Some(username) = auth.username() else {
         return Ok(None);
     };
@@ -31,3 +39,49 @@ pub async fn get_user(
 
     Ok(Some(user_model))
 }
+
+/// Looks up an active SSH session belonging to `username`
+pub async fn find_own_ssh_session(
+    services: &Services,
+    id: &SessionId,
+    username: &str,
+) -> Option<Arc<Mutex<SessionState>>> {
+    let session = services.state.lock().await.sessions.get(id).cloned()?;
+    let is_own = {
+        let state = session.lock().await;
+        state.username.as_deref() == Some(username)
+            && state
+                .target
+                .as_ref()
+                .is_some_and(|target| target.options.protocol_name() == "SSH")
+    };
+    is_own.then_some(session)
+}
+
+/// Active SSH sessions belonging to `username`, oldest first
+pub async fn list_own_ssh_sessions(
+    services: &Services,
+    username: &str,
+) -> Vec<(SessionId, Arc<Mutex<SessionState>>)> {
+    let session_ids = services
+        .state
+        .lock()
+        .await
+        .sessions
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut result = vec![];
+    for id in session_ids {
+        if let Some(session) = find_own_ssh_session(services, &id, username).await {
+            let started = session.lock().await.started;
+            result.push((started, id, session));
+        }
+    }
+    result.sort_by_key(|(started, ..)| *started);
+    result
+        .into_iter()
+        .map(|(_, id, session)| (id, session))
+        .collect()
+}
@@ -7,7 +7,9 @@ use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, Enum, Object, OpenApi};
 use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, QueryFilter, Set};
 use uuid::Uuid;
-use warpgate_common::{User, UserPasswordCredential, UserRequireCredentialsPolicy, WarpgateError};
+use warpgate_common::{
+    Secret, User, UserPasswordCredential, UserRequireCredentialsPolicy, WarpgateError,
+};
 use warpgate_core::Services;
 use warpgate_db_entities::{self as entities, Parameters, PasswordCredential, PublicKeyCredential};
 
@@ -42,7 +44,7 @@ impl From<entities::SsoCredential::Model> for ExistingSsoCredential {
 
 #[derive(Object)]
 struct ChangePasswordRequest {
-    password: String,
+    password: Secret<String>,
 }
 
 #[derive(ApiResponse)]
@@ -182,7 +184,7 @@ impl Api {
     ) -> Result<CredentialsStateResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(*auth, &*db).await? else {
+        let Some(user_model) = get_user(*auth, &db).await? else {
             return Ok(CredentialsStateResponse::Unauthorized);
         };
 
@@ -232,7 +234,7 @@ impl Api {
     ) -> Result<ChangePasswordResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(ChangePasswordResponse::Unauthorized);
         };
 
@@ -240,7 +242,7 @@ impl Api {
             id: Set(Uuid::new_v4()),
             user_id: Set(user_model.id),
             ..PasswordCredential::ActiveModel::from(UserPasswordCredential::from_password(
-                &body.password.clone().into(),
+                &body.password,
             ))
         }
         .insert(&*db)
@@ -273,7 +275,7 @@ impl Api {
     ) -> Result<CreatePublicKeyCredentialResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(CreatePublicKeyCredentialResponse::Unauthorized);
         };
 
@@ -308,7 +310,7 @@ impl Api {
     ) -> Result<DeleteCredentialResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(DeleteCredentialResponse::Unauthorized);
         };
 
@@ -339,7 +341,7 @@ impl Api {
     ) -> Result<CreateOtpCredentialResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(CreateOtpCredentialResponse::Unauthorized);
         };
 
@@ -354,7 +356,7 @@ impl Api {
         .await
         .map_err(WarpgateError::from)?;
 
-        let details = user_model.load_details(&*db).await?;
+        let details = user_model.load_details(&db).await?;
         user.credential_policy = Some(
             user.credential_policy
                 .unwrap_or_default()
@@ -382,7 +384,7 @@ impl Api {
     ) -> Result<DeleteCredentialResponse, WarpgateError> {
         let db = services.db.lock().await;
 
-        let Some(user_model) = get_user(&*auth, &*db).await? else {
+        let Some(user_model) = get_user(&auth, &db).await? else {
             return Ok(DeleteCredentialResponse::Unauthorized);
         };
 
@@ -0,0 +1,360 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem::Request;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Enum, Object, OpenApi};
+use sea_orm::{ActiveModelTrait, Set};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::helpers::hash::generate_ticket_secret;
+use warpgate_common::WarpgateError;
+use warpgate_core::{
+    ConfigProvider, DeviceAuthorization, DeviceAuthorizationStatus, DevicePollResult, Services,
+    DEVICE_CODE_POLL_INTERVAL, TIMEOUT,
+};
+use warpgate_db_entities::Ticket;
+
+use crate::common::{endpoint_auth, RequestAuthorization, SessionAuthorization};
+use crate::logging::get_client_ip;
+
+/// How long a ticket issued through the device flow stays valid
+const DEVICE_TICKET_VALIDITY: chrono::Duration = chrono::Duration::minutes(10);
+
+pub struct Api;
+
+#[derive(Object)]
+struct StartDeviceAuthorizationRequest {
+    target_name: String,
+}
+
+#[derive(Object)]
+struct DeviceAuthorizationParams {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: String,
+    /// Seconds until the codes expire
+    expires_in: u64,
+    /// Minimum number of seconds between polls
+    interval: u64,
+}
+
+#[derive(ApiResponse)]
+enum StartDeviceAuthorizationResponse {
+    #[oai(status = 200)]
+    Ok(Json<DeviceAuthorizationParams>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 429)]
+    TooManyRequests,
+}
+
+#[derive(Object)]
+struct PollDeviceAuthorizationRequest {
+    device_code: String,
+}
+
+#[derive(Object)]
+struct DeviceTicket {
+    ticket_secret: String,
+    username: String,
+    target: String,
+    expiry: DateTime<Utc>,
+}
+
+#[derive(Enum)]
+#[oai(rename_all = "snake_case")]
+enum DeviceTokenErrorCode {
+    AuthorizationPending,
+    SlowDown,
+    AccessDenied,
+    ExpiredToken,
+}
+
+#[derive(Object)]
+struct DeviceTokenError {
+    error: DeviceTokenErrorCode,
+}
+
+#[derive(ApiResponse)]
+enum PollDeviceAuthorizationResponse {
+    #[oai(status = 200)]
+    Ok(Json<DeviceTicket>),
+    #[oai(status = 400)]
+    Error(Json<DeviceTokenError>),
+}
+
+#[derive(Enum)]
+enum DeviceAuthorizationState {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[derive(Object)]
+struct DeviceAuthorizationInfo {
+    user_code: String,
+    target: String,
+    started: DateTime<Utc>,
+    state: DeviceAuthorizationState,
+}
+
+impl From<&DeviceAuthorization> for DeviceAuthorizationInfo {
+    fn from(authorization: &DeviceAuthorization) -> Self {
+        Self {
+            user_code: authorization.user_code.clone(),
+            target: authorization.target.clone(),
+            started: authorization.started,
+            state: match authorization.status {
+                DeviceAuthorizationStatus::Pending => DeviceAuthorizationState::Pending,
+                DeviceAuthorizationStatus::Approved { .. } => DeviceAuthorizationState::Approved,
+                DeviceAuthorizationStatus::Rejected => DeviceAuthorizationState::Rejected,
+            },
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+enum DeviceAuthorizationInfoResponse {
+    #[oai(status = 200)]
+    Ok(Json<DeviceAuthorizationInfo>),
+    #[oai(status = 403)]
+    Forbidden,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/auth/device",
+        method = "post",
+        operation_id = "start_device_authorization"
+    )]
+    async fn api_start_device_authorization(
+        &self,
+        req: &Request,
+        services: Data<&Services>,
+        body: Json<StartDeviceAuthorizationRequest>,
+    ) -> Result<StartDeviceAuthorizationResponse, WarpgateError> {
+        if body.target_name.is_empty() {
+            return Ok(StartDeviceAuthorizationResponse::BadRequest(Json(
+                "target_name".into(),
+            )));
+        }
+
+        let client_ip = get_client_ip(req).await.ok();
+        let Some((device_code, user_code)) = services
+            .auth_state_store
+            .lock()
+            .await
+            .create_device_authorization(&body.target_name, client_ip.as_deref())
+        else {
+            warn!(?client_ip, "Too many pending device authorizations");
+            return Ok(StartDeviceAuthorizationResponse::TooManyRequests);
+        };
+
+        let mut verification_uri = services
+            .config
+            .load()
+            .construct_external_url(Some(req), None)?;
+        verification_uri.set_path("@warpgate");
+        verification_uri.set_fragment(Some("/device"));
+        let mut verification_uri_complete = verification_uri.clone();
+        verification_uri_complete.set_fragment(Some(&format!("/device?code={user_code}")));
+
+        Ok(StartDeviceAuthorizationResponse::Ok(Json(
+            DeviceAuthorizationParams {
+                device_code: device_code.expose_secret().clone(),
+                user_code,
+                verification_uri: verification_uri.to_string(),
+                verification_uri_complete: verification_uri_complete.to_string(),
+                expires_in: TIMEOUT.as_secs(),
+                interval: DEVICE_CODE_POLL_INTERVAL.as_secs(),
+            },
+        )))
+    }
+
+    #[oai(
+        path = "/auth/device/token",
+        method = "post",
+        operation_id = "poll_device_authorization"
+    )]
+    async fn api_poll_device_authorization(
+        &self,
+        services: Data<&Services>,
+        body: Json<PollDeviceAuthorizationRequest>,
+    ) -> Result<PollDeviceAuthorizationResponse, WarpgateError> {
+        let result = services
+            .auth_state_store
+            .lock()
+            .await
+            .poll_device_authorization(&body.device_code);
+
+        let error = match result {
+            DevicePollResult::Approved { username, target } => {
+                let secret = generate_ticket_secret();
+                let expiry = Utc::now() + DEVICE_TICKET_VALIDITY;
+                let db = services.db.lock().await;
+                Ticket::ActiveModel {
+                    id: Set(Uuid::new_v4()),
+                    secret: Set(secret.expose_secret().to_string()),
+                    username: Set(username.clone()),
+                    target: Set(target.clone()),
+                    created: Set(Utc::now()),
+                    expiry: Set(Some(expiry)),
+                    uses_left: Set(Some(1)),
+                }
+                .insert(&*db)
+                .await?;
+                info!(%username, %target, "Issued a ticket through device authorization");
+
+                return Ok(PollDeviceAuthorizationResponse::Ok(Json(DeviceTicket {
+                    ticket_secret: secret.expose_secret().to_string(),
+                    username,
+                    target,
+                    expiry,
+                })));
+            }
+            DevicePollResult::Pending => DeviceTokenErrorCode::AuthorizationPending,
+            DevicePollResult::SlowDown => DeviceTokenErrorCode::SlowDown,
+            DevicePollResult::Denied => DeviceTokenErrorCode::AccessDenied,
+            DevicePollResult::Expired => DeviceTokenErrorCode::ExpiredToken,
+        };
+        Ok(PollDeviceAuthorizationResponse::Error(Json(
+            DeviceTokenError { error },
+        )))
+    }
+
+    #[oai(
+        path = "/auth/device/:user_code",
+        method = "get",
+        operation_id = "get_device_authorization",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_device_authorization(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+        user_code: Path<String>,
+    ) -> Result<DeviceAuthorizationInfoResponse, WarpgateError> {
+        let Some(username) = session_username(&auth) else {
+            return Ok(DeviceAuthorizationInfoResponse::Forbidden);
+        };
+        let mut store = services.auth_state_store.lock().await;
+        let Some(authorization) = store.claim_device_authorization(&user_code, username) else {
+            return Ok(DeviceAuthorizationInfoResponse::NotFound);
+        };
+        Ok(DeviceAuthorizationInfoResponse::Ok(Json(
+            authorization.into(),
+        )))
+    }
+
+    #[oai(
+        path = "/auth/device/:user_code/approve",
+        method = "post",
+        operation_id = "approve_device_authorization",
+        transform = "endpoint_auth"
+    )]
+    async fn api_approve_device_authorization(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+        user_code: Path<String>,
+    ) -> Result<DeviceAuthorizationInfoResponse, WarpgateError> {
+        let Some(username) = session_username(&auth) else {
+            return Ok(DeviceAuthorizationInfoResponse::Forbidden);
+        };
+
+        let Some(target) = services
+            .auth_state_store
+            .lock()
+            .await
+            .claim_device_authorization(&user_code, username)
+            .map(|x| x.target.clone())
+        else {
+            return Ok(DeviceAuthorizationInfoResponse::NotFound);
+        };
+
+        if !services
+            .config_provider
+            .lock()
+            .await
+            .authorize_target(username, &target)
+            .await?
+        {
+            return Ok(DeviceAuthorizationInfoResponse::Forbidden);
+        }
+
+        resolve(
+            &services,
+            &user_code,
+            username,
+            DeviceAuthorizationStatus::Approved {
+                username: username.clone(),
+            },
+        )
+        .await
+    }
+
+    #[oai(
+        path = "/auth/device/:user_code/reject",
+        method = "post",
+        operation_id = "reject_device_authorization",
+        transform = "endpoint_auth"
+    )]
+    async fn api_reject_device_authorization(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+        user_code: Path<String>,
+    ) -> Result<DeviceAuthorizationInfoResponse, WarpgateError> {
+        let Some(username) = session_username(&auth) else {
+            return Ok(DeviceAuthorizationInfoResponse::Forbidden);
+        };
+        if services
+            .auth_state_store
+            .lock()
+            .await
+            .claim_device_authorization(&user_code, username)
+            .is_none()
+        {
+            return Ok(DeviceAuthorizationInfoResponse::NotFound);
+        }
+        resolve(
+            &services,
+            &user_code,
+            username,
+            DeviceAuthorizationStatus::Rejected,
+        )
+        .await
+    }
+}
+
+/// Device authorizations can only be confirmed by an interactively
+/// logged in user, not with a ticket or an API token.
+fn session_username(auth: &RequestAuthorization) -> Option<&String> {
+    match auth {
+        RequestAuthorization::Session(SessionAuthorization::User(username)) => Some(username),
+        _ => None,
+    }
+}
+
+async fn resolve(
+    services: &Services,
+    user_code: &str,
+    username: &str,
+    status: DeviceAuthorizationStatus,
+) -> Result<DeviceAuthorizationInfoResponse, WarpgateError> {
+    let mut store = services.auth_state_store.lock().await;
+    if !store.resolve_device_authorization(user_code, username, status) {
+        return Ok(DeviceAuthorizationInfoResponse::NotFound);
+    }
+    let Some(authorization) = store.find_device_authorization(user_code) else {
+        return Ok(DeviceAuthorizationInfoResponse::NotFound);
+    };
+    Ok(DeviceAuthorizationInfoResponse::Ok(Json(
+        authorization.into(),
+    )))
+}
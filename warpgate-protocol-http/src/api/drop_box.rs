@@ -0,0 +1,247 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::{Binary, Json};
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{SessionId, WarpgateError};
+use warpgate_core::{DropBoxItem, DropBoxItemSource, Services};
+use warpgate_db_entities::DropBoxEvent::DropBoxAction;
+
+use super::common::{find_own_ssh_session, list_own_ssh_sessions};
+use crate::common::{endpoint_auth, RequestAuthorization};
+
+pub struct Api;
+
+/// An active SSH session of the current user and its drop-box
+#[derive(Object)]
+struct DropBoxSession {
+    id: SessionId,
+    target: String,
+    started: DateTime<Utc>,
+    items: Vec<DropBoxItem>,
+}
+
+#[derive(ApiResponse)]
+enum GetDropBoxSessionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<DropBoxSession>>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum PutDropBoxItemResponse {
+    #[oai(status = 201)]
+    Created(Json<DropBoxItem>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum GetDropBoxItemResponse {
+    #[oai(status = 200)]
+    Ok(
+        Binary<Vec<u8>>,
+        #[oai(header = "Content-Disposition")] String,
+    ),
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum DeleteDropBoxItemResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/drop-box",
+        method = "get",
+        operation_id = "get_drop_box_sessions",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_drop_box_sessions(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+    ) -> Result<GetDropBoxSessionsResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(GetDropBoxSessionsResponse::Unauthorized);
+        };
+        let config = services.config.load().store.drop_box.clone();
+
+        let mut result = vec![];
+        for (id, session) in list_own_ssh_sessions(&services, username).await {
+            let mut state = session.lock().await;
+            result.push(DropBoxSession {
+                id,
+                target: state
+                    .target
+                    .as_ref()
+                    .map(|x| x.name.clone())
+                    .unwrap_or_default(),
+                started: state.started,
+                items: state.drop_box.list(&config),
+            });
+        }
+
+        Ok(GetDropBoxSessionsResponse::Ok(Json(result)))
+    }
+
+    #[oai(
+        path = "/drop-box/:session_id/items",
+        method = "post",
+        operation_id = "put_drop_box_item",
+        transform = "endpoint_auth"
+    )]
+    async fn api_put_drop_box_item(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        session_id: Path<SessionId>,
+        name: Query<String>,
+        body: Binary<Vec<u8>>,
+    ) -> Result<PutDropBoxItemResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(PutDropBoxItemResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &session_id, username).await else {
+            return Ok(PutDropBoxItemResponse::NotFound);
+        };
+        let config = services.config.load().store.drop_box.clone();
+
+        let result = session.lock().await.drop_box.put(
+            &name,
+            DropBoxItemSource::Portal,
+            Bytes::from(body.0),
+            &config,
+        );
+        match result {
+            Ok(item) => {
+                info!(
+                    session=%*session_id,
+                    session_username=%username,
+                    item=%item.name,
+                    size=item.size,
+                    "Drop-box item uploaded from the portal"
+                );
+                services
+                    .drop_box_audit
+                    .record(
+                        *session_id,
+                        Some(username.to_owned()),
+                        DropBoxAction::Staged,
+                        true,
+                        &item,
+                    )
+                    .await;
+                Ok(PutDropBoxItemResponse::Created(Json(item)))
+            }
+            Err(error) => Ok(PutDropBoxItemResponse::BadRequest(Json(error.to_string()))),
+        }
+    }
+
+    #[oai(
+        path = "/drop-box/:session_id/items/:id",
+        method = "get",
+        operation_id = "get_drop_box_item",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_drop_box_item(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        session_id: Path<SessionId>,
+        id: Path<Uuid>,
+    ) -> Result<GetDropBoxItemResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(GetDropBoxItemResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &session_id, username).await else {
+            return Ok(GetDropBoxItemResponse::NotFound);
+        };
+        let config = services.config.load().store.drop_box.clone();
+
+        let Some((item, data)) = session.lock().await.drop_box.get_by_id(&id, &config) else {
+            return Ok(GetDropBoxItemResponse::NotFound);
+        };
+        info!(
+            session=%*session_id,
+            session_username=%username,
+            item=%item.name,
+            size=item.size,
+            "Drop-box item downloaded from the portal"
+        );
+        services
+            .drop_box_audit
+            .record(
+                *session_id,
+                Some(username.to_owned()),
+                DropBoxAction::Retrieved,
+                true,
+                &item,
+            )
+            .await;
+        Ok(GetDropBoxItemResponse::Ok(
+            Binary(data.to_vec()),
+            format!("attachment; filename=\"{}\"", item.name.replace('"', "")),
+        ))
+    }
+
+    #[oai(
+        path = "/drop-box/:session_id/items/:id",
+        method = "delete",
+        operation_id = "delete_drop_box_item",
+        transform = "endpoint_auth"
+    )]
+    async fn api_delete_drop_box_item(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        session_id: Path<SessionId>,
+        id: Path<Uuid>,
+    ) -> Result<DeleteDropBoxItemResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(DeleteDropBoxItemResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &session_id, username).await else {
+            return Ok(DeleteDropBoxItemResponse::NotFound);
+        };
+
+        let Some(item) = session.lock().await.drop_box.remove(&id) else {
+            return Ok(DeleteDropBoxItemResponse::NotFound);
+        };
+        info!(
+            session=%*session_id,
+            session_username=%username,
+            item=%item.name,
+            "Drop-box item deleted from the portal"
+        );
+        services
+            .drop_box_audit
+            .record(
+                *session_id,
+                Some(username.to_owned()),
+                DropBoxAction::Deleted,
+                true,
+                &item,
+            )
+            .await;
+        Ok(DeleteDropBoxItemResponse::Deleted)
+    }
+}
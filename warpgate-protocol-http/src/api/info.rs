@@ -18,6 +18,7 @@ pub struct PortsInfo {
     http: Option<u16>,
     mysql: Option<u16>,
     postgres: Option<u16>,
+    redis: Option<u16>,
 }
 
 #[derive(Serialize, Object)]
@@ -30,6 +31,8 @@ pub struct Info {
     authorized_via_ticket: bool,
     authorized_via_sso_with_single_logout: bool,
     own_credential_management_allowed: bool,
+    self_service_tickets_allowed: bool,
+    drop_box_enabled: bool,
 }
 
 #[derive(ApiResponse)]
@@ -47,7 +50,7 @@ impl Api {
         session: &Session,
         services: Data<&Services>,
     ) -> Result<InstanceInfoResponse, WarpgateError> {
-        let config = services.config.lock().await;
+        let config = services.config.load();
         let external_host = config
             .construct_external_url(Some(req), None)
             .ok()
@@ -68,7 +71,7 @@ impl Api {
             ),
             authorized_via_sso_with_single_logout: session
                 .get_sso_login_state()
-                .map_or(false, |state| state.supports_single_logout),
+                .is_some_and(|state| state.supports_single_logout),
             ports: if session.is_authenticated() {
                 PortsInfo {
                     ssh: if config.store.ssh.enable {
@@ -91,6 +94,11 @@ impl Api {
                     } else {
                         None
                     },
+                    redis: if config.store.redis.enable {
+                        Some(config.store.redis.external_port())
+                    } else {
+                        None
+                    },
                 }
             } else {
                 PortsInfo {
@@ -98,9 +106,12 @@ impl Api {
                     http: None,
                     mysql: None,
                     postgres: None,
+                    redis: None,
                 }
             },
             own_credential_management_allowed: parameters.allow_own_credential_management,
+            self_service_tickets_allowed: parameters.allow_self_service_tickets,
+            drop_box_enabled: config.store.drop_box.enable,
         })))
     }
 }
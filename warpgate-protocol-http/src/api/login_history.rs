@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use poem::session::Session;
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryOrder, QuerySelect};
+use tracing::*;
+use warpgate_common::{SessionId, Target, WarpgateError};
+use warpgate_core::{HttpSessionInfo, HttpSessionStorage, Services};
+use warpgate_db_entities::Session::{self as SessionEntity, SessionTerminationReason};
+
+use crate::common::{endpoint_auth, RequestAuthorization, SessionAuthorization};
+use crate::session::current_web_session_id;
+
+/// How many past logins are shown to the user
+const LOGIN_HISTORY_LIMIT: u64 = 50;
+
+pub struct Api;
+
+#[derive(Object)]
+struct LoginHistoryEntry {
+    id: SessionId,
+    protocol: String,
+    remote_address: String,
+    /// Only known for web logins
+    user_agent: Option<String>,
+    target: Option<String>,
+    started: DateTime<Utc>,
+    ended: Option<DateTime<Utc>>,
+}
+
+impl From<SessionEntity::Model> for LoginHistoryEntry {
+    fn from(model: SessionEntity::Model) -> Self {
+        Self {
+            id: model.id,
+            protocol: model.protocol,
+            remote_address: model.remote_address,
+            user_agent: model.user_agent,
+            target: model
+                .target_snapshot
+                .and_then(|s| serde_json::from_str::<Target>(&s).ok())
+                .map(|t| t.name),
+            started: model.started,
+            ended: model.ended,
+        }
+    }
+}
+
+#[derive(Object)]
+struct OwnWebSession {
+    id: String,
+    created: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    expires: Option<DateTime<Utc>>,
+    remote_address: Option<String>,
+    user_agent: Option<String>,
+    /// The session this request was made in
+    current: bool,
+}
+
+#[derive(ApiResponse)]
+enum GetLoginHistoryResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<LoginHistoryEntry>>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum GetOwnWebSessionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<OwnWebSession>>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum RevokeOwnWebSessionResponse {
+    #[oai(status = 204)]
+    Deleted,
+    /// Use logout to end the current session
+    #[oai(status = 400)]
+    BadRequest,
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum RevokeOtherWebSessionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<u32>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+/// Web sessions can only be managed from an interactive login
+fn web_session_owner(auth: &RequestAuthorization) -> Option<&String> {
+    match auth {
+        RequestAuthorization::Session(SessionAuthorization::User(username)) => Some(username),
+        _ => None,
+    }
+}
+
+async fn list_own_web_sessions(
+    storage: &HttpSessionStorage,
+    username: &str,
+) -> Result<Vec<HttpSessionInfo>, WarpgateError> {
+    let mut sessions = storage.list().await?;
+    sessions.retain(|s| s.username.as_deref() == Some(username));
+    Ok(sessions)
+}
+
+/// Logs the web session out and closes its Warpgate session
+async fn revoke_web_session(
+    services: &Services,
+    storage: &HttpSessionStorage,
+    id: &str,
+) -> Result<(), WarpgateError> {
+    let Some(session) = storage.revoke(id).await? else {
+        return Ok(());
+    };
+    if let Some(session_id) = session.session_id {
+        let session_state = services
+            .state
+            .lock()
+            .await
+            .sessions
+            .get(&session_id)
+            .cloned();
+        if let Some(session_state) = session_state {
+            session_state
+                .lock()
+                .await
+                .terminate(SessionTerminationReason::UserDisconnect);
+        }
+    }
+    Ok(())
+}
+
+#[OpenApi]
+impl Api {
+    /// Recent logins of the current user across all protocols, newest first
+    #[oai(
+        path = "/profile/logins",
+        method = "get",
+        operation_id = "get_my_logins",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_my_logins(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+    ) -> Result<GetLoginHistoryResponse, WarpgateError> {
+        let Some(username) = web_session_owner(&auth) else {
+            return Ok(GetLoginHistoryResponse::Unauthorized);
+        };
+
+        let db = services.db.lock().await;
+        let sessions = SessionEntity::Entity::find()
+            .filter(SessionEntity::Column::Username.eq(username))
+            .order_by_desc(SessionEntity::Column::Started)
+            .limit(LOGIN_HISTORY_LIMIT)
+            .all(&*db)
+            .await?;
+
+        Ok(GetLoginHistoryResponse::Ok(Json(
+            sessions.into_iter().map(Into::into).collect(),
+        )))
+    }
+
+    #[oai(
+        path = "/profile/web-sessions",
+        method = "get",
+        operation_id = "get_my_web_sessions",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_my_web_sessions(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        storage: Data<&HttpSessionStorage>,
+        session: &Session,
+    ) -> Result<GetOwnWebSessionsResponse, WarpgateError> {
+        let Some(username) = web_session_owner(&auth) else {
+            return Ok(GetOwnWebSessionsResponse::Unauthorized);
+        };
+
+        let current_id = current_web_session_id(session);
+        let web_sessions = list_own_web_sessions(&storage, username).await?;
+
+        let session_ids = web_sessions
+            .iter()
+            .filter_map(|s| s.session_id)
+            .collect::<Vec<_>>();
+        let db = services.db.lock().await;
+        let sessions = SessionEntity::Entity::find()
+            .filter(SessionEntity::Column::Id.is_in(session_ids))
+            .all(&*db)
+            .await?
+            .into_iter()
+            .map(|s| (s.id, s))
+            .collect::<HashMap<_, _>>();
+
+        let mut result = web_sessions
+            .into_iter()
+            .map(|web_session| {
+                let session = web_session.session_id.and_then(|id| sessions.get(&id));
+                OwnWebSession {
+                    current: current_id.as_ref() == Some(&web_session.id),
+                    remote_address: session.map(|s| s.remote_address.clone()),
+                    user_agent: session.and_then(|s| s.user_agent.clone()),
+                    id: web_session.id,
+                    created: web_session.created,
+                    last_seen: web_session.last_seen,
+                    expires: web_session.expires,
+                }
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+
+        Ok(GetOwnWebSessionsResponse::Ok(Json(result)))
+    }
+
+    #[oai(
+        path = "/profile/web-sessions/:id",
+        method = "delete",
+        operation_id = "revoke_my_web_session",
+        transform = "endpoint_auth"
+    )]
+    async fn api_revoke_my_web_session(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        storage: Data<&HttpSessionStorage>,
+        session: &Session,
+        id: Path<String>,
+    ) -> Result<RevokeOwnWebSessionResponse, WarpgateError> {
+        let Some(username) = web_session_owner(&auth) else {
+            return Ok(RevokeOwnWebSessionResponse::Unauthorized);
+        };
+
+        if current_web_session_id(session).as_ref() == Some(&id.0) {
+            return Ok(RevokeOwnWebSessionResponse::BadRequest);
+        }
+
+        let web_sessions = list_own_web_sessions(&storage, username).await?;
+        if !web_sessions.iter().any(|s| s.id == id.0) {
+            return Ok(RevokeOwnWebSessionResponse::NotFound);
+        }
+
+        revoke_web_session(&services, &storage, &id.0).await?;
+        info!(%username, "User revoked one of their web sessions");
+
+        Ok(RevokeOwnWebSessionResponse::Deleted)
+    }
+
+    /// Logs out everywhere except the current session.
+    /// Returns the number of revoked sessions.
+    #[oai(
+        path = "/profile/web-sessions/revoke-others",
+        method = "post",
+        operation_id = "revoke_my_other_web_sessions",
+        transform = "endpoint_auth"
+    )]
+    async fn api_revoke_my_other_web_sessions(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        storage: Data<&HttpSessionStorage>,
+        session: &Session,
+    ) -> Result<RevokeOtherWebSessionsResponse, WarpgateError> {
+        let Some(username) = web_session_owner(&auth) else {
+            return Ok(RevokeOtherWebSessionsResponse::Unauthorized);
+        };
+
+        let current_id = current_web_session_id(session);
+        let mut revoked = 0;
+        for web_session in list_own_web_sessions(&storage, username).await? {
+            if current_id.as_ref() == Some(&web_session.id) {
+                continue;
+            }
+            revoke_web_session(&services, &storage, &web_session.id).await?;
+            revoked += 1;
+        }
+        info!(%username, revoked, "User revoked their other web sessions");
+
+        Ok(RevokeOtherWebSessionsResponse::Ok(Json(revoked)))
+    }
+}
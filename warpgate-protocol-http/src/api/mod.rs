@@ -5,10 +5,17 @@ mod api_tokens;
 pub mod auth;
 mod common;
 mod credentials;
+mod device_auth;
+mod drop_box;
 pub mod info;
+mod login_history;
+mod portal;
+mod refresh_tokens;
+pub mod session_sharing;
 pub mod sso_provider_detail;
 pub mod sso_provider_list;
 pub mod targets_list;
+mod tickets;
 
 #[derive(SecurityScheme)]
 #[oai(ty = "api_key", key_name = "X-Warpgate-Token", key_in = "header")]
@@ -35,5 +42,12 @@ pub fn get() -> impl OpenApi {
         sso_provider_detail::Api,
         credentials::Api,
         api_tokens::Api,
+        refresh_tokens::Api,
+        login_history::Api,
+        device_auth::Api,
+        tickets::Api,
+        drop_box::Api,
+        session_sharing::Api,
+        portal::Api,
     )
 }
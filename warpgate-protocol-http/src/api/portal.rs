@@ -0,0 +1,48 @@
+use poem::web::Data;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use warpgate_common::WarpgateError;
+use warpgate_core::{user_portal_layout, PortalLayout, Services};
+use warpgate_db_entities::RolePortalSettings::PortalSection;
+
+use crate::common::{endpoint_auth, RequestAuthorization, SessionAuthorization};
+
+pub struct Api;
+
+#[derive(Object)]
+struct PortalInfo {
+    /// Landing page sections, in display order
+    sections: Vec<PortalSection>,
+}
+
+#[derive(ApiResponse)]
+enum GetPortalResponse {
+    #[oai(status = 200)]
+    Ok(Json<PortalInfo>),
+}
+
+#[OpenApi]
+impl Api {
+    /// Tailored by the user's roles
+    #[oai(
+        path = "/portal",
+        method = "get",
+        operation_id = "get_portal",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_portal(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+    ) -> Result<GetPortalResponse, WarpgateError> {
+        let layout = match *auth {
+            RequestAuthorization::Session(SessionAuthorization::User(ref username)) => {
+                user_portal_layout(&*services.db.lock().await, username).await?
+            }
+            _ => PortalLayout::default(),
+        };
+        Ok(GetPortalResponse::Ok(Json(PortalInfo {
+            sections: layout.sections,
+        })))
+    }
+}
@@ -0,0 +1,255 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ColumnTrait, ModelTrait, QueryFilter};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{Secret, WarpgateError};
+use warpgate_core::{
+    issue_refresh_token, revoke_refresh_token, revoke_refresh_token_family, rotate_refresh_token,
+    IssuedRefreshToken, RefreshOutcome, Services,
+};
+use warpgate_db_entities::ApiToken::ApiTokenScope;
+use warpgate_db_entities::RefreshToken;
+
+use super::common::get_user;
+use crate::common::{endpoint_auth, RequestAuthorization, SessionAuthorization};
+
+pub struct Api;
+
+#[derive(Object)]
+struct NewRefreshToken {
+    /// Shown in the list of logins, e.g. the name of the CLI tool
+    label: String,
+    /// Limits the access tokens to specific targets and protocols
+    scope: Option<ApiTokenScope>,
+}
+
+#[derive(Object)]
+struct ExistingRefreshToken {
+    /// Stays the same across refreshes
+    id: Uuid,
+    label: String,
+    created: DateTime<Utc>,
+    last_refreshed: DateTime<Utc>,
+    expiry: DateTime<Utc>,
+    scope: Option<ApiTokenScope>,
+}
+
+impl From<RefreshToken::Model> for ExistingRefreshToken {
+    fn from(token: RefreshToken::Model) -> Self {
+        Self {
+            id: token.family_id,
+            label: token.label,
+            created: token.created,
+            last_refreshed: token.refreshed,
+            expiry: token.expiry,
+            scope: token.scope,
+        }
+    }
+}
+
+#[derive(Object)]
+struct RefreshTokenPair {
+    token: ExistingRefreshToken,
+    /// Pass in the `X-Warpgate-Token` header
+    access_token: String,
+    access_token_expiry: DateTime<Utc>,
+    /// Can only be used once
+    refresh_token: String,
+}
+
+impl From<IssuedRefreshToken> for RefreshTokenPair {
+    fn from(issued: IssuedRefreshToken) -> Self {
+        Self {
+            access_token_expiry: issued.model.access_expiry,
+            token: issued.model.into(),
+            access_token: issued.access_token.expose_secret().clone(),
+            refresh_token: issued.refresh_token.expose_secret().clone(),
+        }
+    }
+}
+
+#[derive(Object)]
+struct RefreshTokenRequest {
+    refresh_token: Secret<String>,
+}
+
+#[derive(ApiResponse)]
+enum CreateRefreshTokenResponse {
+    #[oai(status = 201)]
+    Created(Json<RefreshTokenPair>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum RefreshResponse {
+    #[oai(status = 200)]
+    Ok(Json<RefreshTokenPair>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum RevokeRefreshTokenResponse {
+    #[oai(status = 204)]
+    Revoked,
+}
+
+#[derive(ApiResponse)]
+enum GetRefreshTokensResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<ExistingRefreshToken>>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(ApiResponse)]
+enum DeleteRefreshTokenResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[OpenApi]
+impl Api {
+    /// Exchanges an interactive login for a refresh token,
+    /// so that API clients don't need to keep the user's password
+    #[oai(
+        path = "/auth/refresh-tokens",
+        method = "post",
+        operation_id = "create_refresh_token",
+        transform = "endpoint_auth"
+    )]
+    async fn api_create_refresh_token(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        body: Json<NewRefreshToken>,
+    ) -> Result<CreateRefreshTokenResponse, WarpgateError> {
+        // Tokens can't be used to mint longer-lived tokens
+        let RequestAuthorization::Session(SessionAuthorization::User(_)) = *auth else {
+            return Ok(CreateRefreshTokenResponse::Unauthorized);
+        };
+
+        let config = services.config.load().store.http.clone();
+        let db = services.db.lock().await;
+        let Some(user_model) = get_user(&auth, &db).await? else {
+            return Ok(CreateRefreshTokenResponse::Unauthorized);
+        };
+
+        let issued = issue_refresh_token(
+            &db,
+            user_model.id,
+            body.label.clone(),
+            body.scope.clone(),
+            &config,
+        )
+        .await?;
+        info!(username=%user_model.username, label=%body.label, "Issued a refresh token");
+        Ok(CreateRefreshTokenResponse::Created(Json(issued.into())))
+    }
+
+    /// Returns a new token pair. The refresh token that was passed in
+    /// and its access token stop working.
+    #[oai(
+        path = "/auth/refresh-tokens/refresh",
+        method = "post",
+        operation_id = "refresh_access_token"
+    )]
+    async fn api_refresh_access_token(
+        &self,
+        services: Data<&Services>,
+        body: Json<RefreshTokenRequest>,
+    ) -> Result<RefreshResponse, WarpgateError> {
+        let config = services.config.load().store.http.clone();
+        let db = services.db.lock().await;
+        match rotate_refresh_token(&db, body.refresh_token.expose_secret(), &config).await? {
+            RefreshOutcome::Refreshed(issued) => Ok(RefreshResponse::Ok(Json((*issued).into()))),
+            RefreshOutcome::Invalid | RefreshOutcome::Reused => Ok(RefreshResponse::Unauthorized),
+        }
+    }
+
+    /// Logs out a client holding the refresh token.
+    /// Succeeds even if the token is unknown.
+    #[oai(
+        path = "/auth/refresh-tokens/revoke",
+        method = "post",
+        operation_id = "revoke_refresh_token"
+    )]
+    async fn api_revoke_refresh_token(
+        &self,
+        services: Data<&Services>,
+        body: Json<RefreshTokenRequest>,
+    ) -> Result<RevokeRefreshTokenResponse, WarpgateError> {
+        let db = services.db.lock().await;
+        revoke_refresh_token(&db, body.refresh_token.expose_secret()).await?;
+        Ok(RevokeRefreshTokenResponse::Revoked)
+    }
+
+    #[oai(
+        path = "/profile/refresh-tokens",
+        method = "get",
+        operation_id = "get_my_refresh_tokens",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_refresh_tokens(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+    ) -> Result<GetRefreshTokensResponse, WarpgateError> {
+        let db = services.db.lock().await;
+
+        let Some(user_model) = get_user(*auth, &db).await? else {
+            return Ok(GetRefreshTokensResponse::Unauthorized);
+        };
+
+        let tokens = user_model
+            .find_related(RefreshToken::Entity)
+            .filter(RefreshToken::Column::Used.eq(false))
+            .filter(RefreshToken::Column::Expiry.gt(Utc::now()))
+            .all(&*db)
+            .await?;
+
+        Ok(GetRefreshTokensResponse::Ok(Json(
+            tokens.into_iter().map(Into::into).collect(),
+        )))
+    }
+
+    #[oai(
+        path = "/profile/refresh-tokens/:id",
+        method = "delete",
+        operation_id = "delete_my_refresh_token",
+        transform = "endpoint_auth"
+    )]
+    async fn api_delete_refresh_token(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+    ) -> Result<DeleteRefreshTokenResponse, WarpgateError> {
+        let db = services.db.lock().await;
+
+        let Some(user_model) = get_user(*auth, &db).await? else {
+            return Ok(DeleteRefreshTokenResponse::Unauthorized);
+        };
+
+        let Some(token) = user_model
+            .find_related(RefreshToken::Entity)
+            .filter(RefreshToken::Column::FamilyId.eq(id.0))
+            .one(&*db)
+            .await?
+        else {
+            return Ok(DeleteRefreshTokenResponse::NotFound);
+        };
+
+        revoke_refresh_token_family(&db, token.family_id).await?;
+        Ok(DeleteRefreshTokenResponse::Deleted)
+    }
+}
@@ -0,0 +1,652 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use poem::error::{InternalServerError, NotFoundError};
+use poem::web::websocket::{Message, WebSocket};
+use poem::web::Data;
+use poem::{handler, IntoResponse};
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::Mutex;
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{SessionId, WarpgateError};
+use warpgate_core::recordings::{read_asciicast, AsciiCast, TerminalRecordingItem};
+use warpgate_core::{
+    ConfigProvider, Services, SessionController, SessionShare, SessionState, SessionViewer,
+};
+use warpgate_db_entities::Recording::{self, RecordingKind};
+
+use super::common::{find_own_ssh_session, list_own_ssh_sessions};
+use crate::common::{endpoint_auth, RequestAuthorization};
+
+/// Longest validity of a watch link
+const MAX_SHARE_DURATION: chrono::Duration = chrono::Duration::hours(24);
+
+/// Longest time a viewer can be given input control for at once
+const MAX_CONTROL_DURATION: chrono::Duration = chrono::Duration::hours(1);
+
+/// How often open viewer streams re-check their link and input control
+const SHARE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Api;
+
+/// An active SSH session of the current user and its watch links
+#[derive(Object)]
+struct OwnSession {
+    id: SessionId,
+    target: String,
+    started: DateTime<Utc>,
+    shares: Vec<SessionShare>,
+    viewers: Vec<SessionViewer>,
+    controller: Option<SessionController>,
+}
+
+#[derive(ApiResponse)]
+enum GetMySessionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<OwnSession>>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(Object)]
+struct SessionShareRequest {
+    expiry: DateTime<Utc>,
+}
+
+#[derive(Object)]
+struct SessionShareAndSecret {
+    share: SessionShare,
+    secret: String,
+}
+
+#[derive(ApiResponse)]
+enum CreateSessionShareResponse {
+    #[oai(status = 201)]
+    Created(Json<SessionShareAndSecret>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum DeleteSessionShareResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(Object)]
+struct SessionControlRequest {
+    viewer_id: Uuid,
+    expiry: DateTime<Utc>,
+}
+
+#[derive(ApiResponse)]
+enum GrantSessionControlResponse {
+    #[oai(status = 201)]
+    Created(Json<SessionController>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 403)]
+    Forbidden,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum RevokeSessionControlResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(Object)]
+struct SharedSession {
+    target: String,
+    owner: String,
+    started: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    recordings: Vec<Recording::Model>,
+}
+
+#[derive(ApiResponse)]
+enum GetSharedSessionResponse {
+    #[oai(status = 200)]
+    Ok(Json<SharedSession>),
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+/// Looks up a live session that `token` grants watch access to
+async fn find_shared_session(
+    services: &Services,
+    id: &SessionId,
+    token: &str,
+) -> Option<(Arc<Mutex<SessionState>>, SessionShare)> {
+    let session = services.state.lock().await.sessions.get(id).cloned()?;
+    let share = session.lock().await.shares.authorize(token)?;
+    Some((session, share))
+}
+
+fn validate_expiry(expiry: DateTime<Utc>, max_duration: chrono::Duration) -> Result<(), String> {
+    let now = Utc::now();
+    if expiry <= now {
+        return Err("Expiry must be in the future".into());
+    }
+    if expiry - now > max_duration {
+        return Err(format!(
+            "Expiry can be at most {}h from now",
+            max_duration.num_hours()
+        ));
+    }
+    Ok(())
+}
+
+/// Logs a change of input control and marks it in the terminal recordings
+fn record_control_change(
+    state: &mut SessionState,
+    session_id: &SessionId,
+    controller: &SessionController,
+    action: &str,
+) {
+    info!(
+        session=%session_id,
+        session_username=%state.username.as_deref().unwrap_or_default(),
+        viewer=%controller.username,
+        expires=%controller.expires,
+        "Input control {action}"
+    );
+    state
+        .handle
+        .add_recording_marker(format!("Input control {action}: {}", controller.username));
+}
+
+async fn find_shared_recording(
+    db: &Arc<Mutex<DatabaseConnection>>,
+    session_id: &SessionId,
+    recording_id: &Uuid,
+) -> poem::Result<Recording::Model> {
+    let db = db.lock().await;
+    Recording::Entity::find_by_id(*recording_id)
+        .filter(Recording::Column::SessionId.eq(*session_id))
+        .filter(Recording::Column::Kind.eq(RecordingKind::Terminal))
+        .one(&*db)
+        .await
+        .map_err(InternalServerError)?
+        .ok_or_else(|| NotFoundError.into())
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/profile/sessions",
+        method = "get",
+        operation_id = "get_my_sessions",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_my_sessions(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+    ) -> Result<GetMySessionsResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(GetMySessionsResponse::Unauthorized);
+        };
+
+        let mut result = vec![];
+        for (id, session) in list_own_ssh_sessions(&services, username).await {
+            let mut state = session.lock().await;
+            result.push(OwnSession {
+                id,
+                target: state
+                    .target
+                    .as_ref()
+                    .map(|x| x.name.clone())
+                    .unwrap_or_default(),
+                started: state.started,
+                shares: state.shares.list(),
+                viewers: state.shares.viewers(),
+                controller: state.shares.controller(),
+            });
+        }
+
+        Ok(GetMySessionsResponse::Ok(Json(result)))
+    }
+
+    #[oai(
+        path = "/profile/sessions/:id/shares",
+        method = "post",
+        operation_id = "create_session_share",
+        transform = "endpoint_auth"
+    )]
+    async fn api_create_session_share(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<SessionId>,
+        body: Json<SessionShareRequest>,
+    ) -> Result<CreateSessionShareResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(CreateSessionShareResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &id, username).await else {
+            return Ok(CreateSessionShareResponse::NotFound);
+        };
+
+        if !services.config.load().store.recordings.enable {
+            return Ok(CreateSessionShareResponse::BadRequest(Json(
+                "Session recording is disabled, so sessions can't be watched".into(),
+            )));
+        }
+        if let Err(error) = validate_expiry(body.expiry, MAX_SHARE_DURATION) {
+            return Ok(CreateSessionShareResponse::BadRequest(Json(error)));
+        }
+
+        let (share, secret) = session.lock().await.shares.create(body.expiry);
+        info!(
+            session=%*id,
+            session_username=%username,
+            share=%share.id,
+            expires=%share.expires,
+            "Watch link created"
+        );
+        Ok(CreateSessionShareResponse::Created(Json(
+            SessionShareAndSecret {
+                share,
+                secret: secret.expose_secret().clone(),
+            },
+        )))
+    }
+
+    #[oai(
+        path = "/profile/sessions/:id/shares/:share_id",
+        method = "delete",
+        operation_id = "delete_session_share",
+        transform = "endpoint_auth"
+    )]
+    async fn api_delete_session_share(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<SessionId>,
+        share_id: Path<Uuid>,
+    ) -> Result<DeleteSessionShareResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(DeleteSessionShareResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &id, username).await else {
+            return Ok(DeleteSessionShareResponse::NotFound);
+        };
+
+        let Some(share) = session.lock().await.shares.revoke(&share_id) else {
+            return Ok(DeleteSessionShareResponse::NotFound);
+        };
+        info!(
+            session=%*id,
+            session_username=%username,
+            share=%share.id,
+            "Watch link revoked"
+        );
+        Ok(DeleteSessionShareResponse::Deleted)
+    }
+
+    #[oai(
+        path = "/profile/sessions/:id/control",
+        method = "post",
+        operation_id = "grant_session_control",
+        transform = "endpoint_auth"
+    )]
+    async fn api_grant_session_control(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<SessionId>,
+        body: Json<SessionControlRequest>,
+    ) -> Result<GrantSessionControlResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(GrantSessionControlResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &id, username).await else {
+            return Ok(GrantSessionControlResponse::NotFound);
+        };
+        if let Err(error) = validate_expiry(body.expiry, MAX_CONTROL_DURATION) {
+            return Ok(GrantSessionControlResponse::BadRequest(Json(error)));
+        }
+
+        let (viewer, target) = {
+            let state = session.lock().await;
+            let viewer = state
+                .shares
+                .viewers()
+                .into_iter()
+                .find(|x| x.id == body.viewer_id);
+            (viewer, state.target.clone())
+        };
+        let (Some(viewer), Some(target)) = (viewer, target) else {
+            return Ok(GrantSessionControlResponse::NotFound);
+        };
+        // Watching only needs the link, but typing is the same as using the target
+        if !services
+            .config_provider
+            .lock()
+            .await
+            .authorize_target(&viewer.username, &target.name)
+            .await?
+        {
+            warn!(
+                session=%*id,
+                viewer=%viewer.username,
+                target=%target.name,
+                "Viewer is not allowed to access the target, not granting input control"
+            );
+            return Ok(GrantSessionControlResponse::Forbidden);
+        }
+
+        let mut state = session.lock().await;
+        // The viewer might have left in the meantime
+        if !state.shares.viewers().iter().any(|x| x.id == viewer.id) {
+            return Ok(GrantSessionControlResponse::NotFound);
+        }
+        if let Some(previous) = state.shares.revoke_control() {
+            record_control_change(&mut state, &id, &previous, "revoked");
+        }
+        let Some(controller) = state.shares.grant_control(&body.viewer_id, body.expiry) else {
+            return Ok(GrantSessionControlResponse::NotFound);
+        };
+        record_control_change(&mut state, &id, &controller, "granted");
+        Ok(GrantSessionControlResponse::Created(Json(controller)))
+    }
+
+    #[oai(
+        path = "/profile/sessions/:id/control",
+        method = "delete",
+        operation_id = "revoke_session_control",
+        transform = "endpoint_auth"
+    )]
+    async fn api_revoke_session_control(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<SessionId>,
+    ) -> Result<RevokeSessionControlResponse, WarpgateError> {
+        let Some(username) = auth.interactive_username() else {
+            return Ok(RevokeSessionControlResponse::Unauthorized);
+        };
+        let Some(session) = find_own_ssh_session(&services, &id, username).await else {
+            return Ok(RevokeSessionControlResponse::NotFound);
+        };
+
+        let mut state = session.lock().await;
+        let Some(controller) = state.shares.revoke_control() else {
+            return Ok(RevokeSessionControlResponse::NotFound);
+        };
+        record_control_change(&mut state, &id, &controller, "revoked");
+        Ok(RevokeSessionControlResponse::Deleted)
+    }
+
+    #[oai(
+        path = "/shared-sessions/:id",
+        method = "get",
+        operation_id = "get_shared_session",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_shared_session(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<SessionId>,
+        token: Query<String>,
+    ) -> Result<GetSharedSessionResponse, WarpgateError> {
+        if auth.interactive_username().is_none() {
+            return Ok(GetSharedSessionResponse::Unauthorized);
+        }
+        let Some((session, share)) = find_shared_session(&services, &id, &token).await else {
+            return Ok(GetSharedSessionResponse::NotFound);
+        };
+
+        let (target, owner, started) = {
+            let state = session.lock().await;
+            (
+                state
+                    .target
+                    .as_ref()
+                    .map(|x| x.name.clone())
+                    .unwrap_or_default(),
+                state.username.clone().unwrap_or_default(),
+                state.started,
+            )
+        };
+
+        let recordings = {
+            let db = services.db.lock().await;
+            Recording::Entity::find()
+                .filter(Recording::Column::SessionId.eq(*id))
+                .filter(Recording::Column::Kind.eq(RecordingKind::Terminal))
+                .order_by_asc(Recording::Column::Started)
+                .all(&*db)
+                .await?
+        };
+
+        Ok(GetSharedSessionResponse::Ok(Json(SharedSession {
+            target,
+            owner,
+            started,
+            expires: share.expires,
+            recordings,
+        })))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ShareTokenParams {
+    token: String,
+}
+
+/// Sent by a viewer that has input control
+#[derive(Deserialize)]
+struct ViewerMessage {
+    input: String,
+}
+
+/// Terminal output so far, without the owner's keystrokes
+#[handler]
+pub async fn api_get_shared_recording_cast(
+    auth: Data<&RequestAuthorization>,
+    services: Data<&Services>,
+    poem::web::Path((id, recording_id)): poem::web::Path<(SessionId, Uuid)>,
+    poem::web::Query(params): poem::web::Query<ShareTokenParams>,
+) -> poem::Result<String> {
+    if auth.interactive_username().is_none() {
+        return Err(NotFoundError.into());
+    }
+    if find_shared_session(&services, &id, &params.token)
+        .await
+        .is_none()
+    {
+        return Err(NotFoundError.into());
+    }
+    let recording = find_shared_recording(&services.db, &id, &recording_id).await?;
+
+    let path = {
+        services
+            .recordings
+            .lock()
+            .await
+            .path_for(&recording.session_id, &recording.name)
+    };
+
+    let response = read_asciicast(&path, recording.name)
+        .await
+        .map_err(InternalServerError)?
+        .iter()
+        .filter(|x| !x.is_input())
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(InternalServerError)?;
+
+    Ok(response.join("\n"))
+}
+
+/// Live terminal output for a viewer. Ends when the link expires or is revoked.
+/// While the owner has given the viewer input control, the viewer's keystrokes
+/// are typed into the terminal.
+#[handler]
+pub async fn api_get_shared_recording_stream(
+    ws: WebSocket,
+    auth: Data<&RequestAuthorization>,
+    services: Data<&Services>,
+    poem::web::Path((id, recording_id)): poem::web::Path<(SessionId, Uuid)>,
+    poem::web::Query(params): poem::web::Query<ShareTokenParams>,
+) -> poem::Result<impl IntoResponse> {
+    let Some(viewer) = auth.interactive_username().cloned() else {
+        return Err(NotFoundError.into());
+    };
+    let Some((session, share)) = find_shared_session(&services, &id, &params.token).await else {
+        return Err(NotFoundError.into());
+    };
+    find_shared_recording(&services.db, &id, &recording_id).await?;
+
+    let receiver = services
+        .recordings
+        .lock()
+        .await
+        .subscribe_live(&recording_id)
+        .await;
+    let (owner, viewer) = {
+        let mut state = session.lock().await;
+        let viewer = state.shares.join(share.id, viewer);
+        state
+            .handle
+            .add_recording_marker(format!("Viewer joined: {}", viewer.username));
+        (state.username.clone().unwrap_or_default(), viewer)
+    };
+    let token = params.token;
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let (mut sink, mut stream) = socket.split();
+
+        info!(
+            session=%id,
+            session_username=%owner,
+            share=%share.id,
+            viewer=%viewer.username,
+            "Viewer joined"
+        );
+
+        if let Err(error) = async {
+            sink.send(Message::Text(serde_json::to_string(&json!({
+                "start": true,
+                "live": receiver.is_some(),
+            }))?))
+            .await?;
+
+            let Some(mut receiver) = receiver else {
+                return Ok(());
+            };
+            let mut has_control = false;
+            let mut check_interval = tokio::time::interval(SHARE_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    data = receiver.recv() => {
+                        let Ok(data) = data else {
+                            sink.send(Message::Text(serde_json::to_string(&json!({
+                                "end": true,
+                            }))?))
+                            .await?;
+                            break;
+                        };
+                        let content: TerminalRecordingItem = serde_json::from_slice(&data)?;
+                        let cast: AsciiCast = content.into();
+                        if cast.is_input() {
+                            continue;
+                        }
+                        let msg = serde_json::to_string(&json!({ "data": cast }))?;
+                        sink.send(Message::Text(msg)).await?;
+                    }
+                    message = stream.next() => {
+                        let Some(Ok(message)) = message else {
+                            break;
+                        };
+                        let Message::Text(message) = message else {
+                            continue;
+                        };
+                        let message: ViewerMessage = serde_json::from_str(&message)?;
+                        let mut state = session.lock().await;
+                        if state.shares.has_control(&viewer.id) {
+                            state
+                                .handle
+                                .send_input(recording_id, Bytes::from(message.input));
+                        }
+                    }
+                    _ = check_interval.tick() => {
+                        let mut state = session.lock().await;
+                        if let Some(controller) = state.shares.take_expired_control() {
+                            record_control_change(&mut state, &id, &controller, "expired");
+                        }
+                        if state.shares.authorize(&token).is_none() {
+                            drop(state);
+                            sink.send(Message::Text(serde_json::to_string(&json!({
+                                "end": true,
+                            }))?))
+                            .await?;
+                            break;
+                        }
+                        let control = state.shares.has_control(&viewer.id);
+                        drop(state);
+                        if control != has_control {
+                            has_control = control;
+                            sink.send(Message::Text(serde_json::to_string(&json!({
+                                "control": control,
+                            }))?))
+                            .await?;
+                        }
+                    }
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await
+        {
+            error!(%error, "Watch stream error:");
+        }
+
+        {
+            let mut state = session.lock().await;
+            if let Some(controller) = state.shares.leave(&viewer.id) {
+                record_control_change(&mut state, &id, &controller, "returned");
+            }
+            state
+                .handle
+                .add_recording_marker(format!("Viewer left: {}", viewer.username));
+        }
+        info!(
+            session=%id,
+            session_username=%owner,
+            share=%share.id,
+            viewer=%viewer.username,
+            "Viewer left"
+        );
+    }))
+}
@@ -51,7 +51,7 @@ impl Api {
         name: Path<String>,
         next: Query<Option<String>>,
     ) -> Result<StartSsoResponse, WarpgateError> {
-        let config = services.config.lock().await;
+        let config = services.config.load();
 
         let name = name.0;
 
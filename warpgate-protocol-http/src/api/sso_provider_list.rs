@@ -95,7 +95,7 @@ impl Api {
         &self,
         services: Data<&Services>,
     ) -> Result<GetSsoProvidersResponse, WarpgateError> {
-        let mut providers = services.config.lock().await.store.sso_providers.clone();
+        let mut providers = services.config.load().store.sso_providers.clone();
         providers.sort_by(|a, b| a.label().cmp(b.label()));
         Ok(GetSsoProvidersResponse::Ok(Json(
             providers
@@ -239,7 +239,7 @@ impl Api {
             });
         }
 
-        let providers_config = services.config.lock().await.store.sso_providers.clone();
+        let providers_config = services.config.load().store.sso_providers.clone();
         let mut iter = providers_config.iter();
         let Some(provider_config) = iter.find(|x| x.name == provider) else {
             return Ok(Err(format!("No provider matching {provider}")));
@@ -294,7 +294,7 @@ impl Api {
             return Ok(StartSloResponse::NotInSsoSession);
         };
 
-        let config = services.config.lock().await;
+        let config = services.config.load();
 
         let return_url = config.construct_external_url(Some(req), None)?;
         debug!("Return URL: {}", &return_url);
@@ -1,11 +1,20 @@
-use futures::{stream, StreamExt};
+use std::collections::HashMap;
+
+use futures::{stream, SinkExt, StreamExt};
+use http::StatusCode;
+use poem::web::websocket::{Message, WebSocket};
 use poem::web::Data;
-use poem_openapi::param::Query;
+use poem::{handler, IntoResponse};
+use poem_openapi::param::{Path, Query};
 use poem_openapi::payload::Json;
 use poem_openapi::{ApiResponse, Object, OpenApi};
 use serde::Serialize;
-use warpgate_common::TargetOptions;
-use warpgate_core::{ConfigProvider, Services};
+use tokio::sync::broadcast::error::RecvError;
+use warpgate_common::{Target as TargetConfig, TargetOptions, WarpgateError};
+use warpgate_core::{
+    issue_direct_credential, known_host_fingerprints, user_portal_layout, ConfigProvider,
+    DirectCredentialError, IssuedDirectCredential, Services, TargetFingerprint, TargetHealth,
+};
 use warpgate_db_entities::Target;
 
 use crate::common::{endpoint_auth, RequestAuthorization, SessionAuthorization};
@@ -17,6 +26,17 @@ pub struct TargetSnapshot {
     pub name: String,
     pub kind: Target::TargetKind,
     pub external_host: Option<String>,
+    /// SSH forward presets available to the user
+    pub forward_presets: Vec<TargetForwardPreset>,
+    /// The user can get a short-lived account on the target itself
+    pub direct_credentials: bool,
+}
+
+/// Leaves out the destination host, which users don't need to know
+#[derive(Debug, Serialize, Clone, Object)]
+pub struct TargetForwardPreset {
+    pub name: String,
+    pub port: u16,
 }
 
 #[derive(ApiResponse)]
@@ -25,6 +45,148 @@ enum GetTargetsResponse {
     Ok(Json<Vec<TargetSnapshot>>),
 }
 
+/// Targets the user may access, sorted by name
+async fn authorized_targets(
+    services: &Services,
+    auth: &SessionAuthorization,
+    search: Option<&str>,
+) -> Result<Vec<TargetConfig>, WarpgateError> {
+    let mut targets = services.config_provider.lock().await.list_targets().await?;
+
+    if let Some(search) = search {
+        let search = search.to_lowercase();
+        targets.retain(|t| t.name.to_lowercase().contains(&search))
+    }
+
+    let mut targets = stream::iter(targets)
+        .filter(|t| {
+            let services = services.clone();
+            let auth = auth.clone();
+            let name = t.name.clone();
+            async move {
+                match auth {
+                    SessionAuthorization::Ticket { target_name, .. } => target_name == name,
+                    SessionAuthorization::User(_) | SessionAuthorization::ApiToken { .. } => {
+                        if !auth.allows_target(&name) {
+                            return false;
+                        }
+                        let mut config_provider = services.config_provider.lock().await;
+
+                        matches!(
+                            config_provider
+                                .authorize_target(auth.username(), &name)
+                                .await,
+                            Ok(true)
+                        )
+                    }
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .await;
+    targets.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(targets)
+}
+
+#[derive(Debug, Serialize, Clone, Object)]
+pub struct TargetStatus {
+    pub name: String,
+    pub health: TargetHealth,
+    /// The user's own sessions to this target
+    pub active_sessions: u32,
+}
+
+async fn target_statuses(
+    services: &Services,
+    auth: &SessionAuthorization,
+) -> Result<Vec<TargetStatus>, WarpgateError> {
+    let targets = authorized_targets(services, auth, None).await?;
+
+    let mut session_counts: HashMap<String, u32> = HashMap::new();
+    let sessions = services
+        .state
+        .lock()
+        .await
+        .sessions
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    for session in sessions {
+        let session = session.lock().await;
+        if session.username.as_ref() != Some(auth.username()) {
+            continue;
+        }
+        if let Some(ref target) = session.target {
+            *session_counts.entry(target.name.clone()).or_default() += 1;
+        }
+    }
+
+    Ok(targets
+        .into_iter()
+        .map(|t| TargetStatus {
+            health: services.target_health.get(&t.name),
+            active_sessions: session_counts.get(&t.name).copied().unwrap_or_default(),
+            name: t.name,
+        })
+        .collect())
+}
+
+#[derive(ApiResponse)]
+enum GetTargetStatusesResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<TargetStatus>>),
+}
+
+#[derive(ApiResponse)]
+enum CreateDirectCredentialResponse {
+    #[oai(status = 201)]
+    Created(Json<IssuedDirectCredential>),
+    /// The target doesn't offer direct credentials
+    #[oai(status = 400)]
+    NotAvailable,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+#[derive(ApiResponse)]
+enum GetTargetFingerprintsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<TargetFingerprint>>),
+    #[oai(status = 404)]
+    NotFound,
+}
+
+/// Empty unless the target is set up to show them
+async fn target_fingerprints(
+    services: &Services,
+    target: &TargetConfig,
+) -> Result<Vec<TargetFingerprint>, WarpgateError> {
+    let last_seen = || services.target_fingerprints.get(&target.name);
+    Ok(match target.options {
+        TargetOptions::Ssh(ref options) if options.show_fingerprint => {
+            let known = {
+                let db = services.db.lock().await;
+                known_host_fingerprints(&db, &options.host, options.port).await?
+            };
+            if known.is_empty() {
+                last_seen().into_iter().collect()
+            } else {
+                known
+            }
+        }
+        TargetOptions::Docker(ref options) if options.ssh.show_fingerprint => {
+            last_seen().into_iter().collect()
+        }
+        TargetOptions::MySql(ref options) if options.show_fingerprint => {
+            last_seen().into_iter().collect()
+        }
+        TargetOptions::Postgres(ref options) if options.show_fingerprint => {
+            last_seen().into_iter().collect()
+        }
+        _ => vec![],
+    })
+}
+
 #[OpenApi]
 impl Api {
     #[oai(
@@ -43,41 +205,25 @@ impl Api {
             return Ok(GetTargetsResponse::Ok(Json(vec![])));
         };
 
-        let mut targets = {
-            let mut config_provider = services.config_provider.lock().await;
-            config_provider.list_targets().await?
-        };
+        let user_roles = services
+            .config_provider
+            .lock()
+            .await
+            .list_user_roles(auth.username())
+            .await?;
+        let mut targets = authorized_targets(&services, auth, search.as_deref()).await?;
 
-        if let Some(ref search) = *search {
-            let search = search.to_lowercase();
-            targets.retain(|t| t.name.to_lowercase().contains(&search))
+        // Hidden targets only turn up when searched for by their full name
+        if let SessionAuthorization::User(ref username) = auth {
+            let layout = user_portal_layout(&*services.db.lock().await, username).await?;
+            targets.retain(|t| {
+                !layout.hidden_targets.contains(&t.id)
+                    || search
+                        .as_deref()
+                        .is_some_and(|x| x.eq_ignore_ascii_case(&t.name))
+            });
         }
 
-        let mut targets = stream::iter(targets)
-            .filter(|t| {
-                let services = services.clone();
-                let auth = auth.clone();
-                let name = t.name.clone();
-                async move {
-                    match auth {
-                        SessionAuthorization::Ticket { target_name, .. } => target_name == name,
-                        SessionAuthorization::User(_) => {
-                            let mut config_provider = services.config_provider.lock().await;
-
-                            matches!(
-                                config_provider
-                                    .authorize_target(auth.username(), &name)
-                                    .await,
-                                Ok(true)
-                            )
-                        }
-                    }
-                }
-            })
-            .collect::<Vec<_>>()
-            .await;
-        targets.sort_by(|a, b| a.name.cmp(&b.name));
-
         Ok(GetTargetsResponse::Ok(Json(
             targets
                 .into_iter()
@@ -88,8 +234,137 @@ impl Api {
                         TargetOptions::Http(ref opt) => opt.external_host.clone(),
                         _ => None,
                     },
+                    forward_presets: match t.options {
+                        TargetOptions::Ssh(ref opt) => opt
+                            .forward_presets
+                            .iter()
+                            .filter(|x| x.is_allowed_for(&user_roles))
+                            .map(|x| TargetForwardPreset {
+                                name: x.name.clone(),
+                                port: x.port,
+                            })
+                            .collect(),
+                        _ => vec![],
+                    },
+                    direct_credentials: t.options.direct_credentials().is_some(),
                 })
                 .collect(),
         )))
     }
+
+    /// Health of the user's targets and the number of their open sessions
+    #[oai(
+        path = "/targets/status",
+        method = "get",
+        operation_id = "get_target_statuses",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_target_statuses(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+    ) -> poem::Result<GetTargetStatusesResponse> {
+        let RequestAuthorization::Session(auth) = *auth else {
+            return Ok(GetTargetStatusesResponse::Ok(Json(vec![])));
+        };
+        Ok(GetTargetStatusesResponse::Ok(Json(
+            target_statuses(&services, auth).await?,
+        )))
+    }
+
+    /// Host key or certificate fingerprints for the user to verify
+    /// before connecting
+    #[oai(
+        path = "/targets/:name/fingerprints",
+        method = "get",
+        operation_id = "get_target_fingerprints",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_target_fingerprints(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+        name: Path<String>,
+    ) -> poem::Result<GetTargetFingerprintsResponse> {
+        let RequestAuthorization::Session(auth) = *auth else {
+            return Ok(GetTargetFingerprintsResponse::NotFound);
+        };
+        let Some(target) = authorized_targets(&services, auth, None)
+            .await?
+            .into_iter()
+            .find(|t| t.name == *name)
+        else {
+            return Ok(GetTargetFingerprintsResponse::NotFound);
+        };
+        Ok(GetTargetFingerprintsResponse::Ok(Json(
+            target_fingerprints(&services, &target).await?,
+        )))
+    }
+
+    /// Creates a short-lived account on the target for connecting
+    /// to it directly, bypassing Warpgate
+    #[oai(
+        path = "/targets/:name/direct-credentials",
+        method = "post",
+        operation_id = "create_direct_credential",
+        transform = "endpoint_auth"
+    )]
+    async fn api_create_direct_credential(
+        &self,
+        services: Data<&Services>,
+        auth: Data<&RequestAuthorization>,
+        name: Path<String>,
+    ) -> poem::Result<CreateDirectCredentialResponse> {
+        let RequestAuthorization::Session(auth @ SessionAuthorization::User(_)) = *auth else {
+            return Ok(CreateDirectCredentialResponse::NotFound);
+        };
+        let Some(target) = authorized_targets(&services, auth, None)
+            .await?
+            .into_iter()
+            .find(|t| t.name == *name)
+        else {
+            return Ok(CreateDirectCredentialResponse::NotFound);
+        };
+        match issue_direct_credential(&services, &target, auth.username()).await {
+            Ok(credential) => Ok(CreateDirectCredentialResponse::Created(Json(credential))),
+            Err(DirectCredentialError::NotAvailable) => {
+                Ok(CreateDirectCredentialResponse::NotAvailable)
+            }
+            Err(DirectCredentialError::Other(error)) => Err(error.into()),
+        }
+    }
+}
+
+/// Sends the target statuses as JSON whenever a session starts or ends
+/// or a target's health changes
+#[handler]
+pub async fn api_get_target_statuses_stream(
+    ws: WebSocket,
+    services: Data<&Services>,
+    auth: Data<&RequestAuthorization>,
+) -> poem::Result<impl IntoResponse> {
+    let RequestAuthorization::Session(auth) = auth.0.clone() else {
+        return Err(poem::Error::from_status(StatusCode::UNAUTHORIZED));
+    };
+    let services = services.clone();
+    let mut state_changes = services.state.lock().await.subscribe();
+    let mut health_changes = services.target_health.subscribe();
+
+    Ok(ws.on_upgrade(|socket| async move {
+        let (mut sink, _) = socket.split();
+        loop {
+            let statuses = target_statuses(&services, &auth).await?;
+            sink.send(Message::Text(serde_json::to_string(&statuses)?))
+                .await?;
+
+            let closed = tokio::select! {
+                result = state_changes.recv() => matches!(result, Err(RecvError::Closed)),
+                result = health_changes.recv() => matches!(result, Err(RecvError::Closed)),
+            };
+            if closed {
+                break;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    }))
 }
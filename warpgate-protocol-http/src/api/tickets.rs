@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use poem::web::Data;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::{ApiResponse, Object, OpenApi};
+use uuid::Uuid;
+use warpgate_common::WarpgateError;
+use warpgate_core::{
+    issue_self_service_ticket, list_user_tickets, revoke_user_ticket, SelfServiceTicketError,
+    Services,
+};
+use warpgate_db_entities::Ticket;
+
+use crate::common::{endpoint_auth, RequestAuthorization, SessionAuthorization};
+
+pub struct Api;
+
+#[derive(ApiResponse)]
+enum GetMyTicketsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<Ticket::Model>>),
+    #[oai(status = 401)]
+    Unauthorized,
+}
+
+#[derive(Object)]
+struct SelfServiceTicketRequest {
+    target_name: String,
+    /// Defaults to the longest validity allowed by the administrator
+    expiry: Option<DateTime<Utc>>,
+    number_of_uses: Option<i16>,
+}
+
+#[derive(Object)]
+struct SelfServiceTicketAndSecret {
+    ticket: Ticket::Model,
+    secret: String,
+}
+
+#[derive(ApiResponse)]
+enum CreateMyTicketResponse {
+    #[oai(status = 201)]
+    Created(Json<SelfServiceTicketAndSecret>),
+    #[oai(status = 400)]
+    BadRequest(Json<String>),
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 403)]
+    Forbidden(Json<String>),
+}
+
+#[derive(ApiResponse)]
+enum DeleteMyTicketResponse {
+    #[oai(status = 204)]
+    Deleted,
+    #[oai(status = 401)]
+    Unauthorized,
+    #[oai(status = 404)]
+    NotFound,
+}
+
+/// Tickets can't be used to issue further tickets.
+fn ticket_issuer(auth: &RequestAuthorization) -> Option<&SessionAuthorization> {
+    match auth {
+        RequestAuthorization::Session(SessionAuthorization::Ticket { .. }) => None,
+        RequestAuthorization::Session(auth) => Some(auth),
+        RequestAuthorization::AdminToken => None,
+    }
+}
+
+#[OpenApi]
+impl Api {
+    #[oai(
+        path = "/profile/tickets",
+        method = "get",
+        operation_id = "get_my_tickets",
+        transform = "endpoint_auth"
+    )]
+    async fn api_get_my_tickets(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+    ) -> Result<GetMyTicketsResponse, WarpgateError> {
+        let Some(auth) = ticket_issuer(&auth) else {
+            return Ok(GetMyTicketsResponse::Unauthorized);
+        };
+
+        Ok(GetMyTicketsResponse::Ok(Json(
+            list_user_tickets(&services, auth.username()).await?,
+        )))
+    }
+
+    #[oai(
+        path = "/profile/tickets",
+        method = "post",
+        operation_id = "create_my_ticket",
+        transform = "endpoint_auth"
+    )]
+    async fn api_create_my_ticket(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        body: Json<SelfServiceTicketRequest>,
+    ) -> Result<CreateMyTicketResponse, WarpgateError> {
+        let Some(auth) = ticket_issuer(&auth) else {
+            return Ok(CreateMyTicketResponse::Unauthorized);
+        };
+
+        let result = issue_self_service_ticket(
+            &services,
+            auth.username(),
+            warpgate_core::SelfServiceTicketRequest {
+                target_name: body.target_name.clone(),
+                expiry: body.expiry,
+                number_of_uses: body.number_of_uses,
+            },
+            |target_name| auth.allows_target(target_name),
+            |protocol| auth.allows_protocol(protocol),
+        )
+        .await;
+
+        match result {
+            Ok((ticket, secret)) => Ok(CreateMyTicketResponse::Created(Json(
+                SelfServiceTicketAndSecret {
+                    ticket,
+                    secret: secret.expose_secret().to_string(),
+                },
+            ))),
+            Err(SelfServiceTicketError::Invalid(field)) => {
+                Ok(CreateMyTicketResponse::BadRequest(Json(field.into())))
+            }
+            Err(SelfServiceTicketError::Disabled) => Ok(CreateMyTicketResponse::Forbidden(Json(
+                "Self-service tickets are disabled".into(),
+            ))),
+            Err(SelfServiceTicketError::NoAccess) => Ok(CreateMyTicketResponse::Forbidden(Json(
+                "No access to this target".into(),
+            ))),
+            Err(SelfServiceTicketError::ProtocolNotAllowed(protocol)) => {
+                Ok(CreateMyTicketResponse::Forbidden(Json(format!(
+                    "Tickets for {protocol} targets are not allowed"
+                ))))
+            }
+            Err(SelfServiceTicketError::Warpgate(error)) => Err(error),
+        }
+    }
+
+    #[oai(
+        path = "/profile/tickets/:id",
+        method = "delete",
+        operation_id = "delete_my_ticket",
+        transform = "endpoint_auth"
+    )]
+    async fn api_delete_my_ticket(
+        &self,
+        auth: Data<&RequestAuthorization>,
+        services: Data<&Services>,
+        id: Path<Uuid>,
+    ) -> Result<DeleteMyTicketResponse, WarpgateError> {
+        let Some(auth) = ticket_issuer(&auth) else {
+            return Ok(DeleteMyTicketResponse::Unauthorized);
+        };
+
+        if !revoke_user_ticket(&services, auth.username(), id.0).await? {
+            return Ok(DeleteMyTicketResponse::NotFound);
+        }
+        Ok(DeleteMyTicketResponse::Deleted)
+    }
+}
@@ -7,11 +7,26 @@ use poem::{handler, Body, IntoResponse, Request, Response};
 use serde::Deserialize;
 use tokio::sync::Mutex;
 use tracing::*;
-use warpgate_common::{Target, TargetHTTPOptions, TargetOptions};
-use warpgate_core::{ConfigProvider, Services, WarpgateServerHandle};
+use warpgate_common::auth::TargetParameters;
+use warpgate_common::{
+    is_host_pattern, match_host_pattern, Target, TargetHTTPOptions, TargetOptions,
+};
+use warpgate_core::{
+    normalize_work_item, resolve_target_address, trip_honeypot, ConfigProvider, Services,
+    WarpgateServerHandle,
+};
 
-use crate::common::{RequestAuthorization, SessionAuthorization, SessionExt};
-use crate::proxy::{proxy_normal_request, proxy_websocket_request};
+use crate::access_rules::is_request_allowed;
+use crate::common::{
+    RequestAuthorization, SessionAuthorization, SessionExt, PROTOCOL_NAME, X_WARPGATE_WORK_ITEM,
+};
+use crate::honeypot::honeypot_response;
+use crate::logging::get_client_ip;
+use crate::proxy::{
+    proxy_normal_request, proxy_websocket_request, CapabilitiesContext, ShadowContext,
+};
+use crate::recording::RecordingContext;
+use crate::session::SessionStore;
 
 #[derive(Deserialize)]
 struct QueryParams {
@@ -31,29 +46,105 @@ pub async fn catchall_endpoint(
     body: Body,
     services: Data<&Services>,
     server_handle: Option<Data<&Arc<Mutex<WarpgateServerHandle>>>>,
+    session_store: Data<&Arc<Mutex<SessionStore>>>,
 ) -> poem::Result<Response> {
     let target_and_options = get_target_for_request(req, services.0).await?;
     let Some((target, options)) = target_and_options else {
         return Ok(target_select_redirect());
     };
 
-    session.set_target_name(target.name.clone());
+    let previous_target_name = session.get_target_name();
+    // Requests authenticated with an API token don't get a session
+    if session.is_authenticated() {
+        session.set_target_name(target.name.clone());
+    }
+
+    let work_item = req
+        .header(&X_WARPGATE_WORK_ITEM)
+        .and_then(normalize_work_item)
+        .filter(|x| session.get_work_item().as_ref() != Some(x));
 
+    let mut session_id = None;
     if let Some(server_handle) = server_handle {
-        server_handle.lock().await.set_target(&target).await?;
+        let server_handle = server_handle.lock().await;
+        session_id = Some(server_handle.id());
+        server_handle.last_activity().touch();
+        server_handle.set_target(&target).await?;
+        if let Some(work_item) = work_item {
+            info!(%work_item, "Tagged session with a work item");
+            server_handle.set_work_item(Some(work_item.clone())).await?;
+            session.set_work_item(work_item);
+        }
+    }
+
+    if target.honeypot {
+        if previous_target_name.as_ref() != Some(&target.name) {
+            trip_honeypot(
+                &services,
+                &target,
+                PROTOCOL_NAME,
+                session_id,
+                session.get_username().as_deref(),
+                get_client_ip(req).await.ok(),
+            )
+            .await;
+        }
+        return Ok(honeypot_response(req));
     }
 
+    let resolver = services.dns.for_target(&target);
     let span = info_span!("", target=%target.name);
 
+    if !is_request_allowed(&options, req.method(), req.uri().path()) {
+        span.in_scope(|| {
+            warn!(
+                method=%req.method(),
+                path=%req.uri().path(),
+                "Request refused by the target's access rules"
+            );
+        });
+        return Ok(http::StatusCode::FORBIDDEN.into_response());
+    }
+
     Ok(match ws {
-        Some(ws) => proxy_websocket_request(req, ws, &options)
+        Some(ws) => proxy_websocket_request(req, ws, &options, &resolver)
             .instrument(span)
             .await?
             .into_response(),
-        None => proxy_normal_request(req, body, &options)
+        None => {
+            let shadow = ShadowContext {
+                target_name: target.name.clone(),
+                reports: services.shadow_reports.clone(),
+            };
+            let recording = match session_id {
+                Some(session_id) if options.record_bodies.is_some() => session_store
+                    .lock()
+                    .await
+                    .http_recorder_for(session_id, &services)
+                    .await
+                    .map(|recorder| RecordingContext {
+                        target_name: target.name.clone(),
+                        recorder,
+                    }),
+                _ => None,
+            };
+            let capabilities = CapabilitiesContext {
+                target_name: target.name.clone(),
+                store: services.target_capabilities.clone(),
+            };
+            proxy_normal_request(
+                req,
+                body,
+                &options,
+                &resolver,
+                Some(shadow),
+                recording,
+                Some(capabilities),
+            )
             .instrument(span)
             .await?
-            .into_response(),
+            .into_response()
+        }
     })
 }
 
@@ -68,23 +159,13 @@ async fn get_target_for_request(
     let selected_target_name;
     let need_role_auth;
 
-    let host_based_target_name = if let Some(host) = req.original_uri().host() {
-        services
-            .config_provider
-            .lock()
-            .await
-            .list_targets()
-            .await?
-            .iter()
-            .filter_map(|t| match t.options {
-                TargetOptions::Http(ref options) => Some((t, options)),
-                _ => None,
-            })
-            .find(|(_, o)| o.external_host.as_deref() == Some(host))
-            .map(|(t, _)| t.name.clone())
+    let host_based_target = if let Some(host) = req.original_uri().host() {
+        let targets = services.config_provider.lock().await.list_targets().await?;
+        find_host_based_target(&targets, host)
     } else {
         None
     };
+    let (host_based_target_name, host_parameters) = host_based_target.unzip();
 
     let username = match *auth {
         RequestAuthorization::Session(SessionAuthorization::Ticket {
@@ -95,20 +176,27 @@ async fn get_target_for_request(
             need_role_auth = false;
             username
         }
-        RequestAuthorization::Session(SessionAuthorization::User(username)) => {
+        RequestAuthorization::Session(
+            SessionAuthorization::User(username) | SessionAuthorization::ApiToken { username, .. },
+        ) => {
             need_role_auth = true;
 
-            selected_target_name =
-                host_based_target_name.or(if let Some(warpgate_target) = params.warpgate_target {
+            selected_target_name = host_based_target_name.clone().or(
+                if let Some(warpgate_target) = params.warpgate_target {
                     Some(warpgate_target)
                 } else {
                     session.get_target_name()
-                });
+                },
+            );
             username
         }
-        RequestAuthorization::UserToken { .. } | RequestAuthorization::AdminToken => {
-            return Ok(None)
-        }
+        RequestAuthorization::AdminToken => return Ok(None),
+    };
+
+    // Labels captured by a wildcard `external_host`
+    let parameters = match host_based_target_name == selected_target_name {
+        true => host_parameters.unwrap_or_default(),
+        false => TargetParameters::new(),
     };
 
     if let Some(target_name) = selected_target_name {
@@ -120,30 +208,70 @@ async fn get_target_for_request(
                 .list_targets()
                 .await?
                 .iter()
-                .filter(|t| t.name == target_name)
-                .filter_map(|t| match t.options {
-                    TargetOptions::Http(ref options) => Some((t, options)),
-                    _ => None,
-                })
-                .next()
-                .map(|(t, o)| (t.clone(), o.clone()))
+                .find(|t| t.name == target_name && matches!(t.options, TargetOptions::Http(_)))
+                .cloned()
         };
 
-        if let Some(target) = target {
+        if let Some(mut target) = target {
+            if let RequestAuthorization::Session(auth) = *auth {
+                if !auth.allows_target(&target.name) || !auth.allows_protocol(PROTOCOL_NAME) {
+                    return Ok(None);
+                }
+            }
+
             if need_role_auth
                 && !services
                     .config_provider
                     .lock()
                     .await
-                    .authorize_target(username, &target.0.name)
+                    .authorize_target(username, &target.name)
                     .await?
             {
                 return Ok(None);
             }
 
-            return Ok(Some(target));
+            if !target.honeypot {
+                if let Err(error) =
+                    resolve_target_address(services, username, &mut target, &parameters).await
+                {
+                    warn!(%error, target=%target.name, "Could not resolve the target URL");
+                    return Err(poem::Error::from_string(
+                        error.code().annotate(&error),
+                        http::StatusCode::BAD_REQUEST,
+                    ));
+                }
+            }
+
+            let TargetOptions::Http(ref options) = target.options else {
+                return Ok(None);
+            };
+            let options = options.clone();
+            return Ok(Some((target, options)));
         }
     }
 
     Ok(None)
 }
+
+/// Exact `external_host` matches take precedence over wildcard patterns
+fn find_host_based_target(targets: &[Target], host: &str) -> Option<(String, TargetParameters)> {
+    let external_hosts = targets
+        .iter()
+        .filter_map(|t| match t.options {
+            TargetOptions::Http(ref options) => Some((t, options.external_host.as_deref()?)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    external_hosts
+        .iter()
+        .find(|(_, pattern)| !is_host_pattern(pattern) && pattern.eq_ignore_ascii_case(host))
+        .map(|(t, _)| (t.name.clone(), TargetParameters::new()))
+        .or_else(|| {
+            external_hosts.iter().find_map(|(t, pattern)| {
+                match_host_pattern(pattern, host)
+                    .filter(|_| is_host_pattern(pattern))
+                    .map(|parameters| (t.name.clone(), parameters))
+            })
+        })
+}
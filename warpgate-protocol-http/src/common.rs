@@ -7,23 +7,35 @@ use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 use poem::session::Session;
 use poem::web::{Data, Redirect};
 use poem::{Endpoint, EndpointExt, FromRequest, IntoResponse, Request, Response};
+use sea_orm::ActiveValue::Set;
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 use warpgate_common::auth::{AuthState, CredentialKind};
+use warpgate_common::i18n::Language;
 use warpgate_common::{ProtocolName, TargetOptions, WarpgateError};
-use warpgate_core::{AuthStateStore, ConfigProvider, Services};
+use warpgate_core::{
+    AdminIdentity, AuthFailureContext, AuthStateStore, ConfigChangeAuthor, ConfigProvider, Services,
+};
+use warpgate_db_entities::ApiToken::ApiTokenScope;
+use warpgate_db_entities::HttpSessionDetails;
 use warpgate_sso::CoreIdToken;
 
+use crate::logging::get_client_ip;
 use crate::session::SessionStore;
 
 pub const PROTOCOL_NAME: ProtocolName = "HTTP";
 static TARGET_SESSION_KEY: &str = "target_name";
-static AUTH_SESSION_KEY: &str = "auth";
+pub static AUTH_SESSION_KEY: &str = "auth";
 static AUTH_STATE_ID_SESSION_KEY: &str = "auth_state_id";
 static AUTH_SSO_LOGIN_STATE: &str = "auth_sso_login_state";
+static WORK_ITEM_SESSION_KEY: &str = "work_item";
 pub static SESSION_COOKIE_NAME: &str = "warpgate-http-session";
-static X_WARPGATE_TOKEN: HeaderName = HeaderName::from_static("x-warpgate-token");
+/// Longer `User-Agent` headers are truncated before being stored
+const MAX_USER_AGENT_LENGTH: usize = 512;
+pub static X_WARPGATE_TOKEN: HeaderName = HeaderName::from_static("x-warpgate-token");
+/// Tags the session with a work item
+pub static X_WARPGATE_WORK_ITEM: HeaderName = HeaderName::from_static("x-warpgate-work-item");
 
 #[derive(Serialize, Deserialize)]
 pub struct SsoLoginState {
@@ -35,6 +47,8 @@ pub struct SsoLoginState {
 pub trait SessionExt {
     fn get_target_name(&self) -> Option<String>;
     fn set_target_name(&self, target_name: String);
+    fn get_work_item(&self) -> Option<String>;
+    fn set_work_item(&self, work_item: String);
     fn is_authenticated(&self) -> bool;
     fn get_username(&self) -> Option<String>;
     fn get_auth(&self) -> Option<SessionAuthorization>;
@@ -55,6 +69,14 @@ impl SessionExt for Session {
         self.set(TARGET_SESSION_KEY, target_name);
     }
 
+    fn get_work_item(&self) -> Option<String> {
+        self.get(WORK_ITEM_SESSION_KEY)
+    }
+
+    fn set_work_item(&self, work_item: String) {
+        self.set(WORK_ITEM_SESSION_KEY, work_item);
+    }
+
     fn is_authenticated(&self) -> bool {
         self.get_username().is_some()
     }
@@ -101,6 +123,10 @@ pub enum SessionAuthorization {
         username: String,
         target_name: String,
     },
+    ApiToken {
+        username: String,
+        scope: ApiTokenScope,
+    },
 }
 
 impl SessionAuthorization {
@@ -108,6 +134,27 @@ impl SessionAuthorization {
         match self {
             Self::User(username) => username,
             Self::Ticket { username, .. } => username,
+            Self::ApiToken { username, .. } => username,
+        }
+    }
+
+    /// Whether the authorization is not narrowed down to exclude the target.
+    /// Role-based access is checked separately.
+    pub fn allows_target(&self, target_name: &str) -> bool {
+        match self {
+            Self::User(_) => true,
+            Self::Ticket {
+                target_name: ticket_target,
+                ..
+            } => ticket_target == target_name,
+            Self::ApiToken { scope, .. } => scope.allows_target(target_name),
+        }
+    }
+
+    pub fn allows_protocol(&self, protocol: &str) -> bool {
+        match self {
+            Self::User(_) | Self::Ticket { .. } => true,
+            Self::ApiToken { scope, .. } => scope.allows_protocol(protocol),
         }
     }
 }
@@ -115,7 +162,6 @@ impl SessionAuthorization {
 #[derive(Clone, Serialize, Deserialize)]
 pub enum RequestAuthorization {
     Session(SessionAuthorization),
-    UserToken { username: String },
     AdminToken,
 }
 
@@ -123,26 +169,36 @@ impl RequestAuthorization {
     pub fn username(&self) -> Option<&String> {
         match self {
             Self::Session(auth) => Some(auth.username()),
-            Self::UserToken { username } => Some(username),
             Self::AdminToken => None,
         }
     }
+
+    /// Only set for users that logged in interactively,
+    /// not with a ticket or an API token
+    pub fn interactive_username(&self) -> Option<&String> {
+        match self {
+            Self::Session(SessionAuthorization::User(username)) => Some(username),
+            _ => None,
+        }
+    }
 }
 
 async fn is_user_admin(req: &Request, auth: &RequestAuthorization) -> poem::Result<bool> {
     let services = Data::<&Services>::from_request_without_body(req).await?;
 
-    let username = match auth {
-        RequestAuthorization::Session(SessionAuthorization::User(username)) => username,
+    let auth = match auth {
         RequestAuthorization::Session(SessionAuthorization::Ticket { .. }) => return Ok(false),
-        RequestAuthorization::UserToken { username } => username,
+        RequestAuthorization::Session(auth) => auth,
         RequestAuthorization::AdminToken => return Ok(true),
     };
+    let username = auth.username();
 
     let mut config_provider = services.config_provider.lock().await;
     let targets = config_provider.list_targets().await?;
     for target in targets {
         if matches!(target.options, TargetOptions::WebAdmin(_))
+            && auth.allows_target(&target.name)
+            && auth.allows_protocol(PROTOCOL_NAME)
             && config_provider
                 .authorize_target(username, &target.name)
                 .await?
@@ -155,9 +211,13 @@ async fn is_user_admin(req: &Request, auth: &RequestAuthorization) -> poem::Resu
 }
 
 pub fn endpoint_admin_auth<E: Endpoint + 'static>(e: E) -> impl Endpoint {
-    e.around(|ep, req| async move {
+    e.around(|ep, mut req| async move {
         let auth = Data::<&RequestAuthorization>::from_request_without_body(&req).await?;
         if is_user_admin(&req, &auth).await? {
+            let username = auth.username().cloned();
+            req.extensions_mut()
+                .insert(ConfigChangeAuthor(username.clone()));
+            req.extensions_mut().insert(AdminIdentity { username });
             return Ok(ep.call(req).await?.into_response());
         }
         Err(poem::Error::from_status(StatusCode::UNAUTHORIZED))
@@ -183,25 +243,17 @@ pub async fn _inner_auth<E: Endpoint + 'static>(
     let session = <&Session>::from_request_without_body(&req).await?;
     let services = Data::<&Services>::from_request_without_body(&req).await?;
 
-    let auth = match session.get_auth() {
-        Some(auth) => RequestAuthorization::Session(auth),
-        None => match req.headers().get(&X_WARPGATE_TOKEN) {
+    let auth = match (session.get_auth(), req.data::<RequestAuthorization>()) {
+        (Some(auth), _) => RequestAuthorization::Session(auth),
+        // User API tokens are validated by TokenMiddleware
+        (None, Some(auth)) => auth.clone(),
+        (None, None) => match req.headers().get(&X_WARPGATE_TOKEN) {
             Some(token_from_header) => {
                 let token_from_header = token_from_header
                     .to_str()
                     .map_err(poem::error::BadRequest)?;
                 if Some(token_from_header) == services.admin_token.lock().await.as_deref() {
                     RequestAuthorization::AdminToken
-                } else if let Some(user) = services
-                    .config_provider
-                    .lock()
-                    .await
-                    .validate_api_token(token_from_header)
-                    .await?
-                {
-                    RequestAuthorization::UserToken {
-                        username: user.username,
-                    }
                 } else {
                     return Ok(None);
                 }
@@ -231,6 +283,13 @@ pub fn page_auth<E: Endpoint + 'static>(e: E) -> impl Endpoint {
     })
 }
 
+/// Language for user-facing messages, negotiated from `Accept-Language`
+pub fn request_language(req: &Request) -> Language {
+    req.header(http::header::ACCEPT_LANGUAGE)
+        .and_then(Language::from_accept_language)
+        .unwrap_or_default()
+}
+
 pub fn gateway_redirect(req: &Request) -> Response {
     let path = req
         .original_uri()
@@ -282,6 +341,45 @@ pub async fn get_auth_state_for_request(
     Ok(state)
 }
 
+fn request_user_agent(req: &Request) -> Option<String> {
+    req.header(http::header::USER_AGENT)
+        .map(|x| x.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
+pub async fn record_auth_failure(
+    req: &Request,
+    services: &Services,
+    username: &str,
+    credential_kinds: Vec<CredentialKind>,
+) {
+    let socket_address = req.remote_addr().as_socket_addr();
+    let remote_ip = get_client_ip(req).await.ok();
+    // Only meaningful when the client is connected directly
+    let remote_port = socket_address
+        .filter(|x| remote_ip.as_deref() == Some(x.ip().to_string().as_str()))
+        .map(|x| x.port());
+    let context = AuthFailureContext {
+        protocol: PROTOCOL_NAME.into(),
+        remote_ip,
+        remote_port,
+        username: Some(username.into()),
+        credential_kinds,
+        client_banner: None,
+        user_agent: request_user_agent(req),
+    };
+    services.auth_rate_limiter.record_failure(&context).await;
+    services.auth_failures.record(context).await;
+}
+
+/// Whether a login attempt for `username` may proceed, see [warpgate_core::AuthRateLimiter]
+pub async fn auth_attempt_allowed(req: &Request, services: &Services, username: &str) -> bool {
+    let remote_ip = get_client_ip(req).await.ok();
+    services
+        .auth_rate_limiter
+        .allows(Some(username), remote_ip.as_deref())
+        .await
+}
+
 pub async fn authorize_session(req: &Request, username: String) -> Result<(), WarpgateError> {
     let session_middleware = Data::<&Arc<Mutex<SessionStore>>>::from_request_without_body(req)
         .await
@@ -296,11 +394,19 @@ pub async fn authorize_session(req: &Request, username: String) -> Result<(), Wa
         .create_handle_for(req)
         .await
         .context("create_handle_for")?;
-    server_handle
-        .lock()
-        .await
-        .set_username(username.clone())
-        .await?;
+    {
+        let server_handle = server_handle.lock().await;
+        let user_agent = request_user_agent(req);
+        server_handle.set_username(username.clone()).await?;
+        server_handle.set_user_agent(user_agent.clone()).await?;
+        server_handle
+            .update_http_details(HttpSessionDetails::ActiveModel {
+                host: Set(req.header(http::header::HOST).map(Into::into)),
+                user_agent: Set(user_agent),
+                ..Default::default()
+            })
+            .await?;
+    }
     session.set_auth(SessionAuthorization::User(username));
 
     Ok(())
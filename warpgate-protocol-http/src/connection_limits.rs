@@ -0,0 +1,632 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::Future;
+use http::uri::Scheme;
+use poem::listener::Acceptor;
+use poem::web::{LocalAddr, RemoteAddr};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+use tracing::*;
+use warpgate_core::IpBanList;
+
+const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const HTTP2_FRAME_HEADER_SIZE: usize = 9;
+const HTTP2_FRAME_HEADERS: u8 = 0x1;
+const HTTP2_FRAME_CONTINUATION: u8 = 0x9;
+const HTTP2_FLAG_END_HEADERS: u8 = 0x4;
+
+/// Wraps an [Acceptor] to drop connections from banned addresses, cap the
+/// number of concurrent connections per client IP and close connections
+/// that send oversized request headers or take too long to send them.
+pub struct LimitedAcceptor<A> {
+    inner: A,
+    header_read_timeout: Duration,
+    max_header_size: usize,
+    max_connections_per_ip: Option<usize>,
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip_bans: Arc<IpBanList>,
+}
+
+impl<A: Acceptor> LimitedAcceptor<A> {
+    pub fn new(
+        inner: A,
+        header_read_timeout: Duration,
+        max_header_size: usize,
+        max_connections_per_ip: Option<usize>,
+        ip_bans: Arc<IpBanList>,
+    ) -> Self {
+        Self {
+            inner,
+            header_read_timeout,
+            max_header_size,
+            max_connections_per_ip,
+            connections: Default::default(),
+            ip_bans,
+        }
+    }
+
+    fn register_connection(&self, ip: IpAddr, limit: usize) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        let count = connections.entry(ip).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(ConnectionGuard {
+            connections: self.connections.clone(),
+            ip,
+        })
+    }
+}
+
+impl<A: Acceptor> Acceptor for LimitedAcceptor<A> {
+    type Io = LimitedStream<A::Io>;
+
+    fn local_addr(&self) -> Vec<LocalAddr> {
+        self.inner.local_addr()
+    }
+
+    async fn accept(&mut self) -> io::Result<(Self::Io, LocalAddr, RemoteAddr, Scheme)> {
+        loop {
+            let (io, local_addr, remote_addr, scheme) = self.inner.accept().await?;
+
+            if let Some(addr) = remote_addr.as_socket_addr() {
+                if !self.ip_bans.is_allowed(addr.ip()) {
+                    debug!(%addr, "Dropping a connection from a banned address");
+                    continue;
+                }
+            }
+
+            let guard = match (self.max_connections_per_ip, remote_addr.as_socket_addr()) {
+                (Some(limit), Some(addr)) => match self.register_connection(addr.ip(), limit) {
+                    Some(guard) => Some(guard),
+                    None => {
+                        debug!(%addr, "Too many connections from this address, dropping");
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+
+            return Ok((
+                LimitedStream::new(io, guard, self.header_read_timeout, self.max_header_size),
+                local_addr,
+                remote_addr,
+                scheme,
+            ));
+        }
+    }
+}
+
+struct ConnectionGuard {
+    connections: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.connections.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HeadTooLarge;
+
+/// Follows the requests sent on a connection closely enough to tell
+/// whether a request head (HTTP/1) or a header block (HTTP/2) is being
+/// received at the moment, and how large it has grown.
+struct RequestTracker {
+    max_header_size: usize,
+    state: TrackerState,
+}
+
+enum TrackerState {
+    /// Nothing but a prefix of the HTTP/2 preface has been read so far
+    Preface {
+        matched: usize,
+    },
+    /// Between HTTP/1 requests
+    Idle,
+    Head {
+        head: Vec<u8>,
+    },
+    Body {
+        remaining: u64,
+    },
+    Chunked(ChunkState),
+    Http2(Http2State),
+    /// Upgraded (e.g. to a websocket) or tunnelled, no longer HTTP
+    Opaque,
+}
+
+enum ChunkState {
+    Size {
+        size: u64,
+        in_extension: bool,
+    },
+    Data {
+        remaining: u64,
+    },
+    /// The CRLF after a chunk's data
+    DataEnd,
+    Trailer {
+        line_length: usize,
+    },
+}
+
+#[derive(Default)]
+struct Http2State {
+    frame_header: [u8; HTTP2_FRAME_HEADER_SIZE],
+    filled: usize,
+    payload_remaining: usize,
+    /// Whether the frame whose payload is being read is part of a header block
+    in_header_frame: bool,
+    /// Size so far of a header block that is still waiting for END_HEADERS
+    header_block: Option<usize>,
+}
+
+impl RequestTracker {
+    fn new(max_header_size: usize) -> Self {
+        Self {
+            max_header_size,
+            state: TrackerState::Preface { matched: 0 },
+        }
+    }
+
+    /// Whether the connection is in the middle of sending request headers
+    fn in_head(&self) -> bool {
+        match &self.state {
+            TrackerState::Preface { .. } | TrackerState::Head { .. } => true,
+            TrackerState::Http2(h2) => {
+                h2.filled > 0
+                    || h2.header_block.is_some()
+                    || (h2.payload_remaining > 0 && h2.in_header_frame)
+            }
+            TrackerState::Idle
+            | TrackerState::Body { .. }
+            | TrackerState::Chunked(_)
+            | TrackerState::Opaque => false,
+        }
+    }
+
+    fn feed(&mut self, mut data: &[u8]) -> Result<(), HeadTooLarge> {
+        while !data.is_empty() {
+            let consumed = self.consume(data)?;
+            data = data.get(consumed..).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes consumed from the start of `data`
+    fn consume(&mut self, data: &[u8]) -> Result<usize, HeadTooLarge> {
+        let Some((&byte, _)) = data.split_first() else {
+            return Ok(0);
+        };
+        match &mut self.state {
+            TrackerState::Preface { matched } => {
+                if HTTP2_PREFACE.get(*matched) == Some(&byte) {
+                    *matched += 1;
+                    if *matched == HTTP2_PREFACE.len() {
+                        self.state = TrackerState::Http2(Http2State::default());
+                    }
+                    return Ok(1);
+                }
+                // Not HTTP/2 after all - replay what was held back as HTTP/1
+                let matched = *matched;
+                self.state = TrackerState::Idle;
+                self.feed(HTTP2_PREFACE.get(..matched).unwrap_or_default())?;
+                Ok(0)
+            }
+            TrackerState::Idle => {
+                // Stray line breaks between requests are tolerated by the server
+                if byte != b'\r' && byte != b'\n' {
+                    self.state = TrackerState::Head { head: vec![byte] };
+                }
+                Ok(1)
+            }
+            TrackerState::Head { head } => {
+                head.push(byte);
+                if head.len() > self.max_header_size {
+                    return Err(HeadTooLarge);
+                }
+                if head.ends_with(b"\r\n\r\n") || head.ends_with(b"\n\n") {
+                    let next = state_after_head(head);
+                    self.state = next;
+                }
+                Ok(1)
+            }
+            TrackerState::Body { remaining } => {
+                let n = (*remaining).min(data.len() as u64);
+                *remaining -= n;
+                if *remaining == 0 {
+                    self.state = TrackerState::Idle;
+                }
+                Ok(n as usize)
+            }
+            TrackerState::Chunked(chunk) => match chunk {
+                ChunkState::Size { size, in_extension } => {
+                    match byte {
+                        b'\n' if *size == 0 => *chunk = ChunkState::Trailer { line_length: 0 },
+                        b'\n' => *chunk = ChunkState::Data { remaining: *size },
+                        b';' => *in_extension = true,
+                        _ if *in_extension => (),
+                        _ => {
+                            if let Some(digit) = (byte as char).to_digit(16) {
+                                match size.checked_mul(16) {
+                                    Some(x) => *size = x + u64::from(digit),
+                                    // The server will reject this anyway
+                                    None => self.state = TrackerState::Opaque,
+                                }
+                            }
+                        }
+                    }
+                    Ok(1)
+                }
+                ChunkState::Data { remaining } => {
+                    let n = (*remaining).min(data.len() as u64);
+                    *remaining -= n;
+                    if *remaining == 0 {
+                        *chunk = ChunkState::DataEnd;
+                    }
+                    Ok(n as usize)
+                }
+                ChunkState::DataEnd => {
+                    if byte == b'\n' {
+                        *chunk = ChunkState::Size {
+                            size: 0,
+                            in_extension: false,
+                        };
+                    }
+                    Ok(1)
+                }
+                ChunkState::Trailer { line_length } => {
+                    match byte {
+                        b'\n' if *line_length == 0 => self.state = TrackerState::Idle,
+                        b'\n' => *line_length = 0,
+                        b'\r' => (),
+                        _ => *line_length += 1,
+                    }
+                    Ok(1)
+                }
+            },
+            TrackerState::Http2(h2) => {
+                if h2.payload_remaining > 0 {
+                    let n = h2.payload_remaining.min(data.len());
+                    h2.payload_remaining -= n;
+                    return Ok(n);
+                }
+
+                let Some(slot) = h2.frame_header.get_mut(h2.filled) else {
+                    // Lost track of the framing - stop inspecting the connection
+                    self.state = TrackerState::Opaque;
+                    return Ok(data.len());
+                };
+                *slot = byte;
+                h2.filled += 1;
+                if h2.filled < HTTP2_FRAME_HEADER_SIZE {
+                    return Ok(1);
+                }
+                h2.filled = 0;
+
+                let [l1, l2, l3, kind, flags, ..] = h2.frame_header;
+                let length = u32::from_be_bytes([0, l1, l2, l3]) as usize;
+                h2.payload_remaining = length;
+                h2.in_header_frame =
+                    kind == HTTP2_FRAME_HEADERS || kind == HTTP2_FRAME_CONTINUATION;
+                if h2.in_header_frame {
+                    let size = h2.header_block.unwrap_or(0) + length;
+                    if size > self.max_header_size {
+                        return Err(HeadTooLarge);
+                    }
+                    h2.header_block = (flags & HTTP2_FLAG_END_HEADERS == 0).then_some(size);
+                }
+                Ok(1)
+            }
+            TrackerState::Opaque => Ok(data.len()),
+        }
+    }
+}
+
+/// Works out how the request body that follows `head` is framed
+fn state_after_head(head: &[u8]) -> TrackerState {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.lines();
+    if lines
+        .next()
+        .is_some_and(|request_line| request_line.starts_with("CONNECT "))
+    {
+        return TrackerState::Opaque;
+    }
+
+    let mut content_length = None;
+    let mut chunked = false;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => content_length = value.parse::<u64>().ok(),
+            "transfer-encoding" => {
+                chunked = value
+                    .rsplit(',')
+                    .next()
+                    .is_some_and(|x| x.trim().eq_ignore_ascii_case("chunked"))
+            }
+            "upgrade" => return TrackerState::Opaque,
+            _ => (),
+        }
+    }
+
+    if chunked {
+        TrackerState::Chunked(ChunkState::Size {
+            size: 0,
+            in_extension: false,
+        })
+    } else {
+        match content_length {
+            Some(length) if length > 0 => TrackerState::Body { remaining: length },
+            _ => TrackerState::Idle,
+        }
+    }
+}
+
+pub struct LimitedStream<Io> {
+    inner: Io,
+    _guard: Option<ConnectionGuard>,
+    tracker: RequestTracker,
+    header_read_timeout: Duration,
+    /// Armed while the headers of a request are being received
+    header_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<Io> LimitedStream<Io> {
+    fn new(
+        inner: Io,
+        guard: Option<ConnectionGuard>,
+        header_read_timeout: Duration,
+        max_header_size: usize,
+    ) -> Self {
+        Self {
+            inner,
+            _guard: guard,
+            tracker: RequestTracker::new(max_header_size),
+            header_read_timeout,
+            header_deadline: Some(Box::pin(tokio::time::sleep(header_read_timeout))),
+        }
+    }
+}
+
+impl<Io: AsyncRead + Unpin> AsyncRead for LimitedStream<Io> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(deadline) = self.header_deadline.as_mut() {
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out waiting for request headers",
+                )));
+            }
+        }
+
+        let filled_before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let this = &mut *self;
+            let received = buf.filled().get(filled_before..).unwrap_or_default();
+            if this.tracker.feed(received).is_err() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request headers too large",
+                )));
+            }
+            match (this.tracker.in_head(), this.header_deadline.is_some()) {
+                (true, false) => {
+                    this.header_deadline =
+                        Some(Box::pin(tokio::time::sleep(this.header_read_timeout)));
+                }
+                (false, true) => this.header_deadline = None,
+                _ => (),
+            }
+        }
+        result
+    }
+}
+
+impl<Io: AsyncWrite + Unpin> AsyncWrite for LimitedStream<Io> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+    const MAX_HEADER_SIZE: usize = 1024;
+
+    #[tokio::test]
+    async fn slow_headers_time_out() {
+        let (mut client, server) = duplex(1024);
+        let mut stream = LimitedStream::new(server, None, TIMEOUT, MAX_HEADER_SIZE);
+
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\n";
+        client.write_all(head).await.unwrap();
+        let mut buf = [0; 1024];
+        stream.read_exact(&mut buf[..head.len()]).await.unwrap();
+
+        let error = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn complete_headers_disarm_the_timeout() {
+        let (mut client, server) = duplex(1024);
+        let mut stream = LimitedStream::new(server, None, TIMEOUT, MAX_HEADER_SIZE);
+
+        // Terminator split across reads
+        let head = b"GET / HTTP/1.1\r\nHost: x\r\n\r";
+        let mut buf = [0; 1024];
+        client.write_all(head).await.unwrap();
+        stream.read_exact(&mut buf[..head.len()]).await.unwrap();
+        client.write_all(b"\n").await.unwrap();
+        stream.read_exact(&mut buf[..1]).await.unwrap();
+
+        tokio::time::sleep(TIMEOUT * 2).await;
+        client.write_all(b"body").await.unwrap();
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn slow_headers_of_a_later_request_time_out() {
+        let (mut client, server) = duplex(1024);
+        let mut stream = LimitedStream::new(server, None, TIMEOUT, MAX_HEADER_SIZE);
+        let mut buf = [0; 1024];
+
+        let first = b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nbody";
+        client.write_all(first).await.unwrap();
+        stream.read_exact(&mut buf[..first.len()]).await.unwrap();
+
+        // An idle keep-alive connection is left alone
+        tokio::time::sleep(TIMEOUT * 2).await;
+
+        let second = b"GET / HTTP/1.1\r\nHost: x\r\n";
+        client.write_all(second).await.unwrap();
+        stream.read_exact(&mut buf[..second.len()]).await.unwrap();
+
+        let error = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn oversized_headers_are_rejected() {
+        let (mut client, server) = duplex(4096);
+        let mut stream = LimitedStream::new(server, None, TIMEOUT, MAX_HEADER_SIZE);
+
+        let head = format!("GET / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(2048));
+        client.write_all(head.as_bytes()).await.unwrap();
+        let mut buf = vec![0; 4096];
+        let error = stream.read_exact(&mut buf[..head.len()]).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn http2_frame(kind: u8, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags, 0, 0, 0, 1]);
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[tokio::test]
+    async fn incomplete_http2_header_block_times_out() {
+        let (mut client, server) = duplex(1024);
+        let mut stream = LimitedStream::new(server, None, TIMEOUT, MAX_HEADER_SIZE);
+        let mut buf = [0; 1024];
+
+        let mut data = HTTP2_PREFACE.to_vec();
+        data.extend(http2_frame(HTTP2_FRAME_HEADERS, 0, &[0; 16]));
+        client.write_all(&data).await.unwrap();
+        stream.read_exact(&mut buf[..data.len()]).await.unwrap();
+
+        let error = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn tracks_http1_request_framing() {
+        let mut tracker = RequestTracker::new(MAX_HEADER_SIZE);
+        assert!(tracker.in_head());
+
+        tracker
+            .feed(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .unwrap();
+        assert!(!tracker.in_head());
+        tracker
+            .feed(b"4;ext=1\r\nGET \r\n0\r\nX-Trailer: 1\r\n")
+            .unwrap();
+        assert!(!tracker.in_head());
+        tracker.feed(b"\r\n").unwrap();
+        assert!(!tracker.in_head());
+
+        tracker.feed(b"\r\nGET / HTTP/1.1\r\n").unwrap();
+        assert!(tracker.in_head());
+        tracker.feed(b"Upgrade: websocket\r\n\r\n").unwrap();
+        assert!(!tracker.in_head());
+
+        // Websocket frames are not HTTP
+        tracker.feed(b"GET / HTTP/1.1\r\n").unwrap();
+        assert!(!tracker.in_head());
+    }
+
+    #[test]
+    fn tracks_http2_header_blocks() {
+        let mut tracker = RequestTracker::new(MAX_HEADER_SIZE);
+        tracker.feed(HTTP2_PREFACE).unwrap();
+        assert!(!tracker.in_head());
+
+        tracker.feed(&http2_frame(0x0, 0, &[0; 2048])).unwrap();
+        assert!(!tracker.in_head());
+
+        tracker
+            .feed(&http2_frame(HTTP2_FRAME_HEADERS, 0, &[0; 512]))
+            .unwrap();
+        assert!(tracker.in_head());
+        tracker
+            .feed(&http2_frame(
+                HTTP2_FRAME_CONTINUATION,
+                HTTP2_FLAG_END_HEADERS,
+                &[0; 256],
+            ))
+            .unwrap();
+        assert!(!tracker.in_head());
+
+        tracker
+            .feed(&http2_frame(HTTP2_FRAME_HEADERS, 0, &[0; 512]))
+            .unwrap();
+        assert!(tracker
+            .feed(&http2_frame(HTTP2_FRAME_CONTINUATION, 0, &[0; 1024]))
+            .is_err());
+    }
+}
@@ -1,9 +1,32 @@
 use http::StatusCode;
 use poem::IntoResponse;
 use tracing::error;
+use warpgate_common::i18n::{Language, Message};
+use warpgate_common::{ErrorCode, WarpgateError};
 
-pub fn error_page(e: poem::Error) -> impl IntoResponse {
+pub fn error_page(e: poem::Error, language: Language) -> impl IntoResponse {
     error!("{:?}", e);
+    let code = e
+        .downcast_ref::<WarpgateError>()
+        .map(WarpgateError::code)
+        .unwrap_or(ErrorCode::TargetConnectionFailed);
+    message_page(Message::RequestFailed.text(language), &e.to_string(), code)
+        .with_status(StatusCode::BAD_GATEWAY)
+}
+
+/// Shown once to a user whose session an admin has closed
+pub fn session_closed_page(message: &str, language: Language) -> impl IntoResponse {
+    message_page(
+        Message::SessionClosedByAdmin.text(language),
+        message,
+        ErrorCode::SessionClosedByAdmin,
+    )
+    .with_status(StatusCode::FORBIDDEN)
+}
+
+fn message_page(title: &str, text: &str, code: ErrorCode) -> poem::web::Html<String> {
+    let title = html_escape(title);
+    let text = html_escape(text);
     poem::web::Html(format!(
         r#"<!DOCTYPE html>
         <style>
@@ -22,9 +45,17 @@ pub fn error_page(e: poem::Error) -> impl IntoResponse {
         </style>
         <main>
             <img src="/@warpgate/assets/brand.svg" />
-            <h1>Request failed</h1>
-            <p>{e}</p>
+            <h1>{title}</h1>
+            <p>{text}</p>
+            <p><code>{code}</code></p>
         </main>
         "#
-    )).with_status(StatusCode::BAD_GATEWAY)
+    ))
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
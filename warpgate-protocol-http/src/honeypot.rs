@@ -0,0 +1,27 @@
+use poem::{IntoResponse, Request, Response};
+
+const LOGIN_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>Sign in</title></head>
+<body>
+<h2>Sign in</h2>
+{error}
+<form method="post">
+<p><input name="username" placeholder="Username" autofocus></p>
+<p><input name="password" type="password" placeholder="Password"></p>
+<p><button type="submit">Sign in</button></p>
+</form>
+</body>
+</html>
+"#;
+
+/// Served instead of proxying to a honeypot target: a login form that
+/// never accepts anything.
+pub fn honeypot_response(req: &Request) -> Response {
+    let error = if req.method() == http::Method::POST {
+        r#"<p style="color: red">Invalid credentials</p>"#
+    } else {
+        ""
+    };
+    poem::web::Html(LOGIN_PAGE.replace("{error}", error)).into_response()
+}
@@ -1,10 +1,15 @@
+mod access_rules;
 pub mod api;
 mod catchall;
 mod common;
+mod connection_limits;
 mod error;
+mod honeypot;
 mod logging;
 mod middleware;
+mod oidc_provider;
 mod proxy;
+mod recording;
 mod session;
 mod session_handle;
 
@@ -20,7 +25,7 @@ use logging::{get_client_ip, log_request_error, log_request_result, span_for_req
 use poem::endpoint::{EmbeddedFileEndpoint, EmbeddedFilesEndpoint};
 use poem::listener::{Listener, RustlsConfig};
 use poem::middleware::SetHeader;
-use poem::session::{CookieConfig, MemoryStorage, ServerSession, Session};
+use poem::session::{CookieConfig, ServerSession, Session};
 use poem::web::Data;
 use poem::{Endpoint, EndpointExt, FromRequest, IntoEndpoint, IntoResponse, Route, Server};
 use poem_openapi::OpenApiService;
@@ -28,16 +33,24 @@ use tokio::sync::Mutex;
 use tracing::*;
 use warpgate_admin::admin_api_app;
 use warpgate_common::{
-    ListenEndpoint, Target, TargetOptions, TlsCertificateAndPrivateKey, TlsCertificateBundle,
-    TlsPrivateKey,
+    HttpSessionStorageKind, ListenEndpoint, Target, TargetOptions, TlsCertificateAndPrivateKey,
+    TlsCertificateBundle, TlsPrivateKey,
+};
+use warpgate_core::{
+    HttpSessionCipher, HttpSessionStorage, ProtocolServer, Services, TargetTestError,
 };
-use warpgate_core::{ProtocolServer, Services, TargetTestError};
 use warpgate_web::Assets;
 
-use crate::common::{endpoint_admin_auth, endpoint_auth, page_auth, SESSION_COOKIE_NAME};
-use crate::error::error_page;
-use crate::middleware::{CookieHostMiddleware, TicketMiddleware};
-use crate::session::{SessionStore, SharedSessionStorage};
+use crate::common::{
+    endpoint_admin_auth, endpoint_auth, page_auth, request_language, SESSION_COOKIE_NAME,
+};
+use crate::connection_limits::LimitedAcceptor;
+use crate::error::{error_page, session_closed_page};
+use crate::middleware::{
+    CookieHostMiddleware, RequestLimitsMiddleware, TicketMiddleware, TokenMiddleware,
+};
+use crate::oidc_provider::{oidc_provider_app, OidcProvider};
+use crate::session::{take_close_message, SessionStore, SharedSessionStorage};
 
 pub struct HTTPProtocolServer {
     services: Services,
@@ -51,8 +64,31 @@ impl HTTPProtocolServer {
     }
 }
 
-fn make_session_storage() -> SharedSessionStorage {
-    SharedSessionStorage(Arc::new(Mutex::new(Box::<MemoryStorage>::default())))
+async fn make_session_storage(services: &Services) -> Result<SharedSessionStorage> {
+    let config = services.config.load();
+    let http_config = &config.store.http;
+    let secret = match http_config.session_encryption_key {
+        Some(ref key) => key.expose_secret().as_bytes().to_vec(),
+        None if http_config.session_storage == HttpSessionStorageKind::Database => {
+            anyhow::bail!(
+                "`http.session_encryption_key` must be set when using `http.session_storage: database`"
+            );
+        }
+        None => {
+            let key_path = config.paths_relative_to.join(&http_config.key);
+            tokio::fs::read(&key_path).await.with_context(|| {
+                format!(
+                    "reading TLS private key from '{}' to derive the session encryption key",
+                    key_path.display()
+                )
+            })?
+        }
+    };
+    Ok(SharedSessionStorage(HttpSessionStorage::new(
+        http_config.session_storage,
+        HttpSessionCipher::new(&secret),
+        services.db.clone(),
+    )))
 }
 
 impl ProtocolServer for HTTPProtocolServer {
@@ -67,9 +103,11 @@ impl ProtocolServer for HTTPProtocolServer {
         let ui = api_service.swagger_ui();
         let spec = api_service.spec_endpoint();
 
-        let session_storage = make_session_storage();
+        let session_storage = make_session_storage(&self.services).await?;
+        let http_sessions = session_storage.0.clone();
         let session_store = SessionStore::new();
         let db = self.services.db.clone();
+        let read_db = self.services.read_db.clone();
 
         let cache_bust = || {
             SetHeader::new().overriding(
@@ -85,12 +123,14 @@ impl ProtocolServer for HTTPProtocolServer {
             )
         };
 
-        let (cookie_max_age, session_max_age) = {
-            let config = self.services.config.lock().await;
-            (
-                config.store.http.cookie_max_age,
-                config.store.http.session_max_age,
-            )
+        let http_config = self.services.config.load().store.http.clone();
+
+        let oidc_provider = match http_config.oidc_provider {
+            Some(ref options) => {
+                let config = self.services.config.load().as_ref().clone();
+                Some(Arc::new(OidcProvider::new(&config, options).await?))
+            }
+            None => None,
         };
 
         let app = Route::new()
@@ -98,8 +138,21 @@ impl ProtocolServer for HTTPProtocolServer {
                 "/@warpgate",
                 Route::new()
                     .nest("/api/swagger", ui)
+                    .at(
+                        "/api/shared-sessions/:id/recordings/:recording_id/cast",
+                        endpoint_auth(api::session_sharing::api_get_shared_recording_cast),
+                    )
+                    .at(
+                        "/api/shared-sessions/:id/recordings/:recording_id/stream",
+                        endpoint_auth(api::session_sharing::api_get_shared_recording_stream),
+                    )
+                    .at(
+                        "/api/targets/status/stream",
+                        endpoint_auth(api::targets_list::api_get_target_statuses_stream),
+                    )
                     .nest("/api", api_service.with(cache_bust()))
                     .nest("/api/openapi.json", spec)
+                    .nest("/oidc", oidc_provider_app(oidc_provider))
                     .nest_no_strip(
                         "/assets",
                         EmbeddedFilesEndpoint::<Assets>::new().with(cache_static()),
@@ -136,9 +189,10 @@ impl ProtocolServer for HTTPProtocolServer {
             .nest_no_strip(
                 "/",
                 page_auth(catchall::catchall_endpoint).around(move |ep, req| async move {
+                    let language = request_language(&req);
                     Ok(match ep.call(req).await {
                         Ok(response) => response.into_response(),
-                        Err(error) => error_page(error).into_response(),
+                        Err(error) => error_page(error, language).into_response(),
                     })
                 }),
             )
@@ -147,6 +201,13 @@ impl ProtocolServer for HTTPProtocolServer {
                     .await?
                     .clone();
 
+                let session = <&Session>::from_request_without_body(&req).await?;
+                if let Some(message) = take_close_message(session) {
+                    return Ok(
+                        session_closed_page(&message, request_language(&req)).into_response()
+                    );
+                }
+
                 let req = { sm.lock().await.process_request(req).await? };
 
                 let span = span_for_request(&req).await?;
@@ -158,28 +219,36 @@ impl ProtocolServer for HTTPProtocolServer {
                     .overriding(http::header::STRICT_TRANSPORT_SECURITY, "max-age=31536000"),
             )
             .with(TicketMiddleware::new())
+            .with(TokenMiddleware::new())
             .with(ServerSession::new(
                 CookieConfig::default()
                     .secure(false)
-                    .max_age(cookie_max_age)
+                    .max_age(http_config.cookie_max_age)
                     .name(SESSION_COOKIE_NAME),
                 session_storage.clone(),
             ))
             .with(CookieHostMiddleware::new())
+            .with(RequestLimitsMiddleware::new(http_config.max_body_size))
             .data(self.services.clone())
             .data(session_store.clone())
+            .data(http_sessions.clone())
             .data(session_storage)
-            .data(db);
+            .data(db)
+            .data(read_db);
 
+        let session_max_age = http_config.session_max_age;
         tokio::spawn(async move {
             loop {
                 session_store.lock().await.vacuum(session_max_age).await;
+                if let Err(error) = http_sessions.vacuum().await {
+                    error!(?error, "Failed to remove expired web sessions");
+                }
                 tokio::time::sleep(Duration::from_secs(60)).await;
             }
         });
 
         let certificate_and_key = {
-            let config = self.services.config.lock().await;
+            let config = self.services.config.load();
             let certificate_path = config
                 .paths_relative_to
                 .join(&config.store.http.certificate);
@@ -201,12 +270,19 @@ impl ProtocolServer for HTTPProtocolServer {
         };
 
         info!(?address, "Listening");
-        Server::new(
-            address
-                .poem_listener()
-                .await?
-                .rustls(RustlsConfig::new().fallback(certificate_and_key.into())),
-        )
+        let acceptor = address
+            .poem_listener(&http_config.socket)
+            .await?
+            .rustls(RustlsConfig::new().fallback(certificate_and_key.into()))
+            .into_acceptor()
+            .await?;
+        Server::new_with_acceptor(LimitedAcceptor::new(
+            acceptor,
+            http_config.header_read_timeout,
+            http_config.max_header_size,
+            http_config.max_connections_per_ip,
+            self.services.ip_bans.clone(),
+        ))
         .run(app)
         .await?;
 
@@ -214,6 +290,7 @@ impl ProtocolServer for HTTPProtocolServer {
     }
 
     async fn test_target(&self, target: Target) -> Result<(), TargetTestError> {
+        let resolver = self.services.dns.for_target(&target);
         let TargetOptions::Http(options) = target.options else {
             return Err(TargetTestError::Misconfigured(
                 "Not an HTTP target".to_owned(),
@@ -222,9 +299,20 @@ impl ProtocolServer for HTTPProtocolServer {
 
         let mut request = poem::Request::builder().uri_str("http://host/").finish();
         request.extensions_mut().insert(Session::default());
-        crate::proxy::proxy_normal_request(&request, poem::Body::empty(), &options)
-            .await
-            .map_err(|e| TargetTestError::ConnectionError(format!("{e}")))?;
+        crate::proxy::proxy_normal_request(
+            &request,
+            poem::Body::empty(),
+            &options,
+            &resolver,
+            None,
+            None,
+            Some(crate::proxy::CapabilitiesContext {
+                target_name: target.name.clone(),
+                store: self.services.target_capabilities.clone(),
+            }),
+        )
+        .await
+        .map_err(|e| TargetTestError::ConnectionError(format!("{e}")))?;
         Ok(())
     }
 }
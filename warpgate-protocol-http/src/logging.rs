@@ -14,15 +14,10 @@ pub async fn span_for_request(req: &Request) -> poem::Result<Span> {
     let client_ip = get_client_ip(req).await?;
 
     Ok(match handle {
+        // The session's own `client_ip` is the direct peer, which
+        // can differ from the forwarded one
         Ok(ref handle) => {
-            let handle = handle.lock().await;
-            let ss = handle.session_state().lock().await;
-            match ss.username.clone() {
-                Some(ref username) => {
-                    info_span!("HTTP", session=%handle.id(), session_username=%username, %client_ip)
-                }
-                None => info_span!("HTTP", session=%handle.id(), %client_ip),
-            }
+            info_span!(parent: handle.lock().await.context().span(), "HTTP", %client_ip)
         }
         Err(_) => info_span!("HTTP"),
     })
@@ -43,7 +38,7 @@ pub fn log_request_error<E: Error>(method: &Method, url: &Uri, client_ip: &str,
 pub async fn get_client_ip(req: &Request) -> poem::Result<String> {
     let services = Data::<&Services>::from_request_without_body(req).await.ok();
     let trust_x_forwarded_headers = if let Some(services) = services {
-        let config = services.config.lock().await;
+        let config = services.config.load();
         config.store.http.trust_x_forwarded_headers
     } else {
         false
@@ -1,9 +1,29 @@
 use http::header::Entry;
 use poem::web::cookie::Cookie;
 use poem::{Endpoint, IntoResponse, Middleware, Request, Response};
+use warpgate_common::{is_host_pattern, match_host_pattern, TargetOptions};
+use warpgate_core::{ConfigProvider, Services};
 
 use crate::common::SESSION_COOKIE_NAME;
 
+/// Hosts matched by a wildcard HTTP target get host-only cookies,
+/// so that every concrete host has a separate session
+async fn is_wildcard_target_host(services: Option<&Services>, host: &str) -> bool {
+    let Some(services) = services else {
+        return false;
+    };
+    let Ok(targets) = services.config_provider.lock().await.list_targets().await else {
+        return false;
+    };
+    targets.iter().any(|t| match t.options {
+        TargetOptions::Http(ref options) => options
+            .external_host
+            .as_deref()
+            .is_some_and(|p| is_host_pattern(p) && match_host_pattern(p, host).is_some()),
+        _ => false,
+    })
+}
+
 pub struct CookieHostMiddleware {}
 
 impl CookieHostMiddleware {
@@ -29,6 +49,7 @@ impl<E: Endpoint> Endpoint for CookieHostMiddlewareEndpoint<E> {
 
     async fn call(&self, req: Request) -> poem::Result<Self::Output> {
         let host = req.original_uri().host().map(|x| x.to_string());
+        let services = req.data::<Services>().cloned();
 
         let mut resp = self.inner.call(req).await?.into_response();
 
@@ -36,7 +57,9 @@ impl<E: Endpoint> Endpoint for CookieHostMiddlewareEndpoint<E> {
             if let Entry::Occupied(mut entry) = resp.headers_mut().entry(http::header::SET_COOKIE) {
                 if let Ok(cookie_str) = entry.get().to_str() {
                     if let Ok(mut cookie) = Cookie::parse(cookie_str) {
-                        if cookie.name() == SESSION_COOKIE_NAME {
+                        if cookie.name() == SESSION_COOKIE_NAME
+                            && !is_wildcard_target_host(services.as_ref(), &host).await
+                        {
                             cookie.set_domain(host);
                             if let Ok(value) = cookie.to_string().parse() {
                                 entry.insert(value);
@@ -1,5 +1,9 @@
 mod cookie_host;
+mod request_limits;
 mod ticket;
+mod token;
 
 pub use cookie_host::*;
+pub use request_limits::*;
 pub use ticket::*;
+pub use token::*;
@@ -0,0 +1,79 @@
+use std::io;
+
+use futures::StreamExt;
+use http::{HeaderMap, StatusCode};
+use poem::{Body, Endpoint, IntoResponse, Middleware, Request, Response};
+
+/// Enforces the body size limit. Header sizes are limited at the connection
+/// level by [crate::connection_limits::LimitedAcceptor], since the server
+/// has already buffered and parsed the headers by the time a middleware runs.
+pub struct RequestLimitsMiddleware {
+    max_body_size: Option<u64>,
+}
+
+impl RequestLimitsMiddleware {
+    pub fn new(max_body_size: Option<u64>) -> Self {
+        Self { max_body_size }
+    }
+}
+
+pub struct RequestLimitsMiddlewareEndpoint<E: Endpoint> {
+    inner: E,
+    max_body_size: Option<u64>,
+}
+
+impl<E: Endpoint> Middleware<E> for RequestLimitsMiddleware {
+    type Output = RequestLimitsMiddlewareEndpoint<E>;
+
+    fn transform(&self, inner: E) -> Self::Output {
+        RequestLimitsMiddlewareEndpoint {
+            inner,
+            max_body_size: self.max_body_size,
+        }
+    }
+}
+
+/// Returns `None` if the declared `Content-Length` already exceeds `max_size`.
+/// Bodies without one (chunked HTTP/1.1 or HTTP/2) are wrapped to fail once
+/// they grow past the limit, since the length is otherwise enforced by the
+/// HTTP server itself.
+pub fn limit_body(headers: &HeaderMap, body: Body, max_size: u64) -> Option<Body> {
+    let content_length = headers
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<u64>().ok());
+
+    match content_length {
+        Some(length) if length > max_size => None,
+        Some(_) => Some(body),
+        None => {
+            let mut size = 0u64;
+            Some(Body::from_bytes_stream(body.into_bytes_stream().map(
+                move |chunk| {
+                    let chunk = chunk?;
+                    size += chunk.len() as u64;
+                    if size > max_size {
+                        return Err(io::Error::other("request body too large"));
+                    }
+                    Ok(chunk)
+                },
+            )))
+        }
+    }
+}
+
+impl<E: Endpoint> Endpoint for RequestLimitsMiddlewareEndpoint<E> {
+    type Output = Response;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        if let Some(max_body_size) = self.max_body_size {
+            let body = req.take_body();
+            match limit_body(req.headers(), body, max_body_size) {
+                Some(body) => req.set_body(body),
+                None => return Ok(StatusCode::PAYLOAD_TOO_LARGE.into_response()),
+            }
+        }
+
+        Ok(self.inner.call(req).await?.into_response())
+    }
+}
@@ -0,0 +1,71 @@
+use poem::session::Session;
+use poem::web::{Data, FromRequest};
+use poem::{Endpoint, Middleware, Request};
+use tracing::*;
+use warpgate_core::{ConfigProvider, Services};
+
+use crate::common::{RequestAuthorization, SessionAuthorization, SessionExt, X_WARPGATE_TOKEN};
+
+/// Authenticates requests carrying a user API token in the
+/// `X-Warpgate-Token` header. The token's user and scope are attached to
+/// the request itself, so the session (and its cookie) is left untouched.
+pub struct TokenMiddleware {}
+
+impl TokenMiddleware {
+    pub fn new() -> Self {
+        TokenMiddleware {}
+    }
+}
+
+pub struct TokenMiddlewareEndpoint<E: Endpoint> {
+    inner: E,
+}
+
+impl<E: Endpoint> Middleware<E> for TokenMiddleware {
+    type Output = TokenMiddlewareEndpoint<E>;
+
+    fn transform(&self, inner: E) -> Self::Output {
+        TokenMiddlewareEndpoint { inner }
+    }
+}
+
+impl<E: Endpoint> Endpoint for TokenMiddlewareEndpoint<E> {
+    type Output = E::Output;
+
+    async fn call(&self, mut req: Request) -> poem::Result<Self::Output> {
+        let session = <&Session>::from_request_without_body(&req).await?;
+
+        if !session.is_authenticated() {
+            if let Some(token) = req
+                .headers()
+                .get(&X_WARPGATE_TOKEN)
+                .and_then(|x| x.to_str().ok())
+                .map(ToOwned::to_owned)
+            {
+                let services = Data::<&Services>::from_request_without_body(&req).await?;
+                let is_admin_token =
+                    Some(token.as_str()) == services.admin_token.lock().await.as_deref();
+
+                if !is_admin_token {
+                    let validated = services
+                        .config_provider
+                        .lock()
+                        .await
+                        .validate_api_token(&token)
+                        .await?;
+                    if let Some((user, scope)) = validated {
+                        debug!(username=%user.username, "Authenticated with an API token");
+                        req.set_data(RequestAuthorization::Session(
+                            SessionAuthorization::ApiToken {
+                                username: user.username,
+                                scope,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
+        self.inner.call(req).await
+    }
+}
@@ -0,0 +1,652 @@
+//! Warpgate as an OpenID Connect provider for other apps, using the
+//! authorization code flow. Users log in to Warpgate as usual and get
+//! sent back to the app with a code that it exchanges for an ID token.
+//! Codes are kept in the database and access tokens are signed JWTs,
+//! so both survive restarts and work across instances.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use data_encoding::{BASE64, BASE64URL_NOPAD, HEXLOWER};
+use http::StatusCode;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use poem::endpoint::BoxEndpoint;
+use poem::web::{Data, Form, Json, Query, Redirect};
+use poem::{handler, EndpointExt, IntoResponse, Request, Response, Route};
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256};
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tracing::*;
+use url::Url;
+use uuid::Uuid;
+use warpgate_common::helpers::fs::secure_file;
+use warpgate_common::helpers::hash::generate_ticket_secret;
+use warpgate_common::{OidcProviderClient, OidcProviderConfig, WarpgateConfig, WarpgateError};
+use warpgate_core::{ConfigProvider, Services};
+use warpgate_db_entities::OidcAuthorizationCode::{self, RoleNameList};
+
+use crate::common::{gateway_redirect, page_auth, RequestAuthorization, SessionAuthorization};
+
+const CODE_LIFETIME: Duration = Duration::from_secs(60);
+/// JWT `typ` of access tokens (RFC 9068), so that ID tokens can't be used as such
+const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+
+pub struct OidcProvider {
+    signing_key: EncodingKey,
+    verification_key: DecodingKey,
+    jwk: serde_json::Value,
+    key_id: String,
+}
+
+impl OidcProvider {
+    pub async fn new(
+        config: &WarpgateConfig,
+        options: &OidcProviderConfig,
+    ) -> anyhow::Result<Self> {
+        // The issuer must not depend on the Host header of the request
+        config
+            .construct_external_url(None, None)
+            .context("the OIDC provider requires `external_host` to be set")?;
+
+        let path = config.paths_relative_to.join(&options.signing_key);
+        let key = load_or_generate_key(&path)
+            .await
+            .with_context(|| format!("loading OIDC signing key from '{}'", path.display()))?;
+
+        let digest = Sha256::digest(key.public_key_raw());
+        let key_id = BASE64URL_NOPAD.encode(
+            digest
+                .first_chunk::<12>()
+                .context("the key digest is too short")?,
+        );
+        let jwk = ec_jwk(key.public_key_raw(), &key_id)?;
+        let signing_key = EncodingKey::from_ec_pem(key.serialize_pem().as_bytes())?;
+        let verification_key = DecodingKey::from_ec_components(
+            jwk["x"].as_str().unwrap_or_default(),
+            jwk["y"].as_str().unwrap_or_default(),
+        )?;
+
+        Ok(Self {
+            signing_key,
+            verification_key,
+            jwk,
+            key_id,
+        })
+    }
+}
+
+async fn load_or_generate_key(path: &Path) -> anyhow::Result<KeyPair> {
+    if tokio::fs::try_exists(path).await? {
+        let key = KeyPair::from_pem(&tokio::fs::read_to_string(path).await?)?;
+        if !key.is_compatible(&PKCS_ECDSA_P256_SHA256) {
+            anyhow::bail!("not an ECDSA P-256 key");
+        }
+        return Ok(key);
+    }
+
+    let key = KeyPair::generate(&PKCS_ECDSA_P256_SHA256)?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, key.serialize_pem()).await?;
+    secure_file(path)?;
+    info!(path=%path.display(), "Generated a new OIDC signing key");
+    Ok(key)
+}
+
+/// Public part of an uncompressed P-256 point as a JWK
+fn ec_jwk(public_key: &[u8], key_id: &str) -> anyhow::Result<serde_json::Value> {
+    let [0x04, point @ ..] = public_key else {
+        anyhow::bail!("unexpected public key format");
+    };
+    if point.len() != 64 {
+        anyhow::bail!("unexpected public key length");
+    }
+    let (x, y) = point.split_at(32);
+    Ok(json!({
+        "kty": "EC",
+        "crv": "P-256",
+        "x": BASE64URL_NOPAD.encode(x),
+        "y": BASE64URL_NOPAD.encode(y),
+        "use": "sig",
+        "alg": "ES256",
+        "kid": key_id,
+    }))
+}
+
+pub fn oidc_provider_app(provider: Option<Arc<OidcProvider>>) -> BoxEndpoint<'static> {
+    let Some(provider) = provider else {
+        return Route::new().boxed();
+    };
+    Route::new()
+        .at("/.well-known/openid-configuration", discovery)
+        .at("/jwks", jwks)
+        .at("/authorize", page_auth(authorize))
+        .at("/token", poem::post(token))
+        .at("/userinfo", poem::get(userinfo).post(userinfo))
+        .data(provider)
+        .boxed()
+}
+
+/// Always built from `external_host`, which [OidcProvider::new] requires
+fn issuer_url(services: &Services) -> Result<String, WarpgateError> {
+    let mut url = services.config.load().construct_external_url(None, None)?;
+    url.set_path("/@warpgate/oidc");
+    Ok(url.to_string())
+}
+
+fn find_client(services: &Services, client_id: &str) -> Option<OidcProviderClient> {
+    services
+        .config
+        .load()
+        .store
+        .http
+        .oidc_provider
+        .as_ref()?
+        .clients
+        .iter()
+        .find(|c| c.client_id == client_id)
+        .cloned()
+}
+
+fn token_lifetime(services: &Services) -> Duration {
+    services
+        .config
+        .load()
+        .store
+        .http
+        .oidc_provider
+        .as_ref()
+        .map(|x| x.token_lifetime)
+        .unwrap_or(Duration::from_secs(60 * 60))
+}
+
+#[handler]
+async fn discovery(services: Data<&Services>) -> poem::Result<Json<serde_json::Value>> {
+    let issuer = issuer_url(&services)?;
+    Ok(Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "userinfo_endpoint": format!("{issuer}/userinfo"),
+        "jwks_uri": format!("{issuer}/jwks"),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+        "scopes_supported": ["openid", "profile"],
+        "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+        "code_challenge_methods_supported": ["S256", "plain"],
+        "claims_supported": [
+            "iss", "sub", "aud", "exp", "iat", "auth_time", "nonce", "preferred_username", "roles",
+        ],
+    })))
+}
+
+#[handler]
+async fn jwks(provider: Data<&Arc<OidcProvider>>) -> Json<serde_json::Value> {
+    Json(json!({ "keys": [provider.jwk] }))
+}
+
+#[derive(Deserialize)]
+struct AuthorizeParams {
+    response_type: String,
+    client_id: String,
+    redirect_uri: String,
+    scope: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+fn redirect_with(redirect_uri: &str, params: &[(&str, Option<&str>)]) -> poem::Result<Response> {
+    let mut url = Url::parse(redirect_uri).map_err(poem::error::BadRequest)?;
+    {
+        let mut query = url.query_pairs_mut();
+        for (key, value) in params {
+            if let Some(value) = value {
+                query.append_pair(key, value);
+            }
+        }
+    }
+    Ok(Redirect::see_other(url.to_string()).into_response())
+}
+
+#[handler]
+async fn authorize(
+    req: &Request,
+    services: Data<&Services>,
+    auth: Data<&RequestAuthorization>,
+    Query(params): Query<AuthorizeParams>,
+) -> poem::Result<Response> {
+    // A client or a redirect URL that isn't registered doesn't get redirected to
+    let Some(client) = find_client(&services, &params.client_id) else {
+        return Err(poem::Error::from_string(
+            "Unknown client_id",
+            StatusCode::BAD_REQUEST,
+        ));
+    };
+    if !client.redirect_uris.contains(&params.redirect_uri) {
+        return Err(poem::Error::from_string(
+            "redirect_uri is not registered for this client",
+            StatusCode::BAD_REQUEST,
+        ));
+    }
+
+    let state = params.state.as_deref();
+    let error = |error: &str| {
+        redirect_with(
+            &params.redirect_uri,
+            &[("error", Some(error)), ("state", state)],
+        )
+    };
+
+    // Tickets and API tokens are narrowed down to targets, so only
+    // an interactive login counts
+    let RequestAuthorization::Session(SessionAuthorization::User(username)) = *auth else {
+        return Ok(gateway_redirect(req));
+    };
+
+    if params.response_type != "code" {
+        return error("unsupported_response_type");
+    }
+    if !params.scope.split(' ').any(|x| x == "openid") {
+        return error("invalid_scope");
+    }
+    let code_challenge = match (params.code_challenge, params.code_challenge_method) {
+        (Some(challenge), method) => {
+            let method = method.unwrap_or_else(|| "plain".into());
+            if method != "S256" && method != "plain" {
+                return error("invalid_request");
+            }
+            Some((challenge, method))
+        }
+        (None, _) => None,
+    };
+
+    let roles = services
+        .config_provider
+        .lock()
+        .await
+        .list_user_roles(username)
+        .await?;
+    if !client.allowed_roles.is_empty() && !roles.iter().any(|x| client.allowed_roles.contains(x)) {
+        warn!(
+            %username,
+            client=%client.client_id,
+            "OIDC login refused, the user has none of the allowed roles"
+        );
+        return error("access_denied");
+    }
+
+    let code = generate_ticket_secret().expose_secret().clone();
+    {
+        let db = services.db.lock().await;
+        let now = Utc::now();
+        OidcAuthorizationCode::Entity::delete_many()
+            .filter(OidcAuthorizationCode::Column::Expires.lte(now))
+            .exec(&*db)
+            .await
+            .map_err(WarpgateError::from)?;
+        let (code_challenge, code_challenge_method) = code_challenge.unzip();
+        OidcAuthorizationCode::ActiveModel {
+            id: Set(code_hash(&code)),
+            client_id: Set(client.client_id.clone()),
+            redirect_uri: Set(params.redirect_uri.clone()),
+            username: Set(username.clone()),
+            roles: Set(RoleNameList(roles)),
+            nonce: Set(params.nonce),
+            code_challenge: Set(code_challenge),
+            code_challenge_method: Set(code_challenge_method),
+            auth_time: Set(now),
+            expires: Set(now + CODE_LIFETIME),
+        }
+        .insert(&*db)
+        .await
+        .map_err(WarpgateError::from)?;
+    }
+    info!(%username, client=%client.client_id, "Authorized an OIDC login");
+
+    redirect_with(
+        &params.redirect_uri,
+        &[("code", Some(&code)), ("state", state)],
+    )
+}
+
+/// Codes are stored hashed so that a database dump can't be used to log in
+fn code_hash(code: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(code.as_bytes()))
+}
+
+/// Takes a code out of the database so that it can only be used once,
+/// even if the exchange fails
+async fn take_code(
+    services: &Services,
+    code: &str,
+) -> Result<Option<OidcAuthorizationCode::Model>, WarpgateError> {
+    let db = services.db.lock().await;
+    let id = code_hash(code);
+    let Some(authorization) = OidcAuthorizationCode::Entity::find_by_id(&id)
+        .one(&*db)
+        .await?
+    else {
+        return Ok(None);
+    };
+    // Another instance sharing the database could have taken it first
+    let result = OidcAuthorizationCode::Entity::delete_by_id(&id)
+        .exec(&*db)
+        .await?;
+    if result.rows_affected == 0 || authorization.expires <= Utc::now() {
+        return Ok(None);
+    }
+    Ok(Some(authorization))
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    grant_type: String,
+    code: String,
+    redirect_uri: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    code_verifier: Option<String>,
+}
+
+#[derive(Serialize)]
+struct IdTokenClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    exp: i64,
+    iat: i64,
+    auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nonce: Option<&'a str>,
+    preferred_username: &'a str,
+    roles: &'a [String],
+}
+
+#[derive(Serialize, Deserialize)]
+struct AccessTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    client_id: String,
+    exp: i64,
+    iat: i64,
+    jti: String,
+    roles: Vec<String>,
+}
+
+fn token_error(status: StatusCode, error: &str) -> Response {
+    Response::builder()
+        .status(status)
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .content_type("application/json")
+        .body(json!({ "error": error }).to_string())
+}
+
+/// Client credentials from either HTTP Basic authentication or the form
+fn client_credentials(req: &Request, form: &TokenRequest) -> Option<(String, String)> {
+    if let Some(header) = req.header(http::header::AUTHORIZATION) {
+        let encoded = header.strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(BASE64.decode(encoded.trim().as_bytes()).ok()?).ok()?;
+        let (id, secret) = decoded.split_once(':')?;
+        let decode = |x: &str| {
+            percent_encoding::percent_decode_str(&x.replace('+', " "))
+                .decode_utf8()
+                .ok()
+                .map(|x| x.into_owned())
+        };
+        return Some((decode(id)?, decode(secret)?));
+    }
+    Some((form.client_id.clone()?, form.client_secret.clone()?))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn verify_code_challenge(challenge: &str, method: &str, verifier: &str) -> bool {
+    let expected = match method {
+        "S256" => BASE64URL_NOPAD.encode(&Sha256::digest(verifier.as_bytes())),
+        _ => verifier.to_owned(),
+    };
+    constant_time_eq(expected.as_bytes(), challenge.as_bytes())
+}
+
+#[handler]
+async fn token(
+    req: &Request,
+    services: Data<&Services>,
+    provider: Data<&Arc<OidcProvider>>,
+    Form(form): Form<TokenRequest>,
+) -> poem::Result<Response> {
+    let Some((client_id, client_secret)) = client_credentials(req, &form) else {
+        return Ok(token_error(StatusCode::UNAUTHORIZED, "invalid_client"));
+    };
+    let Some(client) = find_client(&services, &client_id).filter(|client| {
+        constant_time_eq(
+            client.client_secret.expose_secret().as_bytes(),
+            client_secret.as_bytes(),
+        )
+    }) else {
+        warn!(client=%client_id, "OIDC client authentication failed");
+        return Ok(token_error(StatusCode::UNAUTHORIZED, "invalid_client"));
+    };
+
+    if form.grant_type != "authorization_code" {
+        return Ok(token_error(
+            StatusCode::BAD_REQUEST,
+            "unsupported_grant_type",
+        ));
+    }
+
+    let Some(authorization) = take_code(&services, &form.code).await? else {
+        return Ok(token_error(StatusCode::BAD_REQUEST, "invalid_grant"));
+    };
+    if authorization.client_id != client.client_id
+        || authorization.redirect_uri != form.redirect_uri
+    {
+        return Ok(token_error(StatusCode::BAD_REQUEST, "invalid_grant"));
+    }
+    if let Some(ref challenge) = authorization.code_challenge {
+        let method = authorization
+            .code_challenge_method
+            .as_deref()
+            .unwrap_or("plain");
+        match form.code_verifier {
+            Some(ref verifier) if verify_code_challenge(challenge, method, verifier) => (),
+            _ => return Ok(token_error(StatusCode::BAD_REQUEST, "invalid_grant")),
+        }
+    }
+
+    let lifetime = token_lifetime(&services);
+    let issuer = issuer_url(&services)?;
+    let now = Utc::now().timestamp();
+    let claims = IdTokenClaims {
+        iss: &issuer,
+        sub: &authorization.username,
+        aud: &client.client_id,
+        exp: now + lifetime.as_secs() as i64,
+        iat: now,
+        auth_time: authorization.auth_time.timestamp(),
+        nonce: authorization.nonce.as_deref(),
+        preferred_username: &authorization.username,
+        roles: &authorization.roles.0,
+    };
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = Some(provider.key_id.clone());
+    let id_token = jsonwebtoken::encode(&header, &claims, &provider.signing_key)
+        .map_err(poem::error::InternalServerError)?;
+
+    header.typ = Some(ACCESS_TOKEN_TYPE.into());
+    let access_token = jsonwebtoken::encode(
+        &header,
+        &AccessTokenClaims {
+            iss: issuer.clone(),
+            sub: authorization.username.clone(),
+            aud: client.client_id.clone(),
+            client_id: client.client_id.clone(),
+            exp: claims.exp,
+            iat: now,
+            jti: Uuid::new_v4().to_string(),
+            roles: authorization.roles.0.clone(),
+        },
+        &provider.signing_key,
+    )
+    .map_err(poem::error::InternalServerError)?;
+    info!(
+        username=%authorization.username,
+        client=%client.client_id,
+        "Issued an OIDC ID token"
+    );
+
+    Ok(Response::builder()
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .content_type("application/json")
+        .body(
+            json!({
+                "access_token": access_token,
+                "token_type": "Bearer",
+                "expires_in": lifetime.as_secs(),
+                "id_token": id_token,
+            })
+            .to_string(),
+        ))
+}
+
+fn verify_access_token(
+    provider: &OidcProvider,
+    issuer: &str,
+    access_token: &str,
+) -> Option<AccessTokenClaims> {
+    let mut validation = Validation::new(Algorithm::ES256);
+    validation.set_issuer(&[issuer]);
+    // Any of the clients may call the userinfo endpoint, so `aud` is not checked
+    let data = jsonwebtoken::decode::<AccessTokenClaims>(
+        access_token,
+        &provider.verification_key,
+        &validation,
+    )
+    .ok()?;
+    (data.header.typ.as_deref() == Some(ACCESS_TOKEN_TYPE)).then_some(data.claims)
+}
+
+#[handler]
+async fn userinfo(
+    req: &Request,
+    services: Data<&Services>,
+    provider: Data<&Arc<OidcProvider>>,
+) -> poem::Result<Response> {
+    let issuer = issuer_url(&services)?;
+    let Some(claims) = req
+        .header(http::header::AUTHORIZATION)
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .and_then(|x| verify_access_token(&provider, &issuer, x.trim()))
+    else {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(
+                http::header::WWW_AUTHENTICATE,
+                r#"Bearer error="invalid_token""#,
+            )
+            .finish());
+    };
+    Ok(Json(json!({
+        "sub": claims.sub,
+        "preferred_username": claims.sub,
+        "roles": claims.roles,
+    }))
+    .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_challenge() {
+        // From RFC 7636, appendix B
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(verify_code_challenge(
+            challenge,
+            "S256",
+            "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"
+        ));
+        assert!(!verify_code_challenge(challenge, "S256", "wrong"));
+        assert!(verify_code_challenge("abc", "plain", "abc"));
+    }
+
+    #[test]
+    fn test_access_tokens() {
+        let key = KeyPair::generate(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let jwk = ec_jwk(key.public_key_raw(), "kid").unwrap();
+        let provider = OidcProvider {
+            signing_key: EncodingKey::from_ec_pem(key.serialize_pem().as_bytes()).unwrap(),
+            verification_key: DecodingKey::from_ec_components(
+                jwk["x"].as_str().unwrap(),
+                jwk["y"].as_str().unwrap(),
+            )
+            .unwrap(),
+            jwk,
+            key_id: "kid".into(),
+        };
+        let now = Utc::now().timestamp();
+        let sign = |typ: Option<&str>, exp: i64| {
+            let mut header = Header::new(Algorithm::ES256);
+            header.typ = typ.map(Into::into);
+            let claims = AccessTokenClaims {
+                iss: "https://wg/@warpgate/oidc".into(),
+                sub: "user".into(),
+                aud: "app".into(),
+                client_id: "app".into(),
+                exp,
+                iat: now,
+                jti: "x".into(),
+                roles: vec!["role".into()],
+            };
+            jsonwebtoken::encode(&header, &claims, &provider.signing_key).unwrap()
+        };
+
+        let access_token = sign(Some(ACCESS_TOKEN_TYPE), now + 60);
+        let claims =
+            verify_access_token(&provider, "https://wg/@warpgate/oidc", &access_token).unwrap();
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.roles, vec!["role".to_owned()]);
+
+        assert!(
+            verify_access_token(&provider, "https://other/@warpgate/oidc", &access_token).is_none()
+        );
+        // ID tokens are plain JWTs
+        let id_token = sign(Some("JWT"), now + 60);
+        assert!(verify_access_token(&provider, "https://wg/@warpgate/oidc", &id_token).is_none());
+        let expired = sign(Some(ACCESS_TOKEN_TYPE), now - 3600);
+        assert!(verify_access_token(&provider, "https://wg/@warpgate/oidc", &expired).is_none());
+        let mut tampered = access_token.clone();
+        tampered.pop();
+        assert!(verify_access_token(&provider, "https://wg/@warpgate/oidc", &tampered).is_none());
+    }
+
+    #[test]
+    fn test_ec_jwk() {
+        let key = KeyPair::generate(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let jwk = ec_jwk(key.public_key_raw(), "kid").unwrap();
+        assert_eq!(jwk["kty"], "EC");
+        assert_eq!(
+            BASE64URL_NOPAD
+                .decode(jwk["x"].as_str().unwrap().as_bytes())
+                .unwrap()
+                .len(),
+            32
+        );
+        assert!(ec_jwk(&[0x04, 1, 2], "kid").is_err());
+        EncodingKey::from_ec_pem(key.serialize_pem().as_bytes()).unwrap();
+    }
+}
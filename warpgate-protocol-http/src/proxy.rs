@@ -1,26 +1,40 @@
 use std::borrow::Cow;
 use std::collections::HashSet;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
+use bytes::{Bytes, BytesMut};
 use cookie::Cookie;
 use delegate::delegate;
-use futures::{SinkExt, StreamExt, TryStreamExt};
+use futures::{SinkExt, Stream, StreamExt, TryStreamExt};
 use http::header::HeaderName;
 use http::uri::{Authority, Scheme};
 use http::Uri;
 use once_cell::sync::Lazy;
-use poem::session::Session;
 use poem::web::websocket::{Message, WebSocket};
-use poem::{Body, FromRequest, IntoResponse, Request, Response};
-use tokio_tungstenite::{connect_async_with_config, tungstenite};
+use poem::{Body, IntoResponse, Request, Response};
+use tokio_tungstenite::{client_async_tls_with_config, tungstenite, Connector};
 use tracing::*;
 use url::Url;
-use warpgate_common::{try_block, TargetHTTPOptions, TlsMode, WarpgateError};
+use warpgate_common::{
+    configure_tls_connector, try_block, TargetHTTPOptions, TargetHttpShadow, TlsMode, WarpgateError,
+};
+use warpgate_core::{
+    http_client_builder, NegotiatedCapabilities, ShadowComparison, ShadowReports,
+    TargetCapabilities, TargetResolver,
+};
 use warpgate_web::lookup_built_file;
 
-use crate::common::{SessionAuthorization, SessionExt};
+use crate::common::{
+    RequestAuthorization, SessionAuthorization, X_WARPGATE_TOKEN, X_WARPGATE_WORK_ITEM,
+};
 use crate::logging::{get_client_ip, log_request_result};
+use crate::middleware::limit_body;
+use crate::recording::{ExchangeRecording, RecordingContext};
+
+const SHADOW_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
 static X_WARPGATE_USERNAME: HeaderName = HeaderName::from_static("x-warpgate-username");
 static X_WARPGATE_AUTHENTICATION_TYPE: HeaderName =
@@ -82,6 +96,8 @@ static DONT_FORWARD_HEADERS: Lazy<HashSet<HeaderName>> = Lazy::new(|| {
     s.insert(http::header::CONNECTION);
     s.insert(http::header::STRICT_TRANSPORT_SECURITY);
     s.insert(http::header::UPGRADE_INSECURE_REQUESTS);
+    s.insert(X_WARPGATE_TOKEN.clone());
+    s.insert(X_WARPGATE_WORK_ITEM.clone());
     s
 });
 
@@ -234,8 +250,7 @@ fn inject_forwarding_headers<B: SomeRequestBuilder>(req: &Request, mut target: B
 }
 
 async fn inject_own_headers<B: SomeRequestBuilder>(req: &Request, mut target: B) -> Result<B> {
-    let session = <&Session>::from_request_without_body(req).await?;
-    if let Some(auth) = session.get_auth() {
+    if let Some(RequestAuthorization::Session(auth)) = req.data::<RequestAuthorization>() {
         target = target
             .header(&X_WARPGATE_USERNAME, auth.username().into())
             .header(
@@ -243,6 +258,7 @@ async fn inject_own_headers<B: SomeRequestBuilder>(req: &Request, mut target: B)
                 match auth {
                     SessionAuthorization::Ticket { .. } => "ticket",
                     SessionAuthorization::User { .. } => "user",
+                    SessionAuthorization::ApiToken { .. } => "token",
                 }
                 .into(),
             );
@@ -250,16 +266,13 @@ async fn inject_own_headers<B: SomeRequestBuilder>(req: &Request, mut target: B)
     Ok(target)
 }
 
-pub async fn proxy_normal_request(
-    req: &Request,
-    body: Body,
+fn build_client(
     options: &TargetHTTPOptions,
-) -> poem::Result<Response> {
-    let uri = construct_uri(req, options, false)?;
-
-    tracing::debug!("URI: {:?}", uri);
-
-    let mut client = reqwest::Client::builder()
+    resolver: &TargetResolver,
+    uri: &Uri,
+) -> Result<reqwest::Client> {
+    let mut client = http_client_builder()
+        .dns_resolver(Arc::new(resolver.clone()))
         .redirect(reqwest::redirect::Policy::none())
         .connection_verbose(true);
 
@@ -287,7 +300,138 @@ pub async fn proxy_normal_request(
         client = client.danger_accept_invalid_certs(true);
     }
 
-    let client = client.build().context("Could not build request")?;
+    client.build().context("Could not build request")
+}
+
+/// Where to report how the shadow upstream answered
+pub struct ShadowContext {
+    pub target_name: String,
+    pub reports: Arc<ShadowReports>,
+}
+
+/// Where to report the HTTP version the target responded with
+pub struct CapabilitiesContext {
+    pub target_name: String,
+    pub store: Arc<TargetCapabilities>,
+}
+
+async fn build_shadow_request(
+    req: &Request,
+    options: &TargetHTTPOptions,
+    resolver: &TargetResolver,
+    shadow: &TargetHttpShadow,
+) -> Result<(reqwest::Client, reqwest::Request)> {
+    let options = TargetHTTPOptions {
+        url: shadow.url.clone(),
+        tls: shadow.tls.clone(),
+        record_bodies: None,
+        ..options.clone()
+    };
+    let uri = construct_uri(req, &options, false)?;
+    let client = build_client(&options, resolver, &uri)?;
+
+    let mut client_request = client.request(req.method().into(), uri.to_string());
+    client_request = copy_server_request(req, client_request);
+    client_request = inject_forwarding_headers(req, client_request)?;
+    client_request = inject_own_headers(req, client_request).await?;
+    client_request = rewrite_request(client_request, &options)?;
+    client_request = client_request.timeout(SHADOW_REQUEST_TIMEOUT).header(
+        http::header::HOST,
+        uri.authority()
+            .context("No authority in the URL")?
+            .to_string(),
+    );
+    let mut client_request = client_request.build().context("Could not build request")?;
+    strip_credentials(client_request.headers_mut());
+    Ok((client, client_request))
+}
+
+/// The shadow upstream is a different server, and it must not be handed
+/// the user's or the primary target's credentials
+fn strip_credentials(headers: &mut http::HeaderMap) {
+    for header in [
+        http::header::AUTHORIZATION,
+        http::header::PROXY_AUTHORIZATION,
+        http::header::COOKIE,
+    ] {
+        headers.remove(header);
+    }
+}
+
+async fn run_shadow_request(
+    client: reqwest::Client,
+    request: reqwest::Request,
+    primary_status: http::StatusCode,
+    primary_latency: Duration,
+    context: ShadowContext,
+) {
+    let description = format!("{} {}", request.method(), request.url().path());
+    let started = Instant::now();
+    let shadow = client
+        .execute(request)
+        .await
+        .map(|response| response.status().as_u16().to_string())
+        .map_err(|error| error.to_string());
+    context.reports.record(
+        &context.target_name,
+        ShadowComparison {
+            request: description,
+            primary: primary_status.as_u16().to_string(),
+            shadow,
+            primary_latency,
+            shadow_latency: started.elapsed(),
+        },
+    );
+}
+
+pub async fn proxy_normal_request(
+    req: &Request,
+    body: Body,
+    options: &TargetHTTPOptions,
+    resolver: &TargetResolver,
+    shadow: Option<ShadowContext>,
+    recording: Option<RecordingContext>,
+    capabilities: Option<CapabilitiesContext>,
+) -> poem::Result<Response> {
+    let uri = construct_uri(req, options, false)?;
+
+    tracing::debug!("URI: {:?}", uri);
+
+    let body = match options.max_body_size {
+        Some(max_body_size) => match limit_body(req.headers(), body, max_body_size) {
+            Some(body) => body,
+            None => return Ok(http::StatusCode::PAYLOAD_TOO_LARGE.into_response()),
+        },
+        None => body,
+    };
+
+    let mut recording = match (&options.record_bodies, recording) {
+        (Some(record_bodies), Some(context)) => Some(ExchangeRecording::new(
+            context,
+            record_bodies,
+            req.method(),
+            req.original_uri(),
+        )),
+        _ => None,
+    };
+
+    // Only requests that are safe to repeat are sent to the shadow
+    let shadow = match (&options.shadow, shadow) {
+        (Some(shadow_options), Some(context))
+            if matches!(*req.method(), http::Method::GET | http::Method::HEAD) =>
+        {
+            match build_shadow_request(req, options, resolver, shadow_options).await {
+                Ok(request) => Some((request, context)),
+                Err(error) => {
+                    warn!(%error, "Could not build shadow request");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let client = build_client(options, resolver, &uri)?;
 
     let mut client_request = client.request(req.method().into(), uri.to_string());
 
@@ -295,7 +439,12 @@ pub async fn proxy_normal_request(
     client_request = inject_forwarding_headers(req, client_request)?;
     client_request = inject_own_headers(req, client_request).await?;
     client_request = rewrite_request(client_request, options)?;
-    client_request = client_request.body(reqwest::Body::wrap_stream(body.into_bytes_stream()));
+    client_request = match recording {
+        Some(ref recording) => client_request.body(reqwest::Body::wrap_stream(
+            recording.capture_request(body.into_bytes_stream()),
+        )),
+        None => client_request.body(reqwest::Body::wrap_stream(body.into_bytes_stream())),
+    };
     client_request = client_request.header(
         http::header::HOST,
         uri.authority()
@@ -304,16 +453,38 @@ pub async fn proxy_normal_request(
     );
 
     let client_request = client_request.build().context("Could not build request")?;
+    let started = Instant::now();
     let client_response = client
         .execute(client_request)
         .await
         .map_err(|e| anyhow::anyhow!("Could not execute request: {e}"))?;
     let status = client_response.status();
+    if let Some(context) = capabilities {
+        let mut negotiated = NegotiatedCapabilities::new();
+        negotiated.http_version = Some(format!("{:?}", client_response.version()));
+        context.store.record(&context.target_name, negotiated);
+    }
+    if let Some(ref mut recording) = recording {
+        recording.set_status(status);
+    }
+
+    if let Some(((shadow_client, shadow_request), context)) = shadow {
+        tokio::spawn(
+            run_shadow_request(
+                shadow_client,
+                shadow_request,
+                status,
+                started.elapsed(),
+                context,
+            )
+            .in_current_span(),
+        );
+    }
 
     let mut response: Response = "".into();
 
     copy_client_response(&client_response, &mut response);
-    copy_client_body(client_response, &mut response).await?;
+    copy_client_body(client_response, &mut response, recording).await?;
 
     log_request_result(
         req.method(),
@@ -329,28 +500,34 @@ pub async fn proxy_normal_request(
 async fn copy_client_body(
     client_response: reqwest::Response,
     response: &mut Response,
+    recording: Option<ExchangeRecording>,
 ) -> Result<()> {
+    let body = client_response
+        .bytes_stream()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e));
+    let body = match recording {
+        Some(recording) => recording.capture_response(body).boxed(),
+        None => body.boxed(),
+    };
+
     if response.content_type().map(|c| c.starts_with("text/html")) == Some(true)
         && response.status() == 200
+        && !response
+            .headers()
+            .contains_key(http::header::CONTENT_ENCODING)
     {
-        copy_client_body_and_embed(client_response, response).await?;
+        copy_client_body_and_embed(body, response)?;
         return Ok(());
     }
 
-    response.set_body(Body::from_bytes_stream(
-        client_response
-            .bytes_stream()
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
-    ));
+    response.set_body(Body::from_bytes_stream(body));
     Ok(())
 }
 
-async fn copy_client_body_and_embed(
-    client_response: reqwest::Response,
-    response: &mut Response,
-) -> Result<()> {
-    let content = client_response.text().await?;
-
+fn copy_client_body_and_embed<S>(body: S, response: &mut Response) -> Result<()>
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
     let script_manifest = lookup_built_file("src/embed/index.ts")?;
 
     let mut inject = format!(
@@ -361,32 +538,81 @@ async fn copy_client_body_and_embed(
         inject += &format!(r#"<link rel="stylesheet" href="/@warpgate/{css_file}" />"#,);
     }
 
-    let before = "</head>";
-    let content = content.replacen(before, &format!("{inject}{before}"), 1);
-
     response.headers_mut().remove(http::header::CONTENT_LENGTH);
-    response
-        .headers_mut()
-        .remove(http::header::CONTENT_ENCODING);
-    response.headers_mut().remove(http::header::CONTENT_TYPE);
     response
         .headers_mut()
         .remove(http::header::TRANSFER_ENCODING);
-    response.headers_mut().insert(
-        http::header::CONTENT_TYPE,
-        "text/html; charset=utf-8".parse()?,
-    );
-    response.set_body(content);
+
+    let injector = HeadInjector::new(inject.into());
+    response.set_body(Body::from_bytes_stream(futures::stream::unfold(
+        Some((Box::pin(body), injector)),
+        |state| async move {
+            let (mut body, mut injector) = state?;
+            match body.next().await {
+                Some(Ok(chunk)) => Some((Ok(injector.feed(chunk)), Some((body, injector)))),
+                Some(Err(error)) => Some((Err(error), None)),
+                None => Some((Ok(injector.finish()), None)),
+            }
+        },
+    )));
     Ok(())
 }
 
+const HEAD_END: &[u8] = b"</head>";
+
+/// Inserts a snippet before the first `</head>` of a streamed HTML document
+/// while only holding on to enough bytes to catch a tag split across chunks.
+struct HeadInjector {
+    inject: Option<Bytes>,
+    carry: BytesMut,
+}
+
+impl HeadInjector {
+    fn new(inject: Bytes) -> Self {
+        Self {
+            inject: Some(inject),
+            carry: BytesMut::new(),
+        }
+    }
+
+    fn feed(&mut self, chunk: Bytes) -> Bytes {
+        let Some(inject) = &self.inject else {
+            return chunk;
+        };
+
+        self.carry.extend_from_slice(&chunk);
+        if let Some(pos) = self
+            .carry
+            .windows(HEAD_END.len())
+            .position(|w| w == HEAD_END)
+        {
+            let head = self.carry.split_to(pos);
+            let tail = self.carry.split();
+            let mut output = BytesMut::with_capacity(head.len() + inject.len() + tail.len());
+            output.extend_from_slice(&head);
+            output.extend_from_slice(inject);
+            output.extend_from_slice(&tail);
+            self.inject = None;
+            return output.freeze();
+        }
+
+        let keep = (HEAD_END.len() - 1).min(self.carry.len());
+        self.carry.split_to(self.carry.len() - keep).freeze()
+    }
+
+    fn finish(&mut self) -> Bytes {
+        self.carry.split().freeze()
+    }
+}
+
 pub async fn proxy_websocket_request(
     req: &Request,
     ws: WebSocket,
     options: &TargetHTTPOptions,
+    resolver: &TargetResolver,
 ) -> poem::Result<impl IntoResponse> {
     let uri = construct_uri(req, options, true)?;
-    proxy_ws_inner(req, ws, uri.clone(), options)
+    proxy_ws_inner(req, ws, uri.clone(), options, resolver)
         .await
         .map_err(|error| {
             tracing::error!(?uri, ?error, "WebSocket proxy failed");
@@ -399,6 +625,7 @@ async fn proxy_ws_inner(
     ws: WebSocket,
     uri: Uri,
     options: &TargetHTTPOptions,
+    resolver: &TargetResolver,
 ) -> poem::Result<impl IntoResponse> {
     let mut client_request = http::request::Builder::new()
         .uri(uri.clone())
@@ -421,12 +648,26 @@ async fn proxy_ws_inner(
     client_request = inject_own_headers(req, client_request).await?;
     client_request = rewrite_request(client_request, options)?;
 
-    let (client, client_response) = connect_async_with_config(
+    let tls_config = configure_tls_connector(!options.tls.verify, false, None)
+        .await
+        .map_err(poem::error::InternalServerError)?;
+    let host = uri.host().ok_or(WarpgateError::NoHostInUrl)?;
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("wss") => 443,
+        _ => 80,
+    });
+    let stream = resolver
+        .connect(host, port)
+        .await
+        .map_err(poem::error::BadGateway)?;
+    stream.set_nodelay(true).map_err(poem::error::BadGateway)?;
+    let (client, client_response) = client_async_tls_with_config(
         client_request
             .body(())
             .map_err(poem::error::InternalServerError)?,
+        stream,
         None,
-        true,
+        Some(Connector::Rustls(Arc::new(tls_config))),
     )
     .await
     .map_err(poem::error::BadGateway)?;
@@ -516,3 +757,47 @@ async fn proxy_ws_inner(
     rewrite_response(&mut response, options, &uri)?;
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(chunks: &[&str]) -> String {
+        let mut injector = HeadInjector::new(Bytes::from_static(b"<x>"));
+        let mut output = chunks
+            .iter()
+            .map(|c| injector.feed(Bytes::copy_from_slice(c.as_bytes())))
+            .collect::<Vec<_>>();
+        output.push(injector.finish());
+        String::from_utf8(output.concat()).unwrap()
+    }
+
+    #[test]
+    fn injects_before_head_end() {
+        assert_eq!(
+            run(&["<html><head></head><body></head></body>"]),
+            "<html><head><x></head><body></head></body>"
+        );
+        assert_eq!(
+            run(&["<head>", "</he", "ad>", "<body>"]),
+            "<head><x></head><body>"
+        );
+        assert_eq!(run(&["<body>", "</bo", "dy>"]), "<body></body>");
+        assert_eq!(run(&["</h"]), "</h");
+    }
+
+    #[test]
+    fn test_strip_credentials() {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(http::header::AUTHORIZATION, "Bearer x".parse().unwrap());
+        headers.insert(
+            http::header::PROXY_AUTHORIZATION,
+            "Basic x".parse().unwrap(),
+        );
+        headers.insert(http::header::COOKIE, "session=x".parse().unwrap());
+        headers.insert(http::header::ACCEPT, "text/html".parse().unwrap());
+        strip_credentials(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(http::header::ACCEPT));
+    }
+}
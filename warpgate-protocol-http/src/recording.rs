@@ -0,0 +1,217 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use regex::bytes::Regex;
+use tracing::*;
+use warpgate_common::TargetHttpBodyRecording;
+use warpgate_core::recordings::{HttpRecordedBody, HttpRecorder, HttpRecordingItem};
+
+const REDACTED: &[u8] = b"[REDACTED]";
+
+/// Where to record the bodies of proxied requests
+pub struct RecordingContext {
+    pub target_name: String,
+    pub recorder: HttpRecorder,
+}
+
+pub struct Redactor {
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> Self {
+        Self {
+            patterns: patterns
+                .iter()
+                .filter_map(|pattern| match Regex::new(pattern) {
+                    Ok(regex) => Some(regex),
+                    Err(error) => {
+                        warn!(%error, %pattern, "Ignoring an invalid redaction pattern");
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    pub fn redact(&self, data: &[u8]) -> Bytes {
+        let mut data = data.to_vec();
+        for pattern in &self.patterns {
+            let group = if pattern.captures_len() > 1 { 1 } else { 0 };
+            let mut output = Vec::with_capacity(data.len());
+            let mut last = 0;
+            for captures in pattern.captures_iter(&data) {
+                let Some(secret) = captures.get(group).filter(|m| !m.is_empty()) else {
+                    continue;
+                };
+                output.extend_from_slice(data.get(last..secret.start()).unwrap_or_default());
+                output.extend_from_slice(REDACTED);
+                last = secret.end();
+            }
+            output.extend_from_slice(data.get(last..).unwrap_or_default());
+            data = output;
+        }
+        data.into()
+    }
+}
+
+#[derive(Default)]
+struct BodyCapture {
+    data: BytesMut,
+    size: u64,
+}
+
+impl BodyCapture {
+    fn feed(&mut self, chunk: &[u8], limit: usize) {
+        self.size += chunk.len() as u64;
+        let room = limit.saturating_sub(self.data.len()).min(chunk.len());
+        self.data
+            .extend_from_slice(chunk.get(..room).unwrap_or_default());
+    }
+}
+
+fn capture_into<S>(
+    body: S,
+    capture: Arc<Mutex<BodyCapture>>,
+    limit: usize,
+) -> impl Stream<Item = std::io::Result<Bytes>>
+where
+    S: Stream<Item = std::io::Result<Bytes>>,
+{
+    body.inspect(move |chunk| {
+        if let (Ok(chunk), Ok(mut capture)) = (chunk, capture.lock()) {
+            capture.feed(chunk, limit);
+        }
+    })
+}
+
+/// Keeps the start of both bodies of a request as they stream through
+/// and writes them into the recording once the response is done with,
+/// whether it was read to the end or not
+pub struct ExchangeRecording {
+    recorder: HttpRecorder,
+    redactor: Redactor,
+    limit: usize,
+    time: f32,
+    target: String,
+    method: String,
+    uri: String,
+    status: Option<u16>,
+    request_body: Arc<Mutex<BodyCapture>>,
+    response_body: BodyCapture,
+}
+
+impl ExchangeRecording {
+    pub fn new(
+        context: RecordingContext,
+        options: &TargetHttpBodyRecording,
+        method: &http::Method,
+        uri: &http::Uri,
+    ) -> Self {
+        let redactor = Redactor::new(&options.redact);
+        let uri = String::from_utf8_lossy(&redactor.redact(uri.to_string().as_bytes())).into();
+        Self {
+            time: context.recorder.get_time(),
+            recorder: context.recorder,
+            redactor,
+            limit: options.max_size,
+            target: context.target_name,
+            method: method.to_string(),
+            uri,
+            status: None,
+            request_body: Default::default(),
+            response_body: Default::default(),
+        }
+    }
+
+    pub fn capture_request<S>(&self, body: S) -> impl Stream<Item = std::io::Result<Bytes>>
+    where
+        S: Stream<Item = std::io::Result<Bytes>>,
+    {
+        capture_into(body, self.request_body.clone(), self.limit)
+    }
+
+    pub fn set_status(&mut self, status: http::StatusCode) {
+        self.status = Some(status.as_u16());
+    }
+
+    /// Takes over the recording, which gets written when the returned
+    /// stream is dropped
+    pub fn capture_response<S>(mut self, body: S) -> impl Stream<Item = std::io::Result<Bytes>>
+    where
+        S: Stream<Item = std::io::Result<Bytes>>,
+    {
+        body.inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                self.response_body.feed(chunk, self.limit);
+            }
+        })
+    }
+
+    fn recorded_body(&self, capture: &BodyCapture) -> HttpRecordedBody {
+        HttpRecordedBody {
+            data: self.redactor.redact(&capture.data),
+            size: capture.size,
+        }
+    }
+}
+
+impl Drop for ExchangeRecording {
+    fn drop(&mut self) {
+        let request_body = match self.request_body.lock() {
+            Ok(capture) => self.recorded_body(&capture),
+            Err(_) => HttpRecordedBody::default(),
+        };
+        let item = HttpRecordingItem {
+            time: self.time,
+            target: std::mem::take(&mut self.target),
+            method: std::mem::take(&mut self.method),
+            uri: std::mem::take(&mut self.uri),
+            status: self.status,
+            request_body,
+            response_body: self.recorded_body(&self.response_body),
+        };
+        let mut recorder = self.recorder.clone();
+        tokio::spawn(async move {
+            if let Err(error) = recorder.write_exchange(&item).await {
+                error!(%error, "Failed to record an HTTP exchange");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(patterns: &[&str], data: &str) -> String {
+        let patterns = patterns.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        String::from_utf8(Redactor::new(&patterns).redact(data.as_bytes()).to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact(&[r"sk_[a-z0-9]+"], "key=sk_abc1&other=sk_def2"),
+            "key=[REDACTED]&other=[REDACTED]"
+        );
+        assert_eq!(
+            redact(
+                &[r#""password":\s*"([^"]*)""#],
+                r#"{"user":"a","password": "hunter2"}"#
+            ),
+            r#"{"user":"a","password": "[REDACTED]"}"#
+        );
+        assert_eq!(redact(&["(", "x*"], "abc"), "abc");
+    }
+
+    #[test]
+    fn test_capture_limit() {
+        let mut capture = BodyCapture::default();
+        capture.feed(b"hello ", 8);
+        capture.feed(b"world", 8);
+        assert_eq!(&capture.data[..], b"hello wo");
+        assert_eq!(capture.size, 11);
+    }
+}
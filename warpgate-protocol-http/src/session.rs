@@ -2,39 +2,61 @@ use std::collections::{BTreeMap, HashMap};
 use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 
-use poem::session::{MemoryStorage, Session, SessionStorage};
+use poem::session::{Session, SessionStorage};
 use poem::web::{Data, RemoteAddr};
 use poem::{FromRequest, Request};
 use serde_json::Value;
 use tokio::sync::Mutex;
 use tracing::*;
 use warpgate_common::SessionId;
-use warpgate_core::{Services, SessionStateInit, WarpgateServerHandle};
+use warpgate_core::recordings::{self, HttpRecorder};
+use warpgate_core::{
+    HttpSessionMetadata, HttpSessionStorage, Services, SessionStateInit, WarpgateServerHandle,
+};
 
-use crate::common::PROTOCOL_NAME;
+use crate::common::{SessionAuthorization, AUTH_SESSION_KEY, PROTOCOL_NAME};
 use crate::session_handle::{
     HttpSessionHandle, SessionHandleCommand, WarpgateServerHandleFromRequest,
 };
 
 #[derive(Clone)]
-pub struct SharedSessionStorage(pub Arc<Mutex<Box<MemoryStorage>>>);
+pub struct SharedSessionStorage(pub HttpSessionStorage);
 
 static POEM_SESSION_ID_SESSION_KEY: &str = "poem_session_id";
 
+/// The [warpgate_core::HttpSessionInfo::id] of the web session a request was made in
+pub fn current_web_session_id(session: &Session) -> Option<String> {
+    session
+        .get::<String>(POEM_SESSION_ID_SESSION_KEY)
+        .map(|cookie| HttpSessionStorage::id_for_cookie(&cookie))
+}
+
+impl SharedSessionStorage {
+    fn metadata(entries: &BTreeMap<String, Value>) -> HttpSessionMetadata {
+        HttpSessionMetadata {
+            username: entries
+                .get(AUTH_SESSION_KEY)
+                .and_then(|x| serde_json::from_value::<SessionAuthorization>(x.clone()).ok())
+                .map(|x| x.username().to_owned()),
+            session_id: entries
+                .get(SESSION_ID_SESSION_KEY)
+                .and_then(|x| serde_json::from_value(x.clone()).ok()),
+        }
+    }
+}
+
 impl SessionStorage for SharedSessionStorage {
     async fn load_session<'a>(
         &'a self,
         session_id: &'a str,
     ) -> poem::Result<Option<BTreeMap<String, Value>>> {
-        self.0.lock().await.load_session(session_id).await.map(|o| {
-            o.map(|mut s| {
-                s.insert(
-                    POEM_SESSION_ID_SESSION_KEY.to_string(),
-                    session_id.to_string().into(),
-                );
-                s
-            })
-        })
+        Ok(self.0.load(session_id).await?.map(|mut s| {
+            s.insert(
+                POEM_SESSION_ID_SESSION_KEY.to_string(),
+                session_id.to_string().into(),
+            );
+            s
+        }))
     }
 
     /// Insert or update a session.
@@ -44,27 +66,39 @@ impl SessionStorage for SharedSessionStorage {
         entries: &'a BTreeMap<String, Value>,
         expires: Option<Duration>,
     ) -> poem::Result<()> {
-        self.0
-            .lock()
-            .await
-            .update_session(session_id, entries, expires)
-            .await
+        let mut entries = entries.clone();
+        entries.remove(POEM_SESSION_ID_SESSION_KEY);
+        let metadata = Self::metadata(&entries);
+        Ok(self.0.save(session_id, &entries, expires, metadata).await?)
     }
 
     /// Remove a session by session id.
     async fn remove_session<'a>(&'a self, session_id: &'a str) -> poem::Result<()> {
-        self.0.lock().await.remove_session(session_id).await
+        Ok(self.0.remove(session_id).await?)
     }
 }
 
 pub struct SessionStore {
     session_handles: HashMap<SessionId, Arc<Mutex<WarpgateServerHandle>>>,
     session_timestamps: HashMap<SessionId, Instant>,
+    http_recorders: HashMap<SessionId, HttpRecorder>,
     this: Weak<Mutex<SessionStore>>,
 }
 
-static SESSION_ID_SESSION_KEY: &str = "session_id";
-static REQUEST_COUNTER_SESSION_KEY: &str = "request_counter";
+pub static SESSION_ID_SESSION_KEY: &str = "session_id";
+static LAST_SEEN_SESSION_KEY: &str = "last_seen";
+/// Any change to a session writes it back to the storage, so activity
+/// is only noted this often
+const LAST_SEEN_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+static CLOSE_MESSAGE_SESSION_KEY: &str = "close_message";
+const CLOSE_MESSAGE_MAX_AGE: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// Returns the message an admin left when closing this session, once
+pub fn take_close_message(session: &Session) -> Option<String> {
+    let message = session.get::<String>(CLOSE_MESSAGE_SESSION_KEY)?;
+    session.purge();
+    Some(message)
+}
 
 impl SessionStore {
     pub fn new() -> Arc<Mutex<Self>> {
@@ -72,6 +106,7 @@ impl SessionStore {
             Mutex::new(Self {
                 session_handles: HashMap::new(),
                 session_timestamps: HashMap::new(),
+                http_recorders: HashMap::new(),
                 this: me.clone(),
             })
         })
@@ -80,14 +115,17 @@ impl SessionStore {
     pub async fn process_request(&mut self, req: Request) -> poem::Result<Request> {
         let session = <&Session>::from_request_without_body(&req).await?;
 
-        let request_counter = session.get::<u64>(REQUEST_COUNTER_SESSION_KEY).unwrap_or(0);
-        session.set(REQUEST_COUNTER_SESSION_KEY, request_counter + 1);
+        // Don't start a session (and set a cookie) for requests that have none
+        if !session.is_empty() {
+            let now = chrono::Utc::now().timestamp();
+            let last_seen = session.get::<i64>(LAST_SEEN_SESSION_KEY).unwrap_or(0);
+            if now - last_seen >= LAST_SEEN_UPDATE_INTERVAL.as_secs() as i64 {
+                session.set(LAST_SEEN_SESSION_KEY, now);
+            }
+        }
 
         if let Some(session_id) = session.get::<SessionId>(SESSION_ID_SESSION_KEY) {
             self.session_timestamps.insert(session_id, Instant::now());
-            // } else if request_counter == 5 {
-            // Start logging sessions when they've got 5 requests
-            // self.create_handle_for(&req).await?;
         };
 
         Ok(req)
@@ -136,14 +174,30 @@ impl SessionStore {
             async move {
                 while let Some(command) = session_handle_rx.recv().await {
                     match command {
-                        SessionHandleCommand::Close => {
+                        SessionHandleCommand::Close(message) => {
                             if let Some(ref poem_session_id) = poem_session_id {
-                                let _ = session_storage.remove_session(poem_session_id).await;
+                                let _ = match message {
+                                    // Replaces the session's contents, logging the user out
+                                    Some(message) => {
+                                        session_storage
+                                            .update_session(
+                                                poem_session_id,
+                                                &BTreeMap::from([(
+                                                    CLOSE_MESSAGE_SESSION_KEY.to_string(),
+                                                    message.into(),
+                                                )]),
+                                                Some(CLOSE_MESSAGE_MAX_AGE),
+                                            )
+                                            .await
+                                    }
+                                    None => session_storage.remove_session(poem_session_id).await,
+                                };
                             }
                             info!(%id, "Removed HTTP session");
                             let mut that = this.lock().await;
                             that.session_handles.remove(&id);
                             that.session_timestamps.remove(&id);
+                            that.http_recorders.remove(&id);
                         }
                     }
                 }
@@ -162,10 +216,39 @@ impl SessionStore {
             .and_then(|id| self.session_handles.get(&id).cloned())
     }
 
+    /// Starts recording the session's HTTP exchanges on first use
+    pub async fn http_recorder_for(
+        &mut self,
+        id: SessionId,
+        services: &Services,
+    ) -> Option<HttpRecorder> {
+        if let Some(recorder) = self.http_recorders.get(&id) {
+            return Some(recorder.clone());
+        }
+        let recorder = services
+            .recordings
+            .lock()
+            .await
+            .start::<HttpRecorder>(&id, "http".into())
+            .await;
+        match recorder {
+            Ok(recorder) => {
+                self.http_recorders.insert(id, recorder.clone());
+                Some(recorder)
+            }
+            Err(recordings::Error::Disabled) => None,
+            Err(error) => {
+                error!(%id, ?error, "Failed to start recording");
+                None
+            }
+        }
+    }
+
     pub fn remove_session(&mut self, session: &Session) {
         if let Some(id) = session.get::<SessionId>(SESSION_ID_SESSION_KEY) {
             self.session_handles.remove(&id);
             self.session_timestamps.remove(&id);
+            self.http_recorders.remove(&id);
         }
     }
 
@@ -180,6 +263,7 @@ impl SessionStore {
         for id in to_remove {
             self.session_handles.remove(&id);
             self.session_timestamps.remove(&id);
+            self.http_recorders.remove(&id);
         }
     }
 }
@@ -12,7 +12,8 @@ use crate::session::SessionStore;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SessionHandleCommand {
-    Close,
+    /// With an optional message from the admin
+    Close(Option<String>),
 }
 
 pub struct HttpSessionHandle {
@@ -28,7 +29,11 @@ impl HttpSessionHandle {
 
 impl SessionHandle for HttpSessionHandle {
     fn close(&mut self) {
-        let _ = self.sender.send(SessionHandleCommand::Close);
+        let _ = self.sender.send(SessionHandleCommand::Close(None));
+    }
+
+    fn close_with_message(&mut self, message: String) {
+        let _ = self.sender.send(SessionHandleCommand::Close(Some(message)));
     }
 }
 
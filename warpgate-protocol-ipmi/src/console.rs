@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use pty_process::{OwnedReadPty, OwnedWritePty, Pty, Size};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+use tracing::*;
+use warpgate_common::TargetIpmiOptions;
+
+use crate::{ipmitool_args, ipmitool_password, IpmiError, IPMITOOL};
+
+const DEACTIVATE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// An active SOL session. Output is read from the half returned by
+/// [SolConsole::open] and ends once the session is closed on either side.
+pub struct SolConsole {
+    child: Child,
+    input: OwnedWritePty,
+}
+
+impl SolConsole {
+    pub fn open(
+        options: &TargetIpmiOptions,
+        cols: u16,
+        rows: u16,
+    ) -> Result<(Self, OwnedReadPty), IpmiError> {
+        let pty = Pty::new()?;
+        pty.resize(Size::new(rows, cols))?;
+
+        info!(host=%options.host, port=%options.port, "Activating SOL");
+        let child = pty_process::Command::new(IPMITOOL)
+            .args(ipmitool_args(options))
+            .args(["sol", "activate"])
+            .env("IPMI_PASSWORD", ipmitool_password(options))
+            .spawn(&pty.pts()?)?;
+
+        let (output, input) = pty.into_split();
+        Ok((Self { child, input }, output))
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), IpmiError> {
+        self.input.write_all(data).await?;
+        Ok(())
+    }
+
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), IpmiError> {
+        self.input.resize(Size::new(rows, cols))?;
+        Ok(())
+    }
+
+    /// Lets ipmitool deactivate the SOL payload, since the BMC would otherwise
+    /// keep it reserved and refuse the next session until it times out
+    pub async fn close(mut self) {
+        let _ = self.input.write_all(b"\r~.").await;
+        if tokio::time::timeout(DEACTIVATE_TIMEOUT, self.child.wait())
+            .await
+            .is_err()
+        {
+            warn!("ipmitool did not exit, killing it");
+            let _ = self.child.kill().await;
+        }
+    }
+}
+
+impl Drop for SolConsole {
+    fn drop(&mut self) {
+        let _ = self.child.start_kill();
+    }
+}
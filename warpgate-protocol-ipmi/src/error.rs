@@ -0,0 +1,7 @@
+#[derive(thiserror::Error, Debug)]
+pub enum IpmiError {
+    #[error("pty: {0}")]
+    Pty(#[from] pty_process::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
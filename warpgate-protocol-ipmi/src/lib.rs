@@ -0,0 +1,88 @@
+mod console;
+mod error;
+
+pub use console::SolConsole;
+pub use error::IpmiError;
+use tokio::process::Command;
+use warpgate_common::TargetIpmiOptions;
+use warpgate_core::TargetTestError;
+
+/// Serial-over-LAN consoles are reached through `ipmitool`, which has to be
+/// installed on the Warpgate host.
+const IPMITOOL: &str = "ipmitool";
+
+fn ipmitool_args(options: &TargetIpmiOptions) -> Vec<String> {
+    let mut args = vec![
+        "-I".to_owned(),
+        "lanplus".to_owned(),
+        "-H".to_owned(),
+        options.host.clone(),
+        "-p".to_owned(),
+        options.port.to_string(),
+        "-U".to_owned(),
+        options.username.clone(),
+        // Password is taken from IPMI_PASSWORD to keep it out of the process list
+        "-E".to_owned(),
+    ];
+    if let Some(cipher_suite) = options.cipher_suite {
+        args.push("-C".to_owned());
+        args.push(cipher_suite.to_string());
+    }
+    args
+}
+
+fn ipmitool_password(options: &TargetIpmiOptions) -> &str {
+    options
+        .password
+        .as_ref()
+        .map(|x| &x.expose_secret()[..])
+        .unwrap_or("")
+}
+
+/// Checks that the BMC accepts the credentials and has SOL configured
+pub async fn test_connection(options: &TargetIpmiOptions) -> Result<(), TargetTestError> {
+    let output = Command::new(IPMITOOL)
+        .args(ipmitool_args(options))
+        .args(["sol", "info"])
+        .env("IPMI_PASSWORD", ipmitool_password(options))
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+    if stderr.contains("RAKP") || stderr.contains("nauthorized") {
+        return Err(TargetTestError::AuthenticationError);
+    }
+    if stderr.contains("Unable to establish") {
+        return Err(TargetTestError::Unreachable);
+    }
+    Err(TargetTestError::ConnectionError(stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::Secret;
+
+    use super::*;
+
+    #[test]
+    fn test_password_not_in_args() {
+        let options = TargetIpmiOptions {
+            host: "10.0.0.5".into(),
+            port: 623,
+            username: "ADMIN".into(),
+            password: Some(Secret::new("hunter2".into())),
+            cipher_suite: Some(17),
+        };
+        let args = ipmitool_args(&options);
+        assert_eq!(
+            args.join(" "),
+            "-I lanplus -H 10.0.0.5 -p 623 -U ADMIN -E -C 17"
+        );
+        assert_eq!(ipmitool_password(&options), "hunter2");
+    }
+}
@@ -0,0 +1,95 @@
+use warpgate_database_protocols::mysql::protocol::auth::AuthPlugin;
+use warpgate_database_protocols::mysql::protocol::Capabilities;
+
+use crate::response::read_bytes;
+
+/// The parts of a `COM_CHANGE_USER` request that Warpgate needs
+#[derive(Debug, PartialEq, Eq)]
+pub struct ChangeUser {
+    /// An auth selector, just like the username at login
+    pub username: String,
+    pub auth_response: Vec<u8>,
+    pub database: Option<String>,
+    pub auth_plugin: Option<AuthPlugin>,
+}
+
+fn read_bytes_nul<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let end = buf.iter().position(|x| *x == 0)?;
+    let value = read_bytes(buf, end)?;
+    // the NUL terminator
+    read_bytes(buf, 1)?;
+    Some(value)
+}
+
+fn read_str_nul(buf: &mut &[u8]) -> Option<String> {
+    read_bytes_nul(buf).map(|x| String::from_utf8_lossy(x).into_owned())
+}
+
+impl ChangeUser {
+    /// Takes the whole payload including the command byte
+    pub fn decode(payload: &[u8], capabilities: Capabilities) -> Option<Self> {
+        let mut buf = payload.strip_prefix(&[0x11])?;
+        let username = read_str_nul(&mut buf)?;
+        let auth_response = if capabilities.contains(Capabilities::SECURE_CONNECTION) {
+            let (len, rest) = buf.split_first()?;
+            buf = rest;
+            read_bytes(&mut buf, *len as usize)?
+        } else {
+            read_bytes_nul(&mut buf)?
+        }
+        .to_vec();
+        let database = Some(read_str_nul(&mut buf)?).filter(|x| !x.is_empty());
+
+        let mut auth_plugin = None;
+        if !buf.is_empty() {
+            // character set
+            read_bytes(&mut buf, 2)?;
+            if capabilities.contains(Capabilities::PLUGIN_AUTH) {
+                auth_plugin = read_str_nul(&mut buf).and_then(|x| x.parse().ok());
+            }
+        }
+
+        Some(Self {
+            username,
+            auth_response,
+            database,
+            auth_plugin,
+        })
+    }
+
+    /// The password, if the client has sent it in the clear already
+    pub fn clear_password(&self) -> Option<String> {
+        if self.auth_plugin != Some(AuthPlugin::MySqlClearPassword) {
+            return None;
+        }
+        let password = self
+            .auth_response
+            .strip_suffix(&[0])
+            .unwrap_or(&self.auth_response);
+        Some(String::from_utf8_lossy(password).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let capabilities =
+            Capabilities::PROTOCOL_41 | Capabilities::SECURE_CONNECTION | Capabilities::PLUGIN_AUTH;
+        let payload = b"\x11alice#db\x00\x07secret\x00app\x00\x2d\x00mysql_clear_password\x00";
+        let change_user = ChangeUser::decode(payload, capabilities).unwrap();
+        assert_eq!(change_user.username, "alice#db");
+        assert_eq!(change_user.database.as_deref(), Some("app"));
+        assert_eq!(change_user.clear_password().as_deref(), Some("secret"));
+
+        let payload = b"\x11alice#db\x00\x00\x00";
+        let change_user = ChangeUser::decode(payload, capabilities).unwrap();
+        assert_eq!(change_user.database, None);
+        assert_eq!(change_user.clear_password(), None);
+
+        assert_eq!(ChangeUser::decode(b"\x11alice", capabilities), None);
+        assert_eq!(ChangeUser::decode(b"\x03alice\x00", capabilities), None);
+    }
+}
@@ -2,23 +2,28 @@ use std::sync::Arc;
 
 use bytes::BytesMut;
 use tokio::net::TcpStream;
+use tokio_rustls::rustls::ClientConnection;
 use tracing::*;
 use warpgate_common::{configure_tls_connector, TargetMySqlOptions, TlsMode};
+use warpgate_core::{NegotiatedCapabilities, TargetResolver};
 use warpgate_database_protocols::io::Decode;
 use warpgate_database_protocols::mysql::protocol::auth::AuthPlugin;
 use warpgate_database_protocols::mysql::protocol::connect::{
     Handshake, HandshakeResponse, SslRequest,
 };
 use warpgate_database_protocols::mysql::protocol::response::ErrPacket;
+use warpgate_database_protocols::mysql::protocol::text::Query;
 use warpgate_database_protocols::mysql::protocol::Capabilities;
 
 use crate::common::compute_auth_challenge_response;
 use crate::error::MySqlError;
+use crate::response::{read_first_value, ResultSetTracker};
 use crate::stream::MySqlStream;
 
 pub struct MySqlClient {
     pub stream: MySqlStream<tokio_rustls::client::TlsStream<TcpStream>>,
-    pub _capabilities: Capabilities,
+    pub capabilities: Capabilities,
+    pub auth_plugin: Option<AuthPlugin>,
 }
 
 pub struct ConnectionOptions {
@@ -26,6 +31,8 @@ pub struct ConnectionOptions {
     pub database: Option<String>,
     pub max_packet_size: u32,
     pub capabilities: Capabilities,
+    /// Limit for packets reassembled by Warpgate itself
+    pub max_allowed_packet: usize,
 }
 
 impl Default for ConnectionOptions {
@@ -47,18 +54,37 @@ impl Default for ConnectionOptions {
                 | Capabilities::TRANSACTIONS
                 | Capabilities::DEPRECATE_EOF
                 | Capabilities::SECURE_CONNECTION
-                | Capabilities::SSL,
+                | Capabilities::SSL
+                | Capabilities::COMPRESS,
+            max_allowed_packet: 64 * 1024 * 1024,
         }
     }
 }
 
 impl MySqlClient {
+    pub fn tls_connection(&self) -> Option<&ClientConnection> {
+        Some(self.stream.tls()?.get_ref().1)
+    }
+
+    pub fn negotiated_capabilities(&self) -> NegotiatedCapabilities {
+        let mut capabilities = NegotiatedCapabilities::new().with_tls(self.tls_connection());
+        capabilities.auth_method = self.auth_plugin.map(|x| x.name().to_owned());
+        capabilities.flags = format!("{:?}", self.capabilities)
+            .split(" | ")
+            .map(str::to_owned)
+            .collect();
+        capabilities
+    }
+
     pub async fn connect(
         target: &TargetMySqlOptions,
+        resolver: &TargetResolver,
         mut options: ConnectionOptions,
     ) -> Result<Self, MySqlError> {
-        let mut stream =
-            MySqlStream::new(TcpStream::connect((target.host.clone(), target.port)).await?);
+        let mut stream = MySqlStream::new(
+            resolver.connect(&target.host, target.port).await?,
+            options.max_allowed_packet,
+        );
 
         options.capabilities.remove(Capabilities::SSL);
         if target.tls.mode != TlsMode::Disabled {
@@ -112,6 +138,7 @@ impl MySqlClient {
             database: options.database,
             max_packet_size: options.max_packet_size,
             username: target.username.clone(),
+            connect_attrs: vec![],
         };
 
         if handshake.auth_plugin == Some(AuthPlugin::MySqlNativePassword) {
@@ -130,7 +157,11 @@ impl MySqlClient {
                         BytesMut::from(
                             compute_auth_challenge_response(
                                 scramble,
-                                target.password.as_deref().unwrap_or(""),
+                                target
+                                    .password
+                                    .as_ref()
+                                    .map(|x| x.expose_secret().as_str())
+                                    .unwrap_or(""),
                             )
                             .map_err(MySqlError::other)?
                             .as_bytes(),
@@ -142,6 +173,7 @@ impl MySqlClient {
             }
         }
 
+        let auth_plugin = response.auth_plugin;
         stream.push(&response, options.capabilities)?;
         stream.flush().await?;
 
@@ -163,10 +195,78 @@ impl MySqlClient {
         }
 
         stream.reset_sequence_id();
+        if options.capabilities.contains(Capabilities::COMPRESS) {
+            stream.enable_compression();
+            debug!("Using protocol compression");
+        }
 
         Ok(Self {
             stream,
-            _capabilities: options.capabilities,
+            capabilities: options.capabilities,
+            auth_plugin,
         })
     }
+
+    /// Runs the target's initialization statements one by one,
+    /// discarding any rows they return
+    pub async fn run_init_statements(&mut self, statements: &[String]) -> Result<(), MySqlError> {
+        for statement in statements {
+            self.run_statement(statement).await?;
+            info!(%statement, "Ran initialization statement");
+        }
+        Ok(())
+    }
+
+    /// Runs a query without logging it, discarding any rows it returns
+    pub async fn run_statement(&mut self, statement: &str) -> Result<(), MySqlError> {
+        self.query(statement, |_| ()).await
+    }
+
+    /// Runs a query without logging it and returns the first column of
+    /// every row, skipping `NULL`s
+    pub async fn query_first_column(&mut self, statement: &str) -> Result<Vec<String>, MySqlError> {
+        let mut values = vec![];
+        self.query(statement, |row| {
+            if let Some(value) = read_first_value(row) {
+                values.push(String::from_utf8_lossy(value).into_owned());
+            }
+        })
+        .await?;
+        Ok(values)
+    }
+
+    async fn query(
+        &mut self,
+        statement: &str,
+        mut on_row: impl FnMut(&[u8]),
+    ) -> Result<(), MySqlError> {
+        let deprecate_eof = self.capabilities.contains(Capabilities::DEPRECATE_EOF);
+        self.stream.reset_sequence_id();
+        self.stream.push(&Query(statement.to_owned()), ())?;
+        self.stream.flush().await?;
+
+        let mut tracker = ResultSetTracker::new(deprecate_eof);
+        let mut first = true;
+        loop {
+            let Some(response) = self.stream.recv().await? else {
+                return Err(MySqlError::Eof);
+            };
+            if first && response.first() == Some(&0xff) {
+                let error = ErrPacket::decode_with(response, self.capabilities)?;
+                return Err(MySqlError::InitStatementFailed {
+                    statement: statement.to_owned(),
+                    message: error.error_message,
+                });
+            }
+            first = false;
+            if tracker.is_row(&response) {
+                on_row(&response);
+            }
+            if tracker.feed(&response) {
+                break;
+            }
+        }
+        self.stream.reset_sequence_id();
+        Ok(())
+    }
 }
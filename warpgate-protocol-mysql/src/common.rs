@@ -1,5 +1,6 @@
 use sha1::Digest;
 use warpgate_common::ProtocolName;
+use zeroize::Zeroize;
 
 pub const PROTOCOL_NAME: ProtocolName = "MySQL";
 
@@ -7,19 +8,21 @@ pub fn compute_auth_challenge_response(
     challenge: [u8; 20],
     password: &str,
 ) -> Result<password_hash::Output, password_hash::Error> {
-    password_hash::Output::new(
-        &{
-            let password_sha: [u8; 20] = sha1::Sha1::digest(password).into();
-            let password_sha_sha: [u8; 20] = sha1::Sha1::digest(password_sha).into();
-            let password_seed_2sha_sha: [u8; 20] =
-                sha1::Sha1::digest([challenge, password_sha_sha].concat()).into();
+    let mut password_sha: [u8; 20] = sha1::Sha1::digest(password).into();
+    let mut password_sha_sha: [u8; 20] = sha1::Sha1::digest(password_sha).into();
+    let mut seed = [&challenge[..], &password_sha_sha[..]].concat();
+    let mut password_seed_2sha_sha: [u8; 20] = sha1::Sha1::digest(&seed).into();
 
-            let mut result = password_sha;
-            result
-                .iter_mut()
-                .zip(password_seed_2sha_sha.iter())
-                .for_each(|(x1, x2)| *x1 ^= *x2);
-            result
-        }[..],
-    )
+    password_sha
+        .iter_mut()
+        .zip(password_seed_2sha_sha.iter())
+        .for_each(|(x1, x2)| *x1 ^= *x2);
+    let result = password_hash::Output::new(&password_sha[..]);
+
+    // The intermediate hashes are password-equivalent for native auth
+    password_sha.zeroize();
+    password_sha_sha.zeroize();
+    seed.zeroize();
+    password_seed_2sha_sha.zeroize();
+    result
 }
@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tracing::*;
+use warpgate_common::{Target, TargetOptions, WarpgateError};
+use warpgate_core::{DirectCredentialProvisioner, TargetResolver};
+
+use crate::client::{ConnectionOptions, MySqlClient};
+use crate::error::MySqlError;
+
+/// MySQL has no equivalent of `VALID UNTIL`, so accounts can't expire by
+/// themselves - they only go away once Warpgate drops them, which also
+/// kills their connections.
+pub struct MySqlCredentialProvisioner;
+
+impl MySqlCredentialProvisioner {
+    async fn connect(
+        target: &Target,
+        resolver: &TargetResolver,
+    ) -> Result<MySqlClient, WarpgateError> {
+        let TargetOptions::MySql(ref options) = target.options else {
+            return Err(WarpgateError::InconsistentState);
+        };
+        MySqlClient::connect(options, resolver, ConnectionOptions::default())
+            .await
+            .map_err(map_error)
+    }
+
+    async fn run(
+        target: &Target,
+        resolver: &TargetResolver,
+        statements: &[String],
+    ) -> Result<(), WarpgateError> {
+        let mut client = Self::connect(target, resolver).await?;
+        for statement in statements {
+            client.run_statement(statement).await.map_err(map_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn map_error(error: MySqlError) -> WarpgateError {
+    match error {
+        // Don't pass on the statement, it might contain the password
+        MySqlError::InitStatementFailed { message, .. } => anyhow::anyhow!(message).into(),
+        e => WarpgateError::other(e),
+    }
+}
+
+#[async_trait]
+impl DirectCredentialProvisioner for MySqlCredentialProvisioner {
+    async fn create_user(
+        &self,
+        target: &Target,
+        resolver: &TargetResolver,
+        username: &str,
+        password: &str,
+        _expires: DateTime<Utc>,
+    ) -> Result<(), WarpgateError> {
+        let account = format!("'{username}'@'%'");
+        let mut statements = vec![format!("CREATE USER {account} IDENTIFIED BY '{password}'")];
+        if let Some(options) = target.options.direct_credentials() {
+            statements.extend(
+                options
+                    .grant_statements
+                    .iter()
+                    .map(|x| x.replace("{username}", &account)),
+            );
+        }
+        Self::run(target, resolver, &statements).await
+    }
+
+    async fn drop_user(
+        &self,
+        target: &Target,
+        resolver: &TargetResolver,
+        username: &str,
+    ) -> Result<(), WarpgateError> {
+        let mut client = Self::connect(target, resolver).await?;
+        let connections = client
+            .query_first_column(&format!(
+                "SELECT ID FROM information_schema.PROCESSLIST WHERE USER = '{username}'"
+            ))
+            .await
+            .map_err(map_error)?;
+        for id in connections.iter().filter_map(|x| x.parse::<u64>().ok()) {
+            // The connection might have closed in the meantime
+            if let Err(error) = client.run_statement(&format!("KILL {id}")).await {
+                debug!(%id, ?error, "Could not kill connection");
+            }
+        }
+        client
+            .run_statement(&format!("DROP USER IF EXISTS '{username}'@'%'"))
+            .await
+            .map_err(map_error)
+    }
+}
@@ -27,6 +27,8 @@ pub enum MySqlError {
     MySqlStream(#[from] MySqlStreamError),
     #[error("I/O: {0}")]
     Io(#[from] std::io::Error),
+    #[error("initialization statement {statement:?} failed: {message}")]
+    InitStatementFailed { statement: String, message: String },
     #[error("packet decode error: {0}")]
     Decode(Box<dyn Error + Send + Sync>),
     #[error(transparent)]
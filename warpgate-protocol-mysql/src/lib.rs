@@ -1,6 +1,10 @@
+mod change_user;
 mod client;
 mod common;
+mod direct_credentials;
 mod error;
+mod prepared;
+mod response;
 mod session;
 mod session_handle;
 mod stream;
@@ -9,15 +13,20 @@ use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use client::{ConnectionOptions, MySqlClient};
+use direct_credentials::MySqlCredentialProvisioner;
+use error::MySqlError;
 use futures::TryStreamExt;
 use rustls::server::NoClientAuth;
 use rustls::ServerConfig;
+use tokio::sync::mpsc;
 use tracing::*;
 use warpgate_common::{
     ListenEndpoint, ResolveServerCert, Target, TargetOptions, TlsCertificateAndPrivateKey,
     TlsCertificateBundle, TlsPrivateKey,
 };
-use warpgate_core::{ProtocolServer, Services, SessionStateInit, TargetTestError};
+use warpgate_core::{
+    ProtocolServer, Services, SessionStateInit, TargetTestError, CLOSE_MESSAGE_TIMEOUT,
+};
 
 use crate::session::MySqlSession;
 use crate::session_handle::MySqlSessionHandle;
@@ -28,6 +37,10 @@ pub struct MySQLProtocolServer {
 
 impl MySQLProtocolServer {
     pub async fn new(services: &Services) -> Result<Self> {
+        services.direct_credential_provisioners.register(
+            crate::common::PROTOCOL_NAME,
+            Arc::new(MySqlCredentialProvisioner),
+        );
         Ok(MySQLProtocolServer {
             services: services.clone(),
         })
@@ -37,7 +50,7 @@ impl MySQLProtocolServer {
 impl ProtocolServer for MySQLProtocolServer {
     async fn run(self, address: ListenEndpoint) -> Result<()> {
         let certificate_and_key = {
-            let config = self.services.config.lock().await;
+            let config = self.services.config.load();
             let certificate_path = config
                 .paths_relative_to
                 .join(&config.store.mysql.certificate);
@@ -69,7 +82,8 @@ impl ProtocolServer for MySQLProtocolServer {
 
         info!(?address, "Listening");
 
-        let mut listener = address.tcp_accept_stream().await?;
+        let socket_options = self.services.config.load().store.mysql.socket.clone();
+        let mut listener = address.tcp_accept_stream(&socket_options).await?;
 
         loop {
             let Some(stream) = listener.try_next().await? else {
@@ -77,6 +91,11 @@ impl ProtocolServer for MySQLProtocolServer {
             };
             let remote_address = stream.peer_addr()?;
 
+            if !self.services.ip_bans.is_allowed(remote_address.ip()) {
+                debug!(%remote_address, "Dropping a connection from a banned address");
+                continue;
+            }
+
             let tls_config = tls_config.clone();
             let services = self.services.clone();
             tokio::spawn(async move {
@@ -95,17 +114,31 @@ impl ProtocolServer for MySQLProtocolServer {
                     )
                     .await?;
 
-                let session =
-                    MySqlSession::new(server_handle, services, stream, tls_config, remote_address)
-                        .await;
-                let span = session.make_logging_span();
+                let span = server_handle.lock().await.context().span().clone();
+                let (close_message_tx, close_message_rx) = mpsc::unbounded_channel();
+                let session = MySqlSession::new(
+                    server_handle,
+                    services,
+                    stream,
+                    tls_config,
+                    remote_address,
+                    close_message_rx,
+                )
+                .await;
+                let run = session.run().instrument(span);
+                tokio::pin!(run);
                 tokio::select! {
-                    result = session.run().instrument(span) => match result {
+                    result = &mut run => match result {
                         Ok(_) => info!("Session ended"),
                         Err(e) => error!(error=%e, "Session failed"),
                     },
-                    _ = abort_rx.recv() => {
+                    message = abort_rx.recv() => {
                         warn!("Session aborted by admin");
+                        if let Some(Some(message)) = message {
+                            // Give the session a chance to tell the client why
+                            let _ = close_message_tx.send(message);
+                            let _ = tokio::time::timeout(CLOSE_MESSAGE_TIMEOUT, run).await;
+                        }
                     },
                 }
 
@@ -115,14 +148,32 @@ impl ProtocolServer for MySQLProtocolServer {
     }
 
     async fn test_target(&self, target: Target) -> Result<(), TargetTestError> {
+        let resolver = self.services.dns.for_target(&target);
         let TargetOptions::MySql(options) = target.options else {
             return Err(TargetTestError::Misconfigured(
                 "Not a MySQL target".to_owned(),
             ));
         };
-        MySqlClient::connect(&options, ConnectionOptions::default())
-            .await
-            .map_err(|e| TargetTestError::ConnectionError(format!("{e}")))?;
+        async {
+            let mut client =
+                MySqlClient::connect(&options, &resolver, ConnectionOptions::default()).await?;
+            self.services
+                .target_capabilities
+                .record(&target.name, client.negotiated_capabilities());
+            client.run_init_statements(&options.init_statements).await
+        }
+        .await
+        .map_err(|e| match e {
+            MySqlError::Tls(_)
+            | MySqlError::TlsSetup(_)
+            | MySqlError::TlsNotSupported
+            | MySqlError::InvalidDomainName => TargetTestError::Untrusted(format!("{e}")),
+            MySqlError::Io(e) => TargetTestError::Io(e),
+            MySqlError::InitStatementFailed { .. } => {
+                TargetTestError::Misconfigured(format!("{e}"))
+            }
+            e => TargetTestError::ConnectionError(format!("{e}")),
+        })?;
         Ok(())
     }
 }
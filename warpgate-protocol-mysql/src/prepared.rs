@@ -0,0 +1,327 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::response::{read_bytes, read_u16, read_u32, read_u8, read_uint_lenenc};
+
+/// Statements that are prepared but never closed don't grow the map past this
+const MAX_TRACKED: usize = 1000;
+
+/// Longer parameter values are cut off in logs
+const MAX_VALUE_LENGTH: usize = 256;
+
+const UNSIGNED_FLAG: u8 = 0x80;
+
+struct Statement {
+    query: String,
+    parameters: u16,
+    /// Sent with the first execution and reused by the following ones
+    types: Vec<(u8, u8)>,
+    long_data: HashSet<u16>,
+}
+
+/// What a `COM_STMT_EXECUTE` is going to run
+pub struct ExecutedStatement<'a> {
+    pub query: &'a str,
+    pub parameters: u16,
+    /// Only collected when `log.query_parameters` is enabled
+    pub values: Option<Vec<String>>,
+}
+
+/// Maps the statement IDs assigned by the target to their text so that
+/// executions can be logged with it
+pub struct PreparedStatements {
+    statements: HashMap<u32, Statement>,
+    log_values: bool,
+}
+
+impl PreparedStatements {
+    pub fn new(log_values: bool) -> Self {
+        Self {
+            statements: HashMap::new(),
+            log_values,
+        }
+    }
+
+    /// Forgets all statements, e.g. once the target connection is reset
+    pub fn clear(&mut self) {
+        self.statements.clear();
+    }
+
+    pub fn prepared(&mut self, statement_id: u32, query: String, parameters: u16) {
+        if self.statements.len() >= MAX_TRACKED {
+            return;
+        }
+        self.statements.insert(
+            statement_id,
+            Statement {
+                query,
+                parameters,
+                types: vec![],
+                long_data: HashSet::new(),
+            },
+        );
+    }
+
+    /// Takes the `COM_STMT_CLOSE` payload without the command byte
+    pub fn close(&mut self, mut payload: &[u8]) {
+        if let Some(statement_id) = read_u32(&mut payload) {
+            self.statements.remove(&statement_id);
+        }
+    }
+
+    /// Takes the `COM_STMT_RESET` payload without the command byte
+    pub fn reset(&mut self, mut payload: &[u8]) {
+        if let Some(statement) = read_u32(&mut payload).and_then(|x| self.statements.get_mut(&x)) {
+            statement.long_data.clear();
+        }
+    }
+
+    /// Takes the `COM_STMT_SEND_LONG_DATA` payload without the command byte
+    pub fn long_data(&mut self, mut payload: &[u8]) {
+        let (Some(statement_id), Some(parameter)) =
+            (read_u32(&mut payload), read_u16(&mut payload))
+        else {
+            return;
+        };
+        if let Some(statement) = self.statements.get_mut(&statement_id) {
+            statement.long_data.insert(parameter);
+        }
+    }
+
+    /// Takes the `COM_STMT_EXECUTE` payload without the command byte
+    pub fn execute(&mut self, mut payload: &[u8]) -> Option<ExecutedStatement<'_>> {
+        let statement_id = read_u32(&mut payload)?;
+        let log_values = self.log_values;
+        let statement = self.statements.get_mut(&statement_id)?;
+
+        // flags and iteration count
+        let values = read_bytes(&mut payload, 5)
+            .and_then(|_| read_parameters(statement, payload))
+            .filter(|_| log_values);
+        statement.long_data.clear();
+
+        Some(ExecutedStatement {
+            query: &statement.query,
+            parameters: statement.parameters,
+            values,
+        })
+    }
+}
+
+fn read_parameters(statement: &mut Statement, mut buf: &[u8]) -> Option<Vec<String>> {
+    let count = usize::from(statement.parameters);
+    if count == 0 {
+        return Some(vec![]);
+    }
+    let null_bitmap = read_bytes(&mut buf, count.div_ceil(8))?;
+    if read_bytes(&mut buf, 1)? == [1] {
+        statement.types = (0..count)
+            .map(|_| read_u16(&mut buf).map(|x| (x as u8, (x >> 8) as u8)))
+            .collect::<Option<_>>()?;
+    }
+    if statement.types.len() != count {
+        return None;
+    }
+
+    statement
+        .types
+        .iter()
+        .enumerate()
+        .map(|(index, (kind, flags))| {
+            if null_bitmap.get(index / 8)? & (1 << (index % 8)) != 0 {
+                return Some("NULL".into());
+            }
+            if statement.long_data.contains(&(index as u16)) {
+                return Some("<long data>".into());
+            }
+            read_value(&mut buf, *kind, flags & UNSIGNED_FLAG != 0)
+        })
+        .collect()
+}
+
+fn read_int<const N: usize>(buf: &mut &[u8], unsigned: bool) -> Option<String> {
+    let mut value = [0; 8];
+    value.get_mut(..N)?.copy_from_slice(read_bytes(buf, N)?);
+    let value = u64::from_le_bytes(value);
+    Some(if unsigned {
+        value.to_string()
+    } else {
+        let shift = 64 - N as u32 * 8;
+        (((value << shift) as i64) >> shift).to_string()
+    })
+}
+
+fn read_value(buf: &mut &[u8], kind: u8, unsigned: bool) -> Option<String> {
+    match kind {
+        // TINY
+        0x01 => read_int::<1>(buf, unsigned),
+        // SHORT, YEAR
+        0x02 | 0x0d => read_int::<2>(buf, unsigned),
+        // LONG, INT24
+        0x03 | 0x09 => read_int::<4>(buf, unsigned),
+        // LONGLONG
+        0x08 => read_int::<8>(buf, unsigned),
+        // FLOAT
+        0x04 => Some(f32::from_le_bytes(read_bytes(buf, 4)?.try_into().ok()?).to_string()),
+        // DOUBLE
+        0x05 => Some(f64::from_le_bytes(read_bytes(buf, 8)?.try_into().ok()?).to_string()),
+        // NULL
+        0x06 => Some("NULL".into()),
+        // TIMESTAMP, DATE, DATETIME
+        0x07 | 0x0a | 0x0c => {
+            let len = usize::from(read_u8(buf)?);
+            let mut value = read_bytes(buf, len)?;
+            let mut text = String::new();
+            if len >= 4 {
+                let year = read_u16(&mut value)?;
+                let (month, day) = (read_u8(&mut value)?, read_u8(&mut value)?);
+                let _ = write!(text, "{year:04}-{month:02}-{day:02}");
+            }
+            if len >= 7 {
+                let hour = read_u8(&mut value)?;
+                let (minute, second) = (read_u8(&mut value)?, read_u8(&mut value)?);
+                let _ = write!(text, " {hour:02}:{minute:02}:{second:02}");
+            }
+            if len >= 11 {
+                let _ = write!(text, ".{:06}", read_u32(&mut value)?);
+            }
+            Some(text)
+        }
+        // TIME
+        0x0b => {
+            let len = usize::from(read_u8(buf)?);
+            let mut value = read_bytes(buf, len)?;
+            if len < 8 {
+                return Some("00:00:00".into());
+            }
+            let negative = read_u8(&mut value)? == 1;
+            let days = read_u32(&mut value)?;
+            let hours = read_u8(&mut value)?;
+            let (minutes, seconds) = (read_u8(&mut value)?, read_u8(&mut value)?);
+            let mut text = format!(
+                "{}{:02}:{minutes:02}:{seconds:02}",
+                if negative { "-" } else { "" },
+                u64::from(days) * 24 + u64::from(hours),
+            );
+            if len >= 12 {
+                let _ = write!(text, ".{:06}", read_u32(&mut value)?);
+            }
+            Some(text)
+        }
+        // Strings, decimals, blobs, JSON, etc.
+        _ => {
+            let len = usize::try_from(read_uint_lenenc(buf)?).ok()?;
+            let text = String::from_utf8_lossy(read_bytes(buf, len)?);
+            Some(match text.char_indices().nth(MAX_VALUE_LENGTH) {
+                Some((cutoff, _)) => format!("{}…", text.get(..cutoff)?),
+                None => text.into_owned(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute_payload(
+        statement_id: u32,
+        types: Option<&[u16]>,
+        null_bitmap: u8,
+        values: &[u8],
+    ) -> Vec<u8> {
+        let mut payload = statement_id.to_le_bytes().to_vec();
+        payload.extend([0, 1, 0, 0, 0, null_bitmap]);
+        match types {
+            Some(types) => {
+                payload.push(1);
+                for kind in types {
+                    payload.extend(kind.to_le_bytes());
+                }
+            }
+            None => payload.push(0),
+        }
+        payload.extend(values);
+        payload
+    }
+
+    #[test]
+    fn test_execute_values() {
+        let mut statements = PreparedStatements::new(true);
+        statements.prepared(1, "SELECT ?, ?, ?, ?, ?".into(), 5);
+
+        let mut values = vec![0xfe];
+        values.extend(0xfffeu16.to_le_bytes());
+        values.extend([3, b'a', b'b', b'c']);
+        values.extend([4, 0xe8, 0x07, 2, 29]);
+        let payload = execute_payload(1, Some(&[0x01, 0x8002, 0x06, 0xfd, 0x0a]), 0b100, &values);
+
+        let executed = statements.execute(&payload).unwrap();
+        assert_eq!(executed.query, "SELECT ?, ?, ?, ?, ?");
+        assert_eq!(executed.parameters, 5);
+        assert_eq!(
+            executed.values.unwrap(),
+            vec!["-2", "65534", "NULL", "abc", "2024-02-29"]
+        );
+
+        // Types are only sent once
+        let payload = execute_payload(1, None, 0b11111, &[]);
+        assert_eq!(
+            statements.execute(&payload).unwrap().values.unwrap(),
+            vec!["NULL"; 5]
+        );
+    }
+
+    #[test]
+    fn test_execute_untracked_and_malformed() {
+        let mut statements = PreparedStatements::new(true);
+        statements.prepared(1, "SELECT ?".into(), 1);
+        assert!(statements
+            .execute(&execute_payload(2, None, 0, &[]))
+            .is_none());
+
+        let payload = execute_payload(1, Some(&[0x0f]), 0, &[10, b'x']);
+        let executed = statements.execute(&payload).unwrap();
+        assert_eq!(executed.query, "SELECT ?");
+        assert_eq!(executed.values, None);
+
+        statements.close(&1u32.to_le_bytes());
+        assert!(statements.execute(&payload).is_none());
+    }
+
+    #[test]
+    fn test_truncated_values() {
+        let mut statements = PreparedStatements::new(true);
+        statements.prepared(1, "SELECT ?".into(), 1);
+
+        // DATETIME claiming 7 bytes but carrying 5, and TIME cut short
+        for (kind, value) in [
+            (0x0c, &[7, 0xe8, 0x07, 2, 29, 1][..]),
+            (0x0b, &[8, 0, 1, 0][..]),
+        ] {
+            let payload = execute_payload(1, Some(&[kind]), 0, value);
+            assert_eq!(statements.execute(&payload).unwrap().values, None);
+        }
+    }
+
+    #[test]
+    fn test_long_data() {
+        let mut statements = PreparedStatements::new(true);
+        statements.prepared(1, "INSERT INTO t VALUES (?, ?)".into(), 2);
+        statements.long_data(&[1, 0, 0, 0, 0, 0, b'x']);
+
+        let payload = execute_payload(1, Some(&[0xfc, 0x03]), 0, &[5, 0, 0, 0]);
+        assert_eq!(
+            statements.execute(&payload).unwrap().values.unwrap(),
+            vec!["<long data>", "5"]
+        );
+    }
+
+    #[test]
+    fn test_values_not_collected_by_default() {
+        let mut statements = PreparedStatements::new(false);
+        statements.prepared(1, "SELECT ?".into(), 1);
+        let payload = execute_payload(1, Some(&[0x03]), 0, &[5, 0, 0, 0]);
+        assert_eq!(statements.execute(&payload).unwrap().values, None);
+    }
+}
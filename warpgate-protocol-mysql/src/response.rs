@@ -0,0 +1,353 @@
+use warpgate_database_protocols::mysql::protocol::response::Status;
+
+/// Packets of this length are continued in the next one, so they can't be
+/// OK/EOF terminators even if they start with `0xfe`
+const MAX_PAYLOAD_LEN: usize = 0xff_ffff;
+
+pub(crate) fn read_u8(buf: &mut &[u8]) -> Option<u8> {
+    let (value, rest) = buf.split_first()?;
+    *buf = rest;
+    Some(*value)
+}
+
+pub(crate) fn read_u16(buf: &mut &[u8]) -> Option<u16> {
+    let (value, rest) = buf.split_first_chunk::<2>()?;
+    *buf = rest;
+    Some(u16::from_le_bytes(*value))
+}
+
+pub(crate) fn read_u32(buf: &mut &[u8]) -> Option<u32> {
+    let (value, rest) = buf.split_first_chunk::<4>()?;
+    *buf = rest;
+    Some(u32::from_le_bytes(*value))
+}
+
+pub(crate) fn read_bytes<'a>(buf: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if buf.len() < len {
+        return None;
+    }
+    let (value, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(value)
+}
+
+pub(crate) fn read_uint_lenenc(buf: &mut &[u8]) -> Option<u64> {
+    let (first, rest) = buf.split_first()?;
+    *buf = rest;
+    let len = match first {
+        0xfc => 2,
+        0xfd => 3,
+        0xfe => 8,
+        value => return Some(u64::from(*value)),
+    };
+    let mut value = [0; 8];
+    value[..len].copy_from_slice(read_bytes(buf, len)?);
+    Some(u64::from_le_bytes(value))
+}
+
+/// First value of a text protocol row, `None` for `NULL`
+pub(crate) fn read_first_value(row: &[u8]) -> Option<&[u8]> {
+    let mut buf = row;
+    if buf.first() == Some(&0xfb) {
+        return None;
+    }
+    let len = usize::try_from(read_uint_lenenc(&mut buf)?).ok()?;
+    read_bytes(&mut buf, len)
+}
+
+fn is_terminator(packet: &[u8]) -> bool {
+    packet.first() == Some(&0xfe) && packet.len() < MAX_PAYLOAD_LEN
+}
+
+/// Status flags of an OK packet or, with the `0xfe` header, of either
+/// an EOF or an OK packet
+fn read_status(packet: &[u8], deprecate_eof: bool) -> Status {
+    let mut buf = packet.get(1..).unwrap_or_default();
+    let status = if packet.first() == Some(&0xfe) && !deprecate_eof {
+        read_u16(&mut buf).and_then(|_warnings| read_u16(&mut buf))
+    } else {
+        read_uint_lenenc(&mut buf)
+            .and_then(|_affected_rows| read_uint_lenenc(&mut buf))
+            .and_then(|_last_insert_id| read_u16(&mut buf))
+    };
+    Status::from_bits_truncate(status.unwrap_or_default())
+}
+
+/// `ERR_Packet` message, without the code and SQL state
+fn read_error_message(packet: &[u8]) -> String {
+    let mut buf = packet.get(3..).unwrap_or_default();
+    if buf.first() == Some(&b'#') {
+        buf = buf.get(6..).unwrap_or_default();
+    }
+    String::from_utf8_lossy(buf).into_owned()
+}
+
+enum State {
+    Start,
+    Columns(u64),
+    ColumnsEof,
+    Rows,
+}
+
+/// Follows a command response packet by packet to find where it ends,
+/// including result sets whose rows can start with any byte
+pub struct ResultSetTracker {
+    state: State,
+    deprecate_eof: bool,
+    result_set: bool,
+    rows: u64,
+    affected_rows: u64,
+    error: Option<String>,
+}
+
+impl ResultSetTracker {
+    pub fn new(deprecate_eof: bool) -> Self {
+        Self {
+            state: State::Start,
+            deprecate_eof,
+            result_set: false,
+            rows: 0,
+            affected_rows: 0,
+            error: None,
+        }
+    }
+
+    /// For `COM_STMT_FETCH`, which only returns rows
+    pub fn rows(deprecate_eof: bool) -> Self {
+        Self {
+            state: State::Rows,
+            deprecate_eof,
+            result_set: true,
+            rows: 0,
+            affected_rows: 0,
+            error: None,
+        }
+    }
+
+    /// Rows returned or, if there was no result set, affected.
+    /// Not set if the command failed.
+    pub fn row_count(&self) -> Option<u64> {
+        match (&self.error, self.result_set) {
+            (Some(_), _) => None,
+            (None, true) => Some(self.rows),
+            (None, false) => Some(self.affected_rows),
+        }
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Whether the next packet, if it's not the end of the result set, is a row
+    pub fn is_row(&self, packet: &[u8]) -> bool {
+        matches!(self.state, State::Rows) && packet.first() != Some(&0xff) && !is_terminator(packet)
+    }
+
+    /// Returns `true` once the packet was the last one of the response
+    pub fn feed(&mut self, packet: &[u8]) -> bool {
+        if packet.first() == Some(&0xff) {
+            self.error = Some(read_error_message(packet));
+        }
+        match self.state {
+            State::Start => match packet.first() {
+                Some(0x00) => {
+                    let mut buf = &packet[1..];
+                    self.affected_rows += read_uint_lenenc(&mut buf).unwrap_or_default();
+                    !self.more_results(packet)
+                }
+                Some(0xff) | None => true,
+                // LOCAL INFILE requests aren't supported
+                Some(0xfb) => true,
+                Some(_) => {
+                    self.result_set = true;
+                    let columns = read_uint_lenenc(&mut &packet[..]).unwrap_or_default();
+                    self.state = State::Columns(columns);
+                    if columns == 0 {
+                        self.columns_done();
+                    }
+                    false
+                }
+            },
+            State::Columns(remaining) => {
+                if packet.first() == Some(&0xff) {
+                    return true;
+                }
+                self.state = State::Columns(remaining - 1);
+                if remaining == 1 {
+                    self.columns_done();
+                }
+                false
+            }
+            State::ColumnsEof => {
+                if packet.first() == Some(&0xff) {
+                    return true;
+                }
+                // With a cursor open, rows are fetched with separate commands
+                if read_status(packet, false).contains(Status::SERVER_STATUS_CURSOR_EXISTS) {
+                    return true;
+                }
+                self.state = State::Rows;
+                false
+            }
+            State::Rows => {
+                if packet.first() == Some(&0xff) {
+                    return true;
+                }
+                if is_terminator(packet) {
+                    return !self.more_results(packet);
+                }
+                self.rows += 1;
+                false
+            }
+        }
+    }
+
+    fn columns_done(&mut self) {
+        self.state = if self.deprecate_eof {
+            State::Rows
+        } else {
+            State::ColumnsEof
+        };
+    }
+
+    fn more_results(&mut self, packet: &[u8]) -> bool {
+        let more = read_status(packet, self.deprecate_eof && packet.first() == Some(&0xfe))
+            .contains(Status::SERVER_MORE_RESULTS_EXISTS);
+        self.state = State::Start;
+        more
+    }
+}
+
+/// A successful `COM_STMT_PREPARE` response
+#[derive(Debug, PartialEq, Eq)]
+pub struct PrepareOk {
+    pub statement_id: u32,
+    pub columns: u16,
+    pub parameters: u16,
+}
+
+impl PrepareOk {
+    pub fn decode(packet: &[u8]) -> Option<Self> {
+        let (0x00, mut buf) = packet.split_first()? else {
+            return None;
+        };
+        Some(Self {
+            statement_id: read_u32(&mut buf)?,
+            columns: read_u16(&mut buf)?,
+            parameters: read_u16(&mut buf)?,
+        })
+    }
+
+    /// Parameter and column definitions sent after this packet
+    pub fn following_packets(&self, deprecate_eof: bool) -> usize {
+        let eof = usize::from(!deprecate_eof);
+        [self.parameters, self.columns]
+            .iter()
+            .filter(|x| **x > 0)
+            .map(|x| usize::from(*x) + eof)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EOF: &[u8] = b"\xfe\x00\x00\x02\x00";
+    const EOF_MORE_RESULTS: &[u8] = b"\xfe\x00\x00\x0a\x00";
+    const EOF_CURSOR: &[u8] = b"\xfe\x00\x00\x42\x00";
+    const OK_EOF: &[u8] = b"\xfe\x00\x00\x02\x00\x00\x00";
+
+    fn feed_all(tracker: &mut ResultSetTracker, packets: &[&[u8]]) -> Vec<bool> {
+        packets.iter().map(|x| tracker.feed(x)).collect()
+    }
+
+    #[test]
+    fn test_ok_and_error() {
+        let mut tracker = ResultSetTracker::new(true);
+        assert!(tracker.feed(b"\x00\x01\x00\x02\x00\x00\x00"));
+        assert_eq!(tracker.row_count(), Some(1));
+
+        let mut tracker = ResultSetTracker::new(true);
+        assert!(tracker.feed(b"\xff\x48\x04#HY000No tables used"));
+        assert_eq!(tracker.error(), Some("No tables used"));
+        assert_eq!(tracker.row_count(), None);
+    }
+
+    #[test]
+    fn test_rows_that_look_like_ok() {
+        let mut tracker = ResultSetTracker::new(false);
+        assert_eq!(
+            feed_all(
+                &mut tracker,
+                &[b"\x01", b"def", EOF, b"\x00", b"\x00\x00", EOF]
+            ),
+            vec![false, false, false, false, false, true]
+        );
+        assert_eq!(tracker.row_count(), Some(2));
+
+        let mut tracker = ResultSetTracker::new(true);
+        assert_eq!(
+            feed_all(&mut tracker, &[b"\x02", b"def", b"def", b"\x00", OK_EOF]),
+            vec![false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_multiple_results() {
+        let mut tracker = ResultSetTracker::new(false);
+        assert_eq!(
+            feed_all(
+                &mut tracker,
+                &[
+                    b"\x01",
+                    b"def",
+                    EOF,
+                    b"row",
+                    EOF_MORE_RESULTS,
+                    b"\x00\x00\x00\x02\x00\x00\x00"
+                ]
+            ),
+            vec![false, false, false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_row_values() {
+        let mut tracker = ResultSetTracker::new(false);
+        let packets: &[&[u8]] = &[b"\x01", b"def", EOF, b"\x0212", b"\xfb", b"\x00", EOF];
+        let mut values = vec![];
+        for packet in packets {
+            if tracker.is_row(packet) {
+                values.push(read_first_value(packet));
+            }
+            tracker.feed(packet);
+        }
+        assert_eq!(values, vec![Some(&b"12"[..]), None, Some(&b""[..])]);
+    }
+
+    #[test]
+    fn test_cursor() {
+        let mut tracker = ResultSetTracker::new(false);
+        assert_eq!(
+            feed_all(&mut tracker, &[b"\x01", b"def", EOF_CURSOR]),
+            vec![false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_prepare_ok() {
+        let ok = PrepareOk::decode(b"\x00\x07\x00\x00\x00\x02\x00\x03\x00\x00\x00\x00").unwrap();
+        assert_eq!(
+            ok,
+            PrepareOk {
+                statement_id: 7,
+                columns: 2,
+                parameters: 3
+            }
+        );
+        assert_eq!(ok.following_packets(false), 7);
+        assert_eq!(ok.following_packets(true), 5);
+        assert_eq!(PrepareOk::decode(b"\x00\x07"), None);
+    }
+}
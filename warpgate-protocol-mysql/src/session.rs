@@ -5,15 +5,24 @@ use std::sync::Arc;
 use bytes::{Buf, Bytes, BytesMut};
 use rand::Rng;
 use rustls::ServerConfig;
+use sea_orm::ActiveValue::Set;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tracing::*;
 use uuid::Uuid;
-use warpgate_common::auth::{AuthCredential, AuthResult, AuthSelector, CredentialKind};
+use warpgate_common::auth::{
+    AuthCredential, AuthResult, AuthSelector, CredentialKind, TargetParameters,
+};
 use warpgate_common::helpers::rng::get_crypto_rng;
-use warpgate_common::{Secret, TargetMySqlOptions, TargetOptions};
+use warpgate_common::{
+    ErrorCode, MySqlChangeUserPolicy, Secret, TargetMySqlOptions, TargetOptions, TlsMode,
+    WarpgateError,
+};
+use warpgate_core::recordings::{self, QueryRecorder};
 use warpgate_core::{
-    authorize_ticket, consume_ticket, ConfigProvider, Services, WarpgateServerHandle,
+    authorize_ticket, check_target_baseline, consume_ticket, resolve_target_address, trip_honeypot,
+    AuthFailureContext, ConfigProvider, Services, SessionLastActivity, TargetFingerprint,
+    TargetObservation, TargetResolver, WarpgateServerHandle,
 };
 use warpgate_database_protocols::io::{BufExt, Decode};
 use warpgate_database_protocols::mysql::protocol::auth::AuthPlugin;
@@ -23,10 +32,15 @@ use warpgate_database_protocols::mysql::protocol::connect::{
 use warpgate_database_protocols::mysql::protocol::response::{ErrPacket, OkPacket, Status};
 use warpgate_database_protocols::mysql::protocol::text::Query;
 use warpgate_database_protocols::mysql::protocol::Capabilities;
+use warpgate_db_entities::DatabaseSessionDetails;
+use warpgate_db_entities::Session::SessionTerminationReason;
 
+use crate::change_user::ChangeUser;
 use crate::client::{ConnectionOptions, MySqlClient};
 use crate::error::MySqlError;
-use crate::stream::MySqlStream;
+use crate::prepared::PreparedStatements;
+use crate::response::{PrepareOk, ResultSetTracker};
+use crate::stream::{MySqlStream, FLUSH_THRESHOLD};
 
 pub struct MySqlSession {
     stream: MySqlStream<tokio_rustls::server::TlsStream<TcpStream>>,
@@ -36,9 +50,14 @@ pub struct MySqlSession {
     database: Option<String>,
     tls_config: Arc<ServerConfig>,
     server_handle: Arc<Mutex<WarpgateServerHandle>>,
+    last_activity: SessionLastActivity,
     id: Uuid,
     services: Services,
     remote_address: SocketAddr,
+    prepared: PreparedStatements,
+    recorder: Option<QueryRecorder>,
+    /// Messages from an admin closing the session
+    close_messages: mpsc::UnboundedReceiver<String>,
 }
 
 impl MySqlSession {
@@ -48,11 +67,22 @@ impl MySqlSession {
         stream: TcpStream,
         tls_config: ServerConfig,
         remote_address: SocketAddr,
+        close_messages: mpsc::UnboundedReceiver<String>,
     ) -> Self {
-        let id = server_handle.lock().await.id();
+        let (id, last_activity) = {
+            let handle = server_handle.lock().await;
+            (handle.id(), handle.last_activity().clone())
+        };
+        let (max_packet_size, log_values) = {
+            let config = services.config.load();
+            (
+                config.store.mysql.max_packet_size,
+                config.store.log.query_parameters,
+            )
+        };
         Self {
             services,
-            stream: MySqlStream::new(stream),
+            stream: MySqlStream::new(stream, max_packet_size),
             capabilities: Capabilities::PROTOCOL_41
                 | Capabilities::PLUGIN_AUTH
                 | Capabilities::FOUND_ROWS
@@ -64,26 +94,24 @@ impl MySqlSession {
                 | Capabilities::IGNORE_SPACE
                 | Capabilities::INTERACTIVE
                 | Capabilities::TRANSACTIONS
+                | Capabilities::MULTI_STATEMENTS
+                | Capabilities::MULTI_RESULTS
                 | Capabilities::DEPRECATE_EOF
                 | Capabilities::SECURE_CONNECTION
-                | Capabilities::SSL,
+                | Capabilities::SSL
+                // zstd isn't offered, so clients that support both pick zlib
+                | Capabilities::COMPRESS,
             challenge: get_crypto_rng().gen(),
             tls_config: Arc::new(tls_config),
             username: None,
             database: None,
             server_handle,
+            last_activity,
             id,
             remote_address,
-        }
-    }
-
-    pub fn make_logging_span(&self) -> tracing::Span {
-        let client_ip = self.remote_address.ip().to_string();
-        match self.username {
-            Some(ref username) => {
-                info_span!("MySQL", session=%self.id, session_username=%username, %client_ip)
-            }
-            None => info_span!("MySQL", session=%self.id, %client_ip),
+            prepared: PreparedStatements::new(log_values),
+            recorder: None,
+            close_messages,
         }
     }
 
@@ -122,7 +150,7 @@ impl MySqlSession {
                 self.stream = self.stream.upgrade(self.tls_config.clone()).await?;
                 continue;
             } else {
-                self.send_error(1002, "Warpgate requires TLS - please enable it in your client: add `--ssl` on the CLI or add `?sslMode=PREFERRED` to your database URI").await?;
+                self.send_error(1002, &ErrorCode::TlsRequired.annotate("Warpgate requires TLS - please enable it in your client: add `--ssl` on the CLI or add `?sslMode=PREFERRED` to your database URI")).await?;
                 return Err(MySqlError::TlsNotSupportedByClient);
             }
         };
@@ -150,6 +178,44 @@ impl MySqlSession {
         self.run_authorization(resp, password).await
     }
 
+    /// Remembers the target's certificate and negotiated capabilities
+    /// and checks the connection against the target's baseline
+    async fn check_target_tls(
+        &self,
+        client: &MySqlClient,
+        target_name: &str,
+        options: &TargetMySqlOptions,
+    ) {
+        self.services
+            .target_capabilities
+            .record(target_name, client.negotiated_capabilities());
+        let connection = client.tls_connection();
+        if let Some(cert) = connection
+            .and_then(|c| c.peer_certificates())
+            .and_then(|c| c.first())
+        {
+            self.services
+                .target_fingerprints
+                .record(target_name, TargetFingerprint::tls_certificate(cert));
+        }
+        if options.tls.mode == TlsMode::Disabled {
+            return;
+        }
+        let observation =
+            TargetObservation::tls(target_name, &options.host, options.port, connection);
+        if let Err(error) = check_target_baseline(&self.services, observation).await {
+            error!(%error, "Failed to check the target baseline");
+        }
+    }
+
+    async fn mark_target_error(&self) {
+        self.server_handle
+            .lock()
+            .await
+            .set_termination_reason(SessionTerminationReason::TargetError)
+            .await;
+    }
+
     async fn send_error(&mut self, code: u16, message: &str) -> Result<(), MySqlError> {
         self.stream.push(
             &ErrPacket {
@@ -169,26 +235,59 @@ impl MySqlSession {
         password: Secret<String>,
     ) -> Result<(), MySqlError> {
         let selector: AuthSelector = handshake.username.deref().into();
+        let client_banner = handshake
+            .connect_attrs
+            .iter()
+            .find(|(key, _)| key == "program_name")
+            .or_else(|| {
+                handshake
+                    .connect_attrs
+                    .iter()
+                    .find(|(key, _)| key == "_client_name")
+            })
+            .map(|(_, value)| value.clone());
+
+        match self.authenticate(selector, password, client_banner).await? {
+            Some((username, target_name, parameters)) => {
+                self.run_authorized(handshake, username, target_name, parameters)
+                    .await
+            }
+            None => {
+                self.send_error(1, &ErrorCode::AuthFailed.annotate("Warpgate access denied"))
+                    .await
+            }
+        }
+    }
 
-        async fn fail(this: &mut MySqlSession) -> Result<(), MySqlError> {
-            this.stream.push(
-                &ErrPacket {
-                    error_code: 1,
-                    error_message: "Warpgate access denied".to_owned(),
-                    sql_state: None,
-                },
-                (),
-            )?;
-            this.stream.flush().await?;
-            Ok(())
+    /// Checks the credentials and the access to the selected target.
+    /// Returns the username, the target name and the target parameters.
+    async fn authenticate(
+        &mut self,
+        selector: AuthSelector,
+        password: Secret<String>,
+        client_banner: Option<String>,
+    ) -> Result<Option<(String, String, TargetParameters)>, MySqlError> {
+        let username = match &selector {
+            AuthSelector::User { username, .. } => Some(username.as_str()),
+            AuthSelector::Ticket { .. } => None,
+        };
+        let client_ip = self.remote_address.ip().to_string();
+        if !self
+            .services
+            .auth_rate_limiter
+            .allows(username, Some(&client_ip))
+            .await
+        {
+            return Ok(None);
         }
 
         match selector {
             AuthSelector::User {
                 username,
                 target_name,
+                parameters,
             } => {
-                let state_arc = self
+                let state = self
                     .services
                     .auth_state_store
                     .lock()
@@ -199,8 +298,20 @@ impl MySqlSession {
                         crate::common::PROTOCOL_NAME,
                         &[CredentialKind::Password],
                     )
-                    .await?
-                    .1;
+                    .await;
+                let state_arc = match state {
+                    Ok((_, state)) => state,
+                    Err(WarpgateError::UserNotFound(_)) => {
+                        self.record_auth_failure(
+                            client_banner,
+                            Some(&username),
+                            vec![CredentialKind::Password],
+                        )
+                        .await;
+                        return Ok(None);
+                    }
+                    Err(error) => return Err(error.into()),
+                };
                 let mut state = state_arc.lock().await;
 
                 let user_auth_result = {
@@ -209,6 +320,8 @@ impl MySqlSession {
                     let mut cp = self.services.config_provider.lock().await;
                     if cp.validate_credential(&username, &credential).await? {
                         state.add_valid_credential(credential);
+                    } else {
+                        state.add_invalid_credential(&credential);
                     }
 
                     state.verify()
@@ -236,11 +349,17 @@ impl MySqlSession {
                                 "Target {} not authorized for user {}",
                                 target_name, username
                             );
-                            return fail(&mut self).await;
+                            return Ok(None);
                         }
-                        self.run_authorized(handshake, username, target_name).await
+                        Ok(Some((username, target_name, parameters)))
+                    }
+                    // TODO SSO
+                    AuthResult::Rejected | AuthResult::Need(_) => {
+                        let credential_kinds = state.attempted_credential_kinds();
+                        self.record_auth_failure(client_banner, Some(&username), credential_kinds)
+                            .await;
+                        Ok(None)
                     }
-                    AuthResult::Rejected | AuthResult::Need(_) => fail(&mut self).await, // TODO SSO
                 }
             }
             AuthSelector::Ticket { secret } => {
@@ -253,21 +372,46 @@ impl MySqlSession {
                         consume_ticket(&self.services.db, &ticket.id)
                             .await
                             .map_err(MySqlError::other)?;
-
-                        self.run_authorized(handshake, ticket.username, ticket.target)
-                            .await
+                        Ok(Some((
+                            ticket.username,
+                            ticket.target,
+                            TargetParameters::new(),
+                        )))
+                    }
+                    _ => {
+                        self.record_auth_failure(client_banner, None, vec![]).await;
+                        Ok(None)
                     }
-                    _ => fail(&mut self).await,
                 }
             }
         }
     }
 
+    async fn record_auth_failure(
+        &self,
+        client_banner: Option<String>,
+        username: Option<&str>,
+        credential_kinds: Vec<CredentialKind>,
+    ) {
+        let context = AuthFailureContext {
+            username: username.map(Into::into),
+            credential_kinds,
+            client_banner,
+            ..AuthFailureContext::new(crate::common::PROTOCOL_NAME, self.remote_address)
+        };
+        self.services
+            .auth_rate_limiter
+            .record_failure(&context)
+            .await;
+        self.services.auth_failures.record(context).await;
+    }
+
     async fn run_authorized(
         mut self,
         handshake: HandshakeResponse,
         username: String,
         target_name: String,
+        parameters: TargetParameters,
     ) -> Result<(), MySqlError> {
         self.stream.push(
             &OkPacket {
@@ -279,6 +423,9 @@ impl MySqlSession {
             (),
         )?;
         self.stream.flush().await?;
+        if self.capabilities.contains(Capabilities::COMPRESS) {
+            self.stream.enable_compression();
+        }
 
         let target = {
             self.services
@@ -296,12 +443,12 @@ impl MySqlSession {
                 .map(|(t, opt)| (t.clone(), opt.clone()))
         };
 
-        let Some((target, mysql_options)) = target else {
+        let Some((mut target, mut mysql_options)) = target else {
             warn!("Selected target not found");
             self.stream.push(
                 &ErrPacket {
                     error_code: 1,
-                    error_message: "Warpgate access denied".to_owned(),
+                    error_message: ErrorCode::AccessDenied.annotate("Warpgate access denied"),
                     sql_state: None,
                 },
                 (),
@@ -310,116 +457,433 @@ impl MySqlSession {
             return Ok(());
         };
 
+        if !target.honeypot {
+            if let Err(error) =
+                resolve_target_address(&self.services, &username, &mut target, &parameters).await
+            {
+                warn!(%error, "Could not resolve the target address");
+                // ER_BAD_HOST_ERROR
+                self.send_error(1042, &error.code().annotate(&error))
+                    .await?;
+                return Ok(());
+            }
+        }
+        if let TargetOptions::MySql(ref options) = target.options {
+            mysql_options = options.clone();
+        }
+
         {
             let handle = self.server_handle.lock().await;
-            handle.set_username(username).await?;
-            handle.set_target(&target).await?;
+            handle.set_username(username.clone()).await?;
+            if let Err(error) = handle.set_target(&target).await {
+                if let WarpgateError::TargetDraining(_)
+                | WarpgateError::TargetSessionLimit(..)
+                | WarpgateError::RecordingQuotaExceeded(_) = error
+                {
+                    drop(handle);
+                    warn!(%error, "Selected target doesn't accept new sessions");
+                    let code = match error {
+                        // ER_CON_COUNT_ERROR
+                        WarpgateError::TargetSessionLimit(..) => 1040,
+                        // ER_SERVER_SHUTDOWN
+                        _ => 1053,
+                    };
+                    self.send_error(code, &error.code().annotate(&error))
+                        .await?;
+                    return Ok(());
+                }
+                return Err(error.into());
+            }
+        }
+
+        if target.honeypot {
+            let session_id = self.server_handle.lock().await.id();
+            trip_honeypot(
+                &self.services,
+                &target,
+                crate::common::PROTOCOL_NAME,
+                Some(session_id),
+                Some(&username),
+                Some(self.remote_address.to_string()),
+            )
+            .await;
+            // ER_ACCESS_DENIED_ERROR
+            self.send_error(1045, &ErrorCode::AccessDenied.annotate("Access denied"))
+                .await?;
+            return Ok(());
         }
 
-        self.run_authorized_inner(handshake, mysql_options).await
+        let resolver = self.services.dns.for_target(&target);
+        self.run_authorized_inner(handshake, &target.name, mysql_options, resolver)
+            .await
     }
 
     async fn run_authorized_inner(
         mut self,
         handshake: HandshakeResponse,
+        target_name: &str,
         options: TargetMySqlOptions,
+        resolver: TargetResolver,
     ) -> Result<(), MySqlError> {
         self.database = handshake.database.clone();
         self.username = Some(handshake.username);
         if let Some(ref database) = handshake.database {
             info!("Selected database: {database}");
         }
+        self.server_handle
+            .lock()
+            .await
+            .update_database_details(DatabaseSessionDetails::ActiveModel {
+                database: Set(handshake.database.clone()),
+                client_application: Set(handshake
+                    .connect_attrs
+                    .iter()
+                    .find(|(key, _)| key == "program_name")
+                    .map(|(_, value)| value.clone())),
+                ..Default::default()
+            })
+            .await?;
+
+        let mut capabilities = self.capabilities;
+        if !options.allow_multi_statements && capabilities.contains(Capabilities::MULTI_STATEMENTS)
+        {
+            info!("Multi-statement queries are disabled for this target");
+            capabilities.remove(Capabilities::MULTI_STATEMENTS);
+        }
 
         let mut client = match MySqlClient::connect(
             &options,
+            &resolver,
             ConnectionOptions {
                 collation: handshake.collation,
                 database: handshake.database,
                 max_packet_size: handshake.max_packet_size,
-                capabilities: self.capabilities,
+                capabilities,
+                max_allowed_packet: self.services.config.load().store.mysql.max_packet_size,
             },
         )
         .await
         {
             Err(error) => {
                 error!(%error, "Target connection failed");
-                self.send_error(1045, "Access denied").await?;
+                self.mark_target_error().await;
+                self.send_error(
+                    1045,
+                    &ErrorCode::TargetConnectionFailed.annotate("Access denied"),
+                )
+                .await?;
                 Err(error)
             }
             x => x,
         }?;
 
+        self.check_target_tls(&client, target_name, &options).await;
+
+        if let Err(error) = client.run_init_statements(&options.init_statements).await {
+            error!(%error, "Target initialization failed");
+            self.mark_target_error().await;
+            self.send_error(
+                1045,
+                &ErrorCode::TargetConnectionFailed
+                    .annotate("Warpgate could not initialize the target connection"),
+            )
+            .await?;
+            return Err(error);
+        }
+
+        self.start_query_recording().await;
+
         loop {
             self.stream.reset_sequence_id();
             client.stream.reset_sequence_id();
-            let Some(payload) = self.stream.recv().await? else {
+            let payload = tokio::select! {
+                payload = self.stream.recv() => payload?,
+                Some(message) = self.close_messages.recv() => {
+                    // ER_CONNECTION_KILLED
+                    self.send_error(
+                        1927,
+                        &ErrorCode::SessionClosedByAdmin
+                            .annotate(format!("Session closed by admin: {message}")),
+                    )
+                    .await?;
+                    break;
+                }
+            };
+            let Some(payload) = payload else {
                 break;
             };
             trace!(?payload, "server got packet");
+            self.last_activity.touch();
 
-            let com = payload.first();
+            let Some((&com, body)) = payload.split_first() else {
+                return Err(MySqlError::ProtocolError("empty command packet".into()));
+            };
 
             // COM_QUERY
-            if com == Some(&0x03) {
+            if com == 0x03 {
                 let query = Query::decode(payload)?;
                 info!(query=%query.0, "SQL");
+                let time = self.recorder.as_ref().map(QueryRecorder::get_time);
 
                 client.stream.push(&query, ())?;
                 client.stream.flush().await?;
-
-                let mut eof_ctr = 0;
-                loop {
-                    let Some(response) = client.stream.recv().await? else {
-                        return Err(MySqlError::Eof);
-                    };
-                    trace!(?response, "client got packet");
-                    self.stream.push(&&response[..], ())?;
-                    self.stream.flush().await?;
-                    if let Some(com) = response.first() {
-                        if com == &0xfe {
-                            if self.capabilities.contains(Capabilities::DEPRECATE_EOF) {
-                                break;
-                            }
-                            eof_ctr += 1;
-                            if eof_ctr == 2 {
-                                // todo check multiple results
-                                break;
-                            }
-                        }
-                        if com == &0 || com == &0xff {
-                            break;
+                let deprecate_eof = client.capabilities.contains(Capabilities::DEPRECATE_EOF);
+                let tracker = self
+                    .relay_result_set(&mut client, ResultSetTracker::new(deprecate_eof))
+                    .await?;
+                self.record_query(time, query.0, &tracker).await;
+            // COM_STMT_PREPARE
+            } else if com == 0x16 {
+                let query = String::from_utf8_lossy(body).into_owned();
+                info!(%query, "Preparing query");
+                client.stream.push_payload(payload)?;
+                client.stream.flush().await?;
+                self.relay_prepare_response(&mut client, query).await?;
+            // COM_STMT_EXECUTE
+            } else if com == 0x17 {
+                let time = self.recorder.as_ref().map(QueryRecorder::get_time);
+                let query = match self.prepared.execute(body) {
+                    Some(statement) => {
+                        match statement.values {
+                            Some(values) => info!(
+                                query=%statement.query,
+                                parameters=statement.parameters,
+                                ?values,
+                                "Executing prepared query"
+                            ),
+                            None => info!(
+                                query=%statement.query,
+                                parameters=statement.parameters,
+                                "Executing prepared query"
+                            ),
                         }
+                        Some(statement.query.to_owned())
+                    }
+                    None => {
+                        info!("Executing unknown prepared query");
+                        None
                     }
+                };
+                client.stream.push_payload(payload)?;
+                client.stream.flush().await?;
+                let deprecate_eof = client.capabilities.contains(Capabilities::DEPRECATE_EOF);
+                let tracker = self
+                    .relay_result_set(&mut client, ResultSetTracker::new(deprecate_eof))
+                    .await?;
+                if let Some(query) = query {
+                    self.record_query(time, query, &tracker).await;
+                }
+            // COM_STMT_FETCH
+            } else if com == 0x1c {
+                client.stream.push_payload(payload)?;
+                client.stream.flush().await?;
+                let deprecate_eof = client.capabilities.contains(Capabilities::DEPRECATE_EOF);
+                self.relay_result_set(&mut client, ResultSetTracker::rows(deprecate_eof))
+                    .await?;
+            // COM_STMT_SEND_LONG_DATA, COM_STMT_CLOSE - these have no response
+            } else if com == 0x18 || com == 0x19 {
+                if com == 0x18 {
+                    self.prepared.long_data(body);
+                } else {
+                    self.prepared.close(body);
                 }
+                client.stream.push_payload(payload)?;
+                client.stream.flush().await?;
+            // COM_STMT_RESET
+            } else if com == 0x1a {
+                self.prepared.reset(body);
+                client.stream.push_payload(payload)?;
+                client.stream.flush().await?;
+                self.passthrough_until_result(&mut client).await?;
             // COM_QUIT
-            } else if com == Some(&0x01) {
+            } else if com == 0x01 {
                 break;
             // COM_INIT_DB
-            } else if com == Some(&0x02) {
+            } else if com == 0x02 {
                 let mut buf = payload.clone();
                 buf.advance(1);
                 let db = buf.get_str(buf.len())?;
                 self.database = Some(db.clone());
                 info!("Selected database: {db}");
-                client.stream.push(&&payload[..], ())?;
+                self.server_handle
+                    .lock()
+                    .await
+                    .update_database_details(DatabaseSessionDetails::ActiveModel {
+                        database: Set(Some(db)),
+                        ..Default::default()
+                    })
+                    .await?;
+                client.stream.push_payload(payload)?;
                 client.stream.flush().await?;
                 self.passthrough_until_result(&mut client).await?;
+            // COM_SET_OPTION
+            } else if com == 0x1b {
+                // MYSQL_OPTION_MULTI_STATEMENTS_ON
+                if payload.get(1..3) == Some(&[0, 0]) && !options.allow_multi_statements {
+                    warn!("Denied enabling multi-statement queries");
+                    // ER_SPECIFIC_ACCESS_DENIED_ERROR
+                    self.send_error(
+                        1227,
+                        &ErrorCode::RequestRejected
+                            .annotate("Multi-statement queries are not allowed for this target"),
+                    )
+                    .await?;
+                    continue;
+                }
+                client.stream.push_payload(payload)?;
+                client.stream.flush().await?;
+                self.passthrough_until_result(&mut client).await?;
+            // COM_CHANGE_USER
+            } else if com == 0x11 {
+                if !self
+                    .change_user(&mut client, &payload, target_name, &options)
+                    .await?
+                {
+                    break;
+                }
             // COM_FIELD_LIST, COM_PING, COM_RESET_CONNECTION
-            } else if com == Some(&0x04) || com == Some(&0x0e) || com == Some(&0x1f) {
-                client.stream.push(&&payload[..], ())?;
+            } else if com == 0x04 || com == 0x0e || com == 0x1f {
+                client.stream.push_payload(payload)?;
                 client.stream.flush().await?;
                 self.passthrough_until_result(&mut client).await?;
-            } else if let Some(com) = com {
-                warn!("Unknown packet type {com}");
-                self.send_error(1047, "Not implemented").await?;
             } else {
-                break;
+                warn!("Unknown packet type {com}");
+                self.send_error(1047, &ErrorCode::NotImplemented.annotate("Not implemented"))
+                    .await?;
             }
         }
 
         Ok(())
     }
 
+    /// Returns `false` if the session has to end, like a MySQL server
+    /// closes the connection after a failed user change
+    async fn change_user(
+        &mut self,
+        client: &mut MySqlClient,
+        payload: &[u8],
+        target_name: &str,
+        options: &TargetMySqlOptions,
+    ) -> Result<bool, MySqlError> {
+        if options.change_user == MySqlChangeUserPolicy::Deny {
+            warn!("Denied a user change");
+            // ER_SPECIFIC_ACCESS_DENIED_ERROR
+            self.send_error(
+                1227,
+                &ErrorCode::RequestRejected
+                    .annotate("Changing users is not allowed for this target"),
+            )
+            .await?;
+            return Ok(true);
+        }
+
+        let request = ChangeUser::decode(payload, self.capabilities)
+            .ok_or_else(|| MySqlError::ProtocolError("invalid COM_CHANGE_USER packet".into()))?;
+        let password = match request.clear_password() {
+            Some(password) => password,
+            None => {
+                self.stream.push(
+                    &AuthSwitchRequest {
+                        plugin: AuthPlugin::MySqlClearPassword,
+                        data: Bytes::new(),
+                    },
+                    (),
+                )?;
+                self.stream.flush().await?;
+                let Some(response) = self.stream.recv().await? else {
+                    return Err(MySqlError::Eof);
+                };
+                response.clone().get_str_nul()?
+            }
+        };
+
+        let username = match self
+            .authenticate(request.username.deref().into(), Secret::new(password), None)
+            .await?
+        {
+            Some((username, selected_target, _)) if selected_target == target_name => {
+                Some(username)
+            }
+            Some((username, selected_target, _)) => {
+                warn!(%username, %selected_target, "User change can't switch targets");
+                None
+            }
+            None => None,
+        };
+        let Some(username) = username else {
+            // ER_ACCESS_DENIED_ERROR
+            self.send_error(
+                1045,
+                &ErrorCode::AuthFailed.annotate("Warpgate access denied"),
+            )
+            .await?;
+            return Ok(false);
+        };
+
+        // The new user mustn't inherit the previous user's session state
+        let mut commands = vec![Bytes::from_static(&[0x1f])];
+        if let Some(ref database) = request.database {
+            commands.push([&[0x02], database.as_bytes()].concat().into());
+        }
+        for command in commands {
+            client.stream.reset_sequence_id();
+            client.stream.push_payload(command)?;
+            client.stream.flush().await?;
+            let Some(response) = client.stream.recv().await? else {
+                return Err(MySqlError::Eof);
+            };
+            if response.first() == Some(&0xff) {
+                warn!("Target rejected the user change");
+                self.stream.push_payload(response)?;
+                self.stream.flush().await?;
+                return Ok(false);
+            }
+        }
+        if let Err(error) = client.run_init_statements(&options.init_statements).await {
+            error!(%error, "Target initialization failed");
+            self.send_error(
+                1045,
+                &ErrorCode::TargetConnectionFailed
+                    .annotate("Warpgate could not initialize the target connection"),
+            )
+            .await?;
+            return Err(error);
+        }
+        self.prepared.clear();
+
+        info!(%username, "Changed user");
+        self.server_handle
+            .lock()
+            .await
+            .set_username(username)
+            .await?;
+        self.username = Some(request.username);
+        if let Some(ref database) = request.database {
+            info!("Selected database: {database}");
+            self.database = request.database.clone();
+            self.server_handle
+                .lock()
+                .await
+                .update_database_details(DatabaseSessionDetails::ActiveModel {
+                    database: Set(request.database),
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        self.stream.push(
+            &OkPacket {
+                affected_rows: 0,
+                last_insert_id: 0,
+                status: Status::empty(),
+                warnings: 0,
+            },
+            (),
+        )?;
+        self.stream.flush().await?;
+        Ok(true)
+    }
+
     async fn passthrough_until_result(
         &mut self,
         client: &mut MySqlClient,
@@ -429,14 +893,110 @@ impl MySqlSession {
                 return Err(MySqlError::Eof);
             };
             trace!(?response, "client got packet");
-            self.stream.push(&&response[..], ())?;
-            self.stream.flush().await?;
-            if let Some(com) = response.first() {
-                if com == &0 || com == &0xff || com == &0xfe {
-                    break;
-                }
+            self.stream.push_payload(response.clone())?;
+            let done = response
+                .first()
+                .is_some_and(|com| com == &0 || com == &0xff || com == &0xfe);
+            self.maybe_flush(client, done).await?;
+            if done {
+                break;
             }
         }
         Ok(())
     }
+
+    async fn relay_result_set(
+        &mut self,
+        client: &mut MySqlClient,
+        mut tracker: ResultSetTracker,
+    ) -> Result<ResultSetTracker, MySqlError> {
+        loop {
+            let Some(response) = client.stream.recv().await? else {
+                return Err(MySqlError::Eof);
+            };
+            trace!(?response, "client got packet");
+            let done = tracker.feed(&response);
+            self.stream.push_payload(response)?;
+            self.maybe_flush(client, done).await?;
+            if done {
+                return Ok(tracker);
+            }
+        }
+    }
+
+    async fn start_query_recording(&mut self) {
+        match self
+            .services
+            .recordings
+            .lock()
+            .await
+            .start::<QueryRecorder>(&self.id, "queries".into())
+            .await
+        {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(recordings::Error::Disabled) => (),
+            Err(error) => error!(?error, "Failed to start recording"),
+        }
+    }
+
+    /// `time` is only set while recording
+    async fn record_query(&mut self, time: Option<f32>, query: String, tracker: &ResultSetTracker) {
+        let (Some(recorder), Some(time)) = (&mut self.recorder, time) else {
+            return;
+        };
+        if let Err(error) = recorder
+            .write_query(
+                time,
+                query,
+                tracker.row_count(),
+                tracker.error().map(str::to_owned),
+            )
+            .await
+        {
+            error!(?error, "Failed to record the query");
+        }
+    }
+
+    async fn relay_prepare_response(
+        &mut self,
+        client: &mut MySqlClient,
+        query: String,
+    ) -> Result<(), MySqlError> {
+        let Some(response) = client.stream.recv().await? else {
+            return Err(MySqlError::Eof);
+        };
+        trace!(?response, "client got packet");
+        let prepare_ok = PrepareOk::decode(&response);
+        self.stream.push_payload(response)?;
+
+        let Some(prepare_ok) = prepare_ok else {
+            self.stream.flush().await?;
+            return Ok(());
+        };
+        self.prepared
+            .prepared(prepare_ok.statement_id, query, prepare_ok.parameters);
+
+        let deprecate_eof = client.capabilities.contains(Capabilities::DEPRECATE_EOF);
+        for _ in 0..prepare_ok.following_packets(deprecate_eof) {
+            let Some(response) = client.stream.recv().await? else {
+                return Err(MySqlError::Eof);
+            };
+            self.stream.push_payload(response)?;
+            self.maybe_flush(client, false).await?;
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Batches relayed packets, flushing them to the client at the end of a
+    /// response or whenever the target has nothing more buffered
+    async fn maybe_flush(&mut self, client: &MySqlClient, force: bool) -> Result<(), MySqlError> {
+        if force
+            || !client.stream.has_buffered_input()
+            || self.stream.pending_outbound() >= FLUSH_THRESHOLD
+        {
+            self.stream.flush().await?;
+        }
+        Ok(())
+    }
 }
@@ -1,4 +1,5 @@
 use bytes::{Bytes, BytesMut};
+use flate2::Compression;
 use mysql_common::proto::codec::error::PacketCodecError;
 use mysql_common::proto::codec::PacketCodec;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -7,6 +8,21 @@ use tracing::*;
 use warpgate_common::{MaybeTlsStream, MaybeTlsStreamError, UpgradableStream};
 use warpgate_database_protocols::io::Encode;
 
+/// Minimum free space kept in the inbound buffer before reading from the socket
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Three bytes of payload length and a sequence ID
+const PACKET_HEADER_SIZE: usize = 4;
+
+/// Three bytes of compressed length, a sequence ID
+/// and three bytes of uncompressed length
+const COMPRESSED_PACKET_HEADER_SIZE: usize = 7;
+
+const MAX_PAYLOAD_LEN: usize = 0xff_ffff;
+
+/// Pending outbound data is flushed once it grows past this size
+pub const FLUSH_THRESHOLD: usize = 64 * 1024;
+
 #[derive(thiserror::Error, Debug)]
 pub enum MySqlStreamError {
     #[error("packet codec error: {0}")]
@@ -24,6 +40,9 @@ where
     codec: PacketCodec,
     inbound_buffer: BytesMut,
     outbound_buffer: BytesMut,
+    payload_buffer: BytesMut,
+    encode_buffer: Vec<u8>,
+    compressed: bool,
 }
 
 impl<TS> MySqlStream<TS>
@@ -31,12 +50,17 @@ where
     TcpStream: UpgradableStream<TS>,
     TS: AsyncRead + AsyncWrite + Unpin,
 {
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: TcpStream, max_packet_size: usize) -> Self {
+        let mut codec = PacketCodec::default();
+        codec.max_allowed_packet = max_packet_size;
         Self {
             stream: MaybeTlsStream::new(stream),
-            codec: PacketCodec::default(),
-            inbound_buffer: BytesMut::new(),
-            outbound_buffer: BytesMut::new(),
+            codec,
+            inbound_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            outbound_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            payload_buffer: BytesMut::new(),
+            encode_buffer: vec![],
+            compressed: false,
         }
     }
 
@@ -45,30 +69,71 @@ where
         packet: &'a P,
         context: C,
     ) -> Result<(), MySqlStreamError> {
-        let mut buf = vec![];
-        packet.encode_with(&mut buf, context);
-        self.codec.encode(&mut &*buf, &mut self.outbound_buffer)?;
+        self.encode_buffer.clear();
+        packet.encode_with(&mut self.encode_buffer, context);
+        self.codec
+            .encode(&mut &self.encode_buffer[..], &mut self.outbound_buffer)?;
         Ok(())
     }
 
+    /// Queues an already encoded packet payload without an intermediate copy
+    pub fn push_payload(&mut self, mut payload: Bytes) -> Result<(), MySqlStreamError> {
+        self.codec.encode(&mut payload, &mut self.outbound_buffer)?;
+        Ok(())
+    }
+
+    pub fn pending_outbound(&self) -> usize {
+        self.outbound_buffer.len()
+    }
+
+    /// Whether a complete packet has already been received and can be
+    /// decoded without waiting on the socket
+    pub fn has_buffered_input(&self) -> bool {
+        let header_size = if self.compressed {
+            COMPRESSED_PACKET_HEADER_SIZE
+        } else {
+            PACKET_HEADER_SIZE
+        };
+        let mut buffer: &[u8] = &self.inbound_buffer;
+        while let Some(&[l1, l2, l3]) = buffer.first_chunk::<3>() {
+            let len = u32::from_le_bytes([l1, l2, l3, 0]) as usize;
+            let Some(rest) = buffer.get(header_size + len..) else {
+                return false;
+            };
+            // Uncompressed payloads of the maximum length continue
+            // in the next packet
+            if self.compressed || len < MAX_PAYLOAD_LEN {
+                return true;
+            }
+            buffer = rest;
+        }
+        false
+    }
+
     pub async fn flush(&mut self) -> std::io::Result<()> {
         trace!(outbound_buffer=?self.outbound_buffer, "sending");
         self.stream.write_all(&self.outbound_buffer[..]).await?;
-        self.outbound_buffer = BytesMut::new();
+        self.outbound_buffer.clear();
         self.stream.flush().await?;
         Ok(())
     }
 
     pub async fn recv(&mut self) -> Result<Option<Bytes>, MySqlStreamError> {
-        let mut payload = BytesMut::new();
         loop {
             {
-                let got_full_packet = self.codec.decode(&mut self.inbound_buffer, &mut payload)?;
+                let got_full_packet = self
+                    .codec
+                    .decode(&mut self.inbound_buffer, &mut self.payload_buffer)?;
                 if got_full_packet {
+                    // Splitting off keeps the spare capacity around for the next
+                    // packet, and the allocation is reclaimed once the returned
+                    // payload is dropped
+                    let payload = self.payload_buffer.split().freeze();
                     trace!(?payload, "received");
-                    return Ok(Some(payload.freeze()));
+                    return Ok(Some(payload));
                 }
             }
+            self.inbound_buffer.reserve(READ_BUFFER_SIZE);
             let read_bytes = self.stream.read_buf(&mut self.inbound_buffer).await?;
             if read_bytes == 0 {
                 return Ok(None);
@@ -81,6 +146,13 @@ where
         self.codec.reset_seq_id();
     }
 
+    /// Switches to the compressed protocol - both sides do this right
+    /// after the authentication OK packet when `COMPRESS` was negotiated
+    pub fn enable_compression(&mut self) {
+        self.codec.compress(Compression::default());
+        self.compressed = true;
+    }
+
     pub async fn upgrade(
         mut self,
         config: <TcpStream as UpgradableStream<TS>>::UpgradeConfig,
@@ -96,4 +168,71 @@ where
             MaybeTlsStream::Upgrading => false,
         }
     }
+
+    pub fn tls(&self) -> Option<&TS> {
+        self.stream.tls()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    type Stream = MySqlStream<tokio_rustls::server::TlsStream<TcpStream>>;
+
+    async fn pair(max_packet_size: usize) -> (Stream, Stream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (client, server) = tokio::join!(TcpStream::connect(address), listener.accept());
+        (
+            MySqlStream::new(client.unwrap(), max_packet_size),
+            MySqlStream::new(server.unwrap().0, max_packet_size),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_compressed_large_packet() {
+        let size = 20 * 1024 * 1024;
+        let (mut a, mut b) = pair(size).await;
+        a.enable_compression();
+        b.enable_compression();
+
+        let payload = Bytes::from((0..size).map(|x| (x % 251) as u8).collect::<Vec<_>>());
+        a.push_payload(payload.clone()).unwrap();
+        a.push_payload(Bytes::from_static(b"\x03SELECT 1")).unwrap();
+        let (flushed, first) = tokio::join!(a.flush(), b.recv());
+        flushed.unwrap();
+
+        assert_eq!(first.unwrap(), Some(payload));
+        assert_eq!(
+            b.recv().await.unwrap(),
+            Some(Bytes::from_static(b"\x03SELECT 1"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_buffered_input() {
+        let (mut a, _b) = pair(1024).await;
+        assert!(!a.has_buffered_input());
+
+        a.inbound_buffer.extend_from_slice(b"\x09\0\0\0\x03SELECT");
+        assert!(!a.has_buffered_input());
+
+        a.inbound_buffer.extend_from_slice(b" 1");
+        assert!(a.has_buffered_input());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_packet() {
+        let (mut a, mut b) = pair(1024).await;
+        assert!(a.push_payload(Bytes::from(vec![0; 2048])).is_err());
+
+        let mut codec = PacketCodec::default();
+        let mut data = BytesMut::new();
+        codec.encode(&mut &[0; 2048][..], &mut data).unwrap();
+        a.stream.write_all(&data).await.unwrap();
+        assert!(b.recv().await.is_err());
+    }
 }
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bytes::{Buf, BufMut, BytesMut};
+use pgwire::error::PgWireResult;
+use pgwire::messages::startup::BackendKeyData;
+use pgwire::messages::Message;
+use rand::Rng;
+use tracing::*;
+use warpgate_common::helpers::rng::get_crypto_rng;
+use warpgate_common::TargetPostgresOptions;
+use warpgate_core::TargetResolver;
+
+use crate::client::PostgresClient;
+use crate::error::PostgresError;
+
+/// Sent on a new connection instead of a startup message to cancel
+/// the query running in the session identified by the key
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct CancelRequest {
+    pub pid: i32,
+    pub secret_key: i32,
+}
+
+impl CancelRequest {
+    const MESSAGE_SIZE: usize = 16;
+    const MAGIC_NUMBER: i32 = 80877102;
+}
+
+impl From<&BackendKeyData> for CancelRequest {
+    fn from(key: &BackendKeyData) -> Self {
+        Self {
+            pid: key.pid,
+            secret_key: key.secret_key,
+        }
+    }
+}
+
+impl From<CancelRequest> for BackendKeyData {
+    fn from(key: CancelRequest) -> Self {
+        BackendKeyData::new(key.pid, key.secret_key)
+    }
+}
+
+impl Message for CancelRequest {
+    #[inline]
+    fn message_type() -> Option<u8> {
+        None
+    }
+
+    #[inline]
+    fn message_length(&self) -> usize {
+        Self::MESSAGE_SIZE
+    }
+
+    fn encode_body(&self, buf: &mut BytesMut) -> PgWireResult<()> {
+        buf.put_i32(Self::MAGIC_NUMBER);
+        buf.put_i32(self.pid);
+        buf.put_i32(self.secret_key);
+        Ok(())
+    }
+
+    fn decode_body(buf: &mut BytesMut, _full_len: usize) -> PgWireResult<Self> {
+        buf.advance(4);
+        Ok(Self {
+            pid: buf.get_i32(),
+            secret_key: buf.get_i32(),
+        })
+    }
+
+    fn decode(buf: &mut BytesMut) -> PgWireResult<Option<Self>> {
+        if buf.remaining() >= Self::MESSAGE_SIZE
+            && (&buf[0..4]).get_i32() == Self::MESSAGE_SIZE as i32
+            && (&buf[4..8]).get_i32() == Self::MAGIC_NUMBER
+        {
+            buf.advance(4);
+            Self::decode_body(buf, Self::MESSAGE_SIZE).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// Where a cancel request has to go, given the key the client knows
+struct CancelTarget {
+    options: TargetPostgresOptions,
+    resolver: TargetResolver,
+    key: CancelRequest,
+}
+
+/// Clients only ever see backend keys made up by Warpgate, which are
+/// translated back to the target's own keys when a cancel request arrives
+#[derive(Default)]
+pub struct CancelKeys {
+    targets: Mutex<HashMap<CancelRequest, Option<CancelTarget>>>,
+}
+
+impl CancelKeys {
+    /// Makes up a new client-facing key. It isn't usable
+    /// until [CancelKeyRegistration::set_target] is called.
+    pub fn register(self: &Arc<Self>) -> CancelKeyRegistration {
+        let mut rng = get_crypto_rng();
+        let mut targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+        let key = loop {
+            let key = CancelRequest {
+                pid: rng.gen_range(1..i32::MAX),
+                secret_key: rng.gen(),
+            };
+            if !targets.contains_key(&key) {
+                break key;
+            }
+        };
+        targets.insert(key, None);
+        CancelKeyRegistration {
+            keys: self.clone(),
+            key,
+        }
+    }
+
+    /// Returns `false` if the key is unknown
+    pub async fn cancel(&self, request: CancelRequest) -> Result<bool, PostgresError> {
+        let target = {
+            let targets = self.targets.lock().unwrap_or_else(|e| e.into_inner());
+            match targets.get(&request) {
+                Some(Some(target)) => {
+                    Some((target.options.clone(), target.resolver.clone(), target.key))
+                }
+                // No query can be running without a target connection
+                Some(None) => return Ok(true),
+                None => None,
+            }
+        };
+        let Some((options, resolver, key)) = target else {
+            return Ok(false);
+        };
+        PostgresClient::send_cancel_request(&options, &resolver, key).await?;
+        Ok(true)
+    }
+}
+
+/// Keeps a client-facing key valid for as long as the session lasts
+pub struct CancelKeyRegistration {
+    keys: Arc<CancelKeys>,
+    key: CancelRequest,
+}
+
+impl CancelKeyRegistration {
+    pub fn client_key(&self) -> BackendKeyData {
+        self.key.into()
+    }
+
+    /// Points the key at the target connection that is currently in use
+    pub fn set_target(
+        &self,
+        target: Option<(&TargetPostgresOptions, &TargetResolver, CancelRequest)>,
+    ) {
+        let target = target.map(|(options, resolver, key)| CancelTarget {
+            options: options.clone(),
+            resolver: resolver.clone(),
+            key,
+        });
+        self.keys
+            .targets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(self.key, target);
+    }
+}
+
+impl Drop for CancelKeyRegistration {
+    fn drop(&mut self) {
+        self.keys
+            .targets
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.key);
+        trace!(pid = self.key.pid, "Released cancel key");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_request_roundtrip() {
+        let request = CancelRequest {
+            pid: 1234,
+            secret_key: -5678,
+        };
+        let mut buf = BytesMut::new();
+        request.encode(&mut buf).unwrap();
+        assert_eq!(buf.len(), 16);
+        assert_eq!(CancelRequest::decode(&mut buf).unwrap(), Some(request));
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::new();
+        pgwire::messages::startup::SslRequest::new()
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(CancelRequest::decode(&mut buf).unwrap(), None);
+    }
+
+    #[test]
+    fn test_registration() {
+        let keys = Arc::new(CancelKeys::default());
+        let registration = keys.register();
+        let key = CancelRequest::from(&registration.client_key());
+        assert!(keys.targets.lock().unwrap().contains_key(&key));
+        drop(registration);
+        assert!(keys.targets.lock().unwrap().is_empty());
+    }
+}
@@ -3,19 +3,26 @@ use std::fmt::Debug;
 use std::io::Write;
 use std::sync::Arc;
 
+use bytes::Bytes;
+use pgwire::messages::simplequery::Query;
+use pgwire::messages::startup::ParameterStatus;
 use pgwire::messages::PgWireBackendMessage;
 use rsasl::config::SASLConfig;
 use rsasl::prelude::{Mechname, SASLClient};
 use tokio::net::TcpStream;
 use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::ClientConnection;
 use tracing::*;
 use warpgate_common::{configure_tls_connector, TargetPostgresOptions, TlsMode};
+use warpgate_core::{NegotiatedCapabilities, TargetResolver};
 
+use crate::cancel::CancelRequest;
 use crate::error::PostgresError;
 use crate::stream::{PgWireGenericBackendMessage, PostgresEncode, PostgresStream};
 
 pub struct PostgresClient {
     pub stream: PostgresStream<TlsStream<TcpStream>>,
+    pub auth_method: String,
 }
 
 pub struct ConnectionOptions {
@@ -36,7 +43,7 @@ impl Default for ConnectionOptions {
 
 struct SaslBufferWriter<'a>(&'a mut Option<Vec<u8>>);
 
-impl<'a> Write for SaslBufferWriter<'a> {
+impl Write for SaslBufferWriter<'_> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         if let Some(data) = self.0.as_mut() {
             data.extend_from_slice(buf);
@@ -52,59 +59,22 @@ impl<'a> Write for SaslBufferWriter<'a> {
 }
 
 impl PostgresClient {
+    pub fn tls_connection(&self) -> Option<&ClientConnection> {
+        Some(self.stream.tls()?.get_ref().1)
+    }
+
+    pub fn negotiated_capabilities(&self) -> NegotiatedCapabilities {
+        let mut capabilities = NegotiatedCapabilities::new().with_tls(self.tls_connection());
+        capabilities.auth_method = Some(self.auth_method.clone());
+        capabilities
+    }
+
     pub async fn connect(
         target: &TargetPostgresOptions,
+        resolver: &TargetResolver,
         options: ConnectionOptions,
     ) -> Result<Self, PostgresError> {
-        let mut stream =
-            PostgresStream::new(TcpStream::connect((target.host.clone(), target.port)).await?);
-
-        if target.tls.mode != TlsMode::Disabled {
-            stream.push(pgwire::messages::startup::SslRequest::new())?;
-            stream.flush().await?;
-
-            let Some(response) = stream
-                .recv::<pgwire::messages::response::SslResponse>()
-                .await?
-            else {
-                return Err(PostgresError::Eof);
-            };
-
-            match target.tls.mode {
-                TlsMode::Disabled => unreachable!(),
-                TlsMode::Required => {
-                    if response == pgwire::messages::response::SslResponse::Refuse {
-                        return Err(PostgresError::TlsNotSupported);
-                    }
-                }
-                TlsMode::Preferred => {
-                    if response == pgwire::messages::response::SslResponse::Refuse {
-                        warn!("TLS not supported by target");
-                    }
-                }
-            }
-
-            if response == pgwire::messages::response::SslResponse::Accept {
-                let accept_invalid_certs = !target.tls.verify;
-                let accept_invalid_hostname = false; // ca + hostname verification
-                let client_config = Arc::new(
-                    configure_tls_connector(accept_invalid_certs, accept_invalid_hostname, None)
-                        .await?,
-                );
-
-                stream = stream
-                    .upgrade((
-                        target
-                            .host
-                            .clone()
-                            .try_into()
-                            .map_err(|_| PostgresError::InvalidDomainName)?,
-                        client_config,
-                    ))
-                    .await?;
-                info!("Target connection upgraded to TLS");
-            }
-        }
+        let mut stream = Self::open_stream(target, resolver).await?;
 
         let mut startup = pgwire::messages::startup::Startup::new();
         startup.parameters = options.parameters.clone();
@@ -117,6 +87,7 @@ impl PostgresClient {
         stream.push(startup)?;
         stream.flush().await?;
 
+        let mut auth_method = "trust".to_owned();
         loop {
             let Some(payload) = stream.recv::<PgWireGenericBackendMessage>().await? else {
                 return Err(PostgresError::Eof);
@@ -126,6 +97,7 @@ impl PostgresClient {
                 target
                     .password
                     .as_ref()
+                    .map(|x| x.expose_secret())
                     .ok_or(PostgresError::PasswordRequired)
             };
 
@@ -139,6 +111,7 @@ impl PostgresClient {
                         break;
                     }
                     pgwire::messages::startup::Authentication::CleartextPassword => {
+                        auth_method = "password".into();
                         let password = get_password()?;
                         let password_message =
                             pgwire::messages::startup::Password::new(password.into());
@@ -146,6 +119,7 @@ impl PostgresClient {
                         stream.flush().await?;
                     }
                     pgwire::messages::startup::Authentication::MD5Password(scramble) => {
+                        auth_method = "md5".into();
                         let password = get_password()?;
                         let hashed = pgwire::api::auth::md5pass::hash_md5_password(
                             &target.username,
@@ -158,7 +132,7 @@ impl PostgresClient {
                     }
                     pgwire::messages::startup::Authentication::SASL(mechanisms) => {
                         let password = get_password()?;
-                        PostgresClient::run_sasl_auth(
+                        auth_method = PostgresClient::run_sasl_auth(
                             &mut stream,
                             mechanisms,
                             &target.username,
@@ -181,7 +155,80 @@ impl PostgresClient {
             }
         }
 
-        Ok(Self { stream })
+        Ok(Self {
+            stream,
+            auth_method,
+        })
+    }
+
+    /// Connects to the target and negotiates TLS
+    async fn open_stream(
+        target: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+    ) -> Result<PostgresStream<TlsStream<TcpStream>>, PostgresError> {
+        let mut stream = PostgresStream::new(resolver.connect(&target.host, target.port).await?);
+
+        if target.tls.mode != TlsMode::Disabled {
+            stream.push(pgwire::messages::startup::SslRequest::new())?;
+            stream.flush().await?;
+
+            let Some(response) = stream
+                .recv::<pgwire::messages::response::SslResponse>()
+                .await?
+            else {
+                return Err(PostgresError::Eof);
+            };
+
+            match target.tls.mode {
+                TlsMode::Disabled => unreachable!(),
+                TlsMode::Required => {
+                    if response == pgwire::messages::response::SslResponse::Refuse {
+                        return Err(PostgresError::TlsNotSupported);
+                    }
+                }
+                TlsMode::Preferred => {
+                    if response == pgwire::messages::response::SslResponse::Refuse {
+                        warn!("TLS not supported by target");
+                    }
+                }
+            }
+
+            if response == pgwire::messages::response::SslResponse::Accept {
+                let accept_invalid_certs = !target.tls.verify;
+                let accept_invalid_hostname = false; // ca + hostname verification
+                let client_config = Arc::new(
+                    configure_tls_connector(accept_invalid_certs, accept_invalid_hostname, None)
+                        .await?,
+                );
+
+                stream = stream
+                    .upgrade((
+                        target
+                            .host
+                            .clone()
+                            .try_into()
+                            .map_err(|_| PostgresError::InvalidDomainName)?,
+                        client_config,
+                    ))
+                    .await?;
+                info!("Target connection upgraded to TLS");
+            }
+        }
+
+        Ok(stream)
+    }
+
+    /// Cancel requests are sent on a separate connection,
+    /// which the target closes without a response
+    pub async fn send_cancel_request(
+        target: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+        key: CancelRequest,
+    ) -> Result<(), PostgresError> {
+        let mut stream = Self::open_stream(target, resolver).await?;
+        stream.push(key)?;
+        stream.flush().await?;
+        Ok(())
     }
 
     async fn run_sasl_auth(
@@ -189,7 +236,7 @@ impl PostgresClient {
         mechanisms: Vec<String>,
         username: &str,
         password: &str,
-    ) -> Result<(), PostgresError> {
+    ) -> Result<String, PostgresError> {
         let cfg = SASLConfig::with_credentials(None, username.into(), password.into())?;
         let sasl = SASLClient::new(cfg);
         let mut session = sasl.start_suggested(
@@ -207,6 +254,7 @@ impl PostgresClient {
             ));
         }
 
+        let mut mechanism = String::new();
         let mut is_first_response = true;
         while {
             let mut data_to_send = None;
@@ -220,6 +268,7 @@ impl PostgresClient {
                 if is_first_response {
                     let selected_mechanism = session.get_mechname();
                     debug!("Selected SASL mechanism: {selected_mechanism:?}");
+                    mechanism = selected_mechanism.to_string();
                     stream.push(pgwire::messages::startup::SASLInitialResponse::new(
                         selected_mechanism.to_string(),
                         Some(data.into()),
@@ -257,14 +306,81 @@ impl PostgresClient {
             }
         }
 
-        Ok(())
+        Ok(mechanism)
+    }
+
+    /// Reads the rest of the startup phase, up to and including
+    /// the first `ReadyForQuery`
+    pub async fn finish_startup(&mut self) -> Result<Vec<PgWireBackendMessage>, PostgresError> {
+        let mut messages = vec![];
+        loop {
+            let Some(message) = self.stream.recv::<PgWireGenericBackendMessage>().await? else {
+                return Err(PostgresError::Eof);
+            };
+            match message.0 {
+                PgWireBackendMessage::ErrorResponse(error) => return Err(error.into()),
+                PgWireBackendMessage::ReadyForQuery(_) => {
+                    messages.push(message.0);
+                    return Ok(messages);
+                }
+                message => messages.push(message),
+            }
+        }
+    }
+
+    /// Runs the target's initialization statements one by one, returning
+    /// the parameter changes that they have caused
+    pub async fn run_init_statements(
+        &mut self,
+        statements: &[String],
+    ) -> Result<Vec<ParameterStatus>, PostgresError> {
+        let mut parameters = vec![];
+        for statement in statements {
+            for tag in self.run_statement(statement, &mut parameters).await? {
+                info!(%statement, result=%tag, "Ran initialization statement");
+            }
+        }
+        Ok(parameters)
+    }
+
+    /// Runs a simple query without logging it, returning its command tags
+    pub async fn run_statement(
+        &mut self,
+        statement: &str,
+        parameters: &mut Vec<ParameterStatus>,
+    ) -> Result<Vec<String>, PostgresError> {
+        self.send(Query::new(statement.to_owned())).await?;
+        let mut tags = vec![];
+        let mut error = None;
+        loop {
+            let Some(message) = self.stream.recv::<PgWireGenericBackendMessage>().await? else {
+                return Err(PostgresError::Eof);
+            };
+            match message.0 {
+                PgWireBackendMessage::CommandComplete(complete) => tags.push(complete.tag),
+                PgWireBackendMessage::ParameterStatus(status) => parameters.push(status),
+                PgWireBackendMessage::ErrorResponse(response) => error = Some(response),
+                PgWireBackendMessage::ReadyForQuery(_) => break,
+                _ => (),
+            }
+        }
+        if let Some(error) = error {
+            let message = error
+                .fields
+                .into_iter()
+                .find(|(field, _)| *field == b'M')
+                .map(|(_, message)| message)
+                .unwrap_or_default();
+            return Err(PostgresError::InitStatementFailed {
+                statement: statement.to_owned(),
+                message,
+            });
+        }
+        Ok(tags)
     }
 
-    pub async fn recv(&mut self) -> Result<Option<PgWireGenericBackendMessage>, PostgresError> {
-        self.stream
-            .recv::<PgWireGenericBackendMessage>()
-            .await
-            .map_err(Into::into)
+    pub async fn recv_frame(&mut self) -> Result<Option<Bytes>, PostgresError> {
+        self.stream.recv_frame().await.map_err(Into::into)
     }
 
     pub async fn send<M: PostgresEncode + Debug>(
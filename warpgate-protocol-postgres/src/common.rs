@@ -1,3 +1,49 @@
 use warpgate_common::ProtocolName;
 
 pub const PROTOCOL_NAME: ProtocolName = "PostgreSQL";
+
+/// Set with `PGOPTIONS='-c warpgate.work_item=INC-123'` to tag the session
+const WORK_ITEM_SETTING: &str = "warpgate.work_item";
+
+/// Takes the work item setting out of the `options` startup parameter,
+/// returning it along with the options to pass on to the target
+pub fn take_work_item(options: &str) -> (Option<String>, String) {
+    let mut work_item = None;
+    let mut rest = vec![];
+    let mut tokens = options.split_whitespace();
+    while let Some(token) = tokens.next() {
+        let (prefix, setting) = match token {
+            "-c" => match tokens.next() {
+                Some(setting) => ("-c ", setting),
+                None => ("", token),
+            },
+            _ => match token.strip_prefix("--").or(token.strip_prefix("-c")) {
+                Some(setting) => (&token[..2], setting),
+                None => ("", token),
+            },
+        };
+        match setting.split_once('=') {
+            Some((WORK_ITEM_SETTING, value)) => work_item = Some(value.to_owned()),
+            _ => rest.push(format!("{prefix}{setting}")),
+        }
+    }
+    (work_item, rest.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_work_item() {
+        assert_eq!(
+            take_work_item("-c warpgate.work_item=INC-1 -c geqo=off"),
+            (Some("INC-1".into()), "-c geqo=off".into())
+        );
+        assert_eq!(
+            take_work_item("--warpgate.work_item=INC-2 -cgeqo=off"),
+            (Some("INC-2".into()), "-cgeqo=off".into())
+        );
+        assert_eq!(take_work_item("-c geqo=off"), (None, "-c geqo=off".into()));
+    }
+}
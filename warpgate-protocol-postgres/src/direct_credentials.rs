@@ -0,0 +1,93 @@
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use warpgate_common::{Target, TargetOptions, TargetPostgresOptions, WarpgateError};
+use warpgate_core::{DirectCredentialProvisioner, TargetResolver};
+
+use crate::client::{ConnectionOptions, PostgresClient};
+use crate::error::PostgresError;
+
+pub struct PostgresCredentialProvisioner;
+
+impl PostgresCredentialProvisioner {
+    async fn run(
+        target: &Target,
+        resolver: &TargetResolver,
+        statements: &[String],
+    ) -> Result<(), WarpgateError> {
+        let TargetOptions::Postgres(ref options) = target.options else {
+            return Err(WarpgateError::InconsistentState);
+        };
+        run_statements(options, resolver, statements)
+            .await
+            .map_err(|e| match e {
+                // Don't pass on the statement, it might contain the password
+                PostgresError::InitStatementFailed { message, .. } => {
+                    anyhow::anyhow!(message).into()
+                }
+                e => WarpgateError::other(e),
+            })
+    }
+}
+
+async fn run_statements(
+    options: &TargetPostgresOptions,
+    resolver: &TargetResolver,
+    statements: &[String],
+) -> Result<(), PostgresError> {
+    let mut connection_options = ConnectionOptions::default();
+    connection_options
+        .parameters
+        .insert("database".into(), "postgres".into());
+    let mut client = PostgresClient::connect(options, resolver, connection_options).await?;
+    client.finish_startup().await?;
+    let mut parameters = vec![];
+    for statement in statements {
+        client.run_statement(statement, &mut parameters).await?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl DirectCredentialProvisioner for PostgresCredentialProvisioner {
+    async fn create_user(
+        &self,
+        target: &Target,
+        resolver: &TargetResolver,
+        username: &str,
+        password: &str,
+        expires: DateTime<Utc>,
+    ) -> Result<(), WarpgateError> {
+        let mut statements = vec![format!(
+            "CREATE ROLE \"{username}\" WITH LOGIN PASSWORD '{password}' VALID UNTIL '{}'",
+            expires.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )];
+        if let Some(options) = target.options.direct_credentials() {
+            statements.extend(
+                options
+                    .grant_statements
+                    .iter()
+                    .map(|x| x.replace("{username}", &format!("\"{username}\""))),
+            );
+        }
+        Self::run(target, resolver, &statements).await
+    }
+
+    async fn drop_user(
+        &self,
+        target: &Target,
+        resolver: &TargetResolver,
+        username: &str,
+    ) -> Result<(), WarpgateError> {
+        Self::run(
+            target,
+            resolver,
+            &[
+                format!("SELECT pg_terminate_backend(pid) FROM pg_stat_activity WHERE usename = '{username}'"),
+                format!(
+                    "DO $$ BEGIN IF EXISTS (SELECT FROM pg_roles WHERE rolname = '{username}') THEN DROP OWNED BY \"{username}\"; DROP ROLE \"{username}\"; END IF; END $$"
+                ),
+            ],
+        )
+        .await
+    }
+}
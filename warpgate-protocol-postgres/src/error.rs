@@ -38,6 +38,8 @@ pub enum PostgresError {
     SaslSession(#[from] SessionError),
     #[error("Password is required for authentication")]
     PasswordRequired,
+    #[error("initialization statement {statement:?} failed: {message}")]
+    InitStatementFailed { statement: String, message: String },
     #[error(transparent)]
     Warpgate(#[from] WarpgateError),
     #[error(transparent)]
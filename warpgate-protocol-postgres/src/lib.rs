@@ -1,35 +1,56 @@
+mod cancel;
 mod client;
 mod common;
+mod direct_credentials;
 mod error;
+mod pool;
+mod prepared;
+mod recording;
 mod session;
 mod session_handle;
+mod shadow;
 mod stream;
 
 use std::fmt::Debug;
 use std::sync::Arc;
 
 use anyhow::{Context, Result};
+use cancel::CancelKeys;
 use client::{ConnectionOptions, PostgresClient};
+use direct_credentials::PostgresCredentialProvisioner;
+use error::PostgresError;
 use futures::TryStreamExt;
+use pool::PostgresPools;
 use rustls::server::NoClientAuth;
 use rustls::ServerConfig;
 use session::PostgresSession;
 use session_handle::PostgresSessionHandle;
+use tokio::sync::mpsc;
 use tracing::*;
 use warpgate_common::{
     ListenEndpoint, ResolveServerCert, Target, TargetOptions, TlsCertificateAndPrivateKey,
     TlsCertificateBundle, TlsPrivateKey,
 };
-use warpgate_core::{ProtocolServer, Services, SessionStateInit, TargetTestError};
+use warpgate_core::{
+    ProtocolServer, Services, SessionStateInit, TargetTestError, CLOSE_MESSAGE_TIMEOUT,
+};
 
 pub struct PostgresProtocolServer {
     services: Services,
+    pools: Arc<PostgresPools>,
+    cancel_keys: Arc<CancelKeys>,
 }
 
 impl PostgresProtocolServer {
     pub async fn new(services: &Services) -> Result<Self> {
+        services.direct_credential_provisioners.register(
+            crate::common::PROTOCOL_NAME,
+            Arc::new(PostgresCredentialProvisioner),
+        );
         Ok(PostgresProtocolServer {
             services: services.clone(),
+            pools: Arc::new(PostgresPools::default()),
+            cancel_keys: Arc::new(CancelKeys::default()),
         })
     }
 }
@@ -37,7 +58,7 @@ impl PostgresProtocolServer {
 impl ProtocolServer for PostgresProtocolServer {
     async fn run(self, address: ListenEndpoint) -> Result<()> {
         let certificate_and_key = {
-            let config = self.services.config.lock().await;
+            let config = self.services.config.load();
             let certificate_path = config
                 .paths_relative_to
                 .join(&config.store.postgres.certificate);
@@ -68,7 +89,8 @@ impl ProtocolServer for PostgresProtocolServer {
         ))));
 
         info!(?address, "Listening");
-        let mut listener = address.tcp_accept_stream().await?;
+        let socket_options = self.services.config.load().store.postgres.socket.clone();
+        let mut listener = address.tcp_accept_stream(&socket_options).await?;
         loop {
             let Some(stream) = listener.try_next().await? else {
                 return Ok(());
@@ -76,8 +98,15 @@ impl ProtocolServer for PostgresProtocolServer {
 
             let remote_address = stream.peer_addr()?;
 
+            if !self.services.ip_bans.is_allowed(remote_address.ip()) {
+                debug!(%remote_address, "Dropping a connection from a banned address");
+                continue;
+            }
+
             let tls_config = tls_config.clone();
             let services = self.services.clone();
+            let pools = self.pools.clone();
+            let cancel_keys = self.cancel_keys.clone();
             tokio::spawn(async move {
                 let (session_handle, mut abort_rx) = PostgresSessionHandle::new();
 
@@ -94,23 +123,34 @@ impl ProtocolServer for PostgresProtocolServer {
                     )
                     .await?;
 
+                let span = server_handle.lock().await.context().span().clone();
+                let (close_message_tx, close_message_rx) = mpsc::unbounded_channel();
                 let session = PostgresSession::new(
                     server_handle,
                     services,
                     stream,
                     tls_config,
                     remote_address,
+                    pools,
+                    cancel_keys,
+                    close_message_rx,
                 )
                 .await;
 
-                let span = session.make_logging_span();
+                let run = session.run().instrument(span);
+                tokio::pin!(run);
                 tokio::select! {
-                    result = session.run().instrument(span) => match result {
+                    result = &mut run => match result {
                         Ok(_) => info!("Session ended"),
                         Err(e) => error!(error=%e, "Session failed"),
                     },
-                    _ = abort_rx.recv() => {
+                    message = abort_rx.recv() => {
                         warn!("Session aborted by admin");
+                        if let Some(Some(message)) = message {
+                            // Give the session a chance to tell the client why
+                            let _ = close_message_tx.send(message);
+                            let _ = tokio::time::timeout(CLOSE_MESSAGE_TIMEOUT, run).await;
+                        }
                     },
                 }
 
@@ -120,6 +160,7 @@ impl ProtocolServer for PostgresProtocolServer {
     }
 
     async fn test_target(&self, target: Target) -> Result<(), TargetTestError> {
+        let resolver = self.services.dns.for_target(&target);
         let TargetOptions::Postgres(options) = target.options else {
             return Err(TargetTestError::Misconfigured(
                 "Not a PostgreSQL target".to_owned(),
@@ -129,9 +170,39 @@ impl ProtocolServer for PostgresProtocolServer {
         conn_options
             .parameters
             .insert("database".into(), "postgres".into());
-        PostgresClient::connect(&options, conn_options)
-            .await
-            .map_err(|e| TargetTestError::ConnectionError(format!("{e}")))?;
+        async {
+            let mut client = PostgresClient::connect(&options, &resolver, conn_options).await?;
+            self.services
+                .target_capabilities
+                .record(&target.name, client.negotiated_capabilities());
+            if !options.init_statements.is_empty() {
+                client.finish_startup().await?;
+                client.run_init_statements(&options.init_statements).await?;
+            }
+            Ok(())
+        }
+        .await
+        .map_err(|e| match e {
+            PostgresError::Tls(_)
+            | PostgresError::TlsSetup(_)
+            | PostgresError::TlsNotSupported
+            | PostgresError::InvalidDomainName => TargetTestError::Untrusted(format!("{e}")),
+            PostgresError::PasswordRequired => TargetTestError::AuthenticationError,
+            // SQLSTATE class 28: invalid authorization specification
+            PostgresError::RemoteError(ref response)
+                if response
+                    .fields
+                    .iter()
+                    .any(|(field, value)| *field == b'C' && value.starts_with("28")) =>
+            {
+                TargetTestError::AuthenticationError
+            }
+            PostgresError::Io(e) => TargetTestError::Io(e),
+            PostgresError::InitStatementFailed { .. } => {
+                TargetTestError::Misconfigured(format!("{e}"))
+            }
+            e => TargetTestError::ConnectionError(format!("{e}")),
+        })?;
         Ok(())
     }
 }
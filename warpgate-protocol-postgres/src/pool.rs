@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use pgwire::messages::response::READY_STATUS_IDLE;
+use pgwire::messages::PgWireBackendMessage;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{PostgresTransactionPooling, TargetPostgresOptions};
+use warpgate_core::TargetResolver;
+
+use crate::cancel::CancelRequest;
+use crate::client::{ConnectionOptions, PostgresClient};
+use crate::error::PostgresError;
+
+/// Connections are only reused between sessions of the same Warpgate user
+/// that would have opened an identical connection themselves, so that
+/// session state (`SET ROLE`, temporary tables, etc.) can't leak to
+/// other users
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct PoolKey {
+    target: String,
+    host: String,
+    port: u16,
+    username: String,
+    warpgate_username: String,
+    /// Client startup parameters, including the database
+    parameters: BTreeMap<String, String>,
+}
+
+/// An authenticated target connection that has no transaction open
+/// in between leases
+pub struct PooledConnection {
+    pub id: Uuid,
+    pub client: PostgresClient,
+    /// `ParameterStatus` values sent by the target after startup
+    pub parameters: Vec<(String, String)>,
+    pub backend_key: Option<CancelRequest>,
+    idle_since: Instant,
+}
+
+struct Pool {
+    idle: Vec<PooledConnection>,
+    slots: Arc<Semaphore>,
+    max_connections: u32,
+}
+
+impl Pool {
+    fn new(max_connections: u32) -> Self {
+        Pool {
+            idle: vec![],
+            slots: Arc::new(Semaphore::new(max_connections.max(1) as usize)),
+            max_connections,
+        }
+    }
+}
+
+/// Target connections shared by transaction-pooled sessions,
+/// one pool per target, user and set of startup parameters
+#[derive(Default)]
+pub struct PostgresPools {
+    pools: Mutex<HashMap<PoolKey, Pool>>,
+}
+
+impl PostgresPools {
+    /// Waits for a free connection slot, reusing an idle connection if possible
+    pub async fn acquire(
+        self: &Arc<Self>,
+        target_name: &str,
+        options: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+        pooling: &PostgresTransactionPooling,
+        warpgate_username: &str,
+        parameters: &BTreeMap<String, String>,
+    ) -> Result<PoolLease, PostgresError> {
+        let mut parameters = parameters.clone();
+        // Replaced with the target's username when connecting
+        parameters.remove("user");
+        let key = PoolKey {
+            target: target_name.to_owned(),
+            host: options.host.clone(),
+            port: options.port,
+            username: options.username.clone(),
+            warpgate_username: warpgate_username.to_owned(),
+            parameters,
+        };
+        let idle_timeout = Duration::from_secs(pooling.idle_timeout_seconds);
+
+        let slots = {
+            let mut pools = self.pools.lock().await;
+            let pool = pools
+                .entry(key.clone())
+                .or_insert_with(|| Pool::new(pooling.max_connections));
+            if pool.max_connections != pooling.max_connections {
+                *pool = Pool::new(pooling.max_connections);
+            }
+            pool.slots.clone()
+        };
+
+        #[allow(clippy::expect_used)]
+        let permit = slots
+            .acquire_owned()
+            .await
+            .expect("pool semaphore is never closed");
+
+        let connection = {
+            let mut pools = self.pools.lock().await;
+            let pool = pools.get_mut(&key);
+            let mut connection = None;
+            if let Some(pool) = pool {
+                pool.idle.retain(|x| x.idle_since.elapsed() < idle_timeout);
+                connection = pool.idle.pop();
+            }
+            connection
+        };
+
+        let connection = match connection {
+            Some(connection) => connection,
+            None => Self::connect(options, resolver, &key.parameters).await?,
+        };
+
+        Ok(PoolLease {
+            connection: Some(connection),
+            key,
+            pools: self.clone(),
+            _permit: permit,
+        })
+    }
+
+    async fn connect(
+        options: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+        parameters: &BTreeMap<String, String>,
+    ) -> Result<PooledConnection, PostgresError> {
+        let database = parameters.get("database").cloned().unwrap_or_default();
+        let connection_options = ConnectionOptions {
+            parameters: parameters.clone(),
+            ..Default::default()
+        };
+        let mut client = PostgresClient::connect(options, resolver, connection_options).await?;
+
+        // Collect the startup parameters so that they can be replayed
+        // to every session using this connection
+        let mut parameters = vec![];
+        let mut backend_key = None;
+        for message in client.finish_startup().await? {
+            match message {
+                PgWireBackendMessage::ParameterStatus(status) => {
+                    parameters.push((status.name, status.value))
+                }
+                PgWireBackendMessage::BackendKeyData(key) => backend_key = Some((&key).into()),
+                _ => (),
+            }
+        }
+        for status in client.run_init_statements(&options.init_statements).await? {
+            parameters.push((status.name, status.value))
+        }
+
+        let id = Uuid::new_v4();
+        info!(connection=%id, %database, "Opened pooled target connection");
+        Ok(PooledConnection {
+            id,
+            client,
+            parameters,
+            backend_key,
+            idle_since: Instant::now(),
+        })
+    }
+
+    async fn put_back(&self, key: PoolKey, mut connection: PooledConnection) {
+        connection.idle_since = Instant::now();
+        if let Some(pool) = self.pools.lock().await.get_mut(&key) {
+            pool.idle.push(connection);
+        }
+    }
+}
+
+/// Exclusive use of a pooled connection. Dropping the lease without
+/// [PoolLease::release] closes the connection, since it might be
+/// in the middle of a transaction.
+pub struct PoolLease {
+    connection: Option<PooledConnection>,
+    key: PoolKey,
+    pools: Arc<PostgresPools>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PoolLease {
+    #[allow(clippy::expect_used)]
+    pub fn connection(&mut self) -> &mut PooledConnection {
+        self.connection
+            .as_mut()
+            .expect("connection is only taken on release")
+    }
+
+    pub fn has_buffered_frame(&self) -> bool {
+        self.connection
+            .as_ref()
+            .is_some_and(|x| x.client.stream.has_buffered_frame())
+    }
+
+    /// Returns the connection to the pool
+    pub async fn release(mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pools.put_back(self.key.clone(), connection).await;
+        }
+    }
+}
+
+/// Whether a frame is a `ReadyForQuery` that leaves the connection
+/// outside of a transaction
+pub fn is_ready_for_query_idle(frame: &[u8]) -> bool {
+    frame.first() == Some(&b'Z') && frame.get(5) == Some(&READY_STATUS_IDLE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ready_for_query() {
+        assert!(is_ready_for_query_idle(b"Z\0\0\0\x05I"));
+        assert!(!is_ready_for_query_idle(b"Z\0\0\0\x05T"));
+        assert!(!is_ready_for_query_idle(b"Z\0\0\0\x05E"));
+        assert!(!is_ready_for_query_idle(b"C\0\0\0\x05I"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use pgwire::messages::extendedquery::{Bind, Close, Execute, Parse, TARGET_TYPE_BYTE_STATEMENT};
+
+/// Statements that are prepared but never closed don't grow the map past this
+const MAX_TRACKED: usize = 1000;
+
+/// Longer parameter values are cut off in logs
+const MAX_VALUE_LENGTH: usize = 256;
+
+struct Portal {
+    query: Arc<str>,
+    parameters: usize,
+    values: Option<Vec<String>>,
+}
+
+/// What an `Execute` message is going to run
+pub struct ExecutedStatement<'a> {
+    pub query: &'a str,
+    pub parameters: usize,
+    /// Only collected when `log.query_parameters` is enabled
+    pub values: Option<&'a [String]>,
+}
+
+/// Follows the extended query protocol messages of a client so that
+/// `Execute` can be logged with the statement text instead of a name
+pub struct PreparedStatements {
+    statements: HashMap<String, Arc<str>>,
+    portals: HashMap<String, Portal>,
+    log_values: bool,
+}
+
+impl PreparedStatements {
+    pub fn new(log_values: bool) -> Self {
+        Self {
+            statements: HashMap::new(),
+            portals: HashMap::new(),
+            log_values,
+        }
+    }
+
+    pub fn parse(&mut self, msg: &Parse) {
+        let name = msg.name.clone().unwrap_or_default();
+        if self.statements.len() >= MAX_TRACKED && !self.statements.contains_key(&name) {
+            return;
+        }
+        self.statements.insert(name, msg.query.as_str().into());
+    }
+
+    pub fn bind(&mut self, msg: &Bind) {
+        let name = msg.portal_name.clone().unwrap_or_default();
+        let statement = msg.statement_name.as_deref().unwrap_or_default();
+        let Some(query) = self.statements.get(statement).cloned() else {
+            self.portals.remove(&name);
+            return;
+        };
+        if self.portals.len() >= MAX_TRACKED && !self.portals.contains_key(&name) {
+            return;
+        }
+        let values = self.log_values.then(|| {
+            msg.parameters
+                .iter()
+                .enumerate()
+                .map(|(index, value)| {
+                    format_value(
+                        value.as_ref(),
+                        format_code(&msg.parameter_format_codes, index),
+                    )
+                })
+                .collect()
+        });
+        self.portals.insert(
+            name,
+            Portal {
+                query,
+                parameters: msg.parameters.len(),
+                values,
+            },
+        );
+    }
+
+    pub fn close(&mut self, msg: &Close) {
+        let name = msg.name.as_deref().unwrap_or_default();
+        if msg.target_type == TARGET_TYPE_BYTE_STATEMENT {
+            self.statements.remove(name);
+        } else {
+            self.portals.remove(name);
+        }
+    }
+
+    pub fn execute(&self, msg: &Execute) -> Option<ExecutedStatement<'_>> {
+        let portal = self.portals.get(msg.name.as_deref().unwrap_or_default())?;
+        Some(ExecutedStatement {
+            query: &portal.query,
+            parameters: portal.parameters,
+            values: portal.values.as_deref(),
+        })
+    }
+}
+
+/// A single format code applies to all parameters, none means text
+fn format_code(codes: &[i16], index: usize) -> i16 {
+    match codes {
+        [] => 0,
+        [code] => *code,
+        codes => codes.get(index).copied().unwrap_or(0),
+    }
+}
+
+fn format_value(value: Option<&Bytes>, format: i16) -> String {
+    let Some(value) = value else {
+        return "NULL".into();
+    };
+    if format != 0 {
+        return format!("<{} bytes>", value.len());
+    }
+    let text = String::from_utf8_lossy(value);
+    match text.char_indices().nth(MAX_VALUE_LENGTH) {
+        Some((cutoff, _)) => format!("{}…", &text[..cutoff]),
+        None => text.into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(name: &str, query: &str) -> Parse {
+        Parse::new(Some(name.into()), query.into(), vec![])
+    }
+
+    fn bind(portal: &str, statement: &str, formats: Vec<i16>, values: Vec<Option<&str>>) -> Bind {
+        Bind::new(
+            Some(portal.into()),
+            Some(statement.into()),
+            formats,
+            values
+                .into_iter()
+                .map(|x| x.map(|x| Bytes::copy_from_slice(x.as_bytes())))
+                .collect(),
+            vec![],
+        )
+    }
+
+    fn execute(portal: &str) -> Execute {
+        Execute::new(Some(portal.into()), 0)
+    }
+
+    #[test]
+    fn test_execute_shows_statement() {
+        let mut statements = PreparedStatements::new(true);
+        statements.parse(&parse("s1", "SELECT $1, $2, $3"));
+        statements.bind(&bind(
+            "p1",
+            "s1",
+            vec![0, 0, 1],
+            vec![Some("a"), None, Some("xy")],
+        ));
+
+        let executed = statements.execute(&execute("p1")).unwrap();
+        assert_eq!(executed.query, "SELECT $1, $2, $3");
+        assert_eq!(executed.parameters, 3);
+        assert_eq!(
+            executed.values,
+            Some(&["a".to_owned(), "NULL".to_owned(), "<2 bytes>".to_owned()][..])
+        );
+
+        statements.close(&Close::new(TARGET_TYPE_BYTE_STATEMENT, Some("s1".into())));
+        statements.bind(&bind("p1", "s1", vec![], vec![]));
+        assert!(statements.execute(&execute("p1")).is_none());
+    }
+
+    #[test]
+    fn test_values_not_collected_by_default() {
+        let mut statements = PreparedStatements::new(false);
+        statements.parse(&parse("", "SELECT $1"));
+        statements.bind(&bind("", "", vec![], vec![Some("secret")]));
+
+        let executed = statements.execute(&execute("")).unwrap();
+        assert_eq!(executed.parameters, 1);
+        assert_eq!(executed.values, None);
+    }
+}
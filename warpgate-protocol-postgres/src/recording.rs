@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+
+use bytes::BytesMut;
+use pgwire::messages::PgWireBackendMessage;
+
+/// A statement whose response has been fully relayed
+#[derive(Debug, PartialEq)]
+pub struct FinishedQuery {
+    pub time: f32,
+    pub query: String,
+    pub rows: Option<u64>,
+    pub error: Option<String>,
+}
+
+enum Pending {
+    Statement {
+        time: f32,
+        /// Not known for statements prepared before the recording started
+        query: Option<String>,
+        /// Simple queries are answered until `ReadyForQuery`,
+        /// `Execute` until its `CommandComplete`
+        simple: bool,
+        rows: Option<u64>,
+        error: Option<String>,
+    },
+    Sync,
+}
+
+/// Pairs the statements a client sends with the target's responses,
+/// in order, so that they can be written to the session recording
+#[derive(Default)]
+pub struct QueryTracker {
+    pending: VecDeque<Pending>,
+}
+
+impl QueryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn query(&mut self, time: f32, query: String) {
+        self.push_statement(time, Some(query), true);
+    }
+
+    pub fn execute(&mut self, time: f32, query: Option<String>) {
+        self.push_statement(time, query, false);
+    }
+
+    pub fn sync(&mut self) {
+        self.pending.push_back(Pending::Sync);
+    }
+
+    fn push_statement(&mut self, time: f32, query: Option<String>, simple: bool) {
+        self.pending.push_back(Pending::Statement {
+            time,
+            query,
+            simple,
+            rows: None,
+            error: None,
+        });
+    }
+
+    pub fn server_frame(&mut self, frame: &[u8]) -> Vec<FinishedQuery> {
+        let mut finished = vec![];
+        match frame.first() {
+            Some(b'C' | b'E') => {
+                let Ok(Some(msg)) = PgWireBackendMessage::decode(&mut BytesMut::from(frame)) else {
+                    return finished;
+                };
+                let Some(Pending::Statement {
+                    simple,
+                    rows,
+                    error,
+                    ..
+                }) = self.pending.front_mut()
+                else {
+                    return finished;
+                };
+                let simple = *simple;
+                match msg {
+                    PgWireBackendMessage::CommandComplete(complete) => {
+                        *rows = row_count(&complete.tag);
+                    }
+                    PgWireBackendMessage::ErrorResponse(response) => {
+                        *error = response
+                            .fields
+                            .into_iter()
+                            .find(|(field, _)| *field == b'M')
+                            .map(|(_, value)| value);
+                    }
+                    _ => (),
+                }
+                if !simple {
+                    self.finish_front(&mut finished);
+                    if frame.first() == Some(&b'E') {
+                        // The target skips everything up to the next Sync
+                        while let Some(Pending::Statement { .. }) = self.pending.front() {
+                            self.pending.pop_front();
+                        }
+                    }
+                }
+            }
+            // EmptyQueryResponse and PortalSuspended also end an `Execute`
+            Some(b'I' | b's') => {
+                if let Some(Pending::Statement { simple: false, .. }) = self.pending.front() {
+                    self.finish_front(&mut finished);
+                }
+            }
+            Some(b'Z') => {
+                while let Some(pending) = self.pending.front() {
+                    let done = matches!(pending, Pending::Sync)
+                        || matches!(pending, Pending::Statement { simple: true, .. });
+                    self.finish_front(&mut finished);
+                    if done {
+                        break;
+                    }
+                }
+            }
+            _ => (),
+        }
+        finished
+    }
+
+    fn finish_front(&mut self, finished: &mut Vec<FinishedQuery>) {
+        if let Some(Pending::Statement {
+            time,
+            query: Some(query),
+            rows,
+            error,
+            ..
+        }) = self.pending.pop_front()
+        {
+            finished.push(FinishedQuery {
+                time,
+                query,
+                rows: if error.is_some() { None } else { rows },
+                error,
+            });
+        }
+    }
+}
+
+/// Command tags end with the number of rows, e.g. `INSERT 0 5` or `SELECT 3`
+fn row_count(tag: &str) -> Option<u64> {
+    tag.rsplit(' ').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use pgwire::messages::response::{
+        CommandComplete, ErrorResponse, ReadyForQuery, TransactionStatus,
+    };
+    use pgwire::messages::Message;
+
+    use super::*;
+
+    fn frame<M: Message>(msg: M) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        msg.encode(&mut buf).unwrap();
+        buf.to_vec()
+    }
+
+    fn ready() -> Vec<u8> {
+        frame(ReadyForQuery::new(TransactionStatus::Idle))
+    }
+
+    #[test]
+    fn test_simple_query() {
+        let mut tracker = QueryTracker::new();
+        tracker.query(1.0, "UPDATE t SET x = 1".into());
+        assert!(tracker
+            .server_frame(&frame(CommandComplete::new("UPDATE 3".into())))
+            .is_empty());
+        assert_eq!(
+            tracker.server_frame(&ready()),
+            vec![FinishedQuery {
+                time: 1.0,
+                query: "UPDATE t SET x = 1".into(),
+                rows: Some(3),
+                error: None,
+            }]
+        );
+        assert!(tracker.server_frame(&ready()).is_empty());
+    }
+
+    #[test]
+    fn test_extended_query_error() {
+        let mut tracker = QueryTracker::new();
+        tracker.execute(1.0, Some("SELECT 1/0".into()));
+        tracker.execute(2.0, Some("SELECT 1".into()));
+        tracker.sync();
+        tracker.query(3.0, "BEGIN".into());
+
+        let error = ErrorResponse::new(vec![
+            (b'S', "ERROR".into()),
+            (b'C', "22012".into()),
+            (b'M', "division by zero".into()),
+        ]);
+        let finished = tracker.server_frame(&frame(error));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].error.as_deref(), Some("division by zero"));
+        assert_eq!(finished[0].rows, None);
+
+        assert!(tracker.server_frame(&ready()).is_empty());
+        tracker.server_frame(&frame(CommandComplete::new("BEGIN".into())));
+        let finished = tracker.server_frame(&ready());
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].query, "BEGIN");
+        assert_eq!(finished[0].rows, None);
+    }
+
+    #[test]
+    fn test_row_count() {
+        assert_eq!(row_count("INSERT 0 5"), Some(5));
+        assert_eq!(row_count("SELECT 3"), Some(3));
+        assert_eq!(row_count("CREATE TABLE"), None);
+    }
+}
@@ -1,44 +1,87 @@
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use bytes::BytesMut;
 use pgwire::error::ErrorInfo;
-use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+use pgwire::messages::response::TransactionStatus;
+use pgwire::messages::startup::{BackendKeyData, MESSAGE_TYPE_BYTE_BACKEND_KEY_DATA};
+use pgwire::messages::{Message, PgWireBackendMessage, PgWireFrontendMessage};
 use rustls::ServerConfig;
+use sea_orm::ActiveValue::Set;
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tokio_rustls::server::TlsStream;
 use tracing::*;
 use uuid::Uuid;
-use warpgate_common::auth::{AuthCredential, AuthResult, AuthSelector, CredentialKind};
-use warpgate_common::{Secret, TargetOptions, TargetPostgresOptions};
+use warpgate_common::auth::{
+    AuthCredential, AuthResult, AuthSelector, CredentialKind, TargetParameters,
+};
+use warpgate_common::{
+    ErrorCode, PostgresTransactionPooling, Secret, TargetOptions, TargetPostgresOptions, TlsMode,
+    WarpgateError,
+};
+use warpgate_core::recordings::{self, QueryRecorder};
 use warpgate_core::{
-    authorize_ticket, consume_ticket, ConfigProvider, Services, WarpgateServerHandle,
+    authorize_ticket, check_target_baseline, consume_ticket, normalize_work_item,
+    resolve_target_address, trip_honeypot, AuthFailureContext, ConfigProvider, Services,
+    SessionLastActivity, TargetFingerprint, TargetObservation, TargetResolver,
+    WarpgateServerHandle,
 };
+use warpgate_db_entities::DatabaseSessionDetails;
+use warpgate_db_entities::Session::SessionTerminationReason;
 
+use crate::cancel::{CancelKeyRegistration, CancelKeys, CancelRequest};
 use crate::client::{ConnectionOptions, PostgresClient};
+use crate::common::take_work_item;
 use crate::error::PostgresError;
-use crate::stream::{PgWireGenericFrontendMessage, PgWireStartupOrSslRequest, PostgresStream};
+use crate::pool::{is_ready_for_query_idle, PoolLease, PostgresPools};
+use crate::prepared::PreparedStatements;
+use crate::recording::QueryTracker;
+use crate::shadow::PostgresShadow;
+use crate::stream::{
+    PgWireGenericBackendMessage, PgWireGenericFrontendMessage, PgWireStartupOrSslRequest,
+    PostgresStream, FLUSH_THRESHOLD,
+};
 
 pub struct PostgresSession {
     stream: PostgresStream<TlsStream<TcpStream>>,
     tls_config: Arc<ServerConfig>,
     username: Option<String>,
     database: Option<String>,
+    work_item: Option<String>,
     server_handle: Arc<Mutex<WarpgateServerHandle>>,
+    last_activity: SessionLastActivity,
     id: Uuid,
     services: Services,
     remote_address: SocketAddr,
+    pools: Arc<PostgresPools>,
+    cancel_keys: Arc<CancelKeys>,
+    cancel_key: Option<CancelKeyRegistration>,
+    prepared: PreparedStatements,
+    recorder: Option<QueryRecorder>,
+    queries: QueryTracker,
+    /// Messages from an admin closing the session
+    close_messages: mpsc::UnboundedReceiver<String>,
 }
 
 impl PostgresSession {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         server_handle: Arc<Mutex<WarpgateServerHandle>>,
         services: Services,
         stream: TcpStream,
         tls_config: ServerConfig,
         remote_address: SocketAddr,
+        pools: Arc<PostgresPools>,
+        cancel_keys: Arc<CancelKeys>,
+        close_messages: mpsc::UnboundedReceiver<String>,
     ) -> Self {
-        let id = server_handle.lock().await.id();
+        let (id, last_activity) = {
+            let handle = server_handle.lock().await;
+            (handle.id(), handle.last_activity().clone())
+        };
+        let log_values = services.config.load().store.log.query_parameters;
 
         Self {
             services,
@@ -46,19 +89,18 @@ impl PostgresSession {
             stream: PostgresStream::new(stream),
             username: None,
             database: None,
+            work_item: None,
             server_handle,
+            last_activity,
             id,
             remote_address,
-        }
-    }
-
-    pub fn make_logging_span(&self) -> tracing::Span {
-        let client_ip = self.remote_address.ip().to_string();
-        match self.username {
-            Some(ref username) => {
-                info_span!("PostgreSQL", session=%self.id, session_username=%username, %client_ip)
-            }
-            None => info_span!("PostgreSQL", session=%self.id, %client_ip),
+            pools,
+            cancel_keys,
+            cancel_key: None,
+            prepared: PreparedStatements::new(log_values),
+            recorder: None,
+            queries: QueryTracker::new(),
+            close_messages,
         }
     }
 
@@ -83,13 +125,25 @@ impl PostgresSession {
             initial_message = next_message;
         }
 
-        let PgWireStartupOrSslRequest::Startup(startup) = initial_message else {
+        if let PgWireStartupOrSslRequest::CancelRequest(request) = initial_message {
+            self.forward_cancel_request(request).await;
+            return Ok(());
+        }
+
+        let PgWireStartupOrSslRequest::Startup(mut startup) = initial_message else {
             return Err(PostgresError::ProtocolError("expected Startup".into()));
         };
 
         let username = startup.parameters.get("user").cloned();
         self.username = username.clone();
         self.database = startup.parameters.get("database").cloned();
+        if let Some(options) = startup.parameters.remove("options") {
+            let (work_item, options) = take_work_item(&options);
+            self.work_item = work_item.as_deref().and_then(normalize_work_item);
+            if !options.is_empty() {
+                startup.parameters.insert("options".into(), options);
+            }
+        }
 
         let password = if let AuthSelector::Ticket { .. } =
             AuthSelector::from(username.as_deref().unwrap_or(""))
@@ -119,6 +173,14 @@ impl PostgresSession {
             .await
     }
 
+    async fn forward_cancel_request(&self, request: CancelRequest) {
+        match self.cancel_keys.cancel(request).await {
+            Ok(true) => info!("Forwarded a query cancellation request"),
+            Ok(false) => warn!("Received a query cancellation request with an unknown key"),
+            Err(error) => warn!(%error, "Could not forward a query cancellation request"),
+        }
+    }
+
     pub async fn run_authorization(
         mut self,
         startup: pgwire::messages::startup::Startup,
@@ -131,7 +193,7 @@ impl PostgresSession {
             let error_info = ErrorInfo::new(
                 "FATAL".to_owned(),
                 "28P01".to_owned(),
-                "Authentication failed".to_owned(),
+                ErrorCode::AuthFailed.annotate("Authentication failed"),
             );
 
             this.stream
@@ -140,12 +202,27 @@ impl PostgresSession {
             Ok(())
         }
 
+        let username = match &selector {
+            AuthSelector::User { username, .. } => Some(username.as_str()),
+            AuthSelector::Ticket { .. } => None,
+        };
+        let client_ip = self.remote_address.ip().to_string();
+        if !self
+            .services
+            .auth_rate_limiter
+            .allows(username, Some(&client_ip))
+            .await
+        {
+            return fail(&mut self).await;
+        }
+
         match selector {
             AuthSelector::User {
                 username,
                 target_name,
+                parameters,
             } => {
-                let state_arc = self
+                let state = self
                     .services
                     .auth_state_store
                     .lock()
@@ -156,8 +233,20 @@ impl PostgresSession {
                         crate::common::PROTOCOL_NAME,
                         &[CredentialKind::Password],
                     )
-                    .await?
-                    .1;
+                    .await;
+                let state_arc = match state {
+                    Ok((_, state)) => state,
+                    Err(WarpgateError::UserNotFound(_)) => {
+                        self.record_auth_failure(
+                            &startup,
+                            Some(&username),
+                            vec![CredentialKind::Password],
+                        )
+                        .await;
+                        return fail(&mut self).await;
+                    }
+                    Err(error) => return Err(error.into()),
+                };
                 let mut state = state_arc.lock().await;
 
                 let user_auth_result = {
@@ -166,6 +255,8 @@ impl PostgresSession {
                     let mut cp = self.services.config_provider.lock().await;
                     if cp.validate_credential(&username, &credential).await? {
                         state.add_valid_credential(credential);
+                    } else {
+                        state.add_invalid_credential(&credential);
                     }
 
                     state.verify()
@@ -195,9 +286,15 @@ impl PostgresSession {
                             );
                             return fail(&mut self).await;
                         }
-                        self.run_authorized(startup, username, target_name).await
+                        self.run_authorized(startup, username, target_name, parameters)
+                            .await
+                    }
+                    AuthResult::Rejected | AuthResult::Need(_) => {
+                        let credential_kinds = state.attempted_credential_kinds();
+                        self.record_auth_failure(&startup, Some(&username), credential_kinds)
+                            .await;
+                        fail(&mut self).await
                     }
-                    AuthResult::Rejected | AuthResult::Need(_) => fail(&mut self).await,
                 }
             }
             AuthSelector::Ticket { secret } => {
@@ -211,20 +308,49 @@ impl PostgresSession {
                             .await
                             .map_err(PostgresError::other)?;
 
-                        self.run_authorized(startup, ticket.username, ticket.target)
-                            .await
+                        self.run_authorized(
+                            startup,
+                            ticket.username,
+                            ticket.target,
+                            TargetParameters::new(),
+                        )
+                        .await
+                    }
+                    _ => {
+                        self.record_auth_failure(&startup, None, vec![]).await;
+                        fail(&mut self).await
                     }
-                    _ => fail(&mut self).await,
                 }
             }
         }
     }
 
+    async fn record_auth_failure(
+        &self,
+        startup: &pgwire::messages::startup::Startup,
+        username: Option<&str>,
+        credential_kinds: Vec<CredentialKind>,
+    ) {
+        let client_banner = startup.parameters.get("application_name").cloned();
+        let context = AuthFailureContext {
+            username: username.map(Into::into),
+            credential_kinds,
+            client_banner,
+            ..AuthFailureContext::new(crate::common::PROTOCOL_NAME, self.remote_address)
+        };
+        self.services
+            .auth_rate_limiter
+            .record_failure(&context)
+            .await;
+        self.services.auth_failures.record(context).await;
+    }
+
     async fn run_authorized(
         mut self,
         startup: pgwire::messages::startup::Startup,
         username: String,
         target_name: String,
+        parameters: TargetParameters,
     ) -> Result<(), PostgresError> {
         self.stream
             .push(pgwire::messages::startup::Authentication::Ok)?;
@@ -246,23 +372,155 @@ impl PostgresSession {
                 .map(|(t, opt)| (t.clone(), opt.clone()))
         };
 
-        let Some((target, postgres_options)) = target else {
+        let Some((mut target, mut postgres_options)) = target else {
             warn!("Selected target not found");
             self.send_error_response(
                 "0W001".into(),
-                format!("Warpgate target {target_name} not found"),
+                ErrorCode::TargetNotFound
+                    .annotate(format!("Warpgate target {target_name} not found")),
             )
             .await?;
             return Ok(());
         };
 
+        if !target.honeypot {
+            if let Err(error) =
+                resolve_target_address(&self.services, &username, &mut target, &parameters).await
+            {
+                warn!(%error, "Could not resolve the target address");
+                // sqlclient_unable_to_establish_sqlconnection
+                self.send_error_response("08001".into(), error.code().annotate(&error))
+                    .await?;
+                return Ok(());
+            }
+        }
+        if let TargetOptions::Postgres(ref options) = target.options {
+            postgres_options = options.clone();
+        }
+
         {
             let handle = self.server_handle.lock().await;
-            handle.set_username(username).await?;
-            handle.set_target(&target).await?;
+            handle.set_username(username.clone()).await?;
+            if let Some(work_item) = self.work_item.take() {
+                info!(%work_item, "Tagged session with a work item");
+                handle.set_work_item(Some(work_item)).await?;
+            }
+            if let Err(error) = handle.set_target(&target).await {
+                if let WarpgateError::TargetDraining(_)
+                | WarpgateError::TargetSessionLimit(..)
+                | WarpgateError::RecordingQuotaExceeded(_) = error
+                {
+                    drop(handle);
+                    warn!(%error, "Selected target doesn't accept new sessions");
+                    let code = match error {
+                        // too_many_connections
+                        WarpgateError::TargetSessionLimit(..) => "53300",
+                        // cannot_connect_now
+                        _ => "57P03",
+                    };
+                    self.send_error_response(code.into(), error.code().annotate(&error))
+                        .await?;
+                    return Ok(());
+                }
+                return Err(error.into());
+            }
+            handle
+                .update_database_details(DatabaseSessionDetails::ActiveModel {
+                    database: Set(self.database.clone()),
+                    client_application: Set(startup.parameters.get("application_name").cloned()),
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        if target.honeypot {
+            let session_id = self.server_handle.lock().await.id();
+            trip_honeypot(
+                &self.services,
+                &target,
+                crate::common::PROTOCOL_NAME,
+                Some(session_id),
+                Some(&username),
+                Some(self.remote_address.to_string()),
+            )
+            .await;
+            // connection_failure
+            self.send_error_response("08006".into(), "Connection failure".into())
+                .await?;
+            return Ok(());
+        }
+
+        let resolver = self.services.dns.for_target(&target);
+        let shadow = postgres_options.shadow.as_ref().map(|shadow| {
+            PostgresShadow::start(
+                target.name.clone(),
+                shadow,
+                ConnectionOptions {
+                    protocol_number_major: startup.protocol_number_major,
+                    protocol_number_minor: startup.protocol_number_minor,
+                    parameters: startup.parameters.clone(),
+                },
+                resolver.clone(),
+                self.services.shadow_reports.clone(),
+            )
+        });
+
+        match postgres_options.transaction_pooling.clone() {
+            Some(pooling) => {
+                self.run_pooled(
+                    startup,
+                    &username,
+                    &target.name,
+                    postgres_options,
+                    resolver,
+                    pooling,
+                    shadow,
+                )
+                .await
+            }
+            None => {
+                self.run_authorized_inner(startup, &target.name, postgres_options, resolver, shadow)
+                    .await
+            }
+        }
+    }
+
+    /// Remembers the target's certificate and negotiated capabilities
+    /// and checks the connection against the target's baseline
+    async fn check_target_tls(
+        &self,
+        client: &PostgresClient,
+        target_name: &str,
+        options: &TargetPostgresOptions,
+    ) {
+        self.services
+            .target_capabilities
+            .record(target_name, client.negotiated_capabilities());
+        let connection = client.tls_connection();
+        if let Some(cert) = connection
+            .and_then(|c| c.peer_certificates())
+            .and_then(|c| c.first())
+        {
+            self.services
+                .target_fingerprints
+                .record(target_name, TargetFingerprint::tls_certificate(cert));
+        }
+        if options.tls.mode == TlsMode::Disabled {
+            return;
+        }
+        let observation =
+            TargetObservation::tls(target_name, &options.host, options.port, connection);
+        if let Err(error) = check_target_baseline(&self.services, observation).await {
+            error!(%error, "Failed to check the target baseline");
         }
+    }
 
-        self.run_authorized_inner(startup, postgres_options).await
+    async fn mark_target_error(&self) {
+        self.server_handle
+            .lock()
+            .await
+            .set_termination_reason(SessionTerminationReason::TargetError)
+            .await;
     }
 
     async fn send_error_response(
@@ -280,10 +538,14 @@ impl PostgresSession {
     async fn run_authorized_inner(
         mut self,
         startup: pgwire::messages::startup::Startup,
+        target_name: &str,
         options: TargetPostgresOptions,
+        resolver: TargetResolver,
+        mut shadow: Option<PostgresShadow>,
     ) -> Result<(), PostgresError> {
         let mut client = match PostgresClient::connect(
             &options,
+            &resolver,
             ConnectionOptions {
                 protocol_number_major: startup.protocol_number_major,
                 protocol_number_minor: startup.protocol_number_minor,
@@ -293,9 +555,10 @@ impl PostgresSession {
         .await
         {
             Err(error) => {
+                self.mark_target_error().await;
                 self.send_error_response(
                     "0W002".into(),
-                    "Warpgate target connection failed".into(),
+                    ErrorCode::TargetConnectionFailed.annotate("Warpgate target connection failed"),
                 )
                 .await?;
                 Err(error)
@@ -303,12 +566,38 @@ impl PostgresSession {
             x => x,
         }?;
 
+        self.check_target_tls(&client, target_name, &options).await;
+        self.cancel_key = Some(self.cancel_keys.register());
+
+        if !options.init_statements.is_empty() {
+            if let Err(error) = self
+                .initialize_target(&mut client, &options, &resolver)
+                .await
+            {
+                error!(%error, "Target initialization failed");
+                self.mark_target_error().await;
+                self.send_error_response(
+                    "0W002".into(),
+                    ErrorCode::TargetConnectionFailed
+                        .annotate("Warpgate could not initialize the target connection"),
+                )
+                .await?;
+                return Err(error);
+            }
+        }
+
+        self.start_query_recording().await;
+
         loop {
             tokio::select! {
                 c_to_s = self.stream.recv::<PgWireGenericFrontendMessage>() => {
                     match c_to_s {
                         Ok(Some(msg)) => {
+                            self.last_activity.touch();
                             self.maybe_log_client_msg(&msg.0);
+                            if let Some(ref mut shadow) = shadow {
+                                shadow.client_message(&msg.0);
+                            }
                             client.send(msg).await?;
                         }
                         Ok(None) => {
@@ -320,12 +609,193 @@ impl PostgresSession {
                         }
                     };
                 },
-                s_to_c = client.recv() => {
+                s_to_c = client.recv_frame() => {
                     match s_to_c {
+                        Ok(Some(frame)) => {
+                            self.maybe_log_server_frame(&frame);
+                            self.record_server_frame(&frame).await;
+                            if let Some(ref mut shadow) = shadow {
+                                shadow.server_frame(&frame);
+                            }
+                            if frame.first() == Some(&MESSAGE_TYPE_BYTE_BACKEND_KEY_DATA) {
+                                match BackendKeyData::decode(&mut BytesMut::from(&frame[..])) {
+                                    Ok(Some(key)) => {
+                                        let key = self.substitute_backend_key(&key, &options, &resolver);
+                                        self.stream.push(key)?;
+                                    }
+                                    _ => self.stream.push_frame(&frame),
+                                }
+                            } else {
+                                // Pass the frame through as-is
+                                self.stream.push_frame(&frame);
+                            }
+                            // Only flush once the target has nothing else buffered
+                            if !client.stream.has_buffered_frame()
+                                || self.stream.pending_outbound() >= FLUSH_THRESHOLD
+                            {
+                                self.stream.flush().await?;
+                            }
+                        }
+                        Ok(None) => {
+                            break
+                        }
+                        Err(err) => {
+                            error!(error=%err, "Error receiving message");
+                            break
+                        }
+                    };
+                }
+                Some(message) = self.close_messages.recv() => {
+                    self.send_error_response(
+                        "57P01".into(),
+                        ErrorCode::SessionClosedByAdmin
+                            .annotate(format!("Session closed by admin: {message}")),
+                    )
+                    .await?;
+                    break
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    /// Holds back the target's `ReadyForQuery` until the initialization
+    /// statements have run, passing on any parameters they've changed
+    async fn initialize_target(
+        &mut self,
+        client: &mut PostgresClient,
+        options: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+    ) -> Result<(), PostgresError> {
+        let mut messages = client.finish_startup().await?;
+        let ready = messages.pop();
+        let parameters = client.run_init_statements(&options.init_statements).await?;
+        for message in messages {
+            let message = match message {
+                PgWireBackendMessage::BackendKeyData(key) => PgWireBackendMessage::BackendKeyData(
+                    self.substitute_backend_key(&key, options, resolver),
+                ),
+                message => message,
+            };
+            self.stream.push(PgWireGenericBackendMessage(message))?;
+        }
+        for status in parameters {
+            self.stream.push(status)?;
+        }
+        if let Some(ready) = ready {
+            self.stream.push(PgWireGenericBackendMessage(ready))?;
+        }
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Hides the target's backend key from the client, so that
+    /// cancel requests have to go through Warpgate
+    fn substitute_backend_key(
+        &self,
+        key: &BackendKeyData,
+        options: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+    ) -> BackendKeyData {
+        match self.cancel_key {
+            Some(ref registration) => {
+                registration.set_target(Some((options, resolver, key.into())));
+                registration.client_key()
+            }
+            None => BackendKeyData::new(key.pid, key.secret_key),
+        }
+    }
+
+    /// Relays the session through shared target connections, holding one
+    /// only while a request or transaction is in progress
+    #[allow(clippy::too_many_arguments)]
+    async fn run_pooled(
+        mut self,
+        startup: pgwire::messages::startup::Startup,
+        username: &str,
+        target_name: &str,
+        options: TargetPostgresOptions,
+        resolver: TargetResolver,
+        pooling: PostgresTransactionPooling,
+        mut shadow: Option<PostgresShadow>,
+    ) -> Result<(), PostgresError> {
+        let mut parameters = startup.parameters;
+        parameters
+            .entry("database".into())
+            .or_insert_with(|| options.username.clone());
+
+        let mut lease = self
+            .acquire_pooled(
+                username,
+                target_name,
+                &options,
+                &resolver,
+                &pooling,
+                &parameters,
+            )
+            .await?;
+        // Pooled connections are opened with the client's own startup
+        // parameters, so the reported values apply to this session too
+        for (name, value) in lease.connection().parameters.iter() {
+            self.stream
+                .push(pgwire::messages::startup::ParameterStatus::new(
+                    name.clone(),
+                    value.clone(),
+                ))?;
+        }
+        lease.release().await;
+        // Cancel requests go to whichever connection the session is using
+        let cancel_key = self.cancel_keys.register();
+        self.stream.push(cancel_key.client_key())?;
+        self.stream
+            .push(pgwire::messages::response::ReadyForQuery::new(
+                TransactionStatus::Idle,
+            ))?;
+        self.stream.flush().await?;
+        self.start_query_recording().await;
+
+        let mut lease: Option<PoolLease> = None;
+        // Each simple query and each Sync is answered with one ReadyForQuery
+        let mut pending_responses = 0usize;
+
+        loop {
+            tokio::select! {
+                c_to_s = self.stream.recv::<PgWireGenericFrontendMessage>() => {
+                    match c_to_s {
+                        Ok(Some(PgWireGenericFrontendMessage(PgWireFrontendMessage::Terminate(_)))) => {
+                            break
+                        }
                         Ok(Some(msg)) => {
-                            self.maybe_log_server_msg(&msg.0);
-                            self.stream.push(msg)?;
-                            self.stream.flush().await?;
+                            self.last_activity.touch();
+                            self.maybe_log_client_msg(&msg.0);
+                            if let Some(ref mut shadow) = shadow {
+                                shadow.client_message(&msg.0);
+                            }
+                            if matches!(
+                                msg.0,
+                                PgWireFrontendMessage::Query(_) | PgWireFrontendMessage::Sync(_)
+                            ) {
+                                pending_responses += 1;
+                            }
+                            let lease = match lease {
+                                Some(ref mut lease) => lease,
+                                None => {
+                                    let mut new_lease = self
+                                        .acquire_pooled(username, target_name, &options, &resolver, &pooling, &parameters)
+                                        .await?;
+                                    cancel_key.set_target(
+                                        new_lease
+                                            .connection()
+                                            .backend_key
+                                            .map(|key| (&options, &resolver, key)),
+                                    );
+                                    lease.insert(new_lease)
+                                }
+                            };
+                            let connection = lease.connection();
+                            debug!(connection=%connection.id, "Using pooled target connection");
+                            connection.client.send(msg).await?;
                         }
                         Ok(None) => {
                             break
@@ -335,6 +805,64 @@ impl PostgresSession {
                             break
                         }
                     };
+                },
+                s_to_c = async {
+                    match lease {
+                        Some(ref mut lease) => lease.connection().client.recv_frame().await,
+                        None => std::future::pending().await,
+                    }
+                } => {
+                    match s_to_c {
+                        Ok(Some(frame)) => {
+                            self.maybe_log_server_frame(&frame);
+                            self.record_server_frame(&frame).await;
+                            if let Some(ref mut shadow) = shadow {
+                                shadow.server_frame(&frame);
+                            }
+                            self.stream.push_frame(&frame);
+                            let mut has_buffered_frame = lease
+                                .as_ref()
+                                .is_some_and(|x| x.has_buffered_frame());
+                            if frame.first() == Some(&b'Z') {
+                                pending_responses = pending_responses.saturating_sub(1);
+                                if pending_responses == 0 && is_ready_for_query_idle(&frame) {
+                                    if let Some(lease) = lease.take() {
+                                        cancel_key.set_target(None);
+                                        lease.release().await;
+                                    }
+                                    has_buffered_frame = false;
+                                }
+                            }
+                            if !has_buffered_frame
+                                || self.stream.pending_outbound() >= FLUSH_THRESHOLD
+                            {
+                                self.stream.flush().await?;
+                            }
+                        }
+                        Ok(None) => {
+                            warn!("Pooled target connection closed");
+                            self.send_error_response(
+                                "08006".into(),
+                                ErrorCode::TargetConnectionClosed
+                                    .annotate("Warpgate target connection closed"),
+                            )
+                            .await?;
+                            break
+                        }
+                        Err(err) => {
+                            error!(error=%err, "Error receiving message");
+                            break
+                        }
+                    };
+                }
+                Some(message) = self.close_messages.recv() => {
+                    self.send_error_response(
+                        "57P01".into(),
+                        ErrorCode::SessionClosedByAdmin
+                            .annotate(format!("Session closed by admin: {message}")),
+                    )
+                    .await?;
+                    break
                 }
             };
         }
@@ -342,22 +870,138 @@ impl PostgresSession {
         Ok(())
     }
 
-    fn maybe_log_client_msg(&self, msg: &PgWireFrontendMessage) {
+    /// Tells the client why the session ends if no connection can be opened
+    async fn acquire_pooled(
+        &mut self,
+        username: &str,
+        target_name: &str,
+        options: &TargetPostgresOptions,
+        resolver: &TargetResolver,
+        pooling: &PostgresTransactionPooling,
+        parameters: &BTreeMap<String, String>,
+    ) -> Result<PoolLease, PostgresError> {
+        match self
+            .pools
+            .acquire(
+                target_name,
+                options,
+                resolver,
+                pooling,
+                username,
+                parameters,
+            )
+            .await
+        {
+            Err(error) => {
+                self.mark_target_error().await;
+                self.send_error_response(
+                    "0W002".into(),
+                    ErrorCode::TargetConnectionFailed.annotate("Warpgate target connection failed"),
+                )
+                .await?;
+                Err(error)
+            }
+            Ok(lease) => Ok(lease),
+        }
+    }
+
+    fn maybe_log_client_msg(&mut self, msg: &PgWireFrontendMessage) {
         debug!(?msg, "C->S message");
         match msg {
             PgWireFrontendMessage::Parse(query) => {
                 info!(query_name=?query.name, "Preparing query");
+                self.prepared.parse(query);
+            }
+            PgWireFrontendMessage::Bind(bind) => {
+                self.prepared.bind(bind);
+            }
+            PgWireFrontendMessage::Close(close) => {
+                self.prepared.close(close);
             }
             PgWireFrontendMessage::Execute(query) => {
-                info!(query_name=?query.name, "Executing prepared query");
+                let statement_query = match self.prepared.execute(query) {
+                    Some(statement) => {
+                        match statement.values {
+                            Some(values) => info!(
+                                query=%statement.query,
+                                parameters=statement.parameters,
+                                ?values,
+                                "Executing prepared query"
+                            ),
+                            None => info!(
+                                query=%statement.query,
+                                parameters=statement.parameters,
+                                "Executing prepared query"
+                            ),
+                        }
+                        Some(statement.query.to_owned())
+                    }
+                    None => {
+                        info!(query_name=?query.name, "Executing prepared query");
+                        None
+                    }
+                };
+                if let Some(ref recorder) = self.recorder {
+                    self.queries.execute(recorder.get_time(), statement_query);
+                }
             }
             PgWireFrontendMessage::Query(query) => {
                 info!(query=%query.query, "Query");
+                if let Some(ref recorder) = self.recorder {
+                    self.queries.query(recorder.get_time(), query.query.clone());
+                }
+            }
+            PgWireFrontendMessage::Sync(_) => {
+                if self.recorder.is_some() {
+                    self.queries.sync();
+                }
             }
             _ => (),
         }
     }
 
+    async fn start_query_recording(&mut self) {
+        match self
+            .services
+            .recordings
+            .lock()
+            .await
+            .start::<QueryRecorder>(&self.id, "queries".into())
+            .await
+        {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(recordings::Error::Disabled) => (),
+            Err(error) => error!(?error, "Failed to start recording"),
+        }
+    }
+
+    async fn record_server_frame(&mut self, frame: &[u8]) {
+        let Some(ref mut recorder) = self.recorder else {
+            return;
+        };
+        for query in self.queries.server_frame(frame) {
+            if let Err(error) = recorder
+                .write_query(query.time, query.query, query.rows, query.error)
+                .await
+            {
+                error!(?error, "Failed to record the query");
+            }
+        }
+    }
+
+    fn maybe_log_server_frame(&self, frame: &[u8]) {
+        // Only decode messages we're interested in, since result sets
+        // are relayed without being parsed
+        if frame.first() != Some(&b'E') && !enabled!(Level::DEBUG) {
+            return;
+        }
+        match PgWireBackendMessage::decode(&mut BytesMut::from(frame)) {
+            Ok(Some(msg)) => self.maybe_log_server_msg(&msg),
+            Ok(None) => (),
+            Err(error) => debug!(%error, "Could not decode S->C message"),
+        }
+    }
+
     fn maybe_log_server_msg(&self, msg: &PgWireBackendMessage) {
         debug!(?msg, "S->C message");
         if let PgWireBackendMessage::ErrorResponse(error) = msg {
@@ -2,11 +2,11 @@ use tokio::sync::mpsc;
 use warpgate_core::SessionHandle;
 
 pub struct PostgresSessionHandle {
-    abort_tx: mpsc::UnboundedSender<()>,
+    abort_tx: mpsc::UnboundedSender<Option<String>>,
 }
 
 impl PostgresSessionHandle {
-    pub fn new() -> (Self, mpsc::UnboundedReceiver<()>) {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Option<String>>) {
         let (abort_tx, abort_rx) = mpsc::unbounded_channel();
         (PostgresSessionHandle { abort_tx }, abort_rx)
     }
@@ -14,6 +14,10 @@ impl PostgresSessionHandle {
 
 impl SessionHandle for PostgresSessionHandle {
     fn close(&mut self) {
-        let _ = self.abort_tx.send(());
+        let _ = self.abort_tx.send(None);
+    }
+
+    fn close_with_message(&mut self, message: String) {
+        let _ = self.abort_tx.send(Some(message));
     }
 }
@@ -0,0 +1,323 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use pgwire::messages::simplequery::Query;
+use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+use tokio::sync::mpsc;
+use tracing::*;
+use warpgate_common::{TargetPostgresOptions, TargetPostgresShadow};
+use warpgate_core::{ShadowComparison, ShadowReports, TargetResolver};
+
+use crate::client::{ConnectionOptions, PostgresClient};
+use crate::error::PostgresError;
+use crate::stream::{PgWireGenericBackendMessage, PgWireGenericFrontendMessage};
+
+/// Queries waiting to be replayed - more are dropped rather than
+/// slowing down the session
+const SHADOW_QUEUE_SIZE: usize = 16;
+const SHADOW_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct ShadowQuery {
+    query: String,
+    primary: String,
+    primary_latency: Duration,
+}
+
+struct InFlightQuery {
+    query: String,
+    started: Instant,
+    outcome: Option<String>,
+}
+
+/// Watches a session's traffic and replays its read-only simple queries
+/// on the target's shadow upstream over a separate connection.
+/// Extended protocol queries are not replayed.
+///
+/// The shadow connection defaults to read-only transactions and every
+/// query runs in a read-only transaction that is rolled back, so that
+/// a query wrongly picked by [is_read_only] can't change anything.
+pub struct PostgresShadow {
+    sender: mpsc::Sender<ShadowQuery>,
+    in_flight: Option<InFlightQuery>,
+    /// Each simple query and each Sync is answered with one ReadyForQuery
+    pending_responses: usize,
+}
+
+impl PostgresShadow {
+    pub fn start(
+        target_name: String,
+        shadow: &TargetPostgresShadow,
+        connection_options: ConnectionOptions,
+        resolver: TargetResolver,
+        reports: Arc<ShadowReports>,
+    ) -> Self {
+        let options = TargetPostgresOptions {
+            host: shadow.host.clone(),
+            port: shadow.port,
+            username: shadow.username.clone(),
+            password: shadow.password.clone(),
+            tls: shadow.tls.clone(),
+            transaction_pooling: None,
+            shadow: None,
+            init_statements: vec![],
+            show_fingerprint: false,
+            direct_credentials: None,
+        };
+        let (sender, receiver) = mpsc::channel(SHADOW_QUEUE_SIZE);
+        tokio::spawn(
+            run_shadow(
+                target_name,
+                options,
+                connection_options,
+                resolver,
+                receiver,
+                reports,
+            )
+            .in_current_span(),
+        );
+        PostgresShadow {
+            sender,
+            in_flight: None,
+            pending_responses: 0,
+        }
+    }
+
+    pub fn client_message(&mut self, msg: &PgWireFrontendMessage) {
+        match msg {
+            PgWireFrontendMessage::Query(query) => {
+                // Pipelined queries can't be told apart in the responses
+                if self.pending_responses == 0 && is_read_only(&query.query) {
+                    self.in_flight = Some(InFlightQuery {
+                        query: query.query.clone(),
+                        started: Instant::now(),
+                        outcome: None,
+                    });
+                }
+                self.pending_responses += 1;
+            }
+            PgWireFrontendMessage::Sync(_) => {
+                self.pending_responses += 1;
+            }
+            _ => (),
+        }
+    }
+
+    pub fn server_frame(&mut self, frame: &[u8]) {
+        match frame.first() {
+            Some(b'Z') => {
+                self.pending_responses = self.pending_responses.saturating_sub(1);
+                if let Some(in_flight) = self.in_flight.take() {
+                    let query = ShadowQuery {
+                        query: in_flight.query,
+                        primary: in_flight.outcome.unwrap_or_default(),
+                        primary_latency: in_flight.started.elapsed(),
+                    };
+                    if self.sender.try_send(query).is_err() {
+                        debug!("Shadow queue is full, skipping query");
+                    }
+                }
+            }
+            Some(b'C' | b'E') => {
+                let Some(ref mut in_flight) = self.in_flight else {
+                    return;
+                };
+                if let Ok(Some(msg)) = PgWireBackendMessage::decode(&mut BytesMut::from(frame)) {
+                    update_outcome(&mut in_flight.outcome, &msg);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Picks the queries worth replaying. Functions called from a `SELECT` can
+/// still have side effects - those are stopped by the read-only transaction.
+fn is_read_only(query: &str) -> bool {
+    let query = query.trim().trim_end_matches(';');
+    if query.contains(';') {
+        return false;
+    }
+    let keyword = query
+        .trim_start_matches('(')
+        .split(|c: char| !c.is_ascii_alphabetic())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let selects_into = query.to_ascii_uppercase().contains(" INTO ");
+    matches!(keyword.as_str(), "SELECT" | "SHOW" | "VALUES" | "TABLE") && !selects_into
+}
+
+/// The last command tag, or the SQLSTATE of the first error
+fn update_outcome(outcome: &mut Option<String>, msg: &PgWireBackendMessage) {
+    if outcome.as_ref().is_some_and(|x| x.starts_with("ERROR")) {
+        return;
+    }
+    match msg {
+        PgWireBackendMessage::CommandComplete(complete) => {
+            *outcome = Some(complete.tag.clone());
+        }
+        PgWireBackendMessage::ErrorResponse(error) => {
+            let code = error
+                .fields
+                .iter()
+                .find(|(field, _)| *field == b'C')
+                .map(|(_, value)| value.as_str())
+                .unwrap_or_default();
+            *outcome = Some(format!("ERROR {code}"));
+        }
+        _ => (),
+    }
+}
+
+async fn run_shadow(
+    target_name: String,
+    options: TargetPostgresOptions,
+    connection_options: ConnectionOptions,
+    resolver: TargetResolver,
+    mut receiver: mpsc::Receiver<ShadowQuery>,
+    reports: Arc<ShadowReports>,
+) {
+    let mut client = None;
+    while let Some(query) = receiver.recv().await {
+        let started = Instant::now();
+        let result = tokio::time::timeout(
+            SHADOW_QUERY_TIMEOUT,
+            run_shadow_query(
+                &mut client,
+                &options,
+                &resolver,
+                &connection_options,
+                &query.query,
+            ),
+        )
+        .await
+        .map_err(|_| "timed out".to_owned())
+        .and_then(|x| x.map_err(|error| error.to_string()));
+
+        if result.is_err() {
+            // Reconnect for the next query
+            client = None;
+        }
+
+        reports.record(
+            &target_name,
+            ShadowComparison {
+                request: query.query,
+                primary: query.primary,
+                shadow: result,
+                primary_latency: query.primary_latency,
+                shadow_latency: started.elapsed(),
+            },
+        );
+    }
+}
+
+async fn run_shadow_query(
+    client: &mut Option<PostgresClient>,
+    options: &TargetPostgresOptions,
+    resolver: &TargetResolver,
+    connection_options: &ConnectionOptions,
+    query: &str,
+) -> Result<String, PostgresError> {
+    let client = match client {
+        Some(client) => client,
+        None => {
+            let mut new_client = PostgresClient::connect(
+                options,
+                resolver,
+                ConnectionOptions {
+                    protocol_number_major: connection_options.protocol_number_major,
+                    protocol_number_minor: connection_options.protocol_number_minor,
+                    parameters: read_only_parameters(&connection_options.parameters),
+                },
+            )
+            .await?;
+            wait_for_ready(&mut new_client, &mut None).await?;
+            debug!("Connected to the shadow target");
+            client.insert(new_client)
+        }
+    };
+
+    // A query can't turn off read-only mode for the transaction it runs in,
+    // and the rollback undoes any changes to the session's settings
+    send_query(client, "BEGIN READ ONLY").await?;
+    wait_for_ready(client, &mut None).await?;
+    send_query(client, query).await?;
+    let mut outcome = None;
+    wait_for_ready(client, &mut outcome).await?;
+    send_query(client, "ROLLBACK").await?;
+    wait_for_ready(client, &mut None).await?;
+    Ok(outcome.unwrap_or_default())
+}
+
+async fn send_query(client: &mut PostgresClient, query: &str) -> Result<(), PostgresError> {
+    client
+        .send(PgWireGenericFrontendMessage(PgWireFrontendMessage::Query(
+            Query::new(query.to_owned()),
+        )))
+        .await
+}
+
+/// The client's startup parameters, with all transactions read-only by default
+fn read_only_parameters(parameters: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut parameters = parameters.clone();
+    let options = parameters.entry("options".to_owned()).or_default();
+    if !options.is_empty() {
+        options.push(' ');
+    }
+    options.push_str("-c default_transaction_read_only=on");
+    parameters
+}
+
+async fn wait_for_ready(
+    client: &mut PostgresClient,
+    outcome: &mut Option<String>,
+) -> Result<(), PostgresError> {
+    loop {
+        let Some(message) = client.stream.recv::<PgWireGenericBackendMessage>().await? else {
+            return Err(PostgresError::Eof);
+        };
+        match message.0 {
+            PgWireBackendMessage::ReadyForQuery(_) => return Ok(()),
+            msg => update_outcome(outcome, &msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_read_only() {
+        assert!(is_read_only("select * from users;"));
+        assert!(is_read_only("  (SELECT 1) UNION (SELECT 2)"));
+        assert!(is_read_only("SHOW search_path"));
+        assert!(!is_read_only("SELECT 1; DELETE FROM users"));
+        assert!(!is_read_only("SELECT * INTO copy FROM users"));
+        assert!(!is_read_only("WITH x AS (DELETE FROM users) SELECT 1"));
+        assert!(!is_read_only("update users set name = 'x'"));
+    }
+
+    #[test]
+    fn test_read_only_parameters() {
+        let parameters =
+            read_only_parameters(&BTreeMap::from([("database".to_owned(), "db".to_owned())]));
+        assert_eq!(
+            parameters.get("options").map(String::as_str),
+            Some("-c default_transaction_read_only=on")
+        );
+        assert_eq!(parameters.get("database").map(String::as_str), Some("db"));
+
+        let parameters = read_only_parameters(&BTreeMap::from([(
+            "options".to_owned(),
+            "-c search_path=app".to_owned(),
+        )]));
+        assert_eq!(
+            parameters.get("options").map(String::as_str),
+            Some("-c search_path=app -c default_transaction_read_only=on")
+        );
+    }
+}
@@ -1,13 +1,25 @@
 use std::fmt::Debug;
 
-use bytes::BytesMut;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use pgwire::error::{PgWireError, PgWireResult};
+use pgwire::messages::extendedquery::Parse;
 use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tracing::*;
 use warpgate_common::{MaybeTlsStream, MaybeTlsStreamError, UpgradableStream};
 
+use crate::cancel::CancelRequest;
+
+/// Minimum free space kept in the inbound buffer before reading from the socket
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Pending outbound data is flushed once it grows past this size
+pub(crate) const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+/// Type tag + length
+const FRAME_HEADER_SIZE: usize = 5;
+
 #[derive(thiserror::Error, Debug)]
 pub enum PostgresStreamError {
     #[error("decode: {0}")]
@@ -32,6 +44,7 @@ pub(crate) trait PostgresDecode {
 pub(crate) enum PgWireStartupOrSslRequest {
     Startup(pgwire::messages::startup::Startup),
     SslRequest(pgwire::messages::startup::SslRequest),
+    CancelRequest(CancelRequest),
 }
 
 impl PostgresDecode for PgWireStartupOrSslRequest {
@@ -39,6 +52,9 @@ impl PostgresDecode for PgWireStartupOrSslRequest {
         if let Ok(Some(result)) = pgwire::messages::startup::SslRequest::decode(buf) {
             return Ok(Some(Self::SslRequest(result)));
         }
+        if let Some(result) = CancelRequest::decode(buf)? {
+            return Ok(Some(Self::CancelRequest(result)));
+        }
         pgwire::messages::startup::Startup::decode(buf).map(|x| x.map(Self::Startup))
     }
 }
@@ -69,7 +85,30 @@ impl<T: pgwire::messages::Message> PostgresDecode for T {
 
 impl PostgresEncode for PgWireGenericFrontendMessage {
     fn encode(&self, buf: &mut BytesMut) -> PgWireResult<()> {
-        self.0.encode(buf)
+        match self.0 {
+            PgWireFrontendMessage::Parse(ref parse) => {
+                encode_parse(parse, buf);
+                Ok(())
+            }
+            ref msg => msg.encode(buf),
+        }
+    }
+}
+
+/// pgwire leaves the parameter type count out of the length of `Parse`
+/// messages, which targets reject
+fn encode_parse(parse: &Parse, buf: &mut BytesMut) {
+    let name = parse.name.as_deref().unwrap_or_default();
+    let length = 4 + name.len() + 1 + parse.query.len() + 1 + 2 + 4 * parse.type_oids.len();
+    buf.put_u8(b'P');
+    buf.put_i32(length as i32);
+    buf.put_slice(name.as_bytes());
+    buf.put_u8(0);
+    buf.put_slice(parse.query.as_bytes());
+    buf.put_u8(0);
+    buf.put_u16(parse.type_oids.len() as u16);
+    for oid in &parse.type_oids {
+        buf.put_u32(*oid);
     }
 }
 
@@ -103,8 +142,8 @@ where
     pub fn new(stream: TcpStream) -> Self {
         Self {
             stream: MaybeTlsStream::new(stream),
-            inbound_buffer: BytesMut::new(),
-            outbound_buffer: BytesMut::new(),
+            inbound_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            outbound_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
         }
     }
 
@@ -119,7 +158,7 @@ where
 
     pub async fn flush(&mut self) -> std::io::Result<()> {
         self.stream.write_all(&self.outbound_buffer[..]).await?;
-        self.outbound_buffer = BytesMut::new();
+        self.outbound_buffer.clear();
         self.stream.flush().await?;
         Ok(())
     }
@@ -133,13 +172,54 @@ where
                 return Ok(Some(message));
             };
 
-            let read_bytes = self.stream.read_buf(&mut self.inbound_buffer).await?;
-            if read_bytes == 0 {
+            if !self.read_more().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Receives a raw tagged message frame without decoding it.
+    /// The returned buffer shares the allocation of the inbound buffer.
+    pub(crate) async fn recv_frame(&mut self) -> Result<Option<Bytes>, PostgresStreamError> {
+        loop {
+            if let Some(len) = self.buffered_frame_len() {
+                let frame = self.inbound_buffer.split_to(len).freeze();
+                trace!(tag=?frame.first().map(|x| *x as char), len, "received frame");
+                return Ok(Some(frame));
+            }
+
+            if !self.read_more().await? {
                 return Ok(None);
             }
         }
     }
 
+    /// Queues a raw frame as received by [Self::recv_frame]
+    pub(crate) fn push_frame(&mut self, frame: &[u8]) {
+        self.outbound_buffer.extend_from_slice(frame);
+    }
+
+    pub(crate) fn pending_outbound(&self) -> usize {
+        self.outbound_buffer.len()
+    }
+
+    /// Whether a complete frame can be received without waiting on the socket
+    pub(crate) fn has_buffered_frame(&self) -> bool {
+        self.buffered_frame_len().is_some()
+    }
+
+    fn buffered_frame_len(&self) -> Option<usize> {
+        let mut header = self.inbound_buffer.get(1..FRAME_HEADER_SIZE)?;
+        let len = header.get_u32() as usize + 1;
+        (self.inbound_buffer.len() >= len).then_some(len)
+    }
+
+    async fn read_more(&mut self) -> std::io::Result<bool> {
+        self.inbound_buffer.reserve(READ_BUFFER_SIZE);
+        let read_bytes = self.stream.read_buf(&mut self.inbound_buffer).await?;
+        Ok(read_bytes > 0)
+    }
+
     pub(crate) async fn upgrade(
         mut self,
         config: <TcpStream as UpgradableStream<TS>>::UpgradeConfig,
@@ -147,4 +227,31 @@ where
         self.stream = self.stream.upgrade(config).await?;
         Ok(self)
     }
+
+    pub(crate) fn tls(&self) -> Option<&TS> {
+        self.stream.tls()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_roundtrip() {
+        let parse = Parse::new(Some("s1".into()), "SELECT $1".into(), vec![23]);
+        let mut buf = BytesMut::new();
+        PgWireGenericFrontendMessage(PgWireFrontendMessage::Parse(parse))
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!((&buf[1..5]).get_i32() as usize, buf.len() - 1);
+
+        let Ok(Some(PgWireFrontendMessage::Parse(parse))) = PgWireFrontendMessage::decode(&mut buf)
+        else {
+            panic!("Parse message expected");
+        };
+        assert_eq!(parse.query, "SELECT $1");
+        assert_eq!(parse.type_oids, vec![23]);
+        assert!(buf.is_empty());
+    }
 }
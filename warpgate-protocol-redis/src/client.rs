@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::ClientConnection;
+use tracing::*;
+use warpgate_common::{configure_tls_connector, TargetRedisOptions, TlsMode};
+use warpgate_core::{NegotiatedCapabilities, TargetResolver};
+
+use crate::command::Command;
+use crate::error::RedisError;
+use crate::resp::error_message;
+use crate::stream::RedisStream;
+
+pub struct RedisClient {
+    pub stream: RedisStream<tokio_rustls::client::TlsStream<TcpStream>>,
+    auth_method: &'static str,
+}
+
+impl RedisClient {
+    pub fn tls_connection(&self) -> Option<&ClientConnection> {
+        Some(self.stream.tls()?.get_ref().1)
+    }
+
+    pub fn negotiated_capabilities(&self) -> NegotiatedCapabilities {
+        let mut capabilities = NegotiatedCapabilities::new().with_tls(self.tls_connection());
+        capabilities.auth_method = Some(self.auth_method.to_owned());
+        capabilities
+    }
+
+    async fn connect_tls(
+        target: &TargetRedisOptions,
+        resolver: &TargetResolver,
+    ) -> Result<RedisStream<tokio_rustls::client::TlsStream<TcpStream>>, RedisError> {
+        let accept_invalid_certs = !target.tls.verify;
+        let accept_invalid_hostname = false; // ca + hostname verification
+        let client_config = Arc::new(
+            configure_tls_connector(accept_invalid_certs, accept_invalid_hostname, None).await?,
+        );
+        let stream = RedisStream::new(resolver.connect(&target.host, target.port).await?);
+        Ok(stream
+            .upgrade((
+                target
+                    .host
+                    .clone()
+                    .try_into()
+                    .map_err(|_| RedisError::InvalidDomainName)?,
+                client_config,
+            ))
+            .await?)
+    }
+
+    pub async fn connect(
+        target: &TargetRedisOptions,
+        resolver: &TargetResolver,
+    ) -> Result<Self, RedisError> {
+        // Redis expects TLS right away, so there's nothing to negotiate
+        let stream = match target.tls.mode {
+            TlsMode::Disabled => {
+                RedisStream::new(resolver.connect(&target.host, target.port).await?)
+            }
+            TlsMode::Required => Self::connect_tls(target, resolver).await?,
+            TlsMode::Preferred => match Self::connect_tls(target, resolver).await {
+                Ok(stream) => stream,
+                Err(error) => {
+                    warn!(%error, "TLS connection failed, connecting without TLS");
+                    RedisStream::new(resolver.connect(&target.host, target.port).await?)
+                }
+            },
+        };
+        if stream.tls().is_some() {
+            info!("Target connection uses TLS");
+        }
+
+        let mut client = Self {
+            stream,
+            auth_method: "none",
+        };
+
+        if let Some(ref password) = target.password {
+            let mut args = vec![Bytes::from_static(b"AUTH")];
+            if let Some(ref username) = target.username {
+                args.push(Bytes::copy_from_slice(username.as_bytes()));
+            }
+            args.push(Bytes::copy_from_slice(password.expose_secret().as_bytes()));
+            client.request(&Command::new(args)).await?;
+            client.auth_method = match target.username {
+                Some(_) => "acl",
+                None => "password",
+            };
+            debug!("Authorized");
+        }
+
+        Ok(client)
+    }
+
+    /// Sends a command and waits for its reply, turning error replies into errors
+    pub async fn request(&mut self, command: &Command) -> Result<Bytes, RedisError> {
+        self.stream.push_command(command);
+        self.stream.flush().await?;
+        let Some(reply) = self.stream.recv_reply().await? else {
+            return Err(RedisError::Eof);
+        };
+        if let Some(message) = error_message(&reply) {
+            return Err(RedisError::RemoteError(message));
+        }
+        Ok(reply)
+    }
+}
@@ -0,0 +1,246 @@
+use bytes::Bytes;
+
+/// Arguments longer than this are cut short in the logs
+const MAX_LOGGED_ARG_LENGTH: usize = 64;
+
+const MAX_LOGGED_ARGS: usize = 16;
+
+const REDACTED: &str = "***";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command {
+    args: Vec<Bytes>,
+}
+
+/// `HELLO [protover [AUTH username password] [SETNAME clientname]]`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hello {
+    pub protocol_version: Option<Bytes>,
+    pub auth: Option<(String, String)>,
+    pub client_name: Option<String>,
+}
+
+impl Hello {
+    /// The same request without the credentials, to be sent to the target
+    pub fn without_auth(&self) -> Command {
+        let mut args = vec![Bytes::from_static(b"HELLO")];
+        if let Some(ref version) = self.protocol_version {
+            args.push(version.clone());
+            if let Some(ref name) = self.client_name {
+                args.push(Bytes::from_static(b"SETNAME"));
+                args.push(Bytes::copy_from_slice(name.as_bytes()));
+            }
+        }
+        Command::new(args)
+    }
+}
+
+fn arg_eq(arg: Option<&Bytes>, name: &str) -> bool {
+    arg.is_some_and(|x| x.eq_ignore_ascii_case(name.as_bytes()))
+}
+
+fn lossy(arg: &[u8]) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+impl Command {
+    /// `args` must not be empty
+    pub fn new(args: Vec<Bytes>) -> Self {
+        Self { args }
+    }
+
+    pub fn args(&self) -> &[Bytes] {
+        &self.args
+    }
+
+    /// Upper case, e.g. `GET`
+    pub fn name(&self) -> String {
+        self.args
+            .first()
+            .map(|x| lossy(x).to_ascii_uppercase())
+            .unwrap_or_default()
+    }
+
+    pub fn is(&self, name: &str) -> bool {
+        arg_eq(self.args.first(), name)
+    }
+
+    pub fn parse_hello(&self) -> Option<Hello> {
+        if !self.is("HELLO") {
+            return None;
+        }
+        let mut hello = Hello {
+            protocol_version: self.args.get(1).cloned(),
+            auth: None,
+            client_name: None,
+        };
+        let mut index = 2;
+        while index < self.args.len() {
+            let option = self.args.get(index);
+            if arg_eq(option, "AUTH") {
+                let username = self.args.get(index + 1)?;
+                let password = self.args.get(index + 2)?;
+                hello.auth = Some((lossy(username), lossy(password)));
+                index += 3;
+            } else if arg_eq(option, "SETNAME") {
+                hello.client_name = Some(lossy(self.args.get(index + 1)?));
+                index += 2;
+            } else {
+                return None;
+            }
+        }
+        Some(hello)
+    }
+
+    /// Indices of arguments that must not end up in the logs
+    fn sensitive_args(&self) -> Vec<usize> {
+        let subcommand = self.args.get(1);
+        match self.name().as_str() {
+            "AUTH" => (1..self.args.len()).collect(),
+            // HELLO 3 AUTH <username> <password>
+            "HELLO" => self
+                .args
+                .iter()
+                .position(|x| x.eq_ignore_ascii_case(b"AUTH"))
+                .map(|x| vec![x + 2])
+                .unwrap_or_default(),
+            // CONFIG SET requirepass <password> [masterauth <password> ...]
+            "CONFIG" if arg_eq(subcommand, "SET") => (2..self.args.len())
+                .step_by(2)
+                .filter(|x| {
+                    arg_eq(self.args.get(*x), "requirepass")
+                        || arg_eq(self.args.get(*x), "masterauth")
+                })
+                .map(|x| x + 1)
+                .collect(),
+            // ACL SETUSER <username> >password #hash ...
+            "ACL" if arg_eq(subcommand, "SETUSER") => (3..self.args.len())
+                .filter(|x| {
+                    self.args[*x].starts_with(b">")
+                        || self.args[*x].starts_with(b"<")
+                        || self.args[*x].starts_with(b"#")
+                        || self.args[*x].starts_with(b"!")
+                })
+                .collect(),
+            // MIGRATE ... AUTH <password> | AUTH2 <username> <password>
+            "MIGRATE" => self
+                .args
+                .iter()
+                .enumerate()
+                .flat_map(|(index, arg)| {
+                    if arg.eq_ignore_ascii_case(b"AUTH") {
+                        vec![index + 1]
+                    } else if arg.eq_ignore_ascii_case(b"AUTH2") {
+                        vec![index + 2]
+                    } else {
+                        vec![]
+                    }
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The command for the session log, with passwords redacted
+    /// and long values shortened
+    pub fn audit_string(&self) -> String {
+        let sensitive = self.sensitive_args();
+        let mut parts = vec![self.name()];
+        for (index, arg) in self.args.iter().enumerate().skip(1) {
+            if index > MAX_LOGGED_ARGS {
+                parts.push(format!("... ({} more)", self.args.len() - index));
+                break;
+            }
+            if sensitive.contains(&index) {
+                parts.push(REDACTED.to_owned());
+                continue;
+            }
+            let mut value = lossy(&arg[..arg.len().min(MAX_LOGGED_ARG_LENGTH)]);
+            if arg.len() > MAX_LOGGED_ARG_LENGTH {
+                value.push_str("...");
+            }
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+                value = format!("{value:?}");
+            }
+            parts.push(value);
+        }
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> Command {
+        Command::new(
+            line.split(' ')
+                .map(|x| Bytes::copy_from_slice(x.as_bytes()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_audit_string() {
+        assert_eq!(command("get foo").audit_string(), "GET foo");
+        assert_eq!(command("AUTH alice secret").audit_string(), "AUTH *** ***");
+        assert_eq!(
+            command("HELLO 3 AUTH alice secret SETNAME app").audit_string(),
+            "HELLO 3 AUTH alice *** SETNAME app"
+        );
+        assert_eq!(
+            command("CONFIG SET requirepass secret maxmemory 1gb").audit_string(),
+            "CONFIG SET requirepass *** maxmemory 1gb"
+        );
+        assert_eq!(
+            command("config set maxmemory 1gb").audit_string(),
+            "CONFIG set maxmemory 1gb"
+        );
+        assert_eq!(
+            command("ACL SETUSER bob on >secret ~keys:*").audit_string(),
+            "ACL SETUSER bob on *** ~keys:*"
+        );
+        assert_eq!(
+            command("MIGRATE host 6379 key 0 5000 AUTH2 bob secret").audit_string(),
+            "MIGRATE host 6379 key 0 5000 AUTH2 bob ***"
+        );
+
+        let long = "x".repeat(100);
+        assert_eq!(
+            command(&format!("SET foo {long}")).audit_string(),
+            format!("SET foo {}...", &long[..MAX_LOGGED_ARG_LENGTH])
+        );
+        let mut set = command("SET");
+        set.args.push(Bytes::from("a b"));
+        assert_eq!(set.audit_string(), "SET \"a b\"");
+    }
+
+    #[test]
+    fn test_parse_hello() {
+        assert_eq!(
+            command("hello 3 auth alice#db secret setname app").parse_hello(),
+            Some(Hello {
+                protocol_version: Some(Bytes::from("3")),
+                auth: Some(("alice#db".into(), "secret".into())),
+                client_name: Some("app".into()),
+            })
+        );
+        assert_eq!(
+            Command::new(vec![Bytes::from("HELLO")]).parse_hello(),
+            Some(Hello {
+                protocol_version: None,
+                auth: None,
+                client_name: None,
+            })
+        );
+        assert_eq!(
+            command("HELLO 3 AUTH alice secret SETNAME app")
+                .parse_hello()
+                .unwrap()
+                .without_auth(),
+            command("HELLO 3 SETNAME app")
+        );
+        assert_eq!(command("HELLO 3 AUTH alice").parse_hello(), None);
+        assert_eq!(command("PING").parse_hello(), None);
+    }
+}
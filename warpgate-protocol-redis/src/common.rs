@@ -0,0 +1,3 @@
+use warpgate_common::ProtocolName;
+
+pub const PROTOCOL_NAME: ProtocolName = "Redis";
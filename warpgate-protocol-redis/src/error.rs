@@ -0,0 +1,43 @@
+use std::error::Error;
+
+use warpgate_common::{MaybeTlsStreamError, RustlsSetupError, WarpgateError};
+
+use crate::stream::RedisStreamError;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RedisError {
+    #[error("remote error: {0}")]
+    RemoteError(String),
+    #[error("sudden disconnection")]
+    Eof,
+    #[error("stream: {0}")]
+    Stream(#[from] RedisStreamError),
+    #[error("TLS setup failed: {0}")]
+    TlsSetup(#[from] RustlsSetupError),
+    #[error("TLS stream error: {0}")]
+    Tls(#[from] MaybeTlsStreamError),
+    #[error("Invalid domain name")]
+    InvalidDomainName,
+    #[error("I/O: {0}")]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Warpgate(#[from] WarpgateError),
+    #[error(transparent)]
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl RedisError {
+    pub fn other<E: Error + Send + Sync + 'static>(err: E) -> Self {
+        Self::Other(Box::new(err))
+    }
+
+    /// Whether the target rejected Warpgate's credentials
+    pub fn is_auth_error(&self) -> bool {
+        match self {
+            Self::RemoteError(message) => {
+                message.starts_with("WRONGPASS") || message.starts_with("NOAUTH")
+            }
+            _ => false,
+        }
+    }
+}
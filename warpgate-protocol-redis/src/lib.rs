@@ -0,0 +1,176 @@
+mod client;
+mod command;
+mod common;
+mod error;
+mod resp;
+mod session;
+mod session_handle;
+mod stream;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use client::RedisClient;
+use command::Command;
+use error::RedisError;
+use futures::TryStreamExt;
+use rustls::server::NoClientAuth;
+use rustls::ServerConfig;
+use session::RedisSession;
+use session_handle::RedisSessionHandle;
+use tokio::sync::mpsc;
+use tracing::*;
+use warpgate_common::{
+    ListenEndpoint, ResolveServerCert, Target, TargetOptions, TlsCertificateAndPrivateKey,
+    TlsCertificateBundle, TlsPrivateKey,
+};
+use warpgate_core::{
+    ProtocolServer, Services, SessionStateInit, TargetTestError, CLOSE_MESSAGE_TIMEOUT,
+};
+
+pub struct RedisProtocolServer {
+    services: Services,
+}
+
+impl RedisProtocolServer {
+    pub async fn new(services: &Services) -> Result<Self> {
+        Ok(RedisProtocolServer {
+            services: services.clone(),
+        })
+    }
+}
+
+impl ProtocolServer for RedisProtocolServer {
+    async fn run(self, address: ListenEndpoint) -> Result<()> {
+        let certificate_and_key = {
+            let config = self.services.config.load();
+            let certificate_path = config
+                .paths_relative_to
+                .join(&config.store.redis.certificate);
+            let key_path = config.paths_relative_to.join(&config.store.redis.key);
+
+            TlsCertificateAndPrivateKey {
+                certificate: TlsCertificateBundle::from_file(&certificate_path)
+                    .await
+                    .with_context(|| {
+                        format!(
+                            "reading SSL certificate from '{}'",
+                            certificate_path.display()
+                        )
+                    })?,
+                private_key: TlsPrivateKey::from_file(&key_path).await.with_context(|| {
+                    format!("reading SSL private key from '{}'", key_path.display())
+                })?,
+            }
+        };
+
+        let tls_config = ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_client_cert_verifier(Arc::new(NoClientAuth))
+        .with_cert_resolver(Arc::new(ResolveServerCert(Arc::new(
+            certificate_and_key.into(),
+        ))));
+
+        info!(?address, "Listening");
+        let socket_options = self.services.config.load().store.redis.socket.clone();
+        let mut listener = address.tcp_accept_stream(&socket_options).await?;
+        loop {
+            let Some(stream) = listener.try_next().await? else {
+                return Ok(());
+            };
+            let remote_address = stream.peer_addr()?;
+
+            if !self.services.ip_bans.is_allowed(remote_address.ip()) {
+                debug!(%remote_address, "Dropping a connection from a banned address");
+                continue;
+            }
+
+            let tls_config = tls_config.clone();
+            let services = self.services.clone();
+            tokio::spawn(async move {
+                let (session_handle, mut abort_rx) = RedisSessionHandle::new();
+
+                let server_handle = services
+                    .state
+                    .lock()
+                    .await
+                    .register_session(
+                        &crate::common::PROTOCOL_NAME,
+                        SessionStateInit {
+                            remote_address: Some(remote_address),
+                            handle: Box::new(session_handle),
+                        },
+                    )
+                    .await?;
+
+                let span = server_handle.lock().await.context().span().clone();
+                let (close_message_tx, close_message_rx) = mpsc::unbounded_channel();
+                let session = RedisSession::new(
+                    server_handle,
+                    services,
+                    stream,
+                    tls_config,
+                    remote_address,
+                    close_message_rx,
+                )
+                .await;
+                let run = session.run().instrument(span);
+                tokio::pin!(run);
+                tokio::select! {
+                    result = &mut run => match result {
+                        Ok(_) => info!("Session ended"),
+                        Err(e) => error!(error=%e, "Session failed"),
+                    },
+                    message = abort_rx.recv() => {
+                        warn!("Session aborted by admin");
+                        if let Some(Some(message)) = message {
+                            // Give the session a chance to tell the client why
+                            let _ = close_message_tx.send(message);
+                            let _ = tokio::time::timeout(CLOSE_MESSAGE_TIMEOUT, run).await;
+                        }
+                    },
+                }
+
+                Ok::<(), anyhow::Error>(())
+            });
+        }
+    }
+
+    async fn test_target(&self, target: Target) -> Result<(), TargetTestError> {
+        let resolver = self.services.dns.for_target(&target);
+        let TargetOptions::Redis(options) = target.options else {
+            return Err(TargetTestError::Misconfigured(
+                "Not a Redis target".to_owned(),
+            ));
+        };
+        async {
+            let mut client = RedisClient::connect(&options, &resolver).await?;
+            self.services
+                .target_capabilities
+                .record(&target.name, client.negotiated_capabilities());
+            client
+                .request(&Command::new(vec![Bytes::from_static(b"PING")]))
+                .await
+        }
+        .await
+        .map_err(|e| match e {
+            RedisError::Tls(_) | RedisError::TlsSetup(_) | RedisError::InvalidDomainName => {
+                TargetTestError::Untrusted(format!("{e}"))
+            }
+            ref e if e.is_auth_error() => TargetTestError::AuthenticationError,
+            RedisError::Io(e) => TargetTestError::Io(e),
+            e => TargetTestError::ConnectionError(format!("{e}")),
+        })?;
+        Ok(())
+    }
+}
+
+impl Debug for RedisProtocolServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RedisProtocolServer")
+    }
+}
@@ -0,0 +1,263 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::command::Command;
+
+/// Same as Redis' default `proto-max-bulk-len`
+const MAX_BULK_LENGTH: usize = 512 * 1024 * 1024;
+
+const MAX_ARRAY_LENGTH: usize = 1024 * 1024;
+
+/// Same as Redis' limit for inline commands and header lines
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum RespError {
+    #[error("invalid {0}")]
+    Invalid(&'static str),
+    #[error("unsupported RESP type {0:?}")]
+    UnsupportedType(char),
+    #[error("{0} too long")]
+    TooLong(&'static str),
+}
+
+/// Returns the line without its terminator and the position after it
+fn read_line(buf: &[u8], start: usize) -> Result<Option<(&[u8], usize)>, RespError> {
+    let rest = &buf[start.min(buf.len())..];
+    let Some(end) = rest.iter().position(|x| *x == b'\n') else {
+        if rest.len() > MAX_LINE_LENGTH {
+            return Err(RespError::TooLong("line"));
+        }
+        return Ok(None);
+    };
+    let line = &rest[..end];
+    Ok(Some((
+        line.strip_suffix(b"\r").unwrap_or(line),
+        start + end + 1,
+    )))
+}
+
+fn parse_length(line: &[u8], max: usize) -> Result<Option<usize>, RespError> {
+    let value = std::str::from_utf8(line)
+        .ok()
+        .and_then(|x| x.parse::<i64>().ok())
+        .ok_or(RespError::Invalid("length"))?;
+    if value < 0 {
+        return Ok(None);
+    }
+    if value as u64 > max as u64 {
+        return Err(RespError::TooLong("value"));
+    }
+    Ok(Some(value as usize))
+}
+
+/// Takes one client command off the buffer, either a RESP array
+/// of bulk strings or an inline command like `PING`
+pub fn decode_command(buf: &mut BytesMut) -> Result<Option<Command>, RespError> {
+    loop {
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        if buf[0] != b'*' {
+            let Some((line, end)) = read_line(buf, 0)? else {
+                return Ok(None);
+            };
+            let args: Vec<Bytes> = line
+                .split(u8::is_ascii_whitespace)
+                .filter(|x| !x.is_empty())
+                .map(Bytes::copy_from_slice)
+                .collect();
+            buf.advance(end);
+            if args.is_empty() {
+                continue;
+            }
+            return Ok(Some(Command::new(args)));
+        }
+
+        let Some((header, mut position)) = read_line(buf, 1)? else {
+            return Ok(None);
+        };
+        let Some(count) = parse_length(header, MAX_ARRAY_LENGTH)? else {
+            buf.advance(position);
+            continue;
+        };
+
+        let mut spans = Vec::with_capacity(count);
+        for _ in 0..count {
+            if position >= buf.len() {
+                return Ok(None);
+            }
+            if buf[position] != b'$' {
+                return Err(RespError::Invalid("command argument"));
+            }
+            let Some((header, start)) = read_line(buf, position + 1)? else {
+                return Ok(None);
+            };
+            let length =
+                parse_length(header, MAX_BULK_LENGTH)?.ok_or(RespError::Invalid("argument"))?;
+            position = start + length + 2;
+            if buf.len() < position {
+                return Ok(None);
+            }
+            if &buf[start + length..position] != b"\r\n" {
+                return Err(RespError::Invalid("argument terminator"));
+            }
+            spans.push(start..start + length);
+        }
+
+        let frame = buf.split_to(position).freeze();
+        if spans.is_empty() {
+            continue;
+        }
+        return Ok(Some(Command::new(
+            spans.into_iter().map(|x| frame.slice(x)).collect(),
+        )));
+    }
+}
+
+/// Length of the complete RESP2 or RESP3 value at the start of `buf`,
+/// or [None] if more data is needed
+pub fn reply_length(buf: &[u8]) -> Result<Option<usize>, RespError> {
+    let mut position = 0;
+    // Values still to be read, including those inside aggregates
+    let mut remaining = 1usize;
+    while remaining > 0 {
+        remaining -= 1;
+        let Some(&kind) = buf.get(position) else {
+            return Ok(None);
+        };
+        let Some((line, end)) = read_line(buf, position + 1)? else {
+            return Ok(None);
+        };
+        position = end;
+        let nested = match kind {
+            b'+' | b'-' | b':' | b'_' | b'#' | b',' | b'(' => 0,
+            b'$' | b'!' | b'=' => {
+                if let Some(length) = parse_length(line, MAX_BULK_LENGTH)? {
+                    position += length + 2;
+                    if buf.len() < position {
+                        return Ok(None);
+                    }
+                }
+                0
+            }
+            b'*' | b'~' | b'>' => parse_length(line, MAX_ARRAY_LENGTH)?.unwrap_or(0),
+            b'%' => parse_length(line, MAX_ARRAY_LENGTH)?.unwrap_or(0) * 2,
+            // Attributes come before the value they describe
+            b'|' => parse_length(line, MAX_ARRAY_LENGTH)?.unwrap_or(0) * 2 + 1,
+            other => return Err(RespError::UnsupportedType(other as char)),
+        };
+        remaining = remaining
+            .checked_add(nested)
+            .ok_or(RespError::TooLong("reply"))?;
+    }
+    Ok(Some(position))
+}
+
+/// Takes one complete reply off the buffer without decoding it
+pub fn decode_reply(buf: &mut BytesMut) -> Result<Option<Bytes>, RespError> {
+    Ok(reply_length(buf)?.map(|length| buf.split_to(length).freeze()))
+}
+
+/// Whether the reply is a RESP3 push message rather than
+/// the answer to a command
+pub fn is_push(reply: &[u8]) -> bool {
+    reply.first() == Some(&b'>')
+}
+
+/// The message of an error reply
+pub fn error_message(reply: &[u8]) -> Option<String> {
+    let message = match reply.first()? {
+        b'-' => reply.get(1..)?,
+        // Blob error: `!<length>\r\n<message>\r\n`
+        b'!' => {
+            let start = reply.iter().position(|x| *x == b'\n')? + 1;
+            reply.get(start..reply.len().checked_sub(2)?)?
+        }
+        _ => return None,
+    };
+    let message = message.strip_suffix(b"\r\n").unwrap_or(message);
+    Some(String::from_utf8_lossy(message).into_owned())
+}
+
+pub fn encode_command(command: &Command, buf: &mut BytesMut) {
+    buf.extend_from_slice(format!("*{}\r\n", command.args().len()).as_bytes());
+    for arg in command.args() {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg);
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+/// `message` has to start with an error code like `ERR`
+pub fn encode_error(message: &str, buf: &mut BytesMut) {
+    buf.extend_from_slice(b"-");
+    buf.extend_from_slice(message.replace(['\r', '\n'], " ").as_bytes());
+    buf.extend_from_slice(b"\r\n");
+}
+
+pub fn encode_simple_string(value: &str, buf: &mut BytesMut) {
+    buf.extend_from_slice(b"+");
+    buf.extend_from_slice(value.as_bytes());
+    buf.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(command: Command) -> Vec<Bytes> {
+        command.args().to_vec()
+    }
+
+    #[test]
+    fn test_decode_command() {
+        let mut buf =
+            BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$3\r\nfoo\r\nPING\r\n\r\n*1\r\n$4\r\nPI"[..]);
+        assert_eq!(
+            args(decode_command(&mut buf).unwrap().unwrap()),
+            vec![Bytes::from("GET"), Bytes::from("foo")]
+        );
+        assert_eq!(
+            args(decode_command(&mut buf).unwrap().unwrap()),
+            vec![Bytes::from("PING")]
+        );
+        assert_eq!(decode_command(&mut buf), Ok(None));
+        buf.extend_from_slice(b"NG\r\n");
+        assert_eq!(
+            args(decode_command(&mut buf).unwrap().unwrap()),
+            vec![Bytes::from("PING")]
+        );
+        assert!(buf.is_empty());
+
+        let mut buf = BytesMut::from(&b"*1\r\n:1\r\n"[..]);
+        assert!(decode_command(&mut buf).is_err());
+        let mut buf = BytesMut::from(&b"*1\r\n$600000000\r\n"[..]);
+        assert!(decode_command(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_reply_length() {
+        assert_eq!(reply_length(b"+OK\r\n"), Ok(Some(5)));
+        assert_eq!(reply_length(b"$3\r\nfoo\r\n+OK"), Ok(Some(9)));
+        assert_eq!(reply_length(b"$-1\r\n"), Ok(Some(5)));
+        assert_eq!(reply_length(b"*2\r\n$3\r\nfoo\r\n:1\r\n"), Ok(Some(17)));
+        assert_eq!(reply_length(b"*2\r\n$3\r\nfoo\r\n"), Ok(None));
+        assert_eq!(reply_length(b"%1\r\n+a\r\n*1\r\n_\r\n"), Ok(Some(15)));
+        assert_eq!(reply_length(b"|1\r\n+a\r\n+b\r\n:1\r\n"), Ok(Some(16)));
+        assert_eq!(reply_length(b"$3\r\nfo"), Ok(None));
+        assert!(reply_length(b"?1\r\n").is_err());
+    }
+
+    #[test]
+    fn test_error_message() {
+        assert_eq!(
+            error_message(b"-WRONGPASS invalid password\r\n").as_deref(),
+            Some("WRONGPASS invalid password")
+        );
+        assert_eq!(
+            error_message(b"!7\r\nERR foo\r\n").as_deref(),
+            Some("ERR foo")
+        );
+        assert_eq!(error_message(b"+OK\r\n"), None);
+    }
+}
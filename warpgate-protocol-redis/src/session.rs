@@ -0,0 +1,602 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use rustls::ServerConfig;
+use sea_orm::ActiveValue::Set;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, Mutex};
+use tokio_rustls::server::TlsStream;
+use tracing::*;
+use warpgate_common::auth::{
+    AuthCredential, AuthResult, AuthSelector, CredentialKind, TargetParameters,
+};
+use warpgate_common::{
+    ErrorCode, Secret, TargetOptions, TargetRedisOptions, TlsMode, WarpgateError,
+};
+use warpgate_core::{
+    authorize_ticket, check_target_baseline, consume_ticket, resolve_target_address, trip_honeypot,
+    AuthFailureContext, ConfigProvider, Services, SessionLastActivity, TargetFingerprint,
+    TargetObservation, WarpgateServerHandle,
+};
+use warpgate_db_entities::DatabaseSessionDetails;
+use warpgate_db_entities::Session::SessionTerminationReason;
+
+use crate::client::RedisClient;
+use crate::command::{Command, Hello};
+use crate::error::RedisError;
+use crate::resp::is_push;
+use crate::stream::{RedisStream, FLUSH_THRESHOLD};
+
+/// Same as Redis' own message
+const HELLO_WITHOUT_AUTH: &str = "NOAUTH HELLO must be called with the client already \
+    authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used \
+    to authenticate the client and select the RESP protocol version at the same time";
+
+/// How the client logged in, which decides how it's answered
+/// once the target connection is up
+enum Login {
+    Auth,
+    Hello(Hello),
+}
+
+pub struct RedisSession {
+    stream: RedisStream<TlsStream<TcpStream>>,
+    tls_config: Arc<ServerConfig>,
+    client_name: Option<String>,
+    server_handle: Arc<Mutex<WarpgateServerHandle>>,
+    last_activity: SessionLastActivity,
+    services: Services,
+    remote_address: SocketAddr,
+    /// Messages from an admin closing the session
+    close_messages: mpsc::UnboundedReceiver<String>,
+}
+
+impl RedisSession {
+    pub async fn new(
+        server_handle: Arc<Mutex<WarpgateServerHandle>>,
+        services: Services,
+        stream: TcpStream,
+        tls_config: ServerConfig,
+        remote_address: SocketAddr,
+        close_messages: mpsc::UnboundedReceiver<String>,
+    ) -> Self {
+        let last_activity = server_handle.lock().await.last_activity().clone();
+        Self {
+            services,
+            stream: RedisStream::new(stream),
+            tls_config: Arc::new(tls_config),
+            client_name: None,
+            server_handle,
+            last_activity,
+            remote_address,
+            close_messages,
+        }
+    }
+
+    pub async fn run(mut self) -> Result<(), RedisError> {
+        self.stream = self.stream.upgrade(self.tls_config.clone()).await?;
+        debug!("TLS setup complete");
+
+        loop {
+            let Some(command) = self.stream.recv_command().await? else {
+                return Ok(());
+            };
+            debug!(command=%command.name(), "Received command before login");
+
+            let (selector, password, login) = match command.name().as_str() {
+                "AUTH" => match command.args() {
+                    [_, username, password] => (
+                        String::from_utf8_lossy(username).into_owned(),
+                        String::from_utf8_lossy(password).into_owned(),
+                        Login::Auth,
+                    ),
+                    // A ticket doesn't need a password
+                    [_, ticket] => (
+                        String::from_utf8_lossy(ticket).into_owned(),
+                        String::new(),
+                        Login::Auth,
+                    ),
+                    _ => {
+                        self.send_error("ERR wrong number of arguments for 'auth' command")
+                            .await?;
+                        continue;
+                    }
+                },
+                "HELLO" => match command.parse_hello() {
+                    Some(Hello {
+                        auth: Some((username, password)),
+                        protocol_version,
+                        client_name,
+                    }) => {
+                        self.client_name = client_name.clone();
+                        (
+                            username,
+                            password,
+                            Login::Hello(Hello {
+                                protocol_version,
+                                auth: None,
+                                client_name,
+                            }),
+                        )
+                    }
+                    Some(_) => {
+                        self.send_error(HELLO_WITHOUT_AUTH).await?;
+                        continue;
+                    }
+                    None => {
+                        self.send_error("ERR Syntax error in HELLO option").await?;
+                        continue;
+                    }
+                },
+                "QUIT" => {
+                    self.stream.push_ok();
+                    self.stream.flush().await?;
+                    return Ok(());
+                }
+                _ => {
+                    self.send_error("NOAUTH Authentication required.").await?;
+                    continue;
+                }
+            };
+
+            let selector: AuthSelector = selector.into();
+            if let (AuthSelector::User { .. }, Login::Auth) = (&selector, &login) {
+                if command.args().len() < 3 {
+                    self.send_error(
+                        "ERR Warpgate needs a username: AUTH <username>#<target> <password>",
+                    )
+                    .await?;
+                    continue;
+                }
+            }
+
+            let client_banner = self.client_name.clone();
+            return match self
+                .authenticate(selector, Secret::new(password), client_banner)
+                .await?
+            {
+                Some((username, target_name, parameters)) => {
+                    self.run_authorized(login, username, target_name, parameters)
+                        .await
+                }
+                None => {
+                    self.send_error(&format!(
+                        "WRONGPASS {}",
+                        ErrorCode::AuthFailed.annotate("Authentication failed")
+                    ))
+                    .await
+                }
+            };
+        }
+    }
+
+    async fn send_error(&mut self, message: &str) -> Result<(), RedisError> {
+        self.stream.push_error(message);
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn authenticate(
+        &mut self,
+        selector: AuthSelector,
+        password: Secret<String>,
+        client_banner: Option<String>,
+    ) -> Result<Option<(String, String, TargetParameters)>, RedisError> {
+        let username = match &selector {
+            AuthSelector::User { username, .. } => Some(username.as_str()),
+            AuthSelector::Ticket { .. } => None,
+        };
+        let client_ip = self.remote_address.ip().to_string();
+        if !self
+            .services
+            .auth_rate_limiter
+            .allows(username, Some(&client_ip))
+            .await
+        {
+            return Ok(None);
+        }
+
+        match selector {
+            AuthSelector::User {
+                username,
+                target_name,
+                parameters,
+            } => {
+                let state = self
+                    .services
+                    .auth_state_store
+                    .lock()
+                    .await
+                    .create(
+                        Some(&self.server_handle.lock().await.id()),
+                        &username,
+                        crate::common::PROTOCOL_NAME,
+                        &[CredentialKind::Password],
+                    )
+                    .await;
+                let state_arc = match state {
+                    Ok((_, state)) => state,
+                    Err(WarpgateError::UserNotFound(_)) => {
+                        self.record_auth_failure(
+                            client_banner,
+                            Some(&username),
+                            vec![CredentialKind::Password],
+                        )
+                        .await;
+                        return Ok(None);
+                    }
+                    Err(error) => return Err(error.into()),
+                };
+                let mut state = state_arc.lock().await;
+
+                let user_auth_result = {
+                    let credential = AuthCredential::Password(password);
+
+                    let mut cp = self.services.config_provider.lock().await;
+                    if cp.validate_credential(&username, &credential).await? {
+                        state.add_valid_credential(credential);
+                    } else {
+                        state.add_invalid_credential(&credential);
+                    }
+
+                    state.verify()
+                };
+
+                match user_auth_result {
+                    AuthResult::Accepted { username } => {
+                        self.services
+                            .auth_state_store
+                            .lock()
+                            .await
+                            .complete(state.id())
+                            .await;
+                        let target_auth_result = {
+                            self.services
+                                .config_provider
+                                .lock()
+                                .await
+                                .authorize_target(&username, &target_name)
+                                .await
+                                .map_err(RedisError::other)?
+                        };
+                        if !target_auth_result {
+                            warn!(
+                                "Target {} not authorized for user {}",
+                                target_name, username
+                            );
+                            return Ok(None);
+                        }
+                        Ok(Some((username, target_name, parameters)))
+                    }
+                    AuthResult::Rejected | AuthResult::Need(_) => {
+                        let credential_kinds = state.attempted_credential_kinds();
+                        self.record_auth_failure(client_banner, Some(&username), credential_kinds)
+                            .await;
+                        Ok(None)
+                    }
+                }
+            }
+            AuthSelector::Ticket { secret } => {
+                match authorize_ticket(&self.services.db, &secret)
+                    .await
+                    .map_err(RedisError::other)?
+                {
+                    Some(ticket) => {
+                        info!("Authorized for {} with a ticket", ticket.target);
+                        consume_ticket(&self.services.db, &ticket.id)
+                            .await
+                            .map_err(RedisError::other)?;
+                        Ok(Some((
+                            ticket.username,
+                            ticket.target,
+                            TargetParameters::new(),
+                        )))
+                    }
+                    _ => {
+                        self.record_auth_failure(client_banner, None, vec![]).await;
+                        Ok(None)
+                    }
+                }
+            }
+        }
+    }
+
+    async fn record_auth_failure(
+        &self,
+        client_banner: Option<String>,
+        username: Option<&str>,
+        credential_kinds: Vec<CredentialKind>,
+    ) {
+        let context = AuthFailureContext {
+            username: username.map(Into::into),
+            credential_kinds,
+            client_banner,
+            ..AuthFailureContext::new(crate::common::PROTOCOL_NAME, self.remote_address)
+        };
+        self.services
+            .auth_rate_limiter
+            .record_failure(&context)
+            .await;
+        self.services.auth_failures.record(context).await;
+    }
+
+    async fn run_authorized(
+        mut self,
+        login: Login,
+        username: String,
+        target_name: String,
+        parameters: TargetParameters,
+    ) -> Result<(), RedisError> {
+        let target = {
+            self.services
+                .config_provider
+                .lock()
+                .await
+                .list_targets()
+                .await?
+                .iter()
+                .filter_map(|t| match t.options {
+                    TargetOptions::Redis(ref options) => Some((t, options)),
+                    _ => None,
+                })
+                .find(|(t, _)| t.name == target_name)
+                .map(|(t, opt)| (t.clone(), opt.clone()))
+        };
+
+        let Some((mut target, mut redis_options)) = target else {
+            warn!("Selected target not found");
+            self.send_error(&format!(
+                "ERR {}",
+                ErrorCode::TargetNotFound
+                    .annotate(format!("Warpgate target {target_name} not found"))
+            ))
+            .await?;
+            return Ok(());
+        };
+
+        if !target.honeypot {
+            if let Err(error) =
+                resolve_target_address(&self.services, &username, &mut target, &parameters).await
+            {
+                warn!(%error, "Could not resolve the target address");
+                self.send_error(&format!("ERR {}", error.code().annotate(&error)))
+                    .await?;
+                return Ok(());
+            }
+        }
+        if let TargetOptions::Redis(ref options) = target.options {
+            redis_options = options.clone();
+        }
+
+        {
+            let handle = self.server_handle.lock().await;
+            handle.set_username(username.clone()).await?;
+            if let Err(error) = handle.set_target(&target).await {
+                if let WarpgateError::TargetDraining(_)
+                | WarpgateError::TargetSessionLimit(..)
+                | WarpgateError::RecordingQuotaExceeded(_) = error
+                {
+                    drop(handle);
+                    warn!(%error, "Selected target doesn't accept new sessions");
+                    self.send_error(&format!("ERR {}", error.code().annotate(&error)))
+                        .await?;
+                    return Ok(());
+                }
+                return Err(error.into());
+            }
+            handle
+                .update_database_details(DatabaseSessionDetails::ActiveModel {
+                    client_application: Set(self.client_name.clone()),
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        if target.honeypot {
+            let session_id = self.server_handle.lock().await.id();
+            trip_honeypot(
+                &self.services,
+                &target,
+                crate::common::PROTOCOL_NAME,
+                Some(session_id),
+                Some(&username),
+                Some(self.remote_address.to_string()),
+            )
+            .await;
+            self.send_error(&format!(
+                "ERR {}",
+                ErrorCode::AccessDenied.annotate("Access denied")
+            ))
+            .await?;
+            return Ok(());
+        }
+
+        let resolver = self.services.dns.for_target(&target);
+        let mut client = match RedisClient::connect(&redis_options, &resolver).await {
+            Err(error) => {
+                error!(%error, "Target connection failed");
+                self.mark_target_error().await;
+                self.send_error(&format!(
+                    "ERR {}",
+                    ErrorCode::TargetConnectionFailed.annotate("Warpgate target connection failed")
+                ))
+                .await?;
+                return Err(error);
+            }
+            Ok(client) => client,
+        };
+
+        self.check_target_tls(&client, &target.name, &redis_options)
+            .await;
+
+        let mut pending_replies = 0;
+        match login {
+            Login::Auth => {
+                self.stream.push_ok();
+                self.stream.flush().await?;
+            }
+            // The target's HELLO reply goes back to the client
+            Login::Hello(hello) => {
+                let command = hello.without_auth();
+                info!(command=%command.audit_string(), "Command");
+                client.stream.push_command(&command);
+                client.stream.flush().await?;
+                pending_replies += 1;
+            }
+        }
+
+        self.relay(client, pending_replies).await
+    }
+
+    /// Remembers the target's certificate and negotiated capabilities
+    /// and checks the connection against the target's baseline
+    async fn check_target_tls(
+        &self,
+        client: &RedisClient,
+        target_name: &str,
+        options: &TargetRedisOptions,
+    ) {
+        self.services
+            .target_capabilities
+            .record(target_name, client.negotiated_capabilities());
+        let connection = client.tls_connection();
+        if let Some(cert) = connection
+            .and_then(|c| c.peer_certificates())
+            .and_then(|c| c.first())
+        {
+            self.services
+                .target_fingerprints
+                .record(target_name, TargetFingerprint::tls_certificate(cert));
+        }
+        if options.tls.mode == TlsMode::Disabled {
+            return;
+        }
+        let observation =
+            TargetObservation::tls(target_name, &options.host, options.port, connection);
+        if let Err(error) = check_target_baseline(&self.services, observation).await {
+            error!(%error, "Failed to check the target baseline");
+        }
+    }
+
+    async fn mark_target_error(&self) {
+        self.server_handle
+            .lock()
+            .await
+            .set_termination_reason(SessionTerminationReason::TargetError)
+            .await;
+    }
+
+    /// Commands that Warpgate answers itself instead of passing them on
+    fn reject_reason(command: &Command) -> Option<&'static str> {
+        match command.name().as_str() {
+            "AUTH" => Some("ERR Warpgate doesn't support switching users, reconnect instead"),
+            "HELLO" => match command.parse_hello() {
+                Some(Hello { auth: None, .. }) => None,
+                Some(_) => Some("ERR Warpgate doesn't support switching users, reconnect instead"),
+                None => Some("ERR Syntax error in HELLO option"),
+            },
+            // Would log the target connection out of Warpgate's user
+            "RESET" => Some("ERR RESET isn't supported by Warpgate, reconnect instead"),
+            _ => None,
+        }
+    }
+
+    async fn relay(
+        mut self,
+        mut client: RedisClient,
+        mut pending_replies: usize,
+    ) -> Result<(), RedisError> {
+        loop {
+            tokio::select! {
+                c_to_s = self.stream.recv_command() => {
+                    match c_to_s {
+                        Ok(Some(command)) => {
+                            self.last_activity.touch();
+                            info!(command=%command.audit_string(), "Command");
+                            if let Some(reason) = Self::reject_reason(&command) {
+                                warn!(command=%command.name(), "Command rejected");
+                                // Keep the replies in order
+                                if !self.drain_replies(&mut client, &mut pending_replies).await? {
+                                    break
+                                }
+                                self.send_error(reason).await?;
+                                continue
+                            }
+                            pending_replies += 1;
+                            client.stream.push_command(&command);
+                            if !self.stream.has_buffered_input()
+                                || client.stream.pending_outbound() >= FLUSH_THRESHOLD
+                            {
+                                client.stream.flush().await?;
+                            }
+                        }
+                        Ok(None) => {
+                            break
+                        }
+                        Err(err) => {
+                            error!(error=%err, "Error receiving command");
+                            break
+                        }
+                    };
+                },
+                s_to_c = client.stream.recv_reply() => {
+                    match s_to_c {
+                        Ok(Some(reply)) => {
+                            self.relay_reply(&reply, &mut pending_replies);
+                            if !client.stream.has_buffered_input()
+                                || self.stream.pending_outbound() >= FLUSH_THRESHOLD
+                            {
+                                self.stream.flush().await?;
+                            }
+                        }
+                        Ok(None) => {
+                            break
+                        }
+                        Err(err) => {
+                            error!(error=%err, "Error receiving reply");
+                            break
+                        }
+                    };
+                }
+                Some(message) = self.close_messages.recv() => {
+                    self.send_error(&format!(
+                        "ERR {}",
+                        ErrorCode::SessionClosedByAdmin
+                            .annotate(format!("Session closed by admin: {message}"))
+                    ))
+                    .await?;
+                    break
+                }
+            };
+        }
+
+        Ok(())
+    }
+
+    fn relay_reply(&mut self, reply: &[u8], pending_replies: &mut usize) {
+        // Push messages aren't replies to a command. Neither are RESP2
+        // pub/sub messages, hence the saturation.
+        if !is_push(reply) {
+            *pending_replies = pending_replies.saturating_sub(1);
+        }
+        self.stream.push_reply(reply);
+    }
+
+    /// Passes on the replies to the commands that have already been sent
+    /// to the target. Returns `false` if the target has disconnected.
+    async fn drain_replies(
+        &mut self,
+        client: &mut RedisClient,
+        pending_replies: &mut usize,
+    ) -> Result<bool, RedisError> {
+        client.stream.flush().await?;
+        while *pending_replies > 0 {
+            let Some(reply) = client.stream.recv_reply().await? else {
+                self.stream.flush().await?;
+                return Ok(false);
+            };
+            self.relay_reply(&reply, pending_replies);
+        }
+        Ok(true)
+    }
+}
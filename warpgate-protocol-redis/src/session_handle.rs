@@ -0,0 +1,23 @@
+use tokio::sync::mpsc;
+use warpgate_core::SessionHandle;
+
+pub struct RedisSessionHandle {
+    abort_tx: mpsc::UnboundedSender<Option<String>>,
+}
+
+impl RedisSessionHandle {
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Option<String>>) {
+        let (abort_tx, abort_rx) = mpsc::unbounded_channel();
+        (RedisSessionHandle { abort_tx }, abort_rx)
+    }
+}
+
+impl SessionHandle for RedisSessionHandle {
+    fn close(&mut self) {
+        let _ = self.abort_tx.send(None);
+    }
+
+    fn close_with_message(&mut self, message: String) {
+        let _ = self.abort_tx.send(Some(message));
+    }
+}
@@ -0,0 +1,125 @@
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tracing::*;
+use warpgate_common::{MaybeTlsStream, MaybeTlsStreamError, UpgradableStream};
+
+use crate::command::Command;
+use crate::resp::{
+    decode_command, decode_reply, encode_command, encode_error, encode_simple_string, RespError,
+};
+
+/// Minimum free space kept in the inbound buffer before reading from the socket
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Pending outbound data is flushed once it grows past this size
+pub const FLUSH_THRESHOLD: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RedisStreamError {
+    #[error("RESP: {0}")]
+    Resp(#[from] RespError),
+    #[error("I/O: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub struct RedisStream<TS>
+where
+    TcpStream: UpgradableStream<TS>,
+    TS: AsyncRead + AsyncWrite + Unpin,
+{
+    stream: MaybeTlsStream<TcpStream, TS>,
+    inbound_buffer: BytesMut,
+    outbound_buffer: BytesMut,
+}
+
+impl<TS> RedisStream<TS>
+where
+    TcpStream: UpgradableStream<TS>,
+    TS: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream: MaybeTlsStream::new(stream),
+            inbound_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            outbound_buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+        }
+    }
+
+    pub fn push_command(&mut self, command: &Command) {
+        encode_command(command, &mut self.outbound_buffer);
+    }
+
+    /// Queues a reply as received from the other side
+    pub fn push_reply(&mut self, reply: &[u8]) {
+        self.outbound_buffer.extend_from_slice(reply);
+    }
+
+    pub fn push_error(&mut self, message: &str) {
+        encode_error(message, &mut self.outbound_buffer);
+    }
+
+    pub fn push_ok(&mut self) {
+        encode_simple_string("OK", &mut self.outbound_buffer);
+    }
+
+    pub fn pending_outbound(&self) -> usize {
+        self.outbound_buffer.len()
+    }
+
+    /// Whether there's already received data that might be decoded
+    /// without waiting on the socket
+    pub fn has_buffered_input(&self) -> bool {
+        !self.inbound_buffer.is_empty()
+    }
+
+    pub async fn flush(&mut self) -> std::io::Result<()> {
+        trace!(outbound_buffer=?self.outbound_buffer, "sending");
+        self.stream.write_all(&self.outbound_buffer[..]).await?;
+        self.outbound_buffer.clear();
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    async fn read(&mut self) -> Result<bool, RedisStreamError> {
+        self.inbound_buffer.reserve(READ_BUFFER_SIZE);
+        let read_bytes = self.stream.read_buf(&mut self.inbound_buffer).await?;
+        trace!(inbound_buffer=?self.inbound_buffer, "received chunk");
+        Ok(read_bytes > 0)
+    }
+
+    pub async fn recv_command(&mut self) -> Result<Option<Command>, RedisStreamError> {
+        loop {
+            if let Some(command) = decode_command(&mut self.inbound_buffer)? {
+                return Ok(Some(command));
+            }
+            if !self.read().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// A complete reply, undecoded
+    pub async fn recv_reply(&mut self) -> Result<Option<Bytes>, RedisStreamError> {
+        loop {
+            if let Some(reply) = decode_reply(&mut self.inbound_buffer)? {
+                return Ok(Some(reply));
+            }
+            if !self.read().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    pub async fn upgrade(
+        mut self,
+        config: <TcpStream as UpgradableStream<TS>>::UpgradeConfig,
+    ) -> Result<Self, MaybeTlsStreamError> {
+        self.stream = self.stream.upgrade(config).await?;
+        Ok(self)
+    }
+
+    pub fn tls(&self) -> Option<&TS> {
+        self.stream.tls()
+    }
+}
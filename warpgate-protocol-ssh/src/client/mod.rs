@@ -1,35 +1,51 @@
+mod aws_ssm;
 mod channel_direct_tcpip;
 mod channel_session;
 mod error;
 mod handler;
+mod honeypot;
+mod multiplex;
+mod sol;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
-use std::net::ToSocketAddrs;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use bytes::Bytes;
 use channel_direct_tcpip::DirectTCPIPChannel;
 use channel_session::SessionChannel;
 pub use error::SshClientError;
-use futures::pin_mut;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use handler::ClientHandler;
+pub use honeypot::HoneypotClient;
+pub use multiplex::MultiplexKey;
 use russh::client::Handle;
 use russh::keys::PublicKey;
 use russh::{kex, Preferred, Sig};
+pub use sol::SolClient;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::{oneshot, Mutex};
 use tokio::task::JoinHandle;
 use tracing::*;
 use uuid::Uuid;
-use warpgate_common::{SSHTargetAuth, SessionId, TargetSSHOptions};
-use warpgate_core::Services;
+use warpgate_common::{SSHTargetAuth, SessionId, TargetSSHOptions, TargetSshReconnect};
+use warpgate_core::{NegotiatedCapabilities, Services, TargetResolver};
 
 use self::handler::ClientHandlerEvent;
 use super::{ChannelOperation, DirectTCPIPParams};
 use crate::client::handler::ClientHandlerError;
-use crate::{load_all_usable_private_keys, ForwardedTcpIpParams};
+use crate::{
+    issue_user_certificate, load_all_usable_private_keys, load_ssh_ca_key, ForwardedTcpIpParams,
+    PtyRequest, RelayPermit, RELAY_WINDOW_SIZE,
+};
+
+/// Time given to channel tasks to notice that the connection is gone
+const CHANNEL_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConnectionError {
@@ -61,12 +77,20 @@ pub enum ConnectionError {
 
     #[error("Authentication failed")]
     Authentication,
+
+    #[error("AWS SSM: {0}")]
+    AwsSsm(String),
+}
+
+enum Transport {
+    Tcp(SocketAddr),
+    Ssm(aws_ssm::SsmStream),
 }
 
 #[derive(Debug)]
 pub enum RCEvent {
     State(RCState),
-    Output(Uuid, Bytes),
+    Output(Uuid, Bytes, RelayPermit),
     Success(Uuid),
     ChannelFailure(Uuid),
     Eof(Uuid),
@@ -84,8 +108,14 @@ pub enum RCEvent {
         channel: Uuid,
         data: Bytes,
         ext: u32,
+        permit: RelayPermit,
     },
     ConnectionError(ConnectionError),
+    /// The connection has dropped and is being re-established
+    Reconnecting {
+        attempt: u32,
+        attempts: u32,
+    },
     // ForwardedTCPIP(Uuid, DirectTCPIPParams),
     Done,
     HostKeyReceived(PublicKey),
@@ -97,8 +127,9 @@ pub enum RCEvent {
 pub type RCCommandReply = oneshot::Sender<Result<(), SshClientError>>;
 
 #[derive(Clone, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum RCCommand {
-    Connect(TargetSSHOptions),
+    Connect(TargetSSHOptions, TargetResolver, Option<MultiplexKey>),
     Channel(Uuid, ChannelOperation),
     ForwardTCPIP(String, u32),
     CancelTCPIPForward(String, u32),
@@ -113,12 +144,73 @@ pub enum RCState {
     Disconnected,
 }
 
+/// What it takes to reopen a shell channel on a new connection
+#[derive(Default)]
+struct ShellChannel {
+    pty: Option<PtyRequest>,
+    env: Vec<(String, String)>,
+    /// Set once the channel runs an interactive shell -
+    /// commands and subsystems can't be resumed
+    started: bool,
+    detached: Arc<AtomicBool>,
+}
+
 #[derive(Debug)]
 enum InnerEvent {
     RCCommand(RCCommand, Option<RCCommandReply>),
     ClientHandlerEvent(ClientHandlerEvent),
 }
 
+impl InnerEvent {
+    /// Whether this is input typed into one of the given channels
+    fn is_input_for(&self, channels: &HashSet<Uuid>) -> bool {
+        match self {
+            InnerEvent::RCCommand(RCCommand::Channel(id, op), _) => {
+                channels.contains(id) && is_input(op)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn is_input(op: &ChannelOperation) -> bool {
+    matches!(
+        op,
+        ChannelOperation::Data(..) | ChannelOperation::ExtendedData { .. }
+    )
+}
+
+/// Removes input for the given channels and returns how much was removed
+fn discard_input(
+    events: &mut VecDeque<InnerEvent>,
+    pending_ops: &mut Vec<(Uuid, ChannelOperation)>,
+    channels: &HashSet<Uuid>,
+) -> usize {
+    let before = events.len() + pending_ops.len();
+    events.retain(|event| !event.is_input_for(channels));
+    pending_ops.retain(|(id, op)| !(channels.contains(id) && is_input(op)));
+    before - events.len() - pending_ops.len()
+}
+
+/// The SSH client doesn't expose the negotiated key exchange, cipher
+/// and MAC algorithms, so only the host key and the auth method are reported
+pub fn negotiated_capabilities(
+    options: &TargetSSHOptions,
+    host_key_algorithm: Option<String>,
+) -> NegotiatedCapabilities {
+    let mut capabilities = NegotiatedCapabilities::new();
+    capabilities.ssh_host_key_algorithm = host_key_algorithm;
+    capabilities.auth_method = Some(
+        match options.auth {
+            SSHTargetAuth::Password(_) => "password",
+            SSHTargetAuth::PublicKey(_) => "publickey",
+            SSHTargetAuth::Certificate(_) => "publickey (certificate)",
+        }
+        .to_owned(),
+    );
+    capabilities
+}
+
 pub struct RemoteClient {
     id: SessionId,
     tx: UnboundedSender<RCEvent>,
@@ -126,10 +218,19 @@ pub struct RemoteClient {
     channel_pipes: Arc<Mutex<HashMap<Uuid, UnboundedSender<ChannelOperation>>>>,
     pending_ops: Vec<(Uuid, ChannelOperation)>,
     pending_forwards: Vec<(String, u32)>,
+    forwards: Vec<(String, u32)>,
+    shells: HashMap<Uuid, ShellChannel>,
+    options: Option<TargetSSHOptions>,
+    resolver: Option<TargetResolver>,
+    multiplex_key: Option<MultiplexKey>,
+    /// Set while `session` is a pooled connection shared with other sessions
+    shared: Option<multiplex::Lease<Handle<ClientHandler>>>,
     state: RCState,
     abort_rx: UnboundedReceiver<()>,
     inner_event_rx: UnboundedReceiver<InnerEvent>,
     inner_event_tx: UnboundedSender<InnerEvent>,
+    /// Events that queued up during a reconnect, handled before new ones
+    deferred_events: VecDeque<InnerEvent>,
     child_tasks: Vec<JoinHandle<Result<(), SshClientError>>>,
     services: Services,
 }
@@ -155,9 +256,16 @@ impl RemoteClient {
             channel_pipes: Arc::new(Mutex::new(HashMap::new())),
             pending_ops: vec![],
             pending_forwards: vec![],
+            forwards: vec![],
+            shells: HashMap::new(),
+            options: None,
+            resolver: None,
+            multiplex_key: None,
+            shared: None,
             state: RCState::NotInitialized,
             inner_event_rx,
             inner_event_tx: inner_event_tx.clone(),
+            deferred_events: VecDeque::new(),
             child_tasks: vec![],
             services,
             abort_rx,
@@ -185,6 +293,7 @@ impl RemoteClient {
     }
 
     fn set_disconnected(&mut self) {
+        self.release_shared();
         self.session = None;
         for (id, op) in self.pending_ops.drain(..) {
             if let ChannelOperation::OpenShell = op {
@@ -225,6 +334,8 @@ impl RemoteClient {
         channel_id: Uuid,
         op: ChannelOperation,
     ) -> Result<(), SshClientError> {
+        self.track_shell(channel_id, &op);
+
         if self.state != RCState::Connected {
             self.pending_ops.push((channel_id, op));
             return Ok(());
@@ -238,6 +349,9 @@ impl RemoteClient {
                 self.open_direct_tcpip(channel_id, params).await?;
             }
             op => {
+                if let (ChannelOperation::RequestX11(_), Some(shared)) = (&op, &self.shared) {
+                    shared.routes().request_x11();
+                }
                 let mut channel_pipes = self.channel_pipes.lock().await;
                 match channel_pipes.get(&channel_id) {
                     Some(tx) => {
@@ -252,12 +366,49 @@ impl RemoteClient {
         Ok(())
     }
 
+    fn track_shell(&mut self, channel_id: Uuid, op: &ChannelOperation) {
+        if let ChannelOperation::OpenShell = op {
+            self.shells.insert(channel_id, ShellChannel::default());
+            return;
+        }
+        let Some(shell) = self.shells.get_mut(&channel_id) else {
+            return;
+        };
+        match op {
+            ChannelOperation::RequestPty(request) => shell.pty = Some(request.clone()),
+            ChannelOperation::ResizePty(request) => {
+                if let Some(ref mut pty) = shell.pty {
+                    pty.col_width = request.col_width;
+                    pty.row_height = request.row_height;
+                    pty.pix_width = request.pix_width;
+                    pty.pix_height = request.pix_height;
+                }
+            }
+            ChannelOperation::RequestEnv(name, value) => {
+                shell.env.push((name.clone(), value.clone()));
+            }
+            ChannelOperation::RequestShell => shell.started = true,
+            ChannelOperation::RequestExec(_)
+            | ChannelOperation::RequestSubsystem(_)
+            | ChannelOperation::Close => {
+                self.shells.remove(&channel_id);
+            }
+            _ => (),
+        }
+    }
+
     pub fn start(mut self) -> io::Result<JoinHandle<anyhow::Result<()>>> {
         let name = format!("SSH {} client commands", self.id);
         tokio::task::Builder::new().name(&name).spawn(
             async move {
                 async {
                     loop {
+                        if let Some(event) = self.deferred_events.pop_front() {
+                            if self.handle_event(event).await? {
+                                break;
+                            }
+                            continue;
+                        }
                         tokio::select! {
                             Some(event) = self.inner_event_rx.recv() => {
                                 debug!(event=?event, "event");
@@ -334,7 +485,7 @@ impl RemoteClient {
         let (tx, rx) = unbounded_channel();
         self.channel_pipes.lock().await.insert(id, tx);
 
-        let session_channel = SessionChannel::new(channel, id, rx, self.tx.clone(), self.id);
+        let session_channel = SessionChannel::new(channel, id, rx, self.tx.clone(), self.id, None);
 
         self.child_tasks.push(
             tokio::task::Builder::new()
@@ -347,26 +498,31 @@ impl RemoteClient {
 
     async fn handle_command(&mut self, cmd: RCCommand) -> Result<bool, SshClientError> {
         match cmd {
-            RCCommand::Connect(options) => match self.connect(options).await {
-                Ok(_) => {
-                    self.set_state(RCState::Connected)
-                        .map_err(SshClientError::other)?;
-                    let ops = self.pending_ops.drain(..).collect::<Vec<_>>();
-                    for (id, op) in ops {
-                        self.apply_channel_op(id, op).await?;
+            RCCommand::Connect(options, resolver, multiplex_key) => {
+                self.multiplex_key = multiplex_key;
+                match self.connect(options.clone(), &resolver).await {
+                    Ok(_) => {
+                        self.options = Some(options);
+                        self.resolver = Some(resolver);
+                        self.set_state(RCState::Connected)
+                            .map_err(SshClientError::other)?;
+                        let ops = self.pending_ops.drain(..).collect::<Vec<_>>();
+                        for (id, op) in ops {
+                            self.apply_channel_op(id, op).await?;
+                        }
+                        let forwards = self.pending_forwards.drain(..).collect::<Vec<_>>();
+                        for (address, port) in forwards {
+                            self.tcpip_forward(address, port).await?;
+                        }
                     }
-                    let forwards = self.pending_forwards.drain(..).collect::<Vec<_>>();
-                    for (address, port) in forwards {
-                        self.tcpip_forward(address, port).await?;
+                    Err(e) => {
+                        debug!("Connect error: {}", e);
+                        let _ = self.tx.send(RCEvent::ConnectionError(e));
+                        self.set_disconnected();
+                        return Ok(true);
                     }
                 }
-                Err(e) => {
-                    debug!("Connect error: {}", e);
-                    let _ = self.tx.send(RCEvent::ConnectionError(e));
-                    self.set_disconnected();
-                    return Ok(true);
-                }
-            },
+            }
             RCCommand::Channel(ch, op) => {
                 self.apply_channel_op(ch, op).await?;
             }
@@ -384,22 +540,60 @@ impl RemoteClient {
         Ok(false)
     }
 
-    async fn connect(&mut self, ssh_options: TargetSSHOptions) -> Result<(), ConnectionError> {
-        let address_str = format!("{}:{}", ssh_options.host, ssh_options.port);
-        let address = match address_str
-            .to_socket_addrs()
-            .map_err(ConnectionError::Io)
-            .and_then(|mut x| x.next().ok_or(ConnectionError::Resolve))
-        {
-            Ok(address) => address,
+    async fn connect(
+        &mut self,
+        mut ssh_options: TargetSSHOptions,
+        resolver: &TargetResolver,
+    ) -> Result<(), ConnectionError> {
+        let mut pool_ticket = None;
+        if let (Some(key), Some(multiplexing)) = (&self.multiplex_key, &ssh_options.multiplexing) {
+            let checkout = tokio::select! {
+                checkout = multiplex::POOL.checkout(key, self.id, self.inner_event_tx.clone()) => checkout,
+                Some(_) = self.abort_rx.recv() => {
+                    info!("Abort requested");
+                    self.set_disconnected();
+                    return Err(ConnectionError::Aborted)
+                }
+            };
+            match checkout {
+                multiplex::Checkout::Joined(lease) => {
+                    info!("Reusing a shared connection");
+                    self.session = Some(lease.handle());
+                    self.shared = Some(lease);
+                    return Ok(());
+                }
+                multiplex::Checkout::Connect(ticket) => {
+                    pool_ticket = Some((ticket, Duration::from_secs(multiplexing.linger_seconds)));
+                }
+            }
+        }
+
+        let mut address_str = format!("{}:{}", ssh_options.host, ssh_options.port);
+        let transport = match ssh_options.aws_ssm {
+            Some(ref ssm_options) => aws_ssm::open_stream(ssm_options, ssh_options.port)
+                .await
+                .map(|(instance_id, stream)| {
+                    // Host keys are remembered per instance
+                    address_str = format!("ssm:{instance_id}");
+                    ssh_options.host = instance_id;
+                    Transport::Ssm(stream)
+                }),
+            None => resolver
+                .resolve(&ssh_options.host, ssh_options.port)
+                .await
+                .map_err(ConnectionError::Io)
+                .and_then(|x| x.into_iter().next().ok_or(ConnectionError::Resolve))
+                .map(Transport::Tcp),
+        };
+        let transport = match transport {
+            Ok(transport) => transport,
             Err(error) => {
                 error!(?error, address=%address_str, "Cannot resolve target address");
-                self.set_disconnected();
                 return Err(error);
             }
         };
 
-        info!(?address, username = &ssh_options.username[..], "Connecting");
+        info!(address=%address_str, username = &ssh_options.username[..], "Connecting");
         let algos = if ssh_options.allow_insecure_algos.unwrap_or(false) {
             Preferred {
                 kex: Cow::Borrowed(&[
@@ -453,8 +647,19 @@ impl RemoteClient {
             Preferred::default()
         };
 
+        let (keepalive_interval, keepalive_max) = {
+            let config = self.services.config.load();
+            (
+                config.store.ssh.target_keepalive_interval,
+                config.store.ssh.target_keepalive_max,
+            )
+        };
+
         let config = russh::client::Config {
             preferred: algos,
+            window_size: RELAY_WINDOW_SIZE,
+            keepalive_interval,
+            keepalive_max,
             ..Default::default()
         };
         let config = Arc::new(config);
@@ -467,8 +672,13 @@ impl RemoteClient {
             session_id: self.id,
         };
 
-        let fut_connect = russh::client::connect(config, address, handler);
-        pin_mut!(fut_connect);
+        let mut fut_connect: BoxFuture<Result<Handle<ClientHandler>, ClientHandlerError>> =
+            match transport {
+                Transport::Tcp(address) => russh::client::connect(config, address, handler).boxed(),
+                Transport::Ssm(stream) => {
+                    russh::client::connect_stream(config, stream, handler).boxed()
+                }
+            };
 
         loop {
             tokio::select! {
@@ -512,9 +722,28 @@ impl RemoteClient {
                                 debug!(username=&ssh_options.username[..], "Authenticated with password");
                             }
                         }
-                        SSHTargetAuth::PublicKey(_) => {
+                        SSHTargetAuth::Certificate(auth) => {
+                            let ca_key = load_ssh_ca_key(&self.services.config.load())?;
+                            let (key, certificate) = issue_user_certificate(
+                                &ca_key,
+                                &ssh_options.username,
+                                &format!("warpgate-session-{}", self.id),
+                                Duration::from_secs(auth.certificate_validity_seconds),
+                            )
+                            .map_err(|error| {
+                                error!(?error, "Failed to issue a user certificate");
+                                ConnectionError::Internal
+                            })?;
+                            auth_result = session
+                                .authenticate_openssh_cert(ssh_options.username.clone(), Arc::new(key), certificate)
+                                .await?.success();
+                            if auth_result {
+                                debug!(username=&ssh_options.username[..], "Authenticated with certificate");
+                            }
+                        }
+                        SSHTargetAuth::PublicKey(auth) => {
                             #[allow(clippy::explicit_auto_deref)]
-                            let keys = load_all_usable_private_keys(&*self.services.config.lock().await, ssh_options.allow_insecure_algos.unwrap_or(false))?;
+                            let keys = load_all_usable_private_keys(&*self.services.config.load(), ssh_options.allow_insecure_algos.unwrap_or(false), &auth.keys)?;
                             for key in keys.into_iter() {
                                 let key_str = key.public_key().to_openssh().map_err(russh::Error::from)?;
                                 auth_result = session
@@ -539,9 +768,15 @@ impl RemoteClient {
                         return Err(ConnectionError::Authentication);
                     }
 
-                    self.session = Some(Arc::new(Mutex::new(session)));
+                    let session = Arc::new(Mutex::new(session));
+                    self.session = Some(session.clone());
+
+                    info!(address=%address_str, "Connected");
 
-                    info!(?address, "Connected");
+                    if let Some((ticket, linger)) = pool_ticket.take() {
+                        self.shared = Some(ticket.complete(session, event_rx, linger, self.id, self.inner_event_tx.clone()));
+                        return Ok(())
+                    }
 
                     tokio::spawn({
                         let inner_event_tx = self.inner_event_tx.clone();
@@ -568,7 +803,9 @@ impl RemoteClient {
             let (tx, rx) = unbounded_channel();
             self.channel_pipes.lock().await.insert(channel_id, tx);
 
-            let channel = SessionChannel::new(channel, channel_id, rx, self.tx.clone(), self.id);
+            let detached = self.detached_flag(channel_id);
+            let channel =
+                SessionChannel::new(channel, channel_id, rx, self.tx.clone(), self.id, detached);
             self.child_tasks.push(
                 tokio::task::Builder::new()
                     .name(&format!("SSH {} {:?} ops", self.id, channel_id))
@@ -612,8 +849,13 @@ impl RemoteClient {
 
     async fn tcpip_forward(&mut self, address: String, port: u32) -> Result<(), SshClientError> {
         if let Some(session) = &self.session {
+            // The target may open channels as soon as the forward is set up
+            if let Some(ref shared) = self.shared {
+                shared.routes().add_forward(&address, port);
+            }
             let mut session = session.lock().await;
-            session.tcpip_forward(address, port).await?;
+            session.tcpip_forward(address.clone(), port).await?;
+            self.forwards.push((address, port));
         } else {
             self.pending_forwards.push((address, port));
         }
@@ -627,15 +869,24 @@ impl RemoteClient {
     ) -> Result<(), SshClientError> {
         if let Some(session) = &self.session {
             let session = session.lock().await;
-            session.cancel_tcpip_forward(address, port).await?;
+            session.cancel_tcpip_forward(address.clone(), port).await?;
+            if let Some(ref shared) = self.shared {
+                shared.routes().remove_forward(&address, port);
+            }
         } else {
             self.pending_forwards
                 .retain(|x| x.0 != address || x.1 != port);
         }
+        self.forwards.retain(|x| x.0 != address || x.1 != port);
         Ok(())
     }
 
     async fn disconnect(&mut self) {
+        if self.shared.is_some() {
+            self.close_own_channels().await;
+            self.set_disconnected();
+            return;
+        }
         if let Some(session) = &mut self.session {
             let _ = session
                 .lock()
@@ -646,10 +897,217 @@ impl RemoteClient {
         }
     }
 
+    /// A shared connection stays open for other sessions, so only the
+    /// channels and forwards of this session are closed
+    async fn close_own_channels(&mut self) {
+        for (_, tx) in self.channel_pipes.lock().await.drain() {
+            let _ = tx.send(ChannelOperation::Close);
+        }
+        for mut task in self.child_tasks.drain(..) {
+            if tokio::time::timeout(CHANNEL_SHUTDOWN_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                task.abort();
+            }
+        }
+        if let Some(session) = &self.session {
+            let session = session.lock().await;
+            for (address, port) in self.forwards.drain(..) {
+                let _ = session.cancel_tcpip_forward(address, port).await;
+            }
+        }
+    }
+
+    fn release_shared(&mut self) {
+        self.shared = None;
+    }
+
     async fn _on_disconnect(&mut self) -> Result<()> {
+        let reconnect = self.options.as_ref().and_then(|x| x.reconnect.clone());
+        match (reconnect, self.options.clone(), self.resolver.clone()) {
+            (Some(reconnect), Some(options), Some(resolver))
+                if self.state == RCState::Connected =>
+            {
+                self.reconnect(options, resolver, reconnect).await
+            }
+            _ => {
+                self.set_disconnected();
+                Ok(())
+            }
+        }
+    }
+
+    fn detached_flag(&self, channel_id: Uuid) -> Option<Arc<AtomicBool>> {
+        let reconnect = self.options.as_ref().is_some_and(|x| x.reconnect.is_some());
+        self.shells
+            .get(&channel_id)
+            .filter(|_| reconnect)
+            .map(|x| x.detached.clone())
+    }
+
+    async fn reconnect(
+        &mut self,
+        options: TargetSSHOptions,
+        resolver: TargetResolver,
+        reconnect: TargetSshReconnect,
+    ) -> Result<()> {
+        self.release_shared();
+        self.session = None;
+
+        // Channel tasks end once the connection is gone, and only
+        // then it's known which shells have been cut off
+        for mut task in self.child_tasks.drain(..) {
+            if tokio::time::timeout(CHANNEL_SHUTDOWN_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                task.abort();
+            }
+        }
+        self.channel_pipes.lock().await.clear();
+
+        let mut shells = vec![];
+        for (id, shell) in self.shells.drain() {
+            if !shell.detached.load(Ordering::Relaxed) {
+                continue;
+            }
+            if shell.started {
+                shells.push((id, shell));
+            } else {
+                let _ = self.tx.send(RCEvent::Close(id));
+            }
+        }
+
+        if shells.is_empty() {
+            self.set_disconnected();
+            return Ok(());
+        }
+
+        warn!(
+            shells = shells.len(),
+            "Connection to the target lost, reconnecting"
+        );
+        self.set_state(RCState::Connecting)?;
+        self.pending_forwards.append(&mut self.forwards);
+
+        let mut last_error = None;
+        for attempt in 1..=reconnect.attempts {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs(reconnect.interval_seconds)) => (),
+                Some(_) = self.abort_rx.recv() => {
+                    info!("Abort requested");
+                    self.set_disconnected();
+                    return Ok(());
+                }
+            }
+
+            let _ = self.tx.send(RCEvent::Reconnecting {
+                attempt,
+                attempts: reconnect.attempts,
+            });
+            match self.connect(options.clone(), &resolver).await {
+                Ok(()) => {
+                    info!(attempt, "Reconnected");
+                    let reopened = shells.iter().map(|(id, _)| *id).collect();
+                    for (id, shell) in shells {
+                        if let Err(error) = self.reopen_shell(id, shell).await {
+                            warn!(channel=%id, ?error, "Could not reopen the shell");
+                            let _ = self.tx.send(RCEvent::Close(id));
+                        }
+                    }
+                    self.drop_queued_input(&reopened);
+                    self.set_state(RCState::Connected)?;
+                    let ops = self.pending_ops.drain(..).collect::<Vec<_>>();
+                    for (id, op) in ops {
+                        self.apply_channel_op(id, op).await?;
+                    }
+                    let forwards = self.pending_forwards.drain(..).collect::<Vec<_>>();
+                    for (address, port) in forwards {
+                        self.tcpip_forward(address, port).await?;
+                    }
+                    return Ok(());
+                }
+                Err(ConnectionError::Aborted) => return Ok(()),
+                Err(error) => {
+                    warn!(attempt, ?error, "Reconnection failed");
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        if let Some(error) = last_error {
+            let _ = self.tx.send(RCEvent::ConnectionError(error));
+        }
         self.set_disconnected();
         Ok(())
     }
+
+    /// Keystrokes typed while the connection was down were meant for
+    /// the old shell - replaying them into a fresh one could run
+    /// half-typed commands, so they are dropped along with their relay
+    /// permits. Everything else is handled in order once reconnected.
+    fn drop_queued_input(&mut self, channels: &HashSet<Uuid>) {
+        while let Ok(event) = self.inner_event_rx.try_recv() {
+            self.deferred_events.push_back(event);
+        }
+        let dropped = discard_input(&mut self.deferred_events, &mut self.pending_ops, channels);
+        if dropped > 0 {
+            info!(dropped, "Discarded input sent while reconnecting");
+        }
+    }
+
+    /// Opens a new shell under the same channel ID, without replies
+    /// to the replayed requests reaching the client
+    async fn reopen_shell(
+        &mut self,
+        channel_id: Uuid,
+        shell: ShellChannel,
+    ) -> Result<(), SshClientError> {
+        let Some(ref session) = self.session else {
+            return Ok(());
+        };
+        let channel = session.lock().await.channel_open_session().await?;
+        if let Some(ref pty) = shell.pty {
+            channel
+                .request_pty(
+                    false,
+                    &pty.term,
+                    pty.col_width,
+                    pty.row_height,
+                    pty.pix_width,
+                    pty.pix_height,
+                    &pty.modes,
+                )
+                .await?;
+        }
+        for (name, value) in shell.env.iter() {
+            channel.set_env(false, name.clone(), value.clone()).await?;
+        }
+        channel.request_shell(false).await?;
+
+        let (tx, rx) = unbounded_channel();
+        self.channel_pipes.lock().await.insert(channel_id, tx);
+        let detached = shell.detached.clone();
+        detached.store(false, Ordering::Relaxed);
+        self.shells.insert(channel_id, shell);
+
+        let channel = SessionChannel::new(
+            channel,
+            channel_id,
+            rx,
+            self.tx.clone(),
+            self.id,
+            Some(detached),
+        );
+        self.child_tasks.push(
+            tokio::task::Builder::new()
+                .name(&format!("SSH {} {:?} ops", self.id, channel_id))
+                .spawn(channel.run())
+                .map_err(|e| SshClientError::Other(Box::new(e)))?,
+        );
+        Ok(())
+    }
 }
 
 impl Drop for RemoteClient {
@@ -661,3 +1119,79 @@ impl Drop for RemoteClient {
         debug!("Dropped");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RelayWindow;
+
+    fn command(channel: Uuid, op: ChannelOperation) -> InnerEvent {
+        InnerEvent::RCCommand(RCCommand::Channel(channel, op), None)
+    }
+
+    #[tokio::test]
+    async fn test_input_queued_while_reconnecting_is_dropped() {
+        let window = RelayWindow::new(1024);
+        let reopened = Uuid::new_v4();
+        let other = Uuid::new_v4();
+
+        let mut events = VecDeque::from([
+            command(
+                reopened,
+                ChannelOperation::Data(Bytes::from_static(b"rm -"), window.reserve(4).await),
+            ),
+            command(reopened, ChannelOperation::Signal(Sig::INT)),
+            command(
+                reopened,
+                ChannelOperation::ExtendedData {
+                    data: Bytes::from_static(b"rf /"),
+                    ext: 1,
+                    permit: window.reserve(4).await,
+                },
+            ),
+            command(
+                other,
+                ChannelOperation::Data(Bytes::from_static(b"ls"), window.reserve(2).await),
+            ),
+            command(reopened, ChannelOperation::Close),
+        ]);
+        let mut pending_ops = vec![
+            (
+                reopened,
+                ChannelOperation::Data(Bytes::from_static(b"\n"), window.reserve(1).await),
+            ),
+            (reopened, ChannelOperation::Eof),
+        ];
+        assert_eq!(window.available(), 1024 - 11);
+
+        let dropped = discard_input(&mut events, &mut pending_ops, &HashSet::from([reopened]));
+        assert_eq!(dropped, 3);
+
+        let ops = events
+            .iter()
+            .map(|event| match event {
+                InnerEvent::RCCommand(RCCommand::Channel(id, op), _) => (*id, op),
+                event => panic!("unexpected event {event:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert!(matches!(ops[0], (id, ChannelOperation::Signal(_)) if id == reopened));
+        assert!(matches!(ops[1], (id, ChannelOperation::Data(..)) if id == other));
+        assert!(matches!(ops[2], (id, ChannelOperation::Close) if id == reopened));
+        assert_eq!(ops.len(), 3);
+        assert!(matches!(pending_ops[..], [(_, ChannelOperation::Eof)]));
+
+        // Dropped input gives its relay window back
+        assert_eq!(window.available(), 1024 - 2);
+    }
+
+    #[test]
+    fn test_nothing_dropped_without_reopened_shells() {
+        let mut events = VecDeque::from([command(Uuid::new_v4(), ChannelOperation::Eof)]);
+        let mut pending_ops = vec![];
+        assert_eq!(
+            discard_input(&mut events, &mut pending_ops, &HashSet::new()),
+            0
+        );
+        assert_eq!(events.len(), 1);
+    }
+}
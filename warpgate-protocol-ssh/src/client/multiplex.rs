@@ -0,0 +1,617 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, MutexGuard};
+use std::time::Duration;
+
+use russh::client::Handle;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::*;
+use warpgate_common::{SSHTargetAuth, SessionId, TargetSSHOptions};
+
+use super::handler::{ClientHandler, ClientHandlerEvent};
+use super::InnerEvent;
+
+/// Sessions only share a connection if they belong to the same user and
+/// would have connected to the same address with the same credentials.
+/// The address differs between sessions to a target with a templated host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MultiplexKey {
+    username: String,
+    target: String,
+    host: String,
+    port: u16,
+    target_username: String,
+    auth: SSHTargetAuth,
+}
+
+impl MultiplexKey {
+    /// `options` must have the target address resolved already
+    pub fn new(username: String, target: String, options: &TargetSSHOptions) -> Self {
+        MultiplexKey {
+            username,
+            target,
+            host: options.host.clone(),
+            port: options.port,
+            target_username: options.username.clone(),
+            auth: options.auth.clone(),
+        }
+    }
+}
+
+impl Hash for MultiplexKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Credentials are only compared
+        self.username.hash(state);
+        self.target.hash(state);
+        self.host.hash(state);
+        self.port.hash(state);
+        self.target_username.hash(state);
+    }
+}
+
+pub(super) trait SharedSession: Send + 'static {
+    fn close(&mut self) -> impl Future<Output = ()> + Send;
+}
+
+impl SharedSession for Handle<ClientHandler> {
+    async fn close(&mut self) {
+        let _ = self
+            .disconnect(russh::Disconnect::ByApplication, "", "")
+            .await;
+    }
+}
+
+/// Channels that a session has asked the target to open back to it
+#[derive(Default)]
+pub(super) struct Routes {
+    forwards: std::sync::Mutex<Vec<(String, u32)>>,
+    x11: AtomicBool,
+}
+
+impl Routes {
+    fn forwards(&self) -> MutexGuard<'_, Vec<(String, u32)>> {
+        self.forwards.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn add_forward(&self, address: &str, port: u32) {
+        self.forwards().push((address.to_owned(), port));
+    }
+
+    pub fn remove_forward(&self, address: &str, port: u32) {
+        self.forwards().retain(|x| x.0 != address || x.1 != port);
+    }
+
+    pub fn request_x11(&self) {
+        self.x11.store(true, Ordering::Relaxed);
+    }
+
+    /// A forward of port 0 gets a port assigned by the target
+    fn accepts_forward(&self, address: &str, port: u32) -> bool {
+        self.forwards()
+            .iter()
+            .any(|x| x.0 == address && (x.1 == port || x.1 == 0))
+    }
+}
+
+struct Subscriber {
+    session_id: SessionId,
+    tx: UnboundedSender<InnerEvent>,
+    routes: Arc<Routes>,
+}
+
+type Subscribers = Arc<std::sync::Mutex<Vec<Subscriber>>>;
+
+fn lock_subscribers(subscribers: &Subscribers) -> MutexGuard<'_, Vec<Subscriber>> {
+    subscribers.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn route_forward<'a>(
+    subscribers: &'a [Subscriber],
+    address: &str,
+    port: u32,
+) -> Option<&'a Subscriber> {
+    subscribers
+        .iter()
+        .find(|x| x.routes.accepts_forward(address, port))
+}
+
+/// X11 connections aren't tied to the channel that requested them,
+/// so they go to the session that has asked for X11 last
+fn route_x11(subscribers: &[Subscriber]) -> Option<&Subscriber> {
+    subscribers
+        .iter()
+        .rev()
+        .find(|x| x.routes.x11.load(Ordering::Relaxed))
+}
+
+struct SharedConnection<S> {
+    handle: Arc<Mutex<S>>,
+    /// Cleared once the target has disconnected
+    alive: Arc<AtomicBool>,
+    subscribers: Subscribers,
+    linger: Duration,
+    linger_task: Option<JoinHandle<()>>,
+}
+
+enum SlotState<S> {
+    Empty,
+    /// A session is connecting - the receiver resolves once it has
+    /// succeeded or given up
+    Connecting(watch::Receiver<()>),
+    Ready(SharedConnection<S>),
+}
+
+type Slot<S> = Arc<std::sync::Mutex<SlotState<S>>>;
+
+fn lock_slot<S>(slot: &Slot<S>) -> MutexGuard<'_, SlotState<S>> {
+    slot.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(super) static POOL: LazyLock<ConnectionPool<Handle<ClientHandler>>> =
+    LazyLock::new(ConnectionPool::default);
+
+pub(super) struct ConnectionPool<S> {
+    slots: std::sync::Mutex<HashMap<MultiplexKey, Slot<S>>>,
+}
+
+impl<S> Default for ConnectionPool<S> {
+    fn default() -> Self {
+        ConnectionPool {
+            slots: Default::default(),
+        }
+    }
+}
+
+pub(super) enum Checkout<S: SharedSession> {
+    Joined(Lease<S>),
+    /// There is no usable connection. Other sessions wait until the caller
+    /// has connected and handed the connection over with [ConnectTicket::complete],
+    /// or has dropped the ticket.
+    Connect(ConnectTicket<S>),
+}
+
+impl<S: SharedSession> ConnectionPool<S> {
+    pub async fn checkout(
+        &self,
+        key: &MultiplexKey,
+        session_id: SessionId,
+        tx: UnboundedSender<InnerEvent>,
+    ) -> Checkout<S> {
+        let slot = self
+            .slots
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(std::sync::Mutex::new(SlotState::Empty)))
+            .clone();
+
+        loop {
+            let mut pending = {
+                let mut state = lock_slot(&slot);
+                match &mut *state {
+                    SlotState::Ready(connection) if connection.alive.load(Ordering::Relaxed) => {
+                        if let Some(task) = connection.linger_task.take() {
+                            task.abort();
+                        }
+                        let routes = Arc::new(Routes::default());
+                        lock_subscribers(&connection.subscribers).push(Subscriber {
+                            session_id,
+                            tx,
+                            routes: routes.clone(),
+                        });
+                        return Checkout::Joined(Lease {
+                            slot: slot.clone(),
+                            handle: connection.handle.clone(),
+                            routes,
+                            session_id,
+                        });
+                    }
+                    SlotState::Connecting(pending) => pending.clone(),
+                    _ => {
+                        let (done, pending) = watch::channel(());
+                        *state = SlotState::Connecting(pending);
+                        return Checkout::Connect(ConnectTicket {
+                            slot: slot.clone(),
+                            _done: done,
+                        });
+                    }
+                }
+            };
+            // Only fails once the connecting session is done
+            let _ = pending.changed().await;
+        }
+    }
+}
+
+pub(super) struct ConnectTicket<S> {
+    slot: Slot<S>,
+    /// Dropped to wake up waiting sessions
+    _done: watch::Sender<()>,
+}
+
+impl<S: SharedSession> ConnectTicket<S> {
+    /// Puts a freshly established connection into the pool and relays its
+    /// events to the sessions using it. A dropped connection is reported to
+    /// all of them, while channels opened by the target go to the session
+    /// that has requested them.
+    pub fn complete(
+        self,
+        handle: Arc<Mutex<S>>,
+        mut event_rx: UnboundedReceiver<ClientHandlerEvent>,
+        linger: Duration,
+        session_id: SessionId,
+        tx: UnboundedSender<InnerEvent>,
+    ) -> Lease<S> {
+        let routes = Arc::new(Routes::default());
+        let subscribers: Subscribers = Arc::new(std::sync::Mutex::new(vec![Subscriber {
+            session_id,
+            tx,
+            routes: routes.clone(),
+        }]));
+        let alive = Arc::new(AtomicBool::new(true));
+
+        tokio::spawn({
+            let subscribers = subscribers.clone();
+            let alive = alive.clone();
+            async move {
+                while let Some(event) = event_rx.recv().await {
+                    let subscribers = lock_subscribers(&subscribers);
+                    let subscriber = match event {
+                        ClientHandlerEvent::Disconnect => {
+                            alive.store(false, Ordering::Relaxed);
+                            for subscriber in subscribers.iter() {
+                                let _ = subscriber.tx.send(InnerEvent::ClientHandlerEvent(
+                                    ClientHandlerEvent::Disconnect,
+                                ));
+                            }
+                            continue;
+                        }
+                        ClientHandlerEvent::ForwardedTcpIp(_, ref params) => route_forward(
+                            &subscribers,
+                            &params.connected_address,
+                            params.connected_port,
+                        ),
+                        ClientHandlerEvent::X11(..) => route_x11(&subscribers),
+                        _ => None,
+                    };
+                    match subscriber {
+                        Some(subscriber) => {
+                            let _ = subscriber.tx.send(InnerEvent::ClientHandlerEvent(event));
+                        }
+                        None => debug!(?event, "No session for a shared connection event"),
+                    }
+                }
+                alive.store(false, Ordering::Relaxed);
+            }
+            .instrument(Span::current())
+        });
+
+        *lock_slot(&self.slot) = SlotState::Ready(SharedConnection {
+            handle: handle.clone(),
+            alive,
+            subscribers,
+            linger,
+            linger_task: None,
+        });
+        Lease {
+            slot: self.slot.clone(),
+            handle,
+            routes,
+            session_id,
+        }
+    }
+}
+
+impl<S> Drop for ConnectTicket<S> {
+    fn drop(&mut self) {
+        let mut state = lock_slot(&self.slot);
+        if let SlotState::Connecting(_) = *state {
+            *state = SlotState::Empty;
+        }
+    }
+}
+
+/// A session's use of a shared connection. Dropping it closes the
+/// connection after its linger time if no other session is using it.
+pub(super) struct Lease<S: SharedSession> {
+    slot: Slot<S>,
+    handle: Arc<Mutex<S>>,
+    routes: Arc<Routes>,
+    session_id: SessionId,
+}
+
+impl<S: SharedSession> Lease<S> {
+    pub fn handle(&self) -> Arc<Mutex<S>> {
+        self.handle.clone()
+    }
+
+    pub fn routes(&self) -> &Routes {
+        &self.routes
+    }
+}
+
+impl<S: SharedSession> Drop for Lease<S> {
+    fn drop(&mut self) {
+        let mut state = lock_slot(&self.slot);
+        let SlotState::Ready(ref mut connection) = *state else {
+            return;
+        };
+        // The connection might have been replaced after dropping
+        if !Arc::ptr_eq(&connection.handle, &self.handle) {
+            return;
+        }
+        let mut subscribers = lock_subscribers(&connection.subscribers);
+        subscribers.retain(|x| x.session_id != self.session_id);
+        if !subscribers.is_empty() {
+            return;
+        }
+        drop(subscribers);
+
+        debug!(linger=?connection.linger, "Shared connection is idle");
+        let linger = connection.linger;
+        let slot = self.slot.clone();
+        let handle = self.handle.clone();
+        connection.linger_task = Some(tokio::spawn(
+            async move {
+                tokio::time::sleep(linger).await;
+                let is_idle = {
+                    let mut state = lock_slot(&slot);
+                    let is_idle = matches!(
+                        *state,
+                        SlotState::Ready(ref x)
+                            if Arc::ptr_eq(&x.handle, &handle)
+                                && lock_subscribers(&x.subscribers).is_empty()
+                    );
+                    if is_idle {
+                        *state = SlotState::Empty;
+                    }
+                    is_idle
+                };
+                if is_idle {
+                    info!("Closing idle shared connection");
+                    handle.lock().await.close().await;
+                }
+            }
+            .instrument(Span::current()),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::unbounded_channel;
+    use warpgate_common::SshTargetPasswordAuth;
+
+    use super::*;
+
+    struct TestSession {
+        closed: Arc<AtomicBool>,
+    }
+
+    impl SharedSession for TestSession {
+        async fn close(&mut self) {
+            self.closed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn options(host: &str) -> TargetSSHOptions {
+        TargetSSHOptions {
+            host: host.into(),
+            port: 22,
+            username: "root".into(),
+            allow_insecure_algos: None,
+            auth: SSHTargetAuth::Password(SshTargetPasswordAuth {
+                password: "123".to_owned().into(),
+            }),
+            aws_ssm: None,
+            forward_presets: vec![],
+            forwarding_policy: None,
+            allowed_forward_destinations: vec![],
+            role_forwarding_policies: vec![],
+            reconnect: None,
+            multiplexing: None,
+            show_fingerprint: false,
+            block_scp_uploads: false,
+            block_scp_downloads: false,
+        }
+    }
+
+    fn key(host: &str) -> MultiplexKey {
+        MultiplexKey::new("alice".into(), "vm".into(), &options(host))
+    }
+
+    fn session() -> (Arc<Mutex<TestSession>>, Arc<AtomicBool>) {
+        let closed = Arc::new(AtomicBool::new(false));
+        let session = TestSession {
+            closed: closed.clone(),
+        };
+        (Arc::new(Mutex::new(session)), closed)
+    }
+
+    async fn connect(
+        pool: &ConnectionPool<TestSession>,
+        key: &MultiplexKey,
+        linger: Duration,
+    ) -> (
+        Lease<TestSession>,
+        Arc<AtomicBool>,
+        UnboundedSender<ClientHandlerEvent>,
+    ) {
+        let (tx, _) = unbounded_channel();
+        let Checkout::Connect(ticket) = pool.checkout(key, SessionId::new_v4(), tx.clone()).await
+        else {
+            panic!("expected to connect");
+        };
+        let (handle, closed) = session();
+        let (event_tx, event_rx) = unbounded_channel();
+        let lease = ticket.complete(handle, event_rx, linger, SessionId::new_v4(), tx);
+        (lease, closed, event_tx)
+    }
+
+    #[test]
+    fn test_key_includes_resolved_host() {
+        assert_eq!(key("vm-1"), key("vm-1"));
+        assert_ne!(key("vm-1"), key("vm-2"));
+
+        let mut other_password = options("vm-1");
+        other_password.auth = SSHTargetAuth::Password(SshTargetPasswordAuth {
+            password: "456".to_owned().into(),
+        });
+        assert_ne!(
+            key("vm-1"),
+            MultiplexKey::new("alice".into(), "vm".into(), &other_password)
+        );
+        assert_ne!(
+            key("vm-1"),
+            MultiplexKey::new("bob".into(), "vm".into(), &options("vm-1"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reuse_and_separate_hosts() {
+        let pool = ConnectionPool::default();
+        let (lease, _, _event_tx) = connect(&pool, &key("vm-1"), Duration::from_secs(60)).await;
+
+        let (tx, _) = unbounded_channel();
+        let Checkout::Joined(joined) = pool.checkout(&key("vm-1"), SessionId::new_v4(), tx).await
+        else {
+            panic!("expected to reuse the connection");
+        };
+        assert!(Arc::ptr_eq(&joined.handle(), &lease.handle()));
+
+        let (tx, _) = unbounded_channel();
+        assert!(matches!(
+            pool.checkout(&key("vm-2"), SessionId::new_v4(), tx).await,
+            Checkout::Connect(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_connecting_session() {
+        let pool = Arc::new(ConnectionPool::default());
+        let (tx, _) = unbounded_channel();
+        let Checkout::Connect(ticket) = pool.checkout(&key("vm-1"), SessionId::new_v4(), tx).await
+        else {
+            panic!("expected to connect");
+        };
+
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move {
+                let (tx, _) = unbounded_channel();
+                matches!(
+                    pool.checkout(&key("vm-1"), SessionId::new_v4(), tx).await,
+                    Checkout::Joined(_)
+                )
+            }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+
+        let (handle, _) = session();
+        let (_event_tx, event_rx) = unbounded_channel();
+        let (tx, _) = unbounded_channel();
+        let _lease = ticket.complete(
+            handle,
+            event_rx,
+            Duration::from_secs(60),
+            SessionId::new_v4(),
+            tx,
+        );
+        assert!(waiting.await.unwrap_or(false));
+    }
+
+    #[tokio::test]
+    async fn test_failed_connect_lets_others_retry() {
+        let pool = ConnectionPool::<TestSession>::default();
+        let (tx, _) = unbounded_channel();
+        let ticket = pool
+            .checkout(&key("vm-1"), SessionId::new_v4(), tx.clone())
+            .await;
+        drop(ticket);
+        assert!(matches!(
+            pool.checkout(&key("vm-1"), SessionId::new_v4(), tx).await,
+            Checkout::Connect(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dead_connection_is_replaced() {
+        let pool = ConnectionPool::default();
+        let (_lease, _, event_tx) = connect(&pool, &key("vm-1"), Duration::from_secs(60)).await;
+        let _ = event_tx.send(ClientHandlerEvent::Disconnect);
+        tokio::task::yield_now().await;
+
+        let (tx, _) = unbounded_channel();
+        assert!(matches!(
+            pool.checkout(&key("vm-1"), SessionId::new_v4(), tx).await,
+            Checkout::Connect(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_linger() {
+        let pool = ConnectionPool::default();
+        let (lease, closed, _event_tx) =
+            connect(&pool, &key("vm-1"), Duration::from_millis(200)).await;
+        drop(lease);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!closed.load(Ordering::Relaxed));
+        let (tx, _) = unbounded_channel();
+        let Checkout::Joined(lease) = pool.checkout(&key("vm-1"), SessionId::new_v4(), tx).await
+        else {
+            panic!("expected to reuse the connection");
+        };
+
+        // Rejoining cancels the pending close
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(!closed.load(Ordering::Relaxed));
+
+        drop(lease);
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(closed.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_routing() {
+        let subscriber = |forwards: &[(&str, u32)], x11: bool| {
+            let routes = Routes::default();
+            for (address, port) in forwards {
+                routes.add_forward(address, *port);
+            }
+            if x11 {
+                routes.request_x11();
+            }
+            Subscriber {
+                session_id: SessionId::new_v4(),
+                tx: unbounded_channel().0,
+                routes: Arc::new(routes),
+            }
+        };
+        let subscribers = vec![
+            subscriber(&[("127.0.0.1", 8000)], true),
+            subscriber(&[("0.0.0.0", 9000), ("localhost", 0)], true),
+            subscriber(&[], false),
+        ];
+
+        let route =
+            |address, port| route_forward(&subscribers, address, port).map(|x| x.session_id);
+        assert_eq!(route("127.0.0.1", 8000), Some(subscribers[0].session_id));
+        assert_eq!(route("0.0.0.0", 9000), Some(subscribers[1].session_id));
+        assert_eq!(route("localhost", 12345), Some(subscribers[1].session_id));
+        assert_eq!(route("127.0.0.1", 9000), None);
+
+        subscribers[1].routes.remove_forward("0.0.0.0", 9000);
+        assert_eq!(route("0.0.0.0", 9000), None);
+
+        assert_eq!(
+            route_x11(&subscribers).map(|x| x.session_id),
+            Some(subscribers[1].session_id)
+        );
+    }
+}
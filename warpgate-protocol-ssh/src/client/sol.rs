@@ -0,0 +1,193 @@
+use std::io;
+
+use bytes::Bytes;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{SessionId, TargetIpmiOptions};
+use warpgate_protocol_ipmi::SolConsole;
+
+use super::{ConnectionError, RCCommand, RCCommandReply, RCEvent, RCState, RemoteClientHandles};
+use crate::{ChannelOperation, RelayWindow};
+
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// Stands in for [super::RemoteClient] for IPMI Serial-over-LAN targets, so
+/// that a console can be reached through the regular SSH session flow.
+/// Only a single interactive shell channel is supported.
+pub struct SolClient {
+    options: TargetIpmiOptions,
+    tx: UnboundedSender<RCEvent>,
+    channel: Option<Uuid>,
+    size: (u16, u16),
+    console: Option<SolConsole>,
+    closed_tx: UnboundedSender<()>,
+}
+
+impl SolClient {
+    pub fn create(id: SessionId, options: TargetIpmiOptions) -> io::Result<RemoteClientHandles> {
+        let (event_tx, event_rx) = unbounded_channel();
+        let (command_tx, command_rx) = unbounded_channel();
+        let (abort_tx, abort_rx) = unbounded_channel();
+        let (closed_tx, closed_rx) = unbounded_channel();
+
+        let this = Self {
+            options,
+            tx: event_tx,
+            channel: None,
+            size: DEFAULT_SIZE,
+            console: None,
+            closed_tx,
+        };
+
+        let name = format!("SSH {id} SOL client");
+        tokio::task::Builder::new().name(&name).spawn(
+            this.run(command_rx, abort_rx, closed_rx)
+                .instrument(Span::current()),
+        )?;
+
+        Ok(RemoteClientHandles {
+            event_rx,
+            command_tx,
+            abort_tx,
+        })
+    }
+
+    async fn run(
+        mut self,
+        mut command_rx: UnboundedReceiver<(RCCommand, Option<RCCommandReply>)>,
+        mut abort_rx: UnboundedReceiver<()>,
+        mut closed_rx: UnboundedReceiver<()>,
+    ) {
+        loop {
+            tokio::select! {
+                Some((command, reply)) = command_rx.recv() => {
+                    let done = matches!(command, RCCommand::Disconnect);
+                    self.handle_command(command).await;
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Ok(()));
+                    }
+                    if done {
+                        break;
+                    }
+                }
+                Some(_) = closed_rx.recv() => {
+                    info!("Console closed");
+                    if let Some(channel) = self.channel {
+                        let _ = self.tx.send(RCEvent::ExitStatus(channel, 0));
+                        let _ = self.tx.send(RCEvent::Eof(channel));
+                        let _ = self.tx.send(RCEvent::Close(channel));
+                    }
+                    let _ = self.tx.send(RCEvent::State(RCState::Disconnected));
+                    break;
+                }
+                Some(_) = abort_rx.recv() => {
+                    debug!("Abort requested");
+                    break;
+                }
+                else => break,
+            }
+        }
+        if let Some(console) = self.console.take() {
+            console.close().await;
+        }
+    }
+
+    async fn handle_command(&mut self, command: RCCommand) {
+        match command {
+            RCCommand::Channel(channel, op) => {
+                if *self.channel.get_or_insert(channel) != channel {
+                    warn!(%channel, "Only one channel is available on console targets");
+                    let _ = self.tx.send(RCEvent::Close(channel));
+                    return;
+                }
+                self.handle_channel_op(channel, op).await;
+            }
+            RCCommand::Disconnect => {
+                if let Some(console) = self.console.take() {
+                    console.close().await;
+                }
+            }
+            RCCommand::Connect(..)
+            | RCCommand::ForwardTCPIP(..)
+            | RCCommand::CancelTCPIPForward(..) => (),
+        }
+    }
+
+    async fn handle_channel_op(&mut self, channel: Uuid, op: ChannelOperation) {
+        match op {
+            ChannelOperation::OpenShell | ChannelOperation::RequestEnv(..) => (),
+            ChannelOperation::RequestPty(request) | ChannelOperation::ResizePty(request) => {
+                self.size = (
+                    request.col_width.try_into().unwrap_or(u16::MAX),
+                    request.row_height.try_into().unwrap_or(u16::MAX),
+                );
+                if let Some(console) = &self.console {
+                    if let Err(error) = console.resize(self.size.0, self.size.1) {
+                        warn!(?error, "Failed to resize the console");
+                    }
+                }
+            }
+            ChannelOperation::RequestShell if self.console.is_none() => {
+                self.start_console(channel);
+            }
+            ChannelOperation::Data(data, _) => {
+                if let Some(console) = &mut self.console {
+                    if let Err(error) = console.write(&data).await {
+                        error!(?error, "Failed to write to the console");
+                    }
+                }
+            }
+            ChannelOperation::Eof | ChannelOperation::Close => {
+                if let Some(console) = self.console.take() {
+                    console.close().await;
+                }
+            }
+            ChannelOperation::Signal(_) | ChannelOperation::ExtendedData { .. } => (),
+            op => {
+                warn!(?op, "Unsupported operation on a console target");
+                let _ = self.tx.send(RCEvent::ChannelFailure(channel));
+            }
+        }
+    }
+
+    fn start_console(&mut self, channel: Uuid) {
+        let _ = self.tx.send(RCEvent::State(RCState::Connecting));
+
+        let (console, mut output) = match SolConsole::open(&self.options, self.size.0, self.size.1)
+        {
+            Ok(x) => x,
+            Err(error) => {
+                error!(?error, "Failed to start the console");
+                let error = io::Error::other(error.to_string());
+                let _ = self
+                    .tx
+                    .send(RCEvent::ConnectionError(ConnectionError::Io(error)));
+                let _ = self.tx.send(RCEvent::State(RCState::Disconnected));
+                return;
+            }
+        };
+        self.console = Some(console);
+        let _ = self.tx.send(RCEvent::State(RCState::Connected));
+
+        let tx = self.tx.clone();
+        let closed_tx = self.closed_tx.clone();
+        tokio::spawn(
+            async move {
+                let window = RelayWindow::default();
+                let mut buf = vec![0; 4096];
+                // The pty reports EIO rather than EOF once ipmitool exits
+                while let Ok(n @ 1..) = output.read(&mut buf).await {
+                    let permit = window.reserve(n).await;
+                    let data = Bytes::copy_from_slice(&buf[..n]);
+                    if tx.send(RCEvent::Output(channel, data, permit)).is_err() {
+                        break;
+                    }
+                }
+                let _ = closed_tx.send(());
+            }
+            .instrument(Span::current()),
+        );
+    }
+}
@@ -3,6 +3,8 @@ use std::fmt::{Display, Formatter};
 use bytes::Bytes;
 use russh::{ChannelId, Pty, Sig};
 
+use crate::RelayPermit;
+
 #[derive(Clone, Debug)]
 pub struct PtyRequest {
     pub term: String,
@@ -58,8 +60,12 @@ pub enum ChannelOperation {
     RequestExec(String),
     RequestX11(X11Request),
     RequestSubsystem(String),
-    Data(Bytes),
-    ExtendedData { data: Bytes, ext: u32 },
+    Data(Bytes, RelayPermit),
+    ExtendedData {
+        data: Bytes,
+        ext: u32,
+        permit: RelayPermit,
+    },
     Close,
     Eof,
     Signal(Sig),
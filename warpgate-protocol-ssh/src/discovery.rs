@@ -0,0 +1,274 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use ipnet::IpNet;
+use russh::keys::{Algorithm, EcdsaCurve, HashAlg, PublicKey, PublicKeyBase64};
+use russh::Preferred;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::oneshot;
+use tracing::*;
+use warpgate_common::{is_address_template, DiscoveryConfig, TargetOptions};
+use warpgate_core::{ConfigProvider, DiscoveredHost, DiscoveredHostKey, JobHandle, Services};
+
+/// Upper bound on the size of the configured ranges, to keep a typo
+/// like `/8` from turning into a scan of millions of hosts
+const MAX_DISCOVERY_HOSTS: usize = 65536;
+
+const MAX_BANNER_LENGTH: u64 = 4096;
+
+/// How often to check whether discovery has been enabled in the config
+const DISABLED_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// One connection is made per algorithm, since a server only presents
+/// a single host key per handshake
+const HOST_KEY_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::Ed25519,
+    Algorithm::Ecdsa {
+        curve: EcdsaCurve::NistP256,
+    },
+    Algorithm::Rsa {
+        hash: Some(HashAlg::Sha256),
+    },
+];
+
+/// Periodically scans the configured ranges for SSH servers and publishes
+/// the ones that aren't targets yet in [warpgate_core::TargetDiscovery].
+pub async fn run_discovery(services: Services) {
+    let scan_requested = services.discovery.lock().await.scan_requested();
+    loop {
+        let config = services.config.load().store.discovery.clone();
+        let wait = if config.enable {
+            if let Err(error) = scan(&services, &config).await {
+                error!(?error, "Target discovery scan failed");
+            }
+            config.interval
+        } else {
+            DISABLED_RECHECK_INTERVAL
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => (),
+            _ = scan_requested.notified() => (),
+        }
+    }
+}
+
+async fn scan(services: &Services, config: &DiscoveryConfig) -> Result<()> {
+    let job = services
+        .jobs
+        .start("target_discovery", "SSH target discovery scan");
+    let result = scan_with_job(services, config, &job).await;
+    job.finish(&result);
+    result
+}
+
+async fn scan_with_job(
+    services: &Services,
+    config: &DiscoveryConfig,
+    job: &JobHandle,
+) -> Result<()> {
+    let addresses = expand_ranges(&config.ranges)?;
+    let known = existing_target_addresses(services).await?;
+
+    let endpoints = addresses
+        .into_iter()
+        .flat_map(|address| {
+            config
+                .ports
+                .iter()
+                .map(move |port| SocketAddr::new(address, *port))
+        })
+        .filter(|endpoint| !known.contains(endpoint))
+        .collect::<Vec<_>>();
+
+    info!(
+        endpoints = endpoints.len(),
+        "Starting target discovery scan"
+    );
+    services.discovery.lock().await.scan_started();
+
+    let timeout = config.timeout;
+    let total = endpoints.len() as u64;
+    let mut done = 0;
+    let scan = futures::stream::iter(endpoints)
+        .map(|endpoint| async move { Some((endpoint, probe(endpoint, timeout).await?)) })
+        .buffer_unordered(config.concurrency.max(1))
+        .inspect(|_| {
+            done += 1;
+            job.set_progress(done, Some(total));
+        })
+        .filter_map(|x| async { x })
+        .collect::<Vec<_>>();
+    let Some(mut candidates) = job.run_until_cancelled(scan).await else {
+        info!("Target discovery scan cancelled");
+        services.discovery.lock().await.scan_cancelled();
+        return Ok(());
+    };
+    candidates.sort_by_key(|(endpoint, _)| *endpoint);
+
+    info!(found = candidates.len(), "Target discovery scan finished");
+    services
+        .discovery
+        .lock()
+        .await
+        .scan_finished(candidates.into_iter().map(|(_, host)| host).collect());
+    Ok(())
+}
+
+/// Addresses of existing SSH-based targets, so that they aren't offered again
+async fn existing_target_addresses(services: &Services) -> Result<HashSet<SocketAddr>> {
+    let targets = services.config_provider.lock().await.list_targets().await?;
+    let mut result = HashSet::new();
+    for target in targets {
+        let resolver = services.dns.for_target(&target);
+        let options = match target.options {
+            TargetOptions::Ssh(options) => options,
+            TargetOptions::Docker(options) => options.ssh,
+            _ => continue,
+        };
+        if options.aws_ssm.is_some() || is_address_template(&options.host) {
+            continue;
+        }
+        let resolved = resolver.resolve(&options.host, options.port).await;
+        match resolved {
+            Ok(addresses) => result.extend(addresses),
+            Err(error) => debug!(?error, host=%options.host, "Could not resolve target host"),
+        }
+    }
+    Ok(result)
+}
+
+fn expand_ranges(ranges: &[String]) -> Result<Vec<IpAddr>> {
+    let mut addresses = vec![];
+    for range in ranges {
+        let network = match range.parse::<IpNet>() {
+            Ok(network) => network,
+            Err(_) => range
+                .parse::<IpAddr>()
+                .map(IpNet::from)
+                .with_context(|| format!("invalid discovery range: {range}"))?,
+        };
+        addresses.extend(
+            network
+                .hosts()
+                .take(MAX_DISCOVERY_HOSTS + 1 - addresses.len()),
+        );
+        if addresses.len() > MAX_DISCOVERY_HOSTS {
+            bail!("discovery ranges cover more than {MAX_DISCOVERY_HOSTS} hosts");
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+    Ok(addresses)
+}
+
+async fn probe(endpoint: SocketAddr, timeout: Duration) -> Option<DiscoveredHost> {
+    let banner = match tokio::time::timeout(timeout, read_banner(endpoint)).await {
+        Ok(Ok(Some(banner))) => banner,
+        _ => return None,
+    };
+    debug!(%endpoint, %banner, "Found an SSH server");
+
+    let mut host_keys: Vec<DiscoveredHostKey> = vec![];
+    for algorithm in HOST_KEY_ALGORITHMS {
+        if let Some(key) = fetch_host_key(endpoint, algorithm.clone(), timeout).await {
+            let key = DiscoveredHostKey {
+                kind: key.algorithm().to_string(),
+                public_key_base64: key.public_key_base64(),
+            };
+            if !host_keys.iter().any(|x| x.kind == key.kind) {
+                host_keys.push(key);
+            }
+        }
+    }
+
+    Some(DiscoveredHost::new(
+        endpoint.ip().to_string(),
+        endpoint.port(),
+        banner,
+        host_keys,
+    ))
+}
+
+async fn read_banner(endpoint: SocketAddr) -> std::io::Result<Option<String>> {
+    let stream = TcpStream::connect(endpoint).await?;
+    let mut lines = BufReader::new(stream.take(MAX_BANNER_LENGTH)).lines();
+    // Servers are allowed to send other lines before the identification string
+    while let Some(line) = lines.next_line().await? {
+        if line.starts_with("SSH-") {
+            return Ok(Some(line.trim_end().to_owned()));
+        }
+    }
+    Ok(None)
+}
+
+async fn fetch_host_key(
+    endpoint: SocketAddr,
+    algorithm: Algorithm,
+    timeout: Duration,
+) -> Option<PublicKey> {
+    let config = Arc::new(russh::client::Config {
+        preferred: Preferred {
+            key: Cow::Owned(vec![algorithm]),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let (tx, rx) = oneshot::channel();
+
+    // The handshake is always aborted after the key exchange
+    let _ = tokio::time::timeout(timeout, async move {
+        let stream = TcpStream::connect(endpoint).await?;
+        russh::client::connect_stream(config, stream, HostKeyCollector(Some(tx))).await
+    })
+    .await;
+
+    rx.await.ok()
+}
+
+struct HostKeyCollector(Option<oneshot::Sender<PublicKey>>);
+
+impl russh::client::Handler for HostKeyCollector {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKey,
+    ) -> Result<bool, Self::Error> {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(server_public_key.clone());
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_ranges() {
+        let addresses = expand_ranges(&[
+            "10.0.0.0/30".into(),
+            "10.0.0.2".into(),
+            "10.0.1.5/32".into(),
+        ])
+        .unwrap();
+        assert_eq!(
+            addresses,
+            ["10.0.0.1", "10.0.0.2", "10.0.1.5"]
+                .iter()
+                .map(|x| x.parse::<IpAddr>().unwrap())
+                .collect::<Vec<_>>()
+        );
+
+        assert!(expand_ranges(&["10.0.0.0/33".into()]).is_err());
+        assert!(expand_ranges(&["10.0.0.0/8".into()]).is_err());
+    }
+}
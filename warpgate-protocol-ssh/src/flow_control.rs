@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::ServerChannelId;
+
+/// Maximum amount of channel data that can be in flight between the
+/// two legs of a relayed channel. Both the server and the client side
+/// advertise the same SSH window so that a slow reader on one end stalls
+/// the other end through regular SSH flow control instead of piling up
+/// data in Warpgate's memory.
+pub const RELAY_WINDOW_SIZE: u32 = 2 * 1024 * 1024;
+
+/// Byte-based credit window for relaying channel data.
+///
+/// A [RelayPermit] has to be obtained for every chunk before it's handed over
+/// to the other side and is released once the chunk has been written out.
+#[derive(Clone, Debug)]
+pub struct RelayWindow {
+    semaphore: Arc<Semaphore>,
+    size: u32,
+}
+
+impl RelayWindow {
+    pub fn new(size: u32) -> Self {
+        RelayWindow {
+            semaphore: Arc::new(Semaphore::new(size as usize)),
+            size,
+        }
+    }
+
+    /// Waits until `len` bytes fit into the window. Chunks larger than the
+    /// window itself take up the whole window instead of waiting forever.
+    pub async fn reserve(&self, len: usize) -> RelayPermit {
+        #[allow(clippy::expect_used)]
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_many_owned(self.amount(len))
+            .await
+            .expect("relay window semaphore is never closed");
+        RelayPermit(Arc::new(permit))
+    }
+
+    /// Like [RelayWindow::reserve], but returns `None` if the window is full
+    pub fn try_reserve(&self, len: usize) -> Option<RelayPermit> {
+        self.semaphore
+            .clone()
+            .try_acquire_many_owned(self.amount(len))
+            .ok()
+            .map(|permit| RelayPermit(Arc::new(permit)))
+    }
+
+    fn amount(&self, len: usize) -> u32 {
+        len.clamp(1, self.size as usize) as u32
+    }
+
+    pub fn available(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+}
+
+impl Default for RelayWindow {
+    fn default() -> Self {
+        Self::new(RELAY_WINDOW_SIZE)
+    }
+}
+
+/// A [RelayWindow] for every channel of a connection, so that a channel
+/// with a slow target doesn't use up the credit of the other channels.
+#[derive(Clone, Debug)]
+pub struct ChannelWindows<K = ServerChannelId>(Arc<Mutex<HashMap<K, RelayWindow>>>);
+
+impl<K> Default for ChannelWindows<K> {
+    fn default() -> Self {
+        ChannelWindows(Default::default())
+    }
+}
+
+impl<K: Hash + Eq> ChannelWindows<K> {
+    pub fn get(&self, channel: K) -> RelayWindow {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(channel)
+            .or_default()
+            .clone()
+    }
+
+    pub fn remove(&self, channel: K) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&channel);
+    }
+}
+
+/// Credit for a chunk of relayed data. Returned to its [RelayWindow] when
+/// the last clone is dropped.
+#[derive(Clone, Debug)]
+pub struct RelayPermit(#[allow(dead_code)] Arc<OwnedSemaphorePermit>);
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn large_transfer_stays_within_window() {
+        const WINDOW: u32 = 64 * 1024;
+        const CHUNK: usize = 32 * 1024 - 9;
+        const TOTAL: usize = 64 * 1024 * 1024;
+
+        let window = RelayWindow::new(WINDOW);
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = unbounded_channel::<(Vec<u8>, RelayPermit)>();
+
+        let producer = tokio::spawn({
+            let window = window.clone();
+            let in_flight = in_flight.clone();
+            let peak = peak.clone();
+            async move {
+                let mut sent = 0;
+                while sent < TOTAL {
+                    let len = CHUNK.min(TOTAL - sent);
+                    let permit = window.reserve(len).await;
+                    let current = in_flight.fetch_add(len, Ordering::SeqCst) + len;
+                    peak.fetch_max(current, Ordering::SeqCst);
+                    tx.send((vec![0; len], permit)).unwrap();
+                    sent += len;
+                }
+            }
+        });
+
+        let mut received = 0;
+        while let Some((data, permit)) = rx.recv().await {
+            if received % (1024 * 1024) < CHUNK {
+                // Simulate a slow downstream reader
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            received += data.len();
+            in_flight.fetch_sub(data.len(), Ordering::SeqCst);
+            drop(permit);
+        }
+
+        producer.await.unwrap();
+        assert_eq!(received, TOTAL);
+        assert!(peak.load(Ordering::SeqCst) <= WINDOW as usize);
+        assert_eq!(window.available(), WINDOW as usize);
+    }
+
+    #[tokio::test]
+    async fn oversized_chunk_takes_whole_window() {
+        let window = RelayWindow::new(1024);
+        let permit = window.reserve(4096).await;
+        assert_eq!(window.available(), 0);
+        let cloned = permit.clone();
+        drop(permit);
+        assert_eq!(window.available(), 0);
+        drop(cloned);
+        assert_eq!(window.available(), 1024);
+    }
+
+    #[tokio::test]
+    async fn channels_have_separate_windows() {
+        let windows = ChannelWindows::default();
+        let (a, b) = (1, 2);
+
+        let _permit = windows.get(a).reserve(RELAY_WINDOW_SIZE as usize).await;
+        assert!(windows.get(a).try_reserve(1).is_none());
+        assert!(windows.get(b).try_reserve(1024).is_some());
+
+        windows.remove(a);
+        assert!(windows.get(a).try_reserve(1).is_some());
+    }
+}
@@ -1,14 +1,19 @@
-use std::fs::{create_dir_all, File};
+use std::fs::{create_dir_all, read_dir, remove_file, File};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result};
 use russh::keys::key::PrivateKeyWithHashAlg;
-use russh::keys::{encode_pkcs8_pem, load_secret_key, HashAlg, PrivateKey};
+use russh::keys::ssh_key::certificate::{Builder as CertificateBuilder, CertType};
+use russh::keys::ssh_key::{LineEnding, SshSig};
+use russh::keys::{
+    encode_pkcs8_pem, load_secret_key, Certificate, EcdsaCurve, HashAlg, PrivateKey, PublicKey,
+};
 use tracing::*;
 use warpgate_common::helpers::fs::{secure_directory, secure_file};
 use warpgate_common::helpers::rng::get_crypto_rng;
-use warpgate_common::WarpgateConfig;
+use warpgate_common::{SshKeyAlgorithm, WarpgateConfig};
 
 fn get_keys_path(config: &WarpgateConfig) -> PathBuf {
     let mut path = config.paths_relative_to.clone();
@@ -61,57 +66,158 @@ pub fn load_host_keys(config: &WarpgateConfig) -> Result<PrivateKey, russh::keys
     load_secret_key(key_path, None)
 }
 
+/// SSHSIG namespace of the signatures on exported session bundles
+pub const SESSION_BUNDLE_SIGNATURE_NAMESPACE: &str = "warpgate-session-bundle";
+
+/// Signs with the Ed25519 host key, so that the signature can be checked
+/// against the host key clients already know. Returns the public key in
+/// OpenSSH format and the armored signature.
+pub fn sign_with_host_key(
+    config: &WarpgateConfig,
+    namespace: &str,
+    data: &[u8],
+) -> Result<(String, String)> {
+    let key = load_secret_key(get_keys_path(config).join("host-ed25519"), None)?;
+    let signature = SshSig::sign(&key, namespace, HashAlg::Sha512, data)?;
+    Ok((
+        PublicKey::from(&key).to_openssh()?,
+        signature.to_pem(LineEnding::LF)?,
+    ))
+}
+
+const CLIENT_KEY_PREFIX: &str = "client-";
+
+/// A named key that Warpgate presents when authenticating to SSH targets
+pub struct ClientKey {
+    pub name: String,
+    pub key: PrivateKey,
+}
+
+fn generate_key(algorithm: SshKeyAlgorithm) -> Result<PrivateKey> {
+    let name = algorithm.as_str();
+    let algorithm = match algorithm {
+        SshKeyAlgorithm::Ed25519 => russh::keys::Algorithm::Ed25519,
+        SshKeyAlgorithm::Rsa => russh::keys::Algorithm::Rsa {
+            hash: Some(HashAlg::Sha512),
+        },
+        SshKeyAlgorithm::EcdsaP256 => russh::keys::Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP256,
+        },
+        SshKeyAlgorithm::EcdsaP384 => russh::keys::Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP384,
+        },
+        SshKeyAlgorithm::EcdsaP521 => russh::keys::Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP521,
+        },
+    };
+    PrivateKey::random(&mut get_crypto_rng(), algorithm)
+        .with_context(|| format!("Failed to generate {name} key"))
+}
+
+fn get_client_key_path(config: &WarpgateConfig, name: &str) -> Result<PathBuf> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        anyhow::bail!("Invalid client key name: {name:?}");
+    }
+    Ok(get_keys_path(config).join(format!("{CLIENT_KEY_PREFIX}{name}")))
+}
+
 pub fn generate_client_keys(config: &WarpgateConfig) -> Result<()> {
     let path = get_keys_path(config);
     create_dir_all(&path)?;
     secure_directory(&path)?;
 
-    let key_path = path.join("client-ed25519");
-    if !key_path.exists() {
-        info!("Generating Ed25519 client key");
-        let key = PrivateKey::random(&mut get_crypto_rng(), russh::keys::Algorithm::Ed25519)?;
-        let f = File::create(&key_path)?;
-        encode_pkcs8_pem(&key, f)?;
+    for algorithm in &config.store.ssh.client_key_algorithms {
+        let key_path = get_client_key_path(config, algorithm.as_str())?;
+        if !key_path.exists() {
+            info!("Generating {} client key", algorithm.as_str());
+            let key = generate_key(*algorithm)?;
+            let f = File::create(&key_path)?;
+            encode_pkcs8_pem(&key, f)?;
+        }
+        secure_file(&key_path)?;
     }
-    secure_file(&key_path)?;
 
-    let key_path = path.join("client-rsa");
-    if !key_path.exists() {
-        info!("Generating RSA client key (this can take a bit)");
-        let key = PrivateKey::random(
-            &mut get_crypto_rng(),
-            russh::keys::Algorithm::Rsa {
-                hash: Some(HashAlg::Sha512),
-            },
-        )
-        .context("Failed to generate RSA key")?;
-        let f = File::create(&key_path)?;
-        encode_pkcs8_pem(&key, f)?;
+    Ok(())
+}
+
+/// Generates an additional named client key.
+pub fn generate_client_key(
+    config: &WarpgateConfig,
+    name: &str,
+    algorithm: SshKeyAlgorithm,
+) -> Result<ClientKey> {
+    let key_path = get_client_key_path(config, name)?;
+    if key_path.exists() {
+        anyhow::bail!("Client key {name} already exists");
     }
+
+    info!(%name, "Generating {} client key", algorithm.as_str());
+    let key = generate_key(algorithm)?;
+    let f = File::create(&key_path)?;
+    encode_pkcs8_pem(&key, f)?;
     secure_file(&key_path)?;
 
+    Ok(ClientKey {
+        name: name.to_owned(),
+        key,
+    })
+}
+
+pub fn delete_client_key(config: &WarpgateConfig, name: &str) -> Result<()> {
+    let key_path = get_client_key_path(config, name)?;
+    remove_file(&key_path).with_context(|| format!("Failed to delete client key {name}"))?;
     Ok(())
 }
 
-pub fn load_client_keys(config: &WarpgateConfig) -> Result<Vec<PrivateKey>, russh::keys::Error> {
+pub fn load_client_keys(config: &WarpgateConfig) -> Result<Vec<ClientKey>, russh::keys::Error> {
     let path = get_keys_path(config);
-    let mut keys = Vec::new();
-
-    let key_path: PathBuf = path.join("client-ed25519");
-    keys.push(load_secret_key(key_path, None)?);
+    let mut names = vec![];
+    for entry in read_dir(&path)? {
+        let file_name = entry?.file_name();
+        let Some(name) = file_name
+            .to_str()
+            .and_then(|x| x.strip_prefix(CLIENT_KEY_PREFIX))
+        else {
+            continue;
+        };
+        if !name.is_empty() && !name.contains('.') {
+            names.push(name.to_owned());
+        }
+    }
+    names.sort();
 
-    let key_path = path.join("client-rsa");
-    keys.push(load_secret_key(key_path, None)?);
+    let mut keys = Vec::new();
+    for name in names {
+        let key = load_secret_key(path.join(format!("{CLIENT_KEY_PREFIX}{name}")), None)?;
+        keys.push(ClientKey { name, key });
+    }
 
     Ok(keys)
 }
 
+/// Loads the client keys to present to a target, optionally limited to
+/// the named subset of keys.
 pub fn load_all_usable_private_keys(
     config: &WarpgateConfig,
     allow_insecure_algos: bool,
+    only_names: &[String],
 ) -> Result<Vec<PrivateKeyWithHashAlg>, russh::keys::Error> {
+    let client_keys = load_client_keys(config)?;
+    for name in only_names {
+        if !client_keys.iter().any(|x| &x.name == name) {
+            warn!(%name, "Target refers to a client key that does not exist");
+        }
+    }
+
     let mut keys = vec![];
-    for key in load_client_keys(config)? {
+    for ClientKey { name, key } in client_keys {
+        if !only_names.is_empty() && !only_names.contains(&name) {
+            continue;
+        }
         let key = Arc::new(key);
         if key.key_data().is_rsa() {
             for hash in &[Some(HashAlg::Sha512), Some(HashAlg::Sha256)] {
@@ -126,3 +232,89 @@ pub fn load_all_usable_private_keys(
     }
     Ok(keys)
 }
+
+const SSH_CA_KEY_NAME: &str = "user-ca";
+
+/// Certificates are backdated a little to tolerate clock skew on targets
+const CERTIFICATE_BACKDATE: Duration = Duration::from_secs(60);
+
+fn write_ssh_ca_key(config: &WarpgateConfig) -> Result<PrivateKey> {
+    let key_path = get_keys_path(config).join(SSH_CA_KEY_NAME);
+    let key = generate_key(SshKeyAlgorithm::Ed25519)?;
+    let f = File::create(&key_path)?;
+    encode_pkcs8_pem(&key, f)?;
+    secure_file(&key_path)?;
+    Ok(key)
+}
+
+/// Generates the CA key that signs user certificates for targets
+/// using certificate authentication, unless it already exists
+pub fn generate_ssh_ca_key(config: &WarpgateConfig) -> Result<()> {
+    let path = get_keys_path(config);
+    create_dir_all(&path)?;
+    secure_directory(&path)?;
+
+    if !path.join(SSH_CA_KEY_NAME).exists() {
+        info!("Generating SSH user CA key");
+        write_ssh_ca_key(config)?;
+    }
+    Ok(())
+}
+
+pub fn load_ssh_ca_key(config: &WarpgateConfig) -> Result<PrivateKey, russh::keys::Error> {
+    load_secret_key(get_keys_path(config).join(SSH_CA_KEY_NAME), None)
+}
+
+/// Replaces the CA key. Certificates signed by the old key stay valid
+/// until they expire, but targets have to trust the new key for new ones.
+pub fn rotate_ssh_ca_key(config: &WarpgateConfig) -> Result<PrivateKey> {
+    let key = write_ssh_ca_key(config)?;
+    warn!("Rotated the SSH user CA key");
+    Ok(key)
+}
+
+/// Generates a throwaway key pair and a user certificate for it, valid for
+/// `principal` on the target for the given time
+pub fn issue_user_certificate(
+    ca_key: &PrivateKey,
+    principal: &str,
+    key_id: &str,
+    validity: Duration,
+) -> Result<(PrivateKey, Certificate)> {
+    let key = generate_key(SshKeyAlgorithm::Ed25519)?;
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)?;
+    let mut builder = CertificateBuilder::new_with_random_nonce(
+        &mut get_crypto_rng(),
+        key.public_key().key_data().clone(),
+        (now - CERTIFICATE_BACKDATE).as_secs(),
+        (now + validity).as_secs(),
+    )?;
+    builder
+        .cert_type(CertType::User)?
+        .key_id(key_id)?
+        .valid_principal(principal)?
+        .extension("permit-pty", "")?
+        .extension("permit-port-forwarding", "")?
+        .extension("permit-agent-forwarding", "")?
+        .extension("permit-X11-forwarding", "")?;
+    let certificate = builder.sign(ca_key)?;
+    Ok((key, certificate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_user_certificate() {
+        let ca_key = generate_key(SshKeyAlgorithm::Ed25519).unwrap();
+        let (key, certificate) =
+            issue_user_certificate(&ca_key, "root", "session", Duration::from_secs(300)).unwrap();
+
+        assert_eq!(certificate.public_key(), key.public_key().key_data());
+        assert_eq!(certificate.valid_principals(), ["root".to_owned()]);
+        certificate
+            .validate([&ca_key.public_key().fingerprint(HashAlg::Sha256)])
+            .unwrap();
+    }
+}
@@ -1,11 +1,24 @@
 use std::sync::Arc;
 
+use data_encoding::BASE64;
+use hmac::{Hmac, Mac};
 use russh::keys::{PublicKey, PublicKeyBase64};
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use sha1::Sha1;
 use tokio::sync::Mutex;
 use uuid::Uuid;
+use warpgate_core::JobHandle;
 use warpgate_db_entities::KnownHost;
 
+/// Prefix of hashed host names as written by `HashKnownHosts yes`
+const HASHED_HOST_PREFIX: &str = "|1|";
+
+/// Hashed entries already include the port in the hashed name,
+/// so their `port` column is left at zero.
+const HASHED_HOST_PORT: u16 = 0;
+
+const DEFAULT_SSH_PORT: u16 = 22;
+
 pub struct KnownHosts {
     db: Arc<Mutex<DatabaseConnection>>,
 }
@@ -19,6 +32,23 @@ pub enum KnownHostValidationResult {
     Unknown,
 }
 
+/// A single host key parsed from an OpenSSH `known_hosts` file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnownHostEntry {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    pub key_base64: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct KnownHostsImportResult {
+    pub imported: usize,
+    pub duplicates: usize,
+    /// Lines or host patterns that could not be imported, with a reason
+    pub skipped: Vec<String>,
+}
+
 impl KnownHosts {
     pub fn new(db: &Arc<Mutex<DatabaseConnection>>) -> Self {
         Self { db: db.clone() }
@@ -31,13 +61,24 @@ impl KnownHosts {
         key: &PublicKey,
     ) -> Result<KnownHostValidationResult, sea_orm::DbErr> {
         let db = self.db.lock().await;
-        let entries = KnownHost::Entity::find()
+        let mut entries = KnownHost::Entity::find()
             .filter(KnownHost::Column::Host.eq(host))
             .filter(KnownHost::Column::Port.eq(port))
             .filter(KnownHost::Column::KeyType.eq(key.algorithm().as_str()))
             .all(&*db)
             .await?;
 
+        let hostname = format_host_name(host, port);
+        entries.extend(
+            KnownHost::Entity::find()
+                .filter(KnownHost::Column::Host.starts_with(HASHED_HOST_PREFIX))
+                .filter(KnownHost::Column::KeyType.eq(key.algorithm().as_str()))
+                .all(&*db)
+                .await?
+                .into_iter()
+                .filter(|x| hashed_host_matches(&x.host, &hostname)),
+        );
+
         let key_base64 = key.public_key_base64();
         if entries.iter().any(|x| x.key_base64 == key_base64) {
             return Ok(KnownHostValidationResult::Valid);
@@ -72,4 +113,245 @@ impl KnownHosts {
 
         Ok(())
     }
+
+    /// Imports the contents of an OpenSSH `known_hosts` file.
+    /// Entries that are already known are not duplicated. If the job
+    /// is cancelled, the entries imported so far are kept.
+    pub async fn import(
+        &mut self,
+        text: &str,
+        job: &JobHandle,
+    ) -> Result<KnownHostsImportResult, sea_orm::DbErr> {
+        use sea_orm::ActiveValue::Set;
+
+        let (entries, skipped) = parse_known_hosts(text);
+        let mut result = KnownHostsImportResult {
+            skipped,
+            ..Default::default()
+        };
+
+        let total = entries.len() as u64;
+        let db = self.db.lock().await;
+        for (index, entry) in entries.into_iter().enumerate() {
+            if job.is_cancelled() {
+                break;
+            }
+            job.set_progress(index as u64, Some(total));
+            let existing = KnownHost::Entity::find()
+                .filter(KnownHost::Column::Host.eq(&entry.host))
+                .filter(KnownHost::Column::Port.eq(entry.port))
+                .filter(KnownHost::Column::KeyType.eq(&entry.key_type))
+                .filter(KnownHost::Column::KeyBase64.eq(&entry.key_base64))
+                .one(&*db)
+                .await?;
+            if existing.is_some() {
+                result.duplicates += 1;
+                continue;
+            }
+
+            KnownHost::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                host: Set(entry.host),
+                port: Set(entry.port.into()),
+                key_type: Set(entry.key_type),
+                key_base64: Set(entry.key_base64),
+            }
+            .insert(&*db)
+            .await?;
+            result.imported += 1;
+        }
+
+        Ok(result)
+    }
+
+    /// Renders all known hosts in the OpenSSH `known_hosts` format.
+    pub async fn export(&self) -> Result<String, sea_orm::DbErr> {
+        let db = self.db.lock().await;
+        let hosts = KnownHost::Entity::find().all(&*db).await?;
+        Ok(format_known_hosts(&hosts))
+    }
+}
+
+/// Parses an OpenSSH `known_hosts` file. Returns the importable entries
+/// along with descriptions of everything that had to be skipped.
+pub fn parse_known_hosts(text: &str) -> (Vec<KnownHostEntry>, Vec<String>) {
+    let mut entries = vec![];
+    let mut skipped = vec![];
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let (Some(hosts), Some(key_type), Some(key_base64)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            skipped.push(format!("line {line_number}: malformed entry"));
+            continue;
+        };
+
+        if hosts.starts_with('@') {
+            skipped.push(format!(
+                "line {line_number}: {hosts} entries are not supported"
+            ));
+            continue;
+        }
+
+        let key = match russh::keys::parse_public_key_base64(key_base64) {
+            Ok(key) => key,
+            Err(error) => {
+                skipped.push(format!("line {line_number}: invalid key: {error}"));
+                continue;
+            }
+        };
+        if key.algorithm().as_str() != key_type {
+            skipped.push(format!(
+                "line {line_number}: key type {key_type} does not match the key"
+            ));
+            continue;
+        }
+
+        for pattern in hosts.split(',') {
+            match parse_host_pattern(pattern) {
+                Some((host, port)) => entries.push(KnownHostEntry {
+                    host,
+                    port,
+                    key_type: key.algorithm().to_string(),
+                    key_base64: key.public_key_base64(),
+                }),
+                None => skipped.push(format!(
+                    "line {line_number}: unsupported host pattern {pattern}"
+                )),
+            }
+        }
+    }
+
+    (entries, skipped)
+}
+
+/// Renders known host entries in the OpenSSH `known_hosts` format.
+pub fn format_known_hosts(hosts: &[KnownHost::Model]) -> String {
+    let mut result = String::new();
+    for host in hosts {
+        let name = if host.host.starts_with(HASHED_HOST_PREFIX) {
+            host.host.clone()
+        } else {
+            format_host_name(&host.host, host.port.try_into().unwrap_or(DEFAULT_SSH_PORT))
+        };
+        result.push_str(&format!("{name} {} {}\n", host.key_type, host.key_base64));
+    }
+    result
+}
+
+fn parse_host_pattern(pattern: &str) -> Option<(String, u16)> {
+    if let Some(hashed) = pattern.strip_prefix(HASHED_HOST_PREFIX) {
+        let mut parts = hashed.split('|');
+        let (Some(salt), Some(hash), None) = (parts.next(), parts.next(), parts.next()) else {
+            return None;
+        };
+        if BASE64.decode(salt.as_bytes()).is_err() || BASE64.decode(hash.as_bytes()).is_err() {
+            return None;
+        }
+        return Some((pattern.to_owned(), HASHED_HOST_PORT));
+    }
+
+    if pattern.is_empty() || pattern.contains(['*', '?', '!']) {
+        return None;
+    }
+
+    if let Some(rest) = pattern.strip_prefix('[') {
+        let (host, port) = rest.split_once("]:")?;
+        return Some((host.to_owned(), port.parse().ok()?));
+    }
+
+    Some((pattern.to_owned(), DEFAULT_SSH_PORT))
+}
+
+fn format_host_name(host: &str, port: u16) -> String {
+    if port == DEFAULT_SSH_PORT {
+        host.to_owned()
+    } else {
+        format!("[{host}]:{port}")
+    }
+}
+
+fn hashed_host_matches(hashed: &str, hostname: &str) -> bool {
+    let Some(rest) = hashed.strip_prefix(HASHED_HOST_PREFIX) else {
+        return false;
+    };
+    let Some((salt, hash)) = rest.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (
+        BASE64.decode(salt.as_bytes()),
+        BASE64.decode(hash.as_bytes()),
+    ) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(hostname.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIFlwKF/Wn6rTWktbDjbUw5bJfb1kyCEcF8Gsn/Cjlkyx";
+    const HASHED: &str = "|1|AAECAwQFBgcICQoLDA0ODxAREhM=|Wgcx+Fm+LmaWwC7rQ80eIf2uHe0=";
+
+    #[test]
+    fn parses_plain_and_hashed_entries() {
+        let text = format!(
+            "# comment\n\
+             example.com,[10.0.0.1]:2222 ssh-ed25519 {KEY} user@host\n\
+             {HASHED} ssh-ed25519 {KEY}\n\
+             *.example.org ssh-ed25519 {KEY}\n\
+             @revoked bad.example.com ssh-ed25519 {KEY}\n\
+             broken ssh-rsa {KEY}\n"
+        );
+        let (entries, skipped) = parse_known_hosts(&text);
+
+        assert_eq!(
+            entries
+                .iter()
+                .map(|x| (x.host.as_str(), x.port))
+                .collect::<Vec<_>>(),
+            vec![
+                ("example.com", 22),
+                ("10.0.0.1", 2222),
+                (HASHED, HASHED_HOST_PORT)
+            ]
+        );
+        assert!(entries.iter().all(|x| x.key_type == "ssh-ed25519"));
+        assert_eq!(skipped.len(), 3);
+    }
+
+    #[test]
+    fn matches_hashed_host_names() {
+        assert!(hashed_host_matches(HASHED, "[example.com]:2222"));
+        assert!(!hashed_host_matches(HASHED, "example.com"));
+    }
+
+    #[test]
+    fn formats_entries() {
+        let model = |host: &str, port| KnownHost::Model {
+            id: Uuid::new_v4(),
+            host: host.into(),
+            port,
+            key_type: "ssh-ed25519".into(),
+            key_base64: KEY.into(),
+        };
+        assert_eq!(
+            format_known_hosts(&[model("a", 22), model("b", 2222), model(HASHED, 0)]),
+            format!(
+                "a ssh-ed25519 {KEY}\n[b]:2222 ssh-ed25519 {KEY}\n{HASHED} ssh-ed25519 {KEY}\n"
+            )
+        );
+    }
 }
@@ -1,15 +1,23 @@
 mod client;
 mod common;
 mod compat;
+mod discovery;
+mod flow_control;
 mod keys;
 mod known_hosts;
+mod scp;
 mod server;
+mod sftp;
 use std::fmt::Debug;
+use std::io::IsTerminal;
 
 use anyhow::Result;
 pub use client::*;
 pub use common::*;
+pub use discovery::run_discovery;
+pub use flow_control::*;
 pub use keys::*;
+pub use known_hosts::*;
 pub use server::run_server;
 use uuid::Uuid;
 use warpgate_common::{
@@ -26,9 +34,10 @@ pub struct SSHProtocolServer {
 
 impl SSHProtocolServer {
     pub async fn new(services: &Services) -> Result<Self> {
-        let config = services.config.lock().await;
+        let config = services.config.load();
         generate_host_keys(&config)?;
         generate_client_keys(&config)?;
+        generate_ssh_ca_key(&config)?;
         Ok(SSHProtocolServer {
             services: services.clone(),
         })
@@ -41,22 +50,34 @@ impl ProtocolServer for SSHProtocolServer {
     }
 
     async fn test_target(&self, target: Target) -> Result<(), TargetTestError> {
-        let TargetOptions::Ssh(ssh_options) = target.options else {
-            return Err(TargetTestError::Misconfigured(
-                "Not an SSH target".to_owned(),
-            ));
+        let resolver = self.services.dns.for_target(&target);
+        let ssh_options = match target.options {
+            TargetOptions::Ssh(options) => options,
+            TargetOptions::Docker(options) => options.ssh,
+            TargetOptions::Ipmi(options) => {
+                return warpgate_protocol_ipmi::test_connection(&options).await
+            }
+            _ => {
+                return Err(TargetTestError::Misconfigured(
+                    "Not an SSH target".to_owned(),
+                ))
+            }
         };
 
         let mut handles = RemoteClient::create(Uuid::new_v4(), self.services.clone())?;
 
-        let _ = handles
-            .command_tx
-            .send((RCCommand::Connect(ssh_options), None));
+        let _ = handles.command_tx.send((
+            RCCommand::Connect(ssh_options.clone(), resolver, None),
+            None,
+        ));
+
+        let mut rejected_host_key = false;
+        let mut host_key_algorithm = None;
 
         while let Some(event) = handles.event_rx.recv().await {
             match event {
                 RCEvent::HostKeyUnknown(key, reply) => {
-                    println!(
+                    eprintln!(
                         "\nHost key ({}): {}",
                         key.algorithm(),
                         key.to_openssh()
@@ -64,36 +85,25 @@ impl ProtocolServer for SSHProtocolServer {
                                 "ssh_key: {e:?}"
                             )))?
                     );
-                    println!("There is no trusted {} key for this host.", key.algorithm());
+                    eprintln!("There is no trusted {} key for this host.", key.algorithm());
 
-                    match self
-                        .services
-                        .config
-                        .lock()
-                        .await
-                        .store
-                        .ssh
-                        .host_key_verification
-                    {
-                        SshHostKeyVerificationMode::AutoAccept => {
-                            let _ = reply.send(true);
-                        }
-                        SshHostKeyVerificationMode::AutoReject => {
-                            let _ = reply.send(false);
-                        }
-                        SshHostKeyVerificationMode::Prompt => {
-                            if dialoguer::Confirm::new()
-                                .with_prompt("Trust this key?")
-                                .interact()?
-                            {
-                                let _ = reply.send(true);
-                            } else {
-                                let _ = reply.send(false);
-                            }
+                    let accept = match self.services.config.load().store.ssh.host_key_verification {
+                        SshHostKeyVerificationMode::AutoAccept => true,
+                        SshHostKeyVerificationMode::AutoReject => false,
+                        // Nobody to ask, e.g. when running from CI
+                        SshHostKeyVerificationMode::Prompt if !std::io::stdin().is_terminal() => {
+                            false
                         }
-                    }
+                        SshHostKeyVerificationMode::Prompt => dialoguer::Confirm::new()
+                            .with_prompt("Trust this key?")
+                            .interact()?,
+                    };
+                    rejected_host_key = !accept;
+                    let _ = reply.send(accept);
+                }
+                RCEvent::HostKeyReceived(key) => {
+                    host_key_algorithm = Some(key.algorithm().to_string());
                 }
-                RCEvent::HostKeyReceived(_) => (),
                 RCEvent::ConnectionError(err) => {
                     if let ConnectionError::HostKeyMismatch {
                         ref received_key_type,
@@ -102,19 +112,35 @@ impl ProtocolServer for SSHProtocolServer {
                         ref known_key_base64,
                     } = err
                     {
-                        println!("\n");
-                        println!("Stored key   ({known_key_type}): {known_key_base64}");
-                        println!("Received key ({received_key_type}): {received_key_base64}");
-                        println!("Host key doesn't match the stored one.");
-                        println!("If you know that the key is correct (e.g. it has been changed),");
-                        println!("you can remove the old key in the Warpgate management UI and try again");
+                        eprintln!("\n");
+                        eprintln!("Stored key   ({known_key_type}): {known_key_base64}");
+                        eprintln!("Received key ({received_key_type}): {received_key_base64}");
+                        eprintln!("Host key doesn't match the stored one.");
+                        eprintln!(
+                            "If you know that the key is correct (e.g. it has been changed),"
+                        );
+                        eprintln!("you can remove the old key in the Warpgate management UI and try again");
+                        return Err(TargetTestError::Untrusted("host key mismatch".to_owned()));
+                    }
+                    if rejected_host_key {
+                        return Err(TargetTestError::Untrusted("unknown host key".to_owned()));
+                    }
+                    if let ConnectionError::Authentication = err {
+                        return Err(TargetTestError::AuthenticationError);
                     }
                     return Err(TargetTestError::ConnectionError(format!("{err:?}")));
                 }
                 RCEvent::State(state) => match state {
                     RCState::Connected => {
+                        self.services.target_capabilities.record(
+                            &target.name,
+                            negotiated_capabilities(&ssh_options, host_key_algorithm),
+                        );
                         return Ok(());
                     }
+                    RCState::Disconnected if rejected_host_key => {
+                        return Err(TargetTestError::Untrusted("unknown host key".to_owned()));
+                    }
                     RCState::Disconnected => {
                         return Err(TargetTestError::ConnectionError(
                             "Connection failed".to_owned(),
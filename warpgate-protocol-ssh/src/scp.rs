@@ -0,0 +1,247 @@
+use std::fmt::Display;
+
+use bytes::BytesMut;
+use tracing::*;
+
+/// Control lines are short - anything longer means the channel
+/// isn't actually speaking the SCP protocol
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScpDirection {
+    /// `scp -t`, the target receives files
+    Upload,
+    /// `scp -f`, the target sends files
+    Download,
+}
+
+impl Display for ScpDirection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Upload => write!(f, "upload"),
+            Self::Download => write!(f, "download"),
+        }
+    }
+}
+
+/// The remote side of a legacy (non-SFTP) `scp` transfer,
+/// as executed by the client on the target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScpCommand {
+    pub direction: ScpDirection,
+    pub path: String,
+}
+
+impl ScpCommand {
+    pub fn parse(command: &str) -> Option<Self> {
+        let mut args = command.split_whitespace();
+        if args.next()?.rsplit('/').next()? != "scp" {
+            return None;
+        }
+        let mut direction = None;
+        let mut path = vec![];
+        let mut options_done = false;
+        for arg in args {
+            if options_done || !arg.starts_with('-') {
+                path.push(arg);
+            } else if arg == "--" {
+                options_done = true;
+            } else {
+                for flag in arg[1..].chars() {
+                    match flag {
+                        't' => direction = Some(ScpDirection::Upload),
+                        'f' => direction = Some(ScpDirection::Download),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        Some(Self {
+            direction: direction?,
+            path: path.join(" "),
+        })
+    }
+}
+
+/// A file copied during an SCP transfer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScpFile {
+    /// Relative to the path in the command if the transfer is recursive
+    pub name: String,
+    pub size: u64,
+}
+
+/// Follows the stream sent by the source side of an SCP transfer - the client
+/// for uploads, the target for downloads - and picks out the copied files
+pub struct ScpInspector {
+    pub command: ScpCommand,
+    line: BytesMut,
+    directories: Vec<String>,
+    /// File content and the trailing status byte still to be skipped
+    remaining: u64,
+    broken: bool,
+}
+
+impl ScpInspector {
+    pub fn new(command: ScpCommand) -> Self {
+        Self {
+            command,
+            line: BytesMut::new(),
+            directories: vec![],
+            remaining: 0,
+            broken: false,
+        }
+    }
+
+    pub fn client_data(&mut self, data: &[u8]) -> Vec<ScpFile> {
+        match self.command.direction {
+            ScpDirection::Upload => self.source_data(data),
+            ScpDirection::Download => vec![],
+        }
+    }
+
+    pub fn server_data(&mut self, data: &[u8]) -> Vec<ScpFile> {
+        match self.command.direction {
+            ScpDirection::Download => self.source_data(data),
+            ScpDirection::Upload => vec![],
+        }
+    }
+
+    fn source_data(&mut self, mut data: &[u8]) -> Vec<ScpFile> {
+        let mut files = vec![];
+        while !data.is_empty() && !self.broken {
+            if self.remaining > 0 {
+                let skipped = self.remaining.min(data.len() as u64);
+                data = &data[skipped as usize..];
+                self.remaining -= skipped;
+                continue;
+            }
+            let Some(end) = data.iter().position(|x| *x == b'\n') else {
+                self.line.extend_from_slice(data);
+                if self.line.len() > MAX_LINE_LENGTH {
+                    self.stop_inspecting();
+                }
+                break;
+            };
+            self.line.extend_from_slice(&data[..end]);
+            data = &data[end + 1..];
+            let line = self.line.split();
+            if let Some(file) = self.handle_line(&String::from_utf8_lossy(&line)) {
+                files.push(file);
+            }
+        }
+        files
+    }
+
+    fn handle_line(&mut self, line: &str) -> Option<ScpFile> {
+        let mut chars = line.chars();
+        match chars.next() {
+            Some('C') => {
+                let mut parts = chars.as_str().splitn(3, ' ');
+                let (Some(_mode), Some(size), Some(name)) =
+                    (parts.next(), parts.next(), parts.next())
+                else {
+                    self.stop_inspecting();
+                    return None;
+                };
+                let Ok(size) = size.parse::<u64>() else {
+                    self.stop_inspecting();
+                    return None;
+                };
+                self.remaining = size + 1;
+                Some(ScpFile {
+                    name: self
+                        .directories
+                        .iter()
+                        .map(String::as_str)
+                        .chain([name])
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    size,
+                })
+            }
+            Some('D') => {
+                if let Some(name) = chars.as_str().splitn(3, ' ').nth(2) {
+                    self.directories.push(name.to_owned());
+                }
+                None
+            }
+            Some('E') => {
+                self.directories.pop();
+                None
+            }
+            // Timestamps, and warnings or errors from the source
+            Some('T' | '\x01' | '\x02') => None,
+            _ => {
+                self.stop_inspecting();
+                None
+            }
+        }
+    }
+
+    fn stop_inspecting(&mut self) {
+        warn!("Not an SCP stream, no longer inspecting it");
+        self.broken = true;
+        self.line.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            ScpCommand::parse("scp -v -r -d -t -- /tmp/my dir"),
+            Some(ScpCommand {
+                direction: ScpDirection::Upload,
+                path: "/tmp/my dir".into(),
+            })
+        );
+        assert_eq!(
+            ScpCommand::parse("/usr/bin/scp -pf /etc/hosts"),
+            Some(ScpCommand {
+                direction: ScpDirection::Download,
+                path: "/etc/hosts".into(),
+            })
+        );
+        assert_eq!(ScpCommand::parse("scp a b"), None);
+        assert_eq!(ScpCommand::parse("ls -t /tmp"), None);
+    }
+
+    #[test]
+    fn test_recursive_upload() {
+        let mut inspector = ScpInspector::new(ScpCommand::parse("scp -r -t /tmp").unwrap());
+        let stream = b"D0755 0 logs\nT1 0 1 0\nC0644 5 a.log\nhello\0C0600 3 b b.log\nC\nD\0E\nC0644 0 c\n\0";
+        assert!(inspector.server_data(stream).is_empty());
+
+        let (first, rest) = stream.split_at(20);
+        let mut files = inspector.client_data(first);
+        files.extend(inspector.client_data(rest));
+        assert_eq!(
+            files,
+            vec![
+                ScpFile {
+                    name: "logs/a.log".into(),
+                    size: 5,
+                },
+                ScpFile {
+                    name: "logs/b b.log".into(),
+                    size: 3,
+                },
+                ScpFile {
+                    name: "c".into(),
+                    size: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_not_scp() {
+        let mut inspector = ScpInspector::new(ScpCommand::parse("scp -f x").unwrap());
+        assert!(inspector.server_data(b"Welcome!\nC0644 1 x\n").is_empty());
+        assert!(inspector.broken);
+    }
+}
@@ -0,0 +1,109 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// RFC 4253 limits the identification line to 255 bytes
+const MAX_LINE_LENGTH: usize = 255;
+/// Give up if the client hasn't identified itself within this many bytes
+const MAX_SCANNED: usize = 8 * 1024;
+
+/// Picks the client's SSH identification string off the wire, since russh
+/// only exposes it once the client has authenticated
+#[derive(Debug)]
+pub struct ClientIdentSniffer<S> {
+    inner: S,
+    line: Vec<u8>,
+    scanned: usize,
+    ident: Arc<OnceLock<String>>,
+}
+
+impl<S> ClientIdentSniffer<S> {
+    pub fn new(inner: S, ident: Arc<OnceLock<String>>) -> Self {
+        Self {
+            inner,
+            line: vec![],
+            scanned: 0,
+            ident,
+        }
+    }
+
+    fn scan(&mut self, data: &[u8]) {
+        for byte in data {
+            if self.ident.get().is_some() || self.scanned >= MAX_SCANNED {
+                return;
+            }
+            self.scanned += 1;
+            if *byte == b'\n' {
+                let line = String::from_utf8_lossy(&self.line);
+                let line = line.trim_end_matches('\r');
+                if line.starts_with("SSH-") {
+                    let _ = self.ident.set(line.to_owned());
+                }
+                self.line.clear();
+            } else if self.line.len() < MAX_LINE_LENGTH {
+                self.line.push(*byte);
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ClientIdentSniffer<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.scan(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ClientIdentSniffer<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_sniff_ident_across_reads() {
+        let ident = Arc::new(OnceLock::new());
+        let data: &[u8] = b"SSH-2.0-Open";
+        let (mut tx, rx) = tokio::io::duplex(64);
+        let mut sniffer = ClientIdentSniffer::new(rx, ident.clone());
+
+        tx.write_all(data).await.unwrap();
+        let mut buf = [0; 64];
+        let n = sniffer.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], data);
+        assert!(ident.get().is_none());
+
+        tx.write_all(b"SSH_9.6\r\n\0\0\0").await.unwrap();
+        let _ = sniffer.read(&mut buf).await.unwrap();
+        assert_eq!(ident.get().map(String::as_str), Some("SSH-2.0-OpenSSH_9.6"));
+    }
+}
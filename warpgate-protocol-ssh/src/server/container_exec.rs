@@ -0,0 +1,184 @@
+use warpgate_common::{ContainerRuntime, TargetDockerOptions};
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ContainerExecError {
+    #[error("no container specified, use `ssh ... <container> [command]`")]
+    NoContainer,
+    #[error("invalid container name: {0}")]
+    InvalidName(String),
+    #[error("container not allowed: {0}")]
+    NotAllowed(String),
+}
+
+/// Translates an exec request of the form `<container> [command]` (or a shell
+/// request when `request` is `None`) into a runtime invocation on the host.
+/// The container is checked against the target's allowlist and the user's
+/// command is always passed as a single quoted argument to the shell.
+pub fn container_exec_command(
+    options: &TargetDockerOptions,
+    request: Option<&str>,
+    tty: bool,
+) -> Result<String, ContainerExecError> {
+    let (container, command) = match request.map(str::trim) {
+        Some(request) if !request.is_empty() => match request.split_once(char::is_whitespace) {
+            Some((container, command)) => (container, Some(command.trim())),
+            None => (request, None),
+        },
+        // An interactive shell is only unambiguous with a single allowed container
+        _ => match &options.allowed_containers[..] {
+            [container] if !container.ends_with('*') => (&container[..], None),
+            _ => return Err(ContainerExecError::NoContainer),
+        },
+    };
+
+    if !is_valid_container_name(container) {
+        return Err(ContainerExecError::InvalidName(container.to_owned()));
+    }
+    if !options
+        .allowed_containers
+        .iter()
+        .any(|pattern| matches_pattern(pattern, container))
+    {
+        return Err(ContainerExecError::NotAllowed(container.to_owned()));
+    }
+
+    let mut args = vec![match options.runtime {
+        ContainerRuntime::Docker => "docker".to_owned(),
+        ContainerRuntime::Podman => "podman".to_owned(),
+    }];
+    if let Some(socket) = &options.socket {
+        args.push(
+            match options.runtime {
+                ContainerRuntime::Docker => "-H",
+                ContainerRuntime::Podman => "--url",
+            }
+            .to_owned(),
+        );
+        args.push(shell_quote(socket));
+    }
+    args.push("exec".to_owned());
+    args.push(if tty { "-it" } else { "-i" }.to_owned());
+    args.push(container.to_owned());
+    args.push(shell_quote(&options.shell));
+    if let Some(command) = command.filter(|c| !c.is_empty()) {
+        args.push("-c".to_owned());
+        args.push(shell_quote(command));
+    }
+    Ok(args.join(" "))
+}
+
+fn matches_pattern(pattern: &str, container: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => container.starts_with(prefix),
+        None => pattern == container,
+    }
+}
+
+/// Same rules as Docker's own container names, which also rules out anything
+/// that the host shell would interpret
+fn is_valid_container_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::TargetSSHOptions;
+
+    use super::*;
+
+    fn options(allowed_containers: &[&str]) -> TargetDockerOptions {
+        TargetDockerOptions {
+            ssh: TargetSSHOptions {
+                host: "docker-host".into(),
+                port: 22,
+                username: "root".into(),
+                allow_insecure_algos: None,
+                auth: Default::default(),
+                aws_ssm: None,
+                forward_presets: vec![],
+                forwarding_policy: None,
+                allowed_forward_destinations: vec![],
+                role_forwarding_policies: vec![],
+                reconnect: None,
+                multiplexing: None,
+                show_fingerprint: false,
+                block_scp_uploads: false,
+                block_scp_downloads: false,
+            },
+            runtime: ContainerRuntime::Docker,
+            socket: None,
+            allowed_containers: allowed_containers.iter().map(|x| x.to_string()).collect(),
+            shell: "/bin/sh".into(),
+        }
+    }
+
+    #[test]
+    fn test_exec_command() {
+        let options = options(&["web", "worker-*"]);
+        assert_eq!(
+            container_exec_command(&options, Some("web"), true),
+            Ok("docker exec -it web '/bin/sh'".into())
+        );
+        assert_eq!(
+            container_exec_command(&options, Some("worker-1 ls -la; echo 'hi'"), false),
+            Ok(r#"docker exec -i worker-1 '/bin/sh' -c 'ls -la; echo '\''hi'\'''"#.into())
+        );
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let options = options(&["web", "worker-*"]);
+        assert_eq!(
+            container_exec_command(&options, Some("db"), true),
+            Err(ContainerExecError::NotAllowed("db".into()))
+        );
+        assert_eq!(
+            container_exec_command(&options, Some("web2"), true),
+            Err(ContainerExecError::NotAllowed("web2".into()))
+        );
+        assert_eq!(
+            container_exec_command(&options, Some("web;reboot"), true),
+            Err(ContainerExecError::InvalidName("web;reboot".into()))
+        );
+        assert_eq!(
+            container_exec_command(&options, Some("-v"), true),
+            Err(ContainerExecError::InvalidName("-v".into()))
+        );
+    }
+
+    #[test]
+    fn test_shell_request() {
+        assert_eq!(
+            container_exec_command(&options(&["web"]), None, true),
+            Ok("docker exec -it web '/bin/sh'".into())
+        );
+        assert_eq!(
+            container_exec_command(&options(&["web", "db"]), None, true),
+            Err(ContainerExecError::NoContainer)
+        );
+        assert_eq!(
+            container_exec_command(&options(&["web-*"]), None, true),
+            Err(ContainerExecError::NoContainer)
+        );
+    }
+
+    #[test]
+    fn test_podman_socket() {
+        let mut options = options(&["web"]);
+        options.runtime = ContainerRuntime::Podman;
+        options.socket = Some("unix:///run/podman/podman.sock".into());
+        assert_eq!(
+            container_exec_command(&options, Some("web id"), false),
+            Ok(
+                "podman --url 'unix:///run/podman/podman.sock' exec -i web '/bin/sh' -c 'id'"
+                    .into()
+            )
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use data_encoding::BASE64;
+use warpgate_core::{DropBoxError, DropBoxUpload};
+
+/// Exec requests starting with this are handled by Warpgate itself
+/// and never reach the target
+pub const DROP_BOX_COMMAND: &str = "warpgate-dropbox";
+
+pub const DROP_BOX_USAGE: &str = "\
+Usage: warpgate-dropbox [list]         list items staged for this session
+       warpgate-dropbox get <name>     write an item to stdout
+       warpgate-dropbox put <name>     stage stdin as an item
+       warpgate-dropbox helper         print a bash function that runs the
+                                       same commands inside an interactive
+                                       shell on the target
+";
+
+/// Holds the token that in-band requests from the shell helper must carry,
+/// so that other output can't trigger drop-box commands.
+/// `LC_*` variables are accepted by most sshd configurations.
+pub const DROP_BOX_TOKEN_VARIABLE: &str = "LC_WARPGATE_DROPBOX";
+
+/// Requests are written to the terminal as OSC sequences, which Warpgate
+/// removes from the output. Replies are typed into the terminal as lines
+/// starting with `#`, so that a shell that isn't waiting for them ignores them.
+pub const DROP_BOX_HELPER: &str = r##"warpgate-dropbox() {
+    local token="$LC_WARPGATE_DROPBOX" status line saved
+    if [ -z "$token" ]; then
+        echo "warpgate-dropbox: not available in this session" >&2
+        return 1
+    fi
+    _wgdb_send() { printf '\033]warpgate-dropbox;%s;%s\007' "$token" "$1" >/dev/tty; }
+    saved=$(stty -g </dev/tty)
+    trap 'stty "$saved" </dev/tty; trap - INT; return 130' INT
+    stty -echo </dev/tty
+    case "$1" in
+        ""|list) _wgdb_send list ;;
+        get) _wgdb_send "get;$2" ;;
+        put)
+            _wgdb_send "put;$2"
+            base64 -w 76 | while IFS= read -r line; do _wgdb_send "data;$line"; done
+            _wgdb_send end
+            ;;
+        *)
+            stty "$saved" </dev/tty
+            trap - INT
+            echo "Usage: warpgate-dropbox [list | get <name> | put <name>]" >&2
+            return 2
+            ;;
+    esac
+    if IFS= read -r -t 30 status </dev/tty; then
+        sed -n -e '/^#\.$/q' -e 's/^#//p' </dev/tty | base64 -d
+    else
+        status="#error no reply from Warpgate"
+    fi
+    stty "$saved" </dev/tty
+    trap - INT
+    if [ "$status" != "#ok" ]; then
+        echo "warpgate-dropbox: ${status#\#error }" >&2
+        return 1
+    fi
+}
+"##;
+
+const IN_BAND_PREFIX: &[u8] = b"\x1b]warpgate-dropbox;";
+const IN_BAND_TERMINATOR: u8 = 0x07;
+const MAX_IN_BAND_REQUEST: usize = 4096;
+const REPLY_LINE_LENGTH: usize = 76;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DropBoxCommand {
+    List,
+    Get(String),
+    Put(String),
+    Helper,
+    Help,
+    Invalid,
+}
+
+impl DropBoxCommand {
+    /// Returns `None` if the exec request isn't a drop-box command
+    pub fn parse(command: &str) -> Option<Self> {
+        let mut args = command.split_whitespace();
+        if args.next()? != DROP_BOX_COMMAND {
+            return None;
+        }
+        Some(match (args.next(), args.next(), args.next()) {
+            (None | Some("list"), None, None) => Self::List,
+            (Some("helper"), None, None) => Self::Helper,
+            (Some("get"), Some(name), None) => Self::Get(name.to_owned()),
+            (Some("put"), Some(name), None) => Self::Put(name.to_owned()),
+            (Some("help" | "-h" | "--help"), None, None) => Self::Help,
+            _ => Self::Invalid,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InBandRequest {
+    List,
+    Get(String),
+    Put(String),
+    /// Next chunk of the item started with [InBandRequest::Put]
+    Data(Vec<u8>),
+    /// The item started with [InBandRequest::Put] is complete
+    End,
+    Invalid,
+    /// The token didn't match
+    Unauthorized,
+}
+
+impl InBandRequest {
+    fn parse(body: &[u8], token: &str) -> Self {
+        let Ok(body) = std::str::from_utf8(body) else {
+            return Self::Invalid;
+        };
+        let mut parts = body.splitn(3, ';');
+        if !constant_time_eq(
+            parts.next().unwrap_or_default().as_bytes(),
+            token.as_bytes(),
+        ) {
+            return Self::Unauthorized;
+        }
+        match (parts.next(), parts.next()) {
+            (Some("list"), None) => Self::List,
+            (Some("get"), Some(name)) => Self::Get(name.to_owned()),
+            (Some("put"), Some(name)) => Self::Put(name.to_owned()),
+            (Some("data"), Some(data)) => BASE64
+                .decode(data.as_bytes())
+                .map(Self::Data)
+                .unwrap_or(Self::Invalid),
+            (Some("end"), None) => Self::End,
+            _ => Self::Invalid,
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Picks requests written by [DROP_BOX_HELPER] out of a shell's output
+pub struct InBandScanner {
+    token: String,
+    /// Start of a possible request, waiting for more output
+    pending: Vec<u8>,
+}
+
+impl InBandScanner {
+    pub fn new(token: String) -> Self {
+        Self {
+            token,
+            pending: vec![],
+        }
+    }
+
+    /// Returns the output with the requests removed, and the requests
+    pub fn scan(&mut self, data: &[u8]) -> (Vec<u8>, Vec<InBandRequest>) {
+        let mut input = std::mem::take(&mut self.pending);
+        input.extend_from_slice(data);
+
+        let mut output = Vec::with_capacity(input.len());
+        let mut requests = vec![];
+        let mut position = 0;
+        while let Some(offset) = input[position..].iter().position(|x| *x == 0x1b) {
+            let start = position + offset;
+            output.extend_from_slice(&input[position..start]);
+            let rest = &input[start..];
+
+            if rest.len() < IN_BAND_PREFIX.len() && IN_BAND_PREFIX.starts_with(rest) {
+                self.pending = rest.to_vec();
+                return (output, requests);
+            }
+            if !rest.starts_with(IN_BAND_PREFIX) {
+                output.push(0x1b);
+                position = start + 1;
+                continue;
+            }
+            match rest.iter().position(|x| *x == IN_BAND_TERMINATOR) {
+                Some(end) if end <= MAX_IN_BAND_REQUEST => {
+                    requests.push(InBandRequest::parse(
+                        &rest[IN_BAND_PREFIX.len()..end],
+                        &self.token,
+                    ));
+                    position = start + end + 1;
+                }
+                None if rest.len() <= MAX_IN_BAND_REQUEST => {
+                    self.pending = rest.to_vec();
+                    return (output, requests);
+                }
+                // Too long to be a request, pass it through
+                _ => {
+                    output.push(0x1b);
+                    position = start + 1;
+                }
+            }
+        }
+        output.extend_from_slice(&input[position..]);
+        (output, requests)
+    }
+}
+
+/// Drop-box state of an interactive shell that the helper can be used in
+pub struct ShellDropBox {
+    pub scanner: InBandScanner,
+    pub upload: Option<Result<DropBoxUpload, DropBoxError>>,
+}
+
+impl ShellDropBox {
+    pub fn new(token: String) -> Self {
+        Self {
+            scanner: InBandScanner::new(token),
+            upload: None,
+        }
+    }
+}
+
+/// Formats a reply to be typed into the terminal for [DROP_BOX_HELPER]
+pub fn in_band_reply(result: Result<&[u8], String>) -> Vec<u8> {
+    let mut reply = vec![];
+    match result {
+        Ok(data) => {
+            reply.extend_from_slice(b"#ok\n");
+            let encoded = BASE64.encode(data);
+            for line in encoded.as_bytes().chunks(REPLY_LINE_LENGTH) {
+                reply.push(b'#');
+                reply.extend_from_slice(line);
+                reply.push(b'\n');
+            }
+        }
+        Err(error) => {
+            let error = error.replace(|c: char| c.is_control(), " ");
+            reply.extend_from_slice(format!("#error {error}\n").as_bytes());
+        }
+    }
+    reply.extend_from_slice(b"#.\n");
+    reply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(DropBoxCommand::parse("ls -la"), None);
+        assert_eq!(DropBoxCommand::parse("warpgate-dropboxx"), None);
+        assert_eq!(
+            DropBoxCommand::parse("warpgate-dropbox"),
+            Some(DropBoxCommand::List)
+        );
+        assert_eq!(
+            DropBoxCommand::parse(" warpgate-dropbox  get notes.txt "),
+            Some(DropBoxCommand::Get("notes.txt".into()))
+        );
+        assert_eq!(
+            DropBoxCommand::parse("warpgate-dropbox put dump.sql"),
+            Some(DropBoxCommand::Put("dump.sql".into()))
+        );
+        assert_eq!(
+            DropBoxCommand::parse("warpgate-dropbox get a b"),
+            Some(DropBoxCommand::Invalid)
+        );
+        assert_eq!(
+            DropBoxCommand::parse("warpgate-dropbox --help"),
+            Some(DropBoxCommand::Help)
+        );
+    }
+
+    fn request(body: &str) -> Vec<u8> {
+        format!("\x1b]warpgate-dropbox;{body}\x07").into_bytes()
+    }
+
+    #[test]
+    fn test_in_band_requests() {
+        let mut scanner = InBandScanner::new("token".into());
+        let mut data = b"before\x1b[1m".to_vec();
+        data.extend(request("token;list"));
+        data.extend(request("token;get;notes.txt"));
+        data.extend(request("wrong;list"));
+        data.extend(request("token;data;aGVsbG8="));
+        data.extend(request("token;data;not base64"));
+        data.extend(b"after");
+
+        let (output, requests) = scanner.scan(&data);
+        assert_eq!(output, b"before\x1b[1mafter");
+        assert_eq!(
+            requests,
+            vec![
+                InBandRequest::List,
+                InBandRequest::Get("notes.txt".into()),
+                InBandRequest::Unauthorized,
+                InBandRequest::Data(b"hello".to_vec()),
+                InBandRequest::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_in_band_request_split_across_chunks() {
+        let mut scanner = InBandScanner::new("token".into());
+        let data = [b"a".to_vec(), request("token;end"), b"b\x1b".to_vec()].concat();
+        let mut output = vec![];
+        let mut requests = vec![];
+        for chunk in data.chunks(3) {
+            let (chunk_output, chunk_requests) = scanner.scan(chunk);
+            output.extend(chunk_output);
+            requests.extend(chunk_requests);
+        }
+        let (chunk_output, _) = scanner.scan(b"c");
+        output.extend(chunk_output);
+        assert_eq!(output, b"ab\x1bc");
+        assert_eq!(requests, vec![InBandRequest::End]);
+    }
+
+    #[test]
+    fn test_unterminated_request_is_passed_through() {
+        let mut scanner = InBandScanner::new("token".into());
+        let mut data = request("token;data;");
+        data.pop();
+        data.extend(vec![b'A'; MAX_IN_BAND_REQUEST]);
+        let (output, requests) = scanner.scan(&data);
+        assert_eq!(output, data);
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn test_in_band_reply() {
+        assert_eq!(
+            in_band_reply(Ok(b"hello")),
+            b"#ok\n#aGVsbG8=\n#.\n".to_vec()
+        );
+        assert_eq!(
+            in_band_reply(Err("no\nsuch item".into())),
+            b"#error no such item\n#.\n".to_vec()
+        );
+        let reply = in_band_reply(Ok(&[0; 100]));
+        assert!(reply.split(|x| *x == b'\n').all(|line| line.len() <= 77));
+    }
+}
@@ -0,0 +1,233 @@
+use std::net::IpAddr;
+use std::time::Instant;
+
+use ipnet::IpNet;
+use warpgate_common::{SshForwardingPolicy, TargetSSHOptions};
+
+/// Checks a port forward against the target's forwarding policy, or
+/// against the policies of the user's roles if the target has any for them.
+/// A forward is allowed if any of the applicable role policies allows it.
+///
+/// `host` and `port` are the destination for direct TCP/IP channels
+/// (`ssh -L`, `-W` and `-D`) and the bind address for remote forwards (`ssh -R`).
+pub fn is_forward_allowed(
+    options: &TargetSSHOptions,
+    user_roles: &[String],
+    host: &str,
+    port: u32,
+) -> bool {
+    let mut role_policies = options
+        .role_forwarding_policies
+        .iter()
+        .filter(|x| user_roles.contains(&x.role))
+        .peekable();
+    if role_policies.peek().is_none() {
+        return policy_allows(
+            options.effective_forwarding_policy(),
+            &options.allowed_forward_destinations,
+            host,
+            port,
+        );
+    }
+    role_policies.any(|x| policy_allows(x.policy, &x.allowed_destinations, host, port))
+}
+
+fn policy_allows(policy: SshForwardingPolicy, patterns: &[String], host: &str, port: u32) -> bool {
+    match policy {
+        SshForwardingPolicy::AllowAll => true,
+        SshForwardingPolicy::DenyAll => false,
+        SshForwardingPolicy::Allowlist => patterns
+            .iter()
+            .any(|pattern| matches_pattern(pattern, host, port)),
+    }
+}
+
+/// Each pattern has the form `host[:ports]`, where `host` is a host name,
+/// a `*.domain` wildcard or an IP network in CIDR notation (IPv6 in brackets,
+/// e.g. `[fd00::/8]:22`), and `ports` is `*`, a single port or a `first-last` range.
+fn matches_pattern(pattern: &str, host: &str, port: u32) -> bool {
+    let (host_pattern, port_pattern) = split_pattern(pattern.trim());
+    matches_port(port_pattern, port) && matches_host(host_pattern, host)
+}
+
+fn split_pattern(pattern: &str) -> (&str, &str) {
+    if let Some(rest) = pattern.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((host, ports)) => (host, ports.strip_prefix(':').unwrap_or("*")),
+            None => (rest, "*"),
+        };
+    }
+    match pattern.split_once(':') {
+        // A bare IPv6 network has several colons
+        Some((host, ports)) if !ports.contains(':') => (host, ports),
+        _ => (pattern, "*"),
+    }
+}
+
+fn matches_port(pattern: &str, port: u32) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.split_once('-') {
+        Some((first, last)) => match (first.parse::<u32>(), last.parse::<u32>()) {
+            (Ok(first), Ok(last)) => (first..=last).contains(&port),
+            _ => false,
+        },
+        None => pattern.parse::<u32>() == Ok(port),
+    }
+}
+
+fn matches_host(pattern: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if pattern == "*" {
+        return true;
+    }
+    // Host names are resolved on the target, so networks only match IP addresses
+    if let Ok(network) = pattern.parse::<IpNet>() {
+        return host
+            .parse::<IpAddr>()
+            .is_ok_and(|address| network.contains(&address));
+    }
+    if let (Ok(pattern), Ok(address)) = (pattern.parse::<IpAddr>(), host.parse::<IpAddr>()) {
+        return pattern == address;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .to_ascii_lowercase()
+            .strip_suffix(&domain.to_ascii_lowercase())
+            .is_some_and(|x| x.ends_with('.')),
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+/// Per-connection stats of a direct TCP/IP channel, logged once it closes
+pub struct ForwardedConnection {
+    pub destination: String,
+    pub opened: Instant,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl ForwardedConnection {
+    pub fn new(host: &str, port: u32) -> Self {
+        Self {
+            destination: format!("{host}:{port}"),
+            opened: Instant::now(),
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warpgate_common::SshRoleForwardingPolicy;
+
+    use super::*;
+
+    fn allowed(patterns: &[&str], host: &str, port: u32) -> bool {
+        let patterns = patterns.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+        policy_allows(SshForwardingPolicy::Allowlist, &patterns, host, port)
+    }
+
+    fn options(
+        policy: Option<SshForwardingPolicy>,
+        destinations: &[&str],
+        role_policies: Vec<SshRoleForwardingPolicy>,
+    ) -> TargetSSHOptions {
+        TargetSSHOptions {
+            host: "target".into(),
+            port: 22,
+            username: "root".into(),
+            allow_insecure_algos: None,
+            auth: Default::default(),
+            aws_ssm: None,
+            forward_presets: vec![],
+            forwarding_policy: policy,
+            allowed_forward_destinations: destinations.iter().map(|x| x.to_string()).collect(),
+            role_forwarding_policies: role_policies,
+            reconnect: None,
+            multiplexing: None,
+            show_fingerprint: false,
+            block_scp_uploads: false,
+            block_scp_downloads: false,
+        }
+    }
+
+    #[test]
+    fn test_target_policy() {
+        let unset = options(None, &[], vec![]);
+        assert!(is_forward_allowed(&unset, &[], "example.com", 443));
+
+        let legacy = options(None, &["db:5432"], vec![]);
+        assert!(is_forward_allowed(&legacy, &[], "db", 5432));
+        assert!(!is_forward_allowed(&legacy, &[], "example.com", 443));
+
+        let deny = options(Some(SshForwardingPolicy::DenyAll), &["db:5432"], vec![]);
+        assert!(!is_forward_allowed(&deny, &[], "db", 5432));
+
+        let empty = options(Some(SshForwardingPolicy::Allowlist), &[], vec![]);
+        assert!(!is_forward_allowed(&empty, &[], "db", 5432));
+    }
+
+    #[test]
+    fn test_role_policies() {
+        let options = options(
+            Some(SshForwardingPolicy::DenyAll),
+            &[],
+            vec![
+                SshRoleForwardingPolicy {
+                    role: "dba".into(),
+                    policy: SshForwardingPolicy::Allowlist,
+                    allowed_destinations: vec!["db:5432".into()],
+                },
+                SshRoleForwardingPolicy {
+                    role: "ops".into(),
+                    policy: SshForwardingPolicy::AllowAll,
+                    allowed_destinations: vec![],
+                },
+            ],
+        );
+        assert!(!is_forward_allowed(&options, &["dev".into()], "db", 5432));
+        assert!(is_forward_allowed(&options, &["dba".into()], "db", 5432));
+        assert!(!is_forward_allowed(&options, &["dba".into()], "web", 80));
+        assert!(is_forward_allowed(
+            &options,
+            &["dba".into(), "ops".into()],
+            "web",
+            80
+        ));
+    }
+
+    #[test]
+    fn test_host_patterns() {
+        let patterns = ["db.internal:5432", "*.corp.example:443", "10.1.0.0/16:*"];
+        assert!(allowed(&patterns, "db.internal", 5432));
+        assert!(allowed(&patterns, "DB.Internal", 5432));
+        assert!(!allowed(&patterns, "db.internal", 22));
+        assert!(allowed(&patterns, "wiki.corp.example", 443));
+        assert!(!allowed(&patterns, "corp.example", 443));
+        assert!(!allowed(&patterns, "evilcorp.example", 443));
+        assert!(allowed(&patterns, "10.1.2.3", 8080));
+        assert!(!allowed(&patterns, "10.2.0.1", 8080));
+        assert!(!allowed(&patterns, "example.com", 443));
+    }
+
+    #[test]
+    fn test_port_patterns() {
+        let patterns = ["web:8000-8099", "ssh"];
+        assert!(allowed(&patterns, "web", 8042));
+        assert!(!allowed(&patterns, "web", 8100));
+        assert!(allowed(&patterns, "ssh", 22));
+        assert!(allowed(&patterns, "ssh", 2222));
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let patterns = ["[fd00::/8]:22", "::1"];
+        assert!(allowed(&patterns, "fd12::1", 22));
+        assert!(!allowed(&patterns, "fd12::1", 80));
+        assert!(allowed(&patterns, "::1", 80));
+        assert!(allowed(&patterns, "[::1]", 80));
+    }
+}
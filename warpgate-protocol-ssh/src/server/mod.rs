@@ -1,11 +1,17 @@
 mod channel_writer;
+mod client_ident;
+mod container_exec;
+mod drop_box;
+mod forward_policy;
 mod russh_handler;
 mod service_output;
 mod session;
 mod session_handle;
+mod startup_throttle;
+mod ticket_command;
 use std::borrow::Cow;
 use std::fmt::Debug;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
 use anyhow::Result;
@@ -16,21 +22,27 @@ pub use russh_handler::ServerHandler;
 pub use session::ServerSession;
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Mutex;
 use tracing::*;
 use warpgate_common::ListenEndpoint;
-use warpgate_core::{Services, SessionStateInit};
+use warpgate_core::{Services, SessionState, SessionStateInit};
+use warpgate_db_entities::Session::SessionTerminationReason;
 
 use crate::keys::load_host_keys;
+use crate::server::client_ident::ClientIdentSniffer;
 use crate::server::session_handle::SSHSessionHandle;
+use crate::server::startup_throttle::StartupThrottle;
+use crate::{ChannelWindows, RELAY_WINDOW_SIZE};
 
 pub async fn run_server(services: Services, address: ListenEndpoint) -> Result<()> {
-    let russh_config = {
-        let config = services.config.lock().await;
-        russh::server::Config {
+    let (russh_config, socket_options, max_startups) = {
+        let config = services.config.load();
+        let russh_config = russh::server::Config {
             auth_rejection_time: Duration::from_secs(1),
             auth_rejection_time_initial: Some(Duration::from_secs(0)),
             inactivity_timeout: Some(config.store.ssh.inactivity_timeout),
             keepalive_interval: config.store.ssh.keepalive_interval,
+            keepalive_max: config.store.ssh.keepalive_max,
             methods: MethodSet::from(
                 &[
                     MethodKind::PublicKey,
@@ -40,6 +52,7 @@ pub async fn run_server(services: Services, address: ListenEndpoint) -> Result<(
             ),
             keys: vec![load_host_keys(&config)?],
             event_buffer_size: 100,
+            window_size: RELAY_WINDOW_SIZE,
             preferred: Preferred {
                 key: Cow::Borrowed(&[
                     Algorithm::Ed25519,
@@ -54,16 +67,37 @@ pub async fn run_server(services: Services, address: ListenEndpoint) -> Result<(
                 ..<_>::default()
             },
             ..<_>::default()
-        }
+        };
+        (
+            russh_config,
+            config.store.ssh.socket.clone(),
+            config.store.ssh.max_startups,
+        )
     };
 
     let russh_config = Arc::new(russh_config);
+    let startup_throttle = StartupThrottle::new(max_startups);
 
-    let mut listener = address.tcp_accept_stream().await?;
+    let mut listener = address.tcp_accept_stream(&socket_options).await?;
 
     info!(?address, "Listening");
     while let Some(stream) = listener.try_next().await? {
         let remote_address = stream.peer_addr()?;
+
+        if !services.ip_bans.is_allowed(remote_address.ip()) {
+            debug!(%remote_address, "Dropping a connection from a banned address");
+            continue;
+        }
+
+        let startup_permit = match startup_throttle.try_admit(remote_address.ip()) {
+            Ok(permit) => permit,
+            Err(reason) => {
+                // Not logged at a higher level since this happens during floods
+                debug!(%remote_address, ?reason, "Dropping unauthenticated connection");
+                continue;
+            }
+        };
+
         let russh_config = russh_config.clone();
 
         let (session_handle, session_handle_rx) = SSHSessionHandle::new();
@@ -81,11 +115,20 @@ pub async fn run_server(services: Services, address: ListenEndpoint) -> Result<(
             )
             .await?;
 
-        let id = server_handle.lock().await.id();
+        let (id, session_state) = {
+            let server_handle = server_handle.lock().await;
+            (server_handle.id(), server_handle.session_state().clone())
+        };
 
         let (event_tx, event_rx) = unbounded_channel();
 
-        let handler = ServerHandler { event_tx };
+        let upload_windows = ChannelWindows::default();
+        let client_ident = Arc::new(OnceLock::new());
+        let stream = ClientIdentSniffer::new(stream, client_ident.clone());
+        let handler = ServerHandler {
+            event_tx,
+            upload_windows: upload_windows.clone(),
+        };
 
         let session = match ServerSession::start(
             remote_address,
@@ -93,6 +136,9 @@ pub async fn run_server(services: Services, address: ListenEndpoint) -> Result<(
             server_handle,
             session_handle_rx,
             event_rx,
+            upload_windows,
+            startup_permit,
+            client_ident,
         )
         .await
         {
@@ -109,7 +155,7 @@ pub async fn run_server(services: Services, address: ListenEndpoint) -> Result<(
 
         tokio::task::Builder::new()
             .name(&format!("SSH {id} protocol"))
-            .spawn(_run_stream(russh_config, stream, handler))?;
+            .spawn(_run_stream(russh_config, stream, handler, session_state))?;
     }
     Ok(())
 }
@@ -118,11 +164,12 @@ async fn _run_stream<R>(
     config: Arc<russh::server::Config>,
     socket: R,
     handler: ServerHandler,
+    session_state: Arc<Mutex<SessionState>>,
 ) -> Result<()>
 where
     R: AsyncRead + AsyncWrite + Unpin + Debug + Send + 'static,
 {
-    let ret = async move {
+    let ret: Result<()> = async move {
         let session = russh::server::run_stream(config, socket, handler).await?;
         session.await?;
         Ok(())
@@ -130,7 +177,15 @@ where
     .await;
 
     if let Err(ref error) = ret {
-        error!(%error, "Session failed");
+        if let Some(russh::Error::InactivityTimeout) = error.downcast_ref() {
+            info!("Session timed out");
+            session_state
+                .lock()
+                .await
+                .set_termination_reason(SessionTerminationReason::IdleTimeout);
+        } else {
+            error!(%error, "Session failed");
+        }
     }
 
     ret
@@ -10,7 +10,9 @@ use tracing::*;
 use warpgate_common::Secret;
 
 use crate::common::{PtyRequest, ServerChannelId};
-use crate::{DirectTCPIPParams, X11Request};
+use crate::{
+    ChannelWindows, DirectTCPIPParams, RelayPermit, RelayWindow, X11Request, RELAY_WINDOW_SIZE,
+};
 
 pub struct HandleWrapper(pub Handle);
 
@@ -22,7 +24,8 @@ impl Debug for HandleWrapper {
 
 #[derive(Debug)]
 pub enum ServerHandlerEvent {
-    Authenticated(HandleWrapper),
+    /// Carries the client's SSH identification string
+    Authenticated(HandleWrapper, String),
     ChannelOpenSession(ServerChannelId, oneshot::Sender<bool>),
     SubsystemRequest(ServerChannelId, String, oneshot::Sender<bool>),
     PtyRequest(ServerChannelId, PtyRequest, oneshot::Sender<()>),
@@ -35,8 +38,14 @@ pub enum ServerHandlerEvent {
         Option<Secret<String>>,
         oneshot::Sender<Auth>,
     ),
-    Data(ServerChannelId, Bytes, oneshot::Sender<()>),
-    ExtendedData(ServerChannelId, Bytes, u32, oneshot::Sender<()>),
+    Data(ServerChannelId, Bytes, RelayPermit, oneshot::Sender<()>),
+    ExtendedData(
+        ServerChannelId,
+        Bytes,
+        u32,
+        RelayPermit,
+        oneshot::Sender<()>,
+    ),
     ChannelClose(ServerChannelId, oneshot::Sender<()>),
     ChannelEof(ServerChannelId, oneshot::Sender<()>),
     WindowChangeRequest(ServerChannelId, PtyRequest, oneshot::Sender<()>),
@@ -52,6 +61,8 @@ pub enum ServerHandlerEvent {
 
 pub struct ServerHandler {
     pub event_tx: UnboundedSender<ServerHandlerEvent>,
+    /// Limits client-to-target data that hasn't been written upstream yet
+    pub upload_windows: ChannelWindows,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -60,12 +71,29 @@ pub enum ServerHandlerError {
     ChannelSend,
 }
 
+/// Smallest window a channel gets even while its target is falling behind
+const MIN_WINDOW_SIZE: u32 = 64 * 1024;
+
 impl ServerHandler {
     fn send_event(&self, event: ServerHandlerEvent) -> Result<(), ServerHandlerError> {
         self.event_tx
             .send(event)
             .map_err(|_| ServerHandlerError::ChannelSend)
     }
+
+    /// russh handles the messages of a connection one by one, so waiting
+    /// here pauses all of its channels. That only happens once the channel
+    /// has used up its own window, which the client can only do by ignoring
+    /// the smaller window adjustments from [Self::adjust_window].
+    async fn reserve(window: RelayWindow, len: usize) -> RelayPermit {
+        match window.try_reserve(len) {
+            Some(permit) => permit,
+            None => {
+                debug!("Upload window is full");
+                window.reserve(len).await
+            }
+        }
+    }
 }
 
 impl russh::server::Handler for ServerHandler {
@@ -73,7 +101,11 @@ impl russh::server::Handler for ServerHandler {
 
     async fn auth_succeeded(&mut self, session: &mut Session) -> Result<(), Self::Error> {
         let handle = session.handle();
-        self.send_event(ServerHandlerEvent::Authenticated(HandleWrapper(handle)))?;
+        let client_version = String::from_utf8_lossy(session.remote_sshid()).into_owned();
+        self.send_event(ServerHandlerEvent::Authenticated(
+            HandleWrapper(handle),
+            client_version,
+        ))?;
         Ok(())
     }
 
@@ -252,10 +284,11 @@ impl russh::server::Handler for ServerHandler {
     ) -> Result<(), Self::Error> {
         let channel = ServerChannelId(channel);
         let data = Bytes::from(data.to_vec());
+        let permit = Self::reserve(self.upload_windows.get(channel), data.len()).await;
 
         let (tx, rx) = oneshot::channel();
 
-        self.send_event(ServerHandlerEvent::Data(channel, data, tx))?;
+        self.send_event(ServerHandlerEvent::Data(channel, data, permit, tx))?;
 
         let _ = rx.await;
         Ok(())
@@ -270,9 +303,12 @@ impl russh::server::Handler for ServerHandler {
     ) -> Result<(), Self::Error> {
         let channel = ServerChannelId(channel);
         let data = Bytes::from(data.to_vec());
+        let permit = Self::reserve(self.upload_windows.get(channel), data.len()).await;
         let (tx, rx) = oneshot::channel();
 
-        self.send_event(ServerHandlerEvent::ExtendedData(channel, data, code, tx))?;
+        self.send_event(ServerHandlerEvent::ExtendedData(
+            channel, data, code, permit, tx,
+        ))?;
         let _ = rx.await;
         Ok(())
     }
@@ -283,12 +319,26 @@ impl russh::server::Handler for ServerHandler {
         _session: &mut Session,
     ) -> Result<(), Self::Error> {
         let channel = ServerChannelId(channel);
+        self.upload_windows.remove(channel);
         let (tx, rx) = oneshot::channel();
         self.send_event(ServerHandlerEvent::ChannelClose(channel, tx))?;
         let _ = rx.await;
         Ok(())
     }
 
+    /// Called after russh has extended a channel's window. The next window
+    /// is only as large as the credit left for this channel, so that the
+    /// client slows down before the channel's window runs out. russh keeps
+    /// a single target size per connection, but it's recalculated after
+    /// every adjustment, and it's the busy channel that gets adjusted most.
+    fn adjust_window(&mut self, channel: ChannelId, _current: u32) -> u32 {
+        let available = self
+            .upload_windows
+            .get(ServerChannelId(channel))
+            .available();
+        (available as u32).clamp(MIN_WINDOW_SIZE, RELAY_WINDOW_SIZE)
+    }
+
     async fn window_change_request(
         &mut self,
         channel: ChannelId,
@@ -1,10 +1,11 @@
 use std::borrow::Cow;
 use std::collections::hash_map::Entry::Vacant;
 use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::pin::Pin;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::task::Poll;
 
 use ansi_term::Colour;
@@ -14,33 +15,57 @@ use bytes::Bytes;
 use futures::{Future, FutureExt};
 use russh::keys::{PublicKey, PublicKeyBase64};
 use russh::{CryptoVec, MethodKind, MethodSet, Sig};
+use sea_orm::ActiveValue::Set;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::{broadcast, oneshot, Mutex};
+use tokio::task::JoinHandle;
 use tracing::*;
 use uuid::Uuid;
-use warpgate_common::auth::{AuthCredential, AuthResult, AuthSelector, AuthState, CredentialKind};
+use warpgate_common::auth::{
+    AuthCredential, AuthResult, AuthSelector, AuthState, CredentialKind, TargetParameters,
+};
 use warpgate_common::eventhub::{EventHub, EventSender, EventSubscription};
+use warpgate_common::i18n::{Language, Message};
 use warpgate_common::{
-    Secret, SessionId, SshHostKeyVerificationMode, Target, TargetOptions, TargetSSHOptions,
-    WarpgateError,
+    ErrorCode, Secret, SessionId, SshForwardPreset, SshHostKeyVerificationMode, Target,
+    TargetDockerOptions, TargetOptions, TargetSSHOptions, WarpgateError,
 };
 use warpgate_core::recordings::{
-    self, ConnectionRecorder, TerminalRecorder, TerminalRecordingStreamId, TrafficConnectionParams,
-    TrafficRecorder,
+    self, ConnectionRecorder, SftpRecorder, TerminalRecorder, TerminalRecordingStreamId,
+    TrafficConnectionParams, TrafficRecorder,
 };
 use warpgate_core::{
-    authorize_ticket, consume_ticket, ConfigProvider, Services, WarpgateServerHandle,
+    authorize_ticket, check_target_baseline, consume_ticket, issue_self_service_ticket,
+    list_user_tickets, normalize_work_item, resolve_target_address, revoke_user_ticket,
+    trip_honeypot, AuthFailureContext, ConfigProvider, DropBoxError, DropBoxItemSource,
+    DropBoxUpload, SelfServiceTicketError, SelfServiceTicketRequest, Services, SessionChannelKind,
+    SessionChannels, SessionLastActivity, SessionTap, TargetFingerprint, TargetObservation,
+    WarpgateServerHandle,
 };
+use warpgate_db_entities::DropBoxEvent::DropBoxAction;
+use warpgate_db_entities::Session::SessionTerminationReason;
+use warpgate_db_entities::SshSessionDetails;
 
 use super::channel_writer::ChannelWriter;
+use super::container_exec::container_exec_command;
+use super::drop_box::{
+    in_band_reply, DropBoxCommand, InBandRequest, ShellDropBox, DROP_BOX_COMMAND, DROP_BOX_HELPER,
+    DROP_BOX_TOKEN_VARIABLE, DROP_BOX_USAGE,
+};
+use super::forward_policy::{is_forward_allowed, ForwardedConnection};
 use super::russh_handler::ServerHandlerEvent;
 use super::service_output::ServiceOutput;
 use super::session_handle::SessionHandleCommand;
+use super::startup_throttle::StartupPermit;
+use super::ticket_command::{TicketCommand, TICKET_COMMAND, TICKET_USAGE};
 use crate::compat::ContextExt;
+use crate::scp::{ScpCommand, ScpDirection, ScpFile, ScpInspector};
 use crate::server::service_output::ERASE_PROGRESS_SPINNER;
+use crate::sftp::{SftpEvent, SftpInspector};
 use crate::{
-    ChannelOperation, ConnectionError, DirectTCPIPParams, PtyRequest, RCCommand, RCCommandReply,
-    RCEvent, RCState, RemoteClient, ServerChannelId, SshClientError, X11Request,
+    negotiated_capabilities, ChannelOperation, ChannelWindows, ConnectionError, DirectTCPIPParams,
+    HoneypotClient, MultiplexKey, PtyRequest, RCCommand, RCCommandReply, RCEvent, RCState,
+    RelayPermit, RemoteClient, ServerChannelId, SolClient, SshClientError, X11Request,
 };
 
 #[derive(Clone)]
@@ -48,9 +73,21 @@ use crate::{
 enum TargetSelection {
     None,
     NotFound(String),
+    /// The target doesn't accept new sessions, with the reason shown to the user
+    Rejected(String),
+    /// The target's address template couldn't be filled in
+    Unresolved(String),
     Found(Target, TargetSSHOptions),
+    /// Serial console, served by a [SolClient] instead of an SSH connection
+    Console(Target),
+    /// Decoy served by a [HoneypotClient]
+    Honeypot,
 }
 
+/// Subsystem that connects the channel to a forward preset,
+/// e.g. `ssh -s user:target@warpgate warpgate-forward@db`
+const FORWARD_PRESET_SUBSYSTEM_PREFIX: &str = "warpgate-forward@";
+
 #[derive(Debug)]
 enum Event {
     Command(SessionHandleCommand),
@@ -64,6 +101,15 @@ enum KeyboardInteractiveState {
     None,
     OtpRequested,
     WebAuthRequested(broadcast::Receiver<AuthResult>),
+    ConsentRequested,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConsentState {
+    NotRequested,
+    /// The user is authenticated, but hasn't accepted the banner yet
+    Pending,
+    Accepted,
 }
 
 struct CachedSuccessfulTicketAuth {
@@ -73,11 +119,15 @@ struct CachedSuccessfulTicketAuth {
 
 pub struct ServerSession {
     pub id: SessionId,
+    span: Span,
     username: Option<String>,
     session_handle: Option<russh::server::Handle>,
     pty_channels: Vec<Uuid>,
     all_channels: Vec<Uuid>,
     channel_recorders: HashMap<Uuid, TerminalRecorder>,
+    sftp_inspectors: HashMap<Uuid, SftpInspector>,
+    sftp_recorders: HashMap<Uuid, SftpRecorder>,
+    scp_inspectors: HashMap<Uuid, ScpInspector>,
     channel_map: BiMap<ServerChannelId, Uuid>,
     channel_pty_size_map: HashMap<Uuid, PtyRequest>,
     rc_tx: UnboundedSender<(RCCommand, Option<RCCommandReply>)>,
@@ -89,6 +139,25 @@ pub struct ServerSession {
     target: TargetSelection,
     traffic_recorders: HashMap<(String, u32), TrafficRecorder>,
     traffic_connection_recorders: HashMap<Uuid, ConnectionRecorder>,
+    forwarded_connections: HashMap<Uuid, ForwardedConnection>,
+    /// Channels running `warpgate-dropbox` or `warpgate-ticket`,
+    /// with the data of a pending `put`
+    local_channels: HashMap<Uuid, Option<DropBoxUpload>>,
+    /// Expected in requests from [DROP_BOX_HELPER]
+    drop_box_token: String,
+    /// Interactive shells where [DROP_BOX_HELPER] can be used
+    drop_box_shells: HashMap<Uuid, ShellDropBox>,
+    /// Shared with the russh handler so that input from session viewers
+    /// is subject to the same flow control as the client's own data
+    upload_windows: ChannelWindows,
+    /// Input from a session viewer that is waiting for window space
+    pending_control_input: Option<JoinHandle<()>>,
+    /// Shared with the session state for the admin API
+    channels: SessionChannels,
+    tap: SessionTap,
+    last_activity: SessionLastActivity,
+    /// Locale variables sent by the client, used for service messages
+    locale_env: HashMap<String, String>,
     hub: EventHub<Event>,
     event_sender: EventSender<Event>,
     main_event_subscription: EventSubscription<Event>,
@@ -96,9 +165,19 @@ pub struct ServerSession {
     channel_writer: ChannelWriter,
     auth_state: Option<Arc<Mutex<AuthState>>>,
     keyboard_interactive_state: KeyboardInteractiveState,
+    consent: ConsentState,
     cached_successful_ticket_auth: Option<CachedSuccessfulTicketAuth>,
+    startup_permit: Option<StartupPermit>,
+    client_ident: Arc<OnceLock<String>>,
 }
 
+const LOCALE_VARIABLES: &[&str] = &["LC_ALL", "LC_MESSAGES", "LANG"];
+/// Tags the session with a work item, e.g. `ssh -o SetEnv=WARPGATE_WORK_ITEM=INC-123`
+const WORK_ITEM_VARIABLE: &str = "WARPGATE_WORK_ITEM";
+
+/// Large drop-box replies are typed into the terminal in pieces
+const INJECTED_INPUT_CHUNK_SIZE: usize = 32 * 1024;
+
 fn session_debug_tag(id: &SessionId, remote_address: &SocketAddr) -> String {
     format!("[{id} - {remote_address}]")
 }
@@ -110,32 +189,52 @@ impl std::fmt::Debug for ServerSession {
 }
 
 impl ServerSession {
+    #[allow(clippy::too_many_arguments)]
     pub async fn start(
         remote_address: SocketAddr,
         services: &Services,
         server_handle: Arc<Mutex<WarpgateServerHandle>>,
         mut session_handle_rx: UnboundedReceiver<SessionHandleCommand>,
         mut handler_event_rx: UnboundedReceiver<ServerHandlerEvent>,
+        upload_windows: ChannelWindows,
+        startup_permit: StartupPermit,
+        client_ident: Arc<OnceLock<String>>,
     ) -> Result<impl Future<Output = Result<()>>> {
-        let id = server_handle.lock().await.id();
-
-        let _span = info_span!("SSH", session=%id);
+        let (id, span) = {
+            let handle = server_handle.lock().await;
+            (handle.id(), handle.context().span().clone())
+        };
+        let _span = span.clone();
         let _enter = _span.enter();
 
-        let mut rc_handles = RemoteClient::create(id, services.clone())?;
+        let rc_handles = RemoteClient::create(id, services.clone())?;
 
         let (hub, event_sender) = EventHub::setup();
         let main_event_subscription = hub
             .subscribe(|e| !matches!(e, Event::ConsoleInput(_)))
             .await;
 
+        let (channels, tap, last_activity) = {
+            let state = server_handle.lock().await.session_state().clone();
+            let state = state.lock().await;
+            (
+                state.channels.clone(),
+                state.tap.clone(),
+                state.last_activity.clone(),
+            )
+        };
+
         let mut this = Self {
             id,
+            span,
             username: None,
             session_handle: None,
             pty_channels: vec![],
             all_channels: vec![],
             channel_recorders: HashMap::new(),
+            sftp_inspectors: HashMap::new(),
+            sftp_recorders: HashMap::new(),
+            scp_inspectors: HashMap::new(),
             channel_map: BiMap::new(),
             channel_pty_size_map: HashMap::new(),
             rc_tx: rc_handles.command_tx.clone(),
@@ -147,6 +246,16 @@ impl ServerSession {
             target: TargetSelection::None,
             traffic_recorders: HashMap::new(),
             traffic_connection_recorders: HashMap::new(),
+            forwarded_connections: HashMap::new(),
+            local_channels: HashMap::new(),
+            drop_box_token: Uuid::new_v4().simple().to_string(),
+            drop_box_shells: HashMap::new(),
+            upload_windows,
+            pending_control_input: None,
+            channels,
+            tap,
+            last_activity,
+            locale_env: HashMap::new(),
             hub,
             event_sender: event_sender.clone(),
             main_event_subscription,
@@ -154,7 +263,10 @@ impl ServerSession {
             channel_writer: ChannelWriter::new(),
             auth_state: None,
             keyboard_interactive_state: KeyboardInteractiveState::None,
+            consent: ConsentState::NotRequested,
             cached_successful_ticket_auth: None,
+            startup_permit: Some(startup_permit),
+            client_ident,
         };
 
         let mut so_rx = this.service_output.subscribe();
@@ -189,17 +301,7 @@ impl ServerSession {
             }
         })?;
 
-        let name = format!("SSH {id} client events");
-        tokio::task::Builder::new().name(&name).spawn({
-            let sender = event_sender.clone();
-            async move {
-                while let Some(e) = rc_handles.event_rx.recv().await {
-                    if sender.send_once(Event::Client(e)).await.is_err() {
-                        break;
-                    }
-                }
-            }
-        })?;
+        this.forward_client_events(rc_handles.event_rx)?;
 
         let name = format!("SSH {id} server handler events");
         tokio::task::Builder::new().name(&name).spawn({
@@ -222,6 +324,24 @@ impl ServerSession {
         })
     }
 
+    fn forward_client_events(
+        &self,
+        mut event_rx: UnboundedReceiver<RCEvent>,
+    ) -> Result<(), std::io::Error> {
+        let name = format!("SSH {} client events", self.id);
+        tokio::task::Builder::new().name(&name).spawn({
+            let sender = self.event_sender.clone();
+            async move {
+                while let Some(e) = event_rx.recv().await {
+                    if sender.send_once(Event::Client(e)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        })?;
+        Ok(())
+    }
+
     async fn get_next_event(&mut self) -> Option<Event> {
         self.main_event_subscription.recv().await
     }
@@ -255,16 +375,6 @@ impl ServerSession {
         Ok(self.auth_state.as_ref().cloned().unwrap())
     }
 
-    pub fn make_logging_span(&self) -> tracing::Span {
-        let client_ip = self.remote_address.ip().to_string();
-        match self.username {
-            Some(ref username) => {
-                info_span!("SSH", session=%self.id, session_username=%username, %client_ip)
-            }
-            None => info_span!("SSH", session=%self.id, %client_ip),
-        }
-    }
-
     fn map_channel(&self, ch: &ServerChannelId) -> Result<Uuid, WarpgateError> {
         self.channel_map
             .get_by_left(ch)
@@ -272,6 +382,164 @@ impl ServerSession {
             .ok_or(WarpgateError::InconsistentState)
     }
 
+    fn container_options(&self) -> Option<&TargetDockerOptions> {
+        match &self.target {
+            TargetSelection::Found(target, _) => match &target.options {
+                TargetOptions::Docker(options) => Some(options),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Container, console and honeypot targets only offer terminal sessions
+    fn forwarding_disabled(&self) -> bool {
+        self.container_options().is_some()
+            || matches!(
+                self.target,
+                TargetSelection::Console(_) | TargetSelection::Honeypot
+            )
+    }
+
+    /// For container targets, shell and exec requests are rewritten into an
+    /// exec into the requested container on the host
+    fn session_operation(
+        &self,
+        channel_id: Uuid,
+        command: Option<&str>,
+    ) -> Result<ChannelOperation> {
+        if let (TargetSelection::Console(_), Some(_)) = (&self.target, command) {
+            anyhow::bail!("only interactive sessions are available on console targets");
+        }
+        let Some(options) = self.container_options() else {
+            return Ok(match command {
+                Some(command) => ChannelOperation::RequestExec(command.to_string()),
+                None => ChannelOperation::RequestShell,
+            });
+        };
+        let command =
+            container_exec_command(options, command, self.pty_channels.contains(&channel_id))?;
+        info!(channel=%channel_id, %command, "Container exec");
+        Ok(ChannelOperation::RequestExec(command))
+    }
+
+    fn ssh_options(&self) -> Option<&TargetSSHOptions> {
+        match &self.target {
+            TargetSelection::Found(target, _) => match &target.options {
+                TargetOptions::Ssh(options) => Some(options),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn record_target_capabilities(&self) {
+        let TargetSelection::Found(target, _) = &self.target else {
+            return;
+        };
+        let Some(options) = self
+            .ssh_options()
+            .or(self.container_options().map(|options| &options.ssh))
+        else {
+            return;
+        };
+        let host_key_algorithm = self
+            .services
+            .target_fingerprints
+            .get(&target.name)
+            .and_then(|x| x.algorithm);
+        self.services.target_capabilities.record(
+            &target.name,
+            negotiated_capabilities(options, host_key_algorithm),
+        );
+    }
+
+    fn close_forwarded_connection(&mut self, channel_id: &Uuid) {
+        if let Some(connection) = self.forwarded_connections.remove(channel_id) {
+            info!(
+                channel=%channel_id,
+                destination=%connection.destination,
+                duration=?connection.opened.elapsed(),
+                bytes_sent=connection.bytes_sent,
+                bytes_received=connection.bytes_received,
+                "Forwarded connection closed"
+            );
+        }
+    }
+
+    /// Looks up a forward preset of the selected target,
+    /// failing if the user isn't allowed to use it
+    async fn forward_preset(&self, name: &str) -> Result<Option<SshForwardPreset>> {
+        let Some(options) = self.ssh_options() else {
+            return Ok(None);
+        };
+        let Some(preset) = options.forward_presets.iter().find(|x| x.name == name) else {
+            return Ok(None);
+        };
+
+        if !preset.allow_roles.is_empty() && !preset.is_allowed_for(&self.user_roles().await?) {
+            anyhow::bail!("not allowed to use forward preset {name}");
+        }
+        Ok(Some(preset.clone()))
+    }
+
+    async fn user_roles(&self) -> Result<Vec<String>> {
+        Ok(match &self.username {
+            Some(username) => {
+                self.services
+                    .config_provider
+                    .lock()
+                    .await
+                    .list_user_roles(username)
+                    .await?
+            }
+            None => vec![],
+        })
+    }
+
+    /// Violations are logged to the session log
+    async fn check_forward_policy(&self, kind: &str, host: &str, port: u32) -> bool {
+        let Some(options) = self.ssh_options() else {
+            return true;
+        };
+        let user_roles = if options.role_forwarding_policies.is_empty() {
+            vec![]
+        } else {
+            match self.user_roles().await {
+                Ok(roles) => roles,
+                Err(error) => {
+                    warn!(%error, "Could not look up the user's roles for the forwarding policy");
+                    return false;
+                }
+            }
+        };
+        let allowed = is_forward_allowed(options, &user_roles, host, port);
+        if !allowed {
+            warn!(
+                kind,
+                address=%format!("{host}:{port}"),
+                "Port forward rejected by the forwarding policy"
+            );
+        }
+        allowed
+    }
+
+    async fn reject_request(&mut self, server_channel_id: ServerChannelId, error: anyhow::Error) {
+        warn!(channel=%server_channel_id.0, %error, "Rejected request");
+        if let Some(handle) = &self.session_handle {
+            let _ = handle
+                .extended_data(
+                    server_channel_id.0,
+                    1,
+                    CryptoVec::from(format!(
+                        "Warpgate: {}\r\n",
+                        ErrorCode::RequestRejected.annotate(error)
+                    )),
+                )
+                .await;
+        }
+    }
+
     fn map_channel_reverse(&self, ch: &Uuid) -> Result<ServerChannelId> {
         self.channel_map
             .get_by_right(ch)
@@ -279,6 +547,11 @@ impl ServerSession {
             .ok_or_else(|| anyhow::anyhow!("Channel not known"))
     }
 
+    fn language(&self) -> Language {
+        let get = |name| self.locale_env.get(name).map(String::as_str);
+        Language::from_locale(get("LC_ALL"), get("LC_MESSAGES"), get("LANG")).unwrap_or_default()
+    }
+
     pub async fn emit_service_message(&mut self, msg: &str) -> Result<()> {
         debug!("Service message: {}", msg);
 
@@ -310,16 +583,47 @@ impl ServerSession {
                 anyhow::bail!("Invalid session state (target not set)")
             }
             TargetSelection::NotFound(name) => {
-                self.emit_service_message(&format!("Selected target not found: {name}"))
-                    .await?;
+                self.emit_service_message(&ErrorCode::TargetNotFound.annotate(format!(
+                    "{}: {name}",
+                    Message::SelectedTargetNotFound.text(self.language())
+                )))
+                .await?;
                 self.disconnect_server().await;
                 anyhow::bail!("Target not found: {}", name);
             }
+            TargetSelection::Rejected(message) => {
+                self.emit_service_message(&message).await?;
+                self.disconnect_server().await;
+                anyhow::bail!(message);
+            }
+            TargetSelection::Unresolved(error) => {
+                self.emit_service_message(&ErrorCode::InvalidRequest.annotate(&error))
+                    .await?;
+                self.disconnect_server().await;
+                anyhow::bail!(error);
+            }
             TargetSelection::Found(target, ssh_options) => {
                 if self.rc_state == RCState::NotInitialized {
                     self.connect_remote(target, ssh_options).await?;
                 }
             }
+            TargetSelection::Console(target) => {
+                // The console is started by the shell request itself
+                if self.rc_state == RCState::NotInitialized {
+                    self.rc_state = RCState::Connecting;
+                    self.service_output.show_progress();
+                    self.emit_service_message(&format!(
+                        "{}: {}",
+                        Message::SelectedTarget.text(self.language()),
+                        target.name
+                    ))
+                    .await?;
+                }
+            }
+            TargetSelection::Honeypot => {
+                // No connection messages, these would give the decoy away
+                self.rc_state = RCState::Connected;
+            }
         }
         Ok(())
     }
@@ -330,11 +634,23 @@ impl ServerSession {
         ssh_options: TargetSSHOptions,
     ) -> Result<()> {
         self.rc_state = RCState::Connecting;
-        self.send_command(RCCommand::Connect(ssh_options))
-            .map_err(|_| anyhow::anyhow!("cannot send command"))?;
+        let multiplex_key = self
+            .username
+            .clone()
+            .map(|username| MultiplexKey::new(username, target.name.clone(), &ssh_options));
+        self.send_command(RCCommand::Connect(
+            ssh_options,
+            self.services.dns.for_target(&target),
+            multiplex_key,
+        ))
+        .map_err(|_| anyhow::anyhow!("cannot send command"))?;
         self.service_output.show_progress();
-        self.emit_service_message(&format!("Selected target: {}", target.name))
-            .await?;
+        self.emit_service_message(&format!(
+            "{}: {}",
+            Message::SelectedTarget.text(self.language()),
+            target.name
+        ))
+        .await?;
 
         Ok(())
     }
@@ -351,14 +667,14 @@ impl ServerSession {
                 }
                 Event::Client(e) => {
                     debug!(event=?e, "Event");
-                    let span = self.make_logging_span();
+                    let span = self.span.clone();
                     if let Err(err) = self.handle_remote_event(e).instrument(span).await {
                         error!("Client event handler error: {:?}", err);
                         // break;
                     }
                 }
                 Event::ServerHandler(e) => {
-                    let span = self.make_logging_span();
+                    let span = self.span.clone();
                     if let Err(err) = self.handle_server_handler_event(e).instrument(span).await {
                         error!("Server event handler error: {:?}", err);
                         // break;
@@ -383,8 +699,13 @@ impl ServerSession {
 
     async fn handle_server_handler_event(&mut self, event: ServerHandlerEvent) -> Result<()> {
         match event {
-            ServerHandlerEvent::Authenticated(handle) => {
+            ServerHandlerEvent::Authenticated(handle, client_version) => {
                 self.session_handle = Some(handle.0);
+                self.update_details(SshSessionDetails::ActiveModel {
+                    client_version: Set(Some(client_version)),
+                    ..Default::default()
+                })
+                .await;
             }
 
             ServerHandlerEvent::ChannelOpenSession(server_channel_id, reply) => {
@@ -398,6 +719,12 @@ impl ServerSession {
                 {
                     Ok(()) => {
                         self.all_channels.push(channel);
+                        self.channels.open(
+                            channel,
+                            server_channel_id.0.into(),
+                            SessionChannelKind::Session,
+                            None,
+                        );
                         let _ = reply.send(true);
                         Ok(())
                     }
@@ -409,7 +736,22 @@ impl ServerSession {
                 };
             }
 
+            ServerHandlerEvent::SubsystemRequest(server_channel_id, name, reply)
+                if name.starts_with(FORWARD_PRESET_SUBSYSTEM_PREFIX) =>
+            {
+                let preset = &name[FORWARD_PRESET_SUBSYSTEM_PREFIX.len()..];
+                let _ = reply.send(
+                    self._forward_preset_subsystem_request(server_channel_id, preset)
+                        .await?,
+                );
+            }
+
             ServerHandlerEvent::SubsystemRequest(server_channel_id, name, reply) => {
+                if self.forwarding_disabled() {
+                    warn!(%name, "Subsystems are not available for this target");
+                    let _ = reply.send(false);
+                    return Ok(());
+                }
                 return match self
                     ._channel_subsystem_request(server_channel_id, name)
                     .await
@@ -423,13 +765,22 @@ impl ServerSession {
                         Ok(())
                     }
                     Err(x) => Err(x.into()),
-                }
+                };
             }
 
             ServerHandlerEvent::PtyRequest(server_channel_id, request, reply) => {
                 let channel_id = self.map_channel(&server_channel_id)?;
                 self.channel_pty_size_map
                     .insert(channel_id, request.clone());
+                self.channels.set_pty(
+                    channel_id,
+                    &request.term,
+                    request.col_width,
+                    request.row_height,
+                );
+                self.tap
+                    .resize(channel_id, request.col_width, request.row_height);
+                self.update_pty_details(&request).await;
                 if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
                     if let Err(error) = recorder
                         .write_pty_resize(request.col_width, request.row_height)
@@ -456,12 +807,31 @@ impl ServerSession {
 
             ServerHandlerEvent::ShellRequest(server_channel_id, reply) => {
                 let channel_id = self.map_channel(&server_channel_id)?;
+                let operation = match self.session_operation(channel_id, None) {
+                    Ok(operation) => operation,
+                    Err(error) => {
+                        self.reject_request(server_channel_id, error).await;
+                        let _ = reply.send(false);
+                        return Ok(());
+                    }
+                };
                 let _ = self.maybe_connect_remote().await;
 
-                let _ = self.send_command(RCCommand::Channel(
-                    channel_id,
-                    ChannelOperation::RequestShell,
-                ));
+                if self.services.config.load().store.drop_box.enable
+                    && self.ssh_options().is_some()
+                    && self.pty_channels.contains(&channel_id)
+                {
+                    let _ = self.send_command(RCCommand::Channel(
+                        channel_id,
+                        ChannelOperation::RequestEnv(
+                            DROP_BOX_TOKEN_VARIABLE.into(),
+                            self.drop_box_token.clone(),
+                        ),
+                    ));
+                    self.drop_box_shells
+                        .insert(channel_id, ShellDropBox::new(self.drop_box_token.clone()));
+                }
+                let _ = self.send_command(RCCommand::Channel(channel_id, operation));
 
                 self.start_terminal_recording(
                     channel_id,
@@ -470,6 +840,7 @@ impl ServerSession {
                 .await;
 
                 info!(%channel_id, "Opening shell");
+                self.channels.set_description(channel_id, "shell".into());
 
                 let _ = self
                     .session_handle
@@ -482,7 +853,8 @@ impl ServerSession {
             }
 
             ServerHandlerEvent::AuthPublicKey(username, key, reply) => {
-                let _ = reply.send(self._auth_publickey(username, key).await);
+                let auth = self._auth_publickey(username, key).await;
+                let _ = reply.send(self.require_consent(auth));
             }
 
             ServerHandlerEvent::AuthPublicKeyOffer(username, key, reply) => {
@@ -490,20 +862,27 @@ impl ServerSession {
             }
 
             ServerHandlerEvent::AuthPassword(username, password, reply) => {
-                let _ = reply.send(self._auth_password(username, password).await);
+                let auth = self._auth_password(username, password).await;
+                let _ = reply.send(self.require_consent(auth));
             }
 
             ServerHandlerEvent::AuthKeyboardInteractive(username, response, reply) => {
-                let _ = reply.send(self._auth_keyboard_interactive(username, response).await);
+                let auth =
+                    if self.consent != ConsentState::Accepted && self.consent_banner().is_some() {
+                        self._auth_consent(username, response).await
+                    } else {
+                        self._auth_keyboard_interactive(username, response).await
+                    };
+                let _ = reply.send(self.require_consent(auth));
             }
 
-            ServerHandlerEvent::Data(channel, data, reply) => {
-                self._data(channel, data).await?;
+            ServerHandlerEvent::Data(channel, data, permit, reply) => {
+                self._data(channel, data, permit).await?;
                 let _ = reply.send(());
             }
 
-            ServerHandlerEvent::ExtendedData(channel, data, code, reply) => {
-                self._extended_data(channel, code, data).await?;
+            ServerHandlerEvent::ExtendedData(channel, data, code, permit, reply) => {
+                self._extended_data(channel, code, data, permit).await?;
                 let _ = reply.send(());
             }
 
@@ -528,8 +907,14 @@ impl ServerSession {
             }
 
             ServerHandlerEvent::ExecRequest(channel, data, reply) => {
-                self._channel_exec_request(channel, data).await?;
-                let _ = reply.send(true);
+                let _ = reply.send(self._channel_exec_request(channel, data).await?);
+            }
+
+            ServerHandlerEvent::ChannelOpenDirectTcpIp(_, _, reply)
+                if self.forwarding_disabled() =>
+            {
+                warn!("Port forwarding is not available for this target");
+                let _ = reply.send(false);
             }
 
             ServerHandlerEvent::ChannelOpenDirectTcpIp(channel, params, reply) => {
@@ -541,14 +926,23 @@ impl ServerSession {
                 let _ = reply.send(());
             }
 
+            ServerHandlerEvent::X11Request(_, _, reply) if self.forwarding_disabled() => {
+                warn!("X11 forwarding is not available for this target");
+                let _ = reply.send(());
+            }
+
             ServerHandlerEvent::X11Request(channel, request, reply) => {
                 self._channel_x11_request(channel, request).await?;
                 let _ = reply.send(());
             }
 
+            ServerHandlerEvent::TcpIpForward(_, _, reply) if self.forwarding_disabled() => {
+                warn!("Port forwarding is not available for this target");
+                let _ = reply.send(false);
+            }
+
             ServerHandlerEvent::TcpIpForward(address, port, reply) => {
-                self._tcpip_forward(address, port).await?;
-                let _ = reply.send(true);
+                let _ = reply.send(self._tcpip_forward(address, port).await?);
             }
 
             ServerHandlerEvent::CancelTcpIpForward(address, port, reply) => {
@@ -564,12 +958,49 @@ impl ServerSession {
 
     pub async fn handle_session_control(&mut self, command: SessionHandleCommand) -> Result<()> {
         match command {
-            SessionHandleCommand::Close => {
-                let _ = self.emit_service_message("Session closed by admin").await;
+            SessionHandleCommand::Close(message) => {
+                let mut text = ErrorCode::SessionClosedByAdmin
+                    .annotate(Message::SessionClosedByAdmin.text(self.language()));
+                if let Some(message) = message {
+                    text = format!("{text}\n{message}");
+                }
+                let _ = self.emit_service_message(&text).await;
                 info!("Session closed by admin");
                 self.request_disconnect().await;
                 self.disconnect_server().await;
             }
+            SessionHandleCommand::Input { recording_id, data } => {
+                let Some(channel_id) = self
+                    .channel_recorders
+                    .iter()
+                    .find(|(_, recorder)| recorder.id() == recording_id)
+                    .map(|(channel_id, _)| *channel_id)
+                else {
+                    return Ok(());
+                };
+                if !self.pty_channels.contains(&channel_id) {
+                    return Ok(());
+                }
+                if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
+                    if let Err(error) = recorder
+                        .write(TerminalRecordingStreamId::Input, &data)
+                        .await
+                    {
+                        error!(channel=%channel_id, ?error, "Failed to record terminal data");
+                        self.channel_recorders.remove(&channel_id);
+                    }
+                }
+                self.channels.record_sent(channel_id, data.len());
+                self.last_activity.touch();
+                self.inject_input(channel_id, data);
+            }
+            SessionHandleCommand::RecordingMarker(label) => {
+                self.write_recording_marker(None, label).await;
+            }
+            SessionHandleCommand::Notify(message) => {
+                info!(%message, "Notifying the user");
+                let _ = self.emit_service_message(&message).await;
+            }
         }
         Ok(())
     }
@@ -586,8 +1017,11 @@ impl ServerSession {
                             ERASE_PROGRESS_SPINNER,
                             Colour::Black
                                 .on(Colour::Green)
-                                .paint(" ✓ Warpgate connected ")
+                                .paint(format!(" ✓ {} ", Message::Connected.text(self.language())))
                         )));
+                        self.write_recording_marker(None, "Connected to target".into())
+                            .await;
+                        self.record_target_capabilities();
                     }
                     RCState::Disconnected => {
                         self.service_output.hide_progress().await;
@@ -598,6 +1032,11 @@ impl ServerSession {
             }
             RCEvent::ConnectionError(error) => {
                 self.service_output.hide_progress().await;
+                self.server_handle
+                    .lock()
+                    .await
+                    .set_termination_reason(SessionTerminationReason::TargetError)
+                    .await;
 
                 match error {
                     ConnectionError::HostKeyMismatch {
@@ -606,43 +1045,77 @@ impl ServerSession {
                         known_key_type,
                         known_key_base64,
                     } => {
+                        let language = self.language();
                         let msg = format!(
-                            concat!(
-                                "Host key doesn't match the stored one.\n",
-                                "Stored key   ({}): {}\n",
-                                "Received key ({}): {}",
-                            ),
+                            "{}\nStored key   ({}): {}\nReceived key ({}): {}",
+                            ErrorCode::TargetHostKeyMismatch
+                                .annotate(Message::HostKeyMismatch.text(language)),
                             known_key_type,
                             known_key_base64,
                             received_key_type,
                             received_key_base64
                         );
                         self.emit_service_message(&msg).await?;
-                        self.emit_service_message(
-                            "If you know that the key is correct (e.g. it has been changed),",
-                        )
-                        .await?;
-                        self.emit_service_message(
-                        "you can remove the old key in the Warpgate management UI and try again",
-                    )
-                    .await?;
+                        for line in Message::HostKeyMismatchHint.text(language).lines() {
+                            self.emit_service_message(line).await?;
+                        }
                     }
                     error => {
                         self.service_output.emit_output(Bytes::from(format!(
                             "{}{} {}\r\n",
                             ERASE_PROGRESS_SPINNER,
-                            Colour::Black.on(Colour::Red).paint(" Connection failed "),
-                            error
+                            Colour::Black.on(Colour::Red).paint(format!(
+                                " {} ",
+                                Message::ConnectionFailed.text(self.language())
+                            )),
+                            ErrorCode::TargetConnectionFailed.annotate(error)
                         )));
                     }
                 }
             }
+            RCEvent::Reconnecting { attempt, attempts } => {
+                if attempt == 1 {
+                    self.service_output.hide_progress().await;
+                    self.emit_service_message(Message::ConnectionLost.text(self.language()))
+                        .await?;
+                }
+                self.service_output.show_progress();
+                self.emit_service_message(&format!(
+                    "{} ({attempt}/{attempts})",
+                    Message::Reconnecting.text(self.language())
+                ))
+                .await?;
+            }
             RCEvent::Error(e) => {
                 self.service_output.hide_progress().await;
-                let _ = self.emit_service_message(&format!("Error: {e}")).await;
+                self.server_handle
+                    .lock()
+                    .await
+                    .set_termination_reason(SessionTerminationReason::TargetError)
+                    .await;
+                let _ = self
+                    .emit_service_message(&ErrorCode::TargetError.annotate(format!("Error: {e}")))
+                    .await;
                 self.disconnect_server().await;
             }
-            RCEvent::Output(channel, data) => {
+            RCEvent::Output(channel, data, _permit) => {
+                self.channels.record_received(channel, data.len());
+                let data = match self.drop_box_shells.get_mut(&channel) {
+                    Some(shell) => {
+                        let (output, requests) = shell.scanner.scan(&data);
+                        for request in requests {
+                            self._drop_box_in_band_request(channel, request).await;
+                        }
+                        if output.is_empty() {
+                            return Ok(());
+                        }
+                        Bytes::from(output)
+                    }
+                    None => data,
+                };
+                if self.pty_channels.contains(&channel) {
+                    self.tap.output(channel, &data);
+                }
                 if let Some(recorder) = self.channel_recorders.get_mut(&channel) {
                     if let Err(error) = recorder
                         .write(TerminalRecordingStreamId::Output, &data)
@@ -653,6 +1126,19 @@ impl ServerSession {
                     }
                 }
 
+                if let Some(inspector) = self.sftp_inspectors.get_mut(&channel) {
+                    let events = inspector.server_data(&data);
+                    self.record_sftp_events(channel, events).await;
+                }
+                if let Some(inspector) = self.scp_inspectors.get_mut(&channel) {
+                    let files = inspector.server_data(&data);
+                    self.log_scp_files(channel, files);
+                }
+
+                if let Some(connection) = self.forwarded_connections.get_mut(&channel) {
+                    connection.bytes_received += data.len() as u64;
+                }
+
                 if let Some(recorder) = self.traffic_connection_recorders.get_mut(&channel) {
                     if let Err(error) = recorder.write_rx(&data).await {
                         error!(%channel, ?error, "Failed to record traffic data");
@@ -660,6 +1146,8 @@ impl ServerSession {
                     }
                 }
 
+                // The permit is held until the client has accepted the data,
+                // which keeps the target from sending more than the window
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 if let Some(session) = self.session_handle.as_mut() {
                     let _ = session
@@ -688,7 +1176,21 @@ impl ServerSession {
                 .await?;
             }
             RCEvent::Close(channel) => {
-                let server_channel_id = self.map_channel_reverse(&channel)?;
+                self.channels.close(channel);
+                self.tap.close(channel);
+                self.drop_box_shells.remove(&channel);
+                if let Some(mut inspector) = self.sftp_inspectors.remove(&channel) {
+                    let events = inspector.finish();
+                    self.record_sftp_events(channel, events).await;
+                }
+                self.sftp_recorders.remove(&channel);
+                self.scp_inspectors.remove(&channel);
+                self.close_forwarded_connection(&channel);
+                // Session channels replaced by a forward preset are closed on the target only
+                let Ok(server_channel_id) = self.map_channel_reverse(&channel) else {
+                    debug!(%channel, "Closed a channel that is no longer mapped");
+                    return Ok(());
+                };
                 let _ = self
                     .maybe_with_session(|handle| async move {
                         handle
@@ -699,6 +1201,7 @@ impl ServerSession {
                     .await;
             }
             RCEvent::Eof(channel) => {
+                self.channels.target_eof(channel);
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 self.maybe_with_session(|handle| async move {
                     handle
@@ -709,6 +1212,11 @@ impl ServerSession {
                 .await?;
             }
             RCEvent::ExitStatus(channel, code) => {
+                self.server_handle
+                    .lock()
+                    .await
+                    .set_exit_code(code.into())
+                    .await;
                 let server_channel_id = self.map_channel_reverse(&channel)?;
                 self.maybe_with_session(|handle| async move {
                     handle
@@ -742,7 +1250,16 @@ impl ServerSession {
                 .await?;
             }
             RCEvent::Done => {}
-            RCEvent::ExtendedData { channel, data, ext } => {
+            RCEvent::ExtendedData {
+                channel,
+                data,
+                ext,
+                permit: _permit,
+            } => {
+                self.channels.record_received(channel, data.len());
+                if self.pty_channels.contains(&channel) {
+                    self.tap.output(channel, &data);
+                }
                 if let Some(recorder) = self.channel_recorders.get_mut(&channel) {
                     if let Err(error) = recorder
                         .write(TerminalRecordingStreamId::Error, &data)
@@ -770,12 +1287,50 @@ impl ServerSession {
                     key.public_key_base64()
                 ))
                 .await?;
+                let fingerprint = TargetFingerprint::ssh_host_key(
+                    key.algorithm().as_ref(),
+                    &key.public_key_base64(),
+                );
+                let ssh_options = self
+                    .ssh_options()
+                    .or(self.container_options().map(|options| &options.ssh))
+                    .cloned();
+                if let (TargetSelection::Found(target, _), Some(fingerprint), Some(options)) =
+                    (&self.target, fingerprint, ssh_options)
+                {
+                    self.services
+                        .target_fingerprints
+                        .record(&target.name, fingerprint.clone());
+                    let observation = TargetObservation::ssh(
+                        &target.name,
+                        &options.host,
+                        options.port,
+                        &fingerprint,
+                    );
+                    if let Err(error) = check_target_baseline(&self.services, observation).await {
+                        error!(%error, "Failed to check the target baseline");
+                    }
+                    if options.show_fingerprint {
+                        self.emit_service_message(&format!(
+                            "Host key fingerprint: {}",
+                            fingerprint.fingerprint
+                        ))
+                        .await?;
+                    }
+                }
             }
             RCEvent::HostKeyUnknown(key, reply) => {
                 self.handle_unknown_host_key(key, reply).await?;
             }
             RCEvent::ForwardedTcpIp(id, params) => {
                 if let Some(session) = &mut self.session_handle {
+                    let description = format!(
+                        "{}:{} from {}:{}",
+                        params.connected_address,
+                        params.connected_port,
+                        params.originator_address,
+                        params.originator_port
+                    );
                     let server_channel = session
                         .channel_open_forwarded_tcpip(
                             params.connected_address,
@@ -788,6 +1343,12 @@ impl ServerSession {
                     self.channel_map
                         .insert(ServerChannelId(server_channel.id()), id);
                     self.all_channels.push(id);
+                    self.channels.open(
+                        id,
+                        server_channel.id().into(),
+                        SessionChannelKind::ForwardedTcpIp,
+                        Some(description),
+                    );
 
                     let recorder = self
                         .traffic_recorder_for(
@@ -813,6 +1374,7 @@ impl ServerSession {
             }
             RCEvent::X11(id, originator_address, originator_port) => {
                 if let Some(session) = &mut self.session_handle {
+                    let description = format!("{originator_address}:{originator_port}");
                     let server_channel = session
                         .channel_open_x11(originator_address, originator_port)
                         .await?;
@@ -820,6 +1382,12 @@ impl ServerSession {
                     self.channel_map
                         .insert(ServerChannelId(server_channel.id()), id);
                     self.all_channels.push(id);
+                    self.channels.open(
+                        id,
+                        server_channel.id().into(),
+                        SessionChannelKind::X11,
+                        Some(description),
+                    );
                 }
             }
         }
@@ -833,14 +1401,7 @@ impl ServerSession {
     ) -> Result<()> {
         self.service_output.hide_progress().await;
 
-        let mode = self
-            .services
-            .config
-            .lock()
-            .await
-            .store
-            .ssh
-            .host_key_verification;
+        let mode = self.services.config.load().store.ssh.host_key_verification;
 
         if mode == SshHostKeyVerificationMode::AutoAccept {
             let _ = reply.send(true);
@@ -912,8 +1473,32 @@ impl ServerSession {
     async fn _channel_open_direct_tcpip(
         &mut self,
         channel: ServerChannelId,
-        params: DirectTCPIPParams,
+        mut params: DirectTCPIPParams,
     ) -> Result<bool> {
+        match self.forward_preset(&params.host_to_connect).await {
+            Ok(Some(preset)) => {
+                info!(%channel, preset=%preset.name, "Using forward preset");
+                params.host_to_connect = preset.host;
+                params.port_to_connect = preset.port.into();
+            }
+            Ok(None) => {
+                if !self
+                    .check_forward_policy(
+                        "direct-tcpip",
+                        &params.host_to_connect,
+                        params.port_to_connect,
+                    )
+                    .await
+                {
+                    return Ok(false);
+                }
+            }
+            Err(error) => {
+                warn!(%channel, %error, "Rejected direct TCP/IP channel");
+                return Ok(false);
+            }
+        }
+
         let uuid = Uuid::new_v4();
         self.channel_map.insert(channel, uuid);
 
@@ -930,28 +1515,20 @@ impl ServerSession {
         {
             Ok(()) => {
                 self.all_channels.push(uuid);
-
-                let recorder = self
-                    .traffic_recorder_for(
-                        &params.host_to_connect,
-                        params.port_to_connect,
-                        "direct-tcpip",
-                    )
-                    .await;
-                if let Some(recorder) = recorder {
-                    #[allow(clippy::unwrap_used)]
-                    let mut recorder = recorder.connection(TrafficConnectionParams {
-                        dst_addr: Ipv4Addr::from_str("2.2.2.2").unwrap(),
-                        dst_port: params.port_to_connect as u16,
-                        src_addr: Ipv4Addr::from_str("1.1.1.1").unwrap(),
-                        src_port: params.originator_port as u16,
-                    });
-                    if let Err(error) = recorder.write_connection_setup().await {
-                        error!(%channel, ?error, "Failed to record connection setup");
-                    }
-                    self.traffic_connection_recorders.insert(uuid, recorder);
-                }
-
+                self.channels.open(
+                    uuid,
+                    channel.0.into(),
+                    SessionChannelKind::DirectTcpIp,
+                    Some(format!(
+                        "{}:{}",
+                        params.host_to_connect, params.port_to_connect
+                    )),
+                );
+                self.forwarded_connections.insert(
+                    uuid,
+                    ForwardedConnection::new(&params.host_to_connect, params.port_to_connect),
+                );
+                self.start_traffic_recording(uuid, &params).await;
                 Ok(true)
             }
             Err(SshClientError::Russh(russh::Error::ChannelOpenFailure(_))) => Ok(false),
@@ -959,6 +1536,99 @@ impl ServerSession {
         }
     }
 
+    async fn start_traffic_recording(&mut self, channel_id: Uuid, params: &DirectTCPIPParams) {
+        let recorder = self
+            .traffic_recorder_for(
+                &params.host_to_connect,
+                params.port_to_connect,
+                "direct-tcpip",
+            )
+            .await;
+        if let Some(recorder) = recorder {
+            #[allow(clippy::unwrap_used)]
+            let mut recorder = recorder.connection(TrafficConnectionParams {
+                dst_addr: Ipv4Addr::from_str("2.2.2.2").unwrap(),
+                dst_port: params.port_to_connect as u16,
+                src_addr: Ipv4Addr::from_str("1.1.1.1").unwrap(),
+                src_port: params.originator_port as u16,
+            });
+            if let Err(error) = recorder.write_connection_setup().await {
+                error!(channel=%channel_id, ?error, "Failed to record connection setup");
+            }
+            self.traffic_connection_recorders
+                .insert(channel_id, recorder);
+        }
+    }
+
+    /// Connects a session channel to a forward preset. The target side
+    /// session channel is replaced with a direct TCP/IP channel.
+    async fn _forward_preset_subsystem_request(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        name: &str,
+    ) -> Result<bool> {
+        let session_channel_id = self.map_channel(&server_channel_id)?;
+        let preset = match self.forward_preset(name).await {
+            Ok(Some(preset)) => preset,
+            Ok(None) => {
+                let error = anyhow::anyhow!("unknown forward preset {name}");
+                self.reject_request(server_channel_id, error).await;
+                return Ok(false);
+            }
+            Err(error) => {
+                self.reject_request(server_channel_id, error).await;
+                return Ok(false);
+            }
+        };
+
+        info!(channel=%session_channel_id, preset=%preset.name, "Opening forward preset");
+        let _ = self.maybe_connect_remote().await;
+
+        let params = DirectTCPIPParams {
+            host_to_connect: preset.host,
+            port_to_connect: preset.port.into(),
+            originator_address: self.remote_address.ip().to_string(),
+            originator_port: self.remote_address.port().into(),
+        };
+        let uuid = Uuid::new_v4();
+        match self
+            .send_command_and_wait(RCCommand::Channel(
+                uuid,
+                ChannelOperation::OpenDirectTCPIP(params.clone()),
+            ))
+            .await
+        {
+            Ok(()) => (),
+            Err(SshClientError::Russh(russh::Error::ChannelOpenFailure(_))) => return Ok(false),
+            Err(x) => return Err(x.into()),
+        }
+
+        self.channel_map.insert(server_channel_id, uuid);
+        self.all_channels.push(uuid);
+        self.channels.close(session_channel_id);
+        self.tap.close(session_channel_id);
+        self.channels.open(
+            uuid,
+            server_channel_id.0.into(),
+            SessionChannelKind::DirectTcpIp,
+            Some(format!(
+                "{}:{} (preset {})",
+                params.host_to_connect, params.port_to_connect, preset.name
+            )),
+        );
+        self.forwarded_connections.insert(
+            uuid,
+            ForwardedConnection::new(&params.host_to_connect, params.port_to_connect),
+        );
+        self.start_traffic_recording(uuid, &params).await;
+        self.send_command_and_wait(RCCommand::Channel(
+            session_channel_id,
+            ChannelOperation::Close,
+        ))
+        .await?;
+        Ok(true)
+    }
+
     async fn _window_change_request(
         &mut self,
         server_channel_id: ServerChannelId,
@@ -967,7 +1637,16 @@ impl ServerSession {
         let channel_id = self.map_channel(&server_channel_id)?;
         self.channel_pty_size_map
             .insert(channel_id, request.clone());
-        if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
+        self.channels.set_pty(
+            channel_id,
+            &request.term,
+            request.col_width,
+            request.row_height,
+        );
+        self.tap
+            .resize(channel_id, request.col_width, request.row_height);
+        self.update_pty_details(&request).await;
+        if let Some(recorder) = self.channel_recorders.get_mut(&channel_id) {
             if let Err(error) = recorder
                 .write_pty_resize(request.col_width, request.row_height)
                 .await
@@ -976,6 +1655,11 @@ impl ServerSession {
                 self.channel_recorders.remove(&channel_id);
             }
         }
+        self.write_recording_marker(
+            Some(channel_id),
+            format!("Resized to {}x{}", request.col_width, request.row_height),
+        )
+        .await;
         self.send_command_and_wait(RCCommand::Channel(
             channel_id,
             ChannelOperation::ResizePty(request),
@@ -988,7 +1672,7 @@ impl ServerSession {
         &mut self,
         server_channel_id: ServerChannelId,
         data: Bytes,
-    ) -> Result<()> {
+    ) -> Result<bool> {
         let channel_id = self.map_channel(&server_channel_id)?;
         match std::str::from_utf8(&data) {
             Err(e) => {
@@ -996,20 +1680,520 @@ impl ServerSession {
                 anyhow::bail!(e)
             }
             Ok::<&str, _>(command) => {
-                debug!(channel=%channel_id, %command, "Requested exec");
+                if let Some(command) = TicketCommand::parse(command) {
+                    return self._ticket_exec_request(server_channel_id, command).await;
+                }
+                if self.services.config.load().store.drop_box.enable {
+                    if let Some(command) = DropBoxCommand::parse(command) {
+                        return self
+                            ._drop_box_exec_request(server_channel_id, command)
+                            .await;
+                    }
+                }
+                info!(channel=%channel_id, %command, "Requested exec");
+                self.update_details(SshSessionDetails::ActiveModel {
+                    command: Set(Some(command.to_owned())),
+                    ..Default::default()
+                })
+                .await;
+                self.channels
+                    .set_description(channel_id, command.to_owned());
+                let scp_command = ScpCommand::parse(command);
+                let operation = match scp_command
+                    .as_ref()
+                    .map_or(Ok(()), |scp_command| self.check_scp_policy(scp_command))
+                    .and_then(|_| self.session_operation(channel_id, Some(command)))
+                {
+                    Ok(operation) => operation,
+                    Err(error) => {
+                        self.reject_request(server_channel_id, error).await;
+                        return Ok(false);
+                    }
+                };
+                if let Some(scp_command) = scp_command {
+                    self.scp_inspectors
+                        .insert(channel_id, ScpInspector::new(scp_command));
+                }
                 let _ = self.maybe_connect_remote().await;
+                let _ = self.send_command(RCCommand::Channel(channel_id, operation));
+            }
+        }
+
+        self.start_terminal_recording(channel_id, format!("exec-channel-{}", server_channel_id.0))
+            .await;
+        self.write_recording_marker(
+            Some(channel_id),
+            format!("Exec: {}", String::from_utf8_lossy(&data)),
+        )
+        .await;
+        Ok(true)
+    }
+
+    /// Runs `warpgate-dropbox` on Warpgate itself. The target side session
+    /// channel is closed, so nothing is executed on the target.
+    async fn _drop_box_exec_request(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        command: DropBoxCommand,
+    ) -> Result<bool> {
+        let channel_id = self.open_local_channel(server_channel_id, DROP_BOX_COMMAND)?;
+        let (stdout, stderr, code) = match command {
+            DropBoxCommand::List => (self.drop_box_list().await, None, 0),
+            DropBoxCommand::Get(name) => match self.drop_box_get(channel_id, &name).await {
+                Ok(data) => (data, None, 0),
+                Err(error) => (
+                    Bytes::new(),
+                    Some(format!("warpgate-dropbox: {error}\n")),
+                    1,
+                ),
+            },
+            DropBoxCommand::Put(name) => match self.drop_box_start_upload(name).await {
+                Ok(upload) => {
+                    self.local_channels.insert(channel_id, Some(upload));
+                    return Ok(true);
+                }
+                Err(error) => (
+                    Bytes::new(),
+                    Some(format!("warpgate-dropbox: {error}\n")),
+                    1,
+                ),
+            },
+            DropBoxCommand::Helper => (Bytes::from_static(DROP_BOX_HELPER.as_bytes()), None, 0),
+            DropBoxCommand::Help => (Bytes::from_static(DROP_BOX_USAGE.as_bytes()), None, 0),
+            DropBoxCommand::Invalid => (Bytes::new(), Some(DROP_BOX_USAGE.to_owned()), 2),
+        };
+
+        self.local_channels.insert(channel_id, None);
+        self.finish_local_channel(server_channel_id, stdout, stderr, code)
+            .await?;
+        Ok(true)
+    }
+
+    /// Detaches the session channel from the target so that
+    /// Warpgate can run a command of its own on it
+    fn open_local_channel(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        command: &str,
+    ) -> Result<Uuid> {
+        let session_channel_id = self.map_channel(&server_channel_id)?;
+        let channel_id = Uuid::new_v4();
+        self.channel_map.insert(server_channel_id, channel_id);
+        let _ = self.send_command(RCCommand::Channel(
+            session_channel_id,
+            ChannelOperation::Close,
+        ));
+        self.channels.close(session_channel_id);
+        self.tap.close(session_channel_id);
+        self.channels.open(
+            channel_id,
+            server_channel_id.0.into(),
+            SessionChannelKind::Session,
+            Some(command.into()),
+        );
+        Ok(channel_id)
+    }
+
+    /// Runs `warpgate-ticket` on Warpgate itself
+    async fn _ticket_exec_request(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        command: TicketCommand,
+    ) -> Result<bool> {
+        let channel_id = self.open_local_channel(server_channel_id, TICKET_COMMAND)?;
+        self.local_channels.insert(channel_id, None);
+
+        let (stdout, stderr, code) = match (command, self.username.clone()) {
+            (TicketCommand::Help, _) => (TICKET_USAGE.to_owned(), None, 0),
+            (TicketCommand::Invalid, _) => (String::new(), Some(TICKET_USAGE.to_owned()), 2),
+            // Tickets can't be used to issue further tickets
+            _ if self.cached_successful_ticket_auth.is_some() => (
+                String::new(),
+                Some("warpgate-ticket: not available in sessions opened with a ticket\n".into()),
+                1,
+            ),
+            (_, None) => anyhow::bail!("Invalid session state (not authenticated)"),
+            (command, Some(username)) => match self.run_ticket_command(&username, command).await? {
+                Ok(stdout) => (stdout, None, 0),
+                Err(error) => (
+                    String::new(),
+                    Some(format!("warpgate-ticket: {error}\n")),
+                    1,
+                ),
+            },
+        };
+
+        self.finish_local_channel(server_channel_id, Bytes::from(stdout), stderr, code)
+            .await?;
+        Ok(true)
+    }
+
+    /// Returns the command's output, or an error to show to the user
+    async fn run_ticket_command(
+        &self,
+        username: &str,
+        command: TicketCommand,
+    ) -> Result<Result<String, String>> {
+        let format_expiry = |expiry: Option<chrono::DateTime<chrono::Utc>>| {
+            expiry
+                .map(|x| x.to_rfc3339())
+                .unwrap_or_else(|| "never".into())
+        };
+        Ok(match command {
+            TicketCommand::List => {
+                let mut listing = String::new();
+                for ticket in list_user_tickets(&self.services, username).await? {
+                    let _ = writeln!(
+                        listing,
+                        "{}\t{}\t{}\t{}",
+                        ticket.id,
+                        ticket.target,
+                        ticket
+                            .uses_left
+                            .map(|x| x.to_string())
+                            .unwrap_or_else(|| "unlimited".into()),
+                        format_expiry(ticket.expiry),
+                    );
+                }
+                Ok(listing)
+            }
+            TicketCommand::New {
+                target,
+                uses,
+                valid_for,
+            } => {
+                let Ok(valid_for) = valid_for.map(chrono::Duration::from_std).transpose() else {
+                    return Ok(Err("invalid --valid-for".into()));
+                };
+                let result = issue_self_service_ticket(
+                    &self.services,
+                    username,
+                    SelfServiceTicketRequest {
+                        target_name: target,
+                        expiry: valid_for.map(|x| chrono::Utc::now() + x),
+                        number_of_uses: uses,
+                    },
+                    |_| true,
+                    |_| true,
+                )
+                .await;
+                match result {
+                    Ok((ticket, secret)) => Ok(format!(
+                        "id: {}\ntarget: {}\nexpires: {}\nsecret: {}\n",
+                        ticket.id,
+                        ticket.target,
+                        format_expiry(ticket.expiry),
+                        secret.expose_secret()
+                    )),
+                    Err(SelfServiceTicketError::Warpgate(error)) => return Err(error.into()),
+                    Err(error) => Err(error.to_string()),
+                }
+            }
+            TicketCommand::Revoke(id) => {
+                if revoke_user_ticket(&self.services, username, id).await? {
+                    Ok(String::new())
+                } else {
+                    Err(format!("{id}: no such ticket"))
+                }
+            }
+            TicketCommand::Help | TicketCommand::Invalid => Ok(TICKET_USAGE.to_owned()),
+        })
+    }
+
+    async fn _drop_box_data(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        channel_id: Uuid,
+        data: &[u8],
+    ) -> Result<()> {
+        let Some(Some(upload)) = self.local_channels.get_mut(&channel_id) else {
+            return Ok(());
+        };
+        let config = self.services.config.load().store.drop_box.clone();
+        if let Err(error) = upload.append(data, &config) {
+            warn!(channel=%channel_id, item=%upload.name, %error, "Failed to receive a drop-box item");
+            self.local_channels.insert(channel_id, None);
+            self.finish_local_channel(
+                server_channel_id,
+                Bytes::new(),
+                Some(format!("warpgate-dropbox: {error}\n")),
+                1,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Stores a `warpgate-dropbox put` item once the client has sent all data
+    async fn _drop_box_eof(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        channel_id: Uuid,
+    ) -> Result<()> {
+        let Some(Some(upload)) = self.local_channels.insert(channel_id, None) else {
+            return Ok(());
+        };
+        let (stderr, code) = match self.drop_box_finish_upload(channel_id, upload).await {
+            Ok(()) => (None, 0),
+            Err(error) => (Some(format!("warpgate-dropbox: {error}\n")), 1),
+        };
+        self.finish_local_channel(server_channel_id, Bytes::new(), stderr, code)
+            .await
+    }
+
+    /// Handles a request that [DROP_BOX_HELPER] wrote to a shell's terminal.
+    /// The reply is typed into the terminal, where the helper is waiting for it.
+    async fn _drop_box_in_band_request(&mut self, channel_id: Uuid, request: InBandRequest) {
+        let reply = match request {
+            InBandRequest::List => Ok(self.drop_box_list().await),
+            InBandRequest::Get(name) => self.drop_box_get(channel_id, &name).await,
+            InBandRequest::Put(name) => {
+                let upload = self.drop_box_start_upload(name).await;
+                if let Some(shell) = self.drop_box_shells.get_mut(&channel_id) {
+                    shell.upload = Some(upload);
+                }
+                return;
+            }
+            InBandRequest::Data(data) => {
+                let config = self.services.config.load().store.drop_box.clone();
+                let Some(shell) = self.drop_box_shells.get_mut(&channel_id) else {
+                    return;
+                };
+                if let Some(Ok(upload)) = &mut shell.upload {
+                    if let Err(error) = upload.append(&data, &config) {
+                        warn!(channel=%channel_id, item=%upload.name, %error, "Failed to receive a drop-box item");
+                        shell.upload = Some(Err(error));
+                    }
+                }
+                return;
+            }
+            InBandRequest::End => {
+                let upload = self
+                    .drop_box_shells
+                    .get_mut(&channel_id)
+                    .and_then(|shell| shell.upload.take());
+                match upload {
+                    Some(Ok(upload)) => self
+                        .drop_box_finish_upload(channel_id, upload)
+                        .await
+                        .map(|()| Bytes::new()),
+                    Some(Err(error)) => Err(error.to_string()),
+                    None => Err("no item is being staged".into()),
+                }
+            }
+            InBandRequest::Invalid => Err("invalid request".into()),
+            InBandRequest::Unauthorized => {
+                warn!(channel=%channel_id, "Ignoring a drop-box request with an invalid token");
+                return;
+            }
+        };
+
+        let reply = in_band_reply(reply.as_deref().map_err(ToOwned::to_owned));
+        for chunk in Bytes::from(reply).chunks(INJECTED_INPUT_CHUNK_SIZE) {
+            self.inject_input(channel_id, Bytes::copy_from_slice(chunk));
+        }
+    }
+
+    async fn drop_box_list(&self) -> Bytes {
+        let config = self.services.config.load().store.drop_box.clone();
+        let session_state = self.server_handle.lock().await.session_state().clone();
+        let items = session_state.lock().await.drop_box.list(&config);
+        let mut listing = String::new();
+        for item in items {
+            let _ = writeln!(
+                listing,
+                "{}\t{}\t{:?}\t{}",
+                item.name,
+                item.size,
+                item.source,
+                item.created.to_rfc3339()
+            );
+        }
+        Bytes::from(listing)
+    }
+
+    async fn drop_box_get(&mut self, channel_id: Uuid, name: &str) -> Result<Bytes, String> {
+        let config = self.services.config.load().store.drop_box.clone();
+        let session_state = self.server_handle.lock().await.session_state().clone();
+        let Some((item, data)) = session_state.lock().await.drop_box.get(name, &config) else {
+            return Err(format!("{name}: no such item"));
+        };
+        info!(
+            channel=%channel_id,
+            item=%item.name,
+            size=item.size,
+            "Drop-box item retrieved in the session"
+        );
+        self.services
+            .drop_box_audit
+            .record(
+                self.id,
+                self.username.clone(),
+                DropBoxAction::Retrieved,
+                false,
+                &item,
+            )
+            .await;
+        self.write_recording_marker(
+            Some(channel_id),
+            format!("Drop-box item retrieved: {}", item.name),
+        )
+        .await;
+        Ok(data)
+    }
+
+    async fn drop_box_start_upload(&self, name: String) -> Result<DropBoxUpload, DropBoxError> {
+        let config = self.services.config.load().store.drop_box.clone();
+        let session_state = self.server_handle.lock().await.session_state().clone();
+        let drop_box = &session_state.lock().await.drop_box;
+        drop_box.start_upload(name, &config)
+    }
+
+    async fn drop_box_finish_upload(
+        &mut self,
+        channel_id: Uuid,
+        upload: DropBoxUpload,
+    ) -> Result<(), String> {
+        let config = self.services.config.load().store.drop_box.clone();
+        let session_state = self.server_handle.lock().await.session_state().clone();
+        let name = upload.name.clone();
+        let result = session_state.lock().await.drop_box.finish_upload(
+            upload,
+            DropBoxItemSource::Session,
+            &config,
+        );
+        let item = result.map_err(|error| {
+            warn!(channel=%channel_id, item=%name, %error, "Failed to stage a drop-box item");
+            error.to_string()
+        })?;
+        info!(
+            channel=%channel_id,
+            item=%item.name,
+            size=item.size,
+            "Drop-box item staged in the session"
+        );
+        self.services
+            .drop_box_audit
+            .record(
+                self.id,
+                self.username.clone(),
+                DropBoxAction::Staged,
+                false,
+                &item,
+            )
+            .await;
+        self.write_recording_marker(
+            Some(channel_id),
+            format!("Drop-box item staged: {}", item.name),
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Types data into a channel on the target as if the client had sent it
+    fn inject_input(&mut self, channel_id: Uuid, data: Bytes) {
+        let Some(server_channel_id) = self.channel_map.get_by_right(&channel_id) else {
+            return;
+        };
+        let window = self.upload_windows.get(*server_channel_id);
+        let previous = self
+            .pending_control_input
+            .take()
+            .filter(|x| !x.is_finished());
+        if previous.is_none() {
+            if let Some(permit) = window.try_reserve(data.len()) {
                 let _ = self.send_command(RCCommand::Channel(
                     channel_id,
-                    ChannelOperation::RequestExec(command.to_string()),
+                    ChannelOperation::Data(data, permit),
                 ));
+                return;
             }
         }
+        // Waiting for window space here would stall the whole session,
+        // so the input waits in the background, behind any earlier input
+        let rc_tx = self.rc_tx.clone();
+        self.pending_control_input = Some(tokio::spawn(
+            async move {
+                if let Some(previous) = previous {
+                    let _ = previous.await;
+                }
+                let permit = window.reserve(data.len()).await;
+                let _ = rc_tx.send((
+                    RCCommand::Channel(channel_id, ChannelOperation::Data(data, permit)),
+                    None,
+                ));
+            }
+            .instrument(Span::current()),
+        ));
+    }
 
-        self.start_terminal_recording(channel_id, format!("exec-channel-{}", server_channel_id.0))
-            .await;
+    async fn finish_local_channel(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        stdout: Bytes,
+        stderr: Option<String>,
+        code: u32,
+    ) -> Result<()> {
+        let channel = server_channel_id.0;
+        self.maybe_with_session(|handle| async move {
+            if !stdout.is_empty() {
+                handle
+                    .data(channel, CryptoVec::from_slice(&stdout))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("failed to send data"))?;
+            }
+            if let Some(stderr) = stderr {
+                handle
+                    .extended_data(channel, 1, CryptoVec::from_slice(stderr.as_bytes()))
+                    .await
+                    .map_err(|_| anyhow::anyhow!("failed to send data"))?;
+            }
+            handle
+                .exit_status_request(channel, code)
+                .await
+                .context("failed to send exit status")?;
+            handle.eof(channel).await.context("failed to send eof")?;
+            handle.close(channel).await.context("failed to close ch")?;
+            Ok(())
+        })
+        .await?;
         Ok(())
     }
 
+    async fn update_details(&self, details: SshSessionDetails::ActiveModel) {
+        if let Err(error) = self
+            .server_handle
+            .lock()
+            .await
+            .update_ssh_details(details)
+            .await
+        {
+            error!(?error, "Failed to save session details");
+        }
+    }
+
+    async fn update_pty_details(&self, request: &PtyRequest) {
+        self.update_details(SshSessionDetails::ActiveModel {
+            pty_columns: Set(Some(request.col_width as i32)),
+            pty_rows: Set(Some(request.row_height as i32)),
+            ..Default::default()
+        })
+        .await;
+    }
+
+    /// Adds a chapter marker to the terminal recording of the channel,
+    /// or to all of them if no channel is given
+    async fn write_recording_marker(&mut self, channel_id: Option<Uuid>, label: String) {
+        for (id, recorder) in self.channel_recorders.iter_mut() {
+            if channel_id.is_some_and(|channel_id| channel_id != *id) {
+                continue;
+            }
+            if let Err(error) = recorder.write_marker(label.clone()).await {
+                error!(channel=%id, ?error, "Failed to record marker");
+            }
+        }
+    }
+
     async fn start_terminal_recording(&mut self, channel_id: Uuid, name: String) {
         let recorder = async {
             let mut recorder = self
@@ -1062,6 +2246,26 @@ impl ServerSession {
     ) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, %name, %value, "Environment");
+        if LOCALE_VARIABLES.contains(&name.as_str()) {
+            self.locale_env.insert(name.clone(), value.clone());
+        }
+        if name == WORK_ITEM_VARIABLE {
+            match normalize_work_item(&value) {
+                Some(work_item) => {
+                    info!(%work_item, "Tagged session with a work item");
+                    if let Err(error) = self
+                        .server_handle
+                        .lock()
+                        .await
+                        .set_work_item(Some(work_item))
+                        .await
+                    {
+                        error!(?error, "Failed to store the work item");
+                    }
+                }
+                None => warn!("Ignoring an invalid work item"),
+            }
+        }
         self.send_command_and_wait(RCCommand::Channel(
             channel_id,
             ChannelOperation::RequestEnv(name, value),
@@ -1107,15 +2311,107 @@ impl ServerSession {
         let _ = self.maybe_connect_remote().await;
         self.send_command_and_wait(RCCommand::Channel(
             channel_id,
-            ChannelOperation::RequestSubsystem(name),
+            ChannelOperation::RequestSubsystem(name.clone()),
         ))
         .await?;
+        self.channels
+            .set_description(channel_id, format!("subsystem {name}"));
+        if name == "sftp" {
+            self.start_sftp_inspection(channel_id, server_channel_id)
+                .await;
+        }
         Ok(())
     }
 
-    async fn _data(&mut self, server_channel_id: ServerChannelId, data: Bytes) -> Result<()> {
+    async fn start_sftp_inspection(
+        &mut self,
+        channel_id: Uuid,
+        server_channel_id: ServerChannelId,
+    ) {
+        self.sftp_inspectors
+            .insert(channel_id, SftpInspector::default());
+        match self
+            .services
+            .recordings
+            .lock()
+            .await
+            .start::<SftpRecorder>(&self.id, format!("sftp-channel-{}", server_channel_id.0))
+            .await
+        {
+            Ok(recorder) => {
+                self.sftp_recorders.insert(channel_id, recorder);
+            }
+            Err(recordings::Error::Disabled) => (),
+            Err(error) => error!(channel=%channel_id, ?error, "Failed to start recording"),
+        }
+    }
+
+    fn check_scp_policy(&self, command: &ScpCommand) -> Result<()> {
+        let Some(options) = self
+            .ssh_options()
+            .or(self.container_options().map(|options| &options.ssh))
+        else {
+            return Ok(());
+        };
+        match command.direction {
+            ScpDirection::Upload if options.block_scp_uploads => {
+                anyhow::bail!("SCP uploads to this target are not allowed")
+            }
+            ScpDirection::Download if options.block_scp_downloads => {
+                anyhow::bail!("SCP downloads from this target are not allowed")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn log_scp_files(&self, channel_id: Uuid, files: Vec<ScpFile>) {
+        let Some(inspector) = self.scp_inspectors.get(&channel_id) else {
+            return;
+        };
+        let target = match &self.target {
+            TargetSelection::Found(target, _) => Some(target.name.as_str()),
+            _ => None,
+        };
+        for file in files {
+            info!(
+                channel=%channel_id,
+                target,
+                direction=%inspector.command.direction,
+                path=%inspector.command.path,
+                file=%file.name,
+                size=file.size,
+                "SCP transfer"
+            );
+        }
+    }
+
+    async fn record_sftp_events(&mut self, channel_id: Uuid, events: Vec<SftpEvent>) {
+        for (operation, error) in events {
+            info!(channel=%channel_id, ?operation, ?error, "SFTP");
+            if let Some(recorder) = self.sftp_recorders.get_mut(&channel_id) {
+                if let Err(error) = recorder.write_operation(channel_id, operation, error).await {
+                    error!(channel=%channel_id, ?error, "Failed to record SFTP operation");
+                    self.sftp_recorders.remove(&channel_id);
+                }
+            }
+        }
+    }
+
+    async fn _data(
+        &mut self,
+        server_channel_id: ServerChannelId,
+        data: Bytes,
+        permit: RelayPermit,
+    ) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%server_channel_id.0, ?data, "Data");
+        self.channels.record_sent(channel_id, data.len());
+        self.last_activity.touch();
+        if self.local_channels.contains_key(&channel_id) {
+            return self
+                ._drop_box_data(server_channel_id, channel_id, &data)
+                .await;
+        }
         if self.rc_state == RCState::Connecting && data.first() == Some(&3) {
             info!(channel=%channel_id, "User requested connection abort (Ctrl-C)");
             self.request_disconnect().await;
@@ -1132,6 +2428,19 @@ impl ServerSession {
             }
         }
 
+        if let Some(inspector) = self.sftp_inspectors.get_mut(&channel_id) {
+            let events = inspector.client_data(&data);
+            self.record_sftp_events(channel_id, events).await;
+        }
+        if let Some(inspector) = self.scp_inspectors.get_mut(&channel_id) {
+            let files = inspector.client_data(&data);
+            self.log_scp_files(channel_id, files);
+        }
+
+        if let Some(connection) = self.forwarded_connections.get_mut(&channel_id) {
+            connection.bytes_sent += data.len() as u64;
+        }
+
         if let Some(recorder) = self.traffic_connection_recorders.get_mut(&channel_id) {
             if let Err(error) = recorder.write_tx(&data).await {
                 error!(channel=%channel_id, ?error, "Failed to record traffic data");
@@ -1146,7 +2455,10 @@ impl ServerSession {
                 .await;
         }
 
-        let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Data(data)));
+        let _ = self.send_command(RCCommand::Channel(
+            channel_id,
+            ChannelOperation::Data(data, permit),
+        ));
         Ok(())
     }
 
@@ -1155,26 +2467,44 @@ impl ServerSession {
         server_channel_id: ServerChannelId,
         code: u32,
         data: Bytes,
+        permit: RelayPermit,
     ) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%server_channel_id.0, ?data, "Data");
+        self.channels.record_sent(channel_id, data.len());
+        self.last_activity.touch();
+        if self.local_channels.contains_key(&channel_id) {
+            return Ok(());
+        }
         let _ = self.send_command(RCCommand::Channel(
             channel_id,
-            ChannelOperation::ExtendedData { ext: code, data },
+            ChannelOperation::ExtendedData {
+                ext: code,
+                data,
+                permit,
+            },
         ));
         Ok(())
     }
 
-    async fn _tcpip_forward(&mut self, address: String, port: u32) -> Result<()> {
+    async fn _tcpip_forward(&mut self, address: String, port: u32) -> Result<bool> {
         info!(%address, %port, "Remote port forwarding requested");
-        let _ = self.maybe_connect_remote().await;
-        self.send_command_and_wait(RCCommand::ForwardTCPIP(address, port))
+        if !self
+            .check_forward_policy("tcpip-forward", &address, port)
             .await
-            .map_err(anyhow::Error::from)
+        {
+            return Ok(false);
+        }
+        let _ = self.maybe_connect_remote().await;
+        self.send_command_and_wait(RCCommand::ForwardTCPIP(address.clone(), port))
+            .await?;
+        self.channels.add_remote_forward(address, port);
+        Ok(true)
     }
 
     pub async fn _cancel_tcpip_forward(&mut self, address: String, port: u32) -> Result<()> {
         info!(%address, %port, "Remote port forwarding cancelled");
+        self.channels.remove_remote_forward(&address, port);
         self.send_command_and_wait(RCCommand::CancelTCPIPForward(address, port))
             .await
             .map_err(anyhow::Error::from)
@@ -1310,6 +2640,9 @@ impl ServerSession {
                 let _ = event.recv().await;
                 // the auth state has been updated by now
             }
+            KeyboardInteractiveState::ConsentRequested => {
+                cred = None;
+            }
         }
 
         self.keyboard_interactive_state = KeyboardInteractiveState::None;
@@ -1348,8 +2681,7 @@ impl ServerSession {
                     let mut login_url = match self
                         .services
                         .config
-                        .lock()
-                        .await
+                        .load()
                         .construct_external_url(None, None)
                     {
                         Ok(url) => url,
@@ -1398,6 +2730,71 @@ impl ServerSession {
         }
     }
 
+    /// Holds back a successful authentication until the user has accepted
+    /// the consent banner, if one is configured
+    fn require_consent(&mut self, auth: russh::server::Auth) -> russh::server::Auth {
+        if !matches!(auth, russh::server::Auth::Accept)
+            || self.consent == ConsentState::Accepted
+            || self.consent_banner().is_none()
+        {
+            return auth;
+        }
+        self.consent = ConsentState::Pending;
+        let mut methods = MethodSet::empty();
+        methods.push(MethodKind::KeyboardInteractive);
+        russh::server::Auth::Reject {
+            proceed_with_methods: Some(methods),
+        }
+    }
+
+    fn consent_banner(&self) -> Option<String> {
+        self.services.config.load().store.ssh.consent_banner.clone()
+    }
+
+    /// Clients usually try keyboard-interactive auth before a password,
+    /// so the banner can also come up before the user is authenticated
+    async fn _auth_consent(
+        &mut self,
+        ssh_username: Secret<String>,
+        response: Option<Secret<String>>,
+    ) -> russh::server::Auth {
+        let state = std::mem::replace(
+            &mut self.keyboard_interactive_state,
+            KeyboardInteractiveState::None,
+        );
+        let (KeyboardInteractiveState::ConsentRequested, Some(response)) = (state, response) else {
+            let banner = self.consent_banner().unwrap_or_default();
+            self.keyboard_interactive_state = KeyboardInteractiveState::ConsentRequested;
+            return russh::server::Auth::Partial {
+                name: Cow::Borrowed("Consent required"),
+                instructions: Cow::Owned(format!("{}\n", banner.trim_end())),
+                prompts: Cow::Owned(vec![(Cow::Borrowed("Type 'yes' to continue: "), true)]),
+            };
+        };
+
+        if !response.expose_secret().trim().eq_ignore_ascii_case("yes") {
+            warn!("Consent banner declined");
+            return russh::server::Auth::Reject {
+                proceed_with_methods: None,
+            };
+        }
+
+        if let Err(error) = self.server_handle.lock().await.record_consent().await {
+            error!(?error, "Failed to record consent");
+            return russh::server::Auth::Reject {
+                proceed_with_methods: None,
+            };
+        }
+        info!("Consent banner accepted");
+        let authenticated = self.consent == ConsentState::Pending;
+        self.consent = ConsentState::Accepted;
+        if authenticated {
+            return russh::server::Auth::Accept;
+        }
+        // Carry on with the regular keyboard-interactive flow
+        self._auth_keyboard_interactive(ssh_username, None).await
+    }
+
     fn get_remaining_auth_methods(&self, kinds: HashSet<CredentialKind>) -> MethodSet {
         let mut m = MethodSet::empty();
         for kind in kinds {
@@ -1471,14 +2868,40 @@ impl ServerSession {
         selector: &AuthSelector,
         credential: Option<AuthCredential>,
     ) -> Result<AuthResult> {
+        let username = match selector {
+            AuthSelector::User { username, .. } => Some(username.as_str()),
+            AuthSelector::Ticket { .. } => None,
+        };
+        let client_ip = self.remote_address.ip().to_string();
+        if !self
+            .services
+            .auth_rate_limiter
+            .allows(username, Some(&client_ip))
+            .await
+        {
+            return Ok(AuthResult::Rejected);
+        }
+
         match selector {
             AuthSelector::User {
                 username,
                 target_name,
+                parameters,
             } => {
                 let cp = self.services.config_provider.clone();
 
-                let state_arc = self.get_auth_state(username).await?;
+                let state_arc = match self.get_auth_state(username).await {
+                    Ok(state) => state,
+                    Err(error) => {
+                        if let (Some(WarpgateError::UserNotFound(_)), Some(credential)) =
+                            (error.downcast_ref(), &credential)
+                        {
+                            self.record_auth_failure(Some(username), vec![credential.kind()])
+                                .await;
+                        }
+                        return Err(error);
+                    }
+                };
                 let mut state = state_arc.lock().await;
 
                 if let Some(credential) = credential {
@@ -1489,6 +2912,13 @@ impl ServerSession {
                         .await?
                     {
                         state.add_valid_credential(credential);
+                    } else {
+                        state.add_invalid_credential(&credential);
+                        self.record_auth_failure(
+                            Some(username),
+                            state.attempted_credential_kinds(),
+                        )
+                        .await;
                     }
                 }
 
@@ -1517,7 +2947,8 @@ impl ServerSession {
                             );
                             return Ok(AuthResult::Rejected);
                         }
-                        self._auth_accept(&username, target_name).await?;
+                        self._auth_accept(&username, target_name, parameters)
+                            .await?;
                         Ok(AuthResult::Accepted { username })
                     }
                     x => Ok(x),
@@ -1528,22 +2959,52 @@ impl ServerSession {
                     Some(ticket) => {
                         info!("Authorized for {} with a ticket", ticket.target);
                         consume_ticket(&self.services.db, &ticket.id).await?;
-                        self._auth_accept(&ticket.username, &ticket.target).await?;
+                        self._auth_accept(
+                            &ticket.username,
+                            &ticket.target,
+                            &TargetParameters::new(),
+                        )
+                        .await?;
                         Ok(AuthResult::Accepted {
                             username: ticket.username.clone(),
                         })
                     }
-                    None => Ok(AuthResult::Rejected),
+                    None => {
+                        self.record_auth_failure(None, vec![]).await;
+                        Ok(AuthResult::Rejected)
+                    }
                 }
             }
         }
     }
 
+    async fn record_auth_failure(
+        &self,
+        username: Option<&str>,
+        credential_kinds: Vec<CredentialKind>,
+    ) {
+        let context = AuthFailureContext {
+            username: username.map(Into::into),
+            credential_kinds,
+            client_banner: self.client_ident.get().cloned(),
+            ..AuthFailureContext::new(crate::PROTOCOL_NAME, self.remote_address)
+        };
+        self.services
+            .auth_rate_limiter
+            .record_failure(&context)
+            .await;
+        self.services.auth_failures.record(context).await;
+    }
+
     async fn _auth_accept(
         &mut self,
         username: &str,
         target_name: &str,
+        parameters: &TargetParameters,
     ) -> Result<(), WarpgateError> {
+        // No longer counts towards the unauthenticated connection limit
+        self.startup_permit = None;
+
         let _ = self
             .server_handle
             .lock()
@@ -1559,19 +3020,84 @@ impl ServerSession {
                 .await
                 .list_targets()
                 .await?
-                .iter()
-                .filter_map(|t| match t.options {
-                    TargetOptions::Ssh(ref options) => Some((t, options)),
-                    _ => None,
-                })
-                .find(|(t, _)| t.name == target_name)
-                .map(|(t, opt)| (t.clone(), opt.clone()))
+                .into_iter()
+                .find(|t| t.name == target_name)
         };
 
-        let Some((target, mut ssh_options)) = target else {
-            self.target = TargetSelection::NotFound(target_name.to_string());
-            warn!("Selected target not found");
+        if let Some(target) = target.as_ref().filter(|t| t.honeypot) {
+            if !self.attach_to_target(target).await {
+                return Ok(());
+            }
+            trip_honeypot(
+                &self.services,
+                target,
+                crate::PROTOCOL_NAME,
+                Some(self.id),
+                Some(username),
+                Some(self.remote_address.to_string()),
+            )
+            .await;
+
+            let handles = HoneypotClient::create(self.id, username, &target.name)?;
+            let _ = self.rc_abort_tx.send(());
+            self.rc_tx = handles.command_tx;
+            self.rc_abort_tx = handles.abort_tx;
+            self.forward_client_events(handles.event_rx)?;
+
+            self.target = TargetSelection::Honeypot;
             return Ok(());
+        }
+
+        let target = match target {
+            Some(mut target) => {
+                if let Err(error) =
+                    resolve_target_address(&self.services, username, &mut target, parameters).await
+                {
+                    warn!(%error, "Could not resolve the target address");
+                    self.target = TargetSelection::Unresolved(error.to_string());
+                    return Ok(());
+                }
+                Some(target)
+            }
+            None => None,
+        };
+
+        let (target, mut ssh_options) = match target {
+            Some(target) => match target.options {
+                TargetOptions::Ssh(ref options) => {
+                    let options = options.clone();
+                    (target, options)
+                }
+                TargetOptions::Docker(ref options) => {
+                    let options = options.ssh.clone();
+                    (target, options)
+                }
+                TargetOptions::Ipmi(ref options) => {
+                    if !self.attach_to_target(&target).await {
+                        return Ok(());
+                    }
+
+                    // Swap the not yet connected SSH client for a console
+                    let handles = SolClient::create(self.id, options.clone())?;
+                    let _ = self.rc_abort_tx.send(());
+                    self.rc_tx = handles.command_tx;
+                    self.rc_abort_tx = handles.abort_tx;
+                    self.forward_client_events(handles.event_rx)?;
+
+                    self.target = TargetSelection::Console(target);
+                    return Ok(());
+                }
+                _ => {
+                    self.target = TargetSelection::NotFound(target_name.to_string());
+                    warn!("Selected target not found");
+                    return Ok(());
+                }
+            },
+            None => {
+                self.target = TargetSelection::NotFound(target_name.to_string());
+                warn!("Selected target not found");
+                return Ok(());
+            }
         };
 
         // Forward username from the authenticated user to the target, if target has no username
@@ -1579,14 +3105,38 @@ impl ServerSession {
             ssh_options.username = username.to_string();
         }
 
-        let _ = self.server_handle.lock().await.set_target(&target).await;
-        self.target = TargetSelection::Found(target, ssh_options);
+        if self.attach_to_target(&target).await {
+            self.target = TargetSelection::Found(target, ssh_options);
+        }
         Ok(())
     }
 
+    /// Returns `false` if the target doesn't accept new sessions
+    async fn attach_to_target(&mut self, target: &Target) -> bool {
+        match self.server_handle.lock().await.set_target(target).await {
+            Err(
+                error @ (WarpgateError::TargetDraining(_)
+                | WarpgateError::TargetSessionLimit(..)
+                | WarpgateError::RecordingQuotaExceeded(_)),
+            ) => {
+                warn!(%error, "Selected target doesn't accept new sessions");
+                self.target = TargetSelection::Rejected(error.code().annotate(&error));
+                false
+            }
+            _ => true,
+        }
+    }
+
     async fn _channel_close(&mut self, server_channel_id: ServerChannelId) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, "Closing channel");
+        if self.local_channels.remove(&channel_id).is_some() {
+            self.channels.close(channel_id);
+            self.tap.close(channel_id);
+            return Ok(());
+        }
+        self.channels.closing(channel_id);
+        self.close_forwarded_connection(&channel_id);
         self.send_command_and_wait(RCCommand::Channel(channel_id, ChannelOperation::Close))
             .await?;
         Ok(())
@@ -1595,6 +3145,10 @@ impl ServerSession {
     async fn _channel_eof(&mut self, server_channel_id: ServerChannelId) -> Result<()> {
         let channel_id = self.map_channel(&server_channel_id)?;
         debug!(channel=%channel_id, "EOF");
+        self.channels.client_eof(channel_id);
+        if self.local_channels.contains_key(&channel_id) {
+            return self._drop_box_eof(server_channel_id, channel_id).await;
+        }
         let _ = self.send_command(RCCommand::Channel(channel_id, ChannelOperation::Eof));
         Ok(())
     }
@@ -1614,8 +3168,10 @@ impl ServerSession {
         Ok(())
     }
 
-    fn send_command(&mut self, command: RCCommand) -> Result<(), RCCommand> {
-        self.rc_tx.send((command, None)).map_err(|e| e.0 .0)
+    fn send_command(&mut self, command: RCCommand) -> Result<(), SshClientError> {
+        self.rc_tx
+            .send((command, None))
+            .map_err(|_| SshClientError::MpscError)
     }
 
     async fn send_command_and_wait(&mut self, command: RCCommand) -> Result<(), SshClientError> {
@@ -1679,6 +3235,14 @@ impl ServerSession {
 impl Drop for ServerSession {
     fn drop(&mut self) {
         let _ = self.rc_abort_tx.send(());
+        for channel_id in self
+            .forwarded_connections
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+        {
+            self.close_forwarded_connection(&channel_id);
+        }
         info!("Closed session");
         debug!("Dropped");
     }
@@ -1,9 +1,18 @@
+use bytes::Bytes;
 use tokio::sync::mpsc;
+use uuid::Uuid;
 use warpgate_core::SessionHandle;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SessionHandleCommand {
-    Close,
+    /// With an optional message from the admin
+    Close(Option<String>),
+    Input {
+        recording_id: Uuid,
+        data: Bytes,
+    },
+    RecordingMarker(String),
+    Notify(String),
 }
 
 pub struct SSHSessionHandle {
@@ -19,6 +28,28 @@ impl SSHSessionHandle {
 
 impl SessionHandle for SSHSessionHandle {
     fn close(&mut self) {
-        let _ = self.sender.send(SessionHandleCommand::Close);
+        let _ = self.sender.send(SessionHandleCommand::Close(None));
+    }
+
+    fn close_with_message(&mut self, message: String) {
+        let _ = self.sender.send(SessionHandleCommand::Close(Some(message)));
+    }
+
+    fn send_input(&mut self, recording_id: Uuid, data: Bytes) -> bool {
+        self.sender
+            .send(SessionHandleCommand::Input { recording_id, data })
+            .is_ok()
+    }
+
+    fn add_recording_marker(&mut self, label: String) {
+        let _ = self
+            .sender
+            .send(SessionHandleCommand::RecordingMarker(label));
+    }
+
+    fn notify(&mut self, message: String) -> bool {
+        self.sender
+            .send(SessionHandleCommand::Notify(message))
+            .is_ok()
     }
 }
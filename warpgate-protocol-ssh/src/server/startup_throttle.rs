@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rand::Rng;
+use warpgate_common::SshMaxStartups;
+
+/// Tracks connections that haven't completed authentication yet and decides
+/// whether new ones should be let in, similar to OpenSSH's `MaxStartups`.
+#[derive(Debug)]
+pub struct StartupThrottle {
+    config: SshMaxStartups,
+    state: Mutex<StartupThrottleState>,
+}
+
+#[derive(Debug, Default)]
+struct StartupThrottleState {
+    total: usize,
+    per_source: HashMap<IpAddr, usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StartupRejection {
+    /// Randomly dropped between `start` and `full`
+    EarlyDrop { pending: usize },
+    /// `full` unauthenticated connections are already pending
+    Full { pending: usize },
+    /// The source address has too many unauthenticated connections
+    PerSource { pending: usize },
+}
+
+impl StartupThrottle {
+    pub fn new(config: SshMaxStartups) -> Arc<Self> {
+        Arc::new(StartupThrottle {
+            config,
+            state: Mutex::new(<_>::default()),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, StartupThrottleState> {
+        // The state is only ever mutated with simple counter updates
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Probability of dropping a new connection with `pending`
+    /// unauthenticated connections already in progress.
+    pub fn drop_probability(&self, pending: usize) -> f64 {
+        let SshMaxStartups {
+            start, rate, full, ..
+        } = self.config;
+        if pending < start {
+            return 0.0;
+        }
+        if pending >= full {
+            return 1.0;
+        }
+        let rate = f64::from(rate.min(100)) / 100.0;
+        let progress = (pending - start) as f64 / (full - start) as f64;
+        rate + (1.0 - rate) * progress
+    }
+
+    pub fn try_admit(self: &Arc<Self>, source: IpAddr) -> Result<StartupPermit, StartupRejection> {
+        self.try_admit_with(source, &mut rand::thread_rng())
+    }
+
+    fn try_admit_with<R: Rng>(
+        self: &Arc<Self>,
+        source: IpAddr,
+        rng: &mut R,
+    ) -> Result<StartupPermit, StartupRejection> {
+        let mut state = self.state();
+        let pending = state.total;
+
+        if let Some(limit) = self.config.per_source {
+            let from_source = state.per_source.get(&source).copied().unwrap_or(0);
+            if from_source >= limit {
+                return Err(StartupRejection::PerSource {
+                    pending: from_source,
+                });
+            }
+        }
+
+        if pending >= self.config.full {
+            return Err(StartupRejection::Full { pending });
+        }
+
+        let p = self.drop_probability(pending);
+        if p > 0.0 && rng.gen_bool(p) {
+            return Err(StartupRejection::EarlyDrop { pending });
+        }
+
+        state.total += 1;
+        *state.per_source.entry(source).or_default() += 1;
+
+        Ok(StartupPermit {
+            throttle: self.clone(),
+            source,
+        })
+    }
+
+    #[cfg(test)]
+    fn pending(&self) -> usize {
+        self.state().total
+    }
+
+    fn release(&self, source: IpAddr) {
+        let mut state = self.state();
+        state.total = state.total.saturating_sub(1);
+        if let Some(count) = state.per_source.get_mut(&source) {
+            *count -= 1;
+            if *count == 0 {
+                state.per_source.remove(&source);
+            }
+        }
+    }
+}
+
+/// Held by a connection until it authenticates or goes away.
+#[derive(Debug)]
+pub struct StartupPermit {
+    throttle: Arc<StartupThrottle>,
+    source: IpAddr,
+}
+
+impl Drop for StartupPermit {
+    fn drop(&mut self) {
+        self.throttle.release(self.source);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use rand::rngs::mock::StepRng;
+
+    use super::*;
+
+    fn config(per_source: Option<usize>) -> SshMaxStartups {
+        SshMaxStartups {
+            start: 10,
+            rate: 30,
+            full: 100,
+            per_source,
+        }
+    }
+
+    fn ip(n: u8) -> IpAddr {
+        Ipv4Addr::new(10, 0, 0, n).into()
+    }
+
+    #[test]
+    fn drop_probability_ramps_from_rate_to_full() {
+        let throttle = StartupThrottle::new(config(None));
+        assert_eq!(throttle.drop_probability(0), 0.0);
+        assert_eq!(throttle.drop_probability(9), 0.0);
+        assert!((throttle.drop_probability(10) - 0.3).abs() < 1e-9);
+        assert!((throttle.drop_probability(55) - 0.65).abs() < 1e-9);
+        assert_eq!(throttle.drop_probability(100), 1.0);
+        assert_eq!(throttle.drop_probability(1000), 1.0);
+    }
+
+    #[test]
+    fn full_rejects_and_release_readmits() {
+        let throttle = StartupThrottle::new(config(None));
+        // An RNG that always yields the maximum value never triggers the random early drop
+        let mut rng = StepRng::new(u64::MAX, 0);
+
+        let mut permits = (0..100)
+            .map(|i| throttle.try_admit_with(ip(i as u8), &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(throttle.pending(), 100);
+        assert_eq!(
+            throttle.try_admit_with(ip(1), &mut rng).unwrap_err(),
+            StartupRejection::Full { pending: 100 }
+        );
+
+        permits.pop();
+        assert_eq!(throttle.pending(), 99);
+        permits.push(throttle.try_admit_with(ip(1), &mut rng).unwrap());
+
+        drop(permits);
+        assert_eq!(throttle.pending(), 0);
+    }
+
+    #[test]
+    fn early_drop_is_probabilistic() {
+        let throttle = StartupThrottle::new(config(None));
+        let mut rng = StepRng::new(u64::MAX, 0);
+        let _permits = (0..50)
+            .map(|i| throttle.try_admit_with(ip(i as u8), &mut rng).unwrap())
+            .collect::<Vec<_>>();
+
+        // An RNG that always yields zero always drops
+        let mut rng = StepRng::new(0, 0);
+        assert_eq!(
+            throttle.try_admit_with(ip(1), &mut rng).unwrap_err(),
+            StartupRejection::EarlyDrop { pending: 50 }
+        );
+
+        let mut rng = rand::thread_rng();
+        let admitted = (0..1000)
+            .filter_map(|_| throttle.try_admit_with(ip(1), &mut rng).ok())
+            .count();
+        // p = 0.3 + 0.7 * 40/90 ≈ 0.61
+        assert!((250..550).contains(&admitted), "{admitted}");
+    }
+
+    #[test]
+    fn per_source_limit() {
+        let throttle = StartupThrottle::new(config(Some(2)));
+        let mut rng = StepRng::new(u64::MAX, 0);
+
+        let a = throttle.try_admit_with(ip(1), &mut rng).unwrap();
+        let _b = throttle.try_admit_with(ip(1), &mut rng).unwrap();
+        assert_eq!(
+            throttle.try_admit_with(ip(1), &mut rng).unwrap_err(),
+            StartupRejection::PerSource { pending: 2 }
+        );
+        let _c = throttle.try_admit_with(ip(2), &mut rng).unwrap();
+
+        drop(a);
+        let _d = throttle.try_admit_with(ip(1), &mut rng).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+/// Exec requests starting with this are handled by Warpgate itself
+/// and never reach the target
+pub const TICKET_COMMAND: &str = "warpgate-ticket";
+
+pub const TICKET_USAGE: &str = "\
+Usage: warpgate-ticket [list]                   list your tickets
+       warpgate-ticket new <target> [options]   issue a ticket for a target
+           --uses <count>       limit how many times it can be used
+           --valid-for <time>   e.g. 8h, defaults to the longest allowed
+       warpgate-ticket revoke <id>              delete a ticket
+";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TicketCommand {
+    List,
+    New {
+        target: String,
+        uses: Option<i16>,
+        valid_for: Option<Duration>,
+    },
+    Revoke(Uuid),
+    Help,
+    Invalid,
+}
+
+impl TicketCommand {
+    /// Returns `None` if the exec request isn't a ticket command
+    pub fn parse(command: &str) -> Option<Self> {
+        let mut args = command.split_whitespace();
+        if args.next()? != TICKET_COMMAND {
+            return None;
+        }
+        Some(match args.next() {
+            None | Some("list") => match args.next() {
+                None => Self::List,
+                Some(_) => Self::Invalid,
+            },
+            Some("new") => Self::parse_new(args).unwrap_or(Self::Invalid),
+            Some("revoke") => match (args.next().map(Uuid::parse_str), args.next()) {
+                (Some(Ok(id)), None) => Self::Revoke(id),
+                _ => Self::Invalid,
+            },
+            Some("help" | "-h" | "--help") => Self::Help,
+            _ => Self::Invalid,
+        })
+    }
+
+    fn parse_new<'a>(mut args: impl Iterator<Item = &'a str>) -> Option<Self> {
+        let target = args.next()?.to_owned();
+        let mut uses = None;
+        let mut valid_for = None;
+        while let Some(arg) = args.next() {
+            match arg {
+                "--uses" => uses = Some(args.next()?.parse().ok()?),
+                "--valid-for" => valid_for = Some(humantime::parse_duration(args.next()?).ok()?),
+                _ => return None,
+            }
+        }
+        Some(Self::New {
+            target,
+            uses,
+            valid_for,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(TicketCommand::parse("ls"), None);
+        assert_eq!(
+            TicketCommand::parse("warpgate-ticket"),
+            Some(TicketCommand::List)
+        );
+        assert_eq!(
+            TicketCommand::parse("warpgate-ticket new db --uses 3 --valid-for 2h"),
+            Some(TicketCommand::New {
+                target: "db".into(),
+                uses: Some(3),
+                valid_for: Some(Duration::from_secs(7200)),
+            })
+        );
+        assert_eq!(
+            TicketCommand::parse("warpgate-ticket new db --uses many"),
+            Some(TicketCommand::Invalid)
+        );
+        assert_eq!(
+            TicketCommand::parse("warpgate-ticket revoke 67e55044-10b1-426f-9247-bb680e5fe0c8"),
+            Some(TicketCommand::Revoke(
+                "67e55044-10b1-426f-9247-bb680e5fe0c8".parse().unwrap()
+            ))
+        );
+        assert_eq!(
+            TicketCommand::parse("warpgate-ticket revoke 42"),
+            Some(TicketCommand::Invalid)
+        );
+    }
+}
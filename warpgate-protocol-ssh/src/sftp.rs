@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+
+use bytes::{Buf, BytesMut};
+use tracing::*;
+use warpgate_core::recordings::SftpOperation;
+
+const SSH_FXP_OPEN: u8 = 3;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_READ: u8 = 5;
+const SSH_FXP_WRITE: u8 = 6;
+const SSH_FXP_REMOVE: u8 = 13;
+const SSH_FXP_MKDIR: u8 = 14;
+const SSH_FXP_RMDIR: u8 = 15;
+const SSH_FXP_RENAME: u8 = 18;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_DATA: u8 = 103;
+const SSH_FXP_EXTENDED: u8 = 200;
+
+const SSH_FXF_WRITE: u32 = 0x02;
+const SSH_FXF_APPEND: u32 = 0x04;
+const SSH_FXF_CREAT: u32 = 0x08;
+const SSH_FXF_TRUNC: u32 = 0x10;
+
+const SSH_FX_OK: u32 = 0;
+
+/// Clients don't send packets anywhere near this size - anything
+/// larger means that the channel isn't actually speaking SFTP
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+/// A file operation, and the target's error message if it failed
+pub type SftpEvent = (SftpOperation, Option<String>);
+
+enum PendingRequest {
+    /// Reported once the target hands out a handle
+    Open {
+        path: String,
+        write: bool,
+    },
+    Read {
+        handle: Vec<u8>,
+    },
+    /// Reported once the target confirms it
+    Other(SftpOperation),
+}
+
+struct OpenFile {
+    path: String,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+/// Follows the SFTP (v3) conversation on a subsystem channel and
+/// extracts file operations from it. Both directions are needed
+/// to tell which file a handle refers to and whether requests succeeded.
+#[derive(Default)]
+pub struct SftpInspector {
+    client_buffer: BytesMut,
+    server_buffer: BytesMut,
+    pending: HashMap<u32, PendingRequest>,
+    files: HashMap<Vec<u8>, OpenFile>,
+    broken: bool,
+}
+
+impl SftpInspector {
+    pub fn client_data(&mut self, data: &[u8]) -> Vec<SftpEvent> {
+        let mut events = vec![];
+        for packet in self.take_packets(data, true) {
+            if let Some(event) = self.handle_request(&packet) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    pub fn server_data(&mut self, data: &[u8]) -> Vec<SftpEvent> {
+        let mut events = vec![];
+        for packet in self.take_packets(data, false) {
+            if let Some(event) = self.handle_response(&packet) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Totals for the files that were still open when the channel closed
+    pub fn finish(&mut self) -> Vec<SftpEvent> {
+        self.files
+            .drain()
+            .map(|(_, file)| (file.into_close_operation(), None))
+            .collect()
+    }
+
+    fn take_packets(&mut self, data: &[u8], from_client: bool) -> Vec<Vec<u8>> {
+        if self.broken {
+            return vec![];
+        }
+        let buffer = match from_client {
+            true => &mut self.client_buffer,
+            false => &mut self.server_buffer,
+        };
+        buffer.extend_from_slice(data);
+
+        let mut packets = vec![];
+        while buffer.len() >= 4 {
+            let length = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+            if length > MAX_PACKET_SIZE {
+                warn!(length, "Not an SFTP stream, no longer inspecting it");
+                self.broken = true;
+                self.client_buffer.clear();
+                self.server_buffer.clear();
+                return packets;
+            }
+            if buffer.len() < 4 + length {
+                break;
+            }
+            buffer.advance(4);
+            packets.push(buffer.split_to(length).to_vec());
+        }
+        packets
+    }
+
+    fn handle_request(&mut self, packet: &[u8]) -> Option<SftpEvent> {
+        let mut reader = PacketReader(packet);
+        let kind = reader.u8()?;
+        if !matches!(
+            kind,
+            SSH_FXP_OPEN
+                | SSH_FXP_CLOSE
+                | SSH_FXP_READ
+                | SSH_FXP_WRITE
+                | SSH_FXP_REMOVE
+                | SSH_FXP_MKDIR
+                | SSH_FXP_RMDIR
+                | SSH_FXP_RENAME
+                | SSH_FXP_EXTENDED
+        ) {
+            return None;
+        }
+        let id = reader.u32()?;
+        let request = match kind {
+            SSH_FXP_OPEN => {
+                let path = reader.path()?;
+                let flags = reader.u32()?;
+                let write =
+                    flags & (SSH_FXF_WRITE | SSH_FXF_APPEND | SSH_FXF_CREAT | SSH_FXF_TRUNC) != 0;
+                PendingRequest::Open { path, write }
+            }
+            SSH_FXP_CLOSE => {
+                // Whether or not the target agrees, the handle is gone
+                let file = self.files.remove(reader.string()?)?;
+                return Some((file.into_close_operation(), None));
+            }
+            SSH_FXP_READ => PendingRequest::Read {
+                handle: reader.string()?.to_vec(),
+            },
+            SSH_FXP_WRITE => {
+                let handle = reader.string()?;
+                let _offset = reader.u64()?;
+                let data = reader.string()?;
+                if let Some(file) = self.files.get_mut(handle) {
+                    file.bytes_written += data.len() as u64;
+                }
+                return None;
+            }
+            SSH_FXP_REMOVE => PendingRequest::Other(SftpOperation::Remove {
+                path: reader.path()?,
+            }),
+            SSH_FXP_MKDIR => PendingRequest::Other(SftpOperation::Mkdir {
+                path: reader.path()?,
+            }),
+            SSH_FXP_RMDIR => PendingRequest::Other(SftpOperation::Rmdir {
+                path: reader.path()?,
+            }),
+            SSH_FXP_RENAME => PendingRequest::Other(SftpOperation::Rename {
+                path: reader.path()?,
+                new_path: reader.path()?,
+            }),
+            SSH_FXP_EXTENDED if reader.string()? == b"posix-rename@openssh.com" => {
+                PendingRequest::Other(SftpOperation::Rename {
+                    path: reader.path()?,
+                    new_path: reader.path()?,
+                })
+            }
+            _ => return None,
+        };
+        self.pending.insert(id, request);
+        None
+    }
+
+    fn handle_response(&mut self, packet: &[u8]) -> Option<SftpEvent> {
+        let mut reader = PacketReader(packet);
+        let kind = reader.u8()?;
+        if !matches!(kind, SSH_FXP_STATUS | SSH_FXP_HANDLE | SSH_FXP_DATA) {
+            return None;
+        }
+        let request = self.pending.remove(&reader.u32()?)?;
+        match (kind, request) {
+            (SSH_FXP_HANDLE, PendingRequest::Open { path, write }) => {
+                self.files.insert(
+                    reader.string()?.to_vec(),
+                    OpenFile {
+                        path: path.clone(),
+                        bytes_read: 0,
+                        bytes_written: 0,
+                    },
+                );
+                Some((SftpOperation::Open { path, write }, None))
+            }
+            (SSH_FXP_DATA, PendingRequest::Read { handle }) => {
+                let length = reader.string()?.len() as u64;
+                if let Some(file) = self.files.get_mut(&handle) {
+                    file.bytes_read += length;
+                }
+                None
+            }
+            (SSH_FXP_STATUS, PendingRequest::Open { path, write }) => Some((
+                SftpOperation::Open { path, write },
+                Some(reader.status_error()?),
+            )),
+            (SSH_FXP_STATUS, PendingRequest::Other(operation)) => {
+                let code = reader.u32()?;
+                let error = (code != SSH_FX_OK).then(|| reader.message(code));
+                Some((operation, error))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl OpenFile {
+    fn into_close_operation(self) -> SftpOperation {
+        SftpOperation::Close {
+            path: self.path,
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
+        }
+    }
+}
+
+struct PacketReader<'a>(&'a [u8]);
+
+impl<'a> PacketReader<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (value, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(value)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    fn path(&mut self) -> Option<String> {
+        Some(String::from_utf8_lossy(self.string()?).into_owned())
+    }
+
+    /// Rest of a STATUS packet, after the code
+    fn message(&mut self, code: u32) -> String {
+        match self.path() {
+            Some(message) if !message.is_empty() => message,
+            _ => format!("status {code}"),
+        }
+    }
+
+    fn status_error(&mut self) -> Option<String> {
+        let code = self.u32()?;
+        Some(self.message(code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(kind: u8, id: u32, fields: &[&[u8]]) -> Vec<u8> {
+        let mut body = vec![kind];
+        body.extend_from_slice(&id.to_be_bytes());
+        for field in fields {
+            body.extend_from_slice(field);
+        }
+        let mut result = (body.len() as u32).to_be_bytes().to_vec();
+        result.extend(body);
+        result
+    }
+
+    fn string(value: &[u8]) -> Vec<u8> {
+        let mut result = (value.len() as u32).to_be_bytes().to_vec();
+        result.extend_from_slice(value);
+        result
+    }
+
+    #[test]
+    fn test_upload() {
+        let mut inspector = SftpInspector::default();
+        let open = packet(
+            SSH_FXP_OPEN,
+            1,
+            &[
+                &string(b"/tmp/upload.bin"),
+                &(SSH_FXF_WRITE | SSH_FXF_CREAT).to_be_bytes(),
+                &0u32.to_be_bytes(),
+            ],
+        );
+        // Packets may be split across channel data chunks
+        assert!(inspector.client_data(&open[..5]).is_empty());
+        assert!(inspector.client_data(&open[5..]).is_empty());
+
+        assert_eq!(
+            inspector.server_data(&packet(SSH_FXP_HANDLE, 1, &[&string(b"h1")])),
+            vec![(
+                SftpOperation::Open {
+                    path: "/tmp/upload.bin".into(),
+                    write: true
+                },
+                None
+            )]
+        );
+
+        let write = packet(
+            SSH_FXP_WRITE,
+            2,
+            &[&string(b"h1"), &0u64.to_be_bytes(), &string(&[0; 100])],
+        );
+        let close = packet(SSH_FXP_CLOSE, 3, &[&string(b"h1")]);
+        assert_eq!(
+            inspector.client_data(&[write, close].concat()),
+            vec![(
+                SftpOperation::Close {
+                    path: "/tmp/upload.bin".into(),
+                    bytes_read: 0,
+                    bytes_written: 100,
+                },
+                None
+            )]
+        );
+        assert!(inspector.finish().is_empty());
+    }
+
+    #[test]
+    fn test_failed_rename() {
+        let mut inspector = SftpInspector::default();
+        inspector.client_data(&packet(
+            SSH_FXP_RENAME,
+            7,
+            &[&string(b"/a"), &string(b"/b")],
+        ));
+        assert_eq!(
+            inspector.server_data(&packet(
+                SSH_FXP_STATUS,
+                7,
+                &[
+                    &3u32.to_be_bytes(),
+                    &string(b"Permission denied"),
+                    &string(b"")
+                ],
+            )),
+            vec![(
+                SftpOperation::Rename {
+                    path: "/a".into(),
+                    new_path: "/b".into(),
+                },
+                Some("Permission denied".into())
+            )]
+        );
+    }
+
+    #[test]
+    fn test_not_sftp() {
+        let mut inspector = SftpInspector::default();
+        assert!(inspector.client_data(b"GET / HTTP/1.1\r\n").is_empty());
+        assert!(inspector.broken);
+    }
+}
@@ -1,7 +1,9 @@
 use std::borrow::Cow;
 use std::ops::Deref;
+use std::sync::RwLock;
 
 use futures::future::OptionFuture;
+use once_cell::sync::Lazy;
 use openidconnect::core::{
     CoreAuthenticationFlow, CoreClient, CoreGenderClaim, CoreIdToken, CoreIdTokenClaims,
 };
@@ -27,6 +29,15 @@ pub struct WarpgateClaims {
 
 impl AdditionalClaims for WarpgateClaims {}
 
+/// DER-encoded CAs to trust in addition to the bundled roots
+static TRUSTED_CA_CERTIFICATES: Lazy<RwLock<Vec<Vec<u8>>>> = Lazy::new(Default::default);
+
+pub fn set_trusted_ca_certificates(certificates: Vec<Vec<u8>>) {
+    *TRUSTED_CA_CERTIFICATES
+        .write()
+        .unwrap_or_else(|e| e.into_inner()) = certificates;
+}
+
 pub struct SsoResult {
     pub token: CoreIdToken,
     pub claims: CoreIdTokenClaims,
@@ -80,9 +91,17 @@ async fn make_client(
 
 impl SsoClient {
     pub fn new(config: SsoInternalProviderConfig) -> Result<Self, SsoError> {
+        let mut http_client = reqwest::ClientBuilder::new();
+        for cert in TRUSTED_CA_CERTIFICATES
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            http_client = http_client.add_root_certificate(reqwest::Certificate::from_der(cert)?);
+        }
         Ok(Self {
             config,
-            http_client: reqwest::ClientBuilder::new().build()?,
+            http_client: http_client.build()?,
         })
     }
 
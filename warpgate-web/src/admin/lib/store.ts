@@ -0,0 +1,8 @@
+import { writable } from 'svelte/store'
+import { api } from './api'
+
+export const unreadNotificationCount = writable(0)
+
+export async function reloadUnreadNotificationCount (): Promise<void> {
+    unreadNotificationCount.set((await api.getUnreadNotificationCount()).count)
+}
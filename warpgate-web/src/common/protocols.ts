@@ -1,5 +1,5 @@
 import { shellEscape } from 'gateway/lib/shellEscape'
-import type { Info } from 'gateway/lib/api'
+import type { Info, TargetForwardPreset } from 'gateway/lib/api'
 import { CredentialKind } from 'admin/lib/api'
 
 export interface ConnectionOptions {
@@ -21,6 +21,14 @@ export function makeExampleSSHCommand (opt: ConnectionOptions): string {
     return shellEscape(['ssh', `${makeSSHUsername(opt)}@${opt.serverInfo?.externalHost ?? 'warpgate-host'}`, '-p', (opt.serverInfo?.ports.ssh ?? 'warpgate-ssh-port').toString()])
 }
 
+export function makeExampleDockerCommand (opt: ConnectionOptions): string {
+    return shellEscape(['ssh', '-t', `${makeSSHUsername(opt)}@${opt.serverInfo?.externalHost ?? 'warpgate-host'}`, '-p', (opt.serverInfo?.ports.ssh ?? 'warpgate-ssh-port').toString(), 'container-name'])
+}
+
+export function makeExampleSSHForwardCommand (opt: ConnectionOptions, preset: TargetForwardPreset): string {
+    return shellEscape(['ssh', '-N', '-L', `${preset.port}:${preset.name}:${preset.port}`, `${makeSSHUsername(opt)}@${opt.serverInfo?.externalHost ?? 'warpgate-host'}`, '-p', (opt.serverInfo?.ports.ssh ?? 'warpgate-ssh-port').toString()])
+}
+
 export function makeMySQLUsername (opt: ConnectionOptions): string {
     if (opt.ticketSecret) {
         return `ticket-${opt.ticketSecret}`
@@ -52,6 +60,25 @@ export function makeExamplePostgreSQLURI (opt: ConnectionOptions): string {
     return `postgresql://${makePostgreSQLUsername(opt)}${pwSuffix}@${opt.serverInfo?.externalHost ?? 'warpgate-host'}:${opt.serverInfo?.ports.postgres ?? 'warpgate-postgres-port'}/database-name?sslmode=require`
 }
 
+export const makeRedisUsername = makeMySQLUsername
+
+export function makeExampleRedisCommand (opt: ConnectionOptions): string {
+    const cmd = ['redis-cli', '--tls', '-h', opt.serverInfo?.externalHost ?? 'warpgate-host', '-p', (opt.serverInfo?.ports.redis ?? 'warpgate-redis-port').toString()]
+    if (opt.ticketSecret) {
+        cmd.push('-a', makeRedisUsername(opt))
+    } else {
+        cmd.push('--user', makeRedisUsername(opt), '--askpass')
+    }
+    return shellEscape(cmd)
+}
+
+export function makeExampleRedisURI (opt: ConnectionOptions): string {
+    // '#' separates the target name and has to be escaped in a URL
+    const username = encodeURIComponent(makeRedisUsername(opt))
+    const credentials = opt.ticketSecret ? `:${username}` : `${username}:<password>`
+    return `rediss://${credentials}@${opt.serverInfo?.externalHost ?? 'warpgate-host'}:${opt.serverInfo?.ports.redis ?? 'warpgate-redis-port'}`
+}
+
 export function makeTargetURL (opt: ConnectionOptions): string {
     const host = opt.targetExternalHost ? `${opt.targetExternalHost}:${opt.serverInfo?.ports.http ?? 443}` : location.host
     if (opt.ticketSecret) {
@@ -65,4 +92,5 @@ export const possibleCredentials: Record<string, Set<CredentialKind>> = {
     http: new Set([CredentialKind.Password, CredentialKind.Totp, CredentialKind.Sso]),
     mysql: new Set([CredentialKind.Password]),
     postgres: new Set([CredentialKind.Password]),
+    redis: new Set([CredentialKind.Password]),
 }
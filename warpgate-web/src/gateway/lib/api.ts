@@ -8,5 +8,14 @@ export const api = new DefaultApi(configuration)
 export * from './api-client'
 
 export async function stringifyError (err: ResponseError): Promise<string> {
-    return `API error: ${await err.response.text()}`
+    const text = await err.response.text()
+    try {
+        const { code, message } = JSON.parse(text)
+        if (code && message) {
+            return `API error: ${message} [${code}]`
+        }
+    } catch {
+        // not a Warpgate error body
+    }
+    return `API error: ${text}`
 }
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use poem_openapi::types::ParseFromJSON;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, Set, TransactionTrait};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use tracing::*;
+use uuid::Uuid;
+use warpgate_common::{
+    Secret, TargetOptions, UserPasswordCredential, UserPublicKeyCredential,
+    UserRequireCredentialsPolicy,
+};
+use warpgate_db_entities::{
+    PasswordCredential, PublicKeyCredential, Role, Target, TargetRoleAssignment, User,
+    UserRoleAssignment,
+};
+use warpgate_sso::SsoProviderConfig;
+
+/// Initial state for `unattended-setup --bootstrap`, so that deployments can
+/// come up fully configured on first boot.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Bootstrap {
+    #[serde(default)]
+    pub sso_providers: Vec<SsoProviderConfig>,
+
+    /// Roles to create in addition to the built-in `warpgate:admin`
+    #[serde(default)]
+    pub roles: Vec<String>,
+
+    #[serde(default)]
+    pub users: Vec<BootstrapUser>,
+
+    #[serde(default)]
+    pub targets: Vec<BootstrapTarget>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BootstrapUser {
+    pub username: String,
+
+    #[serde(default)]
+    pub password: Option<Secret<String>>,
+
+    /// Argon2 hash, for keeping plaintext passwords out of the file
+    #[serde(default)]
+    pub password_hash: Option<Secret<String>>,
+
+    /// OpenSSH public keys
+    #[serde(default)]
+    pub public_keys: Vec<String>,
+
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct BootstrapTarget {
+    pub name: String,
+
+    /// Same format as in the admin API, e.g. `kind: Ssh`
+    #[serde(deserialize_with = "deserialize_target_options")]
+    pub options: TargetOptions,
+
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+fn deserialize_target_options<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<TargetOptions, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    TargetOptions::parse_from_json(Some(value)).map_err(|e| D::Error::custom(e.into_message()))
+}
+
+impl Bootstrap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let bootstrap: Self =
+            serde_yaml::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        bootstrap.validate()?;
+        Ok(bootstrap)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for user in &self.users {
+            if user.password.is_some() && user.password_hash.is_some() {
+                anyhow::bail!(
+                    "User {}: only one of password and password_hash can be set",
+                    user.username
+                );
+            }
+        }
+        for target in &self.targets {
+            if let TargetOptions::WebAdmin(_) = target.options {
+                anyhow::bail!("Target {}: the admin target is built-in", target.name);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn apply(&self, db: &DatabaseConnection) -> Result<()> {
+        let txn = db.begin().await?;
+
+        let mut roles: HashMap<String, Uuid> = Role::Entity::find()
+            .all(&txn)
+            .await?
+            .into_iter()
+            .map(|r| (r.name, r.id))
+            .collect();
+
+        for name in &self.roles {
+            if roles.contains_key(name) {
+                continue;
+            }
+            let role = Role::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                name: Set(name.clone()),
+            }
+            .insert(&txn)
+            .await?;
+            roles.insert(role.name, role.id);
+        }
+
+        let role_id = |name: &String| {
+            roles
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("Unknown role: {name}"))
+        };
+
+        for user in &self.users {
+            let model = User::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                username: Set(user.username.clone()),
+                credential_policy: Set(serde_json::to_value(None::<UserRequireCredentialsPolicy>)?),
+                ..Default::default()
+            }
+            .insert(&txn)
+            .await
+            .with_context(|| format!("creating user {}", user.username))?;
+
+            let password = match (&user.password, &user.password_hash) {
+                (Some(password), _) => Some(UserPasswordCredential::from_password(password)),
+                (_, Some(hash)) => Some(UserPasswordCredential { hash: hash.clone() }),
+                _ => None,
+            };
+            if let Some(password) = password {
+                PasswordCredential::ActiveModel {
+                    user_id: Set(model.id),
+                    id: Set(Uuid::new_v4()),
+                    ..password.into()
+                }
+                .insert(&txn)
+                .await?;
+            }
+
+            for key in &user.public_keys {
+                let key = russh::keys::PublicKey::from_openssh(key)
+                    .with_context(|| format!("User {}: invalid public key", user.username))?;
+                let label = match key.comment() {
+                    "" => "Bootstrap".to_owned(),
+                    comment => comment.to_owned(),
+                };
+                PublicKeyCredential::ActiveModel {
+                    user_id: Set(model.id),
+                    id: Set(Uuid::new_v4()),
+                    date_added: Set(Some(Utc::now())),
+                    last_used: Set(None),
+                    label: Set(label),
+                    ..UserPublicKeyCredential {
+                        key: key.to_openssh()?.into(),
+                    }
+                    .into()
+                }
+                .insert(&txn)
+                .await?;
+            }
+
+            for role in &user.roles {
+                UserRoleAssignment::ActiveModel {
+                    user_id: Set(model.id),
+                    role_id: Set(role_id(role)?),
+                    ..Default::default()
+                }
+                .insert(&txn)
+                .await?;
+            }
+        }
+
+        for target in &self.targets {
+            let model = Target::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                name: Set(target.name.clone()),
+                kind: Set((&target.options).into()),
+                options: Set(serde_json::to_value(target.options.clone())?),
+                honeypot: Set(false),
+                host_overrides: Set(None),
+                max_concurrent_sessions: Set(None),
+                session_limit_message: Set(None),
+                monitor: Set(None),
+                idle_timeout_seconds: Set(None),
+                draining: Set(false),
+            }
+            .insert(&txn)
+            .await
+            .with_context(|| format!("creating target {}", target.name))?;
+
+            for role in &target.roles {
+                TargetRoleAssignment::ActiveModel {
+                    target_id: Set(model.id),
+                    role_id: Set(role_id(role)?),
+                    ..Default::default()
+                }
+                .insert(&txn)
+                .await?;
+            }
+        }
+
+        txn.commit().await?;
+
+        info!(
+            roles = self.roles.len(),
+            users = self.users.len(),
+            targets = self.targets.len(),
+            "Applied bootstrap file"
+        );
+        Ok(())
+    }
+}
@@ -1,46 +1,34 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Result};
 use tracing::*;
-use warpgate_common::{TlsCertificateBundle, TlsPrivateKey};
+use warpgate_core::{check_certificates, check_listeners, ConfigDiagnosticSeverity};
 
 use crate::config::load_config;
 
 pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     let config = load_config(&cli.config, true)?;
-    if config.store.http.enable {
-        TlsCertificateBundle::from_file(
-            config
-                .paths_relative_to
-                .join(&config.store.http.certificate),
-        )
-        .await
-        .with_context(|| "Checking HTTPS certificate".to_string())?;
-        TlsPrivateKey::from_file(config.paths_relative_to.join(&config.store.http.key))
-            .await
-            .with_context(|| "Checking HTTPS key".to_string())?;
-    }
-    if config.store.mysql.enable {
-        TlsCertificateBundle::from_file(
-            config
-                .paths_relative_to
-                .join(&config.store.mysql.certificate),
-        )
-        .await
-        .with_context(|| "Checking MySQL certificate".to_string())?;
-        TlsPrivateKey::from_file(config.paths_relative_to.join(&config.store.mysql.key))
-            .await
-            .with_context(|| "Checking MySQL key".to_string())?;
+
+    let mut diagnostics = check_certificates(&config).await;
+    diagnostics.extend(check_listeners(&config, false));
+
+    let mut failed = false;
+    for diagnostic in diagnostics {
+        let hint = diagnostic.hint.unwrap_or_default();
+        match diagnostic.severity {
+            ConfigDiagnosticSeverity::Ok => {
+                debug!(subject=%diagnostic.subject, "{}", diagnostic.message)
+            }
+            ConfigDiagnosticSeverity::Warning => {
+                warn!(subject=%diagnostic.subject, %hint, "{}", diagnostic.message)
+            }
+            ConfigDiagnosticSeverity::Error => {
+                error!(subject=%diagnostic.subject, %hint, "{}", diagnostic.message);
+                failed = true;
+            }
+        }
     }
-    if config.store.postgres.enable {
-        TlsCertificateBundle::from_file(
-            config
-                .paths_relative_to
-                .join(&config.store.postgres.certificate),
-        )
-        .await
-        .with_context(|| "Checking PostgreSQL certificate".to_string())?;
-        TlsPrivateKey::from_file(config.paths_relative_to.join(&config.store.postgres.key))
-            .await
-            .with_context(|| "Checking PostgreSQL key".to_string())?;
+
+    if failed {
+        bail!("Problems found in the configuration");
     }
     info!("No problems found");
     Ok(())
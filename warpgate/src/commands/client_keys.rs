@@ -1,15 +1,27 @@
 use anyhow::Result;
 
 use crate::config::load_config;
+use crate::ClientKeysCommand;
 
-pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
+pub(crate) async fn command(cli: &crate::Cli, command: &Option<ClientKeysCommand>) -> Result<()> {
     let config = load_config(&cli.config, true)?;
-    let keys = warpgate_protocol_ssh::load_client_keys(&config)?;
-    println!("Warpgate SSH client keys:");
-    println!("(add these to your target's authorized_keys file)");
-    println!();
-    for key in keys {
-        println!("{}", key.public_key().to_openssh()?);
+    match command {
+        None => {
+            let keys = warpgate_protocol_ssh::load_client_keys(&config)?;
+            println!("Warpgate SSH client keys:");
+            println!("(add these to your target's authorized_keys file)");
+            println!();
+            for key in keys {
+                println!("{}: {}", key.name, key.key.public_key().to_openssh()?);
+            }
+        }
+        Some(ClientKeysCommand::Generate { name, algorithm }) => {
+            let key = warpgate_protocol_ssh::generate_client_key(&config, name, *algorithm)?;
+            println!("{}", key.key.public_key().to_openssh()?);
+        }
+        Some(ClientKeysCommand::Delete { name }) => {
+            warpgate_protocol_ssh::delete_client_key(&config, name)?;
+        }
     }
     Ok(())
 }
@@ -0,0 +1,45 @@
+use anyhow::{Context, Result};
+use tracing::*;
+use warpgate_core::Services;
+use warpgate_protocol_ssh::KnownHosts;
+
+use crate::config::load_config;
+use crate::KnownHostsCommand;
+
+pub(crate) async fn command(cli: &crate::Cli, command: &KnownHostsCommand) -> Result<()> {
+    let config = load_config(&cli.config, true)?;
+    let services = Services::new(config, None).await?;
+    let mut known_hosts = KnownHosts::new(&services.db);
+
+    match command {
+        KnownHostsCommand::Import { path } => {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            let job = services.jobs.start(
+                "known_hosts_import",
+                format!("Importing {}", path.display()),
+            );
+            let result = known_hosts.import(&text, &job).await;
+            job.finish(&result);
+            let result = result?;
+            for reason in &result.skipped {
+                warn!("Skipped {reason}");
+            }
+            info!(
+                imported = result.imported,
+                duplicates = result.duplicates,
+                skipped = result.skipped.len(),
+                "Imported known hosts"
+            );
+        }
+        KnownHostsCommand::Export { path } => {
+            let text = known_hosts.export().await?;
+            match path {
+                Some(path) => std::fs::write(path, text)
+                    .with_context(|| format!("writing {}", path.display()))?,
+                None => print!("{text}"),
+            }
+        }
+    }
+    Ok(())
+}
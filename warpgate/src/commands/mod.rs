@@ -1,7 +1,10 @@
+mod bootstrap;
 pub mod check;
 pub mod client_keys;
 mod common;
+pub mod known_hosts;
 pub mod recover_access;
 pub mod run;
+pub mod sessions;
 pub mod setup;
 pub mod test_target;
@@ -18,6 +18,7 @@ pub(crate) async fn command(cli: &crate::Cli, username: &Option<String>) -> Resu
     let services = Services::new(config.clone(), None).await?;
     warpgate_protocol_ssh::generate_host_keys(&config)?;
     warpgate_protocol_ssh::generate_client_keys(&config)?;
+    warpgate_protocol_ssh::generate_ssh_ca_key(&config)?;
 
     let theme = ColorfulTheme::default();
     let db = services.db.lock().await;
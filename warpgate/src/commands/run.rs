@@ -1,22 +1,38 @@
 use std::path::PathBuf;
 
+use std::future::Future;
+use std::time::Duration;
+
 use anyhow::Result;
 use futures::{FutureExt, StreamExt};
 #[cfg(target_os = "linux")]
 use sd_notify::NotifyState;
+use tokio::runtime::Handle;
 use tokio::signal::unix::SignalKind;
 use tracing::*;
 use warpgate_core::db::cleanup_db;
 use warpgate_core::logging::install_database_logger;
-use warpgate_core::{ConfigProvider, ProtocolServer, Services};
-use warpgate_protocol_http::HTTPProtocolServer;
-use warpgate_protocol_mysql::MySQLProtocolServer;
-use warpgate_protocol_postgres::PostgresProtocolServer;
-use warpgate_protocol_ssh::SSHProtocolServer;
+use warpgate_core::recordings::RecordingReplicator;
+use warpgate_core::{
+    check_certificate_expiry, check_target_health, close_idle_sessions,
+    deactivate_expired_accounts, remove_expired_direct_credentials, ConfigProvider, Services,
+    ACCOUNT_LIFECYCLE_INTERVAL, CERTIFICATE_EXPIRY_CHECK_INTERVAL,
+    DIRECT_CREDENTIALS_CLEANUP_INTERVAL, IDLE_SESSION_CHECK_INTERVAL, TARGET_HEALTH_CHECK_INTERVAL,
+};
+use warpgate_protocol_ssh::run_discovery;
 
+use super::test_target::{start_monitored_target_tests, TARGET_MONITOR_TICK};
 use crate::config::{load_config, watch_config};
+use crate::protocols::protocol_registry;
+use crate::runtime::DedicatedRuntimes;
+
+const SESSION_REAPER_INTERVAL: Duration = Duration::from_secs(60 * 15);
 
-pub(crate) async fn command(cli: &crate::Cli, enable_admin_token: bool) -> Result<()> {
+pub(crate) async fn command(
+    cli: &crate::Cli,
+    enable_admin_token: bool,
+    disabled_protocols: &[String],
+) -> Result<()> {
     let version = env!("CARGO_PKG_VERSION");
     info!(%version, "Warpgate");
 
@@ -37,43 +53,33 @@ pub(crate) async fn command(cli: &crate::Cli, enable_admin_token: bool) -> Resul
 
     let services = Services::new(config.clone(), admin_token).await?;
 
-    install_database_logger(services.db.clone());
+    install_database_logger(services.db.clone(), services.analytics_sink.clone());
 
     let mut protocol_futures = futures::stream::FuturesUnordered::new();
+    let mut runtimes = DedicatedRuntimes::default();
 
-    if config.store.ssh.enable {
-        protocol_futures.push(
-            SSHProtocolServer::new(&services)
-                .await?
-                .run(config.store.ssh.listen.clone())
-                .boxed(),
-        );
-    }
-
-    if config.store.http.enable {
-        protocol_futures.push(
-            HTTPProtocolServer::new(&services)
-                .await?
-                .run(config.store.http.listen.clone())
-                .boxed(),
-        );
-    }
-
-    if config.store.mysql.enable {
-        protocol_futures.push(
-            MySQLProtocolServer::new(&services)
-                .await?
-                .run(config.store.mysql.listen.clone())
-                .boxed(),
-        );
+    let mut registry = protocol_registry();
+    for name in disabled_protocols {
+        if !registry.set_enabled(name, false) {
+            anyhow::bail!(
+                "Unknown protocol {name}, expected one of: {}",
+                registry.names().join(", ")
+            );
+        }
     }
 
-    if config.store.postgres.enable {
+    let listeners = registry.listeners(&config.store);
+    for (factory, listen) in &listeners {
+        let runtime = runtimes.get(
+            &format!("warpgate-{}", factory.name()),
+            factory.runtime_config(&config.store),
+        )?;
         protocol_futures.push(
-            PostgresProtocolServer::new(&services)
-                .await?
-                .run(config.store.postgres.listen.clone())
-                .boxed(),
+            spawn_server(
+                runtime,
+                factory.create(&services).await?.run(listen.clone()),
+            )
+            .boxed(),
         );
     }
 
@@ -81,7 +87,7 @@ pub(crate) async fn command(cli: &crate::Cli, enable_admin_token: bool) -> Resul
         let services = services.clone();
         async move {
             loop {
-                let retention = { services.config.lock().await.store.log.retention };
+                let retention = { services.config.load().store.log.retention };
                 let interval = retention / 10;
                 #[allow(clippy::explicit_auto_deref)]
                 match cleanup_db(
@@ -94,34 +100,122 @@ pub(crate) async fn command(cli: &crate::Cli, enable_admin_token: bool) -> Resul
                     Err(error) => error!(?error, "Failed to cleanup the database"),
                     Ok(_) => debug!("Database cleaned up, next in {:?}", interval),
                 }
+                if let Err(error) = services.auth_failures.cleanup().await {
+                    error!(?error, "Failed to clean up auth failures");
+                }
+                if let Err(error) = services.auth_rate_limiter.cleanup().await {
+                    error!(?error, "Failed to clean up auth rate limits");
+                }
+                if let Err(error) = services.ip_bans.cleanup().await {
+                    error!(?error, "Failed to clean up IP bans");
+                }
+                if let Err(error) = services.notifications.cleanup(retention).await {
+                    error!(?error, "Failed to clean up notifications");
+                }
                 tokio::time::sleep(interval).await;
             }
         }
     });
 
-    if console::user_attended() {
-        info!("--------------------------------------------");
-        info!("Warpgate is now running.");
-        if config.store.ssh.enable {
-            info!("Accepting SSH connections on {:?}", config.store.ssh.listen);
+    tokio::spawn({
+        let reaper = services.reaper.clone();
+        async move {
+            loop {
+                if let Err(error) = reaper.lock().await.run().await {
+                    error!(?error, "Failed to reconcile sessions and recordings");
+                }
+                tokio::time::sleep(SESSION_REAPER_INTERVAL).await;
+            }
         }
-        if config.store.http.enable {
-            info!(
-                "Accepting HTTP connections on https://{:?}",
-                config.store.http.listen
-            );
+    });
+
+    tokio::spawn({
+        let services = services.clone();
+        async move {
+            loop {
+                if let Err(error) = deactivate_expired_accounts(&services).await {
+                    error!(?error, "Failed to deactivate expired accounts");
+                }
+                tokio::time::sleep(ACCOUNT_LIFECYCLE_INTERVAL).await;
+            }
         }
-        if config.store.mysql.enable {
-            info!(
-                "Accepting MySQL connections on {:?}",
-                config.store.mysql.listen
-            );
+    });
+
+    tokio::spawn({
+        let services = services.clone();
+        async move {
+            loop {
+                if let Err(error) = check_target_health(&services).await {
+                    error!(?error, "Failed to check target health");
+                }
+                tokio::time::sleep(TARGET_HEALTH_CHECK_INTERVAL).await;
+            }
         }
-        if config.store.postgres.enable {
-            info!(
-                "Accepting PostgreSQL connections on {:?}",
-                config.store.postgres.listen
-            );
+    });
+
+    tokio::spawn({
+        let services = services.clone();
+        async move {
+            loop {
+                if let Err(error) = start_monitored_target_tests(&services).await {
+                    error!(?error, "Failed to run the scheduled target tests");
+                }
+                tokio::time::sleep(TARGET_MONITOR_TICK).await;
+            }
+        }
+    });
+
+    tokio::spawn({
+        let services = services.clone();
+        async move {
+            loop {
+                if let Err(error) = check_certificate_expiry(&services).await {
+                    error!(?error, "Failed to check certificate expiry");
+                }
+                tokio::time::sleep(CERTIFICATE_EXPIRY_CHECK_INTERVAL).await;
+            }
+        }
+    });
+
+    tokio::spawn({
+        let services = services.clone();
+        async move {
+            loop {
+                if let Err(error) = remove_expired_direct_credentials(&services).await {
+                    error!(?error, "Failed to remove expired direct credentials");
+                }
+                tokio::time::sleep(DIRECT_CREDENTIALS_CLEANUP_INTERVAL).await;
+            }
+        }
+    });
+
+    tokio::spawn({
+        let services = services.clone();
+        async move {
+            loop {
+                close_idle_sessions(&services).await;
+                tokio::time::sleep(IDLE_SESSION_CHECK_INTERVAL).await;
+            }
+        }
+    });
+
+    if let Some(replication) = config.store.recordings.replication.clone() {
+        let replicator = RecordingReplicator::new(
+            services.db.clone(),
+            services.recordings.clone(),
+            replication,
+            services.jobs.clone(),
+        )?;
+        tokio::spawn(replicator.run());
+    }
+
+    tokio::spawn(run_discovery(services.clone()));
+
+    if console::user_attended() {
+        info!("--------------------------------------------");
+        info!("Warpgate is now running.");
+        for (factory, listen) in &listeners {
+            info!("Accepting {} connections on {:?}", factory.name(), listen);
         }
         info!("--------------------------------------------");
     }
@@ -184,10 +278,21 @@ pub(crate) async fn command(cli: &crate::Cli, enable_admin_token: bool) -> Resul
     Ok(())
 }
 
+/// Runs a protocol server on the given runtime, so that its
+/// connection tasks end up on that runtime too
+async fn spawn_server<F>(runtime: Handle, server: F) -> Result<()>
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    runtime.spawn(server).await?
+}
+
 pub async fn watch_config_and_reload(path: PathBuf, services: Services) -> Result<()> {
-    let mut reload_event = watch_config(path, services.config.clone())?;
+    let mut reload_event = services.config.subscribe();
+    watch_config(path, services.config.clone())?;
 
-    while let Ok(()) = reload_event.recv().await {
+    while reload_event.changed().await.is_ok() {
+        services.dns.reload(&services.config.load().store.dns);
         let state = services.state.lock().await;
         let mut cp = services.config_provider.lock().await;
         for (id, session) in state.sessions.iter() {
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use warpgate_core::SessionSnapshot;
+
+use crate::config::load_config;
+use crate::SessionsCommand;
+
+const TAIL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const PAGE_SIZE: u64 = 100;
+
+#[derive(Deserialize)]
+struct SessionsPage {
+    items: Vec<SessionSnapshot>,
+    total: u64,
+}
+
+#[derive(Serialize)]
+struct SessionEvent<'a> {
+    event: &'static str,
+    session: &'a SessionSnapshot,
+}
+
+struct AdminApiClient {
+    client: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl AdminApiClient {
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, format!("{}{path}", self.url.trim_end_matches('/')))
+            .header("X-Warpgate-Token", &self.token)
+    }
+
+    async fn sessions(&self, active_only: bool) -> Result<Vec<SessionSnapshot>> {
+        let mut sessions = vec![];
+        loop {
+            let page: SessionsPage = self
+                .request(reqwest::Method::GET, "/sessions")
+                .query(&[
+                    ("active_only", active_only.to_string()),
+                    ("offset", sessions.len().to_string()),
+                    ("limit", PAGE_SIZE.to_string()),
+                ])
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let done = page.items.is_empty();
+            sessions.extend(page.items);
+            if done || sessions.len() as u64 >= page.total {
+                return Ok(sessions);
+            }
+        }
+    }
+
+    async fn session(&self, id: &Uuid) -> Result<SessionSnapshot> {
+        Ok(self
+            .request(reqwest::Method::GET, &format!("/sessions/{id}"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn close_session(&self, id: &Uuid) -> Result<()> {
+        let response = self
+            .request(reqwest::Method::POST, &format!("/sessions/{id}/close"))
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            anyhow::bail!("Session {id} is not active");
+        }
+        response.error_for_status()?;
+        Ok(())
+    }
+}
+
+pub(crate) async fn command(
+    cli: &crate::Cli,
+    url: &Option<String>,
+    command: &SessionsCommand,
+) -> Result<()> {
+    let token = std::env::var("WARPGATE_ADMIN_TOKEN")
+        .context("WARPGATE_ADMIN_TOKEN must be set to the admin token of the running instance")?;
+
+    let api = match url {
+        Some(url) => AdminApiClient {
+            client: reqwest::Client::new(),
+            url: url.clone(),
+            token,
+        },
+        None => {
+            let config = load_config(&cli.config, true)?;
+            // The local listener normally uses a self-signed certificate
+            AdminApiClient {
+                client: reqwest::Client::builder()
+                    .danger_accept_invalid_certs(true)
+                    .build()?,
+                url: format!(
+                    "https://127.0.0.1:{}/@warpgate/admin/api",
+                    config.store.http.listen.port()
+                ),
+                token,
+            }
+        }
+    };
+
+    match command {
+        SessionsCommand::List { all, json } => {
+            let sessions = api.sessions(!all).await?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&sessions)?);
+            } else {
+                print_table_header(false);
+                for session in &sessions {
+                    print_table_row(None, session);
+                }
+            }
+        }
+        SessionsCommand::Kill { id } => {
+            api.close_session(id).await?;
+        }
+        SessionsCommand::Tail { json } => {
+            let print = |event: &'static str, session: &SessionSnapshot| -> Result<()> {
+                if *json {
+                    println!(
+                        "{}",
+                        serde_json::to_string(&SessionEvent { event, session })?
+                    );
+                } else {
+                    print_table_row(Some(event), session);
+                }
+                Ok(())
+            };
+
+            if !json {
+                print_table_header(true);
+            }
+
+            let mut known = HashMap::new();
+            for session in api.sessions(true).await? {
+                print("active", &session)?;
+                known.insert(session.id, session);
+            }
+
+            loop {
+                tokio::time::sleep(TAIL_POLL_INTERVAL).await;
+
+                let mut current = HashMap::new();
+                for session in api.sessions(true).await? {
+                    match known.remove(&session.id) {
+                        None => print("started", &session)?,
+                        Some(previous) if !same_identity(&previous, &session) => {
+                            print("updated", &session)?
+                        }
+                        Some(_) => (),
+                    }
+                    current.insert(session.id, session);
+                }
+
+                for (id, previous) in known.drain() {
+                    let session = api.session(&id).await.unwrap_or(previous);
+                    print("ended", &session)?;
+                }
+                known = current;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn same_identity(a: &SessionSnapshot, b: &SessionSnapshot) -> bool {
+    a.username == b.username
+        && a.target.as_ref().map(|t| &t.name) == b.target.as_ref().map(|t| &t.name)
+}
+
+fn print_table_header(with_event: bool) {
+    if with_event {
+        print!("{:<8} ", "EVENT");
+    }
+    println!(
+        "{:<36} {:<10} {:<20} {:<20} {:<19} {:<19}",
+        "ID", "PROTOCOL", "USER", "TARGET", "STARTED", "ENDED"
+    );
+}
+
+fn print_table_row(event: Option<&str>, session: &SessionSnapshot) {
+    const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+    if let Some(event) = event {
+        print!("{event:<8} ");
+    }
+    println!(
+        "{:<36} {:<10} {:<20} {:<20} {:<19} {:<19}",
+        session.id,
+        session.protocol,
+        session.username.as_deref().unwrap_or("-"),
+        session.target.as_ref().map(|t| &t.name[..]).unwrap_or("-"),
+        session.started.format(TIME_FORMAT),
+        session
+            .ended
+            .map(|t| t.format(TIME_FORMAT).to_string())
+            .unwrap_or_else(|| "-".into()),
+    );
+}
@@ -13,13 +13,14 @@ use tracing::*;
 use uuid::Uuid;
 use warpgate_common::helpers::fs::{secure_directory, secure_file};
 use warpgate_common::{
-    HttpConfig, ListenEndpoint, MySqlConfig, PostgresConfig, Secret, SshConfig,
+    HttpConfig, ListenEndpoint, MySqlConfig, PostgresConfig, RedisConfig, Secret, SshConfig,
     UserPasswordCredential, UserRequireCredentialsPolicy, WarpgateConfigStore, WarpgateError,
 };
 use warpgate_core::consts::{BUILTIN_ADMIN_ROLE_NAME, BUILTIN_ADMIN_USERNAME};
 use warpgate_core::Services;
 use warpgate_db_entities::{PasswordCredential, Role, User, UserRoleAssignment};
 
+use crate::commands::bootstrap::Bootstrap;
 use crate::commands::common::{assert_interactive_terminal, is_docker};
 use crate::config::load_config;
 use crate::Commands;
@@ -59,6 +60,15 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
         assert_interactive_terminal();
     }
 
+    // Load early so that a broken file fails the setup before anything is written
+    let bootstrap = match &cli.command {
+        Commands::UnattendedSetup {
+            bootstrap: Some(path),
+            ..
+        } => Some(Bootstrap::load(path)?),
+        _ => None,
+    };
+
     let mut config_dir = cli.config.parent().unwrap_or_else(|| Path::new(&"."));
     if config_dir.as_os_str().is_empty() {
         config_dir = Path::new(&".");
@@ -225,6 +235,25 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
             }
         }
     }
+    if let Commands::UnattendedSetup { redis_port, .. } = &cli.command {
+        if let Some(redis_port) = redis_port {
+            store.redis.enable = true;
+            store.redis.listen =
+                ListenEndpoint::from(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), *redis_port));
+        }
+    } else if !is_docker() {
+        store.redis.enable = dialoguer::Confirm::with_theme(&theme)
+            .default(false)
+            .with_prompt("Accept Redis connections?")
+            .interact()?;
+
+        if store.redis.enable {
+            store.redis.listen = prompt_endpoint(
+                "Endpoint to listen for Redis connections on",
+                RedisConfig::default().listen,
+            );
+        }
+    }
 
     store.http.certificate = data_path
         .join("tls.certificate.pem")
@@ -239,6 +268,9 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     store.postgres.certificate = store.http.certificate.clone();
     store.postgres.key = store.http.key.clone();
 
+    store.redis.certificate = store.http.certificate.clone();
+    store.redis.key = store.http.key.clone();
+
     // ---
 
     store.ssh.keys = data_path.join("ssh-keys").to_string_lossy().to_string();
@@ -258,6 +290,10 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     }
     store.recordings.path = data_path.join("recordings").to_string_lossy().to_string();
 
+    if let Some(bootstrap) = &bootstrap {
+        store.sso_providers = bootstrap.sso_providers.clone();
+    }
+
     // ---
 
     let admin_password = Secret::new(
@@ -295,6 +331,7 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
     let services = Services::new(config.clone(), None).await?;
     warpgate_protocol_ssh::generate_host_keys(&config)?;
     warpgate_protocol_ssh::generate_client_keys(&config)?;
+    warpgate_protocol_ssh::generate_ssh_ca_key(&config)?;
 
     {
         let db = services.db.lock().await;
@@ -321,6 +358,7 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
                     credential_policy: Set(serde_json::to_value(
                         None::<UserRequireCredentialsPolicy>,
                     )?),
+                    ..Default::default()
                 };
                 values.insert(&*db).await.map_err(WarpgateError::from)?
             }
@@ -348,6 +386,10 @@ pub(crate) async fn command(cli: &crate::Cli) -> Result<()> {
             };
             values.insert(&*db).await.map_err(WarpgateError::from)?;
         }
+
+        if let Some(bootstrap) = &bootstrap {
+            bootstrap.apply(&db).await?;
+        }
     }
 
     {
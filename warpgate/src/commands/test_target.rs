@@ -1,52 +1,134 @@
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
+use serde::Serialize;
 use tracing::*;
-use warpgate_common::TargetOptions;
-use warpgate_core::{ConfigProvider, ProtocolServer, Services, TargetTestError};
+use warpgate_common::{Target, TargetOptions};
+use warpgate_core::{ConfigProvider, DynProtocolServer, Services, TargetTestError};
 
 use crate::config::load_config;
-use crate::protocols::ProtocolServerEnum;
+use crate::protocols::protocol_registry;
+
+const REACHABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+const TEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often [start_monitored_target_tests] looks for due tests
+pub(crate) const TARGET_MONITOR_TICK: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Ok,
+    Failed,
+    /// Not applicable to the target, e.g. authentication for HTTP targets
+    Skipped,
+}
+
+#[derive(Serialize)]
+struct TargetReport {
+    target: String,
+    kind: &'static str,
+    reachable: bool,
+    /// TCP connect time to the target's address
+    latency_ms: Option<u64>,
+    /// Not set when the connection didn't get as far as authentication
+    auth: Option<CheckStatus>,
+    /// Host key or TLS certificate check
+    trust: Option<CheckStatus>,
+    error: Option<String>,
+}
+
+impl TargetReport {
+    fn passed(&self) -> bool {
+        self.reachable && self.error.is_none()
+    }
+}
 
-pub(crate) async fn command(cli: &crate::Cli, target_name: &String) -> Result<()> {
+pub(crate) async fn command(
+    cli: &crate::Cli,
+    target_name: &Option<String>,
+    all: bool,
+    json: bool,
+) -> Result<()> {
     let config = load_config(&cli.config, true)?;
     let services = Services::new(config.clone(), None).await?;
 
-    let Some(target) = services
-        .config_provider
-        .lock()
-        .await
-        .list_targets()
-        .await?
-        .iter()
-        .find(|x| &x.name == target_name)
-        .cloned()
-    else {
-        error!("Target not found: {}", target_name);
-        return Ok(());
-    };
+    let targets = services.config_provider.lock().await.list_targets().await?;
 
-    let s: ProtocolServerEnum = match target.options {
-        TargetOptions::Ssh(_) => ProtocolServerEnum::SSHProtocolServer(
-            warpgate_protocol_ssh::SSHProtocolServer::new(&services).await?,
-        ),
-        TargetOptions::Http(_) => ProtocolServerEnum::HTTPProtocolServer(
-            warpgate_protocol_http::HTTPProtocolServer::new(&services).await?,
-        ),
-        TargetOptions::MySql(_) => ProtocolServerEnum::MySQLProtocolServer(
-            warpgate_protocol_mysql::MySQLProtocolServer::new(&services).await?,
-        ),
-        TargetOptions::Postgres(_) => ProtocolServerEnum::PostgresProtocolServer(
-            warpgate_protocol_postgres::PostgresProtocolServer::new(&services).await?,
-        ),
-        TargetOptions::WebAdmin(_) => {
-            error!("Unsupported target type");
+    if !all {
+        let Some(target) = targets
+            .into_iter()
+            .find(|x| Some(&x.name) == target_name.as_ref())
+        else {
+            error!("Target not found: {}", target_name.as_deref().unwrap_or(""));
             return Ok(());
+        };
+        return test_single_target(&services, target).await;
+    }
+
+    let mut reports = vec![];
+    for target in targets {
+        if let TargetOptions::WebAdmin(_) = target.options {
+            continue;
         }
+        reports.push(check_target(&services, target).await?);
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        print_matrix(&reports);
+    }
+
+    if reports.iter().all(TargetReport::passed) {
+        Ok(())
+    } else {
+        anyhow::bail!("Some targets failed the connection test")
+    }
+}
+
+/// Starts the scheduled tests that are due, see [warpgate_common::TargetMonitorOptions]
+pub(crate) async fn start_monitored_target_tests(services: &Services) -> Result<()> {
+    let targets = services.config_provider.lock().await.list_targets().await?;
+    for target in services.target_monitor.take_due(&targets, Instant::now()) {
+        let services = services.clone();
+        tokio::spawn(async move {
+            let result = match check_target(&services, target.clone()).await {
+                Ok(report) if report.passed() => Ok(()),
+                Ok(report) => Err(report.error.unwrap_or_else(|| "unreachable".into())),
+                Err(error) => Err(error.to_string()),
+            };
+            services
+                .target_monitor
+                .record(&services.alerts, &target, result)
+                .await;
+        });
+    }
+    Ok(())
+}
+
+async fn protocol_server(
+    services: &Services,
+    target: &Target,
+) -> Result<Option<Box<dyn DynProtocolServer>>> {
+    let Some(factory) = protocol_registry().for_target(&target.options) else {
+        return Ok(None);
+    };
+    Ok(Some(factory.create(services).await?))
+}
+
+async fn test_single_target(services: &Services, target: Target) -> Result<()> {
+    let Some(s) = protocol_server(services, &target).await? else {
+        error!("Unsupported target type");
+        return Ok(());
     };
 
     match s.test_target(target).await {
         Err(TargetTestError::AuthenticationError) => {
             error!("Authentication failed");
         }
+        Err(TargetTestError::Untrusted(error)) => {
+            error!(?error, "Target identity not trusted");
+        }
         Err(TargetTestError::ConnectionError(error)) => {
             error!(?error, "Connection error");
         }
@@ -67,3 +149,151 @@ pub(crate) async fn command(cli: &crate::Cli, target_name: &String) -> Result<()
 
     anyhow::bail!("Connection test failed")
 }
+
+fn target_address(options: &TargetOptions) -> Option<(String, u16)> {
+    match options {
+        TargetOptions::Ssh(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Docker(options) => Some((options.ssh.host.clone(), options.ssh.port)),
+        TargetOptions::Ipmi(_) => None,
+        TargetOptions::MySql(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Postgres(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Redis(options) => Some((options.host.clone(), options.port)),
+        TargetOptions::Http(options) => {
+            let url = url::Url::parse(&options.url).ok()?;
+            Some((url.host_str()?.to_owned(), url.port_or_known_default()?))
+        }
+        TargetOptions::WebAdmin(_) => None,
+    }
+}
+
+async fn check_target(services: &Services, target: Target) -> Result<TargetReport> {
+    let mut report = TargetReport {
+        target: target.name.clone(),
+        kind: match target.options {
+            TargetOptions::Ssh(_) => "ssh",
+            TargetOptions::Http(_) => "http",
+            TargetOptions::MySql(_) => "mysql",
+            TargetOptions::Postgres(_) => "postgres",
+            TargetOptions::Redis(_) => "redis",
+            TargetOptions::Docker(_) => "docker",
+            TargetOptions::Ipmi(_) => "ipmi",
+            TargetOptions::WebAdmin(_) => "web_admin",
+        },
+        reachable: false,
+        latency_ms: None,
+        auth: None,
+        trust: None,
+        error: None,
+    };
+
+    // SSM tunnels and IPMI (over UDP) can't be probed with a TCP connection,
+    // the protocol test covers them instead
+    let probe = match &target.options {
+        TargetOptions::Ssh(options) => options.aws_ssm.is_none(),
+        TargetOptions::Docker(options) => options.ssh.aws_ssm.is_none(),
+        TargetOptions::Ipmi(_) => false,
+        _ => true,
+    };
+
+    if !probe {
+        report.reachable = true;
+    } else {
+        let Some((host, port)) = target_address(&target.options) else {
+            report.error = Some("invalid target address".into());
+            return Ok(report);
+        };
+
+        let resolver = services.dns.for_target(&target);
+        let started = Instant::now();
+        match tokio::time::timeout(REACHABILITY_TIMEOUT, resolver.connect(&host, port)).await {
+            Ok(Ok(_)) => {
+                report.reachable = true;
+                report.latency_ms = Some(started.elapsed().as_millis() as u64);
+            }
+            Ok(Err(error)) => {
+                report.error = Some(error.to_string());
+                return Ok(report);
+            }
+            Err(_) => {
+                report.error = Some("timed out".into());
+                return Ok(report);
+            }
+        }
+    }
+
+    let Some(s) = protocol_server(services, &target).await? else {
+        return Ok(report);
+    };
+
+    // Warpgate doesn't log in to HTTP targets, and can only vouch
+    // for their identity if it verifies their certificate
+    let (auth_checked, trust_checked) = match &target.options {
+        TargetOptions::Http(options) => (
+            false,
+            options.url.starts_with("https://") && options.tls.verify,
+        ),
+        _ => (true, true),
+    };
+    let checked = |checked| {
+        if checked {
+            CheckStatus::Ok
+        } else {
+            CheckStatus::Skipped
+        }
+    };
+
+    match tokio::time::timeout(TEST_TIMEOUT, s.test_target(target)).await {
+        Ok(Ok(())) => {
+            report.auth = Some(checked(auth_checked));
+            report.trust = Some(checked(trust_checked));
+        }
+        Ok(Err(TargetTestError::AuthenticationError)) => {
+            report.auth = Some(CheckStatus::Failed);
+            report.trust = Some(checked(trust_checked));
+            report.error = Some(TargetTestError::AuthenticationError.to_string());
+        }
+        Ok(Err(error @ TargetTestError::Untrusted(_))) => {
+            report.trust = Some(CheckStatus::Failed);
+            report.error = Some(error.to_string());
+        }
+        Ok(Err(error)) => {
+            report.error = Some(error.to_string());
+        }
+        Err(_) => {
+            report.error = Some("connection test timed out".into());
+        }
+    }
+
+    Ok(report)
+}
+
+fn print_matrix(reports: &[TargetReport]) {
+    fn status(status: &Option<CheckStatus>) -> &'static str {
+        match status {
+            Some(CheckStatus::Ok) => "ok",
+            Some(CheckStatus::Failed) => "FAILED",
+            Some(CheckStatus::Skipped) => "skipped",
+            None => "-",
+        }
+    }
+
+    println!(
+        "{:<24} {:<9} {:<10} {:>8} {:<7} {:<7} ERROR",
+        "TARGET", "KIND", "REACHABLE", "LATENCY", "AUTH", "TRUST"
+    );
+    for report in reports {
+        println!(
+            "{:<24} {:<9} {:<10} {:>8} {:<7} {:<7} {}",
+            report.target,
+            report.kind,
+            if report.reachable { "yes" } else { "NO" },
+            report
+                .latency_ms
+                .map(|x| format!("{x}ms"))
+                .unwrap_or_else(|| "-".into()),
+            status(&report.auth),
+            status(&report.trust),
+            report.error.as_deref().unwrap_or(""),
+        );
+    }
+}
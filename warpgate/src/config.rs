@@ -4,10 +4,11 @@ use std::sync::Arc;
 use anyhow::{Context, Result};
 use config::{Config, Environment, File};
 use notify::{recommended_watcher, RecursiveMode, Watcher};
-use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::sync::mpsc;
 use tracing::*;
 use warpgate_common::helpers::fs::secure_file;
-use warpgate_common::{WarpgateConfig, WarpgateConfigStore};
+use warpgate_common::{set_trusted_ca_certificates, WarpgateConfig, WarpgateConfigStore};
+use warpgate_core::SharedConfig;
 
 pub fn load_config(path: &Path, secure: bool) -> Result<WarpgateConfig> {
     let mut store: serde_yaml::Value = Config::builder()
@@ -34,6 +35,13 @@ pub fn load_config(path: &Path, secure: bool) -> Result<WarpgateConfig> {
 
     info!("Using config: {path:?}");
     config.validate();
+
+    set_trusted_ca_certificates(
+        config
+            .load_trusted_ca_certificates()
+            .context("Could not load outbound_tls.trusted_ca_certificates")?,
+    );
+
     Ok(config)
 }
 
@@ -75,8 +83,8 @@ fn check_and_migrate_config(store: &mut serde_yaml::Value) {
 
 pub fn watch_config<P: AsRef<Path> + Send + 'static>(
     path: P,
-    config: Arc<Mutex<WarpgateConfig>>,
-) -> Result<broadcast::Receiver<()>> {
+    config: Arc<SharedConfig>,
+) -> Result<()> {
     let (tx, mut rx) = mpsc::channel(16);
     let mut watcher = recommended_watcher(move |res| {
         let _ = tx.blocking_send(res);
@@ -84,7 +92,6 @@ pub fn watch_config<P: AsRef<Path> + Send + 'static>(
     watcher.watch(path.as_ref(), RecursiveMode::NonRecursive)?;
 
     let path = PathBuf::from(path.as_ref());
-    let (tx2, rx2) = broadcast::channel(16);
     tokio::spawn(async move {
         let _watcher = watcher; // avoid dropping the watcher
         loop {
@@ -93,8 +100,7 @@ pub fn watch_config<P: AsRef<Path> + Send + 'static>(
                     if event.kind.is_modify() {
                         match load_config(&path, false) {
                             Ok(new_config) => {
-                                *(config.lock().await) = new_config;
-                                let _ = tx2.send(());
+                                config.store(new_config);
                                 info!("Reloaded config");
                             }
                             Err(error) => error!(?error, "Failed to reload config"),
@@ -113,5 +119,5 @@ pub fn watch_config<P: AsRef<Path> + Send + 'static>(
         Ok::<_, anyhow::Error>(())
     });
 
-    Ok(rx2)
+    Ok(())
 }
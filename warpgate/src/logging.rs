@@ -3,13 +3,22 @@ use std::sync::Arc;
 use time::{format_description, UtcOffset};
 use tracing_subscriber::filter::dynamic_filter_fn;
 use tracing_subscriber::fmt::time::OffsetTime;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer};
 use warpgate_common::WarpgateConfig;
 use warpgate_core::logging::{make_database_logger_layer, make_socket_logger_layer};
 
-use crate::Cli;
+use crate::{Cli, Commands};
+
+/// Logs go to stdout, except when stdout carries machine-readable output
+fn log_writer(cli: &Cli) -> BoxMakeWriter {
+    match cli.command {
+        Commands::TestTarget { json: true, .. } => BoxMakeWriter::new(std::io::stderr),
+        _ => BoxMakeWriter::new(std::io::stdout),
+    }
+}
 
 pub async fn init_logging(config: Option<&WarpgateConfig>, cli: &Cli) {
     if std::env::var("RUST_LOG").is_err() {
@@ -43,6 +52,7 @@ pub async fn init_logging(config: Option<&WarpgateConfig>, cli: &Cli) {
             let env_filter = env_filter.clone();
             || {
                 tracing_subscriber::fmt::layer()
+                    .with_writer(log_writer(cli))
                     .with_ansi(enable_colors)
                     .with_timer(OffsetTime::new(
                         offset,
@@ -58,6 +68,7 @@ pub async fn init_logging(config: Option<&WarpgateConfig>, cli: &Cli) {
         .with(console::user_attended().then({
             || {
                 tracing_subscriber::fmt::layer()
+                    .with_writer(log_writer(cli))
                     .compact()
                     .with_ansi(enable_colors)
                     .with_target(false)
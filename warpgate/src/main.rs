@@ -2,12 +2,15 @@ mod commands;
 mod config;
 mod logging;
 mod protocols;
+mod runtime;
 use std::path::PathBuf;
 
 use anyhow::Result;
 use clap::{ArgAction, Parser};
 use logging::init_logging;
 use tracing::*;
+use uuid::Uuid;
+use warpgate_common::SshKeyAlgorithm;
 
 use crate::config::load_config;
 
@@ -59,6 +62,10 @@ pub(crate) enum Commands {
         #[clap(long)]
         postgres_port: Option<u16>,
 
+        /// Enable Redis and set port
+        #[clap(long)]
+        redis_port: Option<u16>,
+
         /// Enable session recording
         #[clap(long)]
         record_sessions: bool,
@@ -66,32 +73,123 @@ pub(crate) enum Commands {
         /// Password for the initial user (required if WARPGATE_ADMIN_PASSWORD env var is not set)
         #[clap(long)]
         admin_password: Option<String>,
+
+        /// YAML file with SSO providers, roles, users and targets to create on first boot
+        #[clap(long)]
+        bootstrap: Option<PathBuf>,
+    },
+    /// Show or manage Warpgate's SSH client keys
+    ClientKeys {
+        #[clap(subcommand)]
+        command: Option<ClientKeysCommand>,
+    },
+    /// Import or export trusted SSH target host keys
+    KnownHosts {
+        #[clap(subcommand)]
+        command: KnownHostsCommand,
     },
-    /// Show Warpgate's SSH client keys
-    ClientKeys,
     /// Run Warpgate
     Run {
         /// Enable an API token (passed via the `WARPGATE_ADMIN_TOKEN` env var) that automatically maps to the first admin user
         #[clap(long, action=ArgAction::SetTrue)]
         enable_admin_token: bool,
+
+        /// Don't start this protocol's server even if it's enabled in the config
+        #[clap(long, value_name = "PROTOCOL")]
+        disable_protocol: Vec<String>,
     },
     /// Perform basic config checks
     Check,
     /// Test the connection to a target host
     TestTarget {
-        #[clap(action=ArgAction::Set)]
-        target_name: String,
+        #[clap(action=ArgAction::Set, required_unless_present = "all")]
+        target_name: Option<String>,
+
+        /// Test every target and print a report
+        #[clap(long, conflicts_with = "target_name")]
+        all: bool,
+
+        /// Print the report as JSON (with `--all`)
+        #[clap(long, requires = "all")]
+        json: bool,
     },
     /// Reset password and auth policy for a user
     RecoverAccess {
         #[clap(action=ArgAction::Set)]
         username: Option<String>,
     },
+    /// List, close or follow sessions on a running instance through its admin API.
+    /// Requires the instance to run with `--enable-admin-token` and the same
+    /// `WARPGATE_ADMIN_TOKEN` env var to be set
+    Sessions {
+        /// Admin API base URL (defaults to the local HTTP listener)
+        #[clap(long)]
+        url: Option<String>,
+
+        #[clap(subcommand)]
+        command: SessionsCommand,
+    },
 }
 
-async fn _main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(clap::Subcommand)]
+pub(crate) enum ClientKeysCommand {
+    /// Generate an additional named client key
+    Generate {
+        #[clap(action=ArgAction::Set)]
+        name: String,
+
+        /// Key algorithm (ed25519, rsa, ecdsa-p256, ecdsa-p384, ecdsa-p521)
+        #[clap(long, default_value = "ed25519")]
+        algorithm: SshKeyAlgorithm,
+    },
+    /// Delete a named client key
+    Delete {
+        #[clap(action=ArgAction::Set)]
+        name: String,
+    },
+}
 
+#[derive(clap::Subcommand)]
+pub(crate) enum KnownHostsCommand {
+    /// Import an OpenSSH known_hosts file
+    Import {
+        #[clap(action=ArgAction::Set)]
+        path: PathBuf,
+    },
+    /// Export known hosts in the OpenSSH known_hosts format
+    Export {
+        /// Output file (defaults to stdout)
+        #[clap(action=ArgAction::Set)]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub(crate) enum SessionsCommand {
+    /// List active sessions
+    List {
+        /// Include sessions that have already ended
+        #[clap(long)]
+        all: bool,
+
+        /// Output JSON instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+    /// Close an active session
+    Kill {
+        #[clap(action=ArgAction::Set)]
+        id: Uuid,
+    },
+    /// Follow session starts, logins and ends as they happen
+    Tail {
+        /// Output one JSON object per event instead of a table
+        #[clap(long)]
+        json: bool,
+    },
+}
+
+async fn _main(cli: Cli) -> Result<()> {
     init_logging(load_config(&cli.config, false).ok().as_ref(), &cli).await;
 
     #[allow(clippy::unwrap_used)]
@@ -100,26 +198,50 @@ async fn _main() -> Result<()> {
         .unwrap();
 
     match &cli.command {
-        Commands::Run { enable_admin_token } => {
-            crate::commands::run::command(&cli, *enable_admin_token).await
-        }
+        Commands::Run {
+            enable_admin_token,
+            disable_protocol,
+        } => crate::commands::run::command(&cli, *enable_admin_token, disable_protocol).await,
         Commands::Check => crate::commands::check::command(&cli).await,
-        Commands::TestTarget { target_name } => {
-            crate::commands::test_target::command(&cli, target_name).await
-        }
+        Commands::TestTarget {
+            target_name,
+            all,
+            json,
+        } => crate::commands::test_target::command(&cli, target_name, *all, *json).await,
         Commands::Setup { .. } | Commands::UnattendedSetup { .. } => {
             crate::commands::setup::command(&cli).await
         }
-        Commands::ClientKeys => crate::commands::client_keys::command(&cli).await,
+        Commands::ClientKeys { command } => {
+            crate::commands::client_keys::command(&cli, command).await
+        }
+        Commands::KnownHosts { command } => {
+            crate::commands::known_hosts::command(&cli, command).await
+        }
         Commands::RecoverAccess { username } => {
             crate::commands::recover_access::command(&cli, username).await
         }
+        Commands::Sessions { url, command } => {
+            crate::commands::sessions::command(&cli, url, command).await
+        }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    if let Err(error) = _main().await {
+fn main() {
+    let cli = Cli::parse();
+
+    let runtime_config = load_config(&cli.config, false)
+        .map(|config| config.store.runtime)
+        .unwrap_or_default();
+
+    let runtime = match runtime::build_runtime("warpgate", &runtime_config.main) {
+        Ok(runtime) => runtime,
+        Err(error) => {
+            eprintln!("Failed to start the async runtime: {error}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(error) = runtime.block_on(_main(cli)) {
         error!(?error, "Fatal error");
         std::process::exit(1);
     }
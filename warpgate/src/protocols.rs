@@ -1,38 +1,168 @@
-use enum_dispatch::enum_dispatch;
-use warpgate_common::ListenEndpoint;
-use warpgate_core::{ProtocolServer, TargetTestError};
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use warpgate_common::{ListenEndpoint, RuntimeThreadsConfig, TargetOptions, WarpgateConfigStore};
+use warpgate_core::{DynProtocolServer, ProtocolRegistry, ProtocolServerFactory, Services};
 use warpgate_protocol_http::HTTPProtocolServer;
 use warpgate_protocol_mysql::MySQLProtocolServer;
 use warpgate_protocol_postgres::PostgresProtocolServer;
+use warpgate_protocol_redis::RedisProtocolServer;
 use warpgate_protocol_ssh::SSHProtocolServer;
 
-#[enum_dispatch(ProtocolServer)]
-pub enum ProtocolServerEnum {
-    SSHProtocolServer,
-    HTTPProtocolServer,
-    MySQLProtocolServer,
-    PostgresProtocolServer,
+/// Everything this build can serve. Forks add their own protocols here.
+pub fn protocol_registry() -> ProtocolRegistry {
+    let mut registry = ProtocolRegistry::default();
+    registry.register(Arc::new(SshProtocol));
+    registry.register(Arc::new(HttpProtocol));
+    registry.register(Arc::new(MySqlProtocol));
+    registry.register(Arc::new(PostgresProtocol));
+    registry.register(Arc::new(RedisProtocol));
+    registry
+}
+
+struct SshProtocol;
+
+#[async_trait]
+impl ProtocolServerFactory for SshProtocol {
+    fn name(&self) -> &'static str {
+        "ssh"
+    }
+
+    fn listen_endpoint(&self, config: &WarpgateConfigStore) -> Option<ListenEndpoint> {
+        config.ssh.enable.then(|| config.ssh.listen.clone())
+    }
+
+    fn runtime_config<'a>(
+        &self,
+        config: &'a WarpgateConfigStore,
+    ) -> Option<&'a RuntimeThreadsConfig> {
+        config.runtime.ssh.as_ref()
+    }
+
+    fn supports_target(&self, options: &TargetOptions) -> bool {
+        matches!(
+            options,
+            TargetOptions::Ssh(_) | TargetOptions::Docker(_) | TargetOptions::Ipmi(_)
+        )
+    }
+
+    async fn create(&self, services: &Services) -> Result<Box<dyn DynProtocolServer>> {
+        Ok(Box::new(SSHProtocolServer::new(services).await?))
+    }
+}
+
+struct HttpProtocol;
+
+#[async_trait]
+impl ProtocolServerFactory for HttpProtocol {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn listen_endpoint(&self, config: &WarpgateConfigStore) -> Option<ListenEndpoint> {
+        config.http.enable.then(|| config.http.listen.clone())
+    }
+
+    fn runtime_config<'a>(
+        &self,
+        config: &'a WarpgateConfigStore,
+    ) -> Option<&'a RuntimeThreadsConfig> {
+        config.runtime.http.as_ref()
+    }
+
+    fn supports_target(&self, options: &TargetOptions) -> bool {
+        matches!(options, TargetOptions::Http(_))
+    }
+
+    async fn create(&self, services: &Services) -> Result<Box<dyn DynProtocolServer>> {
+        Ok(Box::new(HTTPProtocolServer::new(services).await?))
+    }
+}
+
+struct MySqlProtocol;
+
+#[async_trait]
+impl ProtocolServerFactory for MySqlProtocol {
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn listen_endpoint(&self, config: &WarpgateConfigStore) -> Option<ListenEndpoint> {
+        config.mysql.enable.then(|| config.mysql.listen.clone())
+    }
+
+    fn runtime_config<'a>(
+        &self,
+        config: &'a WarpgateConfigStore,
+    ) -> Option<&'a RuntimeThreadsConfig> {
+        config.runtime.mysql.as_ref()
+    }
+
+    fn supports_target(&self, options: &TargetOptions) -> bool {
+        matches!(options, TargetOptions::MySql(_))
+    }
+
+    async fn create(&self, services: &Services) -> Result<Box<dyn DynProtocolServer>> {
+        Ok(Box::new(MySQLProtocolServer::new(services).await?))
+    }
+}
+
+struct PostgresProtocol;
+
+#[async_trait]
+impl ProtocolServerFactory for PostgresProtocol {
+    fn name(&self) -> &'static str {
+        "postgres"
+    }
+
+    fn listen_endpoint(&self, config: &WarpgateConfigStore) -> Option<ListenEndpoint> {
+        config
+            .postgres
+            .enable
+            .then(|| config.postgres.listen.clone())
+    }
+
+    fn runtime_config<'a>(
+        &self,
+        config: &'a WarpgateConfigStore,
+    ) -> Option<&'a RuntimeThreadsConfig> {
+        config.runtime.postgres.as_ref()
+    }
+
+    fn supports_target(&self, options: &TargetOptions) -> bool {
+        matches!(options, TargetOptions::Postgres(_))
+    }
+
+    async fn create(&self, services: &Services) -> Result<Box<dyn DynProtocolServer>> {
+        Ok(Box::new(PostgresProtocolServer::new(services).await?))
+    }
 }
 
-impl ProtocolServer for ProtocolServerEnum {
-    async fn run(self, address: ListenEndpoint) -> anyhow::Result<()> {
-        match self {
-            ProtocolServerEnum::SSHProtocolServer(s) => s.run(address).await,
-            ProtocolServerEnum::HTTPProtocolServer(s) => s.run(address).await,
-            ProtocolServerEnum::MySQLProtocolServer(s) => s.run(address).await,
-            ProtocolServerEnum::PostgresProtocolServer(s) => s.run(address).await,
-        }
+struct RedisProtocol;
+
+#[async_trait]
+impl ProtocolServerFactory for RedisProtocol {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    fn listen_endpoint(&self, config: &WarpgateConfigStore) -> Option<ListenEndpoint> {
+        config.redis.enable.then(|| config.redis.listen.clone())
     }
 
-    async fn test_target(
+    fn runtime_config<'a>(
         &self,
-        target: warpgate_common::Target,
-    ) -> anyhow::Result<(), TargetTestError> {
-        match self {
-            ProtocolServerEnum::SSHProtocolServer(s) => s.test_target(target).await,
-            ProtocolServerEnum::HTTPProtocolServer(s) => s.test_target(target).await,
-            ProtocolServerEnum::MySQLProtocolServer(s) => s.test_target(target).await,
-            ProtocolServerEnum::PostgresProtocolServer(s) => s.test_target(target).await,
-        }
+        config: &'a WarpgateConfigStore,
+    ) -> Option<&'a RuntimeThreadsConfig> {
+        config.runtime.redis.as_ref()
+    }
+
+    fn supports_target(&self, options: &TargetOptions) -> bool {
+        matches!(options, TargetOptions::Redis(_))
+    }
+
+    async fn create(&self, services: &Services) -> Result<Box<dyn DynProtocolServer>> {
+        Ok(Box::new(RedisProtocolServer::new(services).await?))
     }
 }
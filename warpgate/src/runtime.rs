@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::runtime::{Builder, Runtime};
+use tracing::*;
+use warpgate_common::RuntimeThreadsConfig;
+
+pub fn build_runtime(name: &str, config: &RuntimeThreadsConfig) -> std::io::Result<Runtime> {
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all().thread_name(name);
+
+    if let Some(worker_threads) = config.worker_threads.filter(|x| *x > 0) {
+        builder.worker_threads(worker_threads);
+    }
+    if let Some(max_blocking_threads) = config.max_blocking_threads.filter(|x| *x > 0) {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+
+    if !config.cpus.is_empty() {
+        // Threads get assigned to the listed cores in a round-robin fashion
+        let cpus = config.cpus.clone();
+        let next = Arc::new(AtomicUsize::new(0));
+        let name = name.to_owned();
+        builder.on_thread_start(move || {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            // No pinning if there's nothing to pin to
+            let Some(&cpu) = index.checked_rem(cpus.len()).and_then(|x| cpus.get(x)) else {
+                return;
+            };
+            if !core_affinity::set_for_current(core_affinity::CoreId { id: cpu }) {
+                warn!(runtime=%name, %cpu, "Could not pin thread to CPU");
+            }
+        });
+    }
+
+    builder.build()
+}
+
+/// Keeps dedicated runtimes alive and shuts them down without blocking,
+/// since they get dropped from within the main runtime
+#[derive(Default)]
+pub struct DedicatedRuntimes(Vec<Runtime>);
+
+impl DedicatedRuntimes {
+    /// Returns a handle to spawn on - either a new dedicated runtime,
+    /// or the current one if `config` is not set
+    pub fn get(
+        &mut self,
+        name: &str,
+        config: Option<&RuntimeThreadsConfig>,
+    ) -> std::io::Result<tokio::runtime::Handle> {
+        let Some(config) = config else {
+            return Ok(tokio::runtime::Handle::current());
+        };
+        let runtime = build_runtime(name, config)?;
+        info!(
+            runtime = name,
+            worker_threads = ?config.worker_threads,
+            cpus = ?config.cpus,
+            "Started a dedicated runtime"
+        );
+        let handle = runtime.handle().clone();
+        self.0.push(runtime);
+        Ok(handle)
+    }
+}
+
+impl Drop for DedicatedRuntimes {
+    fn drop(&mut self) {
+        for runtime in self.0.drain(..) {
+            runtime.shutdown_background();
+        }
+    }
+}